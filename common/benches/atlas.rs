@@ -0,0 +1,71 @@
+//! Benchmarks for the cpu side of block textures: packing the atlas, looking up where a tile
+//! ended up (which the mesher does for every face it emits), and building mips on the cpu.
+
+#[macro_use]
+extern crate criterion;
+extern crate renderer_common;
+
+use criterion::{ black_box, Criterion };
+
+use renderer_common::atlas::{ AtlasBuilder, AtlasLayout, BlockTextureId };
+use renderer_common::texture::generate_mips_cpu;
+
+const TILE_SIZE: u32 = 16;
+const TILE_COUNT: u16 = 256;
+
+/// An atlas builder with `TILE_COUNT` tiles, each filled with its own color.
+fn builder() -> AtlasBuilder {
+    let mut builder = AtlasBuilder::new(TILE_SIZE);
+    for index in 0..TILE_COUNT {
+        let color = [index as u8, (index >> 8) as u8, 128, 255];
+        let pixels = color.iter().cloned().cycle().take((TILE_SIZE * TILE_SIZE * 4) as usize).collect();
+        builder.add_rgba8(&format!("tile{}", index), pixels).unwrap();
+    }
+    builder
+}
+
+fn layout() -> AtlasLayout {
+    builder().pack().0
+}
+
+fn pack(c: &mut Criterion) {
+    c.bench_function("atlas pack", |b| b.iter_with_setup(builder, |builder| builder.pack()));
+}
+
+fn uv_lookup(c: &mut Criterion) {
+    let layout = layout();
+    // All four corners of a face, the way the mesher asks for them
+    c.bench_function("atlas uv lookup", move |b| {
+        b.iter(|| {
+            for id in 0..TILE_COUNT {
+                let rect = layout.uv(BlockTextureId(id));
+                for &(u, v) in &[(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)] {
+                    black_box(rect.lerp(u, v));
+                }
+            }
+        })
+    });
+}
+
+fn name_lookup(c: &mut Criterion) {
+    let layout = layout();
+    let names: Vec<_> = (0..TILE_COUNT).map(|index| format!("tile{}", index)).collect();
+    c.bench_function("atlas name lookup", move |b| {
+        b.iter(|| {
+            for name in &names {
+                black_box(layout.id(name));
+            }
+        })
+    });
+}
+
+fn mips_cpu(c: &mut Criterion) {
+    // Only as many levels as the atlas padding allows, the same as `AtlasBuilder::build`
+    let (layout, pixels) = builder().pack();
+    c.bench_function("atlas mips cpu", move |b| {
+        b.iter(|| generate_mips_cpu(layout.width, layout.height, &pixels, layout.mip_levels))
+    });
+}
+
+criterion_group!(benches, pack, uv_lookup, name_lookup, mips_cpu);
+criterion_main!(benches);
@@ -0,0 +1,71 @@
+//! Benchmarks for meshing chunks, on a few kinds of chunk that stress the mesher differently.
+
+#[macro_use]
+extern crate criterion;
+extern crate renderer_common;
+
+use criterion::Criterion;
+
+use renderer_common::atlas::BlockTextureId;
+use renderer_common::mesher::{ BlockTextures, ChunkNeighborhood, Mesher };
+use renderer_common::world::{ BlockId, Chunk, CHUNK_SIZE };
+
+const STONE: BlockId = BlockId(1);
+const DIRT: BlockId = BlockId(2);
+
+fn textures() -> BlockTextures {
+    let mut textures = BlockTextures::new();
+    textures.set_all(STONE, BlockTextureId(1));
+    textures.set_all(DIRT, BlockTextureId(2));
+    textures
+}
+
+/// Solid up to a gently rolling surface, like most terrain near the ground.
+fn hills() -> Chunk {
+    let mut chunk = Chunk::new();
+    for z in 0..CHUNK_SIZE {
+        for x in 0..CHUNK_SIZE {
+            let height = (12.0 + (x as f32 * 0.3).sin() * 4.0 + (z as f32 * 0.2).cos() * 4.0) as usize;
+            for y in 0..height {
+                chunk.set([x, y, z], if y + 3 < height { STONE } else { DIRT });
+            }
+        }
+    }
+    chunk
+}
+
+/// Every other block solid, so no two solid blocks touch. This is the most faces a chunk can
+/// have, and the worst case for any mesher.
+fn checkerboard() -> Chunk {
+    let mut chunk = Chunk::new();
+    for y in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                if (x + y + z) % 2 == 0 {
+                    chunk.set([x, y, z], STONE);
+                }
+            }
+        }
+    }
+    chunk
+}
+
+fn bench_chunk(c: &mut Criterion, name: &str, chunk: Chunk) {
+    for &kind in &Mesher::ALL {
+        let textures = textures();
+        let chunk = chunk.clone();
+        c.bench_function(&format!("mesh {} {}", kind, name), move |b| {
+            let neighborhood = ChunkNeighborhood::new(&chunk);
+            b.iter(|| kind.mesh(&neighborhood, &textures))
+        });
+    }
+}
+
+fn meshers(c: &mut Criterion) {
+    bench_chunk(c, "hills", hills());
+    bench_chunk(c, "checkerboard", checkerboard());
+    bench_chunk(c, "solid", Chunk::filled(STONE));
+}
+
+criterion_group!(benches, meshers);
+criterion_main!(benches);
@@ -0,0 +1,60 @@
+//! Benchmarks for getting data onto the gpu through staging buffers. These open a real device
+//! (headless, on the Vulkan backend), so they're only built with `--features vulkan`:
+//!
+//! ```sh
+//! cargo bench -p renderer-common --features vulkan --bench upload
+//! ```
+
+#[macro_use]
+extern crate criterion;
+extern crate gfx_backend_vulkan as back;
+extern crate gfx_hal as hal;
+extern crate renderer_common;
+extern crate structopt;
+
+use criterion::Criterion;
+use hal::buffer;
+use structopt::StructOpt;
+
+use renderer_common::{ exit_on_error, upload_buffer, Args, Config, GfxContext, MemoryCategory, Texture };
+
+const APP_NAME: &str = "upload-bench";
+
+/// Buffer sizes in bytes, from a single small chunk mesh up to a large batch of them.
+const BUFFER_SIZES: [usize; 3] = [64 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+
+const TEXTURE_SIZE: u32 = 512;
+
+/// Kept out of the way in `target`, so the benchmarks don't leave a settings file behind.
+const SETTINGS_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../target/upload-bench-settings.toml");
+
+fn headless_context() -> GfxContext<back::Backend> {
+    let args = Args::from_iter(&[APP_NAME, "--headless", "--config", SETTINGS_PATH]);
+    let config = Config::load(&args.config);
+    let instance = back::Instance::create(APP_NAME, 1);
+    exit_on_error(GfxContext::new(APP_NAME, args, config, instance, None, None))
+}
+
+fn buffers(c: &mut Criterion) {
+    let mut context = headless_context();
+    c.bench_function_over_inputs(
+        "upload_buffer",
+        move |b, &size| {
+            let data = vec![0u8; size];
+            b.iter(|| upload_buffer(&mut context, &data, buffer::Usage::VERTEX, MemoryCategory::Meshes).unwrap())
+        },
+        BUFFER_SIZES.to_vec(),
+    );
+}
+
+fn textures(c: &mut Criterion) {
+    let mut context = headless_context();
+    let pixels = vec![255u8; (TEXTURE_SIZE * TEXTURE_SIZE * 4) as usize];
+    // Mips are generated on the gpu where the format allows it, so this times that too
+    c.bench_function("texture upload with mips", move |b| {
+        b.iter(|| Texture::from_rgba8(&mut context, TEXTURE_SIZE, TEXTURE_SIZE, &pixels).unwrap())
+    });
+}
+
+criterion_group!(benches, buffers, textures);
+criterion_main!(benches);
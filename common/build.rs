@@ -0,0 +1,6 @@
+extern crate shader_build;
+
+fn main() {
+    // The shaders for the debug overlay, the HUD and text
+    shader_build::compile_shaders("shaders");
+}
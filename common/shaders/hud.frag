@@ -0,0 +1,24 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform texture2D hud_texture;
+layout(set = 0, binding = 1) uniform sampler hud_sampler;
+
+layout(location = 0) in vec2 frag_uv;
+layout(location = 1) in vec4 frag_color;
+layout(location = 2) flat in float frag_textured;
+layout(location = 3) flat in float frag_srgb_target;
+
+layout(location = 0) out vec4 out_color;
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+void main() {
+    // Every image the HUD shows is sRGB, so it's read back linear, which only an sRGB target
+    // encodes again
+    vec4 texel = texture(sampler2D(hud_texture, hud_sampler), frag_uv);
+    texel.rgb = mix(linear_to_srgb(texel.rgb), texel.rgb, frag_srgb_target);
+    out_color = frag_color * mix(vec4(1.0), texel, frag_textured);
+}
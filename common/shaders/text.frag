@@ -0,0 +1,15 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform texture2D glyph_texture;
+layout(set = 0, binding = 1) uniform sampler glyph_sampler;
+
+layout(location = 0) in vec2 frag_uv;
+layout(location = 1) in vec4 frag_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // The glyph cache is white, with how much of each pixel the glyph covers in alpha
+    out_color = frag_color * texture(sampler2D(glyph_texture, glyph_sampler), frag_uv);
+}
@@ -0,0 +1,34 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Glyph positions are in physical pixels from the top left, which `scale` and `translate` map
+// to clip space
+layout(push_constant) uniform PushConstants {
+    vec2 scale;
+    vec2 translate;
+    // 1 when drawing into an sRGB image, 0 otherwise
+    float srgb_target;
+} push_constants;
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 color;
+
+layout(location = 0) out vec2 frag_uv;
+layout(location = 1) out vec4 frag_color;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+// Text colours are sRGB, like ImGui's, so they're decoded for an sRGB target, which encodes
+// whatever we write
+vec3 srgb_to_linear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+void main() {
+    gl_Position = vec4(position * push_constants.scale + push_constants.translate, 0.0, 1.0);
+    frag_uv = uv;
+    frag_color = vec4(mix(color.rgb, srgb_to_linear(color.rgb), push_constants.srgb_target), color.a);
+}
@@ -0,0 +1,132 @@
+//! Choosing which adapter (GPU) to render with.
+//!
+//! Just taking the first adapter `hal` gives us will happily pick an integrated GPU on a laptop
+//! that also has a discrete one, so instead we give every adapter a score and take the best one,
+//! unless the user asked for a specific one with `--adapter <index|name>`.
+
+use hal::{
+    adapter::DeviceType,
+    memory::Properties,
+    Adapter, Backend, PhysicalDevice, QueueFamily, Surface,
+};
+
+use error::{ AdapterSummary, RendererError, Result };
+
+/// Scores an adapter based on how well suited it is for rendering to `surface`. Returns `None` if
+/// the adapter can't be used at all, i.e. it has no graphics queue family that can present.
+/// Without a surface (in headless mode) any graphics queue family will do.
+pub fn score_adapter<B: Backend>(adapter: &Adapter<B>, surface: Option<&B::Surface>) -> Option<u64> {
+    let can_present = adapter.queue_families.iter().any(|family| {
+        family.supports_graphics() && surface.map_or(true, |surface| surface.supports_queue_family(family))
+    });
+    if !can_present {
+        return None;
+    }
+
+    let type_score = match adapter.info.device_type {
+        DeviceType::DiscreteGpu => 1_000_000,
+        DeviceType::IntegratedGpu => 100_000,
+        DeviceType::VirtualGpu => 10_000,
+        DeviceType::Cpu => 1_000,
+        DeviceType::Other => 0,
+    };
+
+    // Break ties between adapters of the same type with the amount of device local memory, in
+    // megabytes. This stays well below the type score for any gpu that exists today.
+    let memory_score = device_local_memory(adapter) / (1024 * 1024);
+
+    Some(type_score + memory_score.min(99_999))
+}
+
+/// The total size, in bytes, of all the memory heaps on an adapter that are device local.
+pub fn device_local_memory<B: Backend>(adapter: &Adapter<B>) -> u64 {
+    let memory_properties = adapter.physical_device.memory_properties();
+    let mut device_local_heaps = vec![false; memory_properties.memory_heaps.len()];
+    for memory_type in &memory_properties.memory_types {
+        if memory_type.properties.contains(Properties::DEVICE_LOCAL) {
+            device_local_heaps[memory_type.heap_index] = true;
+        }
+    }
+
+    memory_properties.memory_heaps
+        .iter()
+        .zip(device_local_heaps)
+        .filter(|&(_, is_device_local)| is_device_local)
+        .map(|(size, _)| *size)
+        .sum()
+}
+
+/// Summaries of every adapter in `adapters`, for error messages.
+pub fn summarize_adapters<B: Backend>(adapters: &[Adapter<B>], surface: Option<&B::Surface>) -> Vec<AdapterSummary> {
+    adapters
+        .iter()
+        .enumerate()
+        .map(|(index, adapter)| AdapterSummary {
+            index,
+            name: adapter.info.name.clone(),
+            device_type: adapter.info.device_type.clone(),
+            can_present: score_adapter(adapter, surface).is_some(),
+        })
+        .collect()
+}
+
+/// Picks an adapter out of `adapters`. If `requested` is given it is either an index into the
+/// list or a (case insensitive) substring of the adapter's name; otherwise the highest scoring
+/// usable adapter wins.
+pub fn pick_adapter<B: Backend>(
+    mut adapters: Vec<Adapter<B>>,
+    surface: Option<&B::Surface>,
+    requested: Option<&str>,
+) -> Result<Adapter<B>> {
+    for (index, adapter) in adapters.iter().enumerate() {
+        match score_adapter(adapter, surface) {
+            Some(score) => info!("Adapter {}: {} ({:?}), score {}", index, adapter.info.name, adapter.info.device_type, score),
+            None => info!("Adapter {}: {} ({:?}), can't present to the window", index, adapter.info.name, adapter.info.device_type),
+        }
+    }
+
+    let index = match requested {
+        Some(requested) => {
+            let index = match requested.parse::<usize>() {
+                Ok(index) if index < adapters.len() => Some(index),
+                Ok(_) => None,
+                Err(_) => {
+                    let name = requested.to_lowercase();
+                    adapters
+                        .iter()
+                        .position(|adapter| adapter.info.name.to_lowercase().contains(&name))
+                }
+            };
+
+            match index {
+                Some(index) if score_adapter(&adapters[index], surface).is_some() => index,
+                _ => {
+                    return Err(RendererError::AdapterNotUsable {
+                        requested: requested.to_owned(),
+                        adapters: summarize_adapters(&adapters, surface),
+                    })
+                }
+            }
+        }
+        None => adapters
+            .iter()
+            .enumerate()
+            .filter_map(|(index, adapter)| score_adapter(adapter, surface).map(|score| (index, score)))
+            .max_by_key(|&(_, score)| score)
+            .map(|(index, _)| index)
+            .ok_or_else(|| RendererError::NoSuitableAdapter {
+                adapters: summarize_adapters(&adapters, surface),
+            })?,
+    };
+
+    let adapter = adapters.remove(index);
+    info!(
+        "Using adapter {}: {} ({:?}, {} MB device local memory)",
+        index,
+        adapter.info.name,
+        adapter.info.device_type,
+        device_local_memory(&adapter) / (1024 * 1024),
+    );
+
+    Ok(adapter)
+}
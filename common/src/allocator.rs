@@ -0,0 +1,476 @@
+//! A simple gpu memory allocator.
+//!
+//! Allocating a separate `Memory` object for every buffer and image doesn't scale: drivers limit
+//! how many allocations you can have alive at once (often to 4096), and each one is slow to make.
+//! Instead we allocate large blocks of memory and hand out pieces of them, keeping a free list of
+//! ranges for each block. Requests that are bigger than a block get a dedicated allocation, as
+//! do buffers that stay mapped, since a `Memory` object can only be mapped once at a time.
+//!
+//! Every allocation says what it's for with a `MemoryCategory`, and the stats add them up by
+//! category and by memory heap, against the size the adapter gives each heap. When a new block
+//! takes a heap past `BUDGET_WARNING` of its size, it says so in the log once, and the
+//! overlay shows the heap as nearly full.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::rc::Rc;
+
+use hal::{
+    adapter::{ MemoryProperties, MemoryType },
+    memory::{ Properties, Requirements },
+    Backend, Device, MemoryTypeId,
+};
+
+use ranges::RangeAllocator;
+
+/// The size of the blocks that small allocations are carved out of.
+const BLOCK_SIZE: u64 = 64 * 1024 * 1024;
+
+/// How much of a heap can be reserved before it's counted as nearly full. Drivers don't always
+/// let you have all of a heap, and other programs are using it too.
+pub const BUDGET_WARNING: f64 = 0.9;
+
+/// How many `MemoryCategory`s there are.
+pub const CATEGORY_COUNT: usize = 6;
+
+/// What an allocation is for, so the memory use can be broken down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MemoryCategory {
+    /// Vertex and index buffers, for chunks and everything else drawn from them.
+    Meshes,
+    /// Images loaded once and sampled: block textures, the font, the sky.
+    Textures,
+    /// Images rendered into: depth buffers, shadow maps, the g-buffer and the hdr targets.
+    Attachments,
+    /// Uniform buffers.
+    Uniforms,
+    /// Storage buffers the gpu fills and reads itself, like indirect draws and particles.
+    Storage,
+    /// Short-lived or per-frame host visible memory: staging, readback and geometry that's
+    /// written again every frame.
+    Transient,
+}
+
+impl MemoryCategory {
+    pub const ALL: [MemoryCategory; CATEGORY_COUNT] = [
+        MemoryCategory::Meshes,
+        MemoryCategory::Textures,
+        MemoryCategory::Attachments,
+        MemoryCategory::Uniforms,
+        MemoryCategory::Storage,
+        MemoryCategory::Transient,
+    ];
+
+    /// Where the category is in `ALL`, and in `AllocatorStats::categories`.
+    pub fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for MemoryCategory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            MemoryCategory::Meshes => "meshes",
+            MemoryCategory::Textures => "textures",
+            MemoryCategory::Attachments => "attachments",
+            MemoryCategory::Uniforms => "uniforms",
+            MemoryCategory::Storage => "storage",
+            MemoryCategory::Transient => "transient",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Whether memory is going to be bound to a buffer (or linearly tiled image) or to an optimally
+/// tiled image. The two are kept in separate blocks so we never have to worry about the
+/// `bufferImageGranularity` limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Linear,
+    Optimal,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AllocationError {
+    /// None of the memory types allowed by the requirements have the requested properties.
+    NoSuitableMemoryType,
+    /// The device ran out of memory while allocating a new block.
+    OutOfMemory,
+}
+
+/// A handle to a range of memory handed out by the `Allocator`. It has to be given back with
+/// `Allocator::free` before the allocator goes away.
+#[derive(Debug)]
+pub struct Allocation {
+    memory_type: MemoryTypeId,
+    kind: ResourceKind,
+    category: MemoryCategory,
+    block: usize,
+    offset: u64,
+    size: u64,
+}
+
+impl Allocation {
+    /// The offset of this allocation inside its `Memory` object, which is what gets passed to
+    /// `bind_buffer_memory`/`bind_image_memory`.
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn range(&self) -> Range<u64> {
+        self.offset..self.offset + self.size
+    }
+
+    pub fn memory_type(&self) -> MemoryTypeId {
+        self.memory_type
+    }
+
+    pub fn category(&self) -> MemoryCategory {
+        self.category
+    }
+}
+
+struct Block<B: Backend> {
+    memory: B::Memory,
+    ranges: RangeAllocator,
+    dedicated: bool,
+}
+
+impl<B: Backend> Block<B> {
+    fn new(memory: B::Memory, size: u64, dedicated: bool) -> Self {
+        Block {
+            memory,
+            ranges: RangeAllocator::new(size),
+            dedicated,
+        }
+    }
+
+    /// First-fit search of the free list for `size` bytes aligned to `alignment`.
+    fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        self.ranges.allocate(size, alignment).map(|range| range.start)
+    }
+}
+
+/// How much of one of the adapter's memory heaps is in use.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeapStats {
+    /// The size of the heap, as the adapter reports it.
+    pub size: u64,
+    /// Bytes of blocks allocated from the heap.
+    pub reserved_bytes: u64,
+    /// Bytes of those handed out to allocations.
+    pub used_bytes: u64,
+    /// Whether any of its memory types are device local.
+    pub device_local: bool,
+}
+
+impl HeapStats {
+    /// How much of the heap is reserved, from 0 to 1.
+    pub fn fraction(&self) -> f64 {
+        if self.size == 0 {
+            0.0
+        } else {
+            self.reserved_bytes as f64 / self.size as f64
+        }
+    }
+
+    /// Whether the heap is past `BUDGET_WARNING` of its size.
+    pub fn near_budget(&self) -> bool {
+        self.fraction() > BUDGET_WARNING
+    }
+}
+
+/// Totals for the memory managed by an `Allocator`.
+#[derive(Clone, Debug, Default)]
+pub struct AllocatorStats {
+    /// The number of `Memory` objects allocated from the device.
+    pub blocks: usize,
+    /// The number of live allocations handed out.
+    pub allocations: usize,
+    /// Bytes allocated from the device.
+    pub reserved_bytes: u64,
+    /// Bytes handed out to allocations, including alignment padding.
+    pub used_bytes: u64,
+    /// Bytes handed out to each category of allocation, in the order of `MemoryCategory::ALL`.
+    pub categories: [u64; CATEGORY_COUNT],
+    /// Every heap the adapter has, in its order.
+    pub heaps: Vec<HeapStats>,
+}
+
+impl fmt::Display for AllocatorStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} allocations using {:.2} MB of {:.2} MB reserved across {} blocks",
+            self.allocations,
+            self.used_bytes as f64 / (1024.0 * 1024.0),
+            self.reserved_bytes as f64 / (1024.0 * 1024.0),
+            self.blocks,
+        )?;
+        for &category in &MemoryCategory::ALL {
+            let bytes = self.categories[category.index()];
+            if bytes > 0 {
+                write!(f, ", {:.2} MB {}", bytes as f64 / (1024.0 * 1024.0), category)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Sub-allocates device memory out of large blocks, one set of blocks per memory type and
+/// `ResourceKind`.
+pub struct Allocator<B: Backend> {
+    device: Rc<B::Device>,
+    memory_types: Vec<MemoryType>,
+    /// The size of each heap the memory types are in.
+    memory_heaps: Vec<u64>,
+    /// Freed blocks are left as `None` so that the indices in outstanding `Allocation`s stay valid.
+    pools: HashMap<(MemoryTypeId, ResourceKind), Vec<Option<Block<B>>>>,
+    /// Bytes handed out to each category, in the order of `MemoryCategory::ALL`.
+    categories: [u64; CATEGORY_COUNT],
+    /// Which heaps have been warned about being nearly full, so it's only said once each time
+    /// one fills up.
+    warned: Vec<bool>,
+}
+
+impl<B: Backend> Allocator<B> {
+    pub fn new(device: Rc<B::Device>, memory_properties: MemoryProperties) -> Self {
+        let warned = vec![false; memory_properties.memory_heaps.len()];
+        Allocator {
+            device,
+            memory_types: memory_properties.memory_types,
+            memory_heaps: memory_properties.memory_heaps,
+            pools: HashMap::new(),
+            categories: [0; CATEGORY_COUNT],
+            warned,
+        }
+    }
+
+    /// Finds the first memory type allowed by `type_mask` (from a resource's memory
+    /// requirements) that has all of `properties`.
+    pub fn find_memory_type(&self, type_mask: u64, properties: Properties) -> Option<MemoryTypeId> {
+        self.memory_types
+            .iter()
+            .enumerate()
+            .position(|(id, memory_type)| {
+                type_mask & (1 << id) != 0 && memory_type.properties.contains(properties)
+            })
+            .map(MemoryTypeId)
+    }
+
+    pub fn memory_types(&self) -> &[MemoryType] {
+        &self.memory_types
+    }
+
+    /// Allocates memory satisfying `requirements` from a memory type with `properties`, counted
+    /// under `category`.
+    pub fn allocate(
+        &mut self,
+        requirements: &Requirements,
+        properties: Properties,
+        kind: ResourceKind,
+        category: MemoryCategory,
+    ) -> Result<Allocation, AllocationError> {
+        self.allocate_in(requirements, properties, kind, category, false)
+    }
+
+    /// Like `allocate`, but always in a `Memory` object of its own, starting at offset 0, for
+    /// memory that's going to be kept mapped.
+    pub fn allocate_dedicated(
+        &mut self,
+        requirements: &Requirements,
+        properties: Properties,
+        kind: ResourceKind,
+        category: MemoryCategory,
+    ) -> Result<Allocation, AllocationError> {
+        self.allocate_in(requirements, properties, kind, category, true)
+    }
+
+    fn allocate_in(
+        &mut self,
+        requirements: &Requirements,
+        properties: Properties,
+        kind: ResourceKind,
+        category: MemoryCategory,
+        dedicated: bool,
+    ) -> Result<Allocation, AllocationError> {
+        let memory_type = self.find_memory_type(requirements.type_mask, properties)
+            .ok_or(AllocationError::NoSuitableMemoryType)?;
+        let (block, offset, new_block) = self.place(requirements, memory_type, kind, dedicated)?;
+
+        self.categories[category.index()] += requirements.size;
+        if new_block {
+            let heap_index = self.memory_types[memory_type.0].heap_index;
+            self.check_budget(heap_index);
+        }
+        Ok(Allocation {
+            memory_type,
+            kind,
+            category,
+            block,
+            offset,
+            size: requirements.size,
+        })
+    }
+
+    /// Finds room for `requirements` in the blocks for `memory_type` and `kind`, making a new
+    /// block if none of them have any or it has to be `dedicated`. Returns which block it's in,
+    /// the offset in it, and whether the block is new.
+    fn place(
+        &mut self,
+        requirements: &Requirements,
+        memory_type: MemoryTypeId,
+        kind: ResourceKind,
+        dedicated: bool,
+    ) -> Result<(usize, u64, bool), AllocationError> {
+        let device = &self.device;
+        let blocks = self.pools.entry((memory_type, kind)).or_insert_with(Vec::new);
+
+        if !dedicated && requirements.size <= BLOCK_SIZE {
+            let existing = blocks
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, block)| block.as_mut().map(|block| (index, block)))
+                .filter(|&(_, ref block)| !block.dedicated)
+                .filter_map(|(index, block)| {
+                    block.allocate(requirements.size, requirements.alignment).map(|offset| (index, offset))
+                })
+                .next();
+
+            if let Some((block, offset)) = existing {
+                return Ok((block, offset, false));
+            }
+        }
+
+        // Nothing has room, so we need a new block. Big requests get one all to themselves.
+        let dedicated = dedicated || requirements.size > BLOCK_SIZE;
+        let block_size = if dedicated { requirements.size } else { BLOCK_SIZE };
+        let memory = device
+            .allocate_memory(memory_type, block_size)
+            .map_err(|_| AllocationError::OutOfMemory)?;
+
+        let mut block = Block::new(memory, block_size, dedicated);
+        let offset = block.allocate(requirements.size, requirements.alignment).unwrap();
+
+        let index = match blocks.iter().position(|block| block.is_none()) {
+            Some(index) => {
+                blocks[index] = Some(block);
+                index
+            }
+            None => {
+                blocks.push(Some(block));
+                blocks.len() - 1
+            }
+        };
+        Ok((index, offset, true))
+    }
+
+    /// Warns once when the heap at `heap_index` gets nearly full, and forgets it has when it's
+    /// emptied out again.
+    fn check_budget(&mut self, heap_index: usize) {
+        let heap = self.heap_stats()[heap_index];
+        if heap.near_budget() && !self.warned[heap_index] {
+            warn!(
+                "Memory heap {} is {:.0}% full, with {:.1} of {:.1} MB reserved",
+                heap_index,
+                heap.fraction() * 100.0,
+                heap.reserved_bytes as f64 / (1024.0 * 1024.0),
+                heap.size as f64 / (1024.0 * 1024.0),
+            );
+        }
+        self.warned[heap_index] = heap.near_budget();
+    }
+
+    /// Returns an allocation to the allocator. Dedicated blocks are given back to the device
+    /// straight away; regular blocks are kept around for reuse.
+    pub fn free(&mut self, allocation: Allocation) {
+        let release = {
+            let blocks = self.pools
+                .get_mut(&(allocation.memory_type, allocation.kind))
+                .expect("Allocation was not made by this allocator");
+
+            let release = {
+                let block = blocks[allocation.block].as_mut().expect("Allocation was already freed");
+                block.ranges.free(allocation.range());
+                block.dedicated && block.ranges.allocations() == 0
+            };
+
+            if release {
+                let block = blocks[allocation.block].take().unwrap();
+                self.device.free_memory(block.memory);
+            }
+            release
+        };
+
+        self.categories[allocation.category.index()] -= allocation.size;
+        if release {
+            let heap_index = self.memory_types[allocation.memory_type.0].heap_index;
+            self.check_budget(heap_index);
+        }
+    }
+
+    /// The `Memory` object an allocation lives in.
+    pub fn memory(&self, allocation: &Allocation) -> &B::Memory {
+        &self.pools[&(allocation.memory_type, allocation.kind)][allocation.block]
+            .as_ref()
+            .expect("Allocation was already freed")
+            .memory
+    }
+
+    /// How much of each heap is in use, in the adapter's order.
+    fn heap_stats(&self) -> Vec<HeapStats> {
+        let mut heaps: Vec<HeapStats> = self
+            .memory_heaps
+            .iter()
+            .map(|&size| HeapStats { size, ..HeapStats::default() })
+            .collect();
+        for memory_type in &self.memory_types {
+            if memory_type.properties.contains(Properties::DEVICE_LOCAL) {
+                heaps[memory_type.heap_index].device_local = true;
+            }
+        }
+        for (&(memory_type, _), blocks) in &self.pools {
+            let heap = &mut heaps[self.memory_types[memory_type.0].heap_index];
+            for block in blocks.iter().filter_map(|block| block.as_ref()) {
+                heap.reserved_bytes += block.ranges.size();
+                heap.used_bytes += block.ranges.size() - block.ranges.free_size();
+            }
+        }
+        heaps
+    }
+
+    pub fn stats(&self) -> AllocatorStats {
+        let mut stats = AllocatorStats {
+            categories: self.categories,
+            heaps: self.heap_stats(),
+            ..AllocatorStats::default()
+        };
+        for block in self.pools.values().flat_map(|blocks| blocks.iter()).filter_map(|block| block.as_ref()) {
+            stats.blocks += 1;
+            stats.allocations += block.ranges.allocations();
+            stats.reserved_bytes += block.ranges.size();
+            stats.used_bytes += block.ranges.size() - block.ranges.free_size();
+        }
+        stats
+    }
+}
+
+impl<B: Backend> Drop for Allocator<B> {
+    fn drop(&mut self) {
+        let leaked = self.stats().allocations;
+        if leaked > 0 {
+            warn!("Allocator dropped with {} allocations still alive", leaked);
+        }
+
+        for (_, blocks) in self.pools.drain() {
+            for block in blocks.into_iter().filter_map(|block| block) {
+                self.device.free_memory(block.memory);
+            }
+        }
+    }
+}
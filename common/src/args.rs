@@ -0,0 +1,86 @@
+//! Command line options shared by every chapter.
+//!
+//! Anything given here takes precedence over `settings.toml`.
+
+use std::path::PathBuf;
+
+use hal::window::PresentMode;
+
+use fullscreen::FullscreenMode;
+use present::parse_present_mode;
+
+#[derive(Clone, Debug, StructOpt)]
+#[structopt(name = "voxel-renderer")]
+pub struct Args {
+    /// Window width in logical pixels (in pixels with `--headless`)
+    #[structopt(long = "width")]
+    pub width: Option<u32>,
+
+    /// Window height in logical pixels (in pixels with `--headless`)
+    #[structopt(long = "height")]
+    pub height: Option<u32>,
+
+    /// Graphics backend: vulkan, dx12 or metal
+    #[structopt(long = "backend")]
+    pub backend: Option<String>,
+
+    /// Adapter to use, by index or by (part of) its name
+    #[structopt(long = "adapter")]
+    pub adapter: Option<String>,
+
+    /// Enable the backend's validation or debug layers and log what they report
+    #[structopt(long = "validation")]
+    pub validation: bool,
+
+    /// World directory to load
+    #[structopt(long = "world", parse(from_os_str))]
+    pub world: Option<PathBuf>,
+
+    /// Seed to generate new worlds from, instead of the one in the settings
+    #[structopt(long = "seed")]
+    pub seed: Option<u64>,
+
+    /// Settings file to load and save
+    #[structopt(long = "config", default_value = "settings.toml", parse(from_os_str))]
+    pub config: PathBuf,
+
+    /// Samples per pixel: 1, 2, 4 or 8
+    #[structopt(long = "msaa")]
+    pub msaa: Option<u8>,
+
+    /// Present mode: immediate, mailbox, fifo or relaxed
+    #[structopt(long = "present-mode", parse(try_from_str = "parse_present_mode"))]
+    pub present_mode: Option<PresentMode>,
+
+    /// Wait for vertical blank: on or off
+    #[structopt(long = "vsync", parse(try_from_str = "parse_on_off"))]
+    pub vsync: Option<bool>,
+
+    /// What Alt+Enter switches to: borderless or exclusive
+    #[structopt(long = "fullscreen")]
+    pub fullscreen: Option<FullscreenMode>,
+
+    /// Render offscreen without opening a window, then save the last frame to `--output`
+    #[structopt(long = "headless")]
+    pub headless: bool,
+
+    /// How many frames to render in headless mode
+    #[structopt(long = "frames", default_value = "1")]
+    pub frames: u32,
+
+    /// Where headless mode saves the last frame, as a PNG
+    #[structopt(long = "output", default_value = "headless.png", parse(from_os_str))]
+    pub output: PathBuf,
+
+    /// Write the cpu profiler's scopes to this file as chrome://tracing JSON on exit
+    #[structopt(long = "cpu-trace", parse(from_os_str))]
+    pub cpu_trace: Option<PathBuf>,
+}
+
+fn parse_on_off(value: &str) -> Result<bool, String> {
+    match value.trim().to_lowercase().as_str() {
+        "on" | "true" | "1" => Ok(true),
+        "off" | "false" | "0" => Ok(false),
+        _ => Err(format!("Invalid value '{}'. Expected on or off", value)),
+    }
+}
@@ -0,0 +1,82 @@
+//! Running compute work on a queue of its own, alongside the drawing.
+//!
+//! Some adapters have a queue family that does compute but not graphics. Its queues run at the
+//! same time as the graphics queue, filling in whatever parts of the gpu the drawing leaves
+//! idle, so a frame's compute work can start while the frame before it is still being drawn.
+//! The `GfxContext` opens a queue from such a family when there is one, and `AsyncCompute`
+//! keeps the command pools for recording into it a frame at a time.
+//!
+//! What runs there is up to the frame graph. `RenderGraph` records the passes put on the compute
+//! queue into these pools, and splits the frame into submissions on each queue that signal
+//! semaphores for the other to wait on wherever one uses what the other wrote. The drawing's
+//! fence is only signalled after everything it waited for, so once `FrameSync::begin_frame` has
+//! waited on the fence the frame's compute work is done too, and its command pool can be reset.
+//!
+//! Like uploads on the transfer queue, what's written is handed between queue families with the
+//! semaphores and the barriers acquiring it alone, since barriers in this version of `hal` can't
+//! say which families they're between.
+
+use std::rc::Rc;
+
+use hal::{
+    pool,
+    queue::{ QueueFamilyId, QueueType },
+    Adapter, Backend, Compute, Device, QueueFamily, QueueGroup,
+};
+
+/// The queue family to run compute work on alongside the graphics queue, if `adapter` has one
+/// that does compute but not graphics.
+pub fn pick_compute_family<B: Backend>(adapter: &Adapter<B>, graphics: QueueFamilyId) -> Option<&B::QueueFamily> {
+    adapter
+        .queue_families
+        .iter()
+        .find(|family| family.queue_type() == QueueType::Compute && family.id() != graphics)
+}
+
+/// A command pool for each of `frames_in_flight` frames of compute work.
+pub struct AsyncCompute<B: Backend> {
+    device: Rc<B::Device>,
+    command_pools: Vec<Option<pool::CommandPool<B, Compute>>>,
+}
+
+impl<B: Backend> AsyncCompute<B> {
+    /// Pools with room for `buffers` command buffers each, one for each of the frame's
+    /// submissions on the compute queue.
+    pub fn new(
+        device: Rc<B::Device>,
+        queue_group: &QueueGroup<B, Compute>,
+        frames_in_flight: usize,
+        buffers: usize,
+    ) -> Self {
+        let command_pools = (0..frames_in_flight)
+            .map(|_| {
+                Some(device.create_command_pool_typed(
+                    queue_group,
+                    pool::CommandPoolCreateFlags::empty(),
+                    buffers,
+                ))
+            })
+            .collect();
+        AsyncCompute { device, command_pools }
+    }
+
+    /// Frame `frame_index`'s command pool, reset for recording into. Only call this once
+    /// `FrameSync::begin_frame` has waited on the frame.
+    pub fn begin_frame(&mut self, frame_index: usize) -> &mut pool::CommandPool<B, Compute> {
+        let command_pool = self.command_pools[frame_index].as_mut().unwrap();
+        command_pool.reset();
+        command_pool
+    }
+}
+
+impl<B: Backend> Drop for AsyncCompute<B> {
+    fn drop(&mut self) {
+        // Everything the pools recorded has to be done before they go
+        if let Err(err) = self.device.wait_idle() {
+            warn!("Couldn't wait for the compute queue to finish: {:?}", err);
+        }
+        for command_pool in self.command_pools.iter_mut().filter_map(|command_pool| command_pool.take()) {
+            self.device.destroy_command_pool(command_pool.into_raw());
+        }
+    }
+}
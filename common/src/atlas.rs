@@ -0,0 +1,298 @@
+//! Packing block face textures into a single atlas.
+//!
+//! Every block face texture is the same size, so the atlas is a simple grid. Each tile sits in
+//! the middle of a cell twice its size, and the border around it is filled by extending the
+//! tile's edge pixels outwards. Because cells are a power of two in size and aligned to their
+//! size, mip level `n` only ever averages texels from within a single cell as long as
+//! `2^n <= padding`, so neighboring faces don't bleed into each other at a distance.
+//! `AtlasLayout::mip_levels` is the number of levels that stay bleed-free, and the atlas texture
+//! is created with exactly that many.
+//!
+//! Where the adapter can sample them, the tiles can go into an array texture instead, a layer
+//! each, with `AtlasBuilder::build_array`. Then a shader picks the layer by `BlockTextureId`,
+//! every tile has its whole mip chain, and faces repeat their tile with the sampler's wrapping
+//! rather than working out where they are in it, so there's no padding and no grid.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use hal::{ format as f, image as i, Backend };
+
+use image;
+
+use context::GfxContext;
+use error::{ RendererError, Result };
+use texture::{ supports_texture_array, Texture };
+
+/// Identifies one block face texture in the atlas.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockTextureId(pub u16);
+
+/// The region of the atlas a tile covers, in normalized texture coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UvRect {
+    pub u0: f32,
+    pub v0: f32,
+    pub u1: f32,
+    pub v1: f32,
+}
+
+impl UvRect {
+    /// Maps a coordinate in `0..1` across the tile to a coordinate in the atlas.
+    pub fn lerp(&self, u: f32, v: f32) -> [f32; 2] {
+        [self.u0 + (self.u1 - self.u0) * u, self.v0 + (self.v1 - self.v0) * v]
+    }
+}
+
+/// Where the tiles sit in the atlas, for shaders that work out a tile's rect from its id
+/// instead of looking it up. Tile `n` starts at `origin + (n % columns, n / columns) * cell`
+/// and is `size` across, all in normalized texture coordinates.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AtlasGrid {
+    pub columns: u32,
+    pub origin: [f32; 2],
+    pub cell: [f32; 2],
+    pub size: [f32; 2],
+}
+
+/// Collects tiles to be packed into an atlas.
+pub struct AtlasBuilder {
+    tile_size: u32,
+    tiles: Vec<(String, Vec<u8>)>,
+}
+
+impl AtlasBuilder {
+    /// `tile_size` is the width and height, in pixels, of every tile. It must be a power of two.
+    pub fn new(tile_size: u32) -> Self {
+        assert!(tile_size.is_power_of_two(), "Atlas tile size must be a power of two");
+        AtlasBuilder {
+            tile_size,
+            tiles: Vec::new(),
+        }
+    }
+
+    /// Adds a tile from tightly packed sRGB RGBA8 pixels.
+    pub fn add_rgba8(&mut self, name: &str, pixels: Vec<u8>) -> Result<BlockTextureId> {
+        let expected = (self.tile_size * self.tile_size * 4) as usize;
+        if pixels.len() != expected {
+            return Err(RendererError::Asset(format!(
+                "Tile '{}' has {} bytes of pixels, expected {}",
+                name,
+                pixels.len(),
+                expected,
+            )));
+        }
+        if self.tiles.len() > u16::max_value() as usize {
+            return Err(RendererError::Asset("Too many tiles in the atlas".to_owned()));
+        }
+
+        self.tiles.push((name.to_owned(), pixels));
+        Ok(BlockTextureId((self.tiles.len() - 1) as u16))
+    }
+
+    /// Loads an image file as a tile, named after the file's stem (`stone.png` becomes `stone`).
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<BlockTextureId> {
+        let path = path.as_ref();
+        let image = image::open(path)
+            .map_err(|err| RendererError::Asset(format!("Failed to load {}: {}", path.display(), err)))?
+            .to_rgba();
+        if image.dimensions() != (self.tile_size, self.tile_size) {
+            return Err(RendererError::Asset(format!(
+                "{} is {:?}, but atlas tiles are {}x{}",
+                path.display(),
+                image.dimensions(),
+                self.tile_size,
+                self.tile_size,
+            )));
+        }
+
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        self.add_rgba8(&name, image.into_raw())
+    }
+
+    /// Adds every `.png` in `dir`, in file name order so ids are stable between runs.
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<Vec<BlockTextureId>> {
+        let dir = dir.as_ref();
+        let mut paths: Vec<_> = ::std::fs::read_dir(dir)
+            .map_err(|err| RendererError::Asset(format!("Failed to read {}: {}", dir.display(), err)))?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().map_or(false, |ext| ext == "png"))
+            .collect();
+        paths.sort();
+        paths.iter().map(|path| self.add_file(path)).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.tiles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tiles.is_empty()
+    }
+
+    /// Whether the adapter `context` is using can sample these tiles as an array texture, for
+    /// `build_array`.
+    pub fn supports_array<B: Backend>(&self, context: &GfxContext<B>) -> bool {
+        supports_texture_array::<B>(&context.adapter.physical_device, f::Format::Rgba8Srgb, self.tiles.len().max(1))
+    }
+
+    /// Packs every tile, returning the layout and the atlas pixels.
+    pub fn pack(self) -> (AtlasLayout, Vec<u8>) {
+        let tile = self.tile_size;
+        let padding = (tile / 2).max(1);
+        let cell = tile + padding * 2;
+
+        let count = self.tiles.len().max(1) as u32;
+        let columns = (count as f32).sqrt().ceil() as u32;
+        let rows = (count + columns - 1) / columns;
+        let width = (columns * cell).next_power_of_two();
+        let height = (rows * cell).next_power_of_two();
+
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        let mut rects = Vec::with_capacity(self.tiles.len());
+        let mut names = HashMap::new();
+
+        for (index, (name, tile_pixels)) in self.tiles.into_iter().enumerate() {
+            let cell_x = (index as u32 % columns) * cell;
+            let cell_y = (index as u32 / columns) * cell;
+
+            // Fill the whole cell, clamping to the tile's edges in the padding
+            for y in 0..cell {
+                let ty = (y as i32 - padding as i32).max(0).min(tile as i32 - 1) as u32;
+                for x in 0..cell {
+                    let tx = (x as i32 - padding as i32).max(0).min(tile as i32 - 1) as u32;
+                    let src = ((ty * tile + tx) * 4) as usize;
+                    let dst = (((cell_y + y) * width + cell_x + x) * 4) as usize;
+                    pixels[dst..dst + 4].copy_from_slice(&tile_pixels[src..src + 4]);
+                }
+            }
+
+            rects.push(UvRect {
+                u0: (cell_x + padding) as f32 / width as f32,
+                v0: (cell_y + padding) as f32 / height as f32,
+                u1: (cell_x + padding + tile) as f32 / width as f32,
+                v1: (cell_y + padding + tile) as f32 / height as f32,
+            });
+            names.insert(name, BlockTextureId(index as u16));
+        }
+
+        // Level n blends 2^n texels, which stays within the cell while that's no more than the
+        // padding, i.e. levels 0..=log2(padding)
+        let mip_levels = (32 - padding.leading_zeros()) as u8;
+
+        let (width_f, height_f) = (width as f32, height as f32);
+        let grid = AtlasGrid {
+            columns,
+            origin: [padding as f32 / width_f, padding as f32 / height_f],
+            cell: [cell as f32 / width_f, cell as f32 / height_f],
+            size: [tile as f32 / width_f, tile as f32 / height_f],
+        };
+        let layout = AtlasLayout {
+            width,
+            height,
+            mip_levels,
+            grid,
+            rects,
+            names,
+        };
+        (layout, pixels)
+    }
+
+    /// Packs every tile and uploads the atlas to the gpu.
+    pub fn build<B: Backend>(self, context: &mut GfxContext<B>) -> Result<TextureAtlas<B>> {
+        let (layout, pixels) = self.pack();
+        let texture = Texture::from_rgba8_with_mips(
+            context,
+            layout.width,
+            layout.height,
+            &pixels,
+            layout.mip_levels,
+        )?;
+        Ok(TextureAtlas { layout, texture })
+    }
+
+    /// Uploads every tile as a layer of an array texture, in the order of their ids. Check
+    /// `supports_array` first.
+    pub fn build_array<B: Backend>(self, context: &mut GfxContext<B>) -> Result<TileArray<B>> {
+        let layers = self.tiles.len().max(1);
+        let mut pixels = Vec::with_capacity(layers * (self.tile_size * self.tile_size * 4) as usize);
+        let mut names = HashMap::new();
+        for (index, (name, tile_pixels)) in self.tiles.into_iter().enumerate() {
+            pixels.extend_from_slice(&tile_pixels);
+            names.insert(name, BlockTextureId(index as u16));
+        }
+        // An empty array still needs a layer
+        pixels.resize(layers * (self.tile_size * self.tile_size * 4) as usize, 0);
+
+        let texture = Texture::array_from_rgba8(context, self.tile_size, self.tile_size, layers as i::Layer, &pixels)?;
+        Ok(TileArray { texture, names })
+    }
+}
+
+/// Where each tile ended up in the atlas. This doesn't touch the gpu, so the mesher can use it
+/// anywhere.
+#[derive(Clone, Debug)]
+pub struct AtlasLayout {
+    pub width: u32,
+    pub height: u32,
+    /// How many mip levels the atlas can have before tiles start bleeding into each other.
+    pub mip_levels: u8,
+    grid: AtlasGrid,
+    rects: Vec<UvRect>,
+    names: HashMap<String, BlockTextureId>,
+}
+
+impl AtlasLayout {
+    pub fn uv(&self, id: BlockTextureId) -> UvRect {
+        self.rects[id.0 as usize]
+    }
+
+    pub fn grid(&self) -> AtlasGrid {
+        self.grid
+    }
+
+    pub fn id(&self, name: &str) -> Option<BlockTextureId> {
+        self.names.get(name).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.rects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rects.is_empty()
+    }
+}
+
+/// The packed atlas layout along with the gpu texture holding it.
+pub struct TextureAtlas<B: Backend> {
+    pub layout: AtlasLayout,
+    pub texture: Texture<B>,
+}
+
+impl<B: Backend> TextureAtlas<B> {
+    pub fn uv(&self, id: BlockTextureId) -> UvRect {
+        self.layout.uv(id)
+    }
+}
+
+/// The tiles as the layers of one array texture, which a shader indexes by `BlockTextureId`.
+pub struct TileArray<B: Backend> {
+    /// Viewed as a `texture2DArray`.
+    pub texture: Texture<B>,
+    names: HashMap<String, BlockTextureId>,
+}
+
+impl<B: Backend> TileArray<B> {
+    pub fn id(&self, name: &str) -> Option<BlockTextureId> {
+        self.names.get(name).cloned()
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+}
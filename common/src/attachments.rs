@@ -0,0 +1,201 @@
+//! Per-frame images that are rendered into alongside the swapchain image, like depth buffers,
+//! multisampled color targets, the G-buffer and the HDR target.
+//!
+//! Each swapchain image gets its own set so that frames never have to wait on each other to
+//! reuse them. They're sized to match the swapchain, so they need to be recreated along with it.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hal::{
+    format as f, image as i, memory,
+    Backend, Device,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use depth::depth_range;
+use error::Result;
+use resources::SwapchainBundle;
+use texture::COLOR_RANGE;
+
+struct AttachmentImage<B: Backend> {
+    image: B::Image,
+    view: B::ImageView,
+    allocation: Allocation,
+}
+
+/// One image and view per swapchain image, all with the same format, usage and sample count.
+pub struct AttachmentImages<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    format: f::Format,
+    usage: i::Usage,
+    samples: i::NumSamples,
+    range: i::SubresourceRange,
+    images: Vec<AttachmentImage<B>>,
+}
+
+impl<B: Backend> AttachmentImages<B> {
+    /// Depth buffers of `format`, which should come from `depth::choose_depth_format`.
+    pub fn depth(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        format: f::Format,
+        samples: i::NumSamples,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            format,
+            i::Usage::DEPTH_STENCIL_ATTACHMENT,
+            samples,
+            depth_range(format),
+            swapchain,
+        )
+    }
+
+    /// Depth buffers of `format` that a later pass reads in a shader as well as this one testing
+    /// against them, like the G-buffer's. `format` should come from
+    /// `depth::choose_gbuffer_depth_format`.
+    pub fn sampled_depth(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        format: f::Format,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            format,
+            i::Usage::DEPTH_STENCIL_ATTACHMENT | i::Usage::SAMPLED,
+            1,
+            depth_range(format),
+            swapchain,
+        )
+    }
+
+    /// Single sampled color targets of `format` that a later pass reads in a shader, like the
+    /// G-buffer's.
+    pub fn sampled_color(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        format: f::Format,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            format,
+            i::Usage::COLOR_ATTACHMENT | i::Usage::SAMPLED,
+            1,
+            COLOR_RANGE,
+            swapchain,
+        )
+    }
+
+    /// Multisampled color targets of `format`, to be resolved into the swapchain image or
+    /// whatever else is being drawn at the end of the render pass, so `format` has to match it.
+    /// The contents never need to leave the tile memory on GPUs that have it, hence
+    /// `TRANSIENT_ATTACHMENT`.
+    pub fn multisampled_color(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        format: f::Format,
+        samples: i::NumSamples,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<Self> {
+        Self::new(
+            device,
+            allocator,
+            format,
+            i::Usage::COLOR_ATTACHMENT | i::Usage::TRANSIENT_ATTACHMENT,
+            samples,
+            COLOR_RANGE,
+            swapchain,
+        )
+    }
+
+    fn new(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        format: f::Format,
+        usage: i::Usage,
+        samples: i::NumSamples,
+        range: i::SubresourceRange,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<Self> {
+        let mut attachments = AttachmentImages {
+            device,
+            allocator,
+            format,
+            usage,
+            samples,
+            range,
+            images: Vec::new(),
+        };
+        attachments.recreate(swapchain)?;
+        Ok(attachments)
+    }
+
+    /// Rebuilds the images to match `swapchain`'s size and image count.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.destroy();
+
+        let extent = swapchain.extent();
+        let count = swapchain.frame_images().len();
+        for _ in 0..count {
+            let unbound = self.device.create_image(
+                i::Kind::D2(extent.width, extent.height, 1, self.samples),
+                1,
+                self.format,
+                i::Tiling::Optimal,
+                self.usage,
+                i::ViewCapabilities::empty(),
+            )?;
+            let requirements = self.device.get_image_requirements(&unbound);
+
+            let mut allocator = self.allocator.borrow_mut();
+            let allocation = allocator
+                .allocate(
+                    &requirements,
+                    memory::Properties::DEVICE_LOCAL,
+                    ResourceKind::Optimal,
+                    MemoryCategory::Attachments,
+                )?;
+            let image = self.device
+                .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+            let view = self.device
+                .create_image_view(&image, i::ViewKind::D2, self.format, f::Swizzle::NO, self.range.clone())?;
+
+            self.images.push(AttachmentImage { image, view, allocation });
+        }
+        Ok(())
+    }
+
+    pub fn format(&self) -> f::Format {
+        self.format
+    }
+
+    pub fn samples(&self) -> i::NumSamples {
+        self.samples
+    }
+
+    pub fn view(&self, index: usize) -> &B::ImageView {
+        &self.images[index].view
+    }
+
+    fn destroy(&mut self) {
+        for attachment in self.images.drain(..) {
+            self.device.destroy_image_view(attachment.view);
+            self.device.destroy_image(attachment.image);
+            self.allocator.borrow_mut().free(attachment.allocation);
+        }
+    }
+}
+
+impl<B: Backend> Drop for AttachmentImages<B> {
+    fn drop(&mut self) {
+        self.destroy();
+    }
+}
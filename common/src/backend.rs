@@ -0,0 +1,185 @@
+//! Runtime selection between the graphics backends that were compiled into this binary.
+//!
+//! Each `gfx_backend_*` crate has its own `Instance` type, so everything that touches `hal` is
+//! written generically over `hal::Instance` and this module picks which concrete backend to
+//! instantiate it with based on the `--backend` flag or the `VOXEL_BACKEND` environment variable.
+
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+
+#[cfg(all(feature = "dx12", windows))]
+use gfx_backend_dx12;
+#[cfg(all(feature = "metal", target_os = "macos"))]
+use gfx_backend_metal;
+#[cfg(feature = "vulkan")]
+use gfx_backend_vulkan;
+
+use hal;
+use winit;
+
+use args::Args;
+use config::Config;
+use context::GfxContext;
+use error::RendererError;
+use events::Events;
+use exit_on_error;
+
+const BACKEND_ENV_VAR: &str = "VOXEL_BACKEND";
+
+/// How many times the device can be lost before we stop trying to reopen it. If it keeps
+/// happening something is probably wrong with our rendering rather than the driver.
+const MAX_DEVICE_LOSSES: u32 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Vulkan,
+    Dx12,
+    Metal,
+}
+
+impl Backend {
+    /// Every backend, in order of preference when the user doesn't ask for a specific one.
+    pub const ALL: [Backend; 3] = [Backend::Metal, Backend::Dx12, Backend::Vulkan];
+
+    /// Whether this backend was compiled into the current binary.
+    pub fn is_available(self) -> bool {
+        match self {
+            Backend::Vulkan => cfg!(feature = "vulkan"),
+            Backend::Dx12 => cfg!(all(feature = "dx12", windows)),
+            Backend::Metal => cfg!(all(feature = "metal", target_os = "macos")),
+        }
+    }
+
+    pub fn available() -> Vec<Backend> {
+        Backend::ALL.iter().cloned().filter(|backend| backend.is_available()).collect()
+    }
+
+    /// Picks the backend from `--backend <name>`, then the `VOXEL_BACKEND` environment
+    /// variable, then `configured` (from the settings file), and finally falls back to the first
+    /// available one.
+    pub fn from_args_or_env(args: &Args, configured: Option<&str>) -> Result<Backend, RendererError> {
+        let requested = args.backend.clone()
+            .or_else(|| env::var(BACKEND_ENV_VAR).ok())
+            .or_else(|| configured.map(|name| name.to_owned()));
+
+        match requested {
+            Some(name) => {
+                let backend = name.parse::<Backend>().map_err(RendererError::InvalidOption)?;
+                if backend.is_available() {
+                    Ok(backend)
+                } else {
+                    Err(RendererError::BackendUnavailable {
+                        requested: backend,
+                        available: Backend::available(),
+                    })
+                }
+            }
+            None => Backend::available()
+                .into_iter()
+                .next()
+                .ok_or(RendererError::NoBackends),
+        }
+    }
+
+    /// Creates the instance, surface and `GfxContext` for this backend and hands the context to
+    /// `runner`, so that the rest of the program only has to be written once, generically.
+    /// Without a `window` there's no surface either, and the context renders offscreen.
+    #[allow(unreachable_patterns)]
+    pub fn run<R: Runner>(
+        self,
+        runner: R,
+        app_name: &str,
+        args: Args,
+        config: Config,
+        window: Option<winit::Window>,
+        events: Events,
+    ) {
+        match self {
+            #[cfg(feature = "vulkan")]
+            Backend::Vulkan => {
+                let instance = gfx_backend_vulkan::Instance::create(app_name, 1);
+                let surface = window.as_ref().map(|window| instance.create_surface(window));
+                run_with(runner, app_name, args, config, instance, surface, window, events)
+            }
+            #[cfg(all(feature = "dx12", windows))]
+            Backend::Dx12 => {
+                let instance = gfx_backend_dx12::Instance::create(app_name, 1);
+                let surface = window.as_ref().map(|window| instance.create_surface(window));
+                run_with(runner, app_name, args, config, instance, surface, window, events)
+            }
+            #[cfg(all(feature = "metal", target_os = "macos"))]
+            Backend::Metal => {
+                let instance = gfx_backend_metal::Instance::create(app_name, 1);
+                let surface = window.as_ref().map(|window| instance.create_surface(window));
+                run_with(runner, app_name, args, config, instance, surface, window, events)
+            }
+            _ => panic!("The {} backend is not available in this build", self),
+        }
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s.to_lowercase().as_str() {
+            "vulkan" | "vk" => Ok(Backend::Vulkan),
+            "dx12" | "d3d12" => Ok(Backend::Dx12),
+            "metal" | "mtl" => Ok(Backend::Metal),
+            _ => Err(format!("Unknown backend '{}', expected one of vulkan, dx12 or metal", s)),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Backend::Vulkan => "vulkan",
+            Backend::Dx12 => "dx12",
+            Backend::Metal => "metal",
+        };
+        f.write_str(name)
+    }
+}
+
+fn run_with<R: Runner, I: hal::Instance>(
+    mut runner: R,
+    app_name: &str,
+    args: Args,
+    config: Config,
+    instance: I,
+    surface: Option<<I::Backend as hal::Backend>::Surface>,
+    window: Option<winit::Window>,
+    mut events: Events,
+) {
+    let mut context = exit_on_error(GfxContext::new(app_name, args, config, instance, surface, window));
+
+    // When the device is lost everything the runner made from it is gone too, so it's dropped
+    // and the runner starts over from scratch on a new device
+    let mut device_losses = 0;
+    loop {
+        match runner.run(&mut context, &mut events) {
+            Err(ref err) if err.is_device_lost() && device_losses < MAX_DEVICE_LOSSES => {
+                device_losses += 1;
+                error!("{}, reopening it", err);
+                exit_on_error(context.reopen_device());
+            }
+            result => return exit_on_error(result),
+        }
+    }
+}
+
+/// The part of the program that is generic over the backend. This is a trait rather than a
+/// closure because closures can't be generic.
+///
+/// `run` is called again with a fresh device if the device is lost, so it should create
+/// everything it needs from `context` each time rather than holding on to resources between
+/// calls.
+pub trait Runner {
+    fn run<B: hal::Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<(), RendererError>;
+}
@@ -0,0 +1,161 @@
+//! Textured quads that always face the camera, for particles, item icons and flat stand-ins for
+//! things too far off to be worth a mesh.
+//!
+//! Each sprite is a `Sprite` in an instance buffer, saying where its middle is, how big it is and
+//! which part of a texture it shows. There's no vertex buffer: the vertex shader makes each
+//! quad's six corners from `gl_VertexIndex`, stepping out from the middle along the camera's right
+//! and up, and all of the sprites are drawn with one instanced draw.
+//!
+//! Most things drawn as sprites move, so they're written again every frame, into a
+//! `StagingRing`. They're blended, so they're sorted furthest first as they're written.
+
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::mem;
+use std::rc::Rc;
+
+use hal::{ buffer, command, format as f, pso, Backend };
+
+use allocator::Allocator;
+use atlas::UvRect;
+use error::Result;
+use staging::StagingRing;
+
+/// How big the ring sprites are written into starts out, in bytes. It grows if a frame's don't
+/// fit.
+const SPRITE_RING_SIZE: u64 = 256 * 1024;
+
+/// One quad facing the camera. Has to match the attributes `add_sprite_attributes` describes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Sprite {
+    /// Where the middle of the quad is.
+    pub position: [f32; 3],
+    /// How wide and tall the quad is, in world units.
+    pub size: [f32; 2],
+    /// The part of the texture the quad shows, in normalized texture coordinates, from its top
+    /// left corner to its bottom right.
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    /// What the texture is multiplied by, alpha and all.
+    pub color: [f32; 4],
+    /// Sky light, then block light, from 0 to 1, lighting the quad like a face of a block.
+    pub light: [f32; 2],
+}
+
+impl Sprite {
+    /// A square `size` across showing `uv` as it is, lit as if it were out in the open.
+    pub fn new(position: [f32; 3], size: f32, uv: UvRect) -> Self {
+        Sprite {
+            position,
+            size: [size, size],
+            uv_min: [uv.u0, uv.v0],
+            uv_max: [uv.u1, uv.v1],
+            color: [1.0; 4],
+            light: [1.0, 0.0],
+        }
+    }
+}
+
+/// How many vertices each sprite is drawn with, for its two triangles.
+pub const SPRITE_VERTICES: u32 = 6;
+
+/// Describes the `Sprite`s in the vertex buffer at `binding` to the pipeline, read once per
+/// instance, as the attributes at locations 0 to 4 in the order of `Sprite`'s fields, with the
+/// two uv corners read together as one `vec4`.
+pub fn add_sprite_attributes<B: Backend>(pipeline_desc: &mut pso::GraphicsPipelineDesc<B>, binding: u32) {
+    pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+        binding,
+        stride: mem::size_of::<Sprite>() as u32,
+        rate: 1,
+    });
+    let attributes = [
+        (f::Format::Rgb32Float, 0),
+        (f::Format::Rg32Float, 12),
+        (f::Format::Rgba32Float, 20),
+        (f::Format::Rgba32Float, 36),
+        (f::Format::Rg32Float, 52),
+    ];
+    for (location, &(format, offset)) in attributes.iter().enumerate() {
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: location as u32,
+            binding,
+            element: pso::Element { format, offset },
+        });
+    }
+}
+
+/// Puts `sprites` in order of distance from `eye`, furthest first, so each one blends over the
+/// ones behind it.
+pub fn sort_back_to_front(sprites: &mut [Sprite], eye: [f32; 3]) {
+    let from_eye = |sprite: &Sprite| {
+        let offset = [sprite.position[0] - eye[0], sprite.position[1] - eye[1], sprite.position[2] - eye[2]];
+        offset[0] * offset[0] + offset[1] * offset[1] + offset[2] * offset[2]
+    };
+    sprites.sort_by(|a, b| from_eye(b).partial_cmp(&from_eye(a)).unwrap_or(Ordering::Equal));
+}
+
+/// This frame's sprites, in a ring they're written into each frame.
+pub struct Billboards<B: Backend> {
+    ring: StagingRing<B>,
+    frames_in_flight: usize,
+    /// Where in the ring this frame's sprites start, if there are any.
+    offset: Option<u64>,
+    count: u32,
+}
+
+impl<B: Backend> Billboards<B> {
+    pub fn new(device: Rc<B::Device>, allocator: Rc<RefCell<Allocator<B>>>, frames_in_flight: usize) -> Result<Self> {
+        Ok(Billboards {
+            ring: StagingRing::new(device, allocator, SPRITE_RING_SIZE, buffer::Usage::VERTEX)?,
+            frames_in_flight,
+            offset: None,
+            count: 0,
+        })
+    }
+
+    /// Sorts `sprites` for blending, seen from `eye`, and writes them for this frame. Call this
+    /// once a frame, after its fence has been waited on.
+    pub fn write(&mut self, sprites: &mut [Sprite], eye: [f32; 3]) -> Result<()> {
+        self.ring.begin_frame(self.frames_in_flight);
+        self.count = sprites.len() as u32;
+        self.offset = None;
+        if sprites.is_empty() {
+            return Ok(());
+        }
+        sort_back_to_front(sprites, eye);
+        self.offset = Some(self.ring.write(sprites)?);
+        Ok(())
+    }
+
+    /// How many sprites this frame has.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Draws this frame's sprites with whichever billboard pipeline is bound, which reads them
+    /// from binding 0.
+    pub fn draw(&self, encoder: &mut command::RenderPassInlineEncoder<B>) {
+        if let Some(offset) = self.offset {
+            encoder.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(self.ring.buffer(), offset)]));
+            encoder.draw(0..SPRITE_VERTICES, 0..self.count);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sprite_at(z: f32) -> Sprite {
+        Sprite::new([0.0, 0.0, z], 1.0, UvRect { u0: 0.0, v0: 0.0, u1: 1.0, v1: 1.0 })
+    }
+
+    #[test]
+    fn sprites_are_sorted_furthest_first() {
+        let mut sprites = [sprite_at(1.0), sprite_at(-5.0), sprite_at(3.0)];
+        sort_back_to_front(&mut sprites, [0.0; 3]);
+        let order: Vec<f32> = sprites.iter().map(|sprite| sprite.position[2]).collect();
+        assert_eq!(order, vec![-5.0, 3.0, 1.0]);
+    }
+}
@@ -0,0 +1,192 @@
+//! Which kind of land each part of the world is.
+//!
+//! Two slow noises give every column a temperature and a humidity, both from 0 to 1. Each biome
+//! sits at a point in that climate space, and a column belongs to the biome it's closest to.
+//! Near a border it's part of both: every biome within `BLEND` of the closest one gets a weight
+//! that falls to nothing at `BLEND`, so the terrain can mix their shapes and fade smoothly from
+//! one to the next instead of stepping up a cliff.
+//!
+//! The climate only depends on the seed and the column, like the rest of the terrain.
+
+use std::fmt;
+
+use noise::{ derive_seed, Fbm };
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Biome {
+    Plains,
+    Desert,
+    Forest,
+    Mountains,
+}
+
+/// How a biome shapes the ground. Blended between biomes near their borders.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BiomeTerrain {
+    /// Blocks added to the height of the ground.
+    pub height_offset: f32,
+    /// How much the hills are scaled: under 1 is flatter, over 1 rougher.
+    pub hill_scale: f32,
+    /// The chance each column has of growing a tree.
+    pub tree_density: f32,
+}
+
+/// How far in climate space, beyond the closest biome, other biomes still have a say.
+pub const BLEND: f32 = 0.12;
+
+impl Biome {
+    pub const ALL: [Biome; 4] = [Biome::Plains, Biome::Desert, Biome::Forest, Biome::Mountains];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Biome::Plains => "plains",
+            Biome::Desert => "desert",
+            Biome::Forest => "forest",
+            Biome::Mountains => "mountains",
+        }
+    }
+
+    /// The temperature and humidity the biome is centred on.
+    pub fn climate(self) -> [f32; 2] {
+        match self {
+            Biome::Plains => [0.55, 0.45],
+            Biome::Desert => [0.9, 0.1],
+            Biome::Forest => [0.6, 0.85],
+            Biome::Mountains => [0.15, 0.5],
+        }
+    }
+
+    pub fn terrain(self) -> BiomeTerrain {
+        match self {
+            Biome::Plains => BiomeTerrain { height_offset: 0.0, hill_scale: 0.5, tree_density: 0.002 },
+            Biome::Desert => BiomeTerrain { height_offset: -4.0, hill_scale: 0.35, tree_density: 0.0 },
+            Biome::Forest => BiomeTerrain { height_offset: 2.0, hill_scale: 0.9, tree_density: 0.03 },
+            Biome::Mountains => BiomeTerrain { height_offset: 24.0, hill_scale: 2.5, tree_density: 0.004 },
+        }
+    }
+}
+
+impl fmt::Display for Biome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The biomes at one column, and how much each counts there.
+#[derive(Clone, Copy, Debug)]
+pub struct BiomeSample {
+    /// The closest biome, which picks what the ground is made of.
+    pub biome: Biome,
+    /// Indexed like `Biome::ALL`, adding up to 1.
+    pub weights: [f32; 4],
+}
+
+impl BiomeSample {
+    /// The biomes' terrain, mixed by their weights.
+    pub fn terrain(&self) -> BiomeTerrain {
+        let mut terrain = BiomeTerrain { height_offset: 0.0, hill_scale: 0.0, tree_density: 0.0 };
+        for (&biome, &weight) in Biome::ALL.iter().zip(&self.weights) {
+            let biome = biome.terrain();
+            terrain.height_offset += biome.height_offset * weight;
+            terrain.hill_scale += biome.hill_scale * weight;
+            terrain.tree_density += biome.tree_density * weight;
+        }
+        terrain
+    }
+}
+
+pub struct BiomeMap {
+    temperature: Fbm,
+    humidity: Fbm,
+}
+
+impl BiomeMap {
+    pub fn new(seed: u64) -> Self {
+        BiomeMap {
+            temperature: Fbm::new(derive_seed(seed, 101), 2, 1.0 / 700.0),
+            humidity: Fbm::new(derive_seed(seed, 102), 2, 1.0 / 550.0),
+        }
+    }
+
+    /// The temperature and humidity at block column `x`, `z`.
+    pub fn climate_at(&self, x: i32, z: i32) -> [f32; 2] {
+        let (x, z) = (x as f32, z as f32);
+        // Octaves of noise mostly stay well inside -1 to 1, so they're stretched out to reach
+        // the edges of the climate space a fair amount of the time
+        let remap = |noise: f32| (noise * 1.5 + 0.5).max(0.0).min(1.0);
+        [remap(self.temperature.get2(x, z)), remap(self.humidity.get2(x, z))]
+    }
+
+    pub fn sample(&self, x: i32, z: i32) -> BiomeSample {
+        BiomeMap::sample_climate(self.climate_at(x, z))
+    }
+
+    /// The biomes for a column with climate `climate`.
+    pub fn sample_climate(climate: [f32; 2]) -> BiomeSample {
+        let mut distances = [0.0; 4];
+        for (distance, biome) in distances.iter_mut().zip(&Biome::ALL) {
+            let center = biome.climate();
+            let (dt, dh) = (climate[0] - center[0], climate[1] - center[1]);
+            *distance = (dt * dt + dh * dh).sqrt();
+        }
+        let (closest, nearest) = distances
+            .iter()
+            .cloned()
+            .enumerate()
+            .fold((0, ::std::f32::INFINITY), |best, (i, distance)| if distance < best.1 { (i, distance) } else { best });
+
+        let mut weights = [0.0; 4];
+        let mut total = 0.0;
+        for (weight, &distance) in weights.iter_mut().zip(&distances) {
+            let falloff = (1.0 - (distance - nearest) / BLEND).max(0.0);
+            *weight = falloff * falloff;
+            total += *weight;
+        }
+        for weight in &mut weights {
+            *weight /= total;
+        }
+
+        BiomeSample {
+            biome: Biome::ALL[closest],
+            weights,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_biomes_own_climate_is_all_that_biome() {
+        for (i, &biome) in Biome::ALL.iter().enumerate() {
+            let sample = BiomeMap::sample_climate(biome.climate());
+            assert_eq!(sample.biome, biome);
+            assert_eq!(sample.weights[i], 1.0);
+        }
+    }
+
+    #[test]
+    fn weights_add_up_to_one_and_blend_near_borders() {
+        // Halfway between the plains and the desert
+        let sample = BiomeMap::sample_climate([0.725, 0.275]);
+        assert!((sample.weights.iter().sum::<f32>() - 1.0).abs() < 1e-5);
+        assert!((sample.weights[0] - 0.5).abs() < 0.01 && (sample.weights[1] - 0.5).abs() < 0.01);
+        assert_eq!(sample.weights[3], 0.0);
+    }
+
+    #[test]
+    fn every_biome_turns_up_somewhere() {
+        let map = BiomeMap::new(3);
+        let mut seen = Vec::new();
+        for i in 0..200 {
+            for j in 0..200 {
+                let biome = map.sample(i * 64 - 6400, j * 64 - 6400).biome;
+                if !seen.contains(&biome) {
+                    seen.push(biome);
+                }
+            }
+        }
+        assert_eq!(seen.len(), Biome::ALL.len(), "only saw {:?}", seen);
+    }
+}
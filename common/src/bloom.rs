@@ -0,0 +1,258 @@
+//! Bloom, the glow that bleeds out around anything brighter than the screen can show.
+//!
+//! A bright pass keeps only the part of the HDR scene over a threshold, at half resolution, as
+//! the first mip of a chain. Each mip after that is a filtered downsample of the one before, so
+//! the light spreads a little further with each. Then the chain is walked back up, each mip
+//! blurred up to the size of the one above and added onto it, which sums every size of glow
+//! into the first mip without any one wide blur. Tonemapping adds that on top of the scene.
+//!
+//! `Bloom` owns a chain for each swapchain image, with a view and framebuffer for every mip,
+//! and the two render passes that draw into them: one that clears a mip on the way down, and
+//! one that blends onto what's already there on the way up. `mip_count` and `mip_extent` work
+//! out how big the chain is.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hal::{
+    format as f, image as i, memory, pso,
+    Backend, Device, SwapImageIndex,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use context::GfxContext;
+use error::Result;
+use hdr::HDR_FORMAT;
+use pass::{ create_blend_render_pass, create_offscreen_render_pass };
+use resources::SwapchainBundle;
+use samplers::SamplerDesc;
+
+/// The most mips a chain can have. Past this the smallest are only a few pixels across even on
+/// a large screen, and add nothing but cost.
+pub const MAX_BLOOM_MIPS: u32 = 8;
+
+/// One swapchain image's mip chain.
+struct BloomChain<B: Backend> {
+    image: B::Image,
+    /// One view and framebuffer per mip, for reading it and drawing into it.
+    views: Vec<B::ImageView>,
+    framebuffers: Vec<B::Framebuffer>,
+    allocation: Allocation,
+}
+
+/// A bloom mip chain for each swapchain image, and what's needed to draw them and read them.
+/// Like the other per-frame attachments, it has to be recreated along with the swapchain, and
+/// whenever the number of mips changes.
+pub struct Bloom<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    mips: u32,
+    /// The size of each mip, the same in every chain.
+    extents: Vec<(u32, u32)>,
+    downsample_pass: Option<B::RenderPass>,
+    upsample_pass: Option<B::RenderPass>,
+    sampler: Rc<B::Sampler>,
+    chains: Vec<BloomChain<B>>,
+}
+
+impl<B: Backend> Bloom<B> {
+    /// Chains of `mips` mips, down to as many as fit the swapchain. 0 turns bloom off, though
+    /// there's still a single mip to clear, for tonemapping to have something to read.
+    pub fn new(context: &GfxContext<B>, swapchain: &SwapchainBundle<B>, mips: u32) -> Result<Self> {
+        let device = context.device.clone();
+        let downsample_pass = create_offscreen_render_pass::<B>(&device, HDR_FORMAT);
+        let upsample_pass = create_blend_render_pass::<B>(&device, HDR_FORMAT);
+        // Filtering does half the work of each blur, since every tap blends four texels
+        let sampler = context.samplers.borrow_mut().get(SamplerDesc::new(i::Filter::Linear, i::WrapMode::Clamp));
+
+        let mut bloom = Bloom {
+            device,
+            allocator: context.allocator.clone(),
+            mips: 0,
+            extents: Vec::new(),
+            downsample_pass: Some(downsample_pass),
+            upsample_pass: Some(upsample_pass),
+            sampler,
+            chains: Vec::new(),
+        };
+        bloom.recreate(swapchain, mips)?;
+        Ok(bloom)
+    }
+
+    /// Rebuilds the chains to match `swapchain`'s size and image count, with `mips` mips.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>, mips: u32) -> Result<()> {
+        self.destroy_chains();
+
+        let extent = swapchain.extent();
+        self.mips = mip_count(mips, extent.width, extent.height);
+        // Even with bloom off there's a mip for tonemapping to read
+        let levels = self.mips.max(1);
+        self.extents = (0..levels).map(|level| mip_extent(extent.width, extent.height, level)).collect();
+        let (width, height) = self.extents[0];
+
+        for _ in 0..swapchain.frame_images().len() {
+            let unbound = self.device.create_image(
+                i::Kind::D2(width, height, 1, 1),
+                levels as i::Level,
+                HDR_FORMAT,
+                i::Tiling::Optimal,
+                i::Usage::COLOR_ATTACHMENT | i::Usage::SAMPLED,
+                i::ViewCapabilities::empty(),
+            )?;
+            let requirements = self.device.get_image_requirements(&unbound);
+
+            let (image, allocation) = {
+                let mut allocator = self.allocator.borrow_mut();
+                let allocation = allocator
+                    .allocate(
+                        &requirements,
+                        memory::Properties::DEVICE_LOCAL,
+                        ResourceKind::Optimal,
+                        MemoryCategory::Attachments,
+                    )?;
+                let image = self.device
+                    .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+                (image, allocation)
+            };
+
+            let mut views = Vec::with_capacity(levels as usize);
+            let mut framebuffers = Vec::with_capacity(levels as usize);
+            for level in 0..levels {
+                let range = i::SubresourceRange {
+                    aspects: f::Aspects::COLOR,
+                    levels: level as i::Level..level as i::Level + 1,
+                    layers: 0..1,
+                };
+                let view = self.device.create_image_view(&image, i::ViewKind::D2, HDR_FORMAT, f::Swizzle::NO, range)?;
+                let (width, height) = self.extents[level as usize];
+                // Both passes draw into the same framebuffers, which their one attachment
+                // matching makes them compatible with
+                let framebuffer = self.device.create_framebuffer(
+                    self.downsample_pass(),
+                    Some(&view),
+                    i::Extent { width, height, depth: 1 },
+                )?;
+                views.push(view);
+                framebuffers.push(framebuffer);
+            }
+
+            self.chains.push(BloomChain { image, views, framebuffers, allocation });
+        }
+        Ok(())
+    }
+
+    /// How many mips are drawn, which is 0 with bloom off.
+    pub fn mips(&self) -> u32 {
+        self.mips
+    }
+
+    /// Clears the mip it draws into, for the bright pass and the downsamples.
+    pub fn downsample_pass(&self) -> &B::RenderPass {
+        self.downsample_pass.as_ref().unwrap()
+    }
+
+    /// Keeps what's already in the mip it draws into, for the upsamples to add onto.
+    pub fn upsample_pass(&self) -> &B::RenderPass {
+        self.upsample_pass.as_ref().unwrap()
+    }
+
+    /// For drawing mip `level` of swapchain image `image_index`'s chain.
+    pub fn framebuffer(&self, image_index: SwapImageIndex, level: u32) -> &B::Framebuffer {
+        &self.chains[image_index as usize].framebuffers[level as usize]
+    }
+
+    /// Mip `level` of swapchain image `index`'s chain, for sampling once it's been drawn. Mip 0
+    /// holds all of the bloom at the end.
+    pub fn view(&self, index: usize, level: u32) -> &B::ImageView {
+        &self.chains[index].views[level as usize]
+    }
+
+    /// Filters linearly and clamps to the edge.
+    pub fn sampler(&self) -> &B::Sampler {
+        &self.sampler
+    }
+
+    /// The size of mip `level`, in pixels.
+    pub fn extent(&self, level: u32) -> (u32, u32) {
+        self.extents[level as usize]
+    }
+
+    /// A viewport covering the whole of mip `level`.
+    pub fn viewport(&self, level: u32) -> pso::Viewport {
+        let (width, height) = self.extent(level);
+        pso::Viewport {
+            rect: pso::Rect { x: 0, y: 0, w: width as i16, h: height as i16 },
+            depth: 0.0..1.0,
+        }
+    }
+
+    fn destroy_chains(&mut self) {
+        for chain in self.chains.drain(..) {
+            for framebuffer in chain.framebuffers {
+                self.device.destroy_framebuffer(framebuffer);
+            }
+            for view in chain.views {
+                self.device.destroy_image_view(view);
+            }
+            self.device.destroy_image(chain.image);
+            self.allocator.borrow_mut().free(chain.allocation);
+        }
+    }
+}
+
+impl<B: Backend> Drop for Bloom<B> {
+    fn drop(&mut self) {
+        self.destroy_chains();
+        if let Some(render_pass) = self.upsample_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+        if let Some(render_pass) = self.downsample_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
+
+/// How many mips of the `requested` number a chain for a `width` by `height` screen can have,
+/// up to `MAX_BLOOM_MIPS`. Each mip has to be at least 2 pixels along each side, so there's
+/// still something to blur from the one before.
+pub fn mip_count(requested: u32, width: u32, height: u32) -> u32 {
+    let mut count = 0;
+    while count < requested.min(MAX_BLOOM_MIPS) {
+        let (mip_width, mip_height) = mip_extent(width, height, count);
+        if mip_width < 2 || mip_height < 2 {
+            break;
+        }
+        count += 1;
+    }
+    count
+}
+
+/// The size of mip `level` of a chain for a `width` by `height` screen. Mip 0 is half the size
+/// of the screen, rounded down, and each mip after is half the one before.
+pub fn mip_extent(width: u32, height: u32, level: u32) -> (u32, u32) {
+    let shift = level + 1;
+    ((width >> shift).max(1), (height >> shift).max(1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_mip_is_half_the_one_before() {
+        assert_eq!(mip_extent(1280, 720, 0), (640, 360));
+        assert_eq!(mip_extent(1280, 720, 1), (320, 180));
+        assert_eq!(mip_extent(1281, 721, 0), (640, 360));
+        assert_eq!(mip_extent(5, 3, 3), (1, 1));
+    }
+
+    #[test]
+    fn chains_stop_before_the_mips_get_too_small() {
+        assert_eq!(mip_count(5, 1280, 720), 5);
+        assert_eq!(mip_count(100, 1280, 720), MAX_BLOOM_MIPS);
+        // 16 by 8, then 8 by 4, 4 by 2, and 2 by 1 is too small
+        assert_eq!(mip_count(8, 32, 16), 3);
+        assert_eq!(mip_count(8, 2, 2), 0);
+        assert_eq!(mip_count(0, 1280, 720), 0);
+    }
+}
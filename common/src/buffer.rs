@@ -0,0 +1,323 @@
+//! Creating buffers and getting data into them.
+//!
+//! `DeviceBuffer::write_at` maps the memory, copies and unmaps it again, which is fine now and
+//! then but is a driver call or two every time. Data that's written every frame can go in a
+//! `MappedBuffer` instead, which maps its memory once and keeps the pointer. What's written
+//! through the pointer is only guaranteed to reach the gpu as it is if the memory type is
+//! `COHERENT`. Otherwise every write has to be flushed before the gpu reads it, in whole
+//! multiples of the device's `nonCoherentAtomSize`, and a `MappedBuffer` does that itself when
+//! the memory it got needs it.
+
+use std::cell::RefCell;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+
+use hal::{
+    buffer, command, memory,
+    pso::PipelineStage,
+    Backend, Device,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use context::GfxContext;
+use error::Result;
+
+/// A buffer along with the memory backing it, both of which are released on drop.
+pub struct DeviceBuffer<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    buffer: Option<B::Buffer>,
+    allocation: Option<Allocation>,
+    size: u64,
+}
+
+impl<B: Backend> DeviceBuffer<B> {
+    /// Creates a buffer of `size` bytes and binds it to memory with `properties` from
+    /// `allocator`, counted under `category`.
+    pub fn new(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        size: u64,
+        usage: buffer::Usage,
+        properties: memory::Properties,
+        category: MemoryCategory,
+    ) -> Result<Self> {
+        let unbound = device.create_buffer(size, usage)?;
+        let requirements = device.get_buffer_requirements(&unbound);
+
+        let (buffer, allocation) = {
+            let mut allocator = allocator.borrow_mut();
+            let allocation = allocator.allocate(&requirements, properties, ResourceKind::Linear, category)?;
+            let buffer = device
+                .bind_buffer_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+            (buffer, allocation)
+        };
+
+        Ok(DeviceBuffer {
+            device,
+            allocator,
+            buffer: Some(buffer),
+            allocation: Some(allocation),
+            size,
+        })
+    }
+
+    /// Copies `data` into the start of the buffer. The buffer must be host visible.
+    pub fn write<T: Copy>(&self, data: &[T]) -> Result<()> {
+        self.write_at(0, data)
+    }
+
+    /// Copies `data` into the buffer starting `offset` bytes in. The buffer must be host visible.
+    pub fn write_at<T: Copy>(&self, offset: u64, data: &[T]) -> Result<()> {
+        let bytes = (data.len() * mem::size_of::<T>()) as u64;
+        assert!(offset + bytes <= self.size, "Data does not fit in the buffer");
+
+        let allocator = self.allocator.borrow();
+        let allocation = self.allocation();
+        let start = allocation.offset() + offset;
+        let mut writer = self.device
+            .acquire_mapping_writer::<T>(allocator.memory(allocation), start..start + bytes)?;
+        writer[..data.len()].copy_from_slice(data);
+        self.device.release_mapping_writer(writer);
+        Ok(())
+    }
+
+    /// Copies the whole buffer out into a `Vec`. The buffer must be host visible, and the gpu
+    /// has to be done writing to it.
+    pub fn read<T: Copy>(&self) -> Result<Vec<T>> {
+        let count = self.size / mem::size_of::<T>() as u64;
+
+        let allocator = self.allocator.borrow();
+        let allocation = self.allocation();
+        let start = allocation.offset();
+        let end = start + count * mem::size_of::<T>() as u64;
+        let reader = self.device.acquire_mapping_reader::<T>(allocator.memory(allocation), start..end)?;
+        let data = reader[..count as usize].to_vec();
+        self.device.release_mapping_reader(reader);
+        Ok(data)
+    }
+
+    pub fn buffer(&self) -> &B::Buffer {
+        self.buffer.as_ref().unwrap()
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        self.allocation.as_ref().unwrap()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<B: Backend> Drop for DeviceBuffer<B> {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.device.destroy_buffer(buffer);
+        }
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.borrow_mut().free(allocation);
+        }
+    }
+}
+
+/// The largest `nonCoherentAtomSize` Vulkan allows. Flushing whole multiples of it is always
+/// enough, whatever the device's really is.
+pub const NON_COHERENT_ATOM_SIZE: u64 = 256;
+
+/// A host visible buffer whose memory stays mapped for as long as it's alive. It gets a `Memory`
+/// object of its own, since one can only be mapped once at a time.
+pub struct MappedBuffer<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    buffer: Option<B::Buffer>,
+    allocation: Option<Allocation>,
+    size: u64,
+    /// Points at the start of the allocation.
+    mapped: *mut u8,
+    /// Whether writes have to be flushed before the gpu sees them.
+    needs_flush: bool,
+}
+
+impl<B: Backend> MappedBuffer<B> {
+    /// Creates a buffer of `size` bytes in host visible memory, counted under `category`, and
+    /// maps it. Coherent memory isn't asked for, so the first host visible type is used whether
+    /// it's coherent or not.
+    pub fn new(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        size: u64,
+        usage: buffer::Usage,
+        category: MemoryCategory,
+    ) -> Result<Self> {
+        let unbound = device.create_buffer(size, usage)?;
+        let requirements = device.get_buffer_requirements(&unbound);
+
+        let (buffer, allocation, mapped, needs_flush) = {
+            let mut allocator = allocator.borrow_mut();
+            let allocation = allocator.allocate_dedicated(
+                &requirements,
+                memory::Properties::CPU_VISIBLE,
+                ResourceKind::Linear,
+                category,
+            )?;
+            let needs_flush = !allocator.memory_types()[allocation.memory_type().0]
+                .properties
+                .contains(memory::Properties::COHERENT);
+            let memory = allocator.memory(&allocation);
+            let buffer = device.bind_buffer_memory(memory, allocation.offset(), unbound)?;
+            let mapped = device.map_memory(memory, allocation.range())?;
+            (buffer, allocation, mapped, needs_flush)
+        };
+        debug!(
+            "Mapped a {} byte {:?} buffer in {} memory",
+            size,
+            usage,
+            if needs_flush { "non-coherent" } else { "coherent" },
+        );
+
+        Ok(MappedBuffer {
+            device,
+            allocator,
+            buffer: Some(buffer),
+            allocation: Some(allocation),
+            size,
+            mapped,
+            needs_flush,
+        })
+    }
+
+    /// Copies `data` into the buffer starting `offset` bytes in, and flushes it if the memory
+    /// isn't coherent. The gpu can't be reading that part of the buffer.
+    pub fn write_at<T: Copy>(&self, offset: u64, data: &[T]) -> Result<()> {
+        let bytes = (data.len() * mem::size_of::<T>()) as u64;
+        assert!(offset + bytes <= self.size, "Data does not fit in the buffer");
+
+        // The pointer may not be aligned for `T`, so this goes byte by byte
+        unsafe {
+            ptr::copy_nonoverlapping(
+                data.as_ptr() as *const u8,
+                self.mapped.add(offset as usize),
+                bytes as usize,
+            );
+        }
+        self.flush(offset, bytes);
+        Ok(())
+    }
+
+    /// Makes `bytes` bytes written through the mapping from `offset` on visible to the gpu, if the
+    /// memory needs telling. The range is widened out to whole atoms, or to the end of the
+    /// memory, which is where the allocation ends since it has the memory to itself.
+    pub fn flush(&self, offset: u64, bytes: u64) {
+        if !self.needs_flush || bytes == 0 {
+            return;
+        }
+        let allocator = self.allocator.borrow();
+        let allocation = self.allocation();
+        let start = (allocation.offset() + offset) / NON_COHERENT_ATOM_SIZE * NON_COHERENT_ATOM_SIZE;
+        let end = allocation.offset() + offset + bytes;
+        let end = ((end + NON_COHERENT_ATOM_SIZE - 1) / NON_COHERENT_ATOM_SIZE * NON_COHERENT_ATOM_SIZE)
+            .min(allocation.range().end);
+        self.device.flush_mapped_memory_ranges(Some((allocator.memory(allocation), start..end)));
+    }
+
+    /// Whether writes are flushed, because the memory isn't coherent.
+    pub fn needs_flush(&self) -> bool {
+        self.needs_flush
+    }
+
+    pub fn buffer(&self) -> &B::Buffer {
+        self.buffer.as_ref().unwrap()
+    }
+
+    pub fn allocation(&self) -> &Allocation {
+        self.allocation.as_ref().unwrap()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+impl<B: Backend> Drop for MappedBuffer<B> {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.device.destroy_buffer(buffer);
+        }
+        if let Some(allocation) = self.allocation.take() {
+            let mut allocator = self.allocator.borrow_mut();
+            self.device.unmap_memory(allocator.memory(&allocation));
+            allocator.free(allocation);
+        }
+    }
+}
+
+/// Uploads `data` into a new device local buffer with `usage`, counted under `category`.
+///
+/// The data is first written into the context's staging ring, or a staging buffer of its own if
+/// it's too big for that, then copied over with a one-shot transfer command buffer. This waits
+/// for the copy to finish, so it's meant for loading time rather than for data that changes
+/// every frame.
+pub fn upload_buffer<B: Backend, T: Copy>(
+    context: &mut GfxContext<B>,
+    data: &[T],
+    usage: buffer::Usage,
+    category: MemoryCategory,
+) -> Result<DeviceBuffer<B>> {
+    let size = (data.len() * mem::size_of::<T>()) as u64;
+    let device_buffer = DeviceBuffer::new(
+        context.device.clone(),
+        context.allocator.clone(),
+        size,
+        usage | buffer::Usage::TRANSFER_DST,
+        memory::Properties::DEVICE_LOCAL,
+        category,
+    )?;
+    upload_into(context, data, &device_buffer, 0)?;
+    debug!("Uploaded {} bytes into a {:?} buffer", size, usage);
+    Ok(device_buffer)
+}
+
+/// Uploads `data` into `target` starting `offset` bytes in, the same way as `upload_buffer`.
+/// `target` has to have been made with `TRANSFER_DST` usage, and the gpu can't be using that
+/// part of it.
+pub fn upload_into<B: Backend, T: Copy>(
+    context: &mut GfxContext<B>,
+    data: &[T],
+    target: &DeviceBuffer<B>,
+    offset: u64,
+) -> Result<()> {
+    let size = (data.len() * mem::size_of::<T>()) as u64;
+    assert!(offset + size <= target.size(), "Data does not fit in the buffer");
+
+    context.submit_staged(data, |command_buffer, staging, src| {
+        command_buffer.copy_buffer(staging, target.buffer(), &[command::BufferCopy { src, dst: offset, size }]);
+        // Make the copied data visible to whatever reads the buffer next
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..(PipelineStage::VERTEX_INPUT | PipelineStage::VERTEX_SHADER | PipelineStage::FRAGMENT_SHADER),
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Buffer {
+                states: buffer::Access::TRANSFER_WRITE
+                    ..(buffer::Access::VERTEX_BUFFER_READ | buffer::Access::INDEX_BUFFER_READ
+                        | buffer::Access::UNIFORM_READ | buffer::Access::SHADER_READ),
+                target: target.buffer(),
+            }],
+        );
+    })
+}
+
+/// Like `upload_into`, but on the transfer queue if there is one, where the copy is submitted
+/// with the next frame rather than waited for. Whatever reads `target` has to wait on the
+/// semaphore from `TransferQueue::submit` first.
+pub fn queue_upload_into<B: Backend, T: Copy>(
+    context: &mut GfxContext<B>,
+    data: &[T],
+    target: &DeviceBuffer<B>,
+    offset: u64,
+) -> Result<()> {
+    if let Some(ref mut transfer) = context.transfer {
+        return transfer.upload_into(data, target, offset);
+    }
+    upload_into(context, data, target, offset)
+}
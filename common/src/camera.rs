@@ -0,0 +1,406 @@
+//! Cameras, and the controllers that move them.
+//!
+//! `FpsCamera` flies freely: the move actions (WASD, Space and Shift by default) move it along
+//! the way it's facing and straight up and down, and the mouse looks around while the cursor is
+//! grabbed or `Look` is held. `OrbitCamera` circles a focus point instead, for looking at one
+//! thing from every side: the mouse orbits and the wheel zooms. Both implement `Camera`, so render code
+//! only needs `view_projection` and doesn't care which is in use. `CameraSwitch` holds one of
+//! each and flips between them on `SwitchCamera`.
+//!
+//! Chapters call `handle_input` once a frame with the frame's `Input`, and `update` once per
+//! simulation tick, then upload `view_projection` for their shaders.
+
+use std::f32::consts::FRAC_PI_2;
+
+use config::Settings;
+use input::{ Action, Input };
+use math::{ self, Frustum, Mat4, Rad, Vec3, HAL_CLIP_SPACE };
+use timestep::Lerp;
+
+/// How far a camera can look up or down, just short of straight up so the view never flips.
+pub const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+
+/// How fast the FPS camera flies, in units (blocks) per second.
+pub const DEFAULT_SPEED: f32 = 5.0;
+
+/// How close the orbit camera can get to its focus point.
+pub const MIN_ORBIT_DISTANCE: f32 = 0.5;
+
+/// How much one line of mouse wheel movement zooms the orbit camera in or out.
+const ZOOM_PER_LINE: f32 = 0.9;
+
+/// The shape of a camera's view volume.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Projection {
+    /// The vertical field of view, in radians.
+    pub fov_y: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Projection {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Projection {
+            fov_y: settings.fov.to_radians(),
+            near: settings.near,
+            far: settings.far,
+        }
+    }
+
+    /// A right handed perspective projection looking down -z, for gfx-hal's clip space.
+    pub fn matrix(&self, aspect: f32) -> Mat4 {
+        math::perspective(Rad(self.fov_y), aspect, self.near, self.far, HAL_CLIP_SPACE)
+    }
+}
+
+/// What render code needs from a camera, whichever way it's being controlled.
+pub trait Camera {
+    /// Turns the camera with this frame's mouse movement, and anything else that should happen
+    /// as soon as the input arrives rather than on the next tick.
+    fn handle_input(&mut self, input: &Input);
+
+    /// Moves the camera on by `seconds`, normally one `timestep::TICK_SECONDS` tick, going by
+    /// the actions held in `input`.
+    fn update(&mut self, input: &Input, seconds: f32);
+
+    /// Picks up changes to the field of view, clip planes and mouse sensitivity.
+    fn apply_settings(&mut self, settings: &Settings);
+
+    /// Where the camera is, in world space.
+    fn position(&self) -> [f32; 3];
+
+    /// The unit vector the camera is looking along, in world space.
+    fn look_direction(&self) -> [f32; 3];
+
+    /// Transforms world space into view space, where the camera is at the origin looking
+    /// down -z with +y up.
+    fn view(&self) -> Mat4;
+
+    fn projection(&self) -> Projection;
+
+    /// The view to render `alpha` of the way from before the last `update` to after it. Only
+    /// movement is interpolated: looking around happens as soon as the mouse moves rather than
+    /// in ticks, so the latest orientation is always used.
+    fn interpolated_view(&self, _alpha: f32) -> Mat4 {
+        self.view()
+    }
+
+    /// The projection and view together, for a viewport `aspect` times wider than it is tall.
+    fn view_projection(&self, aspect: f32) -> Mat4 {
+        self.projection().matrix(aspect) * self.view()
+    }
+
+    /// `view_projection` with the view from `interpolated_view`.
+    fn interpolated_view_projection(&self, aspect: f32, alpha: f32) -> Mat4 {
+        self.projection().matrix(aspect) * self.interpolated_view(alpha)
+    }
+
+    /// What can be seen with the view from `interpolated_view`, for culling what's drawn.
+    fn frustum(&self, aspect: f32, alpha: f32) -> Frustum {
+        Frustum::from_matrix(&self.interpolated_view_projection(aspect, alpha), HAL_CLIP_SPACE)
+    }
+}
+
+pub struct FpsCamera {
+    pub position: [f32; 3],
+    /// Where the camera was before the last `update`.
+    previous_position: [f32; 3],
+    /// Radians clockwise from looking down -z, seen from above.
+    yaw: f32,
+    /// Radians above the horizon, between `-MAX_PITCH` and `MAX_PITCH`.
+    pitch: f32,
+    pub projection: Projection,
+    /// Units per second.
+    pub speed: f32,
+    /// Radians turned per pixel the mouse moves.
+    pub sensitivity: f32,
+}
+
+impl FpsCamera {
+    /// A camera at `position` looking down -z, set up from `settings`.
+    pub fn new(position: [f32; 3], settings: &Settings) -> Self {
+        FpsCamera {
+            position,
+            previous_position: position,
+            yaw: 0.0,
+            pitch: 0.0,
+            projection: Projection::from_settings(settings),
+            speed: DEFAULT_SPEED,
+            sensitivity: settings.mouse_sensitivity.to_radians(),
+        }
+    }
+
+    pub fn yaw(&self) -> f32 {
+        self.yaw
+    }
+
+    pub fn pitch(&self) -> f32 {
+        self.pitch
+    }
+
+    /// Turns the camera by a mouse movement of `dx` by `dy` pixels. Moving the mouse up (a
+    /// negative `dy`) looks up.
+    pub fn look(&mut self, dx: f32, dy: f32) {
+        let sensitivity = self.sensitivity;
+        self.turn(dx * sensitivity, -dy * sensitivity);
+    }
+
+    /// Turns the camera right by `yaw` and up by `pitch` radians.
+    pub fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.yaw += yaw;
+        self.pitch = clamp_pitch(self.pitch + pitch);
+    }
+
+    /// The unit vector the camera is looking along.
+    pub fn forward(&self) -> Vec3 {
+        forward(self.yaw, self.pitch)
+    }
+
+    /// The unit vector pointing to the camera's right, which is always level.
+    pub fn right(&self) -> Vec3 {
+        right(self.yaw)
+    }
+}
+
+impl Camera for FpsCamera {
+    fn handle_input(&mut self, input: &Input) {
+        if input.is_looking() {
+            let (dx, dy) = input.mouse_delta();
+            self.look(dx, dy);
+        }
+        let (yaw, pitch) = input.stick_look();
+        self.turn(yaw, pitch);
+    }
+
+    fn update(&mut self, input: &Input, seconds: f32) {
+        // The stick adds to the keys rather than replacing them, but together they're no faster
+        // than either on its own
+        let (stick_x, stick_y) = input.move_stick();
+        let forward_keys = input.axis(Action::MoveForward, Action::MoveBack);
+        let right_keys = input.axis(Action::MoveRight, Action::MoveLeft);
+        let forward_amount = clamp_unit(forward_keys + stick_y);
+        let right_amount = clamp_unit(right_keys + stick_x);
+        let up_amount = input.axis(Action::MoveUp, Action::MoveDown);
+
+        self.previous_position = self.position;
+        let direction = self.forward() * forward_amount
+            + self.right() * right_amount
+            + Vec3::unit_y() * up_amount;
+        let distance = self.speed * seconds;
+        self.position = (Vec3::from(self.position) + direction * distance).into();
+    }
+
+    fn apply_settings(&mut self, settings: &Settings) {
+        self.projection = Projection::from_settings(settings);
+        self.sensitivity = settings.mouse_sensitivity.to_radians();
+    }
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn look_direction(&self) -> [f32; 3] {
+        self.forward().into()
+    }
+
+    fn view(&self) -> Mat4 {
+        view(self.position, self.yaw, self.pitch)
+    }
+
+    fn interpolated_view(&self, alpha: f32) -> Mat4 {
+        view(self.previous_position.lerp(&self.position, alpha), self.yaw, self.pitch)
+    }
+
+    fn projection(&self) -> Projection {
+        self.projection
+    }
+}
+
+pub struct OrbitCamera {
+    /// The point the camera circles and looks at.
+    pub focus: [f32; 3],
+    /// How far the camera is from `focus`.
+    distance: f32,
+    /// Radians clockwise from looking down -z, seen from above.
+    yaw: f32,
+    /// Radians above the horizon, between `-MAX_PITCH` and `MAX_PITCH`.
+    pitch: f32,
+    pub projection: Projection,
+    /// Radians orbited per pixel the mouse moves.
+    pub sensitivity: f32,
+}
+
+impl OrbitCamera {
+    /// A camera `distance` away from `focus`, looking at it down -z.
+    pub fn new(focus: [f32; 3], distance: f32, settings: &Settings) -> Self {
+        OrbitCamera {
+            focus,
+            distance: distance.max(MIN_ORBIT_DISTANCE),
+            yaw: 0.0,
+            pitch: 0.0,
+            projection: Projection::from_settings(settings),
+            sensitivity: settings.mouse_sensitivity.to_radians(),
+        }
+    }
+
+    pub fn distance(&self) -> f32 {
+        self.distance
+    }
+
+    /// Moves towards the focus point for a positive number of lines, and away for a negative
+    /// one. The camera never gets closer than `MIN_ORBIT_DISTANCE`, or further away than the far
+    /// plane would let it see the focus.
+    pub fn zoom(&mut self, lines: f32) {
+        self.distance = (self.distance * ZOOM_PER_LINE.powf(lines))
+            .max(MIN_ORBIT_DISTANCE)
+            .min(self.projection.far * 0.5);
+    }
+
+    /// Orbits by a mouse movement of `dx` by `dy` pixels, as if dragging the scene around.
+    pub fn orbit(&mut self, dx: f32, dy: f32) {
+        let sensitivity = self.sensitivity;
+        self.turn(dx * sensitivity, dy * sensitivity);
+    }
+
+    /// Turns the camera right by `yaw` and up by `pitch` radians while keeping it pointed at the
+    /// focus, so it swings around the other way.
+    pub fn turn(&mut self, yaw: f32, pitch: f32) {
+        self.yaw += yaw;
+        self.pitch = clamp_pitch(self.pitch + pitch);
+    }
+}
+
+impl Camera for OrbitCamera {
+    fn handle_input(&mut self, input: &Input) {
+        if input.is_looking() {
+            let (dx, dy) = input.mouse_delta();
+            self.orbit(dx, dy);
+        }
+        let (yaw, pitch) = input.stick_look();
+        self.turn(yaw, pitch);
+        self.zoom(input.wheel_lines());
+    }
+
+    fn update(&mut self, _input: &Input, _seconds: f32) {}
+
+    fn apply_settings(&mut self, settings: &Settings) {
+        self.projection = Projection::from_settings(settings);
+        self.sensitivity = settings.mouse_sensitivity.to_radians();
+    }
+
+    fn position(&self) -> [f32; 3] {
+        let forward = forward(self.yaw, self.pitch);
+        (Vec3::from(self.focus) - forward * self.distance).into()
+    }
+
+    fn look_direction(&self) -> [f32; 3] {
+        forward(self.yaw, self.pitch).into()
+    }
+
+    fn view(&self) -> Mat4 {
+        view(self.position(), self.yaw, self.pitch)
+    }
+
+    fn projection(&self) -> Projection {
+        self.projection
+    }
+}
+
+/// An FPS camera and an orbit camera, with `SwitchCamera` switching between them. Only the one
+/// in use gets input and updates, so the other stays where it was left.
+pub struct CameraSwitch {
+    pub fps: FpsCamera,
+    pub orbit: OrbitCamera,
+    orbiting: bool,
+}
+
+impl CameraSwitch {
+    /// Starts out using the FPS camera.
+    pub fn new(fps: FpsCamera, orbit: OrbitCamera) -> Self {
+        CameraSwitch { fps, orbit, orbiting: false }
+    }
+
+    pub fn is_orbiting(&self) -> bool {
+        self.orbiting
+    }
+
+    pub fn set_orbiting(&mut self, orbiting: bool) {
+        if orbiting != self.orbiting {
+            info!("Switched to the {} camera", if orbiting { "orbit" } else { "FPS" });
+        }
+        self.orbiting = orbiting;
+    }
+
+    pub fn active(&self) -> &Camera {
+        if self.orbiting { &self.orbit } else { &self.fps }
+    }
+
+    pub fn active_mut(&mut self) -> &mut Camera {
+        if self.orbiting { &mut self.orbit } else { &mut self.fps }
+    }
+}
+
+impl Camera for CameraSwitch {
+    fn handle_input(&mut self, input: &Input) {
+        if input.was_pressed(Action::SwitchCamera) {
+            let orbiting = !self.orbiting;
+            self.set_orbiting(orbiting);
+        }
+        self.active_mut().handle_input(input);
+    }
+
+    fn update(&mut self, input: &Input, seconds: f32) {
+        self.active_mut().update(input, seconds);
+    }
+
+    fn apply_settings(&mut self, settings: &Settings) {
+        self.fps.apply_settings(settings);
+        self.orbit.apply_settings(settings);
+    }
+
+    fn position(&self) -> [f32; 3] {
+        self.active().position()
+    }
+
+    fn look_direction(&self) -> [f32; 3] {
+        self.active().look_direction()
+    }
+
+    fn view(&self) -> Mat4 {
+        self.active().view()
+    }
+
+    fn interpolated_view(&self, alpha: f32) -> Mat4 {
+        self.active().interpolated_view(alpha)
+    }
+
+    fn projection(&self) -> Projection {
+        self.active().projection()
+    }
+}
+
+fn clamp_pitch(pitch: f32) -> f32 {
+    pitch.max(-MAX_PITCH).min(MAX_PITCH)
+}
+
+fn clamp_unit(amount: f32) -> f32 {
+    amount.max(-1.0).min(1.0)
+}
+
+/// The unit vector a camera with this yaw and pitch is looking along.
+fn forward(yaw: f32, pitch: f32) -> Vec3 {
+    let (sin_yaw, cos_yaw) = yaw.sin_cos();
+    let (sin_pitch, cos_pitch) = pitch.sin_cos();
+    Vec3::new(sin_yaw * cos_pitch, sin_pitch, -cos_yaw * cos_pitch)
+}
+
+/// The unit vector pointing to the right of a camera with this yaw. It's always level.
+fn right(yaw: f32) -> Vec3 {
+    let (sin_yaw, cos_yaw) = yaw.sin_cos();
+    Vec3::new(cos_yaw, 0.0, sin_yaw)
+}
+
+/// The view matrix for a camera at `position` with this yaw and pitch.
+fn view(position: [f32; 3], yaw: f32, pitch: f32) -> Mat4 {
+    math::look_along(position, forward(yaw, pitch), Vec3::unit_y())
+}
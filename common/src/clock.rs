@@ -0,0 +1,42 @@
+//! The time chapters animate by.
+//!
+//! Normally that's how long the example has been running. Headless runs step it on by a fixed
+//! amount each frame instead, so that frame `n` always looks the same however fast the machine
+//! renders it, which the render tests rely on.
+
+use std::time::Instant;
+
+/// How far the clock moves each frame in headless mode, as if running at 60 fps.
+pub const HEADLESS_FRAME_SECONDS: f32 = 1.0 / 60.0;
+
+pub struct Clock {
+    start: Instant,
+    /// How many frames have been timed so far, in headless mode.
+    headless_frames: Option<u32>,
+}
+
+impl Clock {
+    pub fn new(headless: bool) -> Self {
+        Clock {
+            start: Instant::now(),
+            headless_frames: if headless { Some(0) } else { None },
+        }
+    }
+
+    /// Seconds since the clock was created, for animating the current frame. Call this once per
+    /// frame: in headless mode each call moves the clock on by `HEADLESS_FRAME_SECONDS`, starting
+    /// from zero.
+    pub fn frame_seconds(&mut self) -> f32 {
+        match self.headless_frames {
+            Some(ref mut frames) => {
+                let seconds = *frames as f32 * HEADLESS_FRAME_SECONDS;
+                *frames += 1;
+                seconds
+            }
+            None => {
+                let elapsed = self.start.elapsed();
+                elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9
+            }
+        }
+    }
+}
@@ -0,0 +1,87 @@
+//! Converting colours between sRGB and linear light.
+//!
+//! Light adds up linearly: two lamps give twice the light of one, and a surface lit half as
+//! strongly sends back half as much. Screens and image files don't store it that way. They
+//! store sRGB, which spends more of its values on the darks, where eyes tell shades apart best,
+//! so that 0.5 is only about a fifth as much light as 1.0. Anything that does maths on colours,
+//! lighting and blending and filtering, has to do it on linear values, or the results come out
+//! too dark and shift in hue where they blend.
+//!
+//! So colours are decoded to linear as they come in and only encoded as they go out. Textures
+//! are sRGB formats, which the gpu decodes as it samples them (and before it filters, which is
+//! what keeps the mips from darkening), and the swapchain is an sRGB format too, which encodes
+//! whatever the shaders write. Colours written out in code, like clear colours, are sRGB the
+//! way a colour picker gives them, and go through `srgb_to_linear_rgba` before they're used.
+//! Chapter 10 shows what goes wrong without all this.
+
+use hal::format as f;
+
+/// Decodes one sRGB channel, from 0 to 1, to linear light.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes one channel of linear light, from 0 to 1, as sRGB.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Decodes an sRGB colour to linear light.
+pub fn srgb_to_linear_rgb(color: [f32; 3]) -> [f32; 3] {
+    [srgb_to_linear(color[0]), srgb_to_linear(color[1]), srgb_to_linear(color[2])]
+}
+
+/// Decodes an sRGB colour to linear light. Alpha is how much the colour covers rather than a
+/// colour, so it's linear already and is left alone, the same as sRGB formats leave it.
+pub fn srgb_to_linear_rgba(color: [f32; 4]) -> [f32; 4] {
+    [srgb_to_linear(color[0]), srgb_to_linear(color[1]), srgb_to_linear(color[2]), color[3]]
+}
+
+/// Whether `format` encodes as sRGB what's written to it. A swapchain that doesn't has to be
+/// written sRGB already, which is what the shaders that draw straight to the screen check for.
+pub fn is_srgb(format: f::Format) -> bool {
+    format.base_format().1 == f::ChannelType::Srgb
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_ends_and_middle_grey_decode_like_the_standard_says() {
+        assert_eq!(srgb_to_linear(0.0), 0.0);
+        assert!((srgb_to_linear(1.0) - 1.0).abs() < 1e-6);
+        assert!((srgb_to_linear(0.5) - 0.2140).abs() < 1e-4);
+        assert!((linear_to_srgb(0.18) - 0.4614).abs() < 1e-4);
+    }
+
+    #[test]
+    fn encoding_undoes_decoding() {
+        for step in 0..256 {
+            let value = step as f32 / 255.0;
+            assert!((linear_to_srgb(srgb_to_linear(value)) - value).abs() < 1e-5, "{}", value);
+        }
+    }
+
+    #[test]
+    fn alpha_is_left_alone() {
+        let decoded = srgb_to_linear_rgba([0.5, 0.5, 0.5, 0.5]);
+        assert_eq!(decoded[3], 0.5);
+        assert!(decoded[0] < 0.25);
+    }
+
+    #[test]
+    fn only_srgb_formats_encode() {
+        assert!(is_srgb(f::Format::Bgra8Srgb));
+        assert!(!is_srgb(f::Format::Bgra8Unorm));
+        assert!(!is_srgb(f::Format::Rgba16Float));
+    }
+}
@@ -0,0 +1,299 @@
+//! Renderer settings, loaded from (and saved back to) a TOML file.
+//!
+//! The file is watched while the program runs, so edits to it are picked up straight away.
+//! Whatever can be applied without restarting is; see `GfxContext::poll_config`.
+
+use std::fs;
+use std::mem;
+use std::path::{ Path, PathBuf };
+use std::sync::mpsc::Receiver;
+
+use notify::{ DebouncedEvent, RecommendedWatcher };
+use toml;
+
+use exposure::Tonemap;
+use gbuffer::Shading;
+use input::Bindings;
+use mesher::Mesher;
+use reflection::Reflections;
+use samplers::TextureFiltering;
+use shader;
+use sky::Sky;
+use vertex_format::VertexFormat;
+
+/// Everything that can be set in `settings.toml`. Missing keys take their default value, so
+/// the file only needs to mention what it changes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// The window size in logical pixels.
+    pub width: u32,
+    pub height: u32,
+    /// `vulkan`, `dx12` or `metal`. Only read at startup.
+    pub backend: Option<String>,
+    /// Whether to wait for vertical blank. When unset the present mode is picked from
+    /// `present::DEFAULT_PRESENT_MODES`.
+    pub vsync: Option<bool>,
+    /// How far away chunks are still drawn, in chunks.
+    pub render_distance: u32,
+    /// How far away chunks are still drawn with every block, in chunks. Past it they're drawn
+    /// with less detail, and less again past twice it. 0 draws everything with every block. See
+    /// `lod::Lod`.
+    pub lod_distance: u32,
+    /// The vertical field of view, in degrees.
+    pub fov: f32,
+    /// The distances to the near and far clip planes, in blocks.
+    pub near: f32,
+    pub far: f32,
+    /// How far the camera turns per pixel of mouse movement, in degrees.
+    pub mouse_sensitivity: f32,
+    /// How far a gamepad stick has to move, from 0 to 1, before it does anything. Worn sticks
+    /// don't quite return to the centre, which would otherwise make the camera drift.
+    pub gamepad_deadzone: f32,
+    /// The power the stick position is raised to past the deadzone. 1 is linear, and higher
+    /// values give finer control near the centre while still reaching full speed at the edge.
+    pub gamepad_response_curve: f32,
+    /// How fast the right stick turns the camera when pushed all the way, in degrees per second.
+    pub gamepad_look_speed: f32,
+    /// How chunks are turned into triangles, `naive` or `greedy`. See `mesher::Mesher`.
+    pub mesher: Mesher,
+    /// How many threads mesh chunks in the background. 0 picks one fewer than the number of cpu
+    /// cores.
+    pub mesh_threads: usize,
+    /// Whether chunks are meshed on the gpu in a compute shader, skipping `mesher` and the
+    /// uploads. See `gpu_mesher::GpuMesher`. Only the chapter that has a gpu mesher reads it.
+    pub gpu_meshing: bool,
+    /// Which layout chunk vertices are uploaded in, `full` or `packed`. See
+    /// `vertex_format::VertexFormat`. Only the chapter that can draw packed vertices reads it.
+    pub vertex_format: VertexFormat,
+    /// Whether chunk meshes are reordered for the vertex cache and overdraw after they're made.
+    /// See `mesh_optimizer::optimize`. Only the chapter that meshes everything up front reads it.
+    pub optimize_meshes: bool,
+    /// The seed new worlds are generated from. `--seed` overrides it.
+    pub seed: u64,
+    /// How often the world is saved while it's open, in seconds, when it's opened with
+    /// `--world`. It's always saved on the way out as well.
+    pub save_interval: f32,
+    /// How long a whole day and night takes, in seconds, at the overlay's normal speed.
+    pub day_length: f32,
+    /// How many texels across each of the sun's shadow maps is. Higher gives sharper shadows,
+    /// at the cost of gpu memory and time drawing them.
+    pub shadow_resolution: u32,
+    /// How far along the view shadows reach, in blocks. It's split between the cascades, so
+    /// the further it goes the blurrier they all get.
+    pub shadow_distance: f32,
+    /// How the world is lit, `forward` or `deferred`. See `gbuffer::Shading`.
+    pub shading: Shading,
+    /// How many points screen-space ambient occlusion looks at around each pixel, up to 64.
+    /// More is smoother but slower, and 0 turns it off. Only deferred shading has it.
+    pub ssao_samples: u32,
+    /// How far around each pixel SSAO looks for anything in the way, in blocks.
+    pub ssao_radius: f32,
+    /// How many pixels either side the blur that smooths out SSAO's noise reaches. 0 leaves it
+    /// noisy.
+    pub ssao_blur_radius: u32,
+    /// The curve that takes the HDR scene back into what the screen can show, `aces` or
+    /// `reinhard`. See `exposure::Tonemap`.
+    pub tonemap: Tonemap,
+    /// Whether the exposure follows how bright the scene is, like eyes adjusting.
+    pub auto_exposure: bool,
+    /// Exposure compensation in stops, each doubling the light, on top of auto exposure if
+    /// that's on.
+    pub exposure: f32,
+    /// How many mips the bloom is blurred through, up to 8. Each spreads the glow twice as far
+    /// as the one before. 0 turns bloom off.
+    pub bloom_mips: u32,
+    /// How much of the bloom is added onto the scene.
+    pub bloom_strength: f32,
+    /// How bright something has to be, once it's been exposed, before it glows. 1 is as bright
+    /// as the screen goes before tonemapping.
+    pub bloom_threshold: f32,
+    /// What's drawn behind the terrain, `atmosphere` or `cubemap`. See `sky::Sky`.
+    pub sky: Sky,
+    /// How thick the fog is around the height of the ground, as how much of the light it lets
+    /// through each block, more or less. 0 leaves only the fog at the edge of the loaded world,
+    /// which hides chunks loading in.
+    pub fog_density: f32,
+    /// How quickly the fog thins out going up, exponentially, per block. 0 keeps it the same
+    /// all the way up.
+    pub fog_height_falloff: f32,
+    /// Whether the environment probe that water and polished blocks reflect follows the camera,
+    /// drawing a face of itself each frame. Off, it's only drawn once, where the camera starts.
+    pub dynamic_reflections: bool,
+    /// How the water reflects what's around it, `probe` or `planar`. See
+    /// `reflection::Reflections`.
+    pub reflections: Reflections,
+    /// How the block atlas and other mipmapped textures are filtered, `nearest`, `bilinear`,
+    /// `trilinear` or `anisotropic`. See `samplers::TextureFiltering`. Only read when the
+    /// textures are loaded, at startup.
+    pub texture_filtering: TextureFiltering,
+    /// How many samples anisotropic filtering takes at most. It's clamped to what the adapter
+    /// supports, and adapters that can't do it at all fall back to trilinear filtering.
+    pub max_anisotropy: u32,
+    /// How many snowflakes fall around the camera each second. They're simulated on the gpu,
+    /// so there can be hundreds of thousands of them. 0 turns the snow off.
+    pub snowfall: u32,
+    /// Which keys and buttons trigger each action. This is a table, so it has to come after
+    /// the plain values for the file to be valid TOML.
+    pub bindings: Bindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            width: 1280,
+            height: 720,
+            backend: None,
+            vsync: None,
+            render_distance: 8,
+            lod_distance: 8,
+            fov: 70.0,
+            near: 0.1,
+            far: 1000.0,
+            mouse_sensitivity: 0.1,
+            gamepad_deadzone: 0.15,
+            gamepad_response_curve: 2.0,
+            gamepad_look_speed: 180.0,
+            mesher: Mesher::default(),
+            mesh_threads: 0,
+            gpu_meshing: false,
+            vertex_format: VertexFormat::default(),
+            optimize_meshes: false,
+            seed: 0,
+            save_interval: 30.0,
+            day_length: 600.0,
+            shadow_resolution: 2048,
+            shadow_distance: 128.0,
+            shading: Shading::default(),
+            ssao_samples: 16,
+            ssao_radius: 1.0,
+            ssao_blur_radius: 2,
+            tonemap: Tonemap::default(),
+            auto_exposure: true,
+            exposure: 0.0,
+            bloom_mips: 5,
+            bloom_strength: 0.05,
+            bloom_threshold: 1.0,
+            sky: Sky::default(),
+            fog_density: 0.004,
+            fog_height_falloff: 0.05,
+            dynamic_reflections: true,
+            reflections: Reflections::default(),
+            texture_filtering: TextureFiltering::default(),
+            max_anisotropy: 16,
+            snowfall: 0,
+            bindings: Bindings::default(),
+        }
+    }
+}
+
+/// The settings along with the file they came from.
+pub struct Config {
+    path: PathBuf,
+    settings: Settings,
+    watcher: Option<(RecommendedWatcher, Receiver<DebouncedEvent>)>,
+}
+
+impl Config {
+    /// Reads the settings from `path`. If the file doesn't exist yet it's created with the
+    /// defaults so there's something to edit. If it can't be parsed the defaults are used and
+    /// the file is left alone, so a typo doesn't wipe out everything else in it.
+    pub fn load<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref().to_owned();
+
+        let settings = if path.exists() {
+            read_settings(&path).unwrap_or_else(|err| {
+                warn!("{}, using the default settings", err);
+                Settings::default()
+            })
+        } else {
+            let settings = Settings::default();
+            if let Err(err) = write_settings(&path, &settings) {
+                warn!("{}", err);
+            }
+            settings
+        };
+
+        // Watch the directory rather than the file, since editors often save by replacing it
+        let dir = match path.parent() {
+            Some(dir) if dir != Path::new("") => dir.to_owned(),
+            _ => PathBuf::from("."),
+        };
+        let watcher = match shader::watch(&dir) {
+            Ok(watcher) => Some(watcher),
+            Err(err) => {
+                warn!("Settings hot-reloading is disabled: {}", err);
+                None
+            }
+        };
+
+        Config { path, settings, watcher }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    /// Changes the settings with `change`, and writes them back to the file if anything
+    /// actually changed.
+    pub fn update<F: FnOnce(&mut Settings)>(&mut self, change: F) {
+        let mut settings = self.settings.clone();
+        change(&mut settings);
+        if settings != self.settings {
+            self.settings = settings;
+            if let Err(err) = write_settings(&self.path, &self.settings) {
+                warn!("{}", err);
+            }
+        }
+    }
+
+    /// Rereads the file if it changed on disk since the last call. Returns the previous settings
+    /// if the new ones are different, so the caller can work out what to apply.
+    pub fn poll_changes(&mut self) -> Option<Settings> {
+        let mut changed = false;
+        if let Some((_, ref events)) = self.watcher {
+            let file_name = self.path.file_name();
+            while let Ok(event) = events.try_recv() {
+                match event {
+                    DebouncedEvent::Create(path)
+                    | DebouncedEvent::Write(path)
+                    | DebouncedEvent::Rename(_, path) => {
+                        changed |= path.file_name() == file_name;
+                    }
+                    _ => (),
+                }
+            }
+        }
+        if !changed {
+            return None;
+        }
+
+        match read_settings(&self.path) {
+            Ok(ref settings) if *settings == self.settings => None,
+            Ok(settings) => {
+                info!("Reloaded {}", self.path.display());
+                Some(mem::replace(&mut self.settings, settings))
+            }
+            Err(err) => {
+                warn!("{}, keeping the current settings", err);
+                None
+            }
+        }
+    }
+}
+
+fn read_settings(path: &Path) -> Result<Settings, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    toml::from_str(&text).map_err(|err| format!("Failed to parse {}: {}", path.display(), err))
+}
+
+fn write_settings(path: &Path, settings: &Settings) -> Result<(), String> {
+    let text = toml::to_string_pretty(settings)
+        .map_err(|err| format!("Failed to serialize settings: {}", err))?;
+    fs::write(path, text).map_err(|err| format!("Failed to write {}: {}", path.display(), err))
+}
@@ -0,0 +1,541 @@
+//! The `GfxContext`, which bundles up the objects every chapter needs to talk to the gpu.
+
+use std::cell::RefCell;
+use std::mem;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+use hal::{
+    buffer, command, memory, pool,
+    error::DeviceCreationError,
+    pso::PipelineStage,
+    queue::QueueType,
+    window::{ Extent2D, PresentMode },
+    Adapter, Backend, Compute, Device, General, Gpu, Instance, PhysicalDevice, QueueFamily, QueueGroup,
+    Submission, Surface, Transfer,
+};
+
+use winit;
+
+use adapter;
+use async_compute;
+use allocator::{ Allocator, MemoryCategory };
+use args::Args;
+use buffer::DeviceBuffer;
+use config::Config;
+use cursor::CursorGrab;
+use error::{ RendererError, Result };
+use fullscreen::{ Fullscreen, FullscreenMode };
+use pipeline_cache::{ self, PipelineCache };
+use present;
+use resources::SwapchainBundle;
+use samplers::SamplerCache;
+use staging::StagingRing;
+use transfer::{ self, record_copies, TransferQueue, UploadCopy };
+
+/// How big the ring uploads on the graphics queue are staged in is, in bytes. Anything bigger
+/// gets a staging buffer of its own.
+const UPLOAD_RING_SIZE: u64 = 8 * 1024 * 1024;
+
+/// Owns the instance, surface, adapter, device, memory allocator, pipeline cache, sampler cache
+/// and queues for a window. In headless mode there's no window or surface, and swapchains are
+/// made of offscreen images instead.
+///
+/// Fields are dropped in declaration order, so the device goes first and the instance, which
+/// everything else was created from, goes last.
+pub struct GfxContext<B: Backend> {
+    pub device: Rc<B::Device>,
+    pub allocator: Rc<RefCell<Allocator<B>>>,
+    pub pipeline_cache: PipelineCache<B>,
+    pub samplers: RefCell<SamplerCache<B>>,
+    pub queue_group: QueueGroup<B, General>,
+    /// A queue for uploads, if the adapter has a queue family that only copies. Without one,
+    /// uploads go on the graphics queue.
+    pub transfer: Option<TransferQueue<B>>,
+    /// A queue for compute work to run alongside the drawing, if the adapter has a queue family
+    /// that does compute but not graphics.
+    pub compute_queue_group: Option<QueueGroup<B, Compute>>,
+    pub adapter: Adapter<B>,
+    pub surface: Option<B::Surface>,
+    pub window: Option<winit::Window>,
+    pub fullscreen: Fullscreen,
+    pub cursor: CursorGrab,
+    pub args: Args,
+    pub config: Config,
+    /// Present modes to try when creating swapchains, best first.
+    pub preferred_present_modes: Vec<PresentMode>,
+    /// Where `submit_staged` writes the data it uploads.
+    staging_ring: StagingRing<B>,
+    instance: Box<Instance<Backend = B>>,
+}
+
+impl<B: Backend> GfxContext<B> {
+    /// Picks an adapter (honoring `--adapter`) and opens a device with a queue for graphics and
+    /// compute that can present to `surface`, or any such queue without one, and transfer and
+    /// compute queues if there are families for them.
+    /// `app_name` is used to name the pipeline cache file.
+    ///
+    /// Swapchains use the mode from `--present-mode` if one was given, which has to be supported
+    /// by the surface. Otherwise `--vsync on|off` (or `vsync` in the settings) picks between the
+    /// vsync and non-vsync modes, and failing that the first supported mode from
+    /// `DEFAULT_PRESENT_MODES` is used.
+    pub fn new<I>(
+        app_name: &str,
+        args: Args,
+        config: Config,
+        instance: I,
+        surface: Option<B::Surface>,
+        window: Option<winit::Window>,
+    ) -> Result<Self>
+    where
+        I: Instance<Backend = B>,
+    {
+        let requested_vsync = args.vsync.or(config.settings().vsync);
+        let fullscreen_mode = args.fullscreen.unwrap_or(FullscreenMode::Borderless);
+        let mut adapter = adapter::pick_adapter(
+            instance.enumerate_adapters(),
+            surface.as_ref(),
+            args.adapter.as_ref().map(|s| s.as_str()),
+        )?;
+        let OpenDevice {
+            device,
+            queue_group,
+            transfer,
+            compute_queue_group,
+            allocator,
+            pipeline_cache,
+            samplers,
+        } = open_device(
+            &mut adapter,
+            surface.as_ref(),
+            pipeline_cache::default_cache_path(app_name),
+        )?;
+
+        let preferred_present_modes = match (args.present_mode, surface.as_ref()) {
+            (Some(requested), Some(surface)) => {
+                let (_, _, available) = surface.compatibility(&adapter.physical_device);
+                vec![present::validate_present_mode(&available, requested)?]
+            }
+            // Offscreen images aren't presented, so there's nothing to check the mode against
+            (Some(requested), None) => vec![requested],
+            (None, _) => match requested_vsync {
+                Some(vsync) => present::vsync_present_modes(vsync),
+                None => present::DEFAULT_PRESENT_MODES.to_vec(),
+            },
+        };
+
+        let staging_ring = StagingRing::new(
+            device.clone(),
+            allocator.clone(),
+            UPLOAD_RING_SIZE,
+            buffer::Usage::TRANSFER_SRC,
+        )?;
+
+        Ok(GfxContext {
+            device,
+            allocator,
+            pipeline_cache,
+            samplers,
+            queue_group,
+            transfer,
+            compute_queue_group,
+            adapter,
+            surface,
+            window,
+            fullscreen: Fullscreen::new(fullscreen_mode),
+            cursor: CursorGrab::new(),
+            args,
+            config,
+            preferred_present_modes,
+            staging_ring,
+            instance: Box::new(instance),
+        })
+    }
+
+    pub fn instance(&self) -> &Instance<Backend = B> {
+        &*self.instance
+    }
+
+    /// Throws away the device after it was lost and opens a new one, picking the adapter again
+    /// in case the old one is gone. The window, surface and settings are kept.
+    ///
+    /// Everything else created from the old device has to have been dropped already, or it will
+    /// keep the old device alive.
+    pub fn reopen_device(&mut self) -> Result<()> {
+        let mut adapter = adapter::pick_adapter(
+            self.instance.enumerate_adapters(),
+            self.surface.as_ref(),
+            self.args.adapter.as_ref().map(|s| s.as_str()),
+        )?;
+        let OpenDevice {
+            device,
+            queue_group,
+            transfer,
+            compute_queue_group,
+            allocator,
+            pipeline_cache,
+            samplers,
+        } = open_device(
+            &mut adapter,
+            self.surface.as_ref(),
+            self.pipeline_cache.path().to_owned(),
+        )?;
+
+        // The old caches, allocator and transfer queue each hold on to the old device, so it's
+        // only destroyed once the last of these is replaced
+        self.transfer = transfer;
+        self.samplers = samplers;
+        self.pipeline_cache = pipeline_cache;
+        self.allocator = allocator;
+        self.queue_group = queue_group;
+        self.compute_queue_group = compute_queue_group;
+        self.device = device;
+        self.adapter = adapter;
+        Ok(())
+    }
+
+    /// Whether we're rendering offscreen with `--headless`, without a window.
+    pub fn is_headless(&self) -> bool {
+        self.window.is_none()
+    }
+
+    /// Creates a swapchain for the window at its current size. In headless mode this makes
+    /// offscreen images the size given by `--width` and `--height` (or the settings) instead.
+    pub fn create_swapchain(&mut self) -> Result<SwapchainBundle<B>> {
+        let swapchain = match (self.surface.as_mut(), self.window.as_ref()) {
+            (Some(surface), Some(window)) => {
+                let swapchain = SwapchainBundle::new(
+                    self.device.clone(),
+                    surface,
+                    &self.adapter,
+                    window,
+                    &self.preferred_present_modes,
+                )?;
+                info!("Present mode: {}", present::present_mode_name(swapchain.present_mode()));
+                swapchain
+            }
+            _ => {
+                let extent = Extent2D {
+                    width: self.args.width.unwrap_or(self.config.settings().width),
+                    height: self.args.height.unwrap_or(self.config.settings().height),
+                };
+                let swapchain = SwapchainBundle::offscreen(self.device.clone(), self.allocator.clone(), extent)?;
+                info!("Rendering offscreen at {}x{}", extent.width, extent.height);
+                swapchain
+            }
+        };
+        Ok(swapchain)
+    }
+
+    /// Waits for the gpu to go idle and then rebuilds `swapchain` to match the window. Offscreen
+    /// images never go out of date, so in headless mode this does nothing.
+    pub fn recreate_swapchain(&mut self, swapchain: &mut SwapchainBundle<B>) -> Result<()> {
+        let (surface, window) = match (self.surface.as_mut(), self.window.as_ref()) {
+            (Some(surface), Some(window)) => (surface, window),
+            _ => return Ok(()),
+        };
+        self.device.wait_idle()?;
+        swapchain.recreate(surface, &self.adapter, window, &self.preferred_present_modes)?;
+        debug!(
+            "Recreated swapchain with extent {:?} (scale factor {}), present mode: {}",
+            swapchain.extent(),
+            swapchain.hidpi_factor(),
+            present::present_mode_name(swapchain.present_mode()),
+        );
+
+        // Windows keeps the cursor inside the window's old rectangle until it's grabbed again
+        self.cursor.refresh(window);
+
+        // Remember the window size for next time, unless it's only this size for fullscreen
+        if !self.fullscreen.is_fullscreen() {
+            if let Some(size) = window.get_inner_size() {
+                self.config.update(|settings| {
+                    settings.width = size.width.round() as u32;
+                    settings.height = size.height.round() as u32;
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Switches between vsync and non-vsync present modes for the swapchains created from now
+    /// on, and saves the choice to the settings. The current swapchain has to be recreated for
+    /// this to take effect.
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.preferred_present_modes = present::vsync_present_modes(vsync);
+        self.config.update(|settings| settings.vsync = Some(vsync));
+    }
+
+    /// Applies any changes made to the settings file since the last call. Returns true if the
+    /// swapchain needs to be recreated for them to take effect.
+    ///
+    /// Vsync and the window size are applied here. The backend can only be changed by
+    /// restarting, and the rest of the settings are read by the chapters as they need them.
+    pub fn poll_config(&mut self) -> bool {
+        let previous = match self.config.poll_changes() {
+            Some(previous) => previous,
+            None => return false,
+        };
+        let settings = self.config.settings().clone();
+        let mut recreate_swapchain = false;
+
+        if settings.vsync != previous.vsync {
+            self.preferred_present_modes = match settings.vsync {
+                Some(vsync) => present::vsync_present_modes(vsync),
+                None => present::DEFAULT_PRESENT_MODES.to_vec(),
+            };
+            recreate_swapchain = true;
+        }
+
+        let size_changed = settings.width != previous.width || settings.height != previous.height;
+        if size_changed && !self.fullscreen.is_fullscreen() {
+            if let Some(ref window) = self.window {
+                window.set_inner_size(winit::dpi::LogicalSize::new(
+                    settings.width as f64,
+                    settings.height as f64,
+                ));
+                recreate_swapchain = true;
+            }
+        }
+
+        if settings.backend != previous.backend {
+            info!("The new backend setting will be used the next time the program starts");
+        }
+
+        recreate_swapchain
+    }
+
+    /// Switches the window between windowed and fullscreen (in the mode from `--fullscreen`).
+    /// The swapchain has to be recreated afterwards to match the new window size.
+    pub fn toggle_fullscreen(&mut self) {
+        let window = match self.window {
+            Some(ref window) => window,
+            None => return,
+        };
+        self.fullscreen.toggle(window);
+        info!(
+            "{}",
+            if self.fullscreen.is_fullscreen() {
+                format!("Entered {} fullscreen", self.fullscreen.mode())
+            } else {
+                "Left fullscreen".to_owned()
+            },
+        );
+    }
+
+    /// Grabs and hides the cursor, or lets go of it, to match `grab`. Call this every frame
+    /// with `Input::cursor_grabbed`. Does nothing in headless mode.
+    pub fn update_cursor_grab(&mut self, grab: bool) {
+        if let Some(ref window) = self.window {
+            self.cursor.update(window, grab);
+        }
+    }
+
+    /// Flips vsync relative to the mode `swapchain` is using. As with `set_vsync`, the
+    /// swapchain has to be recreated afterwards.
+    pub fn toggle_vsync(&mut self, swapchain: &SwapchainBundle<B>) {
+        let vsync = present::is_vsync(swapchain.present_mode());
+        self.set_vsync(!vsync);
+    }
+
+    pub fn wait_idle(&self) -> Result<()> {
+        self.device.wait_idle()?;
+        Ok(())
+    }
+
+    /// Records commands with `record` into a throwaway command buffer, submits it to the graphics
+    /// queue and waits for it to finish. Meant for uploads and other work done at loading time.
+    pub fn submit_one_shot<F>(&mut self, record: F) -> Result<()>
+    where
+        F: FnOnce(&mut command::CommandBuffer<B, General, command::OneShot>),
+    {
+        self.submit_one_shot_after(None, record)
+    }
+
+    /// Like `submit_one_shot`, but with `data` written somewhere the gpu can copy it from first.
+    /// `record` is given the buffer it's in and where in that buffer it starts. It goes in the
+    /// staging ring if there's room, which is given back once the submission is done, and in a
+    /// buffer of its own otherwise.
+    pub fn submit_staged<T: Copy, F>(&mut self, data: &[T], record: F) -> Result<()>
+    where
+        F: FnOnce(&mut command::CommandBuffer<B, General, command::OneShot>, &B::Buffer, u64),
+    {
+        if let Some(src) = self.staging_ring.try_write(data)? {
+            let result = {
+                let GfxContext { ref device, ref mut queue_group, ref staging_ring, .. } = *self;
+                submit_and_wait(device, queue_group, None, |command_buffer| {
+                    record(command_buffer, staging_ring.buffer(), src)
+                })
+            };
+            // The fence has been waited on, or the device is lost and nothing is running any more
+            let batch = self.staging_ring.end_batch();
+            self.staging_ring.release_through(batch);
+            return result;
+        }
+
+        let staging = DeviceBuffer::new(
+            self.device.clone(),
+            self.allocator.clone(),
+            (data.len() * mem::size_of::<T>()) as u64,
+            buffer::Usage::TRANSFER_SRC,
+            memory::Properties::CPU_VISIBLE,
+            MemoryCategory::Transient,
+        )?;
+        staging.write(data)?;
+        // The staging buffer is dropped (and its memory freed) once the copy is done
+        self.submit_one_shot(|command_buffer| record(command_buffer, staging.buffer(), 0))
+    }
+
+    /// Like `submit_one_shot`, but with `copies` out of staging buffers done first, on the
+    /// transfer queue if there is one. `record` goes on the graphics queue once they're done,
+    /// with any images they copied into still in the layout for copying into.
+    pub fn submit_upload<F>(&mut self, copies: &[UploadCopy<B>], record: F) -> Result<()>
+    where
+        F: FnOnce(&mut command::CommandBuffer<B, General, command::OneShot>),
+    {
+        if self.transfer.is_none() {
+            return self.submit_one_shot(|command_buffer| {
+                record_copies(command_buffer, copies);
+                record(command_buffer);
+            });
+        }
+
+        let copied = self.device.create_semaphore();
+        let copy_pool = self.transfer.as_mut().unwrap().copy_now(copies, &copied);
+        // Everything after the copies starts with a barrier or a blit from the transfer stage
+        let result = self.submit_one_shot_after(Some((&copied, PipelineStage::TRANSFER)), record);
+
+        // Waiting for the graphics submission also waited for the copies it waited on, unless
+        // the device was lost, in which case nothing is running any more either
+        self.device.destroy_command_pool(copy_pool.into_raw());
+        self.device.destroy_semaphore(copied);
+        result
+    }
+
+    fn submit_one_shot_after<F>(&mut self, wait: Option<(&B::Semaphore, PipelineStage)>, record: F) -> Result<()>
+    where
+        F: FnOnce(&mut command::CommandBuffer<B, General, command::OneShot>),
+    {
+        submit_and_wait(&self.device, &mut self.queue_group, wait, record)
+    }
+}
+
+/// Records commands with `record` into a throwaway command buffer, submits it to the first queue
+/// in `queue_group` once `wait` is signalled, if there's anything to wait for, and waits for it
+/// to finish.
+fn submit_and_wait<B: Backend, F>(
+    device: &B::Device,
+    queue_group: &mut QueueGroup<B, General>,
+    wait: Option<(&B::Semaphore, PipelineStage)>,
+    record: F,
+) -> Result<()>
+where
+    F: FnOnce(&mut command::CommandBuffer<B, General, command::OneShot>),
+{
+    let mut command_pool = device.create_command_pool_typed(
+        queue_group,
+        pool::CommandPoolCreateFlags::TRANSIENT,
+        1,
+    );
+
+    let finished_command_buffer = {
+        let mut command_buffer = command_pool.acquire_command_buffer(false);
+        record(&mut command_buffer);
+        command_buffer.finish()
+    };
+
+    let fence = device.create_fence(false);
+    let mut submission = Submission::new();
+    if let Some(wait) = wait {
+        submission = submission.wait_on(&[wait]);
+    }
+    let submission = submission.submit(Some(finished_command_buffer));
+    queue_group.queues[0].submit(submission, Some(&fence));
+    let finished = device.wait_for_fence(&fence, !0);
+
+    device.destroy_fence(fence);
+    device.destroy_command_pool(command_pool.into_raw());
+
+    if finished {
+        Ok(())
+    } else {
+        Err(RendererError::DeviceLost)
+    }
+}
+
+/// The parts of a `GfxContext` that belong to the device.
+struct OpenDevice<B: Backend> {
+    device: Rc<B::Device>,
+    queue_group: QueueGroup<B, General>,
+    transfer: Option<TransferQueue<B>>,
+    compute_queue_group: Option<QueueGroup<B, Compute>>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    pipeline_cache: PipelineCache<B>,
+    samplers: RefCell<SamplerCache<B>>,
+}
+
+/// Opens a device with a queue that can present to `surface` (if there is one), along with the
+/// allocator, pipeline cache and sampler cache that go with it. The queue does compute as well
+/// as graphics, so compute passes can go in the same command buffers as the draws that use what
+/// they write. If the adapter has a queue family that only copies, a queue from that is opened
+/// too, for uploads, and likewise one from a family that only does compute, for compute work to
+/// run alongside the drawing.
+fn open_device<B: Backend>(
+    adapter: &mut Adapter<B>,
+    surface: Option<&B::Surface>,
+    pipeline_cache_path: PathBuf,
+) -> Result<OpenDevice<B>> {
+    let (device, queue_group, transfer_group, compute_queue_group) = {
+        let device_creation = |error: DeviceCreationError| RendererError::DeviceCreation {
+            adapter: adapter.info.name.clone(),
+            error,
+        };
+        let graphics_family = adapter
+            .queue_families
+            .iter()
+            .find(|family| {
+                family.queue_type() == QueueType::General
+                    && surface.map_or(true, |surface| surface.supports_queue_family(family))
+            })
+            .ok_or_else(|| device_creation(DeviceCreationError::InitializationFailed))?;
+        let transfer_family = transfer::pick_transfer_family(adapter, graphics_family.id());
+        let compute_family = async_compute::pick_compute_family(adapter, graphics_family.id());
+
+        let mut families = vec![(graphics_family, &[1.0][..])];
+        if let Some(transfer_family) = transfer_family {
+            info!("Uploading on queue family {:?}", transfer_family.id());
+            families.push((transfer_family, &[1.0][..]));
+        }
+        if let Some(compute_family) = compute_family {
+            info!("Running compute work alongside the drawing on queue family {:?}", compute_family.id());
+            families.push((compute_family, &[1.0][..]));
+        }
+        let Gpu { device, mut queues } = adapter.physical_device.open(&families).map_err(&device_creation)?;
+        let queue_group = queues.take::<General>(graphics_family.id()).unwrap();
+        let transfer_group = transfer_family.map(|family| queues.take::<Transfer>(family.id()).unwrap());
+        let compute_queue_group = compute_family.map(|family| queues.take::<Compute>(family.id()).unwrap());
+        (device, queue_group, transfer_group, compute_queue_group)
+    };
+
+    let device = Rc::new(device);
+    let allocator = Rc::new(RefCell::new(Allocator::new(
+        device.clone(),
+        adapter.physical_device.memory_properties(),
+    )));
+    let transfer = match transfer_group {
+        Some(queue_group) => Some(TransferQueue::new(device.clone(), allocator.clone(), queue_group)?),
+        None => None,
+    };
+    let pipeline_cache = PipelineCache::load(device.clone(), &adapter.info, pipeline_cache_path)?;
+    let samplers = SamplerCache::new(device.clone(), adapter);
+
+    Ok(OpenDevice {
+        device,
+        queue_group,
+        transfer,
+        compute_queue_group,
+        allocator,
+        pipeline_cache,
+        samplers: RefCell::new(samplers),
+    })
+}
@@ -0,0 +1,145 @@
+//! A small profiler for timing nested scopes on the cpu.
+//!
+//! Scopes are begun and ended explicitly, and can nest. Each call to `begin_frame` finishes the
+//! previous frame, whose scopes can then be looked at with `last_frame` or `report`. With
+//! `--cpu-trace` the scopes from recent frames are also kept around so they can be written out
+//! in the JSON format `chrome://tracing` (and other trace viewers) understands.
+
+use std::collections::VecDeque;
+use std::fmt::Write as FmtWrite;
+use std::fs::File;
+use std::io::{ self, BufWriter, Write };
+use std::path::Path;
+use std::time::{ Duration, Instant };
+
+/// How many frames of scopes are kept for the trace. At 60 fps that's about a minute.
+pub const MAX_TRACE_FRAMES: usize = 3600;
+
+/// One finished scope.
+#[derive(Clone, Debug)]
+pub struct ScopeRecord {
+    pub name: &'static str,
+    /// How many scopes this one is nested in.
+    pub depth: usize,
+    /// When the scope began, relative to when the profiler was created.
+    pub start: Duration,
+    pub duration: Duration,
+}
+
+pub struct CpuProfiler {
+    origin: Instant,
+    /// The scopes begun but not yet ended, innermost last, as indices into `current`.
+    open: Vec<usize>,
+    current: Vec<ScopeRecord>,
+    last_frame: Vec<ScopeRecord>,
+    /// Finished frames for the trace, oldest first. `None` if we're not tracing.
+    trace: Option<VecDeque<Vec<ScopeRecord>>>,
+}
+
+impl CpuProfiler {
+    /// Creates a profiler. If `trace` is set, the scopes from the last `MAX_TRACE_FRAMES` frames
+    /// are kept for `write_chrome_trace`.
+    pub fn new(trace: bool) -> Self {
+        CpuProfiler {
+            origin: Instant::now(),
+            open: Vec::new(),
+            current: Vec::new(),
+            last_frame: Vec::new(),
+            trace: if trace { Some(VecDeque::new()) } else { None },
+        }
+    }
+
+    /// Finishes the current frame and starts a new one. Any scopes that are still open are ended
+    /// first.
+    pub fn begin_frame(&mut self) {
+        while !self.open.is_empty() {
+            self.end_scope();
+        }
+
+        let finished: Vec<_> = self.current.drain(..).collect();
+        if let Some(ref mut trace) = self.trace {
+            if trace.len() == MAX_TRACE_FRAMES {
+                trace.pop_front();
+            }
+            trace.push_back(finished.clone());
+        }
+        self.last_frame = finished;
+    }
+
+    /// Starts timing a scope called `name`, nested inside whichever scope is currently open.
+    pub fn begin_scope(&mut self, name: &'static str) {
+        self.open.push(self.current.len());
+        self.current.push(ScopeRecord {
+            name,
+            depth: self.open.len() - 1,
+            start: self.origin.elapsed(),
+            duration: Duration::from_secs(0),
+        });
+    }
+
+    /// Ends the innermost open scope.
+    pub fn end_scope(&mut self) {
+        if let Some(index) = self.open.pop() {
+            let record = &mut self.current[index];
+            record.duration = self.origin.elapsed() - record.start;
+        }
+    }
+
+    /// The scopes from the last finished frame, in the order they were begun.
+    pub fn last_frame(&self) -> &[ScopeRecord] {
+        &self.last_frame
+    }
+
+    /// The last finished frame as an indented list of scopes and how long they took.
+    pub fn report(&self) -> String {
+        let mut report = String::new();
+        for record in &self.last_frame {
+            let _ = writeln!(
+                report,
+                "{:indent$}{}: {:.3} ms",
+                "",
+                record.name,
+                milliseconds(record.duration),
+                indent = record.depth * 2,
+            );
+        }
+        report
+    }
+
+    /// Writes the traced frames to `path` as a `chrome://tracing` JSON file. Writes an empty
+    /// trace if the profiler wasn't created with tracing on.
+    pub fn write_chrome_trace<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "{{\"traceEvents\":[")?;
+
+        let records = self.trace.iter().flat_map(|frames| frames.iter()).flat_map(|frame| frame.iter());
+        for (index, record) in records.enumerate() {
+            if index > 0 {
+                write!(out, ",")?;
+            }
+            // "X" events are complete scopes, with the start and duration in microseconds
+            write!(
+                out,
+                "\n{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":0,\"tid\":0,\"ts\":{},\"dur\":{}}}",
+                escape_json(record.name),
+                microseconds(record.start),
+                microseconds(record.duration),
+            )?;
+        }
+
+        writeln!(out, "\n]}}")?;
+        out.flush()
+    }
+}
+
+fn milliseconds(duration: Duration) -> f64 {
+    duration.as_secs() as f64 * 1000.0 + duration.subsec_nanos() as f64 / 1_000_000.0
+}
+
+fn microseconds(duration: Duration) -> u64 {
+    duration.as_secs() * 1_000_000 + (duration.subsec_nanos() / 1000) as u64
+}
+
+fn escape_json(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,43 @@
+//! Skipping draws for things the camera can't see.
+//!
+//! Each frame, before recording draws, every object's bounding box is tested against the
+//! camera's `Frustum`. Anything entirely outside it is left out, so it costs nothing on the gpu
+//! and no draw call on the cpu. The test errs on the side of drawing: boxes just past a corner
+//! of the frustum can pass, but nothing visible is ever culled. What survives can then be
+//! tested for being hidden behind other things with `occlusion::OcclusionCuller`.
+
+use math::{ Aabb, Frustum };
+
+/// How many objects were drawn and how many culled, for the overlay.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullStats {
+    pub drawn: usize,
+    /// Outside the frustum.
+    pub culled: usize,
+    /// Inside the frustum, but hidden the last time occlusion culling tested them.
+    pub occluded: usize,
+}
+
+impl CullStats {
+    pub fn new() -> Self {
+        CullStats::default()
+    }
+
+    /// Whether `bounds` might be visible in `frustum`, counting it as drawn if so and culled if
+    /// not.
+    pub fn test(&mut self, frustum: &Frustum, bounds: &Aabb) -> bool {
+        let visible = frustum.intersects_aabb(bounds);
+        if visible {
+            self.drawn += 1;
+        } else {
+            self.culled += 1;
+        }
+        visible
+    }
+
+    /// Moves an object `test` counted as drawn over to occluded.
+    pub fn occlude(&mut self) {
+        self.drawn -= 1;
+        self.occluded += 1;
+    }
+}
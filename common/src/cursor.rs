@@ -0,0 +1,86 @@
+//! Grabbing and hiding the cursor for mouse look.
+//!
+//! `Input` decides when the cursor should be grabbed. This applies that to the window, working
+//! around the ways platforms differ:
+//!
+//! - On X11 the grab fails if another client has the pointer grabbed, which the window manager
+//!   often does for a moment after the window is clicked into focus. We try again each frame.
+//! - Wayland, and macOS in some winit versions, can't grab the cursor at all. It's still hidden,
+//!   and raw mouse motion (or, failing that, cursor movement) still turns the camera. It just
+//!   isn't kept inside the window.
+//! - macOS counts calls to hide the cursor and needs as many to show it again, so it's only
+//!   hidden or shown when that actually changes.
+//! - Windows confines the cursor to the window's rectangle as it was when grabbed, so the grab
+//!   has to be redone when the window changes size. `GfxContext::recreate_swapchain` does this.
+
+use winit;
+
+/// How many frames in a row to keep trying to grab the cursor before giving up.
+const MAX_GRAB_ATTEMPTS: u32 = 60;
+
+pub struct CursorGrab {
+    /// Whether the window currently has the cursor grabbed.
+    grabbed: bool,
+    hidden: bool,
+    /// Failed attempts since the cursor was last asked to be grabbed.
+    failed_attempts: u32,
+}
+
+impl CursorGrab {
+    pub fn new() -> Self {
+        CursorGrab {
+            grabbed: false,
+            hidden: false,
+            failed_attempts: 0,
+        }
+    }
+
+    pub fn is_grabbed(&self) -> bool {
+        self.grabbed
+    }
+
+    /// Grabs and hides the cursor if `grab` is true, or releases and shows it if not. Call this
+    /// every frame with `Input::cursor_grabbed`: it only touches the window when something
+    /// needs to change, or to retry a grab that failed.
+    pub fn update(&mut self, window: &winit::Window, grab: bool) {
+        if self.hidden != grab {
+            window.hide_cursor(grab);
+            self.hidden = grab;
+        }
+
+        if !grab {
+            self.failed_attempts = 0;
+            if self.grabbed {
+                if let Err(err) = window.grab_cursor(false) {
+                    warn!("Couldn't release the cursor: {}", err);
+                }
+                self.grabbed = false;
+            }
+            return;
+        }
+
+        if self.grabbed || self.failed_attempts >= MAX_GRAB_ATTEMPTS {
+            return;
+        }
+        match window.grab_cursor(true) {
+            Ok(()) => {
+                self.grabbed = true;
+                self.failed_attempts = 0;
+            }
+            Err(err) => {
+                self.failed_attempts += 1;
+                if self.failed_attempts == MAX_GRAB_ATTEMPTS {
+                    warn!("Couldn't grab the cursor, so it can leave the window: {}", err);
+                }
+            }
+        }
+    }
+
+    /// Makes the next `update` grab the cursor again, for when the window has changed size.
+    pub fn refresh(&mut self, window: &winit::Window) {
+        if self.grabbed {
+            let _ = window.grab_cursor(false);
+            self.grabbed = false;
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Lines drawn over the world for seeing how it's split into chunks and streamed in.
+//!
+//! Every frame the lines are built again on the CPU into a `DebugLines`, as pairs of coloured
+//! points, and drawn as a line list from a host visible vertex buffer that grows as needed. They
+//! aren't depth tested, so the chunks still loading underground show through the ground over
+//! them.
+//!
+//! The frustum is the one exception to being built from what's there now: the camera can't see
+//! its own frustum from inside it, so what's drawn is the frustum from when that was turned on,
+//! to be walked around and looked at from outside.
+
+use std::fmt;
+use std::ops::Range;
+
+use math::Aabb;
+use world::{ ChunkCoord, CHUNK_SIZE };
+
+/// Has to match the inputs to `debug_line.vert`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 3],
+}
+
+/// The corners of a box that are joined by its edges, as indices into the corners in the order
+/// `Aabb::corners` gives them. Each pair differs along one axis.
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1), (2, 3), (4, 5), (6, 7),
+    (0, 2), (1, 3), (4, 6), (5, 7),
+    (0, 4), (1, 5), (2, 6), (3, 7),
+];
+
+/// What the lines around the camera's chunk are drawn in.
+pub const BORDER_COLOR: [f32; 3] = [1.0, 1.0, 0.2];
+/// What the frustum is drawn in.
+pub const FRUSTUM_COLOR: [f32; 3] = [1.0, 0.3, 1.0];
+
+/// How far along getting onto the screen a chunk is, for colouring its box.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChunkState {
+    /// In range, but not generated or loaded from disk yet.
+    Loading,
+    /// Loaded, with a mesh being built for it.
+    Meshing,
+    /// Meshed, with its mesh uploaded.
+    Resident,
+}
+
+impl ChunkState {
+    pub fn color(self) -> [f32; 3] {
+        match self {
+            ChunkState::Loading => [1.0, 0.2, 0.2],
+            ChunkState::Meshing => [1.0, 0.6, 0.1],
+            ChunkState::Resident => [0.2, 1.0, 0.3],
+        }
+    }
+}
+
+impl fmt::Display for ChunkState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ChunkState::Loading => "loading",
+            ChunkState::Meshing => "meshing",
+            ChunkState::Resident => "resident",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Which of the lines are drawn, each turned on and off from the overlay.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DebugLineSettings {
+    /// The edges of the column of chunks the camera is in.
+    pub chunk_borders: bool,
+    /// A box around each chunk in range, coloured by its `ChunkState`.
+    pub chunk_bounds: bool,
+    /// The frustum from when this was turned on.
+    pub frustum: bool,
+}
+
+impl DebugLineSettings {
+    pub fn any(&self) -> bool {
+        self.chunk_borders || self.chunk_bounds || self.frustum
+    }
+}
+
+/// The lines for one frame, two vertices to a line.
+#[derive(Clone, Debug, Default)]
+pub struct DebugLines {
+    vertices: Vec<LineVertex>,
+}
+
+impl DebugLines {
+    pub fn new() -> Self {
+        DebugLines { vertices: Vec::new() }
+    }
+
+    /// Forgets last frame's lines, keeping the space they took up.
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn vertices(&self) -> &[LineVertex] {
+        &self.vertices
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn line(&mut self, from: [f32; 3], to: [f32; 3], color: [f32; 3]) {
+        self.vertices.push(LineVertex { position: from, color });
+        self.vertices.push(LineVertex { position: to, color });
+    }
+
+    /// The twelve edges of a box with `corners` in the order `Aabb::corners` gives them, which
+    /// doesn't have to be lined up with the axes.
+    pub fn cuboid(&mut self, corners: &[[f32; 3]; 8], color: [f32; 3]) {
+        for &(from, to) in &BOX_EDGES {
+            self.line(corners[from], corners[to], color);
+        }
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 3]) {
+        self.cuboid(&aabb.corners(), color);
+    }
+
+    /// The edges of the column of chunks `coord` is in, through each of the rows `layers`: the
+    /// four corners up its sides, and a ring around it at the bottom of each chunk in it and at
+    /// the top of the last.
+    pub fn chunk_column(&mut self, coord: ChunkCoord, layers: Range<i32>, color: [f32; 3]) {
+        let size = CHUNK_SIZE as f32;
+        let (x, z) = (coord.x as f32 * size, coord.z as f32 * size);
+        let (bottom, top) = (layers.start as f32 * size, layers.end as f32 * size);
+        let corners = [[x, z], [x + size, z], [x + size, z + size], [x, z + size]];
+        for corner in &corners {
+            self.line([corner[0], bottom, corner[1]], [corner[0], top, corner[1]], color);
+        }
+        for layer in layers.start..layers.end + 1 {
+            let y = layer as f32 * size;
+            for side in 0..4 {
+                let (from, to) = (corners[side], corners[(side + 1) % 4]);
+                self.line([from[0], y, from[1]], [to[0], y, to[1]], color);
+            }
+        }
+    }
+}
+
+/// The space the chunk at `coord` takes up, whatever there is in it.
+pub fn chunk_aabb(coord: ChunkCoord) -> Aabb {
+    let origin = coord.origin();
+    let min = [origin[0] as f32, origin[1] as f32, origin[2] as f32];
+    let size = CHUNK_SIZE as f32;
+    Aabb::new(min, [min[0] + size, min[1] + size, min[2] + size])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_pairs_of_vertices() {
+        let mut lines = DebugLines::new();
+        assert!(lines.is_empty());
+        lines.line([0.0; 3], [1.0, 2.0, 3.0], BORDER_COLOR);
+        assert_eq!(lines.vertices().len(), 2);
+        assert_eq!(lines.vertices()[1].position, [1.0, 2.0, 3.0]);
+        lines.clear();
+        assert!(lines.is_empty());
+    }
+
+    #[test]
+    fn box_edges_run_along_one_axis_each() {
+        let mut lines = DebugLines::new();
+        lines.aabb(&Aabb::new([0.0; 3], [1.0, 2.0, 3.0]), ChunkState::Resident.color());
+        assert_eq!(lines.vertices().len(), 24);
+        let mut per_axis = [0; 3];
+        for pair in lines.vertices().chunks(2) {
+            let (from, to) = (pair[0].position, pair[1].position);
+            let changed: Vec<usize> = (0..3).filter(|&axis| from[axis] != to[axis]).collect();
+            assert_eq!(changed.len(), 1, "{:?} to {:?}", from, to);
+            per_axis[changed[0]] += 1;
+        }
+        assert_eq!(per_axis, [4, 4, 4]);
+    }
+
+    #[test]
+    fn chunk_columns_cover_every_layer() {
+        let mut lines = DebugLines::new();
+        lines.chunk_column(ChunkCoord::new(-1, 3, 2), 0..5, BORDER_COLOR);
+        // Four corners up the sides, and six rings of four
+        assert_eq!(lines.vertices().len(), (4 + 6 * 4) * 2);
+        let size = CHUNK_SIZE as f32;
+        for vertex in lines.vertices() {
+            let (x, y, z) = (vertex.position[0], vertex.position[1], vertex.position[2]);
+            assert!(x == -size || x == 0.0);
+            assert!(z == 2.0 * size || z == 3.0 * size);
+            assert!(y >= 0.0 && y <= 5.0 * size);
+        }
+    }
+
+    #[test]
+    fn chunk_boxes_line_up_with_block_positions() {
+        let bounds = chunk_aabb(ChunkCoord::new(1, 0, -1));
+        let size = CHUNK_SIZE as f32;
+        assert_eq!(bounds.min, [size, 0.0, -size]);
+        assert_eq!(bounds.max, [2.0 * size, size, 0.0]);
+    }
+}
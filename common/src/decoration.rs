@@ -0,0 +1,392 @@
+//! Features bigger than a block that are placed on top of the terrain: trees, and caves that
+//! wander further than the cave noise's tunnels do.
+//!
+//! Features don't line up with chunks. A tree near the edge of a chunk spreads its leaves into
+//! the next one, and a cave worm can wander several chunks away from where it started. Each
+//! feature belongs to the chunk it starts in and is placed when that chunk is generated, through
+//! a `FeatureWriter`, which sets the blocks in that chunk straight away and collects the rest as
+//! `BlockEdit`s for the chunks they fall in.
+//!
+//! Those go to `PendingEdits`, which holds on to them for chunks that haven't been generated
+//! yet. They're kept after they've been applied, grouped by the chunk they came from, so a chunk
+//! that's unloaded and generated again gets them back; generating the chunk they came from again
+//! replaces them rather than adding more.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::ops::Range;
+
+use noise::Random;
+use world::{ BlockId, Chunk, ChunkCoord, CHUNK_SIZE };
+
+/// What an edit is allowed to replace.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EditMode {
+    /// Only fills air, so trees don't grow into hillsides or each other.
+    Fill,
+    /// Replaces whatever is there.
+    Replace,
+}
+
+/// A block a feature places in a chunk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockEdit {
+    /// Where in the chunk the block goes.
+    pub local: [u8; 3],
+    pub block: BlockId,
+    pub mode: EditMode,
+}
+
+impl BlockEdit {
+    pub fn local(&self) -> [usize; 3] {
+        [self.local[0] as usize, self.local[1] as usize, self.local[2] as usize]
+    }
+
+    /// Whether the edit would be made over `existing`.
+    pub fn applies_over(&self, existing: BlockId) -> bool {
+        existing != self.block && (self.mode == EditMode::Replace || existing.is_air())
+    }
+
+    /// Makes the edit to `chunk`, returning whether anything changed.
+    pub fn apply(&self, chunk: &mut Chunk) -> bool {
+        let local = self.local();
+        if self.applies_over(chunk.get(local)) {
+            chunk.set(local, self.block);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// The edits a chunk's features make to other chunks, by the chunk they're in.
+pub type Spill = Vec<(ChunkCoord, Vec<BlockEdit>)>;
+
+/// Places a chunk's features, setting the blocks in the chunk itself and collecting the rest.
+pub struct FeatureWriter<'a> {
+    coord: ChunkCoord,
+    chunk: &'a mut Chunk,
+    /// The rows of chunks the world has. Blocks outside them are dropped.
+    layers: Range<i32>,
+    spill: HashMap<ChunkCoord, Vec<BlockEdit>>,
+}
+
+impl<'a> FeatureWriter<'a> {
+    pub fn new(coord: ChunkCoord, chunk: &'a mut Chunk, layers: Range<i32>) -> Self {
+        FeatureWriter {
+            coord,
+            chunk,
+            layers,
+            spill: HashMap::new(),
+        }
+    }
+
+    /// The block at world position `position`, if it's in the chunk being decorated.
+    pub fn get(&self, position: [i32; 3]) -> Option<BlockId> {
+        let (coord, local) = ChunkCoord::of_block(position);
+        if coord == self.coord { Some(self.chunk.get(local)) } else { None }
+    }
+
+    /// Places `block` at world position `position`.
+    pub fn set(&mut self, position: [i32; 3], block: BlockId, mode: EditMode) {
+        let (coord, local) = ChunkCoord::of_block(position);
+        if coord.y < self.layers.start || coord.y >= self.layers.end {
+            return;
+        }
+        let edit = BlockEdit {
+            local: [local[0] as u8, local[1] as u8, local[2] as u8],
+            block,
+            mode,
+        };
+        if coord == self.coord {
+            edit.apply(self.chunk);
+        } else {
+            self.spill.entry(coord).or_insert_with(Vec::new).push(edit);
+        }
+    }
+
+    /// The edits for other chunks. Where more than one was made to the same block, only the
+    /// last is kept, so a worm that carves the same spot on every step doesn't leave a pile of
+    /// copies.
+    pub fn finish(self) -> Spill {
+        let mut spill: Spill = self
+            .spill
+            .into_iter()
+            .map(|(coord, mut edits)| {
+                // Newest first, so the stable sort keeps it first among its block's edits
+                edits.reverse();
+                edits.sort_by_key(|edit| edit.local);
+                edits.dedup_by_key(|edit| edit.local);
+                (coord, edits)
+            })
+            .collect();
+        spill.sort_by_key(|&(coord, _)| coord);
+        spill
+    }
+}
+
+/// The edits chunks' features have made to other chunks.
+#[derive(Default)]
+pub struct PendingEdits {
+    /// By the chunk they're for, and then by the chunk whose features made them.
+    edits: HashMap<ChunkCoord, HashMap<ChunkCoord, Vec<BlockEdit>>>,
+    /// The chunks each chunk last made edits to, so they can be found again to replace.
+    targets: HashMap<ChunkCoord, Vec<ChunkCoord>>,
+}
+
+impl PendingEdits {
+    pub fn new() -> Self {
+        PendingEdits::default()
+    }
+
+    /// Records the edits the chunk at `source` made to other chunks, replacing whatever it made
+    /// the last time it was generated.
+    pub fn insert(&mut self, source: ChunkCoord, spill: &Spill) {
+        for target in self.targets.remove(&source).unwrap_or_default() {
+            let now_empty = self.edits.get_mut(&target).map_or(false, |sources| {
+                sources.remove(&source);
+                sources.is_empty()
+            });
+            if now_empty {
+                self.edits.remove(&target);
+            }
+        }
+        for (target, edits) in spill {
+            self.edits.entry(*target).or_insert_with(HashMap::new).insert(source, edits.clone());
+        }
+        if !spill.is_empty() {
+            self.targets.insert(source, spill.iter().map(|&(target, _)| target).collect());
+        }
+    }
+
+    /// Makes all the edits other chunks have made to the chunk at `coord`, to it freshly
+    /// generated. They're applied in order of the chunk they came from rather than the order
+    /// those were generated in, so generating the chunk again always gives the same result.
+    pub fn apply(&self, coord: ChunkCoord, chunk: &mut Chunk) {
+        if let Some(sources) = self.edits.get(&coord) {
+            let mut sources: Vec<_> = sources.iter().collect();
+            sources.sort_by_key(|&(&source, _)| source);
+            for (_, edits) in sources {
+                for edit in edits {
+                    edit.apply(chunk);
+                }
+            }
+        }
+    }
+
+    /// The edits the chunk at `source` made to other chunks, as they were recorded.
+    pub fn spill_from(&self, source: ChunkCoord) -> Spill {
+        let targets = match self.targets.get(&source) {
+            Some(targets) => targets,
+            None => return Vec::new(),
+        };
+        targets
+            .iter()
+            .filter_map(|&target| {
+                let edits = self.edits.get(&target).and_then(|sources| sources.get(&source));
+                edits.map(|edits| (target, edits.clone()))
+            })
+            .collect()
+    }
+
+    /// How many edits are being held on to, for keeping an eye on memory.
+    pub fn edit_count(&self) -> usize {
+        self.edits.values().flat_map(|sources| sources.values()).map(Vec::len).sum()
+    }
+}
+
+/// Grows a tree of `log` and `leaves` whose trunk starts at `base`, shaped by `random`: a trunk
+/// 4 to 6 blocks tall with a canopy two blocks out around the top and one block out above it.
+pub fn plant_tree(writer: &mut FeatureWriter, base: [i32; 3], log: BlockId, leaves: BlockId, random: &mut Random) {
+    let height = random.range(4, 7);
+    for y in 0..height {
+        writer.set([base[0], base[1] + y, base[2]], log, EditMode::Fill);
+    }
+
+    let top = base[1] + height - 1;
+    for y in top - 1..top + 3 {
+        let radius: i32 = if y <= top { 2 } else { 1 };
+        for dz in -radius..radius + 1 {
+            for dx in -radius..radius + 1 {
+                let corner = dx.abs() == radius && dz.abs() == radius;
+                // The wide layers lose some of their corners, and the top one all of them
+                if corner && (y == top + 2 || random.next_f32() < 0.5) {
+                    continue;
+                }
+                writer.set([base[0] + dx, y, base[2] + dz], leaves, EditMode::Fill);
+            }
+        }
+    }
+}
+
+/// How many blocks a cave worm moves before it stops.
+const WORM_LENGTH: Range<i32> = 64..128;
+/// The thinnest and thickest a worm's tunnel gets, in blocks from its middle.
+const WORM_RADIUS: Range<f32> = 1.5..3.5;
+
+/// Carves a winding tunnel from `start`, shaped by `random`. It squeezes wider and narrower as it
+/// goes, and turns more sideways than up and down. Blocks below `floor` are left alone, so the
+/// tunnel can't cut through the bottom of the world.
+pub fn carve_worm(writer: &mut FeatureWriter, start: [f32; 3], floor: i32, random: &mut Random) {
+    let length = random.range(WORM_LENGTH.start, WORM_LENGTH.end);
+    let mut position = start;
+    let mut yaw = random.next_f32() * 2.0 * PI;
+    let mut pitch = (random.next_f32() - 0.5) * 0.5;
+    let phase = random.next_f32() * 2.0 * PI;
+
+    for step in 0..length {
+        let swell = ((step as f32 * 0.15 + phase).sin() + 1.0) * 0.5;
+        let radius = WORM_RADIUS.start + (WORM_RADIUS.end - WORM_RADIUS.start) * swell;
+        carve_sphere(writer, position, radius, floor);
+
+        yaw += (random.next_f32() - 0.5) * 0.6;
+        pitch = (pitch * 0.8 + (random.next_f32() - 0.5) * 0.3).max(-0.6).min(0.6);
+        position[0] += pitch.cos() * yaw.cos();
+        position[1] += pitch.sin();
+        position[2] += pitch.cos() * yaw.sin();
+    }
+}
+
+fn carve_sphere(writer: &mut FeatureWriter, center: [f32; 3], radius: f32, floor: i32) {
+    let reach = radius.ceil() as i32;
+    let middle = [center[0].floor() as i32, center[1].floor() as i32, center[2].floor() as i32];
+    for dy in -reach..reach + 1 {
+        for dz in -reach..reach + 1 {
+            for dx in -reach..reach + 1 {
+                let block = [middle[0] + dx, middle[1] + dy, middle[2] + dz];
+                if block[1] < floor {
+                    continue;
+                }
+                let distance = |axis: usize| block[axis] as f32 + 0.5 - center[axis];
+                if distance(0).powi(2) + distance(1).powi(2) + distance(2).powi(2) <= radius * radius {
+                    writer.set(block, BlockId::AIR, EditMode::Replace);
+                }
+            }
+        }
+    }
+}
+
+/// Where a feature starting somewhere in the chunk at `coord` starts, picked by `random`.
+pub fn random_position(coord: ChunkCoord, random: &mut Random) -> [i32; 3] {
+    let mut local = || random.range(0, CHUNK_SIZE as i32) as usize;
+    coord.block_position([local(), local(), local()])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE: BlockId = BlockId(1);
+    const LEAVES: BlockId = BlockId(2);
+
+    #[test]
+    fn edits_outside_the_chunk_go_to_the_chunk_theyre_in() {
+        let coord = ChunkCoord::new(0, 0, 0);
+        let mut chunk = Chunk::new();
+        let spill = {
+            let mut writer = FeatureWriter::new(coord, &mut chunk, 0..2);
+            writer.set([31, 5, 0], LEAVES, EditMode::Fill);
+            writer.set([32, 5, 0], LEAVES, EditMode::Fill);
+            writer.set([-1, 40, 3], LEAVES, EditMode::Fill);
+            // Below the world
+            writer.set([0, -1, 0], LEAVES, EditMode::Fill);
+            writer.finish()
+        };
+
+        assert_eq!(chunk.get([31, 5, 0]), LEAVES);
+        assert_eq!(
+            spill.iter().map(|&(coord, ref edits)| (coord, edits.len())).collect::<Vec<_>>(),
+            [(ChunkCoord::new(-1, 1, 0), 1), (ChunkCoord::new(1, 0, 0), 1)],
+        );
+        assert_eq!(spill[0].1[0].local, [31, 8, 3]);
+    }
+
+    #[test]
+    fn only_the_last_edit_to_a_block_is_kept() {
+        let mut chunk = Chunk::new();
+        let spill = {
+            let mut writer = FeatureWriter::new(ChunkCoord::new(0, 0, 0), &mut chunk, 0..1);
+            writer.set([40, 0, 0], LEAVES, EditMode::Fill);
+            writer.set([40, 0, 0], BlockId::AIR, EditMode::Replace);
+            writer.finish()
+        };
+        assert_eq!(spill[0].1, [BlockEdit { local: [8, 0, 0], block: BlockId::AIR, mode: EditMode::Replace }]);
+    }
+
+    #[test]
+    fn fill_edits_only_go_into_air() {
+        let mut chunk = Chunk::filled(STONE);
+        chunk.set([0, 0, 0], BlockId::AIR);
+        let fill = |local| BlockEdit { local, block: LEAVES, mode: EditMode::Fill };
+        assert!(fill([0, 0, 0]).apply(&mut chunk));
+        assert!(!fill([1, 0, 0]).apply(&mut chunk));
+        assert_eq!(chunk.get([1, 0, 0]), STONE);
+
+        let carve = BlockEdit { local: [1, 0, 0], block: BlockId::AIR, mode: EditMode::Replace };
+        assert!(carve.apply(&mut chunk));
+        assert!(!carve.apply(&mut chunk));
+    }
+
+    #[test]
+    fn generating_a_chunk_again_replaces_its_pending_edits() {
+        let (source, target) = (ChunkCoord::new(0, 0, 0), ChunkCoord::new(1, 0, 0));
+        let edit = |x| BlockEdit { local: [x, 0, 0], block: LEAVES, mode: EditMode::Fill };
+        let mut pending = PendingEdits::new();
+        pending.insert(source, &vec![(target, vec![edit(0), edit(1)])]);
+        pending.insert(source, &vec![(target, vec![edit(2)])]);
+        assert_eq!(pending.edit_count(), 1);
+        assert_eq!(pending.spill_from(source), vec![(target, vec![edit(2)])]);
+
+        let mut chunk = Chunk::new();
+        pending.apply(target, &mut chunk);
+        assert_eq!(chunk.get([2, 0, 0]), LEAVES);
+        assert!(chunk.get([0, 0, 0]).is_air());
+
+        // Still there for the next time the chunk is generated
+        let mut again = Chunk::new();
+        pending.apply(target, &mut again);
+        assert_eq!(again, chunk);
+
+        pending.insert(source, &Vec::new());
+        assert_eq!(pending.edit_count(), 0);
+    }
+
+    #[test]
+    fn trees_have_a_trunk_under_a_canopy() {
+        let coord = ChunkCoord::new(0, 0, 0);
+        let mut chunk = Chunk::new();
+        let spill = {
+            let mut writer = FeatureWriter::new(coord, &mut chunk, 0..1);
+            plant_tree(&mut writer, [0, 10, 10], STONE, LEAVES, &mut Random::new(5));
+            writer.finish()
+        };
+
+        assert_eq!(chunk.get([0, 10, 10]), STONE);
+        assert_eq!(chunk.get([0, 13, 10]), STONE);
+        let top = (10..CHUNK_SIZE).rev().find(|&y| !chunk.get([0, y, 10]).is_air()).unwrap();
+        assert_eq!(chunk.get([0, top, 10]), LEAVES);
+        // The canopy reaches over into the chunk next door
+        assert_eq!(spill.len(), 1);
+        assert!(spill[0].1.iter().all(|edit| edit.block == LEAVES && edit.local[0] >= 30));
+    }
+
+    #[test]
+    fn worms_carve_a_long_tunnel_and_leave_the_floor() {
+        let coord = ChunkCoord::new(0, 0, 0);
+        let mut chunk = Chunk::filled(STONE);
+        let spill = {
+            let mut writer = FeatureWriter::new(coord, &mut chunk, 0..1);
+            carve_worm(&mut writer, [16.0, 3.0, 16.0], 2, &mut Random::new(9));
+            writer.finish()
+        };
+
+        let carved = chunk.block_counts().into_iter().find(|&(block, _)| block.is_air()).unwrap().1;
+        let spilled: usize = spill.iter().map(|&(_, ref edits)| edits.len()).sum();
+        assert!(carved + spilled as u32 > 64 * 4, "only carved {} and spilled {}", carved, spilled);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                assert_eq!(chunk.get([x, 1, z]), STONE);
+            }
+        }
+    }
+}
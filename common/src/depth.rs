@@ -0,0 +1,82 @@
+//! Picking a depth format. The depth images themselves are `AttachmentImages::depth`, the
+//! depth images shadows are drawn into are `shadow::ShadowMap`s, and the G-buffer's depth is in
+//! `gbuffer::GBuffer`.
+
+use hal::{
+    format as f, image as i,
+    Adapter, Backend, PhysicalDevice,
+};
+
+use error::{ RendererError, Result };
+
+/// Depth formats we're happy to use, best first. `D32Float` has the most precision but isn't
+/// supported everywhere as an attachment.
+const DEPTH_FORMATS: [f::Format; 3] = [
+    f::Format::D32Float,
+    f::Format::D32FloatS8Uint,
+    f::Format::D24UnormS8Uint,
+];
+
+/// Picks the best depth format the adapter supports as an optimally tiled attachment.
+pub fn choose_depth_format<B: Backend>(adapter: &Adapter<B>) -> Result<f::Format> {
+    DEPTH_FORMATS
+        .iter()
+        .cloned()
+        .find(|&format| {
+            adapter.physical_device
+                .format_properties(Some(format))
+                .optimal_tiling
+                .contains(f::ImageFeature::DEPTH_STENCIL_ATTACHMENT)
+        })
+        .ok_or_else(|| RendererError::UnsupportedFormat {
+            usage: "depth attachment",
+            tried: DEPTH_FORMATS.to_vec(),
+        })
+}
+
+/// Depth formats for depth images that are sampled as well as drawn into, like shadow maps and
+/// the G-buffer's depth, best first. They're depth-only, so a view of the depth is a view of the
+/// whole image.
+const SAMPLED_DEPTH_FORMATS: [f::Format; 2] = [f::Format::D32Float, f::Format::D16Unorm];
+
+/// Picks the best depth format the adapter supports both as an optimally tiled attachment and
+/// for sampling in a shader, for shadow maps.
+pub fn choose_shadow_format<B: Backend>(adapter: &Adapter<B>) -> Result<f::Format> {
+    choose_sampled_depth_format(adapter, "shadow map")
+}
+
+/// Like `choose_shadow_format`, for the depth in a `GBuffer`, which the lighting pass works
+/// each pixel's position out from.
+pub fn choose_gbuffer_depth_format<B: Backend>(adapter: &Adapter<B>) -> Result<f::Format> {
+    choose_sampled_depth_format(adapter, "G-buffer depth")
+}
+
+fn choose_sampled_depth_format<B: Backend>(adapter: &Adapter<B>, usage: &'static str) -> Result<f::Format> {
+    SAMPLED_DEPTH_FORMATS
+        .iter()
+        .cloned()
+        .find(|&format| {
+            adapter.physical_device
+                .format_properties(Some(format))
+                .optimal_tiling
+                .contains(f::ImageFeature::DEPTH_STENCIL_ATTACHMENT | f::ImageFeature::SAMPLED)
+        })
+        .ok_or_else(|| RendererError::UnsupportedFormat {
+            usage,
+            tried: SAMPLED_DEPTH_FORMATS.to_vec(),
+        })
+}
+
+/// The part of a depth image its view covers.
+pub fn depth_range(format: f::Format) -> i::SubresourceRange {
+    let aspects = if format.surface_desc().aspects.contains(f::Aspects::STENCIL) {
+        f::Aspects::DEPTH | f::Aspects::STENCIL
+    } else {
+        f::Aspects::DEPTH
+    };
+    i::SubresourceRange {
+        aspects,
+        levels: 0..1,
+        layers: 0..1,
+    }
+}
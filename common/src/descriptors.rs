@@ -0,0 +1,204 @@
+//! Descriptor set layouts, descriptor pools that grow on demand, and per-frame uniform buffers.
+//!
+//! Descriptor sets are how shaders get at buffers and images. Every chapter past the first few
+//! needs at least one to pass in camera matrices, so this module takes care of the bookkeeping:
+//! `DescriptorSetLayout` owns a layout and remembers its bindings, `DescriptorAllocator` hands
+//! out sets for a layout and makes a bigger pool whenever the current ones run out, and
+//! `UniformRing` keeps one copy of a uniform block per frame in flight, each with its own set,
+//! in a buffer that stays mapped.
+
+use std::cell::RefCell;
+use std::marker::PhantomData;
+use std::mem;
+use std::rc::Rc;
+
+use hal::{
+    buffer,
+    pso::{ self, DescriptorRangeDesc, DescriptorSetLayoutBinding },
+    Adapter, Backend, DescriptorPool, Device, PhysicalDevice,
+};
+
+use allocator::{ Allocator, MemoryCategory };
+use buffer::{ MappedBuffer, NON_COHERENT_ATOM_SIZE };
+use error::Result;
+
+/// The number of sets the first pool made by a `DescriptorAllocator` can hold. Each pool after
+/// that is twice as big as the last.
+const INITIAL_POOL_SIZE: usize = 16;
+
+pub struct DescriptorSetLayout<B: Backend> {
+    device: Rc<B::Device>,
+    layout: Option<B::DescriptorSetLayout>,
+    bindings: Vec<DescriptorSetLayoutBinding>,
+}
+
+impl<B: Backend> DescriptorSetLayout<B> {
+    pub fn new(device: Rc<B::Device>, bindings: Vec<DescriptorSetLayoutBinding>) -> Self {
+        let layout = device.create_descriptor_set_layout(&bindings, &[]);
+        DescriptorSetLayout {
+            device,
+            layout: Some(layout),
+            bindings,
+        }
+    }
+
+    pub fn raw(&self) -> &B::DescriptorSetLayout {
+        self.layout.as_ref().unwrap()
+    }
+
+    pub fn bindings(&self) -> &[DescriptorSetLayoutBinding] {
+        &self.bindings
+    }
+
+    /// The descriptor ranges a pool needs to allocate `sets` sets of this layout.
+    fn ranges(&self, sets: usize) -> Vec<DescriptorRangeDesc> {
+        self.bindings
+            .iter()
+            .map(|binding| DescriptorRangeDesc {
+                ty: binding.ty,
+                count: binding.count * sets,
+            })
+            .collect()
+    }
+}
+
+impl<B: Backend> Drop for DescriptorSetLayout<B> {
+    fn drop(&mut self) {
+        if let Some(layout) = self.layout.take() {
+            self.device.destroy_descriptor_set_layout(layout);
+        }
+    }
+}
+
+/// Allocates descriptor sets for a single layout, adding pools as needed.
+pub struct DescriptorAllocator<B: Backend> {
+    device: Rc<B::Device>,
+    layout: Rc<DescriptorSetLayout<B>>,
+    pools: Vec<B::DescriptorPool>,
+    next_pool_size: usize,
+}
+
+impl<B: Backend> DescriptorAllocator<B> {
+    pub fn new(device: Rc<B::Device>, layout: Rc<DescriptorSetLayout<B>>) -> Self {
+        DescriptorAllocator {
+            device,
+            layout,
+            pools: Vec::new(),
+            next_pool_size: INITIAL_POOL_SIZE,
+        }
+    }
+
+    pub fn layout(&self) -> &Rc<DescriptorSetLayout<B>> {
+        &self.layout
+    }
+
+    pub fn allocate(&mut self) -> Result<B::DescriptorSet> {
+        if let Some(pool) = self.pools.last_mut() {
+            if let Ok(set) = pool.allocate_set(self.layout.raw()) {
+                return Ok(set);
+            }
+        }
+
+        // The newest pool is full (or there isn't one yet), so make a bigger one
+        let sets = self.next_pool_size;
+        self.next_pool_size *= 2;
+        let mut pool = self.device.create_descriptor_pool(sets, &self.layout.ranges(sets));
+        let set = pool.allocate_set(self.layout.raw());
+        self.pools.push(pool);
+        Ok(set?)
+    }
+
+    /// Frees every set allocated so far. The caller must make sure none of them are in use.
+    pub fn reset(&mut self) {
+        for pool in &mut self.pools {
+            pool.reset();
+        }
+    }
+}
+
+impl<B: Backend> Drop for DescriptorAllocator<B> {
+    fn drop(&mut self) {
+        for pool in self.pools.drain(..) {
+            self.device.destroy_descriptor_pool(pool);
+        }
+    }
+}
+
+/// A uniform block of type `T` with one copy per frame in flight, each bound to its own
+/// descriptor set at `binding`. Writing frame `n`'s copy while the gpu is still reading frame
+/// `n - 1`'s doesn't need any synchronization beyond what already guards the frame itself.
+///
+/// The buffer is mapped once, up front, and each update is a copy through the pointer, flushed
+/// if the memory isn't coherent. Copies are a whole number of atoms apart, so flushing one
+/// never touches another frame's.
+pub struct UniformRing<B: Backend, T: Copy> {
+    buffer: MappedBuffer<B>,
+    sets: Vec<B::DescriptorSet>,
+    stride: u64,
+    _marker: PhantomData<T>,
+}
+
+impl<B: Backend, T: Copy> UniformRing<B, T> {
+    pub fn new(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        adapter: &Adapter<B>,
+        descriptors: &mut DescriptorAllocator<B>,
+        binding: u32,
+        frames: usize,
+    ) -> Result<Self> {
+        // Each copy has to start at a multiple of the device's uniform buffer offset alignment,
+        // and of the flush granularity. Both are powers of two, so the bigger is a multiple of
+        // the other.
+        let alignment = adapter
+            .physical_device
+            .limits()
+            .min_uniform_buffer_offset_alignment
+            .max(NON_COHERENT_ATOM_SIZE);
+        let size = mem::size_of::<T>() as u64;
+        let stride = (size + alignment - 1) / alignment * alignment;
+
+        let buffer = MappedBuffer::new(
+            device.clone(),
+            allocator,
+            stride * frames as u64,
+            buffer::Usage::UNIFORM,
+            MemoryCategory::Uniforms,
+        )?;
+
+        let sets = (0..frames).map(|_| descriptors.allocate()).collect::<Result<Vec<_>>>()?;
+        device.write_descriptor_sets(sets.iter().enumerate().map(|(frame, set)| {
+            let offset = stride * frame as u64;
+            pso::DescriptorSetWrite {
+                set,
+                binding,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Buffer(
+                    buffer.buffer(),
+                    Some(offset)..Some(offset + size),
+                )),
+            }
+        }));
+
+        Ok(UniformRing {
+            buffer,
+            sets,
+            stride,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Overwrites the copy used by `frame`.
+    pub fn update(&self, frame: usize, value: &T) -> Result<()> {
+        self.buffer.write_at(self.stride * frame as u64, &[*value])
+    }
+
+    /// The descriptor set pointing at `frame`'s copy.
+    pub fn set(&self, frame: usize) -> &B::DescriptorSet {
+        &self.sets[frame]
+    }
+
+    pub fn frames(&self) -> usize {
+        self.sets.len()
+    }
+}
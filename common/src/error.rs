@@ -0,0 +1,212 @@
+//! The error type for everything that can go wrong while setting up or running the renderer.
+//!
+//! Most `hal` calls have their own error type. They all convert into `RendererError` so they can
+//! be propagated with `?`, and the ones that are likely to be the user's (or their machine's)
+//! problem rather than ours carry enough context to say what to try instead.
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use hal::{
+    self, buffer, device, format as f, image as i, mapping, pso,
+};
+use winit;
+
+use allocator::AllocationError;
+use backend::Backend;
+use graph::GraphError;
+
+/// A short description of an adapter, for listing the options when the one we wanted isn't
+/// usable.
+#[derive(Clone, Debug)]
+pub struct AdapterSummary {
+    pub index: usize,
+    pub name: String,
+    pub device_type: hal::adapter::DeviceType,
+    /// Whether it has a graphics queue family that can present to the window.
+    pub can_present: bool,
+}
+
+impl fmt::Display for AdapterSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} ({:?})", self.index, self.name, self.device_type)?;
+        if !self.can_present {
+            write!(f, ", can't present to the window")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum RendererError {
+    /// No backend features were enabled when building.
+    NoBackends,
+    /// A backend was asked for that wasn't compiled in.
+    BackendUnavailable { requested: Backend, available: Vec<Backend> },
+    /// A command line option or setting had a value we can't use.
+    InvalidOption(String),
+    Window(winit::CreationError),
+    /// None of the adapters can present to the window.
+    NoSuitableAdapter { adapters: Vec<AdapterSummary> },
+    /// `--adapter` didn't match any adapter, or matched one that can't present.
+    AdapterNotUsable { requested: String, adapters: Vec<AdapterSummary> },
+    DeviceCreation { adapter: String, error: hal::error::DeviceCreationError },
+    /// None of the formats we can work with are supported for `usage`.
+    UnsupportedFormat { usage: &'static str, tried: Vec<f::Format> },
+    /// A device feature that `needed_for` can't do without isn't supported.
+    UnsupportedFeature { feature: &'static str, needed_for: &'static str },
+    /// The swapchain's images came back as a kind of backbuffer we can't draw into.
+    UnsupportedBackbuffer { kind: &'static str },
+    Allocation(AllocationError),
+    OutOfMemory(device::OutOfMemory),
+    HostExecution(hal::error::HostExecutionError),
+    /// The gpu stopped responding or the driver was reset, and everything created from the
+    /// device is gone. See `is_device_lost`.
+    DeviceLost,
+    BufferCreation(buffer::CreationError),
+    ImageCreation(i::CreationError),
+    ImageView(i::ViewError),
+    Bind(device::BindError),
+    Framebuffer(device::FramebufferError),
+    Shader(device::ShaderError),
+    Pipeline(pso::CreationError),
+    DescriptorAllocation(pso::AllocationError),
+    Mapping(mapping::Error),
+    /// A render graph's passes don't fit together.
+    Graph(GraphError),
+    /// A texture or other asset couldn't be loaded.
+    Asset(String),
+    /// A screenshot couldn't be read back or saved.
+    Screenshot(String),
+    Io(io::Error),
+}
+
+impl RendererError {
+    /// Whether the device was lost. The device and everything created from it have to be thrown
+    /// away when this happens, but opening the adapter again usually works.
+    pub fn is_device_lost(&self) -> bool {
+        match *self {
+            RendererError::DeviceLost => true,
+            RendererError::HostExecution(hal::error::HostExecutionError::DeviceLost) => true,
+            RendererError::DeviceCreation { error: hal::error::DeviceCreationError::DeviceLost, .. } => true,
+            _ => false,
+        }
+    }
+}
+
+/// Shorthand for results with a `RendererError`.
+pub type Result<T> = ::std::result::Result<T, RendererError>;
+
+impl fmt::Display for RendererError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RendererError::NoBackends => write!(
+                f,
+                "No backends were compiled in, enable one of the `vulkan`, `dx12` or `metal` features",
+            ),
+            RendererError::BackendUnavailable { requested, ref available } => write!(
+                f,
+                "The {} backend is not available in this build (available: {:?}). Rebuild with \
+                 `--features {}` or pick one of the available backends with --backend",
+                requested, available, requested,
+            ),
+            RendererError::InvalidOption(ref message) => f.write_str(message),
+            RendererError::Window(ref err) => write!(f, "Failed to create the window: {}", err),
+            RendererError::NoSuitableAdapter { ref adapters } => {
+                writeln!(f, "None of the available adapters can present to the window.")?;
+                write_adapters(f, adapters)?;
+                write!(f, "Make sure your graphics drivers are up to date, or try another backend with --backend")
+            }
+            RendererError::AdapterNotUsable { ref requested, ref adapters } => {
+                writeln!(f, "No usable adapter matches '{}'.", requested)?;
+                write_adapters(f, adapters)?;
+                write!(f, "Pass the index or part of the name of one that can present with --adapter")
+            }
+            RendererError::DeviceCreation { ref adapter, ref error } => {
+                write!(f, "Failed to open a device on '{}': {:?}", adapter, error)
+            }
+            RendererError::UnsupportedFormat { usage, ref tried } => write!(
+                f,
+                "The adapter doesn't support any {} format (tried {:?})",
+                usage, tried,
+            ),
+            RendererError::UnsupportedFeature { feature, needed_for } => write!(
+                f,
+                "The adapter doesn't support {}, which is needed for {}",
+                feature, needed_for,
+            ),
+            RendererError::UnsupportedBackbuffer { kind } => write!(
+                f,
+                "The swapchain gave back a {} backbuffer, but only images are supported",
+                kind,
+            ),
+            RendererError::Allocation(ref err) => write!(f, "Failed to allocate gpu memory: {:?}", err),
+            RendererError::OutOfMemory(ref err) => write!(f, "Out of memory: {:?}", err),
+            RendererError::HostExecution(ref err) => write!(f, "The device stopped responding: {:?}", err),
+            RendererError::DeviceLost => f.write_str("The device was lost, the graphics driver may have been reset"),
+            RendererError::BufferCreation(ref err) => write!(f, "Failed to create a buffer: {:?}", err),
+            RendererError::ImageCreation(ref err) => write!(f, "Failed to create an image: {:?}", err),
+            RendererError::ImageView(ref err) => write!(f, "Failed to create an image view: {:?}", err),
+            RendererError::Bind(ref err) => write!(f, "Failed to bind memory: {:?}", err),
+            RendererError::Framebuffer(ref err) => write!(f, "Failed to create a framebuffer: {:?}", err),
+            RendererError::Shader(ref err) => write!(f, "Failed to create a shader module: {:?}", err),
+            RendererError::Pipeline(ref err) => write!(f, "Failed to create a pipeline: {:?}", err),
+            RendererError::DescriptorAllocation(ref err) => {
+                write!(f, "Failed to allocate a descriptor set: {:?}", err)
+            }
+            RendererError::Mapping(ref err) => write!(f, "Failed to map memory: {:?}", err),
+            RendererError::Graph(ref err) => write!(f, "Failed to compile the render graph: {}", err),
+            RendererError::Asset(ref message) => f.write_str(message),
+            RendererError::Screenshot(ref message) => write!(f, "Failed to take a screenshot: {}", message),
+            RendererError::Io(ref err) => write!(f, "{}", err),
+        }
+    }
+}
+
+fn write_adapters(f: &mut fmt::Formatter, adapters: &[AdapterSummary]) -> fmt::Result {
+    if adapters.is_empty() {
+        return writeln!(f, "No adapters were found.");
+    }
+    writeln!(f, "Adapters:")?;
+    for adapter in adapters {
+        writeln!(f, "  {}", adapter)?;
+    }
+    Ok(())
+}
+
+impl Error for RendererError {
+    fn description(&self) -> &str {
+        "renderer error"
+    }
+}
+
+macro_rules! impl_from {
+    ($($source:ty => $variant:ident,)*) => {
+        $(
+            impl From<$source> for RendererError {
+                fn from(err: $source) -> Self {
+                    RendererError::$variant(err)
+                }
+            }
+        )*
+    };
+}
+
+impl_from! {
+    winit::CreationError => Window,
+    AllocationError => Allocation,
+    device::OutOfMemory => OutOfMemory,
+    hal::error::HostExecutionError => HostExecution,
+    buffer::CreationError => BufferCreation,
+    i::CreationError => ImageCreation,
+    i::ViewError => ImageView,
+    device::BindError => Bind,
+    device::FramebufferError => Framebuffer,
+    device::ShaderError => Shader,
+    pso::CreationError => Pipeline,
+    pso::AllocationError => DescriptorAllocation,
+    mapping::Error => Mapping,
+    GraphError => Graph,
+    io::Error => Io,
+}
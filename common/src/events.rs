@@ -0,0 +1,118 @@
+//! The window events every chapter responds to the same way.
+
+use winit;
+
+/// What a chapter's main loop should do in response to a window event.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowAction {
+    /// The window was closed or Escape was pressed. Chapters that grab the cursor use Escape
+    /// to let go of it first.
+    Close,
+    /// The window changed size, or moved to a monitor with a different scale factor, so the
+    /// swapchain needs to be rebuilt.
+    Resize,
+    /// F10 was pressed: switch between vsync and non-vsync present modes.
+    ToggleVsync,
+    /// Alt+Enter was pressed: switch between windowed and fullscreen.
+    ToggleFullscreen,
+    /// F12 was pressed: save the next frame to a PNG.
+    Screenshot,
+}
+
+/// Maps `event` to the action the main loop should take, if any.
+pub fn window_action(event: &winit::WindowEvent) -> Option<WindowAction> {
+    match *event {
+        winit::WindowEvent::CloseRequested => Some(WindowAction::Close),
+        winit::WindowEvent::Resized(_) => Some(WindowAction::Resize),
+        // The logical size stays the same when the scale factor changes, but the physical size
+        // (and so the swapchain extent) doesn't, and some platforms don't send `Resized` for it
+        winit::WindowEvent::HiDpiFactorChanged(_) => Some(WindowAction::Resize),
+        winit::WindowEvent::KeyboardInput {
+            input: winit::KeyboardInput {
+                virtual_keycode: Some(key),
+                state: winit::ElementState::Pressed,
+                modifiers,
+                ..
+            },
+            ..
+        } => match key {
+            winit::VirtualKeyCode::Escape => Some(WindowAction::Close),
+            winit::VirtualKeyCode::F10 => Some(WindowAction::ToggleVsync),
+            winit::VirtualKeyCode::F12 => Some(WindowAction::Screenshot),
+            winit::VirtualKeyCode::Return if modifiers.alt => Some(WindowAction::ToggleFullscreen),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Where a chapter's main loop gets its `WindowAction`s from.
+///
+/// With a window that's its event loop. In headless mode there's nothing to poll, so instead
+/// the last of the `--frames` frames asks for a screenshot (which `screenshot::capture` writes
+/// to `--output`) and the frame after that closes.
+pub struct Events {
+    events_loop: Option<winit::EventsLoop>,
+    frames_left: u32,
+}
+
+impl Events {
+    pub fn windowed(events_loop: winit::EventsLoop) -> Self {
+        Events {
+            events_loop: Some(events_loop),
+            frames_left: 0,
+        }
+    }
+
+    /// Stands in for the event loop when there's no window, running for `frames` frames.
+    pub fn headless(frames: u32) -> Self {
+        Events {
+            events_loop: None,
+            frames_left: frames.max(1),
+        }
+    }
+
+    /// The window's event loop, or `None` in headless mode.
+    pub fn events_loop(&mut self) -> Option<&mut winit::EventsLoop> {
+        self.events_loop.as_mut()
+    }
+
+    /// Calls `handle` with the action for each window event that has come in since the last
+    /// call. Chapters call this once at the top of every frame.
+    pub fn poll_actions<F: FnMut(WindowAction)>(&mut self, handle: F) {
+        self.poll_with(|_| false, handle);
+    }
+
+    /// Like `poll_actions`, but also passes every event to `on_event` first, for things like the
+    /// debug overlay and input handling that want the raw events. That includes device events,
+    /// like raw mouse motion. If `on_event` returns true it has used the event up, and it isn't
+    /// turned into an action.
+    pub fn poll_with<E, F>(&mut self, mut on_event: E, mut handle: F)
+    where
+        E: FnMut(&winit::Event) -> bool,
+        F: FnMut(WindowAction),
+    {
+        match self.events_loop {
+            Some(ref mut events_loop) => events_loop.poll_events(|event| {
+                if on_event(&event) {
+                    return;
+                }
+                if let winit::Event::WindowEvent { event, .. } = event {
+                    if let Some(action) = window_action(&event) {
+                        handle(action);
+                    }
+                }
+            }),
+            None => {
+                if self.frames_left == 0 {
+                    handle(WindowAction::Close);
+                } else {
+                    self.frames_left -= 1;
+                    if self.frames_left == 0 {
+                        handle(WindowAction::Screenshot);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,199 @@
+//! Exposure and tonemapping, for showing a scene drawn in HDR on an ordinary screen.
+//!
+//! The lighting goes from a cave lit by nothing but a torch to snow in the midday sun, and
+//! forcing all of that into 0 to 1 as it's drawn either blows out the bright end or crushes the
+//! dark one. So the scene is drawn into a float target instead, where light can go as high as
+//! it likes, and on its way to the swapchain it's scaled by an exposure and squeezed back into
+//! 0 to 1 by a tonemapping curve that rolls off the highlights rather than clipping them.
+//!
+//! With auto exposure the exposure follows how bright the scene is, the way eyes adjust going
+//! into a cave and back out again. `AutoExposure` works out what it should be from the average
+//! luminance of the last few frames, and eases towards it rather than jumping.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// The luminance an average scene is exposed to, which is middle grey.
+const KEY: f32 = 0.18;
+
+/// The range auto exposure stays within. Without a bottom, the sun would take the exposure down
+/// to nothing whenever it's looked at, and without a top, a cave with no light at all would be
+/// turned up until its noise showed.
+pub const MIN_EXPOSURE: f32 = 0.25;
+pub const MAX_EXPOSURE: f32 = 8.0;
+
+/// How quickly the exposure catches up with the scene, as the fraction of the distance left
+/// that it covers each second, more or less. Eyes adjust to the light faster than to the dark.
+const ADAPT_TO_BRIGHTER: f32 = 3.0;
+const ADAPT_TO_DARKER: f32 = 1.0;
+
+/// The curve that takes exposed HDR colours back into 0 to 1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tonemap {
+    /// `x / (1 + x)` on each channel. Gentle, but washes out the brightest colours.
+    Reinhard,
+    /// A fit of the ACES filmic curve, with more contrast in the midtones.
+    Aces,
+}
+
+impl Tonemap {
+    pub const ALL: [Tonemap; 2] = [Tonemap::Reinhard, Tonemap::Aces];
+
+    /// What `tonemap.frag` knows the curve as.
+    pub fn id(self) -> u32 {
+        match self {
+            Tonemap::Reinhard => 0,
+            Tonemap::Aces => 1,
+        }
+    }
+}
+
+impl Default for Tonemap {
+    fn default() -> Self {
+        Tonemap::Aces
+    }
+}
+
+impl fmt::Display for Tonemap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Tonemap::Reinhard => "reinhard",
+            Tonemap::Aces => "aces",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Tonemap {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Tonemap, String> {
+        Tonemap::ALL
+            .iter()
+            .cloned()
+            .find(|tonemap| tonemap.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown tonemap {:?}, expected reinhard or aces", name))
+    }
+}
+
+/// The scale for exposure compensation of `stops`, each of which doubles the light.
+pub fn compensation(stops: f32) -> f32 {
+    2.0f32.powf(stops)
+}
+
+/// The average luminance from the natural logs of luminances across the scene. The log average
+/// keeps a patch of bright sky from outweighing everything else in view. `None` if there's
+/// nothing to average.
+pub fn average_luminance(log_luminances: &[f32]) -> Option<f32> {
+    if log_luminances.is_empty() {
+        return None;
+    }
+    let total: f32 = log_luminances.iter().sum();
+    let average = (total / log_luminances.len() as f32).exp();
+    if average.is_finite() { Some(average) } else { None }
+}
+
+/// The exposure that auto exposure is heading towards, and how far it's got.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AutoExposure {
+    exposure: f32,
+}
+
+impl AutoExposure {
+    /// Starts out with no scaling at all, until there's a scene to measure.
+    pub fn new() -> Self {
+        AutoExposure { exposure: 1.0 }
+    }
+
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// The exposure that takes `average_luminance` to middle grey.
+    pub fn target(average_luminance: f32) -> f32 {
+        (KEY / average_luminance.max(1e-6)).max(MIN_EXPOSURE).min(MAX_EXPOSURE)
+    }
+
+    /// Moves the exposure `seconds` further towards the target for `average_luminance`. The
+    /// easing is done on the log of the exposure, so a stop brighter takes as long as a stop
+    /// darker at the same speed.
+    pub fn adapt(&mut self, average_luminance: f32, seconds: f32) {
+        let target = AutoExposure::target(average_luminance);
+        let speed = if target < self.exposure { ADAPT_TO_BRIGHTER } else { ADAPT_TO_DARKER };
+        let t = 1.0 - (-speed * seconds.max(0.0)).exp();
+        let from = self.exposure.ln();
+        self.exposure = (from + (target.ln() - from) * t).exp();
+    }
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        AutoExposure::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tonemaps_parse_from_their_names() {
+        for &tonemap in &Tonemap::ALL {
+            assert_eq!(tonemap.to_string().parse::<Tonemap>(), Ok(tonemap));
+        }
+        assert_eq!("ACES".parse::<Tonemap>(), Ok(Tonemap::Aces));
+        assert!("filmic".parse::<Tonemap>().is_err());
+    }
+
+    #[test]
+    fn compensation_doubles_with_each_stop() {
+        assert_eq!(compensation(0.0), 1.0);
+        assert!((compensation(1.0) - 2.0).abs() < 1e-6);
+        assert!((compensation(-2.0) - 0.25).abs() < 1e-6);
+    }
+
+    #[test]
+    fn the_average_is_taken_over_the_logs() {
+        assert_eq!(average_luminance(&[]), None);
+        let average = average_luminance(&[0.01f32.ln(), 1.0f32.ln()]).unwrap();
+        assert!((average - 0.1).abs() < 1e-5);
+        assert_eq!(average_luminance(&[::std::f32::INFINITY]), None);
+    }
+
+    #[test]
+    fn the_target_brings_the_scene_to_middle_grey_within_limits() {
+        assert!((AutoExposure::target(0.18) - 1.0).abs() < 1e-6);
+        assert!((AutoExposure::target(0.09) - 2.0).abs() < 1e-6);
+        assert_eq!(AutoExposure::target(0.0), MAX_EXPOSURE);
+        assert_eq!(AutoExposure::target(1000.0), MIN_EXPOSURE);
+    }
+
+    #[test]
+    fn the_exposure_eases_towards_the_target() {
+        let mut exposure = AutoExposure::new();
+        let mut last = exposure.exposure();
+        for _ in 0..600 {
+            exposure.adapt(0.045, 1.0 / 60.0);
+            assert!(exposure.exposure() > last);
+            last = exposure.exposure();
+        }
+        assert!((exposure.exposure() - 4.0).abs() < 0.01);
+
+        // No time, no change
+        exposure.adapt(10.0, 0.0);
+        assert_eq!(exposure.exposure(), last);
+    }
+
+    #[test]
+    fn brightening_is_caught_up_with_faster_than_darkening() {
+        let mut darker = AutoExposure::new();
+        darker.adapt(0.09, 0.5);
+        let mut brighter = AutoExposure::new();
+        brighter.adapt(0.36, 0.5);
+        // Both are a stop away, so compare how far through the stop each got
+        let into_darker = darker.exposure().log2();
+        let into_brighter = -brighter.exposure().log2();
+        assert!(into_brighter > into_darker);
+    }
+}
@@ -0,0 +1,210 @@
+//! Keeping several frames in flight at once.
+//!
+//! If we waited for the gpu to finish each frame before starting on the next one, the cpu and
+//! gpu would take turns sitting idle. Instead each frame in flight gets its own command pool and
+//! synchronization primitives, and we only wait when we come back around to a frame whose
+//! previous submission might still be running.
+
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use hal::{
+    pool, queue,
+    window::AcquireError,
+    Backend, Device, FrameSync as AcquireSync, General, QueueGroup, Swapchain, SwapImageIndex,
+};
+
+use error::RendererError;
+
+/// Two frames lets the cpu record one frame while the gpu draws the previous one, without
+/// adding much latency.
+pub const DEFAULT_FRAMES_IN_FLIGHT: usize = 2;
+
+struct FrameSlot<B: Backend> {
+    command_pool: Option<pool::CommandPool<B, General>>,
+    /// Signalled when the acquired swapchain image is ready to be drawn into.
+    image_available: Option<B::Semaphore>,
+    /// Signalled when the frame's commands are done, so presenting can wait on it.
+    render_finished: Option<B::Semaphore>,
+    /// Signalled when the frame's submission is done, so its command pool can be reused.
+    fence: Option<B::Fence>,
+}
+
+/// The per-frame command pools, semaphores and fences for `frames_in_flight` frames.
+pub struct FrameSync<B: Backend> {
+    device: Rc<B::Device>,
+    frames: Vec<FrameSlot<B>>,
+    current: usize,
+}
+
+/// Everything needed to record, submit and present one frame. Borrowed from `FrameSync` by
+/// `begin_frame`.
+pub struct Frame<'a, B: Backend> {
+    /// Which of the frames in flight this is, for indexing other per-frame resources.
+    pub index: usize,
+    pub command_pool: &'a mut pool::CommandPool<B, General>,
+    image_available: &'a mut B::Semaphore,
+    render_finished: &'a mut B::Semaphore,
+    fence: &'a B::Fence,
+}
+
+impl<B: Backend> FrameSync<B> {
+    pub fn new(
+        device: Rc<B::Device>,
+        queue_group: &QueueGroup<B, General>,
+        frames_in_flight: usize,
+    ) -> Self {
+        assert!(frames_in_flight > 0, "Need at least one frame in flight");
+
+        let frames = (0..frames_in_flight)
+            .map(|_| FrameSlot {
+                command_pool: Some(device.create_command_pool_typed(
+                    queue_group,
+                    pool::CommandPoolCreateFlags::empty(),
+                    16,
+                )),
+                image_available: Some(device.create_semaphore()),
+                render_finished: Some(device.create_semaphore()),
+                // Created signalled so the first wait on each frame returns straight away
+                fence: Some(device.create_fence(true)),
+            })
+            .collect();
+
+        FrameSync {
+            device,
+            frames,
+            current: 0,
+        }
+    }
+
+    pub fn frames_in_flight(&self) -> usize {
+        self.frames.len()
+    }
+
+    /// Moves on to the next frame, waiting for the gpu to finish with its last submission and
+    /// resetting its command pool.
+    ///
+    /// The fence is left signalled: reset it with `Frame::fence` right before submitting, so
+    /// that skipping a frame (say, because the swapchain was out of date) can't leave us waiting
+    /// on a fence that will never be signalled.
+    ///
+    /// Fails with `RendererError::DeviceLost` if the wait does, since without a timeout that's
+    /// the only way it can.
+    pub fn begin_frame(&mut self) -> Result<Frame<B>, RendererError> {
+        let index = self.current;
+        self.current = (self.current + 1) % self.frames.len();
+
+        let slot = &mut self.frames[index];
+        let fence = slot.fence.as_ref().unwrap();
+        if !self.device.wait_for_fence(fence, !0) {
+            return Err(RendererError::DeviceLost);
+        }
+
+        let command_pool = slot.command_pool.as_mut().unwrap();
+        command_pool.reset();
+
+        Ok(Frame {
+            index,
+            command_pool,
+            image_available: slot.image_available.as_mut().unwrap(),
+            render_finished: slot.render_finished.as_mut().unwrap(),
+            fence,
+        })
+    }
+}
+
+/// Resources, like the buffers of a chunk that has just been remeshed, that a frame still in
+/// flight might be drawing with. They're held onto until `frames_in_flight` more frames have
+/// begun, by which point `FrameSync::begin_frame` has waited on every frame that could have used
+/// them, and then dropped.
+pub struct RetiredResources<T> {
+    frames_in_flight: usize,
+    frame: u64,
+    retired: VecDeque<(u64, T)>,
+}
+
+impl<T> RetiredResources<T> {
+    pub fn new(frames_in_flight: usize) -> Self {
+        RetiredResources {
+            frames_in_flight,
+            frame: 0,
+            retired: VecDeque::new(),
+        }
+    }
+
+    /// Holds onto `resource` until it's safe to drop.
+    pub fn retire(&mut self, resource: T) {
+        self.retired.push_back((self.frame, resource));
+    }
+
+    /// Call right after `FrameSync::begin_frame`. Drops everything retired at least
+    /// `frames_in_flight` frames ago.
+    pub fn begin_frame(&mut self) {
+        self.frame += 1;
+        while self
+            .retired
+            .front()
+            .map_or(false, |&(frame, _)| frame + self.frames_in_flight as u64 <= self.frame)
+        {
+            self.retired.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.retired.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.retired.is_empty()
+    }
+}
+
+impl<'a, B: Backend> Frame<'a, B> {
+    /// Gets the index of the next swapchain image. `image_available` is signalled once it's
+    /// safe to draw into.
+    pub fn acquire_image(&mut self, swapchain: &mut B::Swapchain) -> Result<SwapImageIndex, AcquireError> {
+        swapchain.acquire_image(!0, AcquireSync::Semaphore(self.image_available))
+    }
+
+    /// Presents `image_index` once the frame's commands (which must signal `render_finished`)
+    /// are done.
+    pub fn present<C>(
+        &self,
+        swapchain: &mut B::Swapchain,
+        queue: &mut queue::CommandQueue<B, C>,
+        image_index: SwapImageIndex,
+    ) -> Result<(), ()> {
+        swapchain.present(queue, image_index, Some(&*self.render_finished))
+    }
+
+    pub fn image_available(&self) -> &B::Semaphore {
+        self.image_available
+    }
+
+    pub fn render_finished(&self) -> &B::Semaphore {
+        self.render_finished
+    }
+
+    pub fn fence(&self) -> &B::Fence {
+        self.fence
+    }
+}
+
+impl<B: Backend> Drop for FrameSync<B> {
+    fn drop(&mut self) {
+        for slot in &mut self.frames {
+            if let Some(fence) = slot.fence.take() {
+                self.device.destroy_fence(fence);
+            }
+            if let Some(semaphore) = slot.image_available.take() {
+                self.device.destroy_semaphore(semaphore);
+            }
+            if let Some(semaphore) = slot.render_finished.take() {
+                self.device.destroy_semaphore(semaphore);
+            }
+            if let Some(command_pool) = slot.command_pool.take() {
+                self.device.destroy_command_pool(command_pool.into_raw());
+            }
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! A rolling history of how long recent frames took, for the overlay's frame time graph.
+
+use std::collections::VecDeque;
+
+/// How many frames of history are kept: two seconds at 60 fps.
+pub const FRAME_HISTORY: usize = 120;
+
+#[derive(Clone, Debug, Default)]
+pub struct FrameTimes {
+    /// Frame times in milliseconds, oldest first.
+    milliseconds: VecDeque<f32>,
+}
+
+impl FrameTimes {
+    pub fn new() -> Self {
+        FrameTimes { milliseconds: VecDeque::with_capacity(FRAME_HISTORY) }
+    }
+
+    /// Records a frame that took `seconds`, dropping the oldest one once the history is full.
+    pub fn push(&mut self, seconds: f32) {
+        if self.milliseconds.len() == FRAME_HISTORY {
+            self.milliseconds.pop_front();
+        }
+        self.milliseconds.push_back(seconds * 1000.0);
+    }
+
+    /// The frame times in milliseconds, oldest first.
+    pub fn history(&self) -> Vec<f32> {
+        self.milliseconds.iter().cloned().collect()
+    }
+
+    /// The mean frame time over the history, in milliseconds.
+    pub fn average_milliseconds(&self) -> f32 {
+        if self.milliseconds.is_empty() {
+            return 0.0;
+        }
+        self.milliseconds.iter().sum::<f32>() / self.milliseconds.len() as f32
+    }
+
+    /// The slowest frame in the history, in milliseconds.
+    pub fn max_milliseconds(&self) -> f32 {
+        self.milliseconds.iter().cloned().fold(0.0, f32::max)
+    }
+
+    /// Frames per second, going by the average frame time. Averaging keeps the number readable
+    /// instead of flickering with every frame.
+    pub fn frames_per_second(&self) -> f32 {
+        let average = self.average_milliseconds();
+        if average > 0.0 { 1000.0 / average } else { 0.0 }
+    }
+}
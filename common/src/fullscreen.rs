@@ -0,0 +1,105 @@
+//! Switching the window between windowed and fullscreen.
+
+use std::fmt;
+use std::str::FromStr;
+
+use winit;
+
+/// How the window covers the screen when it goes fullscreen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FullscreenMode {
+    /// A window without decorations moved and resized to cover the whole monitor. Switching is
+    /// instant and other windows can still be shown on top of it.
+    Borderless,
+    /// The window system's own fullscreen state for the monitor, which lets the compositor get
+    /// out of the way on platforms that support that. winit can't change the display mode, so
+    /// this always runs at the monitor's current resolution.
+    Exclusive,
+}
+
+impl FromStr for FullscreenMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.trim().to_lowercase().as_str() {
+            "borderless" => Ok(FullscreenMode::Borderless),
+            "exclusive" => Ok(FullscreenMode::Exclusive),
+            _ => Err(format!("Unknown fullscreen mode '{}'. Expected borderless or exclusive", s)),
+        }
+    }
+}
+
+impl fmt::Display for FullscreenMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            FullscreenMode::Borderless => "borderless",
+            FullscreenMode::Exclusive => "exclusive",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Where the window was before going fullscreen, so we can put it back.
+struct WindowedPlacement {
+    position: Option<winit::dpi::LogicalPosition>,
+    size: Option<winit::dpi::LogicalSize>,
+}
+
+/// Tracks whether the window is fullscreen and toggles it.
+pub struct Fullscreen {
+    mode: FullscreenMode,
+    windowed: Option<WindowedPlacement>,
+}
+
+impl Fullscreen {
+    pub fn new(mode: FullscreenMode) -> Self {
+        Fullscreen {
+            mode,
+            windowed: None,
+        }
+    }
+
+    pub fn mode(&self) -> FullscreenMode {
+        self.mode
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.windowed.is_some()
+    }
+
+    /// Makes `window` cover the monitor it's currently on, or puts it back where it was. The
+    /// window's size changes either way, so the swapchain needs to be recreated afterwards.
+    pub fn toggle(&mut self, window: &winit::Window) {
+        match self.windowed.take() {
+            Some(placement) => {
+                match self.mode {
+                    FullscreenMode::Borderless => window.set_decorations(true),
+                    FullscreenMode::Exclusive => window.set_fullscreen(None),
+                }
+                if let Some(size) = placement.size {
+                    window.set_inner_size(size);
+                }
+                if let Some(position) = placement.position {
+                    window.set_position(position);
+                }
+            }
+            None => {
+                self.windowed = Some(WindowedPlacement {
+                    position: window.get_position(),
+                    size: window.get_inner_size(),
+                });
+
+                let monitor = window.get_current_monitor();
+                match self.mode {
+                    FullscreenMode::Borderless => {
+                        let scale = monitor.get_hidpi_factor();
+                        window.set_decorations(false);
+                        window.set_position(monitor.get_position().to_logical(scale));
+                        window.set_inner_size(monitor.get_dimensions().to_logical(scale));
+                    }
+                    FullscreenMode::Exclusive => window.set_fullscreen(Some(monitor)),
+                }
+            }
+        }
+    }
+}
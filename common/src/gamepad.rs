@@ -0,0 +1,124 @@
+//! Gamepad input, through gilrs.
+//!
+//! Buttons go through the same bindings as keys, so `PadSouth` can jump just like `Space` does.
+//! The sticks are handled here instead: the left one moves and the right one looks, once they're
+//! past the deadzone and shaped by the response curve from the settings.
+//!
+//! When several gamepads are connected, the sticks are read from whichever one was used last.
+//! Buttons on any of them count.
+
+use gilrs::{ Axis, EventType, Gilrs };
+
+use config::Settings;
+use input::Input;
+
+pub struct Gamepads {
+    /// `None` when gamepads are turned off, or gilrs couldn't start on this platform.
+    gilrs: Option<Gilrs>,
+    /// The gamepad the sticks are read from.
+    active: Option<usize>,
+}
+
+impl Gamepads {
+    /// Starts listening for gamepads if `enabled`. Headless runs should pass false, so a pad
+    /// left plugged into the machine can't change what they draw.
+    pub fn new(enabled: bool) -> Self {
+        let gilrs = if enabled {
+            match Gilrs::new() {
+                Ok(gilrs) => Some(gilrs),
+                Err(err) => {
+                    warn!("Gamepads won't work: {}", err);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        if let Some(ref gilrs) = gilrs {
+            for (_, gamepad) in gilrs.gamepads() {
+                info!("Found gamepad {}", gamepad.name());
+            }
+        }
+
+        Gamepads { gilrs, active: None }
+    }
+
+    /// Passes this frame's button presses on to `input`, and sets its stick state. Call this
+    /// once a frame, after `Input::begin_frame` and `frame_seconds` since the last call.
+    pub fn poll(&mut self, input: &mut Input, settings: &Settings, frame_seconds: f32) {
+        let gilrs = match self.gilrs {
+            Some(ref mut gilrs) => gilrs,
+            None => return,
+        };
+
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => {
+                    input.set_gamepad_button(button, true);
+                    self.active = Some(event.id);
+                }
+                EventType::ButtonReleased(button, _) => {
+                    input.set_gamepad_button(button, false);
+                }
+                EventType::AxisChanged(..) => {
+                    self.active = Some(event.id);
+                }
+                EventType::Connected => {
+                    info!("Gamepad {} connected", gilrs.gamepad(event.id).name());
+                }
+                EventType::Disconnected => {
+                    info!("Gamepad {} disconnected", gilrs.gamepad(event.id).name());
+                    input.release_gamepad_buttons();
+                    if self.active == Some(event.id) {
+                        self.active = None;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let gamepad = match self.active.and_then(|id| gilrs.connected_gamepad(id)) {
+            Some(gamepad) => gamepad,
+            None => {
+                input.set_move_stick(0.0, 0.0);
+                return;
+            }
+        };
+
+        let deadzone = settings.gamepad_deadzone;
+        let curve = settings.gamepad_response_curve;
+        let (move_x, move_y) = shape_stick(
+            gamepad.value(Axis::LeftStickX),
+            gamepad.value(Axis::LeftStickY),
+            deadzone,
+            curve,
+        );
+        input.set_move_stick(move_x, move_y);
+
+        let (look_x, look_y) = shape_stick(
+            gamepad.value(Axis::RightStickX),
+            gamepad.value(Axis::RightStickY),
+            deadzone,
+            curve,
+        );
+        let turn = settings.gamepad_look_speed.to_radians() * frame_seconds;
+        input.add_stick_look(look_x * turn, look_y * turn);
+    }
+}
+
+/// Applies a deadzone and response curve to a stick at `x`, `y`.
+///
+/// The deadzone is radial, so a stick pushed diagonally doesn't snap to an axis the way it
+/// would if each axis had its own. Past the deadzone the distance is rescaled to start again
+/// from 0, so there's no jump in speed at its edge, and then raised to the power of `curve`.
+/// Cheap sticks can reach a little past 1 on the diagonals, which is clamped.
+fn shape_stick(x: f32, y: f32, deadzone: f32, curve: f32) -> (f32, f32) {
+    let length = (x * x + y * y).sqrt();
+    let deadzone = deadzone.max(0.0).min(0.99);
+    if length <= deadzone {
+        return (0.0, 0.0);
+    }
+    let scaled = ((length.min(1.0) - deadzone) / (1.0 - deadzone)).powf(curve.max(0.1));
+    (x / length * scaled, y / length * scaled)
+}
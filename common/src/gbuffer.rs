@@ -0,0 +1,196 @@
+//! The G-buffer that deferred shading draws the world into.
+//!
+//! Forward shading lights every fragment as it's drawn, including the ones that end up hidden
+//! behind something drawn later, and every one of them loops over all the lights. Deferred
+//! shading splits that in two. The world is drawn first without any lighting, into a G-buffer
+//! holding what the lighting needs to know about the nearest surface at each pixel: its colour,
+//! which way it faces, how much light reached it and how far away it is. Then a single
+//! full-screen pass lights each pixel once from those. However much overdraw there is, and
+//! however many lights, each pixel pays for them just the once.
+//!
+//! The cost is memory: three color targets and a depth buffer for every swapchain image, read
+//! back in full every frame. And since the lighting pass sees one surface per pixel, it can't
+//! blend or multisample, so `--msaa` only applies to forward shading.
+
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use hal::{
+    format as f, image as i,
+    Backend, Device, SwapImageIndex,
+};
+
+use attachments::AttachmentImages;
+use context::GfxContext;
+use depth::choose_gbuffer_depth_format;
+use error::Result;
+use pass::create_gbuffer_render_pass;
+use resources::SwapchainBundle;
+use samplers::SamplerDesc;
+
+/// The surface colour, straight from the atlas. sRGB, so the darks keep their precision.
+pub const ALBEDO_FORMAT: f::Format = f::Format::Rgba8Srgb;
+/// The normal, mapped from -1..1 to 0..1. Block faces only ever point along an axis, so eight
+/// bits is plenty.
+pub const NORMAL_FORMAT: f::Format = f::Format::Rgba8Unorm;
+/// The sky light, block light and ambient occlusion, from 0 to 1, in that order.
+pub const MATERIAL_FORMAT: f::Format = f::Format::Rgba8Unorm;
+
+/// How the world is lit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shading {
+    /// Each fragment is lit as it's drawn.
+    Forward,
+    /// The world is drawn into a `GBuffer`, and lit in a pass of its own.
+    Deferred,
+}
+
+impl Shading {
+    pub const ALL: [Shading; 2] = [Shading::Forward, Shading::Deferred];
+
+    /// The other way of shading, for switching between them.
+    pub fn next(self) -> Shading {
+        match self {
+            Shading::Forward => Shading::Deferred,
+            Shading::Deferred => Shading::Forward,
+        }
+    }
+}
+
+impl Default for Shading {
+    fn default() -> Self {
+        Shading::Deferred
+    }
+}
+
+impl fmt::Display for Shading {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Shading::Forward => "forward",
+            Shading::Deferred => "deferred",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Shading {
+    type Err = String;
+
+    fn from_str(name: &str) -> ::std::result::Result<Shading, String> {
+        Shading::ALL
+            .iter()
+            .cloned()
+            .find(|shading| shading.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown shading {:?}, expected forward or deferred", name))
+    }
+}
+
+/// A G-buffer for each swapchain image, along with the render pass and framebuffers that draw
+/// into them and the sampler the lighting pass reads them with. Like the other per-frame
+/// attachments, it has to be recreated along with the swapchain.
+pub struct GBuffer<B: Backend> {
+    device: Rc<B::Device>,
+    albedo: AttachmentImages<B>,
+    normal: AttachmentImages<B>,
+    material: AttachmentImages<B>,
+    depth: AttachmentImages<B>,
+    render_pass: Option<B::RenderPass>,
+    sampler: Rc<B::Sampler>,
+    framebuffers: Vec<B::Framebuffer>,
+}
+
+impl<B: Backend> GBuffer<B> {
+    pub fn new(context: &GfxContext<B>, swapchain: &SwapchainBundle<B>) -> Result<Self> {
+        let device = context.device.clone();
+        let allocator = context.allocator.clone();
+        let depth_format = choose_gbuffer_depth_format(&context.adapter)?;
+        debug!("G-buffer depth format: {:?}", depth_format);
+
+        let render_pass = create_gbuffer_render_pass::<B>(&device, depth_format);
+        // Every pixel is read back from exactly where it was written
+        let sampler = context.samplers.borrow_mut().get(SamplerDesc::new(i::Filter::Nearest, i::WrapMode::Clamp));
+
+        let mut gbuffer = GBuffer {
+            albedo: AttachmentImages::sampled_color(device.clone(), allocator.clone(), ALBEDO_FORMAT, swapchain)?,
+            normal: AttachmentImages::sampled_color(device.clone(), allocator.clone(), NORMAL_FORMAT, swapchain)?,
+            material: AttachmentImages::sampled_color(device.clone(), allocator.clone(), MATERIAL_FORMAT, swapchain)?,
+            depth: AttachmentImages::sampled_depth(device.clone(), allocator, depth_format, swapchain)?,
+            device,
+            render_pass: Some(render_pass),
+            sampler,
+            framebuffers: Vec::new(),
+        };
+        gbuffer.create_framebuffers(swapchain)?;
+        Ok(gbuffer)
+    }
+
+    /// Rebuilds the images and framebuffers to match `swapchain`'s size and image count.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.albedo.recreate(swapchain)?;
+        self.normal.recreate(swapchain)?;
+        self.material.recreate(swapchain)?;
+        self.depth.recreate(swapchain)?;
+        self.create_framebuffers(swapchain)
+    }
+
+    fn create_framebuffers(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.destroy_framebuffers();
+        let extent = swapchain.extent().to_extent();
+        for index in 0..swapchain.frame_images().len() {
+            let attachments = vec![
+                self.albedo.view(index),
+                self.normal.view(index),
+                self.material.view(index),
+                self.depth.view(index),
+            ];
+            let framebuffer = self.device.create_framebuffer(self.render_pass(), attachments, extent)?;
+            self.framebuffers.push(framebuffer);
+        }
+        Ok(())
+    }
+
+    pub fn render_pass(&self) -> &B::RenderPass {
+        self.render_pass.as_ref().unwrap()
+    }
+
+    pub fn framebuffer(&self, image_index: SwapImageIndex) -> &B::Framebuffer {
+        &self.framebuffers[image_index as usize]
+    }
+
+    /// The depth buffers, for attaching to the lighting pass's framebuffers.
+    pub fn depth(&self) -> &AttachmentImages<B> {
+        &self.depth
+    }
+
+    /// The views of the albedo, normal, material and depth for swapchain image `index`, in the
+    /// order the lighting pass binds them.
+    pub fn views(&self, index: usize) -> [&B::ImageView; 4] {
+        [
+            self.albedo.view(index),
+            self.normal.view(index),
+            self.material.view(index),
+            self.depth.view(index),
+        ]
+    }
+
+    pub fn sampler(&self) -> &B::Sampler {
+        &self.sampler
+    }
+
+    fn destroy_framebuffers(&mut self) {
+        for framebuffer in self.framebuffers.drain(..) {
+            self.device.destroy_framebuffer(framebuffer);
+        }
+    }
+}
+
+impl<B: Backend> Drop for GBuffer<B> {
+    fn drop(&mut self) {
+        self.destroy_framebuffers();
+        if let Some(render_pass) = self.render_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
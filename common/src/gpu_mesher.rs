@@ -0,0 +1,484 @@
+//! Meshing chunks on the gpu, in a compute shader, instead of on the cpu.
+//!
+//! The cpu meshers look at every block of a chunk and every block around it, on worker threads,
+//! and the mesh they make then has to be copied to the gpu. `GpuMesher` copies the blocks
+//! instead, once, and leaves everything else to `mesh.comp`, so neither the meshers nor any
+//! uploads are involved in turning them into triangles.
+//!
+//! The chunks meshed are a box of them, a `MeshRegion`, and every block in it goes into one
+//! storage buffer, a chunk after another, as a word holding the block and its light. `mesh.comp`
+//! runs once for every block, and for each face the naive mesher would make it appends a quad
+//! to another storage buffer: which block and which way it faces, its tile, and the ambient
+//! occlusion and light at its corners, worked out the same way as `ChunkNeighborhood` does
+//! them. Each quad takes the next slot from an atomic counter, which is the instance count of an
+//! indirect draw of six vertices, so a vertex shader like `quads.vert` builds each quad's
+//! corners out of it. The only thing the cpu ever reads back is that count, a frame or two late.
+//!
+//! Only the opaque faces are meshed, the ones in `ChunkMesh::indices`. Translucent blocks and
+//! water need blending over everything else in passes of their own, and faces aren't merged
+//! like `Mesher::Greedy` merges them, since that needs one pass over a whole slice rather than
+//! a block at a time.
+//!
+//! There's only room for so many quads. Past that the rest of the faces are dropped, and the
+//! count read back says how many there would have been.
+
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+
+use hal::{
+    buffer, command, memory, pso,
+    pso::PipelineStage,
+    queue::Supports,
+    Backend, Compute, Device,
+};
+
+use allocator::MemoryCategory;
+use buffer::{ upload_buffer, DeviceBuffer };
+use context::GfxContext;
+use descriptors::{ DescriptorAllocator, DescriptorSetLayout };
+use error::Result;
+use mesher::{ BlockTextures, Surface };
+use world::{ BlockId, Chunk, ChunkCoord, Direction, World, CHUNK_SIZE, CHUNK_VOLUME };
+
+/// How many blocks along each axis `mesh.comp` meshes in each workgroup. Has to match its
+/// `local_size`, and divide `CHUNK_SIZE`.
+const MESH_GROUP_SIZE: u32 = 4;
+
+/// How many block ids `mesh.comp` is told the tiles and surfaces of. Any others are opaque and
+/// show tile 0, like blocks `BlockTextures` hasn't been told about.
+const MESH_BLOCK_KINDS: u16 = 256;
+
+/// How big one quad is in the quads buffer: four words, as `mesh.comp` packs them.
+const QUAD_SIZE: u64 = 16;
+
+/// How many blocks across a region can be along each axis, since `mesh.comp` packs a quad's
+/// position into 10 bits an axis.
+const MAX_REGION_BLOCKS: u32 = 1024;
+
+/// The box of chunks a `GpuMesher` meshes, `size` chunks along each axis from `origin`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshRegion {
+    pub origin: ChunkCoord,
+    pub size: [u32; 3],
+}
+
+impl MeshRegion {
+    pub fn new(origin: ChunkCoord, size: [u32; 3]) -> Self {
+        MeshRegion { origin, size }
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        (self.size[0] * self.size[1] * self.size[2]) as usize
+    }
+
+    /// How many blocks across the region is along each axis.
+    pub fn blocks(&self) -> [u32; 3] {
+        let size = CHUNK_SIZE as u32;
+        [self.size[0] * size, self.size[1] * size, self.size[2] * size]
+    }
+
+    /// Where the chunk at `coord` goes in the voxel buffer, counting in chunks, or `None` if it's
+    /// outside the region. Has to match `voxel` in `mesh.comp`.
+    pub fn slot(&self, coord: ChunkCoord) -> Option<usize> {
+        let offset = [coord.x - self.origin.x, coord.y - self.origin.y, coord.z - self.origin.z];
+        if (0..3).any(|axis| offset[axis] < 0 || offset[axis] >= self.size[axis] as i32) {
+            return None;
+        }
+        let (x, y, z) = (offset[0] as usize, offset[1] as usize, offset[2] as usize);
+        Some(x + self.size[0] as usize * (y + self.size[1] as usize * z))
+    }
+
+    /// Every chunk in the region, in the order of their slots.
+    pub fn coords(&self) -> Vec<ChunkCoord> {
+        let mut coords = Vec::with_capacity(self.chunk_count());
+        for z in 0..self.size[2] as i32 {
+            for y in 0..self.size[1] as i32 {
+                for x in 0..self.size[0] as i32 {
+                    coords.push(self.origin.offset([x, y, z]));
+                }
+            }
+        }
+        coords
+    }
+}
+
+/// The block at `local` in `chunk`, with its light, as `mesh.comp` reads them out of the voxel
+/// buffer: the block in the low 16 bits, then 4 bits each of sky and block light. Chunks that
+/// aren't loaded are air, lit like open sky, which is what `ChunkNeighborhood` takes them to be.
+fn voxel_word(chunk: Option<&Chunk>, local: [usize; 3]) -> u32 {
+    match chunk {
+        Some(chunk) => {
+            let light = chunk.light(local);
+            chunk.get(local).0 as u32 | (light.sky() as u32) << 16 | (light.block() as u32) << 20
+        }
+        None => 15 << 16,
+    }
+}
+
+/// Every block in `region`, a chunk after another in the order of their slots, and the blocks
+/// of each chunk along x, then y, then z. Has to match `voxel` in `mesh.comp`.
+fn voxel_words(world: &World, region: &MeshRegion) -> Vec<u32> {
+    let mut words = Vec::with_capacity(region.chunk_count() * CHUNK_VOLUME);
+    for coord in region.coords() {
+        let chunk = world.chunk(coord);
+        for z in 0..CHUNK_SIZE {
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    words.push(voxel_word(chunk, [x, y, z]));
+                }
+            }
+        }
+    }
+    words
+}
+
+/// The tile on each face of each of the first `MESH_BLOCK_KINDS` blocks, in the order of
+/// `Direction::ALL`, with what kind of surface the block is in the bits above it: 0 for opaque,
+/// 1 for translucent and 2 for water. Has to match `face_entry` in `mesh.comp`.
+fn face_words(textures: &BlockTextures) -> Vec<u32> {
+    (0..MESH_BLOCK_KINDS)
+        .flat_map(|block| {
+            let block = BlockId(block);
+            let surface = match textures.surface(block) {
+                Surface::Opaque => 0,
+                Surface::Translucent => 1,
+                Surface::Water => 2,
+            };
+            Direction::ALL
+                .iter()
+                .map(move |&direction| textures.get(block, direction).0 as u32 | surface << 16)
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// The arguments of the draw, laid out the way `draw_indirect` reads them, followed by how many
+/// quads there would have been with room for them all. Has to match `Draw` in `mesh.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct QuadDraw {
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+    quad_count: u32,
+}
+
+/// Has to match the `PushConstants` block in `mesh.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct MeshConstants {
+    region_chunks: [u32; 3],
+    max_quads: u32,
+}
+
+impl MeshConstants {
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const MeshConstants as *const u32,
+                mem::size_of::<MeshConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+struct FrameMesh<B: Backend> {
+    draw: DeviceBuffer<B>,
+    set: B::DescriptorSet,
+    /// Whether the frame has been meshed, so `draw` has a count in it.
+    meshed: bool,
+}
+
+/// The blocks of a region of chunks on the gpu, and the quads `mesh.comp` makes of them.
+pub struct GpuMesher<B: Backend> {
+    device: Rc<B::Device>,
+    region: MeshRegion,
+    max_quads: u32,
+    _voxels: DeviceBuffer<B>,
+    _faces: DeviceBuffer<B>,
+    quads: DeviceBuffer<B>,
+    frames: Vec<FrameMesh<B>>,
+    /// Only kept to free the frames' sets with.
+    _descriptors: DescriptorAllocator<B>,
+    set_layout: Rc<DescriptorSetLayout<B>>,
+    pipeline_layout: Option<B::PipelineLayout>,
+}
+
+impl<B: Backend> GpuMesher<B> {
+    /// Copies every block in `region` of `world` to the gpu, with the tiles and surfaces of
+    /// `textures`, leaving room for `max_quads` quads. This waits for the copies.
+    pub fn new(
+        context: &mut GfxContext<B>,
+        world: &World,
+        region: MeshRegion,
+        textures: &BlockTextures,
+        max_quads: u32,
+        frames_in_flight: usize,
+    ) -> Result<Self> {
+        assert!(
+            region.blocks().iter().all(|&blocks| blocks <= MAX_REGION_BLOCKS),
+            "A region of {:?} chunks is too big to mesh on the gpu",
+            region.size,
+        );
+        let voxels = upload_buffer(
+            context,
+            &voxel_words(world, &region),
+            buffer::Usage::STORAGE,
+            MemoryCategory::Storage,
+        )?;
+        let faces = upload_buffer(
+            context,
+            &face_words(textures),
+            buffer::Usage::STORAGE,
+            MemoryCategory::Storage,
+        )?;
+        let quads = DeviceBuffer::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            max_quads as u64 * QUAD_SIZE,
+            buffer::Usage::STORAGE,
+            memory::Properties::DEVICE_LOCAL,
+            MemoryCategory::Meshes,
+        )?;
+
+        // The blocks, the tiles, the quads and the draw. The vertex shader reads the quads
+        // through the same set the compute shader writes them through.
+        let set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            (0..4)
+                .map(|binding| pso::DescriptorSetLayoutBinding {
+                    binding,
+                    ty: pso::DescriptorType::StorageBuffer,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::COMPUTE | pso::ShaderStageFlags::VERTEX,
+                    immutable_samplers: false,
+                })
+                .collect(),
+        ));
+        let constants_size = (mem::size_of::<MeshConstants>() / mem::size_of::<u32>()) as u32;
+        let pipeline_layout = context.device.create_pipeline_layout(
+            vec![set_layout.raw()],
+            &[(pso::ShaderStageFlags::COMPUTE, 0..constants_size)],
+        );
+        let mut descriptors = DescriptorAllocator::new(context.device.clone(), set_layout.clone());
+
+        let mut frames = Vec::with_capacity(frames_in_flight);
+        for _ in 0..frames_in_flight {
+            let draw = DeviceBuffer::new(
+                context.device.clone(),
+                context.allocator.clone(),
+                mem::size_of::<QuadDraw>() as u64,
+                buffer::Usage::STORAGE | buffer::Usage::INDIRECT,
+                memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT,
+                MemoryCategory::Storage,
+            )?;
+            let set = descriptors.allocate()?;
+            context.device.write_descriptor_sets(
+                [voxels.buffer(), faces.buffer(), quads.buffer(), draw.buffer()]
+                    .iter()
+                    .enumerate()
+                    .map(|(binding, &buffer)| pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: binding as u32,
+                        array_offset: 0,
+                        descriptors: Some(pso::Descriptor::Buffer(buffer, None..None)),
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            frames.push(FrameMesh { draw, set, meshed: false });
+        }
+
+        Ok(GpuMesher {
+            device: context.device.clone(),
+            region,
+            max_quads,
+            _voxels: voxels,
+            _faces: faces,
+            quads,
+            frames,
+            _descriptors: descriptors,
+            set_layout,
+            pipeline_layout: Some(pipeline_layout),
+        })
+    }
+
+    pub fn region(&self) -> MeshRegion {
+        self.region
+    }
+
+    /// How many quads there's room for.
+    pub fn max_quads(&self) -> u32 {
+        self.max_quads
+    }
+
+    /// The layout `mesh.comp`'s pipeline has to be built with.
+    pub fn pipeline_layout(&self) -> &B::PipelineLayout {
+        self.pipeline_layout.as_ref().unwrap()
+    }
+
+    /// The layout of `set`, for the pipeline that draws the quads to be built with.
+    pub fn set_layout(&self) -> &Rc<DescriptorSetLayout<B>> {
+        &self.set_layout
+    }
+
+    /// Frame `frame_index`'s set, with the quads at binding 2, for drawing them.
+    pub fn set(&self, frame_index: usize) -> &B::DescriptorSet {
+        &self.frames[frame_index].set
+    }
+
+    /// How many quads the last meshing of frame `frame_index` made, or would have with room for
+    /// them all, or `None` if it's never been meshed. Call this after `FrameSync::begin_frame`
+    /// has waited for the frame's fence.
+    pub fn read_quads(&self, frame_index: usize) -> Result<Option<u32>> {
+        let frame = &self.frames[frame_index];
+        if !frame.meshed {
+            return Ok(None);
+        }
+        Ok(Some(frame.draw.read::<QuadDraw>()?[0].quad_count))
+    }
+
+    /// Meshes the whole region with `pipeline`, which has to have been built from `mesh.comp`
+    /// with `pipeline_layout`, writing the quads and frame `frame_index`'s draw of them. Record
+    /// this before the render pass that draws them, and after the frame's fence has been waited
+    /// on.
+    pub fn mesh<C: Supports<Compute>>(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, C, command::OneShot>,
+        pipeline: &B::ComputePipeline,
+        frame_index: usize,
+    ) -> Result<()> {
+        // The shader counts the quads into the instance count
+        let draw = QuadDraw { vertex_count: 6, ..QuadDraw::default() };
+        self.frames[frame_index].draw.write(&[draw])?;
+        self.frames[frame_index].meshed = true;
+
+        // There's only the one quads buffer, so the last frame has to be done drawing from it
+        // before it's written again
+        command_buffer.pipeline_barrier(
+            PipelineStage::VERTEX_SHADER..PipelineStage::COMPUTE_SHADER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Buffer {
+                states: buffer::Access::SHADER_READ..buffer::Access::SHADER_WRITE,
+                target: self.quads.buffer(),
+            }],
+        );
+
+        let frame = &self.frames[frame_index];
+        let pipeline_layout = self.pipeline_layout.as_ref().unwrap();
+        command_buffer.bind_compute_pipeline(pipeline);
+        command_buffer.bind_compute_descriptor_sets(pipeline_layout, 0, Some(&frame.set), &[]);
+        let constants = MeshConstants { region_chunks: self.region.size, max_quads: self.max_quads };
+        command_buffer.push_compute_constants(pipeline_layout, 0, constants.as_words());
+        let blocks = self.region.blocks();
+        command_buffer.dispatch([
+            blocks[0] / MESH_GROUP_SIZE,
+            blocks[1] / MESH_GROUP_SIZE,
+            blocks[2] / MESH_GROUP_SIZE,
+        ]);
+
+        command_buffer.pipeline_barrier(
+            PipelineStage::COMPUTE_SHADER
+                ..(PipelineStage::VERTEX_SHADER | PipelineStage::DRAW_INDIRECT | PipelineStage::HOST),
+            memory::Dependencies::empty(),
+            &[
+                memory::Barrier::Buffer {
+                    states: buffer::Access::SHADER_WRITE..buffer::Access::SHADER_READ,
+                    target: self.quads.buffer(),
+                },
+                memory::Barrier::Buffer {
+                    states: buffer::Access::SHADER_WRITE
+                        ..(buffer::Access::INDIRECT_COMMAND_READ | buffer::Access::HOST_READ),
+                    target: frame.draw.buffer(),
+                },
+            ],
+        );
+        Ok(())
+    }
+
+    /// Draws the quads the last `mesh` of frame `frame_index` made, with whichever pipeline is
+    /// bound, which has to have `set` bound as well.
+    pub fn draw(&self, encoder: &mut command::RenderPassInlineEncoder<B>, frame_index: usize) {
+        let frame = &self.frames[frame_index];
+        if !frame.meshed {
+            return;
+        }
+        encoder.draw_indirect(frame.draw.buffer(), 0, 1, mem::size_of::<QuadDraw>() as u32);
+    }
+}
+
+impl<B: Backend> Drop for GpuMesher<B> {
+    fn drop(&mut self) {
+        if let Some(pipeline_layout) = self.pipeline_layout.take() {
+            self.device.destroy_pipeline_layout(pipeline_layout);
+        }
+        // Everything else frees itself as it's dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atlas::BlockTextureId;
+    use world::Light;
+
+    #[test]
+    fn chunks_go_along_x_then_y_then_z() {
+        let region = MeshRegion::new(ChunkCoord::new(-1, 0, -1), [2, 3, 2]);
+        assert_eq!(region.slot(ChunkCoord::new(-1, 0, -1)), Some(0));
+        assert_eq!(region.slot(ChunkCoord::new(0, 0, -1)), Some(1));
+        assert_eq!(region.slot(ChunkCoord::new(-1, 1, -1)), Some(2));
+        assert_eq!(region.slot(ChunkCoord::new(-1, 0, 0)), Some(6));
+        let coords = region.coords();
+        assert_eq!(coords.len(), region.chunk_count());
+        for (slot, &coord) in coords.iter().enumerate() {
+            assert_eq!(region.slot(coord), Some(slot));
+        }
+    }
+
+    #[test]
+    fn chunks_outside_the_region_have_no_slot() {
+        let region = MeshRegion::new(ChunkCoord::new(0, 0, 0), [2, 2, 2]);
+        assert_eq!(region.slot(ChunkCoord::new(2, 0, 0)), None);
+        assert_eq!(region.slot(ChunkCoord::new(0, -1, 0)), None);
+    }
+
+    #[test]
+    fn voxels_hold_the_block_and_its_light() {
+        let mut chunk = Chunk::new();
+        chunk.set([1, 2, 3], BlockId(7));
+        chunk.set_light([1, 2, 3], Light::new(9, 4));
+        assert_eq!(voxel_word(Some(&chunk), [1, 2, 3]), 7 | 9 << 16 | 4 << 20);
+        // Chunks that aren't there are air in open sky
+        assert_eq!(voxel_word(None, [0, 0, 0]), 15 << 16);
+
+        let mut world = World::new();
+        let coord = ChunkCoord::new(0, 0, 0);
+        world.insert_chunk(coord, chunk);
+
+        let region = MeshRegion::new(coord, [1, 1, 1]);
+        let words = voxel_words(&world, &region);
+        assert_eq!(words.len(), CHUNK_VOLUME);
+        assert_eq!(words[1 + CHUNK_SIZE * (2 + CHUNK_SIZE * 3)] & 0xffff, 7);
+    }
+
+    #[test]
+    fn faces_hold_the_tile_and_surface() {
+        let mut textures = BlockTextures::new();
+        textures.set_top_bottom_sides(BlockId(3), BlockTextureId(5), BlockTextureId(6), BlockTextureId(7));
+        textures.set_surface(BlockId(4), Surface::Water);
+        let words = face_words(&textures);
+        assert_eq!(words.len(), MESH_BLOCK_KINDS as usize * 6);
+        let up = 3 * 6 + Direction::PosY.index();
+        assert_eq!(words[up], 5);
+        assert_eq!(words[3 * 6 + Direction::PosX.index()], 7);
+        assert_eq!(words[4 * 6] >> 16, 2);
+    }
+
+    #[test]
+    fn the_layouts_match_the_shader() {
+        assert_eq!(mem::size_of::<QuadDraw>(), 20);
+        assert_eq!(mem::size_of::<MeshConstants>(), 16);
+    }
+}
@@ -0,0 +1,446 @@
+//! Particles simulated on the gpu, for effects with far more of them than the cpu could move
+//! each tick, like snow falling all around the camera.
+//!
+//! Every particle lives in one big storage buffer, a pool a fixed size. New ones are made on the
+//! cpu, the same way as for `Particles`, and copied into the pool each frame, going round it in
+//! turn like `Particles` does when it's full, so they take the place of whichever have been
+//! around longest. A compute shader, `particles.comp`, runs however many ticks have come due on
+//! each particle, then writes a `Sprite` for each one still alive into that frame's sprite
+//! buffer, counting them into the instance count of an indirect draw. The sprites are drawn with
+//! the billboard pipelines, straight out of that buffer, with that draw, so the cpu never sees
+//! a particle again once it's spawned or even knows how many of them are left.
+//!
+//! The world isn't on the gpu, so the shader can't stop particles at every block they run into
+//! like `Particles` does. Instead each one is given the height of the ground under where it
+//! spawned, and lands there. The sprites aren't sorted either, since the pool is in no
+//! particular order. Blending them unsorted is only wrong where two overlap, and the billboard
+//! pipelines cut out the clear parts of each sprite, so at the sizes particles are it doesn't
+//! show.
+//!
+//! How many are alive is read back the next time a frame comes round, like `ChunkDraws` reads
+//! its stats, so the overlay's number is a frame or two behind.
+
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+
+use hal::{
+    buffer, command, memory, pso,
+    pso::PipelineStage,
+    queue::Supports,
+    Backend, Compute, Device, Transfer,
+};
+
+use allocator::MemoryCategory;
+use billboard::{ Sprite, SPRITE_VERTICES };
+use buffer::DeviceBuffer;
+use context::GfxContext;
+use descriptors::{ DescriptorAllocator, DescriptorSetLayout };
+use error::Result;
+use particles::Particle;
+use staging::StagingRing;
+
+/// How many particles `particles.comp` moves in each workgroup. Has to match its
+/// `local_size_x`.
+const PARTICLE_GROUP_SIZE: u32 = 256;
+
+/// How big the ring new particles are written into starts out, in bytes. It grows if a frame's
+/// don't fit.
+const SPAWN_RING_SIZE: u64 = 64 * 1024;
+
+/// One particle as the gpu keeps it. Has to match `Particle` in `particles.comp`, which is laid
+/// out by std430 rules, hence the order the fields are in and the padding at the end.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct GpuParticle {
+    pub position: [f32; 3],
+    /// How long it's been around, and how long it lasts, in seconds. A particle whose age has
+    /// reached its lifetime is dead, which is what the pool starts out full of.
+    pub age: f32,
+    pub velocity: [f32; 3],
+    pub lifetime: f32,
+    /// Where it was a tick ago, for drawing in between.
+    pub previous: [f32; 3],
+    pub size: f32,
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+    pub color: [f32; 4],
+    pub light: [f32; 2],
+    pub gravity: f32,
+    pub drag: f32,
+    /// How high the ground under it is, which it lands on.
+    pub ground: f32,
+    pub _padding: [f32; 3],
+}
+
+impl GpuParticle {
+    /// `particle` as the gpu keeps it, landing on the ground at `ground` high.
+    pub fn new(particle: &Particle, ground: f32) -> Self {
+        let position = particle.position.current();
+        GpuParticle {
+            position,
+            age: particle.age,
+            velocity: particle.velocity,
+            lifetime: particle.lifetime,
+            previous: position,
+            size: particle.size,
+            uv_min: [particle.uv.u0, particle.uv.v0],
+            uv_max: [particle.uv.u1, particle.uv.v1],
+            color: particle.color,
+            light: particle.light,
+            gravity: particle.gravity,
+            drag: particle.drag,
+            ground,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+/// The arguments of one draw, laid out the way `draw_indirect` reads them. Has to match
+/// `DrawCommand` in `particles.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct DrawCommand {
+    vertex_count: u32,
+    instance_count: u32,
+    first_vertex: u32,
+    first_instance: u32,
+}
+
+/// Has to match the `PushConstants` block in `particles.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct ParticleConstants {
+    ticks: u32,
+    tick_seconds: f32,
+    /// How far the clock is through the next tick, for drawing in between.
+    alpha: f32,
+    capacity: u32,
+}
+
+impl ParticleConstants {
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const ParticleConstants as *const u32,
+                mem::size_of::<ParticleConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// Where the new particles go in the pool, as runs of `(first slot, count)` starting at `next`
+/// and wrapping round to the start. Only the last `capacity` of `count` are kept, since any
+/// before them would be replaced straight away.
+fn spawn_runs(next: u64, count: u64, capacity: u64) -> Vec<(u64, u64)> {
+    let count = count.min(capacity);
+    let first = count.min(capacity - next);
+    let mut runs = vec![(next, first)];
+    if count > first {
+        runs.push((0, count - first));
+    }
+    runs.retain(|&(_, count)| count > 0);
+    runs
+}
+
+struct FrameParticles<B: Backend> {
+    /// A `Sprite` for each particle alive, written by `particles.comp`.
+    sprites: DeviceBuffer<B>,
+    draw: DeviceBuffer<B>,
+    set: B::DescriptorSet,
+    /// Whether the frame has been simulated, so `draw` has a count in it.
+    simulated: bool,
+}
+
+/// A pool of particles on the gpu, and each frame in flight's sprites of them.
+pub struct GpuParticles<B: Backend> {
+    device: Rc<B::Device>,
+    pool: DeviceBuffer<B>,
+    capacity: u64,
+    /// Which slot the next particle spawned goes in.
+    next_slot: u64,
+    spawned: Vec<GpuParticle>,
+    /// The new particles each frame, on their way into the pool.
+    spawns: StagingRing<B>,
+    frames: Vec<FrameParticles<B>>,
+    /// Only kept to free the frames' sets with.
+    _descriptors: DescriptorAllocator<B>,
+    _set_layout: Rc<DescriptorSetLayout<B>>,
+    pipeline_layout: Option<B::PipelineLayout>,
+}
+
+impl<B: Backend> GpuParticles<B> {
+    /// A pool of `capacity` particles, all of them dead, with sprite buffers big enough for
+    /// every one of them to be alive. This waits for the pool to be cleared.
+    pub fn new(context: &mut GfxContext<B>, capacity: u32, frames_in_flight: usize) -> Result<Self> {
+        let capacity = capacity as u64;
+        let pool_size = capacity * mem::size_of::<GpuParticle>() as u64;
+        let pool = DeviceBuffer::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            pool_size,
+            buffer::Usage::STORAGE | buffer::Usage::TRANSFER_DST,
+            memory::Properties::DEVICE_LOCAL,
+            MemoryCategory::Storage,
+        )?;
+        // Nothing is alive with no lifetime
+        context.submit_one_shot(|command_buffer| {
+            command_buffer.fill_buffer(pool.buffer(), 0..pool_size, 0);
+        })?;
+
+        // The pool, the sprites and the draw
+        let set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            (0..3)
+                .map(|binding| pso::DescriptorSetLayoutBinding {
+                    binding,
+                    ty: pso::DescriptorType::StorageBuffer,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::COMPUTE,
+                    immutable_samplers: false,
+                })
+                .collect(),
+        ));
+        let constants_size = (mem::size_of::<ParticleConstants>() / mem::size_of::<u32>()) as u32;
+        let pipeline_layout = context.device.create_pipeline_layout(
+            vec![set_layout.raw()],
+            &[(pso::ShaderStageFlags::COMPUTE, 0..constants_size)],
+        );
+        let mut descriptors = DescriptorAllocator::new(context.device.clone(), set_layout.clone());
+
+        let mut frames = Vec::with_capacity(frames_in_flight);
+        for _ in 0..frames_in_flight {
+            let sprites = DeviceBuffer::new(
+                context.device.clone(),
+                context.allocator.clone(),
+                capacity * mem::size_of::<Sprite>() as u64,
+                buffer::Usage::STORAGE | buffer::Usage::VERTEX,
+                memory::Properties::DEVICE_LOCAL,
+                MemoryCategory::Storage,
+            )?;
+            let draw = DeviceBuffer::new(
+                context.device.clone(),
+                context.allocator.clone(),
+                mem::size_of::<DrawCommand>() as u64,
+                buffer::Usage::STORAGE | buffer::Usage::INDIRECT,
+                memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT,
+                MemoryCategory::Storage,
+            )?;
+            let set = descriptors.allocate()?;
+            context.device.write_descriptor_sets(
+                [pool.buffer(), sprites.buffer(), draw.buffer()]
+                    .iter()
+                    .enumerate()
+                    .map(|(binding, &buffer)| pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: binding as u32,
+                        array_offset: 0,
+                        descriptors: Some(pso::Descriptor::Buffer(buffer, None..None)),
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            frames.push(FrameParticles { sprites, draw, set, simulated: false });
+        }
+
+        Ok(GpuParticles {
+            device: context.device.clone(),
+            pool,
+            capacity,
+            next_slot: 0,
+            spawned: Vec::new(),
+            spawns: StagingRing::new(
+                context.device.clone(),
+                context.allocator.clone(),
+                SPAWN_RING_SIZE,
+                buffer::Usage::TRANSFER_SRC,
+            )?,
+            frames,
+            _descriptors: descriptors,
+            _set_layout: set_layout,
+            pipeline_layout: Some(pipeline_layout),
+        })
+    }
+
+    /// The layout `particles.comp`'s pipeline has to be built with.
+    pub fn pipeline_layout(&self) -> &B::PipelineLayout {
+        self.pipeline_layout.as_ref().unwrap()
+    }
+
+    /// How many particles there's room for.
+    pub fn capacity(&self) -> u32 {
+        self.capacity as u32
+    }
+
+    /// Adds `particle` to the pool with the next frame that's simulated, landing on the ground
+    /// at `ground` high.
+    pub fn spawn(&mut self, particle: &Particle, ground: f32) {
+        self.spawned.push(GpuParticle::new(particle, ground));
+    }
+
+    /// How many particles were alive the last time frame `frame_index` was simulated, or `None`
+    /// if it never has been. Call this after `FrameSync::begin_frame` has waited for the frame's
+    /// fence.
+    pub fn read_alive(&self, frame_index: usize) -> Result<Option<usize>> {
+        let frame = &self.frames[frame_index];
+        if !frame.simulated {
+            return Ok(None);
+        }
+        let draw = frame.draw.read::<DrawCommand>()?[0];
+        Ok(Some(draw.instance_count as usize))
+    }
+
+    /// Copies in the particles spawned since the last frame, then runs `ticks` ticks of
+    /// `tick_seconds` on every particle with `pipeline`, which has to have been built from
+    /// `particles.comp` with `pipeline_layout`, and writes frame `frame_index`'s sprites `alpha`
+    /// of the way through the next tick. Record this once a frame, after the frame's fence has
+    /// been waited on, as a frame graph pass that the passes drawing them read from. It only
+    /// needs compute and transfer, so it can go on the compute queue, and leaves making the
+    /// sprites ready to draw to the graph.
+    pub fn simulate<C>(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, C, command::OneShot>,
+        pipeline: &B::ComputePipeline,
+        frame_index: usize,
+        ticks: u32,
+        tick_seconds: f32,
+        alpha: f32,
+    ) -> Result<()>
+    where
+        C: Supports<Compute> + Supports<Transfer>,
+    {
+        self.spawns.begin_frame(self.frames.len());
+        // The shader counts the sprites into the instance count
+        let draw = DrawCommand { vertex_count: SPRITE_VERTICES, ..DrawCommand::default() };
+        self.frames[frame_index].draw.write(&[draw])?;
+        self.frames[frame_index].simulated = true;
+
+        // The last frame's simulation has to be done with the pool before it's copied into or
+        // simulated again
+        command_buffer.pipeline_barrier(
+            PipelineStage::COMPUTE_SHADER..(PipelineStage::TRANSFER | PipelineStage::COMPUTE_SHADER),
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Buffer {
+                states: buffer::Access::SHADER_WRITE
+                    ..(buffer::Access::TRANSFER_WRITE | buffer::Access::SHADER_READ | buffer::Access::SHADER_WRITE),
+                target: self.pool.buffer(),
+            }],
+        );
+        if !self.spawned.is_empty() {
+            self.copy_spawned(command_buffer)?;
+        }
+
+        let frame = &self.frames[frame_index];
+        command_buffer.bind_compute_pipeline(pipeline);
+        let pipeline_layout = self.pipeline_layout.as_ref().unwrap();
+        command_buffer.bind_compute_descriptor_sets(pipeline_layout, 0, Some(&frame.set), &[]);
+        let constants = ParticleConstants { ticks, tick_seconds, alpha, capacity: self.capacity as u32 };
+        command_buffer.push_compute_constants(pipeline_layout, 0, constants.as_words());
+        let groups = (self.capacity as u32 + PARTICLE_GROUP_SIZE - 1) / PARTICLE_GROUP_SIZE;
+        command_buffer.dispatch([groups, 1, 1]);
+
+        // The frame graph makes the sprites and the draw ready for the passes drawing them, on
+        // whichever queue those are, so all that's left is the count read back after the fence
+        command_buffer.pipeline_barrier(
+            PipelineStage::COMPUTE_SHADER..PipelineStage::HOST,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Buffer {
+                states: buffer::Access::SHADER_WRITE..buffer::Access::HOST_READ,
+                target: frame.draw.buffer(),
+            }],
+        );
+        Ok(())
+    }
+
+    /// Writes the particles spawned since the last frame into the spawns ring, and records
+    /// copying them into the pool after the ones spawned before them.
+    fn copy_spawned<C>(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, C, command::OneShot>,
+    ) -> Result<()>
+    where
+        C: Supports<Transfer>,
+    {
+        let particle_size = mem::size_of::<GpuParticle>() as u64;
+        let count = (self.spawned.len() as u64).min(self.capacity);
+        {
+            let kept = &self.spawned[self.spawned.len() - count as usize..];
+            let start = self.spawns.write(kept)?;
+
+            let mut copied = 0;
+            let regions: Vec<command::BufferCopy> = spawn_runs(self.next_slot, count, self.capacity)
+                .into_iter()
+                .map(|(slot, run)| {
+                    let region = command::BufferCopy {
+                        src: start + copied * particle_size,
+                        dst: slot * particle_size,
+                        size: run * particle_size,
+                    };
+                    copied += run;
+                    region
+                })
+                .collect();
+            command_buffer.copy_buffer(self.spawns.buffer(), self.pool.buffer(), &regions);
+            command_buffer.pipeline_barrier(
+                PipelineStage::TRANSFER..PipelineStage::COMPUTE_SHADER,
+                memory::Dependencies::empty(),
+                &[memory::Barrier::Buffer {
+                    states: buffer::Access::TRANSFER_WRITE
+                        ..(buffer::Access::SHADER_READ | buffer::Access::SHADER_WRITE),
+                    target: self.pool.buffer(),
+                }],
+            );
+        }
+
+        self.next_slot = (self.next_slot + count) % self.capacity;
+        self.spawned.clear();
+        Ok(())
+    }
+
+    /// Draws frame `frame_index`'s sprites, as the last `simulate` of it left them, with
+    /// whichever billboard pipeline is bound, which reads them from binding 0.
+    pub fn draw(&self, encoder: &mut command::RenderPassInlineEncoder<B>, frame_index: usize) {
+        let frame = &self.frames[frame_index];
+        if !frame.simulated {
+            return;
+        }
+        encoder.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(frame.sprites.buffer(), 0)]));
+        encoder.draw_indirect(frame.draw.buffer(), 0, 1, mem::size_of::<DrawCommand>() as u32);
+    }
+}
+
+impl<B: Backend> Drop for GpuParticles<B> {
+    fn drop(&mut self) {
+        if let Some(pipeline_layout) = self.pipeline_layout.take() {
+            self.device.destroy_pipeline_layout(pipeline_layout);
+        }
+        // Everything else frees itself as it's dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawns_go_after_the_last_ones() {
+        assert_eq!(spawn_runs(3, 4, 16), vec![(3, 4)]);
+    }
+
+    #[test]
+    fn spawns_wrap_round_the_pool() {
+        assert_eq!(spawn_runs(14, 4, 16), vec![(14, 2), (0, 2)]);
+        assert_eq!(spawn_runs(12, 4, 16), vec![(12, 4)]);
+    }
+
+    #[test]
+    fn only_a_pool_full_of_spawns_is_kept() {
+        assert_eq!(spawn_runs(0, 40, 16), vec![(0, 16)]);
+        assert_eq!(spawn_runs(5, 40, 16), vec![(5, 11), (0, 5)]);
+    }
+
+    #[test]
+    fn the_layouts_match_the_shader() {
+        assert_eq!(mem::size_of::<GpuParticle>(), 112);
+        assert_eq!(mem::size_of::<DrawCommand>(), 16);
+    }
+}
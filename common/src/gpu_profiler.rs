@@ -0,0 +1,171 @@
+//! Timing passes on the gpu with timestamp queries.
+//!
+//! Each frame in flight gets its own query pool. A scope writes a timestamp when it begins and
+//! another when it ends, and the results are read back the next time that frame comes around.
+//! By then `FrameSync::begin_frame` has waited for the frame's fence, so reading them never
+//! stalls.
+
+use std::rc::Rc;
+use std::slice;
+
+use hal::{
+    command, query,
+    pso::PipelineStage,
+    Adapter, Backend, Device, General, PhysicalDevice,
+};
+
+use error::Result;
+
+/// The most scopes a single frame can have. Any past this aren't timed.
+pub const MAX_SCOPES: usize = 16;
+
+/// Two timestamps per scope.
+const QUERY_COUNT: query::QueryId = MAX_SCOPES as query::QueryId * 2;
+
+/// How long a scope took on the gpu.
+#[derive(Clone, Debug)]
+pub struct ScopeTiming {
+    pub name: &'static str,
+    pub milliseconds: f32,
+}
+
+struct ProfilerFrame<B: Backend> {
+    pool: Option<B::QueryPool>,
+    /// The scopes recorded the last time this frame was used, in order. Scope `n` owns queries
+    /// `2n` (begin) and `2n + 1` (end).
+    scopes: Vec<&'static str>,
+    /// Whether the last scope has been begun but not ended yet.
+    open: bool,
+}
+
+/// Per-pass gpu timings for each frame in flight, for showing in a debug overlay or log.
+pub struct GpuProfiler<B: Backend> {
+    device: Rc<B::Device>,
+    frames: Vec<ProfilerFrame<B>>,
+    current: usize,
+    /// Nanoseconds per timestamp tick.
+    timestamp_period: f32,
+    timings: Vec<ScopeTiming>,
+}
+
+impl<B: Backend> GpuProfiler<B> {
+    pub fn new(device: Rc<B::Device>, adapter: &Adapter<B>, frames_in_flight: usize) -> Self {
+        let frames = (0..frames_in_flight)
+            .map(|_| ProfilerFrame {
+                pool: Some(device.create_query_pool(query::QueryType::Timestamp, QUERY_COUNT)),
+                scopes: Vec::with_capacity(MAX_SCOPES),
+                open: false,
+            })
+            .collect();
+
+        GpuProfiler {
+            device,
+            frames,
+            current: 0,
+            timestamp_period: adapter.physical_device.limits().timestamp_period,
+            timings: Vec::new(),
+        }
+    }
+
+    /// Reads back the timings from the last time frame `frame_index` was rendered, and resets
+    /// its queries with `command_buffer` so it can be timed again. Call this after
+    /// `FrameSync::begin_frame` and before recording any scopes or render passes.
+    pub fn begin_frame(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        frame_index: usize,
+    ) -> Result<()> {
+        self.current = frame_index;
+        let frame = &mut self.frames[frame_index];
+        let pool = frame.pool.as_ref().unwrap();
+
+        // A scope that was never ended has no end timestamp to wait for
+        if frame.open {
+            frame.scopes.pop();
+        }
+        if !frame.scopes.is_empty() {
+            let count = frame.scopes.len() * 2;
+            let mut ticks = vec![0u64; count];
+            let data = unsafe { slice::from_raw_parts_mut(ticks.as_mut_ptr() as *mut u8, count * 8) };
+            self.device.get_query_pool_results(
+                pool,
+                0..count as query::QueryId,
+                data,
+                8,
+                query::ResultFlags::BITS_64 | query::ResultFlags::WAIT,
+            )?;
+
+            let period = self.timestamp_period;
+            self.timings = frame.scopes
+                .iter()
+                .zip(ticks.chunks(2))
+                .map(|(&name, ticks)| ScopeTiming {
+                    name,
+                    milliseconds: ticks[1].saturating_sub(ticks[0]) as f32 * period / 1_000_000.0,
+                })
+                .collect();
+        }
+
+        frame.scopes.clear();
+        frame.open = false;
+        command_buffer.reset_query_pool(pool, 0..QUERY_COUNT);
+        Ok(())
+    }
+
+    /// Starts timing a scope called `name`. Scopes can't be nested, and have to be begun and
+    /// ended outside of render passes.
+    pub fn begin_scope(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        name: &'static str,
+    ) {
+        let frame = &mut self.frames[self.current];
+        debug_assert!(!frame.open, "GpuProfiler scopes can't be nested");
+        if frame.scopes.len() == MAX_SCOPES {
+            return;
+        }
+
+        let query = query::Query {
+            pool: frame.pool.as_ref().unwrap(),
+            id: (frame.scopes.len() * 2) as query::QueryId,
+        };
+        command_buffer.write_timestamp(PipelineStage::TOP_OF_PIPE, query);
+        frame.scopes.push(name);
+        frame.open = true;
+    }
+
+    /// Stops timing the scope started by the last `begin_scope`.
+    pub fn end_scope(&mut self, command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>) {
+        let frame = &mut self.frames[self.current];
+        if !frame.open {
+            return;
+        }
+
+        let query = query::Query {
+            pool: frame.pool.as_ref().unwrap(),
+            id: (frame.scopes.len() * 2 - 1) as query::QueryId,
+        };
+        command_buffer.write_timestamp(PipelineStage::BOTTOM_OF_PIPE, query);
+        frame.open = false;
+    }
+
+    /// The timings from the most recently finished frame.
+    pub fn timings(&self) -> &[ScopeTiming] {
+        &self.timings
+    }
+
+    /// How long all of the scopes in the most recently finished frame took together.
+    pub fn total_milliseconds(&self) -> f32 {
+        self.timings.iter().map(|timing| timing.milliseconds).sum()
+    }
+}
+
+impl<B: Backend> Drop for GpuProfiler<B> {
+    fn drop(&mut self) {
+        for frame in &mut self.frames {
+            if let Some(pool) = frame.pool.take() {
+                self.device.destroy_query_pool(pool);
+            }
+        }
+    }
+}
@@ -0,0 +1,1033 @@
+//! A render graph, where passes say which images they read and draw into and everything else is
+//! worked out from that.
+//!
+//! Ordering render passes by hand, and remembering which barrier goes between which two of them,
+//! gets harder with every pass added. Instead each pass here names the resources it reads and
+//! writes, and `GraphBuilder::compile` works out
+//!
+//! - the order the passes run in. Anything that reads a resource runs after everything that
+//!   draws into it, and passes drawing into the same resource run in the order they were added.
+//!   Passes that nothing ends up using are culled.
+//! - which images the graph's own, transient resources can share. Two with the same description
+//!   that are never in use at the same time get the same image.
+//! - what each image goes through before each pass, as `Transition`s to turn into barriers, and
+//!   whether a pass has to store what it draws at all.
+//! - how the passes are split into submissions, for ones on the compute queue to run alongside
+//!   the graphics queue. A submission waits for the other queue only when one of its passes
+//!   uses what a pass there wrote, so graphics work that needs nothing from the compute queue
+//!   carries on while it runs.
+//!
+//! Resources from outside the graph can be imported to be read, or written by passes in ways
+//! the graph doesn't look into, like buffers filled by compute shaders or images drawn in render
+//! passes of their own, and the graph's own exported to be read after it. This part knows nothing
+//! about the gpu, so the description of a transient resource is whatever the caller likes.
+//! `RenderGraph` builds the images, render passes and framebuffers for a compiled `Graph`, and
+//! records and submits it.
+
+use std::fmt;
+use std::ops::Range;
+
+/// A resource added to a `GraphBuilder`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ResourceId(usize);
+
+/// A pass added to a `GraphBuilder`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PassId(usize);
+
+/// Which queue a pass runs on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Queue {
+    Graphics,
+    /// A queue of its own for compute work, running alongside the graphics queue. Passes on it
+    /// can read images and write storage images and imported resources, but can't draw.
+    Compute,
+}
+
+impl Queue {
+    fn index(self) -> usize {
+        match self {
+            Queue::Graphics => 0,
+            Queue::Compute => 1,
+        }
+    }
+
+    fn other(self) -> Queue {
+        match self {
+            Queue::Graphics => Queue::Compute,
+            Queue::Compute => Queue::Graphics,
+        }
+    }
+}
+
+/// How a pass uses a resource.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Usage {
+    /// Drawn into as a colour attachment.
+    ColorAttachment,
+    /// Drawn into and tested against as the depth attachment.
+    DepthAttachment,
+    /// Read in a shader.
+    Sampled,
+    /// Written by a shader as a storage image.
+    Storage,
+    /// In some way the graph doesn't look into, which is how it sees every use of an imported
+    /// resource.
+    External,
+}
+
+/// What a pass starts from in an image it draws into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Load {
+    ClearColor([f32; 4]),
+    ClearDepth(f32),
+    /// Whatever the passes before it drew, to build on.
+    Keep,
+    /// Anything at all, for a pass that covers every pixel.
+    DontCare,
+}
+
+/// An image a pass draws into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Attachment {
+    pub resource: ResourceId,
+    pub usage: Usage,
+    pub load: Load,
+    /// Whether anything after the pass uses what it draws. If not, it can be thrown away at the
+    /// end of the pass rather than written out to memory.
+    pub store: bool,
+}
+
+/// A change in how a resource is used, which needs a barrier to wait for the last use to finish
+/// and, for the graph's own images, move the image into the layout for the next. Imported
+/// resources only get them after a pass writes them, going from `External` to `External`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transition {
+    pub resource: ResourceId,
+    /// How the image was last used. For the first use in a frame that's the last use in the
+    /// frame before, which the image might be shared with another resource for.
+    pub from: Usage,
+    pub to: Usage,
+    /// The queues of the pass that last used the resource and of the one about to. When they
+    /// differ, the resource is being handed over between queues, and the barrier is the
+    /// acquiring half of that, after the submission it's in has waited for the other queue.
+    pub from_queue: Queue,
+    pub to_queue: Queue,
+    /// Whether what's in the image can be thrown away, because the next use doesn't keep it.
+    pub discard: bool,
+}
+
+impl Transition {
+    pub fn is_handoff(&self) -> bool {
+        self.from_queue != self.to_queue
+    }
+}
+
+/// One pass, once the graph is compiled.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub pass: PassId,
+    pub queue: Queue,
+    /// To make before the pass begins.
+    pub transitions: Vec<Transition>,
+    /// In the order they were added to the pass.
+    pub attachments: Vec<Attachment>,
+}
+
+/// A run of steps on one queue that are submitted together, once the graph is compiled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Submission {
+    pub queue: Queue,
+    /// Which of the graph's steps it records, in order.
+    pub steps: Range<usize>,
+    /// The submission on the other queue to wait for before starting, because the steps use
+    /// what it or one before it on that queue wrote. Waiting for it covers those too.
+    pub wait: Option<usize>,
+}
+
+/// Why a graph couldn't be compiled.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GraphError {
+    /// A pass reads a resource that it also draws into. `Load::Keep` is how to build on it.
+    ReadsWhatItWrites { pass: String, resource: String },
+    /// A pass draws into an imported resource, which the graph can only read, or let passes
+    /// write through `PassBuilder::write`.
+    WritesImport { pass: String, resource: String },
+    /// A pass writes one of the graph's own resources through `PassBuilder::write`, which is
+    /// only for imported ones.
+    UntrackedWrite { pass: String, resource: String },
+    /// A pass on the compute queue draws into an attachment.
+    AttachmentOnCompute { pass: String, resource: String },
+    /// A pass keeps what's in a resource that nothing draws into before it.
+    KeepsNothing { pass: String, resource: String },
+    /// A resource is read or exported, but nothing draws into it.
+    Unwritten { resource: String },
+    /// These passes depend on each other in a loop.
+    Cycle { passes: Vec<String> },
+    /// A pass draws into images of different sizes.
+    MismatchedSizes { pass: String },
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GraphError::ReadsWhatItWrites { ref pass, ref resource } => {
+                write!(f, "The {} pass reads {} as well as drawing into it", pass, resource)
+            }
+            GraphError::WritesImport { ref pass, ref resource } => {
+                write!(f, "The {} pass draws into {}, which is imported and can only be read", pass, resource)
+            }
+            GraphError::UntrackedWrite { ref pass, ref resource } => {
+                write!(f, "The {} pass writes {} itself, but only imported resources can be", pass, resource)
+            }
+            GraphError::AttachmentOnCompute { ref pass, ref resource } => {
+                write!(f, "The {} pass is on the compute queue, so it can't draw into {}", pass, resource)
+            }
+            GraphError::KeepsNothing { ref pass, ref resource } => {
+                write!(f, "The {} pass keeps what's in {}, but nothing draws into it before", pass, resource)
+            }
+            GraphError::Unwritten { ref resource } => write!(f, "{} is used, but nothing draws into it", resource),
+            GraphError::Cycle { ref passes } => write!(f, "The passes {} depend on each other in a loop", passes.join(", ")),
+            GraphError::MismatchedSizes { ref pass } => {
+                write!(f, "The {} pass draws into images of different sizes", pass)
+            }
+        }
+    }
+}
+
+struct Resource<D> {
+    name: String,
+    /// `None` for an imported resource.
+    desc: Option<D>,
+    exported: bool,
+}
+
+struct Pass {
+    name: String,
+    queue: Queue,
+    reads: Vec<ResourceId>,
+    writes: Vec<(ResourceId, Usage, Load)>,
+}
+
+/// How far through the frame an imported resource is: the queue of the last pass to write it,
+/// and which queues have waited for that since.
+#[derive(Clone, Copy)]
+struct ImportState {
+    written_on: Option<Queue>,
+    waited: [bool; 2],
+}
+
+impl ImportState {
+    /// The transition `queue` needs before using the resource, if a pass has written it since
+    /// `queue` last waited for one.
+    fn wait(&mut self, resource: ResourceId, queue: Queue) -> Option<Transition> {
+        let from_queue = self.written_on?;
+        if self.waited[queue.index()] {
+            return None;
+        }
+        self.waited[queue.index()] = true;
+        Some(Transition {
+            resource,
+            from: Usage::External,
+            to: Usage::External,
+            from_queue,
+            to_queue: queue,
+            discard: false,
+        })
+    }
+}
+
+/// Collects the resources and passes of a graph, to be compiled once they're all added. `D` is
+/// the description of a transient resource, and only resources with equal descriptions share
+/// images.
+pub struct GraphBuilder<D> {
+    resources: Vec<Resource<D>>,
+    passes: Vec<Pass>,
+}
+
+impl<D: Clone + PartialEq> GraphBuilder<D> {
+    pub fn new() -> Self {
+        GraphBuilder {
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    /// A resource of the graph's own, which only lasts as long as the passes using it.
+    pub fn transient(&mut self, name: &str, desc: D) -> ResourceId {
+        self.add_resource(name, Some(desc))
+    }
+
+    /// A resource from outside the graph, which passes can read, or write through
+    /// `PassBuilder::write`. It has to be ready before the graph runs, and the graph never
+    /// changes its layout.
+    pub fn import(&mut self, name: &str) -> ResourceId {
+        self.add_resource(name, None)
+    }
+
+    /// Keeps a transient resource for reading after the graph, on the graphics queue, which
+    /// leaves it ready to sample.
+    pub fn export(&mut self, resource: ResourceId) {
+        self.resources[resource.0].exported = true;
+    }
+
+    /// Adds a pass on the graphics queue, whose reads and writes are added through what this
+    /// returns.
+    pub fn pass<'a>(&'a mut self, name: &str) -> PassBuilder<'a, D> {
+        self.passes.push(Pass {
+            name: name.to_string(),
+            queue: Queue::Graphics,
+            reads: Vec::new(),
+            writes: Vec::new(),
+        });
+        let index = self.passes.len() - 1;
+        PassBuilder { graph: self, index }
+    }
+
+    fn add_resource(&mut self, name: &str, desc: Option<D>) -> ResourceId {
+        self.resources.push(Resource {
+            name: name.to_string(),
+            desc,
+            exported: false,
+        });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    fn error(&self, pass: usize, resource: ResourceId) -> (String, String) {
+        (self.passes[pass].name.clone(), self.resources[resource.0].name.clone())
+    }
+
+    /// Works out the order, images, transitions and submissions, or why they can't be worked
+    /// out.
+    pub fn compile(self) -> Result<Graph<D>, GraphError> {
+        let pass_count = self.passes.len();
+
+        // Every pass that writes each resource, in the order they were added
+        let mut writers = vec![Vec::new(); self.resources.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for &(resource, usage, _) in &pass.writes {
+                if pass.reads.contains(&resource) {
+                    let (pass, resource) = self.error(index, resource);
+                    return Err(GraphError::ReadsWhatItWrites { pass, resource });
+                }
+                let imported = self.resources[resource.0].desc.is_none();
+                if imported && usage != Usage::External {
+                    let (pass, resource) = self.error(index, resource);
+                    return Err(GraphError::WritesImport { pass, resource });
+                }
+                if !imported && usage == Usage::External {
+                    let (pass, resource) = self.error(index, resource);
+                    return Err(GraphError::UntrackedWrite { pass, resource });
+                }
+                let attachment = usage == Usage::ColorAttachment || usage == Usage::DepthAttachment;
+                if attachment && pass.queue == Queue::Compute {
+                    let (pass, resource) = self.error(index, resource);
+                    return Err(GraphError::AttachmentOnCompute { pass, resource });
+                }
+                writers[resource.0].push(index);
+            }
+        }
+        // Imported resources have whatever was there before the graph to build on
+        for (resource, info) in self.resources.iter().enumerate().filter(|&(_, info)| info.desc.is_some()) {
+            let resource = ResourceId(resource);
+            if let Some(&first) = writers[resource.0].first() {
+                let keeps = self.passes[first].writes.iter().any(|&(written, _, load)| written == resource && load == Load::Keep);
+                if keeps {
+                    let (pass, resource) = self.error(first, resource);
+                    return Err(GraphError::KeepsNothing { pass, resource });
+                }
+            } else {
+                let read = self.passes.iter().any(|pass| pass.reads.contains(&resource));
+                if read || info.exported {
+                    return Err(GraphError::Unwritten { resource: info.name.clone() });
+                }
+            }
+        }
+
+        // Each writer waits for the one before it, and each reader for the last of them
+        let mut depends_on = vec![Vec::new(); pass_count];
+        for writers in &writers {
+            for pair in writers.windows(2) {
+                depends_on[pair[1]].push(pair[0]);
+            }
+        }
+        for (index, pass) in self.passes.iter().enumerate() {
+            for &resource in &pass.reads {
+                if let Some(&last) = writers[resource.0].last() {
+                    depends_on[index].push(last);
+                }
+            }
+        }
+
+        // Only what leads to an exported resource, or to an imported one that a pass writes for
+        // whatever comes after the graph, is needed
+        let mut needed = vec![false; pass_count];
+        let mut unvisited: Vec<usize> = (0..pass_count)
+            .filter(|&index| {
+                self.passes[index].writes.iter().any(|&(resource, _, _)| {
+                    let info = &self.resources[resource.0];
+                    info.exported || info.desc.is_none()
+                })
+            })
+            .collect();
+        while let Some(index) = unvisited.pop() {
+            if !needed[index] {
+                needed[index] = true;
+                unvisited.extend(depends_on[index].iter().cloned());
+            }
+        }
+
+        // Whichever ready pass was added first goes next, so independent passes keep the order
+        // they were added in. Graphs are small enough that looking through them all each time
+        // is fine.
+        let mut order = Vec::new();
+        let mut scheduled = vec![false; pass_count];
+        while let Some(next) = (0..pass_count)
+            .find(|&index| needed[index] && !scheduled[index] && depends_on[index].iter().all(|&dependency| scheduled[dependency]))
+        {
+            scheduled[next] = true;
+            order.push(next);
+        }
+        if order.len() < needed.iter().filter(|&&needed| needed).count() {
+            let passes = (0..pass_count)
+                .filter(|&index| needed[index] && !scheduled[index])
+                .map(|index| self.passes[index].name.clone())
+                .collect();
+            return Err(GraphError::Cycle { passes });
+        }
+
+        // The first and last steps each transient resource is used in. Exported resources are
+        // in use until after the last step.
+        let mut lifetimes: Vec<Option<(usize, usize)>> = vec![None; self.resources.len()];
+        for (step, &index) in order.iter().enumerate() {
+            let pass = &self.passes[index];
+            let used = pass.reads.iter().cloned().chain(pass.writes.iter().map(|&(resource, _, _)| resource));
+            for resource in used {
+                if self.resources[resource.0].desc.is_some() {
+                    let lifetime = lifetimes[resource.0].get_or_insert((step, step));
+                    lifetime.1 = step;
+                }
+            }
+        }
+        for (resource, info) in self.resources.iter().enumerate() {
+            if let Some(ref mut lifetime) = lifetimes[resource] {
+                if info.exported {
+                    lifetime.1 = order.len();
+                }
+            }
+        }
+
+        let (slots, slot_descs) = assign_slots(&self.resources, &lifetimes);
+
+        // How each slot's image is left at the end of a frame, and by which queue, which is
+        // where the next frame starts from. Exported images are read after the graph, on the
+        // graphics queue.
+        let mut last_uses = vec![(Usage::Sampled, Queue::Graphics); slot_descs.len()];
+        let mut last_steps = vec![None; slot_descs.len()];
+        for (resource, info) in self.resources.iter().enumerate() {
+            let (slot, (_, last)) = match (slots[resource], lifetimes[resource]) {
+                (Some(slot), Some(lifetime)) => (slot, lifetime),
+                _ => continue,
+            };
+            if last_steps[slot].map_or(false, |step| step > last) {
+                continue;
+            }
+            last_steps[slot] = Some(last);
+            last_uses[slot] = if info.exported {
+                (Usage::Sampled, Queue::Graphics)
+            } else {
+                let pass = &self.passes[order[last]];
+                let usage = pass.writes
+                    .iter()
+                    .find(|&&(written, _, _)| written == ResourceId(resource))
+                    .map_or(Usage::Sampled, |&(_, usage, _)| usage);
+                (usage, pass.queue)
+            };
+        }
+
+        let mut uses: Vec<Option<(Usage, Queue)>> = vec![None; slot_descs.len()];
+        let mut imports = vec![ImportState { written_on: None, waited: [false; 2] }; self.resources.len()];
+        // The last step on each queue to use each slot, and the steps on the other queue each step
+        // takes a slot over from. Those have to be waited for as much as the passes it depends
+        // on, since a slot can go from one resource to another between the queues.
+        let mut slot_steps: Vec<[Option<usize>; 2]> = vec![[None; 2]; slot_descs.len()];
+        let mut handoffs = vec![Vec::new(); order.len()];
+        let mut steps = Vec::with_capacity(order.len());
+        for (step, &index) in order.iter().enumerate() {
+            let pass = &self.passes[index];
+            let queue = pass.queue;
+            let mut transitions = Vec::new();
+            for &resource in &pass.reads {
+                let slot = match slots[resource.0] {
+                    Some(slot) => slot,
+                    None => {
+                        transitions.extend(imports[resource.0].wait(resource, queue));
+                        continue;
+                    }
+                };
+                let (from, from_queue) = uses[slot].unwrap_or(last_uses[slot]);
+                // Reading after reading needs nothing in between, even on the other queue
+                if from != Usage::Sampled {
+                    transitions.push(Transition { resource, from, to: Usage::Sampled, from_queue, to_queue: queue, discard: false });
+                    handoffs[step].extend(slot_steps[slot][queue.other().index()]);
+                }
+                uses[slot] = Some((Usage::Sampled, queue));
+                slot_steps[slot][queue.index()] = Some(step);
+            }
+
+            let mut attachments = Vec::with_capacity(pass.writes.len());
+            for &(resource, usage, load) in &pass.writes {
+                let slot = match slots[resource.0] {
+                    Some(slot) => slot,
+                    None => {
+                        transitions.extend(imports[resource.0].wait(resource, queue));
+                        imports[resource.0] = ImportState { written_on: Some(queue), waited: [false; 2] };
+                        continue;
+                    }
+                };
+                let (from, from_queue) = uses[slot].unwrap_or(last_uses[slot]);
+                // Even drawing after drawing has to wait for the first to finish
+                transitions.push(Transition { resource, from, to: usage, from_queue, to_queue: queue, discard: load != Load::Keep });
+                handoffs[step].extend(slot_steps[slot][queue.other().index()]);
+                uses[slot] = Some((usage, queue));
+                slot_steps[slot][queue.index()] = Some(step);
+                if usage != Usage::Storage {
+                    let store = lifetimes[resource.0].map_or(false, |(_, last)| last > step);
+                    attachments.push(Attachment { resource, usage, load, store });
+                }
+            }
+
+            steps.push(Step { pass: PassId(index), queue, transitions, attachments });
+        }
+
+        let mut finish = Vec::new();
+        for (resource, info) in self.resources.iter().enumerate() {
+            if !info.exported {
+                continue;
+            }
+            let slot = slots[resource].unwrap();
+            let (from, from_queue) = uses[slot].unwrap();
+            if from != Usage::Sampled {
+                finish.push(Transition {
+                    resource: ResourceId(resource),
+                    from,
+                    to: Usage::Sampled,
+                    from_queue,
+                    to_queue: Queue::Graphics,
+                    discard: false,
+                });
+            }
+        }
+
+        // Steps in a row on the same queue go in one submission, until one needs to wait for a
+        // submission on the other queue that its own hasn't waited for yet. Waiting for a
+        // submission covers everything before it on its queue, so each queue only has to keep
+        // track of the latest one it's waited for.
+        let mut submissions: Vec<Submission> = Vec::new();
+        let mut submission_of = vec![0; pass_count];
+        let mut waited: [Option<usize>; 2] = [None, None];
+        for (step, &index) in order.iter().enumerate() {
+            let queue = self.passes[index].queue;
+            let wait = depends_on[index]
+                .iter()
+                .cloned()
+                .chain(handoffs[step].iter().map(|&from| order[from]))
+                .filter(|&dependency| self.passes[dependency].queue != queue)
+                .map(|dependency| submission_of[dependency])
+                .filter(|&submission| waited[queue.index()].map_or(true, |waited| submission > waited))
+                .max();
+            match submissions.last_mut() {
+                Some(ref mut last) if last.queue == queue && wait.is_none() => last.steps.end = step + 1,
+                _ => submissions.push(Submission { queue, steps: step..step + 1, wait }),
+            }
+            if wait.is_some() {
+                waited[queue.index()] = wait;
+            }
+            submission_of[index] = submissions.len() - 1;
+        }
+        let wait_after = submissions
+            .iter()
+            .rposition(|submission| submission.queue == Queue::Compute)
+            .filter(|&last| waited[Queue::Graphics.index()].map_or(true, |waited| last > waited));
+
+        let culled = (0..pass_count).filter(|&index| !needed[index]).map(PassId).collect();
+        Ok(Graph {
+            resources: self.resources,
+            passes: self.passes,
+            steps,
+            finish,
+            submissions,
+            wait_after,
+            slots,
+            slot_descs,
+            culled,
+        })
+    }
+}
+
+impl<D: Clone + PartialEq> Default for GraphBuilder<D> {
+    fn default() -> Self {
+        GraphBuilder::new()
+    }
+}
+
+/// Gives each transient resource that's used an image slot, sharing a slot between resources
+/// with the same description when one's done with it before the other starts. Since a pass
+/// using both needs them both, sharing needs one to finish a whole step before the other.
+/// Returns the slot of each resource and the description of each slot.
+fn assign_slots<D: Clone + PartialEq>(
+    resources: &[Resource<D>],
+    lifetimes: &[Option<(usize, usize)>],
+) -> (Vec<Option<usize>>, Vec<D>) {
+    let mut by_start: Vec<usize> = (0..resources.len()).filter(|&resource| lifetimes[resource].is_some()).collect();
+    by_start.sort_by_key(|&resource| lifetimes[resource].unwrap().0);
+
+    let mut slots = vec![None; resources.len()];
+    let mut slot_descs: Vec<D> = Vec::new();
+    // The last step each slot is in use until
+    let mut slot_ends: Vec<usize> = Vec::new();
+    for resource in by_start {
+        let desc = resources[resource].desc.as_ref().unwrap();
+        let (first, last) = lifetimes[resource].unwrap();
+        let free = (0..slot_descs.len()).find(|&slot| slot_descs[slot] == *desc && slot_ends[slot] < first);
+        let slot = match free {
+            Some(slot) => slot,
+            None => {
+                slot_descs.push(desc.clone());
+                slot_ends.push(0);
+                slot_descs.len() - 1
+            }
+        };
+        slot_ends[slot] = last;
+        slots[resource] = Some(slot);
+    }
+    (slots, slot_descs)
+}
+
+/// A compiled graph: the passes in the order they run, and the image slots behind its
+/// resources.
+pub struct Graph<D> {
+    resources: Vec<Resource<D>>,
+    passes: Vec<Pass>,
+    steps: Vec<Step>,
+    finish: Vec<Transition>,
+    submissions: Vec<Submission>,
+    wait_after: Option<usize>,
+    slots: Vec<Option<usize>>,
+    slot_descs: Vec<D>,
+    culled: Vec<PassId>,
+}
+
+impl<D> Graph<D> {
+    /// The passes that are run, in order.
+    pub fn steps(&self) -> &[Step] {
+        &self.steps
+    }
+
+    /// Where `pass` comes in `steps`, or `None` if it was culled.
+    pub fn step_of(&self, pass: PassId) -> Option<usize> {
+        self.steps.iter().position(|step| step.pass == pass)
+    }
+
+    /// To make after the last pass, leaving the exported resources ready to sample.
+    pub fn finish(&self) -> &[Transition] {
+        &self.finish
+    }
+
+    /// How the steps are submitted, in the order to submit them.
+    pub fn submissions(&self) -> &[Submission] {
+        &self.submissions
+    }
+
+    /// The last submission on the compute queue, if nothing on the graphics queue waits for it.
+    /// Whatever comes after the graph on the graphics queue has to, since what it wrote is only
+    /// ready to use once it's done.
+    pub fn wait_after(&self) -> Option<usize> {
+        self.wait_after
+    }
+
+    /// The passes left out because nothing uses what they draw.
+    pub fn culled(&self) -> &[PassId] {
+        &self.culled
+    }
+
+    /// The image slot behind `resource`, or `None` if it's imported or nothing uses it.
+    pub fn slot(&self, resource: ResourceId) -> Option<usize> {
+        self.slots[resource.0]
+    }
+
+    /// The description of each image slot.
+    pub fn slots(&self) -> &[D] {
+        &self.slot_descs
+    }
+
+    pub fn reads(&self, pass: PassId) -> &[ResourceId] {
+        &self.passes[pass.0].reads
+    }
+
+    pub fn pass_queue(&self, pass: PassId) -> Queue {
+        self.passes[pass.0].queue
+    }
+
+    pub fn pass_name(&self, pass: PassId) -> &str {
+        &self.passes[pass.0].name
+    }
+
+    pub fn resource_name(&self, resource: ResourceId) -> &str {
+        &self.resources[resource.0].name
+    }
+}
+
+/// Adds the reads and writes of one pass.
+pub struct PassBuilder<'a, D: 'a> {
+    graph: &'a mut GraphBuilder<D>,
+    index: usize,
+}
+
+impl<'a, D> PassBuilder<'a, D> {
+    /// Reads `resource` in a shader.
+    pub fn read(self, resource: ResourceId) -> Self {
+        {
+            let reads = &mut self.graph.passes[self.index].reads;
+            if !reads.contains(&resource) {
+                reads.push(resource);
+            }
+        }
+        self
+    }
+
+    /// Draws into `resource` as a colour attachment.
+    pub fn color(self, resource: ResourceId, load: Load) -> Self {
+        self.graph.passes[self.index].writes.push((resource, Usage::ColorAttachment, load));
+        self
+    }
+
+    /// Draws into `resource` as the depth attachment. A pass can only have one.
+    pub fn depth(self, resource: ResourceId, load: Load) -> Self {
+        self.graph.passes[self.index].writes.push((resource, Usage::DepthAttachment, load));
+        self
+    }
+
+    /// Writes `resource` as a storage image, which the shader has to cover every pixel of.
+    pub fn storage(self, resource: ResourceId) -> Self {
+        self.graph.passes[self.index].writes.push((resource, Usage::Storage, Load::DontCare));
+        self
+    }
+
+    /// Writes imported `resource` in some way the graph doesn't look into, like filling a buffer
+    /// from a compute shader. Passes that use it afterwards run after this one, and wait for it.
+    pub fn write(self, resource: ResourceId) -> Self {
+        self.graph.passes[self.index].writes.push((resource, Usage::External, Load::Keep));
+        self
+    }
+
+    /// Runs the pass on `queue` rather than the graphics queue.
+    pub fn on(self, queue: Queue) -> Self {
+        self.graph.passes[self.index].queue = queue;
+        self
+    }
+
+    pub fn id(self) -> PassId {
+        PassId(self.index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: Load = Load::ClearColor([0.0; 4]);
+
+    fn names(graph: &Graph<u32>) -> Vec<&str> {
+        graph.steps().iter().map(|step| graph.pass_name(step.pass)).collect()
+    }
+
+    #[test]
+    fn readers_run_after_writers_whatever_order_they_were_added_in() {
+        let mut graph = GraphBuilder::new();
+        let scene = graph.transient("scene", 0);
+        let bright = graph.transient("bright", 0);
+        let output = graph.transient("output", 0);
+        graph.pass("composite").read(scene).read(bright).color(output, CLEAR).id();
+        graph.pass("bright").read(scene).color(bright, CLEAR).id();
+        graph.pass("scene").color(scene, CLEAR).id();
+        graph.export(output);
+
+        let graph = graph.compile().unwrap();
+        assert_eq!(names(&graph), vec!["scene", "bright", "composite"]);
+        assert!(graph.culled().is_empty());
+    }
+
+    #[test]
+    fn writers_of_one_resource_keep_their_order() {
+        let mut graph = GraphBuilder::new();
+        let target = graph.transient("target", 0);
+        graph.pass("first").color(target, CLEAR).id();
+        graph.pass("second").color(target, Load::Keep).id();
+        graph.export(target);
+
+        let graph = graph.compile().unwrap();
+        assert_eq!(names(&graph), vec!["first", "second"]);
+        // The second waits for the first and keeps what it drew
+        let transition = graph.steps()[1].transitions[0];
+        assert_eq!(transition.from, Usage::ColorAttachment);
+        assert!(!transition.discard);
+    }
+
+    #[test]
+    fn passes_nothing_uses_are_culled() {
+        let mut graph = GraphBuilder::new();
+        let used = graph.transient("used", 0);
+        let unused = graph.transient("unused", 0);
+        graph.pass("used").color(used, CLEAR).id();
+        let culled = graph.pass("unused").color(unused, CLEAR).id();
+        graph.export(used);
+
+        let graph = graph.compile().unwrap();
+        assert_eq!(names(&graph), vec!["used"]);
+        assert_eq!(graph.culled(), &[culled]);
+        assert_eq!(graph.step_of(culled), None);
+        assert_eq!(graph.slot(unused), None);
+    }
+
+    #[test]
+    fn resources_share_images_once_they_are_done_with() {
+        let mut graph = GraphBuilder::new();
+        let a = graph.transient("a", 0);
+        let b = graph.transient("b", 0);
+        let c = graph.transient("c", 0);
+        let other = graph.transient("other", 1);
+        graph.pass("a").color(a, CLEAR).id();
+        graph.pass("b").read(a).color(b, CLEAR).id();
+        graph.pass("c").read(b).color(c, CLEAR).id();
+        graph.pass("other").read(c).color(other, CLEAR).id();
+        graph.export(other);
+
+        let graph = graph.compile().unwrap();
+        // a is done with by the time c is drawn, but b isn't, since c's pass reads it
+        assert_eq!(graph.slot(a), graph.slot(c));
+        assert!(graph.slot(a) != graph.slot(b));
+        // Different descriptions never share
+        assert!(graph.slot(other) != graph.slot(c) && graph.slot(other) != graph.slot(b));
+        assert_eq!(graph.slots(), &[0, 0, 1]);
+    }
+
+    #[test]
+    fn transitions_follow_each_image_through_the_frame() {
+        let mut graph = GraphBuilder::new();
+        let gbuffer = graph.import("gbuffer");
+        let occlusion = graph.transient("occlusion", 0);
+        let blurred = graph.transient("blurred", 0);
+        graph.pass("ssao").read(gbuffer).color(occlusion, CLEAR).id();
+        graph.pass("blur").read(gbuffer).read(occlusion).color(blurred, CLEAR).id();
+        graph.export(blurred);
+
+        let graph = graph.compile().unwrap();
+        let steps = graph.steps();
+        // Imports are never transitioned, and each first use waits on the frame before
+        let g = Queue::Graphics;
+        assert_eq!(steps[0].transitions, vec![Transition {
+            resource: occlusion,
+            from: Usage::Sampled,
+            to: Usage::ColorAttachment,
+            from_queue: g,
+            to_queue: g,
+            discard: true,
+        }]);
+        assert_eq!(steps[1].transitions, vec![
+            Transition { resource: occlusion, from: Usage::ColorAttachment, to: Usage::Sampled, from_queue: g, to_queue: g, discard: false },
+            Transition { resource: blurred, from: Usage::Sampled, to: Usage::ColorAttachment, from_queue: g, to_queue: g, discard: true },
+        ]);
+        assert_eq!(graph.finish(), &[Transition {
+            resource: blurred,
+            from: Usage::ColorAttachment,
+            to: Usage::Sampled,
+            from_queue: g,
+            to_queue: g,
+            discard: false,
+        }]);
+        // Both are read later, so both are stored
+        assert!(steps[0].attachments[0].store && steps[1].attachments[0].store);
+    }
+
+    #[test]
+    fn compute_passes_run_in_submissions_of_their_own() {
+        let mut graph = GraphBuilder::new();
+        let draws = graph.import("draws");
+        let gbuffer = graph.import("gbuffer");
+        let shadows = graph.import("shadows");
+        let target = graph.import("target");
+        let occlusion = graph.transient("occlusion", 0);
+        let blurred = graph.transient("blurred", 0);
+        graph.pass("cull").on(Queue::Compute).write(draws).id();
+        graph.pass("scene").read(draws).write(gbuffer).id();
+        graph.pass("ssao").on(Queue::Compute).read(gbuffer).storage(occlusion).id();
+        graph.pass("blur").on(Queue::Compute).read(gbuffer).read(occlusion).storage(blurred).id();
+        graph.pass("shadows").read(draws).write(shadows).id();
+        let lighting = graph.pass("lighting").read(gbuffer).read(blurred).read(shadows).write(target).id();
+
+        let graph = graph.compile().unwrap();
+        assert_eq!(names(&graph), vec!["cull", "scene", "ssao", "blur", "shadows", "lighting"]);
+        assert!(graph.culled().is_empty());
+        assert_eq!(graph.pass_queue(lighting), Queue::Graphics);
+        // The shadows don't need anything from the compute queue that the scene hasn't already
+        // waited for, so they run while it works on the occlusion
+        let (g, c) = (Queue::Graphics, Queue::Compute);
+        assert_eq!(graph.submissions(), &[
+            Submission { queue: c, steps: 0..1, wait: None },
+            Submission { queue: g, steps: 1..2, wait: Some(0) },
+            Submission { queue: c, steps: 2..4, wait: Some(1) },
+            Submission { queue: g, steps: 4..5, wait: None },
+            Submission { queue: g, steps: 5..6, wait: Some(2) },
+        ]);
+        assert_eq!(graph.wait_after(), None);
+
+        let steps = graph.steps();
+        assert_eq!(steps[1].transitions, vec![Transition {
+            resource: draws,
+            from: Usage::External,
+            to: Usage::External,
+            from_queue: c,
+            to_queue: g,
+            discard: false,
+        }]);
+        assert_eq!(steps[2].transitions[0], Transition {
+            resource: gbuffer,
+            from: Usage::External,
+            to: Usage::External,
+            from_queue: g,
+            to_queue: c,
+            discard: false,
+        });
+        assert!(steps[2].attachments.is_empty());
+        // The blur waits for nothing new, and the shadows have already been through the
+        // graphics queue's wait for the culling
+        assert!(steps[4].transitions.is_empty());
+        assert_eq!(steps[5].transitions, vec![
+            Transition { resource: gbuffer, from: Usage::External, to: Usage::External, from_queue: g, to_queue: g, discard: false },
+            Transition { resource: blurred, from: Usage::Storage, to: Usage::Sampled, from_queue: c, to_queue: g, discard: false },
+            Transition { resource: shadows, from: Usage::External, to: Usage::External, from_queue: g, to_queue: g, discard: false },
+        ]);
+        assert!(steps[5].transitions[1].is_handoff());
+    }
+
+    #[test]
+    fn compute_work_nothing_waits_for_is_left_to_after_the_graph() {
+        let mut graph = GraphBuilder::<u32>::new();
+        let buffer = graph.import("buffer");
+        let target = graph.import("target");
+        graph.pass("simulate").on(Queue::Compute).write(buffer).id();
+        graph.pass("draw").write(target).id();
+
+        let graph = graph.compile().unwrap();
+        assert_eq!(graph.submissions(), &[
+            Submission { queue: Queue::Compute, steps: 0..1, wait: None },
+            Submission { queue: Queue::Graphics, steps: 1..2, wait: None },
+        ]);
+        assert_eq!(graph.wait_after(), Some(0));
+    }
+
+    #[test]
+    fn images_shared_between_the_queues_wait_for_the_last_use() {
+        let mut graph = GraphBuilder::new();
+        let first = graph.transient("first", 0);
+        let second = graph.transient("second", 1);
+        let third = graph.transient("third", 0);
+        graph.pass("first").on(Queue::Compute).storage(first).id();
+        graph.pass("second").on(Queue::Compute).read(first).storage(second).id();
+        graph.pass("third").color(third, CLEAR).id();
+        graph.export(second);
+        graph.export(third);
+
+        let graph = graph.compile().unwrap();
+        assert_eq!(graph.slot(first), graph.slot(third));
+        // Nothing the third pass reads comes from the compute queue, but the image it draws
+        // into does
+        assert_eq!(graph.submissions()[1], Submission { queue: Queue::Graphics, steps: 2..3, wait: Some(0) });
+        assert!(graph.steps()[2].transitions[0].is_handoff());
+        assert_eq!(graph.wait_after(), None);
+    }
+
+    #[test]
+    fn attachments_nothing_reads_afterwards_are_not_stored() {
+        let mut graph = GraphBuilder::new();
+        let color = graph.transient("color", 0);
+        let depth = graph.transient("depth", 1);
+        graph.pass("scene").color(color, CLEAR).depth(depth, Load::ClearDepth(1.0)).id();
+        graph.export(color);
+
+        let graph = graph.compile().unwrap();
+        let attachments = &graph.steps()[0].attachments;
+        assert_eq!(attachments[1].usage, Usage::DepthAttachment);
+        assert!(attachments[0].store);
+        assert!(!attachments[1].store);
+    }
+
+    #[test]
+    fn mistakes_are_caught() {
+        let mut graph = GraphBuilder::<u32>::new();
+        let imported = graph.import("imported");
+        graph.pass("writer").color(imported, CLEAR).id();
+        match graph.compile() {
+            Err(GraphError::WritesImport { .. }) => {}
+            _ => panic!("expected WritesImport"),
+        }
+
+        let mut graph = GraphBuilder::new();
+        let target = graph.transient("target", 0);
+        graph.pass("untracked").write(target).id();
+        graph.export(target);
+        match graph.compile() {
+            Err(GraphError::UntrackedWrite { .. }) => {}
+            _ => panic!("expected UntrackedWrite"),
+        }
+
+        let mut graph = GraphBuilder::new();
+        let target = graph.transient("target", 0);
+        graph.pass("drawing").on(Queue::Compute).color(target, CLEAR).id();
+        graph.export(target);
+        assert_eq!(
+            graph.compile().err(),
+            Some(GraphError::AttachmentOnCompute { pass: "drawing".to_string(), resource: "target".to_string() }),
+        );
+
+        let mut graph = GraphBuilder::new();
+        let target = graph.transient("target", 0);
+        graph.pass("feedback").read(target).color(target, Load::Keep).id();
+        graph.export(target);
+        match graph.compile() {
+            Err(GraphError::ReadsWhatItWrites { .. }) => {}
+            _ => panic!("expected ReadsWhatItWrites"),
+        }
+
+        let mut graph = GraphBuilder::new();
+        let target = graph.transient("target", 0);
+        graph.pass("keeper").color(target, Load::Keep).id();
+        graph.export(target);
+        assert_eq!(
+            graph.compile().err(),
+            Some(GraphError::KeepsNothing { pass: "keeper".to_string(), resource: "target".to_string() }),
+        );
+
+        let mut graph = GraphBuilder::<u32>::new();
+        let target = graph.transient("target", 0);
+        graph.export(target);
+        assert_eq!(graph.compile().err(), Some(GraphError::Unwritten { resource: "target".to_string() }));
+    }
+
+    #[test]
+    fn loops_are_caught() {
+        let mut graph = GraphBuilder::new();
+        let a = graph.transient("a", 0);
+        let b = graph.transient("b", 0);
+        let out = graph.transient("out", 0);
+        graph.pass("makes a").read(b).color(a, CLEAR).id();
+        graph.pass("makes b").read(a).color(b, CLEAR).id();
+        graph.pass("out").read(a).color(out, CLEAR).id();
+        graph.export(out);
+        match graph.compile() {
+            Err(GraphError::Cycle { passes }) => assert_eq!(passes.len(), 3),
+            _ => panic!("expected a cycle"),
+        }
+    }
+}
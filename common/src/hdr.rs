@@ -0,0 +1,278 @@
+//! The HDR target the scene is drawn into, and what takes it from there to the swapchain.
+//!
+//! Forward shading and deferred lighting both draw into a float image, where light can go past
+//! 1. A last full-screen pass scales that by the exposure and tonemaps it into the swapchain
+//! image. See `exposure` for the why of it, and for auto exposure.
+//!
+//! Auto exposure needs to know how bright the scene is. Reading all of it back would be far too
+//! slow, so a pass first boils it down to `LUMINANCE_SIZE` by `LUMINANCE_SIZE` tiles, each the
+//! average log luminance of its part of the screen. Those are copied into a buffer for each
+//! frame in flight and read back the next time that frame comes around, the same way
+//! `GpuProfiler` reads its timings, so the exposure is a frame or two behind the scene. It's
+//! eased towards its target over a second or so anyway, so that never shows.
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+
+use hal::{
+    buffer, command, format as f, image as i, memory, pso,
+    pso::PipelineStage,
+    Backend, Device, General, SwapImageIndex,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use attachments::AttachmentImages;
+use buffer::DeviceBuffer;
+use context::GfxContext;
+use error::Result;
+use pass::{ create_color_render_pass, create_offscreen_render_pass };
+use resources::{ Framebuffers, SwapchainBundle };
+use samplers::SamplerDesc;
+use texture::COLOR_RANGE;
+
+/// Half floats go up to 65504, which is plenty for sunlight, and take half the memory and
+/// bandwidth of full ones.
+pub const HDR_FORMAT: f::Format = f::Format::Rgba16Float;
+
+/// How many tiles the scene is boiled down to along each side for measuring its luminance. Has
+/// to match `luminance.frag`.
+pub const LUMINANCE_SIZE: u32 = 16;
+
+/// One average log luminance per tile. Logs of luminances can be negative, so these need a
+/// float format.
+const LUMINANCE_FORMAT: f::Format = f::Format::R32Float;
+
+/// The luminance tiles and what's needed to draw them and read them back.
+struct LuminanceTarget<B: Backend> {
+    image: B::Image,
+    view: B::ImageView,
+    framebuffer: B::Framebuffer,
+    allocation: Allocation,
+}
+
+/// An HDR target for each swapchain image, the passes that measure and tonemap it, and the
+/// buffers the measurements are read back from. Like the other per-frame attachments, the
+/// targets have to be recreated along with the swapchain.
+pub struct Hdr<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    color: AttachmentImages<B>,
+    sampler: Rc<B::Sampler>,
+    luminance_pass: Option<B::RenderPass>,
+    luminance: Option<LuminanceTarget<B>>,
+    /// One per frame in flight, along with whether anything's been copied into it yet.
+    readback: Vec<(DeviceBuffer<B>, bool)>,
+    tonemap_pass: Option<B::RenderPass>,
+    tonemap_framebuffers: Option<Framebuffers<B>>,
+}
+
+impl<B: Backend> Hdr<B> {
+    pub fn new(context: &GfxContext<B>, swapchain: &SwapchainBundle<B>, frames_in_flight: usize) -> Result<Self> {
+        let device = context.device.clone();
+        let allocator = context.allocator.clone();
+        let color = AttachmentImages::sampled_color(device.clone(), allocator.clone(), HDR_FORMAT, swapchain)?;
+        // Every pixel is read back from exactly where it was written
+        let sampler = context.samplers.borrow_mut().get(SamplerDesc::new(i::Filter::Nearest, i::WrapMode::Clamp));
+
+        let luminance_pass = create_offscreen_render_pass::<B>(&device, LUMINANCE_FORMAT);
+        let unbound = device.create_image(
+            i::Kind::D2(LUMINANCE_SIZE, LUMINANCE_SIZE, 1, 1),
+            1,
+            LUMINANCE_FORMAT,
+            i::Tiling::Optimal,
+            // Sampled as well, to be allowed in the layout the render pass leaves it in
+            i::Usage::COLOR_ATTACHMENT | i::Usage::SAMPLED | i::Usage::TRANSFER_SRC,
+            i::ViewCapabilities::empty(),
+        )?;
+        let requirements = device.get_image_requirements(&unbound);
+        let (image, allocation) = {
+            let mut allocator = allocator.borrow_mut();
+            let allocation = allocator
+                .allocate(
+                    &requirements,
+                    memory::Properties::DEVICE_LOCAL,
+                    ResourceKind::Optimal,
+                    MemoryCategory::Attachments,
+                )?;
+            let image = device.bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+            (image, allocation)
+        };
+        let view = device.create_image_view(&image, i::ViewKind::D2, LUMINANCE_FORMAT, f::Swizzle::NO, COLOR_RANGE.clone())?;
+        let framebuffer = device.create_framebuffer(
+            &luminance_pass,
+            Some(&view),
+            i::Extent { width: LUMINANCE_SIZE, height: LUMINANCE_SIZE, depth: 1 },
+        )?;
+
+        let tile_bytes = (LUMINANCE_SIZE * LUMINANCE_SIZE) as u64 * mem::size_of::<f32>() as u64;
+        let mut readback = Vec::with_capacity(frames_in_flight);
+        for _ in 0..frames_in_flight {
+            let buffer = DeviceBuffer::new(
+                device.clone(),
+                allocator.clone(),
+                tile_bytes,
+                buffer::Usage::TRANSFER_DST,
+                memory::Properties::CPU_VISIBLE,
+                MemoryCategory::Transient,
+            )?;
+            readback.push((buffer, false));
+        }
+
+        let tonemap_pass = create_color_render_pass::<B>(&device, swapchain.format());
+        let tonemap_framebuffers = Framebuffers::new(device.clone(), &tonemap_pass, swapchain)?;
+
+        Ok(Hdr {
+            device,
+            allocator,
+            color,
+            sampler,
+            luminance_pass: Some(luminance_pass),
+            luminance: Some(LuminanceTarget { image, view, framebuffer, allocation }),
+            readback,
+            tonemap_pass: Some(tonemap_pass),
+            tonemap_framebuffers: Some(tonemap_framebuffers),
+        })
+    }
+
+    /// Rebuilds the HDR targets and the tonemapping framebuffers to match `swapchain`'s size and
+    /// image count. The luminance tiles stay the same size whatever the swapchain's is.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.color.recreate(swapchain)?;
+        let tonemap_pass = self.tonemap_pass.as_ref().unwrap();
+        self.tonemap_framebuffers.as_mut().unwrap().recreate(tonemap_pass, swapchain)?;
+        Ok(())
+    }
+
+    /// The HDR targets, for attaching to the framebuffers of the passes that draw the scene.
+    pub fn color(&self) -> &AttachmentImages<B> {
+        &self.color
+    }
+
+    /// The HDR target for swapchain image `index`, for sampling once it's been drawn.
+    pub fn view(&self, index: usize) -> &B::ImageView {
+        self.color.view(index)
+    }
+
+    pub fn sampler(&self) -> &B::Sampler {
+        &self.sampler
+    }
+
+    /// Draws the luminance tiles, with a viewport from `luminance_viewport`.
+    pub fn luminance_pass(&self) -> &B::RenderPass {
+        self.luminance_pass.as_ref().unwrap()
+    }
+
+    pub fn luminance_framebuffer(&self) -> &B::Framebuffer {
+        &self.luminance.as_ref().unwrap().framebuffer
+    }
+
+    /// A viewport covering all of the luminance tiles.
+    pub fn luminance_viewport(&self) -> pso::Viewport {
+        let size = LUMINANCE_SIZE as i16;
+        pso::Viewport {
+            rect: pso::Rect { x: 0, y: 0, w: size, h: size },
+            depth: 0.0..1.0,
+        }
+    }
+
+    /// Copies the luminance tiles into frame `frame_index`'s readback buffer. Record this after
+    /// the luminance pass.
+    pub fn copy_luminance(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        frame_index: usize,
+    ) {
+        let image = &self.luminance.as_ref().unwrap().image;
+        let readback = &mut self.readback[frame_index];
+        command_buffer.pipeline_barrier(
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (i::Access::COLOR_ATTACHMENT_WRITE, i::Layout::ShaderReadOnlyOptimal)
+                    ..(i::Access::TRANSFER_READ, i::Layout::TransferSrcOptimal),
+                target: image,
+                range: COLOR_RANGE.clone(),
+            }],
+        );
+
+        command_buffer.copy_image_to_buffer(
+            image,
+            i::Layout::TransferSrcOptimal,
+            readback.0.buffer(),
+            &[command::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_width: LUMINANCE_SIZE,
+                buffer_height: LUMINANCE_SIZE,
+                image_layers: i::SubresourceLayers {
+                    aspects: f::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: i::Offset { x: 0, y: 0, z: 0 },
+                image_extent: i::Extent { width: LUMINANCE_SIZE, height: LUMINANCE_SIZE, depth: 1 },
+            }],
+        );
+
+        // Back to the layout the render pass left it in, and the next frame's luminance pass
+        // waits on the copy through its dependency on fragment shader reads
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (i::Access::TRANSFER_READ, i::Layout::TransferSrcOptimal)
+                    ..(i::Access::SHADER_READ, i::Layout::ShaderReadOnlyOptimal),
+                target: image,
+                range: COLOR_RANGE.clone(),
+            }],
+        );
+
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::HOST,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Buffer {
+                states: buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                target: readback.0.buffer(),
+            }],
+        );
+        readback.1 = true;
+    }
+
+    /// The average log luminance of each tile, from the last time frame `frame_index` copied
+    /// them, or `None` if it never has. Call this after `FrameSync::begin_frame` has waited for
+    /// the frame's fence.
+    pub fn read_luminance(&self, frame_index: usize) -> Result<Option<Vec<f32>>> {
+        let &(ref buffer, copied) = &self.readback[frame_index];
+        if !copied {
+            return Ok(None);
+        }
+        Ok(Some(buffer.read::<f32>()?))
+    }
+
+    /// Draws into the swapchain image, which it leaves ready to present.
+    pub fn tonemap_pass(&self) -> &B::RenderPass {
+        self.tonemap_pass.as_ref().unwrap()
+    }
+
+    pub fn tonemap_framebuffer(&self, image_index: SwapImageIndex) -> &B::Framebuffer {
+        self.tonemap_framebuffers.as_ref().unwrap().get(image_index)
+    }
+}
+
+impl<B: Backend> Drop for Hdr<B> {
+    fn drop(&mut self) {
+        self.tonemap_framebuffers.take();
+        if let Some(render_pass) = self.tonemap_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+        if let Some(luminance) = self.luminance.take() {
+            self.device.destroy_framebuffer(luminance.framebuffer);
+            self.device.destroy_image_view(luminance.view);
+            self.device.destroy_image(luminance.image);
+            self.allocator.borrow_mut().free(luminance.allocation);
+        }
+        if let Some(render_pass) = self.luminance_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
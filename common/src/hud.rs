@@ -0,0 +1,552 @@
+//! A heads up display drawn over the finished frame, in screen space: a crosshair in the middle,
+//! and a hotbar along the bottom showing the blocks that can be placed, with the one that's
+//! picked picked out.
+//!
+//! Everything on it is a `HudQuad`, either a flat colour or part of an image, laid out in
+//! logical pixels each frame. The block atlas is always there to show tiles from, and other
+//! images, like the minimap's, can be added next to it. Quads showing the same image one after
+//! another are drawn together. It's drawn in a render pass of its own like the overlay's
+//! and the text's, before the text so labels go on top of it.
+
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+use std::slice;
+
+use hal::{
+    buffer, command, format as f, image as i, pass, pso,
+    Backend, Device, General, Primitive, SwapImageIndex,
+};
+
+use atlas::UvRect;
+use color::is_srgb;
+use context::GfxContext;
+use descriptors::{ DescriptorAllocator, DescriptorSetLayout };
+use error::Result;
+use pass::create_overlay_render_pass;
+use resources::{ Framebuffers, SwapchainBundle };
+use samplers::SamplerDesc;
+use shader::create_shader_module;
+use staging::StagingRing;
+use texture::Texture;
+
+/// The SPIR-V for the shaders in `common/shaders/`, compiled by `build.rs`
+#[allow(dead_code)]
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// How big each slot of the hotbar is, how far apart they are, and how far the bar is from the
+/// bottom of the window, in logical pixels.
+pub const SLOT_SIZE: f32 = 40.0;
+const SLOT_GAP: f32 = 4.0;
+const HOTBAR_MARGIN: f32 = 12.0;
+/// How far in from the edge of its slot a block's icon is, and how thick the frame around the
+/// picked slot is.
+const ICON_INSET: f32 = 6.0;
+const FRAME_WIDTH: f32 = 2.0;
+
+/// How long each arm of the crosshair is from the middle, and how thick it is.
+const CROSSHAIR_LENGTH: f32 = 8.0;
+const CROSSHAIR_WIDTH: f32 = 2.0;
+
+/// How big the minimap is on the screen and how far it is from the top right corner, and how
+/// big the marker for the camera on it is, in logical pixels.
+const MINIMAP_SIZE: f32 = 160.0;
+const MINIMAP_MARGIN: f32 = 12.0;
+const MARKER_SIZE: f32 = 6.0;
+
+const SLOT_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 0.45];
+const FRAME_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.9];
+const CROSSHAIR_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.8];
+const MARKER_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+
+/// How big the ring the quads' vertices go in starts out, in bytes. It grows if a frame's don't
+/// fit.
+const HUD_RING_SIZE: u64 = 64 * 1024;
+
+/// One of the images a `Hud` can show, from `Hud::add_image`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HudImage(usize);
+
+impl HudImage {
+    /// The block atlas, which every `Hud` starts with.
+    pub const ATLAS: HudImage = HudImage(0);
+}
+
+/// A rectangle on the screen, in logical pixels from the top left of the window.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HudQuad {
+    pub min: [f32; 2],
+    pub max: [f32; 2],
+    /// The part of `image` it shows, or `None` for a flat colour.
+    pub uv: Option<UvRect>,
+    pub image: HudImage,
+    /// sRGB, with alpha. Multiplies the image, if there is one.
+    pub color: [f32; 4],
+}
+
+impl HudQuad {
+    pub fn solid(min: [f32; 2], max: [f32; 2], color: [f32; 4]) -> Self {
+        HudQuad { min, max, uv: None, image: HudImage::ATLAS, color }
+    }
+}
+
+/// The crosshair, at the middle of a window `size` across: a bar across, and one arm above it
+/// and one below, so where they cross isn't blended twice.
+pub fn crosshair(size: [f32; 2]) -> [HudQuad; 3] {
+    let middle = [size[0] * 0.5, size[1] * 0.5];
+    let half_width = CROSSHAIR_WIDTH * 0.5;
+    let (left, right) = (middle[0] - half_width, middle[0] + half_width);
+    let (top, bottom) = (middle[1] - half_width, middle[1] + half_width);
+    [
+        HudQuad::solid(
+            [middle[0] - CROSSHAIR_LENGTH, top],
+            [middle[0] + CROSSHAIR_LENGTH, bottom],
+            CROSSHAIR_COLOR,
+        ),
+        HudQuad::solid([left, middle[1] - CROSSHAIR_LENGTH], [right, top], CROSSHAIR_COLOR),
+        HudQuad::solid([left, bottom], [right, middle[1] + CROSSHAIR_LENGTH], CROSSHAIR_COLOR),
+    ]
+}
+
+/// Where the top edge of the hotbar is, in a window `height` tall, for putting things just
+/// above it.
+pub fn hotbar_top(height: f32) -> f32 {
+    height - HOTBAR_MARGIN - SLOT_SIZE
+}
+
+/// The hotbar along the bottom middle of a window `size` across, with a slot showing each of
+/// `icons` and a frame around slot `selected`.
+pub fn hotbar(size: [f32; 2], icons: &[UvRect], selected: usize) -> Vec<HudQuad> {
+    let count = icons.len() as f32;
+    let width = count * SLOT_SIZE + (count - 1.0).max(0.0) * SLOT_GAP;
+    let left = (size[0] - width) * 0.5;
+    let top = hotbar_top(size[1]);
+
+    let mut quads = Vec::with_capacity(icons.len() * 2 + 4);
+    for (slot, &uv) in icons.iter().enumerate() {
+        let x = left + slot as f32 * (SLOT_SIZE + SLOT_GAP);
+        let (min, max) = ([x, top], [x + SLOT_SIZE, top + SLOT_SIZE]);
+        quads.push(HudQuad::solid(min, max, SLOT_COLOR));
+        if slot == selected {
+            quads.extend_from_slice(&frame(min, max));
+        }
+        quads.push(HudQuad {
+            min: [min[0] + ICON_INSET, min[1] + ICON_INSET],
+            max: [max[0] - ICON_INSET, max[1] - ICON_INSET],
+            uv: Some(uv),
+            image: HudImage::ATLAS,
+            color: [1.0; 4],
+        });
+    }
+    quads
+}
+
+/// The minimap in the top right corner of a window `size` across, showing the whole of `image`
+/// in a frame, with a marker where the camera is. `camera` is where that is on the map, from -1
+/// to 1 across it and down it, and `heading` which way the camera is facing on it, along the
+/// same axes, which the marker points.
+pub fn minimap_panel(size: [f32; 2], image: HudImage, camera: [f32; 2], heading: [f32; 2]) -> Vec<HudQuad> {
+    let min = [size[0] - MINIMAP_MARGIN - MINIMAP_SIZE, MINIMAP_MARGIN];
+    let max = [size[0] - MINIMAP_MARGIN, MINIMAP_MARGIN + MINIMAP_SIZE];
+    let mut quads = Vec::with_capacity(7);
+    quads.extend_from_slice(&frame(
+        [min[0] - FRAME_WIDTH, min[1] - FRAME_WIDTH],
+        [max[0] + FRAME_WIDTH, max[1] + FRAME_WIDTH],
+    ));
+    quads.push(HudQuad {
+        min,
+        max,
+        uv: Some(UvRect { u0: 0.0, v0: 0.0, u1: 1.0, v1: 1.0 }),
+        image,
+        color: [1.0; 4],
+    });
+
+    // A square where the camera is, and a smaller one in front of it
+    let clamp = |value: f32| value.max(-1.0).min(1.0);
+    let middle = [
+        min[0] + (clamp(camera[0]) * 0.5 + 0.5) * MINIMAP_SIZE,
+        min[1] + (clamp(camera[1]) * 0.5 + 0.5) * MINIMAP_SIZE,
+    ];
+    let square = |center: [f32; 2], half_size: f32| {
+        HudQuad::solid(
+            [center[0] - half_size, center[1] - half_size],
+            [center[0] + half_size, center[1] + half_size],
+            MARKER_COLOR,
+        )
+    };
+    quads.push(square(middle, MARKER_SIZE * 0.5));
+    let ahead = [middle[0] + heading[0] * MARKER_SIZE, middle[1] + heading[1] * MARKER_SIZE];
+    quads.push(square(ahead, MARKER_SIZE * 0.25));
+    quads
+}
+
+/// Four thin quads around the inside edge of the rectangle from `min` to `max`.
+fn frame(min: [f32; 2], max: [f32; 2]) -> [HudQuad; 4] {
+    let width = FRAME_WIDTH;
+    [
+        HudQuad::solid(min, [max[0], min[1] + width], FRAME_COLOR),
+        HudQuad::solid([min[0], max[1] - width], max, FRAME_COLOR),
+        HudQuad::solid([min[0], min[1] + width], [min[0] + width, max[1] - width], FRAME_COLOR),
+        HudQuad::solid([max[0] - width, min[1] + width], [max[0], max[1] - width], FRAME_COLOR),
+    ]
+}
+
+/// One corner of a quad.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct HudVertex {
+    /// In logical pixels from the top left of the window.
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+    /// 1 to sample the atlas, 0 for a flat colour.
+    textured: f32,
+}
+
+#[repr(C)]
+struct PushConstants {
+    scale: [f32; 2],
+    translate: [f32; 2],
+    srgb_target: f32,
+}
+
+impl PushConstants {
+    /// Push constants are written as a slice of 32 bit words.
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const PushConstants as *const u32,
+                mem::size_of::<PushConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `PushConstants`, in 32 bit words
+const PUSH_CONSTANTS_SIZE: u32 = (mem::size_of::<PushConstants>() / mem::size_of::<u32>()) as u32;
+
+pub struct Hud<B: Backend> {
+    device: Rc<B::Device>,
+    quads: Vec<HudQuad>,
+    visible: bool,
+    /// One of each for every `HudImage`, in order.
+    samplers: Vec<Rc<B::Sampler>>,
+    set_layout: Rc<DescriptorSetLayout<B>>,
+    descriptors: DescriptorAllocator<B>,
+    descriptor_sets: Vec<B::DescriptorSet>,
+    render_pass: Option<B::RenderPass>,
+    pipeline_layout: Option<B::PipelineLayout>,
+    pipeline: Option<B::GraphicsPipeline>,
+    framebuffers: Framebuffers<B>,
+    /// Each frame's vertices.
+    vertices: StagingRing<B>,
+    frames_in_flight: usize,
+}
+
+impl<B: Backend> Hud<B> {
+    /// Builds the pipeline for drawing into `swapchain`, showing tiles from `atlas`, with
+    /// `frames_in_flight` frames drawing at once.
+    pub fn new(
+        context: &mut GfxContext<B>,
+        swapchain: &SwapchainBundle<B>,
+        frames_in_flight: usize,
+        atlas: &Texture<B>,
+    ) -> Result<Self> {
+        let device = context.device.clone();
+        let set_layout = Rc::new(DescriptorSetLayout::new(
+            device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let descriptors = DescriptorAllocator::new(device.clone(), set_layout.clone());
+
+        let render_pass = create_overlay_render_pass::<B>(&device, swapchain.format());
+        let pipeline_layout = device.create_pipeline_layout(
+            Some(set_layout.raw()),
+            &[(pso::ShaderStageFlags::VERTEX, 0..PUSH_CONSTANTS_SIZE)],
+        );
+        let pipeline = create_pipeline::<B>(
+            &device,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let framebuffers = Framebuffers::new(device.clone(), &render_pass, swapchain)?;
+        let vertices = StagingRing::new(
+            device.clone(),
+            context.allocator.clone(),
+            HUD_RING_SIZE,
+            buffer::Usage::VERTEX,
+        )?;
+
+        let mut hud = Hud {
+            device,
+            quads: Vec::new(),
+            // Like the overlay, headless runs leave it out of their reference images
+            visible: !context.is_headless(),
+            samplers: Vec::new(),
+            set_layout,
+            descriptors,
+            descriptor_sets: Vec::new(),
+            render_pass: Some(render_pass),
+            pipeline_layout: Some(pipeline_layout),
+            pipeline: Some(pipeline),
+            framebuffers,
+            vertices,
+            frames_in_flight,
+        };
+        // Without filtering, so the atlas's tiles stay crisp scaled up into the slots
+        hud.add_image(context, atlas.view(), i::Filter::Nearest)?;
+        Ok(hud)
+    }
+
+    /// Adds `view` to the images quads can show, sampled with `filter`. It has to be in
+    /// `ShaderReadOnlyOptimal` whenever the HUD is drawn, and outlive the `Hud`.
+    pub fn add_image(&mut self, context: &GfxContext<B>, view: &B::ImageView, filter: i::Filter) -> Result<HudImage> {
+        let sampler = context.samplers.borrow_mut().get(SamplerDesc::new(filter, i::WrapMode::Clamp));
+        let descriptor_set = self.descriptors.allocate()?;
+        self.device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set: &descriptor_set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(view, i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &descriptor_set,
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(&*sampler)),
+            },
+        ]);
+        self.samplers.push(sampler);
+        self.descriptor_sets.push(descriptor_set);
+        Ok(HudImage(self.descriptor_sets.len() - 1))
+    }
+
+    /// Rebuilds the framebuffers after `swapchain` has been recreated.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.framebuffers.recreate(self.render_pass.as_ref().unwrap(), swapchain)
+    }
+
+    /// Queues `quads` to be drawn this frame, over the ones queued before them.
+    pub fn queue(&mut self, quads: &[HudQuad]) {
+        self.quads.extend_from_slice(quads);
+    }
+
+    /// Records a render pass drawing everything queued over swapchain image `image_index`. Call
+    /// this once a frame. The queue is empty again after.
+    pub fn draw(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        image_index: SwapImageIndex,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<()> {
+        self.vertices.begin_frame(self.frames_in_flight);
+        let quads = mem::replace(&mut self.quads, Vec::new());
+        if !self.visible || quads.is_empty() {
+            return Ok(());
+        }
+
+        let mut vertices = Vec::with_capacity(quads.len() * 6);
+        let mut runs = Vec::new();
+        for quad in &quads {
+            let start = vertices.len() as u32;
+            let (uv, textured) = match quad.uv {
+                Some(uv) => (uv, 1.0),
+                None => (UvRect { u0: 0.0, v0: 0.0, u1: 0.0, v1: 0.0 }, 0.0),
+            };
+            let corner = |x: f32, y: f32, u: f32, v: f32| HudVertex {
+                position: [x, y],
+                uv: [u, v],
+                color: quad.color,
+                textured,
+            };
+            let top_left = corner(quad.min[0], quad.min[1], uv.u0, uv.v0);
+            let top_right = corner(quad.max[0], quad.min[1], uv.u1, uv.v0);
+            let bottom_left = corner(quad.min[0], quad.max[1], uv.u0, uv.v1);
+            let bottom_right = corner(quad.max[0], quad.max[1], uv.u1, uv.v1);
+            vertices.extend_from_slice(&[top_left, top_right, bottom_right, bottom_right, bottom_left, top_left]);
+            add_to_runs(&mut runs, quad.image, start..vertices.len() as u32);
+        }
+
+        let vertex_offset = self.vertices.write(&vertices)?;
+
+        let pipeline_layout = self.pipeline_layout.as_ref().unwrap();
+        let viewport = swapchain.viewport();
+        // Quads are laid out in logical pixels, so they're the same size whatever the hidpi factor
+        let logical_size = swapchain.logical_size();
+        let push_constants = PushConstants {
+            scale: [2.0 / logical_size.width as f32, 2.0 / logical_size.height as f32],
+            translate: [-1.0, -1.0],
+            srgb_target: if is_srgb(swapchain.format()) { 1.0 } else { 0.0 },
+        };
+
+        command_buffer.set_viewports(0, &[viewport.clone()]);
+        command_buffer.set_scissors(0, &[viewport.rect]);
+        command_buffer.bind_graphics_pipeline(self.pipeline.as_ref().unwrap());
+        command_buffer.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(self.vertices.buffer(), vertex_offset)]));
+
+        let mut encoder = command_buffer.begin_render_pass_inline(
+            self.render_pass.as_ref().unwrap(),
+            self.framebuffers.get(image_index),
+            viewport.rect,
+            &[],
+        );
+        encoder.push_graphics_constants(
+            pipeline_layout,
+            pso::ShaderStageFlags::VERTEX,
+            0,
+            push_constants.as_words(),
+        );
+        for (image, vertices) in runs {
+            encoder.bind_graphics_descriptor_sets(pipeline_layout, 0, Some(&self.descriptor_sets[image.0]), &[]);
+            encoder.draw(vertices, 0..1);
+        }
+        Ok(())
+    }
+}
+
+/// Adds the quad with `vertices`, showing `image`, onto the end of `runs`, which are the images
+/// to draw and the vertices to draw with each, in order.
+fn add_to_runs(runs: &mut Vec<(HudImage, Range<u32>)>, image: HudImage, vertices: Range<u32>) {
+    if let Some(&mut (last, ref mut last_vertices)) = runs.last_mut() {
+        if last == image {
+            last_vertices.end = vertices.end;
+            return;
+        }
+    }
+    runs.push((image, vertices));
+}
+
+impl<B: Backend> Drop for Hud<B> {
+    fn drop(&mut self) {
+        if let Some(pipeline) = self.pipeline.take() {
+            self.device.destroy_graphics_pipeline(pipeline);
+        }
+        if let Some(pipeline_layout) = self.pipeline_layout.take() {
+            self.device.destroy_pipeline_layout(pipeline_layout);
+        }
+        if let Some(render_pass) = self.render_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
+
+fn create_pipeline<B: Backend>(
+    device: &B::Device,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders::HUD_VERT)?;
+    let fs_module = create_shader_module::<B>(device, shaders::HUD_FRAG)?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(
+            pso::ColorMask::ALL,
+            pso::BlendState::ALPHA,
+        ));
+
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<HudVertex>() as u32,
+            rate: 0,
+        });
+        for (location, &(format, offset)) in [
+            (f::Format::Rg32Float, 0),
+            (f::Format::Rg32Float, 8),
+            (f::Format::Rgba32Float, 16),
+            (f::Format::R32Float, 32),
+        ].iter().enumerate() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location: location as u32,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the HUD pipeline");
+    Ok(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UV: UvRect = UvRect { u0: 0.0, v0: 0.0, u1: 1.0, v1: 1.0 };
+
+    #[test]
+    fn the_hotbar_is_centred_along_the_bottom() {
+        let quads = hotbar([800.0, 600.0], &[UV; 3], 1);
+        // A slot and an icon for each block, and a frame around the picked one
+        assert_eq!(quads.len(), 3 * 2 + 4);
+        let left = quads.iter().map(|quad| quad.min[0]).fold(f32::INFINITY, f32::min);
+        let right = quads.iter().map(|quad| quad.max[0]).fold(0.0, f32::max);
+        assert_eq!(left + right, 800.0);
+        assert!(quads.iter().all(|quad| quad.max[1] <= 600.0 - HOTBAR_MARGIN));
+        assert_eq!(quads.iter().filter(|quad| quad.uv.is_some()).count(), 3);
+    }
+
+    #[test]
+    fn quads_showing_the_same_image_are_drawn_together() {
+        let minimap = HudImage(1);
+        let mut runs = Vec::new();
+        add_to_runs(&mut runs, HudImage::ATLAS, 0..6);
+        add_to_runs(&mut runs, HudImage::ATLAS, 6..12);
+        add_to_runs(&mut runs, minimap, 12..18);
+        add_to_runs(&mut runs, HudImage::ATLAS, 18..24);
+        assert_eq!(runs, vec![(HudImage::ATLAS, 0..12), (minimap, 12..18), (HudImage::ATLAS, 18..24)]);
+    }
+}
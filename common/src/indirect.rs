@@ -0,0 +1,699 @@
+//! Drawing every chunk with a few indirect draws, culled on the gpu.
+//!
+//! All of the chunk meshes live in one vertex buffer and one index buffer, each in ranges of
+//! them handed out by a `RangeAllocator`, and each chunk has a slot in a buffer of
+//! `ChunkRecord`s saying where its mesh is and what space it takes up. Every frame a compute
+//! shader, `cull.comp`, tests each chunk against the camera's frustum, each shadow cascade's,
+//! the minimap's, the environment probe's and the planar reflection's, and writes a
+//! `DrawIndexedCommand` for the ones that pass into that view's lists. Each list is then drawn with one `draw_indexed_indirect`,
+//! so recording a frame costs the same however many chunks there are.
+//!
+//! The opaque, shadow, minimap, probe and reflection lists are compacted, with the draws that passed at the
+//! front and empty ones after. The translucent and water lists keep the order the chunks were given in,
+//! furthest first for blending, with empty draws where the culled chunks would have been.
+//!
+//! Each draw's first instance is its chunk's slot, and the chunk pipelines read the chunk's
+//! origin out of the records as a per instance attribute, so nothing is pushed per chunk. That
+//! needs `DRAW_INDIRECT_FIRST_INSTANCE`. Without `MULTI_DRAW_INDIRECT` the lists are drawn one
+//! indirect draw at a time instead, which still leaves the culling to the gpu.
+//!
+//! How many chunks were drawn and how many triangles that came to are read back the next time
+//! a frame comes round, like `Hdr` reads its luminance, so the overlay's numbers are a frame or
+//! two behind.
+
+use std::cell::RefCell;
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+use std::slice;
+
+use hal::{
+    buffer, command, memory, pso,
+    pso::PipelineStage,
+    queue::Supports,
+    Backend, Compute, Device, Features, IndexType, PhysicalDevice, Transfer,
+};
+
+use allocator::MemoryCategory;
+use buffer::{ queue_upload_into, DeviceBuffer };
+use context::GfxContext;
+use culling::CullStats;
+use descriptors::{ DescriptorAllocator, DescriptorSetLayout };
+use error::{ RendererError, Result };
+use math::{ Aabb, Frustum };
+use mesher::{ ChunkMesh, ChunkVertex };
+use ranges::RangeAllocator;
+use shadow::CASCADE_COUNT;
+
+/// How many chunks there's room for at first. The records and draw lists double whenever they
+/// run out.
+const INITIAL_SLOTS: u64 = 4096;
+/// How many vertices and indices there's room for at first. Both double whenever they run out.
+const INITIAL_VERTICES: u64 = 1 << 20;
+const INITIAL_INDICES: u64 = 1 << 21;
+
+/// How many chunks `cull.comp` tests in each workgroup. Has to match its `local_size_x`.
+const CULL_GROUP_SIZE: u32 = 64;
+
+/// The arguments of one indexed draw, laid out the way `draw_indexed_indirect` reads them. Has
+/// to match `DrawCommand` in `cull.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawIndexedCommand {
+    pub index_count: u32,
+    pub instance_count: u32,
+    pub first_index: u32,
+    pub vertex_offset: i32,
+    pub first_instance: u32,
+}
+
+/// Where a chunk's mesh is and what space it takes up. Has to match `Chunk` in `cull.comp`,
+/// which is laid out by std430 rules. Those put each `vec3` on a 16 byte boundary and round the
+/// struct up to a multiple of 16, hence the padding. The chunk pipelines read `origin` from
+/// here too, as a per instance attribute.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ChunkRecord {
+    pub origin: [f32; 3],
+    /// Where the chunk's vertices start in the vertex buffer.
+    pub vertex_offset: i32,
+    pub bounds_min: [f32; 3],
+    /// Where the chunk's indices start in the index buffer: the opaque faces', followed by the
+    /// translucent faces' and then the water's.
+    pub first_index: u32,
+    pub bounds_max: [f32; 3],
+    pub opaque_count: u32,
+    pub translucent_count: u32,
+    pub water_count: u32,
+    pub _padding: [u32; 2],
+}
+
+/// One of the lists of draws `cull.comp` writes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DrawList {
+    /// The opaque faces of the chunks the camera can see.
+    Opaque,
+    /// Their translucent faces, furthest chunk first.
+    Translucent,
+    /// Their water, furthest chunk first.
+    Water,
+    /// The opaque and translucent faces of the chunks in a shadow cascade, which both cast
+    /// shadows.
+    Shadow(usize),
+    /// Every face of the chunks under the minimap, water and all.
+    Minimap,
+    /// Every face of the chunks around the environment probe, for all six of its faces.
+    Probe,
+    /// The opaque and translucent faces of the chunks the mirrored camera of the planar
+    /// reflection can see. The water doesn't reflect itself.
+    Reflection,
+}
+
+/// How many lists there are: the camera's three, one for each cascade, the minimap's, the
+/// probe's and the reflection's.
+pub const DRAW_LIST_COUNT: usize = 6 + CASCADE_COUNT;
+
+/// Which view `cull.comp` is culling for when it's culling for the minimap, after the camera
+/// and the cascades.
+const MINIMAP_VIEW: u32 = 1 + CASCADE_COUNT as u32;
+
+/// And when it's culling for the environment probe and the planar reflection, after that.
+const PROBE_VIEW: u32 = MINIMAP_VIEW + 1;
+const REFLECTION_VIEW: u32 = PROBE_VIEW + 1;
+
+impl DrawList {
+    /// Where the list is among the others, which is also which of `cull.comp`'s counters it
+    /// uses.
+    pub fn index(self) -> usize {
+        match self {
+            DrawList::Opaque => 0,
+            DrawList::Translucent => 1,
+            DrawList::Water => 2,
+            DrawList::Shadow(cascade) => 3 + cascade,
+            DrawList::Minimap => 3 + CASCADE_COUNT,
+            DrawList::Probe => 4 + CASCADE_COUNT,
+            DrawList::Reflection => 5 + CASCADE_COUNT,
+        }
+    }
+}
+
+/// Has to match the `Counters` block in `cull.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct CullCounters {
+    /// How many draws made it into each compacted list so far, for the shader to count where
+    /// the next one goes. Nothing reads them back.
+    _draws: [u32; DRAW_LIST_COUNT],
+    /// How many chunks the camera can see.
+    visible: u32,
+    /// How many indices those have between them, in every list.
+    indices: u32,
+}
+
+/// Has to match the `PushConstants` block in `cull.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CullConstants {
+    /// The view's frustum planes, as `Frustum` has them, with the distance in `w`.
+    planes: [[f32; 4]; 6],
+    /// How many chunks there are to test.
+    count: u32,
+    /// 0 for the camera, 1 more than the cascade, `MINIMAP_VIEW`, `PROBE_VIEW` or
+    /// `REFLECTION_VIEW`.
+    view: u32,
+    /// How many draws each list has room for.
+    capacity: u32,
+}
+
+impl CullConstants {
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const CullConstants as *const u32,
+                mem::size_of::<CullConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// What's handed out of the buffers. It's shared with every `ChunkAllocation`, which gives its
+/// part back when it's dropped.
+struct ArenaRanges {
+    vertices: RangeAllocator,
+    indices: RangeAllocator,
+    slots: RangeAllocator,
+}
+
+/// Where one chunk's mesh is in a `ChunkDraws`. Its ranges and slot go back to be used again
+/// when it's dropped, so like a buffer it has to be kept until no frame in flight can be
+/// drawing it, with `RetiredResources`.
+pub struct ChunkAllocation {
+    ranges: Rc<RefCell<ArenaRanges>>,
+    vertices: Range<u64>,
+    indices: Range<u64>,
+    slot: u32,
+}
+
+impl ChunkAllocation {
+    /// Which record is the chunk's, and what first instance its draws have.
+    pub fn slot(&self) -> u32 {
+        self.slot
+    }
+}
+
+impl Drop for ChunkAllocation {
+    fn drop(&mut self) {
+        let mut ranges = self.ranges.borrow_mut();
+        ranges.vertices.free(self.vertices.clone());
+        if self.indices.start < self.indices.end {
+            ranges.indices.free(self.indices.clone());
+        }
+        ranges.slots.free(self.slot as u64..self.slot as u64 + 1);
+    }
+}
+
+/// What each frame in flight culls into.
+struct FrameDraws<B: Backend> {
+    /// The slots of the chunks to test, in the order the translucent and water lists keep.
+    order: DeviceBuffer<B>,
+    /// Every list, one after another, each with room for a draw per slot.
+    draws: DeviceBuffer<B>,
+    counters: DeviceBuffer<B>,
+    set: B::DescriptorSet,
+    /// How many chunks the last `cull` tested, which is how many draws each list is drawn with.
+    /// `None` until this frame has been culled into.
+    count: Option<u32>,
+}
+
+/// The chunk meshes, their records, and the draw lists culled from them. See the module docs.
+pub struct ChunkDraws<B: Backend> {
+    device: Rc<B::Device>,
+    ranges: Rc<RefCell<ArenaRanges>>,
+    vertices: DeviceBuffer<B>,
+    indices: DeviceBuffer<B>,
+    /// A copy of every slot's record, for filling a bigger buffer when there are more chunks
+    /// than slots.
+    records: Vec<ChunkRecord>,
+    record_buffer: DeviceBuffer<B>,
+    frames: Vec<FrameDraws<B>>,
+    set_layout: Rc<DescriptorSetLayout<B>>,
+    descriptors: DescriptorAllocator<B>,
+    pipeline_layout: Option<B::PipelineLayout>,
+    multi_draw: bool,
+}
+
+impl<B: Backend> ChunkDraws<B> {
+    pub fn new(context: &mut GfxContext<B>, frames_in_flight: usize) -> Result<Self> {
+        let features = context.adapter.physical_device.features();
+        if !features.contains(Features::DRAW_INDIRECT_FIRST_INSTANCE) {
+            return Err(RendererError::UnsupportedFeature {
+                feature: "drawIndirectFirstInstance",
+                needed_for: "drawing chunks indirectly",
+            });
+        }
+        let multi_draw = features.contains(Features::MULTI_DRAW_INDIRECT);
+        if !multi_draw {
+            warn!("The adapter can't draw more than one indirect draw at a time, so each chunk is drawn on its own");
+        }
+
+        // The records, the order, the draws and the counters
+        let set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            (0..4)
+                .map(|binding| pso::DescriptorSetLayoutBinding {
+                    binding,
+                    ty: pso::DescriptorType::StorageBuffer,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::COMPUTE,
+                    immutable_samplers: false,
+                })
+                .collect(),
+        ));
+        let constants_size = (mem::size_of::<CullConstants>() / mem::size_of::<u32>()) as u32;
+        let pipeline_layout = context.device.create_pipeline_layout(
+            vec![set_layout.raw()],
+            &[(pso::ShaderStageFlags::COMPUTE, 0..constants_size)],
+        );
+        let descriptors = DescriptorAllocator::new(context.device.clone(), set_layout.clone());
+
+        let vertices = create_arena_buffer(
+            context,
+            INITIAL_VERTICES * mem::size_of::<ChunkVertex>() as u64,
+            buffer::Usage::VERTEX,
+        )?;
+        let indices = create_arena_buffer(
+            context,
+            INITIAL_INDICES * mem::size_of::<u32>() as u64,
+            buffer::Usage::INDEX,
+        )?;
+        let record_buffer = create_record_buffer(context, INITIAL_SLOTS)?;
+
+        let mut draws = ChunkDraws {
+            device: context.device.clone(),
+            ranges: Rc::new(RefCell::new(ArenaRanges {
+                vertices: RangeAllocator::new(INITIAL_VERTICES),
+                indices: RangeAllocator::new(INITIAL_INDICES),
+                slots: RangeAllocator::new(INITIAL_SLOTS),
+            })),
+            vertices,
+            indices,
+            records: vec![ChunkRecord::default(); INITIAL_SLOTS as usize],
+            record_buffer,
+            frames: Vec::new(),
+            set_layout,
+            descriptors,
+            pipeline_layout: Some(pipeline_layout),
+            multi_draw,
+        };
+        draws.create_frames(context, frames_in_flight)?;
+        Ok(draws)
+    }
+
+    /// The layout `cull.comp`'s pipeline has to be built with.
+    pub fn pipeline_layout(&self) -> &B::PipelineLayout {
+        self.pipeline_layout.as_ref().unwrap()
+    }
+
+    /// How many chunks there's room for before everything has to be made bigger.
+    pub fn capacity(&self) -> u32 {
+        self.records.len() as u32
+    }
+
+    /// Uploads the mesh of a chunk at `origin` taking up `bounds`, which can't be empty. With a
+    /// transfer queue the copies go in with the next frame, which has to wait on them; without
+    /// one this waits for them to finish, like `upload_buffer`. When there isn't room for the
+    /// mesh it waits for the gpu to go idle so the buffers can be made bigger.
+    pub fn upload(
+        &mut self,
+        context: &mut GfxContext<B>,
+        origin: [f32; 3],
+        bounds: Aabb,
+        mesh: &ChunkMesh,
+    ) -> Result<ChunkAllocation> {
+        let indices: Vec<u32> = mesh
+            .indices
+            .iter()
+            .chain(&mesh.translucent_indices)
+            .chain(&mesh.water_indices)
+            .cloned()
+            .collect();
+        let vertex_range = self.allocate_vertices(context, mesh.vertices.len() as u64)?;
+        let index_range = self.allocate_indices(context, indices.len() as u64)?;
+        let slot = self.allocate_slot(context)?;
+        let allocation = ChunkAllocation {
+            ranges: self.ranges.clone(),
+            vertices: vertex_range.clone(),
+            indices: index_range.clone(),
+            slot,
+        };
+
+        let vertex_size = mem::size_of::<ChunkVertex>() as u64;
+        queue_upload_into(context, &mesh.vertices, &self.vertices, vertex_range.start * vertex_size)?;
+        if !indices.is_empty() {
+            queue_upload_into(context, &indices, &self.indices, index_range.start * mem::size_of::<u32>() as u64)?;
+        }
+
+        let record = ChunkRecord {
+            origin,
+            vertex_offset: vertex_range.start as i32,
+            bounds_min: bounds.min,
+            first_index: index_range.start as u32,
+            bounds_max: bounds.max,
+            opaque_count: mesh.indices.len() as u32,
+            translucent_count: mesh.translucent_indices.len() as u32,
+            water_count: mesh.water_indices.len() as u32,
+            _padding: [0; 2],
+        };
+        // The slot was free, so no frame in flight is reading its record
+        self.records[slot as usize] = record;
+        self.record_buffer.write_at(slot as u64 * mem::size_of::<ChunkRecord>() as u64, &[record])?;
+        Ok(allocation)
+    }
+
+    fn allocate_vertices(&mut self, context: &mut GfxContext<B>, count: u64) -> Result<Range<u64>> {
+        if let Some(range) = self.ranges.borrow_mut().vertices.allocate(count, 1) {
+            return Ok(range);
+        }
+        let size = self.ranges.borrow().vertices.size();
+        let new_size = (size * 2).max(size + count);
+        finish_uploads(context)?;
+        self.vertices = grow_arena_buffer(
+            context,
+            &self.vertices,
+            new_size * mem::size_of::<ChunkVertex>() as u64,
+            buffer::Usage::VERTEX,
+        )?;
+        debug!("Made room for {} chunk vertices", new_size);
+        let mut ranges = self.ranges.borrow_mut();
+        ranges.vertices.grow(new_size);
+        Ok(ranges.vertices.allocate(count, 1).unwrap())
+    }
+
+    fn allocate_indices(&mut self, context: &mut GfxContext<B>, count: u64) -> Result<Range<u64>> {
+        // The index buffer isn't touched for chunks with no faces drawn from it
+        if count == 0 {
+            return Ok(0..0);
+        }
+        if let Some(range) = self.ranges.borrow_mut().indices.allocate(count, 1) {
+            return Ok(range);
+        }
+        let size = self.ranges.borrow().indices.size();
+        let new_size = (size * 2).max(size + count);
+        finish_uploads(context)?;
+        self.indices = grow_arena_buffer(
+            context,
+            &self.indices,
+            new_size * mem::size_of::<u32>() as u64,
+            buffer::Usage::INDEX,
+        )?;
+        debug!("Made room for {} chunk indices", new_size);
+        let mut ranges = self.ranges.borrow_mut();
+        ranges.indices.grow(new_size);
+        Ok(ranges.indices.allocate(count, 1).unwrap())
+    }
+
+    /// A free slot, doubling the number of them if there isn't one. The records and each
+    /// frame's lists are made again to fit, so this waits for the gpu to go idle.
+    fn allocate_slot(&mut self, context: &mut GfxContext<B>) -> Result<u32> {
+        if let Some(range) = self.ranges.borrow_mut().slots.allocate(1, 1) {
+            return Ok(range.start as u32);
+        }
+        let slots = self.records.len() as u64 * 2;
+        context.wait_idle()?;
+        self.records.resize(slots as usize, ChunkRecord::default());
+        self.record_buffer = create_record_buffer(context, slots)?;
+        self.record_buffer.write(&self.records)?;
+        let frames_in_flight = self.frames.len();
+        self.frames.clear();
+        self.descriptors.reset();
+        self.create_frames(context, frames_in_flight)?;
+        debug!("Made room for {} chunks", slots);
+
+        let mut ranges = self.ranges.borrow_mut();
+        ranges.slots.grow(slots);
+        Ok(ranges.slots.allocate(1, 1).unwrap().start as u32)
+    }
+
+    /// Makes each frame's buffers and descriptor set, with room for every slot.
+    fn create_frames(&mut self, context: &mut GfxContext<B>, frames_in_flight: usize) -> Result<()> {
+        let slots = self.records.len() as u64;
+        let draw_size = mem::size_of::<DrawIndexedCommand>() as u64;
+        for _ in 0..frames_in_flight {
+            let order = DeviceBuffer::new(
+                context.device.clone(),
+                context.allocator.clone(),
+                slots * mem::size_of::<u32>() as u64,
+                buffer::Usage::STORAGE,
+                memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT,
+                MemoryCategory::Storage,
+            )?;
+            let draws = DeviceBuffer::new(
+                context.device.clone(),
+                context.allocator.clone(),
+                DRAW_LIST_COUNT as u64 * slots * draw_size,
+                buffer::Usage::STORAGE | buffer::Usage::INDIRECT | buffer::Usage::TRANSFER_DST,
+                memory::Properties::DEVICE_LOCAL,
+                MemoryCategory::Storage,
+            )?;
+            let counters = DeviceBuffer::new(
+                context.device.clone(),
+                context.allocator.clone(),
+                mem::size_of::<CullCounters>() as u64,
+                buffer::Usage::STORAGE | buffer::Usage::TRANSFER_DST,
+                memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT,
+                MemoryCategory::Storage,
+            )?;
+            let set = self.descriptors.allocate()?;
+            context.device.write_descriptor_sets(
+                [self.record_buffer.buffer(), order.buffer(), draws.buffer(), counters.buffer()]
+                    .iter()
+                    .enumerate()
+                    .map(|(binding, &buffer)| pso::DescriptorSetWrite {
+                        set: &set,
+                        binding: binding as u32,
+                        array_offset: 0,
+                        descriptors: Some(pso::Descriptor::Buffer(buffer, None..None)),
+                    })
+                    .collect::<Vec<_>>(),
+            );
+            self.frames.push(FrameDraws { order, draws, counters, set, count: None });
+        }
+        Ok(())
+    }
+
+    /// How many chunks the camera could see and how many triangles they had, from the last
+    /// time frame `frame_index` was culled, or `None` if it never has been. Call this after
+    /// `FrameSync::begin_frame` has waited for the frame's fence.
+    pub fn read_stats(&self, frame_index: usize) -> Result<Option<(CullStats, usize)>> {
+        let frame = &self.frames[frame_index];
+        let count = match frame.count {
+            Some(count) => count as usize,
+            None => return Ok(None),
+        };
+        let counters = frame.counters.read::<CullCounters>()?[0];
+        let visible = counters.visible as usize;
+        let stats = CullStats { drawn: visible, culled: count - visible.min(count), occluded: 0 };
+        Ok(Some((stats, counters.indices as usize / 3)))
+    }
+
+    /// Fills frame `frame_index`'s lists with `pipeline`, which has to have been built from
+    /// `cull.comp` with `pipeline_layout`. `order` is the slots of the chunks to draw, furthest
+    /// first, `cascades` the frusta of the shadow cascades to draw into, which is none of them
+    /// with shadows off, and `minimap`, `probe` and `reflection` the minimap's, the environment
+    /// probe's and the planar reflection's, on the frames they're drawn. Record this before the
+    /// render passes that draw the lists, or on a compute queue that they wait for.
+    pub fn cull<C>(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, C, command::OneShot>,
+        pipeline: &B::ComputePipeline,
+        frame_index: usize,
+        order: &[u32],
+        camera: &Frustum,
+        cascades: &[Frustum],
+        minimap: Option<&Frustum>,
+        probe: Option<&Frustum>,
+        reflection: Option<&Frustum>,
+    ) -> Result<()>
+    where
+        C: Supports<Compute> + Supports<Transfer>,
+    {
+        let capacity = self.records.len() as u32;
+        let frame = &mut self.frames[frame_index];
+        frame.order.write(order)?;
+        frame.count = Some(order.len() as u32);
+
+        // Lists are drawn with a draw for every chunk, so whatever isn't written has to be an
+        // empty draw
+        command_buffer.fill_buffer(frame.draws.buffer(), 0..frame.draws.size(), 0);
+        command_buffer.fill_buffer(frame.counters.buffer(), 0..frame.counters.size(), 0);
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::COMPUTE_SHADER,
+            memory::Dependencies::empty(),
+            &[
+                memory::Barrier::Buffer {
+                    states: buffer::Access::TRANSFER_WRITE..buffer::Access::SHADER_WRITE,
+                    target: frame.draws.buffer(),
+                },
+                memory::Barrier::Buffer {
+                    states: buffer::Access::TRANSFER_WRITE
+                        ..(buffer::Access::SHADER_READ | buffer::Access::SHADER_WRITE),
+                    target: frame.counters.buffer(),
+                },
+            ],
+        );
+
+        if !order.is_empty() {
+            command_buffer.bind_compute_pipeline(pipeline);
+            command_buffer.bind_compute_descriptor_sets(
+                self.pipeline_layout.as_ref().unwrap(),
+                0,
+                vec![&frame.set],
+                &[],
+            );
+            let groups = (order.len() as u32 + CULL_GROUP_SIZE - 1) / CULL_GROUP_SIZE;
+            // The camera is view 0, and the cascades come after it. The minimap, the probe and
+            // the reflection have views of their own, whichever cascades there are.
+            let views = iter::once(camera)
+                .chain(cascades)
+                .enumerate()
+                .map(|(view, frustum)| (view as u32, frustum))
+                .chain(minimap.map(|frustum| (MINIMAP_VIEW, frustum)))
+                .chain(probe.map(|frustum| (PROBE_VIEW, frustum)))
+                .chain(reflection.map(|frustum| (REFLECTION_VIEW, frustum)));
+            for (view, frustum) in views {
+                let mut planes = [[0.0; 4]; 6];
+                for (plane, from) in planes.iter_mut().zip(frustum.planes.iter()) {
+                    *plane = [from.normal.x, from.normal.y, from.normal.z, from.distance];
+                }
+                let constants = CullConstants { planes, count: order.len() as u32, view, capacity };
+                command_buffer.push_compute_constants(
+                    self.pipeline_layout.as_ref().unwrap(),
+                    0,
+                    constants.as_words(),
+                );
+                command_buffer.dispatch([groups, 1, 1]);
+            }
+        }
+
+        command_buffer.pipeline_barrier(
+            PipelineStage::COMPUTE_SHADER..(PipelineStage::DRAW_INDIRECT | PipelineStage::HOST),
+            memory::Dependencies::empty(),
+            &[
+                memory::Barrier::Buffer {
+                    states: buffer::Access::SHADER_WRITE..buffer::Access::INDIRECT_COMMAND_READ,
+                    target: frame.draws.buffer(),
+                },
+                memory::Barrier::Buffer {
+                    states: buffer::Access::SHADER_WRITE..buffer::Access::HOST_READ,
+                    target: frame.counters.buffer(),
+                },
+            ],
+        );
+        Ok(())
+    }
+
+    /// Draws `list` as the last `cull` of frame `frame_index` left it, with whichever chunk
+    /// pipeline is bound. Those read the vertices from binding 0 and the records from
+    /// binding 1.
+    pub fn draw(&self, encoder: &mut command::RenderPassInlineEncoder<B>, frame_index: usize, list: DrawList) {
+        let frame = &self.frames[frame_index];
+        let count = match frame.count {
+            Some(count) if count > 0 => count,
+            _ => return,
+        };
+
+        encoder.bind_vertex_buffers(
+            0,
+            pso::VertexBufferSet(vec![(self.vertices.buffer(), 0), (self.record_buffer.buffer(), 0)]),
+        );
+        encoder.bind_index_buffer(buffer::IndexBufferView {
+            buffer: self.indices.buffer(),
+            offset: 0,
+            index_type: IndexType::U32,
+        });
+        let stride = mem::size_of::<DrawIndexedCommand>() as u64;
+        let offset = list.index() as u64 * self.records.len() as u64 * stride;
+        if self.multi_draw {
+            encoder.draw_indexed_indirect(frame.draws.buffer(), offset, count, stride as u32);
+        } else {
+            for draw in 0..count as u64 {
+                encoder.draw_indexed_indirect(frame.draws.buffer(), offset + draw * stride, 1, stride as u32);
+            }
+        }
+    }
+}
+
+impl<B: Backend> Drop for ChunkDraws<B> {
+    fn drop(&mut self) {
+        if let Some(layout) = self.pipeline_layout.take() {
+            self.device.destroy_pipeline_layout(layout);
+        }
+    }
+}
+
+/// Makes one of the big buffers the meshes are uploaded into, which can be copied out of when
+/// it has to be made bigger.
+fn create_arena_buffer<B: Backend>(
+    context: &mut GfxContext<B>,
+    size: u64,
+    usage: buffer::Usage,
+) -> Result<DeviceBuffer<B>> {
+    DeviceBuffer::new(
+        context.device.clone(),
+        context.allocator.clone(),
+        size,
+        usage | buffer::Usage::TRANSFER_SRC | buffer::Usage::TRANSFER_DST,
+        memory::Properties::DEVICE_LOCAL,
+        MemoryCategory::Meshes,
+    )
+}
+
+/// A bigger copy of `old`, `size` bytes long. The gpu can't be using `old`.
+/// Waits for the gpu to go idle, with anything queued on the transfer queue submitted first, so
+/// copies into an arena buffer have all landed before it's copied out of and replaced.
+fn finish_uploads<B: Backend>(context: &mut GfxContext<B>) -> Result<()> {
+    match context.transfer {
+        Some(ref mut transfer) => transfer.finish(),
+        None => context.wait_idle(),
+    }
+}
+
+fn grow_arena_buffer<B: Backend>(
+    context: &mut GfxContext<B>,
+    old: &DeviceBuffer<B>,
+    size: u64,
+    usage: buffer::Usage,
+) -> Result<DeviceBuffer<B>> {
+    let new = create_arena_buffer(context, size, usage)?;
+    context.submit_one_shot(|command_buffer| {
+        command_buffer.copy_buffer(
+            old.buffer(),
+            new.buffer(),
+            &[command::BufferCopy { src: 0, dst: 0, size: old.size() }],
+        );
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::VERTEX_INPUT,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Buffer {
+                states: buffer::Access::TRANSFER_WRITE
+                    ..(buffer::Access::VERTEX_BUFFER_READ | buffer::Access::INDEX_BUFFER_READ),
+                target: new.buffer(),
+            }],
+        );
+    })?;
+    Ok(new)
+}
+
+/// The records are written from the cpu as chunks are uploaded, read by `cull.comp`, and read
+/// as vertex attributes by the chunk pipelines.
+fn create_record_buffer<B: Backend>(context: &mut GfxContext<B>, slots: u64) -> Result<DeviceBuffer<B>> {
+    DeviceBuffer::new(
+        context.device.clone(),
+        context.allocator.clone(),
+        slots * mem::size_of::<ChunkRecord>() as u64,
+        buffer::Usage::STORAGE | buffer::Usage::VERTEX,
+        memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT,
+        MemoryCategory::Storage,
+    )
+}
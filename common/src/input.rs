@@ -0,0 +1,458 @@
+//! Turning keyboard and mouse events into named actions.
+//!
+//! Game code asks about actions like `MoveForward` or `BreakBlock` instead of particular keys.
+//! Which keys and buttons trigger each action comes from the `[bindings]` table in the settings
+//! file, so they can be changed without touching the code:
+//!
+//! ```toml
+//! [bindings]
+//! move_forward = ["W", "Up"]
+//! break_block = ["MouseLeft"]
+//! look = ["MouseMiddle"]
+//! ```
+//!
+//! Keys are named after winit's `VirtualKeyCode`s (`W`, `Space`, `LShift`, `Key1`, `F5`...),
+//! ignoring case, and the mouse buttons are `MouseLeft`, `MouseRight` and `MouseMiddle`. Gamepad
+//! buttons are named by position, as in `GAMEPAD_BUTTON_NAMES`: `PadSouth` is A on an Xbox pad
+//! and cross on a PlayStation one.
+//!
+//! `look` is held to turn the camera while the cursor isn't grabbed, so it's on a button nothing
+//! else uses by default. Otherwise every drag to look around would break or place a block too.
+//!
+//! Gamepad sticks aren't bound. The left one always moves and the right one always looks, on top
+//! of whatever the keyboard and mouse are doing; see `gamepad::Gamepads`.
+
+use std::collections::HashSet;
+
+use gilrs;
+use winit;
+
+/// Everything the input bindings can trigger.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBack,
+    MoveLeft,
+    MoveRight,
+    /// Flying up and down, for the free flying camera.
+    MoveUp,
+    MoveDown,
+    Jump,
+    BreakBlock,
+    PlaceBlock,
+    /// Steps through the blocks `PlaceBlock` can put down.
+    NextBlock,
+    PreviousBlock,
+    /// Picks the block in a slot of the hotbar, counting from 0.
+    SelectSlot(usize),
+    /// Held to look around with the mouse.
+    Look,
+    /// Swaps between the FPS and orbit cameras.
+    SwitchCamera,
+    /// Swaps between the naive and greedy meshers, in chapters that draw chunks.
+    SwitchMesher,
+    /// Steps through the debug views, in chapters that have them.
+    CycleViewMode,
+}
+
+/// The keys and buttons bound to each action, as they're written in the settings file.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Bindings {
+    pub move_forward: Vec<String>,
+    pub move_back: Vec<String>,
+    pub move_left: Vec<String>,
+    pub move_right: Vec<String>,
+    pub move_up: Vec<String>,
+    pub move_down: Vec<String>,
+    pub jump: Vec<String>,
+    pub break_block: Vec<String>,
+    pub place_block: Vec<String>,
+    pub next_block: Vec<String>,
+    pub previous_block: Vec<String>,
+    /// One name for each slot of the hotbar, in order.
+    pub hotbar: Vec<String>,
+    pub look: Vec<String>,
+    pub switch_camera: Vec<String>,
+    pub switch_mesher: Vec<String>,
+    pub cycle_view_mode: Vec<String>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        let names = |names: &[&str]| names.iter().map(|&name| name.to_owned()).collect();
+        Bindings {
+            move_forward: names(&["W"]),
+            move_back: names(&["S"]),
+            move_left: names(&["A"]),
+            move_right: names(&["D"]),
+            move_up: names(&["Space", "PadSouth"]),
+            move_down: names(&["LShift", "PadEast"]),
+            jump: names(&["Space", "PadSouth"]),
+            break_block: names(&["MouseLeft", "PadRightTrigger"]),
+            place_block: names(&["MouseRight", "PadLeftTrigger"]),
+            next_block: names(&["E", "PadRightBumper"]),
+            previous_block: names(&["Q", "PadLeftBumper"]),
+            hotbar: names(&["Key1", "Key2", "Key3", "Key4", "Key5", "Key6", "Key7", "Key8", "Key9", "Key0"]),
+            look: names(&["MouseMiddle"]),
+            switch_camera: names(&["C", "PadNorth"]),
+            switch_mesher: names(&["M", "PadWest"]),
+            cycle_view_mode: names(&["F3"]),
+        }
+    }
+}
+
+impl Bindings {
+    /// Every action along with the names bound to it.
+    pub fn actions(&self) -> Vec<(Action, &[String])> {
+        let mut actions = vec![
+            (Action::MoveForward, &self.move_forward[..]),
+            (Action::MoveBack, &self.move_back[..]),
+            (Action::MoveLeft, &self.move_left[..]),
+            (Action::MoveRight, &self.move_right[..]),
+            (Action::MoveUp, &self.move_up[..]),
+            (Action::MoveDown, &self.move_down[..]),
+            (Action::Jump, &self.jump[..]),
+            (Action::BreakBlock, &self.break_block[..]),
+            (Action::PlaceBlock, &self.place_block[..]),
+            (Action::NextBlock, &self.next_block[..]),
+            (Action::PreviousBlock, &self.previous_block[..]),
+            (Action::Look, &self.look[..]),
+            (Action::SwitchCamera, &self.switch_camera[..]),
+            (Action::SwitchMesher, &self.switch_mesher[..]),
+            (Action::CycleViewMode, &self.cycle_view_mode[..]),
+        ];
+        for slot in 0..self.hotbar.len() {
+            actions.push((Action::SelectSlot(slot), &self.hotbar[slot..slot + 1]));
+        }
+        actions
+    }
+}
+
+/// A key or button that can be bound to an action.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Trigger {
+    Key(winit::VirtualKeyCode),
+    Mouse(winit::MouseButton),
+    /// A button on any connected gamepad.
+    Gamepad(gilrs::Button),
+}
+
+macro_rules! key_names {
+    ($($key:ident),* $(,)*) => {
+        &[$((stringify!($key), winit::VirtualKeyCode::$key)),*]
+    };
+}
+
+/// The keys that can be bound, by name.
+const KEY_NAMES: &[(&str, winit::VirtualKeyCode)] = key_names![
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z,
+    Key1, Key2, Key3, Key4, Key5, Key6, Key7, Key8, Key9, Key0,
+    F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Escape, Tab, Space, Return, Back, Delete, Insert, Home, End, PageUp, PageDown,
+    Left, Right, Up, Down,
+    LShift, RShift, LControl, RControl, LAlt, RAlt,
+    Grave, Minus, Equals, LBracket, RBracket, Semicolon, Apostrophe, Comma, Period, Slash,
+    Backslash,
+    Numpad0, Numpad1, Numpad2, Numpad3, Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9,
+];
+
+/// The gamepad buttons that can be bound, by name. The bumpers are what gilrs calls the upper
+/// triggers, and the triggers its lower ones.
+pub const GAMEPAD_BUTTON_NAMES: &[(&str, gilrs::Button)] = &[
+    ("PadSouth", gilrs::Button::South),
+    ("PadEast", gilrs::Button::East),
+    ("PadNorth", gilrs::Button::North),
+    ("PadWest", gilrs::Button::West),
+    ("PadLeftBumper", gilrs::Button::LeftTrigger),
+    ("PadRightBumper", gilrs::Button::RightTrigger),
+    ("PadLeftTrigger", gilrs::Button::LeftTrigger2),
+    ("PadRightTrigger", gilrs::Button::RightTrigger2),
+    ("PadSelect", gilrs::Button::Select),
+    ("PadStart", gilrs::Button::Start),
+    ("PadLeftStick", gilrs::Button::LeftThumb),
+    ("PadRightStick", gilrs::Button::RightThumb),
+    ("PadUp", gilrs::Button::DPadUp),
+    ("PadDown", gilrs::Button::DPadDown),
+    ("PadLeft", gilrs::Button::DPadLeft),
+    ("PadRight", gilrs::Button::DPadRight),
+];
+
+/// Looks up a key or button by the name it's given in the settings file.
+pub fn parse_trigger(name: &str) -> Option<Trigger> {
+    match name.to_lowercase().as_str() {
+        "mouseleft" => return Some(Trigger::Mouse(winit::MouseButton::Left)),
+        "mouseright" => return Some(Trigger::Mouse(winit::MouseButton::Right)),
+        "mousemiddle" => return Some(Trigger::Mouse(winit::MouseButton::Middle)),
+        _ => (),
+    }
+    let gamepad_button = GAMEPAD_BUTTON_NAMES
+        .iter()
+        .find(|&&(button_name, _)| button_name.eq_ignore_ascii_case(name))
+        .map(|&(_, button)| Trigger::Gamepad(button));
+    gamepad_button.or_else(|| {
+        KEY_NAMES
+            .iter()
+            .find(|&&(key_name, _)| key_name.eq_ignore_ascii_case(name))
+            .map(|&(_, key)| Trigger::Key(key))
+    })
+}
+
+/// The state of the keyboard and mouse, in terms of actions.
+///
+/// Feed it events as they come in and call `begin_frame` before each batch, then ask it which
+/// actions are held or were just pressed. Mouse movement and wheel scrolling since the start of
+/// the frame are tracked too, and so is whether the cursor should be grabbed.
+pub struct Input {
+    bindings: Bindings,
+    /// What each trigger is bound to. A trigger can be bound to more than one action.
+    actions: Vec<(Trigger, Action)>,
+    held: HashSet<Trigger>,
+    /// Triggers that went down since `begin_frame`.
+    pressed: HashSet<Trigger>,
+    last_cursor: Option<(f64, f64)>,
+    /// How far the cursor moved over the window this frame.
+    cursor_delta: (f32, f32),
+    /// Raw mouse motion this frame, while the cursor is grabbed.
+    raw_delta: (f32, f32),
+    /// Whether any raw mouse motion has arrived. Not every platform sends it, and without it
+    /// mouse look falls back to following the cursor.
+    raw_motion: bool,
+    wheel_lines: f32,
+    /// Whether the cursor is grabbed whenever the window is focused or clicked on.
+    grab_on_focus: bool,
+    /// Whether the cursor should be grabbed right now. `GfxContext::update_cursor_grab`
+    /// applies this to the window.
+    cursor_grabbed: bool,
+    /// The left stick, after the deadzone and response curve, from -1 to 1 with +y up.
+    move_stick: (f32, f32),
+    /// How far the right stick turns the camera this frame, in radians.
+    stick_look: (f32, f32),
+}
+
+impl Input {
+    pub fn new(bindings: &Bindings) -> Self {
+        let mut input = Input {
+            bindings: bindings.clone(),
+            actions: Vec::new(),
+            held: HashSet::new(),
+            pressed: HashSet::new(),
+            last_cursor: None,
+            cursor_delta: (0.0, 0.0),
+            raw_delta: (0.0, 0.0),
+            raw_motion: false,
+            wheel_lines: 0.0,
+            grab_on_focus: false,
+            cursor_grabbed: false,
+            move_stick: (0.0, 0.0),
+            stick_look: (0.0, 0.0),
+        };
+        input.rebuild_actions();
+        input
+    }
+
+    /// Switches to `bindings` if they differ from the current ones. Chapters call this every
+    /// frame with the bindings from the settings, so edits to the file apply straight away.
+    pub fn apply_bindings(&mut self, bindings: &Bindings) {
+        if *bindings != self.bindings {
+            self.bindings = bindings.clone();
+            self.rebuild_actions();
+        }
+    }
+
+    fn rebuild_actions(&mut self) {
+        self.actions.clear();
+        for (action, names) in self.bindings.actions() {
+            for name in names {
+                match parse_trigger(name) {
+                    Some(trigger) => self.actions.push((trigger, action)),
+                    None => warn!("Unknown key or button {:?} bound to {:?}", name, action),
+                }
+            }
+        }
+    }
+
+    /// Forgets what was pressed and how far the mouse moved last frame. Call this before
+    /// passing in the frame's events.
+    pub fn begin_frame(&mut self) {
+        self.pressed.clear();
+        self.cursor_delta = (0.0, 0.0);
+        self.raw_delta = (0.0, 0.0);
+        self.stick_look = (0.0, 0.0);
+        self.wheel_lines = 0.0;
+    }
+
+    /// Updates the input state from an event. Returns true if the event was used to grab or
+    /// release the cursor, in which case it shouldn't do anything else: the click that grabs it
+    /// isn't a `BreakBlock`, and the Escape that releases it doesn't close the window.
+    pub fn handle_event(&mut self, event: &winit::Event) -> bool {
+        match *event {
+            winit::Event::WindowEvent { ref event, .. } => self.handle_window_event(event),
+            winit::Event::DeviceEvent { event: winit::DeviceEvent::MouseMotion { delta }, .. } => {
+                // Raw motion keeps coming when the cursor is stopped by the edge of the screen,
+                // and on some platforms even when the window isn't focused
+                if self.cursor_grabbed {
+                    self.raw_motion = true;
+                    self.raw_delta.0 += delta.0 as f32;
+                    self.raw_delta.1 += delta.1 as f32;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn handle_window_event(&mut self, event: &winit::WindowEvent) -> bool {
+        match *event {
+            winit::WindowEvent::KeyboardInput {
+                input: winit::KeyboardInput {
+                    virtual_keycode: Some(winit::VirtualKeyCode::Escape),
+                    state: winit::ElementState::Pressed,
+                    ..
+                },
+                ..
+            } if self.cursor_grabbed => {
+                self.cursor_grabbed = false;
+                return true;
+            }
+            winit::WindowEvent::KeyboardInput {
+                input: winit::KeyboardInput { virtual_keycode: Some(key), state, .. },
+                ..
+            } => self.set_trigger(Trigger::Key(key), state),
+            winit::WindowEvent::MouseInput { state: winit::ElementState::Pressed, .. }
+                if self.grab_on_focus && !self.cursor_grabbed =>
+            {
+                self.cursor_grabbed = true;
+                return true;
+            }
+            winit::WindowEvent::MouseInput { state, button, .. } => {
+                self.set_trigger(Trigger::Mouse(button), state);
+            }
+            winit::WindowEvent::CursorMoved { position, .. } => {
+                if let Some((x, y)) = self.last_cursor {
+                    self.cursor_delta.0 += (position.x - x) as f32;
+                    self.cursor_delta.1 += (position.y - y) as f32;
+                }
+                self.last_cursor = Some((position.x, position.y));
+            }
+            // Wherever the cursor comes back in, it shouldn't count as moving there
+            winit::WindowEvent::CursorLeft { .. } => self.last_cursor = None,
+            winit::WindowEvent::MouseWheel { delta, .. } => {
+                self.wheel_lines += match delta {
+                    winit::MouseScrollDelta::LineDelta(_, lines) => lines,
+                    // Roughly how far a line scrolls on platforms that report pixels
+                    winit::MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+            }
+            winit::WindowEvent::Focused(true) => self.cursor_grabbed = self.grab_on_focus,
+            winit::WindowEvent::Focused(false) => {
+                // Otherwise a key released while the window is in the background would stay held
+                self.held.clear();
+                self.cursor_grabbed = false;
+            }
+            _ => (),
+        }
+        false
+    }
+
+    fn set_trigger(&mut self, trigger: Trigger, state: winit::ElementState) {
+        self.set_held(trigger, state == winit::ElementState::Pressed);
+    }
+
+    fn set_held(&mut self, trigger: Trigger, held: bool) {
+        if held {
+            // Key repeat sends more presses while a key is held, which aren't new presses
+            if self.held.insert(trigger) {
+                self.pressed.insert(trigger);
+            }
+        } else {
+            self.held.remove(&trigger);
+        }
+    }
+
+    /// Presses or releases a gamepad button.
+    pub fn set_gamepad_button(&mut self, button: gilrs::Button, held: bool) {
+        self.set_held(Trigger::Gamepad(button), held);
+    }
+
+    /// Lets go of every gamepad button, for when the gamepad is disconnected.
+    pub fn release_gamepad_buttons(&mut self) {
+        self.held.retain(|trigger| match *trigger {
+            Trigger::Gamepad(_) => false,
+            _ => true,
+        });
+    }
+
+    /// Sets the left stick's position, already past the deadzone and curve.
+    pub fn set_move_stick(&mut self, x: f32, y: f32) {
+        self.move_stick = (x, y);
+    }
+
+    /// Adds to how far the right stick turns the camera this frame, in radians.
+    pub fn add_stick_look(&mut self, yaw: f32, pitch: f32) {
+        self.stick_look.0 += yaw;
+        self.stick_look.1 += pitch;
+    }
+
+    /// The left stick, from -1 to 1 on each axis with +x right and +y forward.
+    pub fn move_stick(&self) -> (f32, f32) {
+        self.move_stick
+    }
+
+    /// How far the right stick turned the camera this frame, as yaw (clockwise from above) and
+    /// pitch (up) in radians.
+    pub fn stick_look(&self) -> (f32, f32) {
+        self.stick_look
+    }
+
+    /// Whether any of the keys or buttons bound to `action` are down.
+    pub fn is_held(&self, action: Action) -> bool {
+        self.actions.iter().any(|&(trigger, bound)| bound == action && self.held.contains(&trigger))
+    }
+
+    /// Whether one of the keys or buttons bound to `action` went down this frame.
+    pub fn was_pressed(&self, action: Action) -> bool {
+        self.actions.iter().any(|&(trigger, bound)| bound == action && self.pressed.contains(&trigger))
+    }
+
+    /// 1 if only `positive` is held, -1 if only `negative` is, and 0 otherwise. Gamepad sticks
+    /// aren't included; see `move_stick`.
+    pub fn axis(&self, positive: Action, negative: Action) -> f32 {
+        self.is_held(positive) as i32 as f32 - self.is_held(negative) as i32 as f32
+    }
+
+    /// How far the mouse moved this frame. While the cursor is grabbed this is raw mouse
+    /// motion, in whatever units the platform reports, which is roughly pixels. Otherwise it's
+    /// how far the cursor moved over the window, in physical pixels.
+    pub fn mouse_delta(&self) -> (f32, f32) {
+        if self.cursor_grabbed && self.raw_motion {
+            self.raw_delta
+        } else {
+            self.cursor_delta
+        }
+    }
+
+    /// Turns grabbing the cursor on or off. With it on, the cursor is hidden and held in the
+    /// window whenever the window is focused or clicked on, Escape lets go of it, and moving the
+    /// mouse always looks around. Chapters turn this on when they have something to look at.
+    pub fn set_grab_on_focus(&mut self, grab: bool) {
+        self.grab_on_focus = grab;
+        self.cursor_grabbed = grab;
+    }
+
+    /// Whether the cursor should be grabbed, to pass to `GfxContext::update_cursor_grab`.
+    pub fn cursor_grabbed(&self) -> bool {
+        self.cursor_grabbed
+    }
+
+    /// Whether the mouse should turn the camera: always while the cursor is grabbed, and
+    /// otherwise while `Look` is held.
+    pub fn is_looking(&self) -> bool {
+        self.cursor_grabbed || self.is_held(Action::Look)
+    }
+
+    /// How far the mouse wheel turned this frame, in lines. Positive is away from the user.
+    pub fn wheel_lines(&self) -> f32 {
+        self.wheel_lines
+    }
+}
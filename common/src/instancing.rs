@@ -0,0 +1,145 @@
+//! Drawing many copies of a mesh with one draw call, for props like grass tufts and flowers.
+//!
+//! The mesh's vertices come from one vertex buffer as usual, and a second one holds an
+//! `Instance` for each copy, which the pipeline steps through once per instance instead of once
+//! per vertex. The vertex shader moves each vertex by its instance's transform, so a field of
+//! grass costs one draw however many tufts are in it.
+//!
+//! An `InstanceBuffer` can hold the instances of several meshes, grouped by mesh, and hands out
+//! the range of instances each one draws.
+
+use std::mem;
+use std::ops::Range;
+
+use hal::{ buffer, format as f, memory, pso, Backend };
+
+use allocator::MemoryCategory;
+use buffer::{ upload_into, DeviceBuffer };
+use context::GfxContext;
+use error::Result;
+use math::{ ShaderMatrix, Transform };
+
+/// One copy of a mesh. Has to match the attributes `add_instance_attributes` describes.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Instance {
+    /// From the mesh's space to the world. Column major, like every matrix the shaders get.
+    pub model: ShaderMatrix,
+    /// What the mesh's colours are multiplied by. It's up to the shader how much of the mesh it
+    /// applies to.
+    pub tint: [f32; 4],
+}
+
+impl Instance {
+    pub fn new(transform: &Transform, tint: [f32; 4]) -> Self {
+        Instance { model: transform.matrix().into(), tint }
+    }
+}
+
+/// How many attribute locations an `Instance` takes up: one for each column of `model`, since
+/// an attribute can't be bigger than a `vec4`, and one for `tint`.
+pub const INSTANCE_LOCATIONS: u32 = 5;
+
+/// Describes the `Instance`s in the vertex buffer at `binding` to the pipeline, as the
+/// attributes from `first_location` on. A shader reads them as a `mat4` at `first_location`
+/// followed by a `vec4` at `first_location + 4`.
+pub fn add_instance_attributes<B: Backend>(
+    pipeline_desc: &mut pso::GraphicsPipelineDesc<B>,
+    binding: u32,
+    first_location: u32,
+) {
+    // Stepped through once per instance rather than once per vertex
+    pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+        binding,
+        stride: mem::size_of::<Instance>() as u32,
+        rate: 1,
+    });
+    let column_size = mem::size_of::<[f32; 4]>() as u32;
+    for location in 0..INSTANCE_LOCATIONS {
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: first_location + location,
+            binding,
+            element: pso::Element {
+                format: f::Format::Rgba32Float,
+                offset: location * column_size,
+            },
+        });
+    }
+}
+
+/// Puts `instances`, each tagged with which of `mesh_count` meshes it's a copy of, in order of
+/// mesh, keeping the order they were given in otherwise. Returns them along with the range each
+/// mesh's take up.
+pub fn batch_instances(mesh_count: usize, instances: &[(usize, Instance)]) -> (Vec<Instance>, Vec<Range<u32>>) {
+    let mut batched = Vec::with_capacity(instances.len());
+    let mut batches = Vec::with_capacity(mesh_count);
+    for mesh in 0..mesh_count {
+        let start = batched.len() as u32;
+        batched.extend(instances.iter().filter(|&&(of, _)| of == mesh).map(|&(_, instance)| instance));
+        batches.push(start..batched.len() as u32);
+    }
+    (batched, batches)
+}
+
+/// The instances of some number of meshes, uploaded once. They stay where they are, so anything
+/// that moves has to be done in the vertex shader.
+pub struct InstanceBuffer<B: Backend> {
+    buffer: DeviceBuffer<B>,
+    batches: Vec<Range<u32>>,
+}
+
+impl<B: Backend> InstanceBuffer<B> {
+    /// Uploads `instances`, tagged as with `batch_instances`. Any not tagged with one of the
+    /// first `mesh_count` meshes are left out.
+    pub fn new(context: &mut GfxContext<B>, mesh_count: usize, instances: &[(usize, Instance)]) -> Result<Self> {
+        let (batched, batches) = batch_instances(mesh_count, instances);
+        // Buffers can't be empty, so there's always room for at least one
+        let size = (batched.len().max(1) * mem::size_of::<Instance>()) as u64;
+        let buffer = DeviceBuffer::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            size,
+            buffer::Usage::VERTEX | buffer::Usage::TRANSFER_DST,
+            memory::Properties::DEVICE_LOCAL,
+            MemoryCategory::Meshes,
+        )?;
+        if !batched.is_empty() {
+            upload_into(context, &batched, &buffer, 0)?;
+        }
+        debug!("Uploaded {} instances of {} meshes", batched.len(), mesh_count);
+        Ok(InstanceBuffer { buffer, batches })
+    }
+
+    pub fn buffer(&self) -> &B::Buffer {
+        self.buffer.buffer()
+    }
+
+    /// The instances of `mesh`, to draw it with. They count from the start of the buffer, so it
+    /// has to be bound at offset 0.
+    pub fn batch(&self, mesh: usize) -> Range<u32> {
+        self.batches[mesh].clone()
+    }
+
+    /// How many instances there are, of every mesh.
+    pub fn count(&self) -> u32 {
+        self.batches.last().map_or(0, |batch| batch.end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(x: f32) -> Instance {
+        Instance::new(&Transform::from_translation([x, 0.0, 0.0]), [1.0; 4])
+    }
+
+    #[test]
+    fn instances_are_grouped_by_mesh_in_order() {
+        let instances = [(1, instance(0.0)), (0, instance(1.0)), (1, instance(2.0)), (3, instance(3.0))];
+        let (batched, batches) = batch_instances(3, &instances);
+        assert_eq!(batches, vec![0..1, 1..3, 3..3]);
+        // The one for a mesh that isn't there is left out
+        assert_eq!(batched, vec![instance(1.0), instance(0.0), instance(2.0)]);
+    }
+}
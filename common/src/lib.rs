@@ -0,0 +1,248 @@
+//! Code shared between all of the tutorial chapters.
+//!
+//! Everything up to and including opening a device and building a swapchain is the same in every
+//! chapter, so it lives here instead of being copied into each `src/NN` crate.
+
+#[cfg(feature = "vulkan")]
+extern crate gfx_backend_vulkan;
+#[cfg(all(feature = "dx12", windows))]
+extern crate gfx_backend_dx12;
+#[cfg(all(feature = "metal", target_os = "macos"))]
+extern crate gfx_backend_metal;
+
+extern crate gfx_hal as hal;
+#[cfg(all(feature = "dx12", windows))]
+extern crate winapi;
+
+extern crate cgmath;
+extern crate crossbeam_channel;
+extern crate env_logger;
+extern crate gilrs;
+extern crate image;
+#[macro_use]
+extern crate imgui;
+#[macro_use]
+extern crate log;
+extern crate notify;
+extern crate num_cpus;
+extern crate rusttype;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate shader_build;
+#[macro_use]
+extern crate structopt;
+extern crate toml;
+extern crate winit;
+
+pub mod adapter;
+pub mod allocator;
+pub mod async_compute;
+pub mod atlas;
+pub mod args;
+pub mod attachments;
+pub mod backend;
+pub mod billboard;
+pub mod biome;
+pub mod bloom;
+pub mod buffer;
+pub mod camera;
+pub mod clock;
+pub mod color;
+pub mod config;
+pub mod context;
+pub mod cpu_profiler;
+pub mod culling;
+pub mod cursor;
+pub mod debug_lines;
+pub mod decoration;
+pub mod depth;
+pub mod descriptors;
+pub mod error;
+pub mod events;
+pub mod exposure;
+pub mod frame_sync;
+pub mod frame_times;
+pub mod fullscreen;
+pub mod gamepad;
+pub mod gbuffer;
+pub mod gpu_mesher;
+pub mod gpu_particles;
+pub mod gpu_profiler;
+pub mod graph;
+pub mod hdr;
+pub mod hud;
+pub mod indirect;
+pub mod input;
+pub mod instancing;
+pub mod light;
+pub mod lod;
+pub mod logging;
+pub mod math;
+pub mod minimap;
+pub mod mesh_optimizer;
+pub mod mesh_workers;
+pub mod mesher;
+pub mod msaa;
+pub mod noise;
+pub mod occlusion;
+pub mod overlay;
+pub mod particles;
+pub mod pass;
+pub mod pipeline_cache;
+pub mod point_lights;
+pub mod present;
+pub mod probe;
+pub mod ranges;
+pub mod raycast;
+pub mod reflection;
+pub mod region;
+pub mod render_graph;
+pub mod resources;
+pub mod samplers;
+pub mod screenshot;
+pub mod shader;
+pub mod shadow;
+pub mod sky;
+pub mod ssao;
+pub mod staging;
+pub mod streaming;
+pub mod text;
+pub mod texture;
+pub mod time_of_day;
+pub mod transfer;
+pub mod timestep;
+pub mod validation;
+pub mod vertex_format;
+pub mod view_mode;
+pub mod water;
+pub mod world;
+pub mod worldgen;
+
+use std::process;
+
+use structopt::StructOpt;
+
+pub use allocator::{ Allocation, Allocator, AllocatorStats, HeapStats, MemoryCategory };
+pub use async_compute::AsyncCompute;
+pub use args::Args;
+pub use attachments::AttachmentImages;
+pub use backend::{ Backend, Runner };
+pub use billboard::{ Billboards, Sprite };
+pub use biome::Biome;
+pub use bloom::Bloom;
+pub use buffer::{ queue_upload_into, upload_buffer, upload_into, DeviceBuffer, MappedBuffer };
+pub use camera::{ Camera, CameraSwitch, FpsCamera, OrbitCamera };
+pub use clock::Clock;
+pub use config::{ Config, Settings };
+pub use context::GfxContext;
+pub use cpu_profiler::CpuProfiler;
+pub use culling::CullStats;
+pub use debug_lines::{ ChunkState, DebugLineSettings, DebugLines, LineVertex };
+pub use decoration::PendingEdits;
+pub use error::{ RendererError, Result };
+pub use events::Events;
+pub use exposure::{ AutoExposure, Tonemap };
+pub use frame_sync::{ Frame, FrameSync, RetiredResources };
+pub use frame_times::FrameTimes;
+pub use gamepad::Gamepads;
+pub use gbuffer::{ GBuffer, Shading };
+pub use gpu_mesher::{ GpuMesher, MeshRegion };
+pub use gpu_particles::GpuParticles;
+pub use gpu_profiler::GpuProfiler;
+pub use graph::{ GraphBuilder, Load, PassId, Queue, ResourceId };
+pub use hdr::Hdr;
+pub use hud::{ Hud, HudImage, HudQuad };
+pub use indirect::{ ChunkAllocation, ChunkDraws, DrawList };
+pub use input::{ Action, Input };
+pub use instancing::{ Instance, InstanceBuffer };
+pub use light::{ BlockLights, Lighting };
+pub use lod::{ ChunkDetail, Lod, LodTracker };
+pub use math::{ Aabb, Frustum, Transform };
+pub use mesh_optimizer::{ MeshOptimization, MeshStats };
+pub use mesh_workers::{ MeshedChunk, MeshWorkers };
+pub use mesher::{ BlockTextures, ChunkMesh, ChunkNeighborhood, ChunkVertex, Mesher, Surface };
+pub use minimap::Minimap;
+pub use occlusion::OcclusionCuller;
+pub use overlay::{ DebugOverlay, OverlaySettings, OverlayStats };
+pub use particles::{ Particle, Particles };
+pub use pipeline_cache::PipelineCache;
+pub use point_lights::{ PointLight, PointLightBlocks, PointLights };
+pub use probe::EnvironmentProbe;
+pub use ranges::RangeAllocator;
+pub use raycast::{ raycast, RayHit };
+pub use reflection::{ PlanarReflection, Reflections };
+pub use region::RegionStore;
+pub use render_graph::{ Commands, ImageDesc, RenderGraph };
+pub use resources::{ Framebuffers, SwapchainBundle };
+pub use samplers::{ SamplerCache, SamplerDesc, TextureFiltering };
+pub use shadow::ShadowMap;
+pub use sky::{ Sky, Skybox };
+pub use staging::{ FrameRing, StagingRing };
+pub use streaming::ChunkLoader;
+pub use text::TextRenderer;
+pub use texture::Texture;
+pub use time_of_day::{ DayPhase, TimeOfDay };
+pub use timestep::{ FixedTimestep, Interpolated };
+pub use transfer::{ TransferQueue, UploadCopy };
+pub use vertex_format::{ PackedVertex, VertexFormat };
+pub use view_mode::ViewMode;
+pub use world::{ BlockId, Chunk, ChunkCoord, Light, World };
+pub use worldgen::{ TerrainBlocks, WorldGenerator };
+
+/// Parses the command line, loads the settings, opens a window titled `title` at the requested
+/// size, picks a backend and adapter, and hands the resulting `GfxContext` to `runner`. Command
+/// line options win over the settings file. Exits the process with a message if any of that
+/// fails.
+///
+/// With `--headless` no window is opened, and the chapter draws into offscreen images instead.
+///
+/// The size is in logical pixels, so the window looks the same size on high DPI monitors. The
+/// swapchain works out the physical size from the window's current scale factor each time it's
+/// created.
+pub fn launch<R: Runner>(title: &str, runner: R) {
+    let args = Args::from_args();
+    logging::init(args.validation);
+    let config = Config::load(&args.config);
+
+    let backend = exit_on_error(
+        Backend::from_args_or_env(&args, config.settings().backend.as_ref().map(|s| s.as_str())),
+    );
+    info!("Using the {} backend", backend);
+    if args.validation {
+        validation::enable(backend);
+    }
+
+    // Headless runs never touch winit, since on a machine without a display even creating the
+    // event loop can fail
+    if args.headless {
+        let events = Events::headless(args.frames);
+        backend.run(runner, title, args, config, None, events);
+        return;
+    }
+
+    let events_loop = winit::EventsLoop::new();
+
+    let wb = winit::WindowBuilder::new()
+        .with_dimensions(winit::dpi::LogicalSize::new(
+            args.width.unwrap_or(config.settings().width) as f64,
+            args.height.unwrap_or(config.settings().height) as f64,
+        ))
+        .with_title(title);
+    
+    let window = exit_on_error(wb.build(&events_loop).map_err(RendererError::from));
+
+    backend.run(runner, title, args, config, Some(window), Events::windowed(events_loop));
+}
+
+/// Unwraps `result`, or prints the error and exits. Errors this early on are almost always down
+/// to the machine or the options it was run with, so a message is more use than a backtrace.
+pub fn exit_on_error<T>(result: Result<T>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,610 @@
+//! Light spreading through the world from the sky and from blocks that give it off.
+//!
+//! Every block has two light levels from 0 to `MAX_LIGHT`, kept in its chunk as a
+//! `world::Light`. Sunlight comes in at full strength through the top of the world and carries
+//! on straight down through air without fading, so anything with open sky above it is fully
+//! lit. Block light starts out at whatever a lamp gives off. Both spread from a lit block to
+//! its six neighbours, a level dimmer with each step and only through air, which is what lets
+//! light reach a little way into an overhang or down a shaft and fade out in a cave. Solid
+//! blocks are dark, apart from a lamp's own light. Transparent blocks like water let light
+//! through as well, but they dim even full sunlight, so it fades away going down into the deep.
+//!
+//! Spreading is a breadth-first flood fill. A chunk that's just been loaded starts out dark,
+//! and is filled from its own lamps, the sky if it's in the top layer, and the light already at
+//! the edges of the chunks around it, and its light spreads back out into those too. Changing a
+//! block only redoes the light around it: the light that could have come through the block is
+//! taken away with a second flood fill first, which stops where it meets light from somewhere
+//! else, and then that light is spread back in.
+//!
+//! Chunks that aren't loaded let no light through. It comes in from around them once they are.
+
+use std::collections::{ HashMap, VecDeque };
+
+use world::{
+    surrounding_index, surrounding_offsets, BlockId, ChunkCoord, Direction, Light, World, CHUNK_SIZE, MAX_LIGHT,
+};
+
+/// How much light each type of block gives off, and which let it through.
+#[derive(Clone, Debug, Default)]
+pub struct BlockLights {
+    /// Indexed by `BlockId`.
+    emission: Vec<u8>,
+    /// Indexed by `BlockId`.
+    transparent: Vec<bool>,
+}
+
+impl BlockLights {
+    pub fn new() -> Self {
+        BlockLights::default()
+    }
+
+    /// Makes `block` give off light at `level`.
+    pub fn set_emission(&mut self, block: BlockId, level: u8) {
+        assert!(level <= MAX_LIGHT, "Light level {} is brighter than {}", level, MAX_LIGHT);
+        let index = block.0 as usize;
+        if index >= self.emission.len() {
+            self.emission.resize(index + 1, 0);
+        }
+        self.emission[index] = level;
+    }
+
+    /// How much light `block` gives off, which is none unless it's been given some.
+    pub fn emission(&self, block: BlockId) -> u8 {
+        self.emission.get(block.0 as usize).cloned().unwrap_or(0)
+    }
+
+    /// Lets light through `block` the way air does, apart from dimming full sunlight.
+    pub fn set_transparent(&mut self, block: BlockId) {
+        let index = block.0 as usize;
+        if index >= self.transparent.len() {
+            self.transparent.resize(index + 1, false);
+        }
+        self.transparent[index] = true;
+    }
+
+    /// Whether light spreads into `block`, which it does for air and transparent blocks.
+    pub fn lets_light_through(&self, block: BlockId) -> bool {
+        block.is_air() || self.transparent.get(block.0 as usize).cloned().unwrap_or(false)
+    }
+}
+
+/// Which of a block's two lights a flood fill is taking away.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+impl Channel {
+    fn get(self, light: Light) -> u8 {
+        match self {
+            Channel::Sky => light.sky(),
+            Channel::Block => light.block(),
+        }
+    }
+
+    fn with(self, light: Light, level: u8) -> Light {
+        match self {
+            Channel::Sky => Light::new(level, light.block()),
+            Channel::Block => Light::new(light.sky(), level),
+        }
+    }
+
+    /// How bright light at `level` is once it's spread a block in `direction`. Full sunlight
+    /// keeps going straight down.
+    fn spread(self, level: u8, direction: Direction) -> u8 {
+        if self == Channel::Sky && level == MAX_LIGHT && direction == Direction::NegY {
+            MAX_LIGHT
+        } else {
+            level.saturating_sub(1)
+        }
+    }
+}
+
+/// Flood fill work waiting to be done, kept by chunk, so that a fill looks each chunk up once
+/// and works through everything in it before moving on, rather than looking up the chunk again
+/// for every block.
+struct Pending<T> {
+    chunks: HashMap<ChunkCoord, VecDeque<([usize; 3], T)>>,
+}
+
+impl<T> Pending<T> {
+    fn new() -> Self {
+        Pending { chunks: HashMap::new() }
+    }
+
+    fn push(&mut self, position: [i32; 3], value: T) {
+        let (coord, local) = ChunkCoord::of_block(position);
+        self.push_local(coord, local, value);
+    }
+
+    fn push_local(&mut self, coord: ChunkCoord, local: [usize; 3], value: T) {
+        self.chunks.entry(coord).or_insert_with(VecDeque::new).push_back((local, value));
+    }
+
+    /// Takes the work waiting in one of the chunks.
+    fn take_chunk(&mut self) -> Option<(ChunkCoord, VecDeque<([usize; 3], T)>)> {
+        let coord = match self.chunks.keys().next() {
+            Some(&coord) => coord,
+            None => return None,
+        };
+        self.chunks.remove(&coord).map(|queue| (coord, queue))
+    }
+}
+
+/// Which of a chunk and the chunks around it touch a block whose light a fill has changed, to
+/// mark dirty once the fill is done with the chunk.
+struct Touched([bool; 27]);
+
+impl Touched {
+    fn new() -> Self {
+        Touched([false; 27])
+    }
+
+    fn touch(&mut self, local: [usize; 3]) {
+        let range = |n: usize| {
+            let low = if n == 0 { -1 } else { 0 };
+            let high = if n == CHUNK_SIZE - 1 { 1 } else { 0 };
+            low..high + 1
+        };
+        for y in range(local[1]) {
+            for z in range(local[2]) {
+                for x in range(local[0]) {
+                    self.0[surrounding_index([x, y, z])] = true;
+                }
+            }
+        }
+    }
+
+    fn mark_dirty(&self, world: &mut World, coord: ChunkCoord) {
+        for (index, offset) in surrounding_offsets().enumerate() {
+            if self.0[index] {
+                world.mark_dirty(coord.offset(offset));
+            }
+        }
+    }
+}
+
+/// Keeps the light in a world up to date as chunks are loaded and blocks change.
+pub struct Lighting {
+    blocks: BlockLights,
+    /// The layer of chunks just above the world. Sunlight comes in through the top of the
+    /// layer below it.
+    top: i32,
+}
+
+impl Lighting {
+    /// Lighting for a world whose highest chunks are at `top - 1`, with lamps from `blocks`.
+    pub fn new(blocks: BlockLights, top: i32) -> Self {
+        Lighting { blocks, top }
+    }
+
+    /// Redoes the light around the blocks that have changed in `world` since the last update,
+    /// and then lights the chunks added since. Call this after loading and editing, before
+    /// meshing, since it marks the chunks whose light changed dirty.
+    pub fn update(&self, world: &mut World) {
+        let unlit = world.take_unlit();
+        // Chunks about to be lit from scratch are lit with their changes already in them
+        let changes: Vec<[i32; 3]> = world
+            .take_block_changes()
+            .into_iter()
+            .filter(|&position| unlit.binary_search(&ChunkCoord::of_block(position).0).is_err())
+            .collect();
+        if !changes.is_empty() {
+            self.blocks_changed(world, &changes);
+        }
+        // They all start out dark, so that none of them spreads whatever light it came with
+        // into the others
+        for &coord in &unlit {
+            if let Some(chunk) = world.chunk_mut(coord) {
+                chunk.fill_light(Light::default());
+            }
+        }
+        for coord in unlit {
+            self.light_chunk(world, coord);
+        }
+    }
+
+    /// Works out the light of a newly loaded chunk, which has to be dark to start with, and
+    /// spreads it into the chunks around it.
+    fn light_chunk(&self, world: &mut World, coord: ChunkCoord) {
+        let mut pending = Pending::new();
+        match world.chunk_mut(coord) {
+            Some(chunk) => {
+                let lamps = chunk
+                    .block_counts()
+                    .iter()
+                    .any(|&(block, _)| self.blocks.emission(block) > 0);
+                if lamps {
+                    for y in 0..CHUNK_SIZE {
+                        for z in 0..CHUNK_SIZE {
+                            for x in 0..CHUNK_SIZE {
+                                let emission = self.blocks.emission(chunk.get([x, y, z]));
+                                if emission > 0 {
+                                    chunk.set_light([x, y, z], Light::new(0, emission));
+                                    pending.push_local(coord, [x, y, z], None);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            None => return,
+        }
+        if coord.y + 1 == self.top {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    pending.push_local(coord, [x, CHUNK_SIZE - 1, z], Some(Light::SKY));
+                }
+            }
+        }
+
+        // The blocks along the faces of the chunks around it, which spread in from outside
+        for &direction in &Direction::ALL {
+            let neighbor = coord.neighbor(direction);
+            if !world.contains_chunk(neighbor) {
+                continue;
+            }
+            let axis = direction.axis();
+            let (u_axis, v_axis) = ((axis + 1) % 3, (axis + 2) % 3);
+            let mut local = [0; 3];
+            if !direction.is_positive() {
+                local[axis] = CHUNK_SIZE - 1;
+            }
+            for v in 0..CHUNK_SIZE {
+                for u in 0..CHUNK_SIZE {
+                    local[u_axis] = u;
+                    local[v_axis] = v;
+                    pending.push_local(neighbor, local, None);
+                }
+            }
+        }
+
+        self.brighten(world, pending);
+        if let Some(chunk) = world.chunk_mut(coord) {
+            chunk.compact_light();
+        }
+    }
+
+    /// Redoes the light around the blocks at `positions` after they've changed.
+    fn blocks_changed(&self, world: &mut World, positions: &[[i32; 3]]) {
+        let mut relight = Pending::new();
+        self.darken(world, Channel::Sky, positions, &mut relight);
+        self.darken(world, Channel::Block, positions, &mut relight);
+
+        for &position in positions {
+            if let Some(block) = world.block(position) {
+                world.set_light(position, self.light_source(block, position[1]));
+            }
+            relight.push(position, None);
+            for &direction in &Direction::ALL {
+                relight.push(step(position, direction), None);
+            }
+        }
+        self.brighten(world, relight);
+    }
+
+    /// The light a block at height `y` has of its own, before anything spreads to it: a lamp's
+    /// light, and full sunlight for air along the top of the world.
+    fn light_source(&self, block: BlockId, y: i32) -> Light {
+        let top = self.top * CHUNK_SIZE as i32 - 1;
+        let sky = if block.is_air() && y == top { MAX_LIGHT } else { 0 };
+        Light::new(sky, self.blocks.emission(block))
+    }
+
+    /// Spreads light from `pending` to the blocks around, and theirs on to theirs, until it's
+    /// either too dim to go further or the blocks it reaches are already at least as bright.
+    /// `None` spreads a block's own light. `Some` is light reaching a block from next door,
+    /// which it takes if it lets light through and that's brighter than what it has.
+    fn brighten(&self, world: &mut World, mut pending: Pending<Option<Light>>) {
+        while let Some((coord, mut queue)) = pending.take_chunk() {
+            let mut touched = Touched::new();
+            {
+                let chunk = match world.chunk_mut(coord) {
+                    Some(chunk) => chunk,
+                    None => continue,
+                };
+                while let Some((local, offered)) = queue.pop_front() {
+                    let light = match offered {
+                        None => chunk.light(local),
+                        Some(offered) => {
+                            let target = chunk.get(local);
+                            if !self.blocks.lets_light_through(target) {
+                                continue;
+                            }
+                            // Full sunlight only carries on undimmed through air
+                            let offered = if !target.is_air() && offered.sky() == MAX_LIGHT {
+                                Light::new(MAX_LIGHT - 1, offered.block())
+                            } else {
+                                offered
+                            };
+                            let current = chunk.light(local);
+                            let sky = offered.sky().max(current.sky());
+                            let block = offered.block().max(current.block());
+                            let brighter = Light::new(sky, block);
+                            if brighter == current {
+                                continue;
+                            }
+                            chunk.set_light(local, brighter);
+                            touched.touch(local);
+                            brighter
+                        }
+                    };
+                    if light == Light::default() {
+                        continue;
+                    }
+
+                    let position = coord.block_position(local);
+                    for &direction in &Direction::ALL {
+                        let sky = Channel::Sky.spread(light.sky(), direction);
+                        let block = Channel::Block.spread(light.block(), direction);
+                        if sky == 0 && block == 0 {
+                            continue;
+                        }
+                        let (next_coord, next_local) = ChunkCoord::of_block(step(position, direction));
+                        let spread = Some(Light::new(sky, block));
+                        if next_coord == coord {
+                            queue.push_back((next_local, spread));
+                        } else {
+                            pending.push_local(next_coord, next_local, spread);
+                        }
+                    }
+                }
+            }
+            touched.mark_dirty(world, coord);
+        }
+    }
+
+    /// Takes away the `channel` light at `starts`, and any light around them that could have
+    /// spread from there. Blocks at the edge of the dark patch that are lit from somewhere else,
+    /// and lamps and sky in the middle of it, are added to `relight`, to spread their light
+    /// back in afterwards.
+    fn darken(&self, world: &mut World, channel: Channel, starts: &[[i32; 3]], relight: &mut Pending<Option<Light>>) {
+        // Each block comes with the brightest it could have been lit from the one before, and
+        // the starts are taken away whatever they are
+        let mut pending = Pending::new();
+        for &start in starts {
+            pending.push(start, MAX_LIGHT);
+        }
+
+        while let Some((coord, mut queue)) = pending.take_chunk() {
+            let mut touched = Touched::new();
+            {
+                let chunk = match world.chunk_mut(coord) {
+                    Some(chunk) => chunk,
+                    None => continue,
+                };
+                while let Some((local, brightest)) = queue.pop_front() {
+                    let light = chunk.light(local);
+                    let level = channel.get(light);
+                    if level == 0 {
+                        continue;
+                    }
+                    // Anything brighter than what could have come from before was lit by
+                    // something else
+                    if level > brightest {
+                        relight.push_local(coord, local, None);
+                        continue;
+                    }
+
+                    let position = coord.block_position(local);
+                    let source = channel.get(self.light_source(chunk.get(local), position[1]));
+                    chunk.set_light(local, channel.with(light, source));
+                    touched.touch(local);
+                    if source > 0 {
+                        relight.push_local(coord, local, None);
+                    }
+
+                    for &direction in &Direction::ALL {
+                        let (next_coord, next_local) = ChunkCoord::of_block(step(position, direction));
+                        let spread = channel.spread(level, direction);
+                        if next_coord == coord {
+                            queue.push_back((next_local, spread));
+                        } else {
+                            pending.push_local(next_coord, next_local, spread);
+                        }
+                    }
+                }
+            }
+            touched.mark_dirty(world, coord);
+        }
+    }
+}
+
+/// The block next to `position` in `direction`.
+fn step(position: [i32; 3], direction: Direction) -> [i32; 3] {
+    let offset = direction.offset();
+    [position[0] + offset[0], position[1] + offset[1], position[2] + offset[2]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::Chunk;
+
+    const STONE: BlockId = BlockId(1);
+    const LAMP: BlockId = BlockId(2);
+    const WATER: BlockId = BlockId(3);
+
+    fn lighting(top: i32) -> Lighting {
+        let mut blocks = BlockLights::new();
+        blocks.set_emission(LAMP, MAX_LIGHT);
+        blocks.set_transparent(WATER);
+        Lighting::new(blocks, top)
+    }
+
+    /// Every light in the chunks from `low` to `high`, by position.
+    fn lights(world: &World, low: [i32; 3], high: [i32; 3]) -> Vec<Light> {
+        let size = CHUNK_SIZE as i32;
+        let mut lights = Vec::new();
+        for y in low[1] * size..high[1] * size + size {
+            for z in low[2] * size..high[2] * size + size {
+                for x in low[0] * size..high[0] * size + size {
+                    lights.push(world.light([x, y, z]).unwrap());
+                }
+            }
+        }
+        lights
+    }
+
+    /// A 2 by 2 by 2 block of chunks from 0 to 1, with stone up to and including `ground`.
+    fn ground(ground: i32) -> Vec<(ChunkCoord, Chunk)> {
+        let mut chunks = Vec::new();
+        for y in 0..2 {
+            for z in 0..2 {
+                for x in 0..2 {
+                    let coord = ChunkCoord::new(x, y, z);
+                    let mut chunk = Chunk::new();
+                    for local_y in 0..CHUNK_SIZE {
+                        if coord.block_position([0, local_y, 0])[1] <= ground {
+                            for local_z in 0..CHUNK_SIZE {
+                                for local_x in 0..CHUNK_SIZE {
+                                    chunk.set([local_x, local_y, local_z], STONE);
+                                }
+                            }
+                        }
+                    }
+                    chunks.push((coord, chunk));
+                }
+            }
+        }
+        chunks
+    }
+
+    fn lit_world(chunks: Vec<(ChunkCoord, Chunk)>, lighting: &Lighting) -> World {
+        let mut world = World::new();
+        for (coord, chunk) in chunks {
+            world.insert_chunk(coord, chunk);
+        }
+        lighting.update(&mut world);
+        world
+    }
+
+    #[test]
+    fn open_sky_lights_the_ground_fully() {
+        let world = lit_world(ground(20), &lighting(2));
+        assert_eq!(world.light([10, 63, 10]), Some(Light::SKY));
+        assert_eq!(world.light([40, 21, 50]), Some(Light::SKY));
+        assert_eq!(world.light([40, 20, 50]), Some(Light::default()));
+    }
+
+    #[test]
+    fn light_fades_under_a_roof() {
+        let lighting = lighting(2);
+        let mut world = lit_world(ground(20), &lighting);
+        // A roof from x = 0 to 31 at y = 30, open to the sky from x = 32 on
+        for z in 0..64 {
+            for x in 0..32 {
+                world.set_block([x, 30, z], STONE);
+            }
+        }
+        lighting.update(&mut world);
+        assert_eq!(world.light([32, 25, 10]).unwrap().sky(), MAX_LIGHT);
+        assert_eq!(world.light([31, 25, 10]).unwrap().sky(), MAX_LIGHT - 1);
+        assert_eq!(world.light([25, 25, 10]).unwrap().sky(), MAX_LIGHT - 7);
+        assert_eq!(world.light([10, 25, 10]).unwrap().sky(), 0);
+        assert_eq!(world.light([10, 31, 10]), Some(Light::SKY));
+    }
+
+    #[test]
+    fn lamps_light_the_dark_by_distance() {
+        // The sky is far above, past chunks that aren't loaded, so only the lamp lights anything
+        let lighting = lighting(10);
+        let mut world = lit_world(ground(-1), &lighting);
+        world.set_block([30, 30, 30], LAMP);
+        lighting.update(&mut world);
+        assert_eq!(world.light([30, 30, 30]), Some(Light::new(0, MAX_LIGHT)));
+        assert_eq!(world.light([31, 30, 30]), Some(Light::new(0, MAX_LIGHT - 1)));
+        assert_eq!(world.light([33, 28, 29]), Some(Light::new(0, MAX_LIGHT - 6)));
+        assert_eq!(world.light([40, 35, 30]), Some(Light::default()));
+
+        world.set_block([30, 30, 30], BlockId::AIR);
+        lighting.update(&mut world);
+        assert!(lights(&world, [0, 0, 0], [1, 1, 1]).iter().all(|&light| light == Light::default()));
+    }
+
+    #[test]
+    fn editing_and_undoing_puts_the_light_back() {
+        let lighting = lighting(2);
+        let mut world = lit_world(ground(20), &lighting);
+        world.set_block([35, 25, 35], LAMP);
+        lighting.update(&mut world);
+        let before = lights(&world, [0, 0, 0], [1, 1, 1]);
+
+        // A slab over the lamp, and a hole through the ground next to it
+        for z in 28..44 {
+            for x in 28..44 {
+                world.set_block([x, 27, z], STONE);
+            }
+        }
+        for y in 10..21 {
+            world.set_block([50, y, 50], BlockId::AIR);
+        }
+        lighting.update(&mut world);
+        assert!(world.light([36, 26, 36]).unwrap().sky() < MAX_LIGHT);
+        assert_eq!(world.light([50, 10, 50]).unwrap().sky(), MAX_LIGHT);
+        assert!(lights(&world, [0, 0, 0], [1, 1, 1]) != before);
+
+        for z in 28..44 {
+            for x in 28..44 {
+                world.set_block([x, 27, z], BlockId::AIR);
+            }
+        }
+        for y in 10..21 {
+            world.set_block([50, y, 50], STONE);
+        }
+        lighting.update(&mut world);
+        assert!(lights(&world, [0, 0, 0], [1, 1, 1]) == before);
+    }
+
+    #[test]
+    fn the_order_chunks_load_in_doesnt_matter() {
+        let lighting = lighting(2);
+        let mut chunks = ground(20);
+        chunks[3].1.set([5, 30, 5], LAMP);
+        chunks[6].1.set([1, 1, 1], LAMP);
+        let all_at_once = lit_world(chunks.clone(), &lighting);
+
+        // One at a time, bottom first, so light has to come down from above afterwards
+        let mut world = World::new();
+        for (coord, chunk) in chunks {
+            world.insert_chunk(coord, chunk);
+            lighting.update(&mut world);
+        }
+        assert!(lights(&world, [0, 0, 0], [1, 1, 1]) == lights(&all_at_once, [0, 0, 0], [1, 1, 1]));
+        assert_eq!(world.light([10, 21, 10]), Some(Light::SKY));
+    }
+
+    #[test]
+    fn sunlight_fades_going_down_through_water() {
+        let mut chunks = ground(20);
+        for &mut (coord, ref mut chunk) in &mut chunks {
+            for local_y in 0..CHUNK_SIZE {
+                let y = coord.block_position([0, local_y, 0])[1];
+                if y > 20 && y <= 30 {
+                    for local_z in 0..CHUNK_SIZE {
+                        for local_x in 0..CHUNK_SIZE {
+                            chunk.set([local_x, local_y, local_z], WATER);
+                        }
+                    }
+                }
+            }
+        }
+        let world = lit_world(chunks, &lighting(2));
+        assert_eq!(world.light([10, 31, 10]), Some(Light::SKY));
+        assert_eq!(world.light([10, 30, 10]).map(|light| light.sky()), Some(MAX_LIGHT - 1));
+        assert_eq!(world.light([10, 21, 10]).map(|light| light.sky()), Some(MAX_LIGHT - 10));
+        assert_eq!(world.light([10, 20, 10]).map(|light| light.sky()), Some(0));
+    }
+
+    #[test]
+    fn block_lights_default_to_none() {
+        let mut blocks = BlockLights::new();
+        blocks.set_emission(LAMP, 12);
+        assert_eq!(blocks.emission(LAMP), 12);
+        assert_eq!(blocks.emission(STONE), 0);
+        assert_eq!(blocks.emission(BlockId(200)), 0);
+        assert!(blocks.lets_light_through(BlockId::AIR));
+        assert!(!blocks.lets_light_through(LAMP));
+    }
+}
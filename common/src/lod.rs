@@ -0,0 +1,342 @@
+//! Meshing distant chunks with less detail, so the render distance can go further out.
+//!
+//! Past `lod_distance` chunks from the camera, counted across the ground like the render
+//! distance is, a chunk is meshed as if each 2 by 2 by 2 cell of its blocks were one block, and
+//! past twice that each 4 by 4 by 4 cell. The merged chunk is still a whole chunk, with every
+//! block in a cell set to the same thing, so the mesher doesn't need to know about cells at all:
+//! the greedy mesher merges each cell's faces into one quad on its own, which is where the
+//! triangles are saved. Chunks with less detail are always meshed greedily for that reason.
+//!
+//! Where chunks at two different levels meet, their shapes don't quite line up, and gaps would
+//! open between them. So a chunk is only meshed against the neighbours at its own level, with
+//! the rest left out as if they weren't loaded. The mesher closes a chunk off with faces along
+//! the sides it has no neighbours on, and those walls hang down past the surface on both sides of
+//! the seam, like skirts, covering whatever gap there is. They cost a few faces underground, and
+//! only along the seams.
+//!
+//! `LodTracker` remembers the detail each chunk was meshed with, so that when the camera moves
+//! into another chunk, the chunks whose level or neighbours' levels have changed can be meshed
+//! again.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use world::{ surrounding_index, surrounding_offsets, BlockId, Chunk, ChunkCoord, Light, CHUNK_SIZE };
+
+/// How much detail a chunk is meshed with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Lod {
+    /// Every block as it is.
+    Full,
+    /// 2 by 2 by 2 cells of blocks.
+    Half,
+    /// 4 by 4 by 4 cells of blocks.
+    Quarter,
+}
+
+impl Lod {
+    pub const ALL: [Lod; 3] = [Lod::Full, Lod::Half, Lod::Quarter];
+
+    /// How many blocks across each cell is.
+    pub fn cell_size(self) -> usize {
+        match self {
+            Lod::Full => 1,
+            Lod::Half => 2,
+            Lod::Quarter => 4,
+        }
+    }
+
+    /// The level for the chunk at `coord` with the camera in `center`. A `lod_distance` of 0
+    /// keeps every chunk at full detail.
+    pub fn for_chunk(center: ChunkCoord, coord: ChunkCoord, lod_distance: u32) -> Lod {
+        if lod_distance == 0 {
+            return Lod::Full;
+        }
+        let (dx, dz) = ((coord.x - center.x) as i64, (coord.z - center.z) as i64);
+        let distance_squared = dx * dx + dz * dz;
+        let lod_distance = lod_distance as i64;
+        if distance_squared <= lod_distance * lod_distance {
+            Lod::Full
+        } else if distance_squared <= 4 * lod_distance * lod_distance {
+            Lod::Half
+        } else {
+            Lod::Quarter
+        }
+    }
+}
+
+impl fmt::Display for Lod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Lod::Full => "full",
+            Lod::Half => "half",
+            Lod::Quarter => "quarter",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The level a chunk is meshed at, and which of the chunks around it it's meshed against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkDetail {
+    pub lod: Lod,
+    /// A bit for each of the 27 chunks around it, by `surrounding_index`, set where that chunk
+    /// is at the same level. Its own bit is always set.
+    pub same_level: u32,
+}
+
+impl ChunkDetail {
+    /// Full detail against every neighbour, which is what every chunk gets without LOD.
+    pub const FULL: ChunkDetail = ChunkDetail { lod: Lod::Full, same_level: (1 << 27) - 1 };
+
+    pub fn new(center: ChunkCoord, coord: ChunkCoord, lod_distance: u32) -> Self {
+        let lod = Lod::for_chunk(center, coord, lod_distance);
+        let mut same_level = 0;
+        for offset in surrounding_offsets() {
+            if Lod::for_chunk(center, coord.offset(offset), lod_distance) == lod {
+                same_level |= 1 << surrounding_index(offset);
+            }
+        }
+        ChunkDetail { lod, same_level }
+    }
+
+    /// Whether the chunk at `index` around this one, by `surrounding_index`, is at the same
+    /// level.
+    pub fn shares_level(&self, index: usize) -> bool {
+        self.same_level & 1 << index != 0
+    }
+}
+
+/// `chunk` with each of `lod`'s cells filled with one block. A cell at least half full is
+/// filled with whichever block is on top of the most columns in it, so the ground keeps its
+/// grass and sand; any less is air. Every block in a cell gets the brightest light in it.
+pub fn merge_cells(chunk: &Chunk, lod: Lod) -> Chunk {
+    let size = lod.cell_size();
+    if size == 1 {
+        return chunk.clone();
+    }
+
+    let cells = CHUNK_SIZE / size;
+    let mut merged = Chunk::new();
+    let mut tops: Vec<(BlockId, usize)> = Vec::new();
+    for cell_y in 0..cells {
+        for cell_z in 0..cells {
+            for cell_x in 0..cells {
+                let origin = [cell_x * size, cell_y * size, cell_z * size];
+                let mut solid = 0;
+                let (mut sky, mut block_light) = (0, 0);
+                tops.clear();
+                for z in origin[2]..origin[2] + size {
+                    for x in origin[0]..origin[0] + size {
+                        let mut top = None;
+                        for y in (origin[1]..origin[1] + size).rev() {
+                            let block = chunk.get([x, y, z]);
+                            if !block.is_air() {
+                                solid += 1;
+                                top = top.or(Some(block));
+                            }
+                            let light = chunk.light([x, y, z]);
+                            sky = sky.max(light.sky());
+                            block_light = block_light.max(light.block());
+                        }
+                        if let Some(top) = top {
+                            match tops.iter().position(|&(block, _)| block == top) {
+                                Some(index) => tops[index].1 += 1,
+                                None => tops.push((top, 1)),
+                            }
+                        }
+                    }
+                }
+
+                // The first block to reach the highest count wins a tie, so the result doesn't
+                // depend on anything but the blocks
+                let mut block = BlockId::AIR;
+                if solid * 2 >= size * size * size {
+                    let mut most = 0;
+                    for &(top, count) in &tops {
+                        if count > most {
+                            block = top;
+                            most = count;
+                        }
+                    }
+                }
+                let light = Light::new(sky, block_light);
+                for y in origin[1]..origin[1] + size {
+                    for z in origin[2]..origin[2] + size {
+                        for x in origin[0]..origin[0] + size {
+                            merged.set([x, y, z], block);
+                            merged.set_light([x, y, z], light);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    merged.compact_light();
+    merged
+}
+
+/// The detail each loaded chunk was last meshed with.
+pub struct LodTracker {
+    lod_distance: u32,
+    /// The chunk the camera was in at the last `update`, or `None` before the first.
+    center: Option<ChunkCoord>,
+    meshed: HashMap<ChunkCoord, ChunkDetail>,
+}
+
+impl LodTracker {
+    pub fn new(lod_distance: u32) -> Self {
+        LodTracker {
+            lod_distance,
+            center: None,
+            meshed: HashMap::new(),
+        }
+    }
+
+    /// The detail the chunk at `coord` should be meshed with now. Everything is at full detail
+    /// until the first `update`.
+    pub fn detail(&self, coord: ChunkCoord) -> ChunkDetail {
+        match self.center {
+            Some(center) => ChunkDetail::new(center, coord, self.lod_distance),
+            None => ChunkDetail::FULL,
+        }
+    }
+
+    /// The detail to mesh the chunk at `coord` with, remembered as what it was meshed with.
+    pub fn mesh(&mut self, coord: ChunkCoord) -> ChunkDetail {
+        let detail = self.detail(coord);
+        self.meshed.insert(coord, detail);
+        detail
+    }
+
+    /// Call once a frame with the chunk the camera is in. When that's changed, or the LOD
+    /// distance has, returns the chunks that were meshed with a different detail than they
+    /// should have now, for the caller to mesh again.
+    pub fn update(&mut self, center: ChunkCoord, lod_distance: u32) -> Vec<ChunkCoord> {
+        if self.center == Some(center) && self.lod_distance == lod_distance {
+            return Vec::new();
+        }
+        self.center = Some(center);
+        self.lod_distance = lod_distance;
+        let mut changed: Vec<ChunkCoord> = self
+            .meshed
+            .iter()
+            .filter(|&(&coord, &detail)| ChunkDetail::new(center, coord, lod_distance) != detail)
+            .map(|(&coord, _)| coord)
+            .collect();
+        changed.sort();
+        changed
+    }
+
+    /// Forgets the chunk at `coord`. Call this when a chunk is unloaded.
+    pub fn remove(&mut self, coord: ChunkCoord) {
+        self.meshed.remove(&coord);
+    }
+
+    /// How many of the meshed chunks are at each level, in the order of `Lod::ALL`.
+    pub fn counts(&self) -> [usize; 3] {
+        let mut counts = [0; 3];
+        for detail in self.meshed.values() {
+            counts[Lod::ALL.iter().position(|&lod| lod == detail.lod).unwrap()] += 1;
+        }
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STONE: BlockId = BlockId(1);
+    const GRASS: BlockId = BlockId(2);
+
+    #[test]
+    fn detail_drops_with_distance() {
+        let center = ChunkCoord::new(0, 1, 0);
+        let at = |x: i32, z: i32| Lod::for_chunk(center, ChunkCoord::new(x, 3, z), 4);
+        assert_eq!(at(0, 0), Lod::Full);
+        assert_eq!(at(4, 0), Lod::Full);
+        assert_eq!(at(3, 3), Lod::Half);
+        assert_eq!(at(0, -8), Lod::Half);
+        assert_eq!(at(9, 0), Lod::Quarter);
+        assert_eq!(Lod::for_chunk(center, ChunkCoord::new(100, 0, 0), 0), Lod::Full);
+    }
+
+    #[test]
+    fn chunks_only_share_a_level_with_neighbours_at_it() {
+        let center = ChunkCoord::new(0, 0, 0);
+        let detail = ChunkDetail::new(center, ChunkCoord::new(2, 0, 0), 2);
+        assert_eq!(detail.lod, Lod::Full);
+        assert!(detail.shares_level(surrounding_index([0, 0, 0])));
+        assert!(detail.shares_level(surrounding_index([-1, 0, 0])));
+        assert!(detail.shares_level(surrounding_index([0, 1, 0])));
+        assert!(!detail.shares_level(surrounding_index([1, 0, 0])));
+        assert!(!detail.shares_level(surrounding_index([0, 0, 1])));
+        assert_eq!(ChunkDetail::new(center, center, 2).same_level, ChunkDetail::FULL.same_level);
+    }
+
+    /// Stone up to `height`, with a layer of grass on top.
+    fn ground(height: usize) -> Chunk {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                for y in 0..height {
+                    chunk.set([x, y, z], STONE);
+                }
+                chunk.set([x, height, z], GRASS);
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn merged_cells_keep_what_is_on_top() {
+        let chunk = ground(10);
+        let half = merge_cells(&chunk, Lod::Half);
+        assert_eq!(half.get([0, 9, 0]), STONE);
+        // Half full, with grass on top of every column
+        assert_eq!(half.get([3, 10, 5]), GRASS);
+        assert_eq!(half.get([3, 11, 5]), GRASS);
+        assert_eq!(half.get([3, 12, 5]), BlockId::AIR);
+
+        let quarter = merge_cells(&chunk, Lod::Quarter);
+        assert_eq!(quarter.get([0, 7, 0]), STONE);
+        assert_eq!(quarter.get([0, 8, 0]), GRASS);
+        assert_eq!(quarter.get([0, 11, 0]), GRASS);
+        assert_eq!(quarter.get([0, 12, 0]), BlockId::AIR);
+    }
+
+    #[test]
+    fn mostly_empty_cells_merge_into_air() {
+        let mut chunk = Chunk::new();
+        chunk.set([5, 5, 5], STONE);
+        assert!(merge_cells(&chunk, Lod::Half).is_empty());
+
+        let mut lit = Chunk::new();
+        lit.fill_light(Light::new(0, 0));
+        lit.set_light([1, 1, 1], Light::new(9, 3));
+        let merged = merge_cells(&lit, Lod::Half);
+        assert_eq!(merged.light([0, 0, 0]), Light::new(9, 3));
+        assert_eq!(merged.light([2, 0, 0]), Light::new(0, 0));
+    }
+
+    #[test]
+    fn moving_remeshes_chunks_whose_detail_changed() {
+        let mut tracker = LodTracker::new(1);
+        assert!(tracker.update(ChunkCoord::new(0, 0, 0), 1).is_empty());
+        let near = ChunkCoord::new(1, 0, 0);
+        let far = ChunkCoord::new(2, 0, 0);
+        let unchanged = ChunkCoord::new(-5, 0, 0);
+        assert_eq!(tracker.mesh(near).lod, Lod::Full);
+        assert_eq!(tracker.mesh(far).lod, Lod::Half);
+        assert_eq!(tracker.mesh(unchanged).lod, Lod::Quarter);
+        assert_eq!(tracker.counts(), [1, 1, 1]);
+        assert!(tracker.update(ChunkCoord::new(0, 0, 0), 1).is_empty());
+
+        // `far` comes up to full detail, and `near` has it as a neighbour at its level now
+        assert_eq!(tracker.update(ChunkCoord::new(1, 0, 0), 1), [near, far]);
+        tracker.mesh(near);
+        tracker.remove(far);
+        assert_eq!(tracker.update(ChunkCoord::new(0, 0, 0), 1), [near]);
+    }
+}
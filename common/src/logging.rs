@@ -0,0 +1,29 @@
+//! Setting up the logger.
+//!
+//! Everything is logged through the `log` crate and printed by `env_logger`. By default that's
+//! info and up from our own crates and warnings and up from everything else, and `RUST_LOG`
+//! overrides it using the usual `env_logger` syntax, e.g. `RUST_LOG=renderer_common=debug` for
+//! swapchain, pipeline and upload details.
+
+use std::env;
+
+use env_logger;
+use log::LevelFilter;
+
+/// Crates whose log messages include validation output.
+const BACKEND_CRATES: [&str; 3] = ["gfx_backend_vulkan", "gfx_backend_dx12", "gfx_backend_metal"];
+
+/// Installs the logger. With `validation` on the backends log at debug level as well, since the
+/// validation layers report plenty of useful things as info and debug messages too.
+pub fn init(validation: bool) {
+    let mut builder = env_logger::Builder::new();
+    builder.filter(None, LevelFilter::Info);
+    for &name in &BACKEND_CRATES {
+        builder.filter(Some(name), if validation { LevelFilter::Debug } else { LevelFilter::Warn });
+    }
+    // Anything from RUST_LOG replaces the defaults above for the modules it mentions
+    if let Ok(filters) = env::var("RUST_LOG") {
+        builder.parse(&filters);
+    }
+    builder.init();
+}
@@ -0,0 +1,471 @@
+//! Vectors and matrices, and the graphics specific helpers built on them.
+//!
+//! The types themselves come from cgmath and are re-exported here, so chapters don't each need
+//! their own dependency on it. On top of those this adds projections that know which clip space
+//! they're targeting, `Transform` for placing things in the world, and `Frustum` for working out
+//! what a camera can see. The cameras, culling and shadows all build on these rather than doing
+//! their own matrix maths.
+//!
+//! Everything is right handed with +y up, and cameras look down -z in view space.
+
+use std::ops::Mul;
+
+pub use cgmath::{ Deg, InnerSpace, Matrix, Point3, Quaternion, Rad, Rotation, Rotation3,
+                  SquareMatrix, Vector3, Vector4, Zero };
+use cgmath;
+
+use backend::Backend;
+
+pub type Mat4 = cgmath::Matrix4<f32>;
+pub type Vec3 = Vector3<f32>;
+pub type Vec4 = Vector4<f32>;
+pub type Quat = Quaternion<f32>;
+
+/// A column major 4x4 matrix laid out like a GLSL `mat4`, for uniform and push constant
+/// structs. `Mat4` converts into one with `into()`.
+pub type ShaderMatrix = [[f32; 4]; 4];
+
+/// The range clip space depth is mapped into after the perspective divide.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthRange {
+    /// Vulkan, D3D12 and Metal: the near plane is at 0 and the far plane at 1.
+    ZeroToOne,
+    /// OpenGL: the near plane is at -1 and the far plane at 1.
+    NegativeOneToOne,
+}
+
+/// What a graphics API expects vertex shaders to output.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ClipSpace {
+    pub depth: DepthRange,
+    /// Whether +y in clip space points down the screen, as it does in Vulkan. D3D12, Metal and
+    /// OpenGL all have it pointing up.
+    pub y_down: bool,
+}
+
+impl ClipSpace {
+    pub const VULKAN: ClipSpace = ClipSpace { depth: DepthRange::ZeroToOne, y_down: true };
+    pub const DIRECT3D: ClipSpace = ClipSpace { depth: DepthRange::ZeroToOne, y_down: false };
+    pub const OPENGL: ClipSpace = ClipSpace { depth: DepthRange::NegativeOneToOne, y_down: false };
+
+    /// The clip space shaders have to write to on `backend`.
+    ///
+    /// gfx-hal follows Vulkan's conventions on every backend. D3D12 and Metal have y pointing up
+    /// in clip space, but gfx translates our SPIR-V for them with spirv-cross set to flip y at the
+    /// end of each vertex shader, which undoes the difference. Their depth ranges already match
+    /// Vulkan's. So this is the same everywhere, and projections built for it work unchanged on
+    /// all three backends; anything drawing with a different API would need one of the others.
+    pub fn for_backend(backend: Backend) -> ClipSpace {
+        match backend {
+            Backend::Vulkan | Backend::Dx12 | Backend::Metal => ClipSpace::VULKAN,
+        }
+    }
+
+    /// Which way up y is, as a factor to multiply it by coming from y up view space.
+    fn y_sign(&self) -> f32 {
+        if self.y_down { -1.0 } else { 1.0 }
+    }
+}
+
+/// The clip space of every backend gfx-hal supports. See `ClipSpace::for_backend`.
+pub const HAL_CLIP_SPACE: ClipSpace = ClipSpace::VULKAN;
+
+/// A perspective projection with a vertical field of view of `fov_y`, for a viewport `aspect`
+/// times wider than it is tall, that maps view space depths from `-near` to `-far` onto `clip`'s
+/// depth range.
+pub fn perspective(fov_y: Rad<f32>, aspect: f32, near: f32, far: f32, clip: ClipSpace) -> Mat4 {
+    let f = 1.0 / (fov_y.0 / 2.0).tan();
+    let (depth_scale, depth_offset) = match clip.depth {
+        DepthRange::ZeroToOne => (far / (near - far), near * far / (near - far)),
+        DepthRange::NegativeOneToOne => {
+            ((far + near) / (near - far), 2.0 * far * near / (near - far))
+        }
+    };
+    Mat4::new(
+        f / aspect, 0.0, 0.0, 0.0,
+        0.0, f * clip.y_sign(), 0.0, 0.0,
+        0.0, 0.0, depth_scale, -1.0,
+        0.0, 0.0, depth_offset, 0.0,
+    )
+}
+
+/// An orthographic projection of the box from `left` to `right`, `bottom` to `top`, and `-near`
+/// to `-far` in view space, for directional light shadows and anything else without
+/// perspective.
+pub fn orthographic(
+    left: f32,
+    right: f32,
+    bottom: f32,
+    top: f32,
+    near: f32,
+    far: f32,
+    clip: ClipSpace,
+) -> Mat4 {
+    let y_sign = clip.y_sign();
+    let (depth_scale, depth_offset) = match clip.depth {
+        DepthRange::ZeroToOne => (1.0 / (near - far), near / (near - far)),
+        DepthRange::NegativeOneToOne => (2.0 / (near - far), (far + near) / (near - far)),
+    };
+    let x_offset = -(right + left) / (right - left);
+    let y_offset = -y_sign * (top + bottom) / (top - bottom);
+    Mat4::new(
+        2.0 / (right - left), 0.0, 0.0, 0.0,
+        0.0, y_sign * 2.0 / (top - bottom), 0.0, 0.0,
+        0.0, 0.0, depth_scale, 0.0,
+        x_offset, y_offset, depth_offset, 1.0,
+    )
+}
+
+/// The view matrix for a camera at `eye` looking along `direction`, with `up` roughly up.
+pub fn look_along(eye: [f32; 3], direction: Vec3, up: Vec3) -> Mat4 {
+    Mat4::look_at_dir(Point3::from(eye), direction, up)
+}
+
+/// Where something is, which way it's facing and how big it is. Scaling is uniform, which keeps
+/// composing them simple and means normals never need a separate matrix.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: Vec3,
+    pub rotation: Quat,
+    pub scale: f32,
+}
+
+impl Transform {
+    /// Leaves everything where it is.
+    pub fn identity() -> Self {
+        Transform {
+            translation: Vec3::zero(),
+            rotation: Quat::from_angle_y(Rad(0.0)),
+            scale: 1.0,
+        }
+    }
+
+    pub fn from_translation(translation: [f32; 3]) -> Self {
+        Transform { translation: translation.into(), ..Transform::identity() }
+    }
+
+    pub fn from_rotation(rotation: Quat) -> Self {
+        Transform { rotation, ..Transform::identity() }
+    }
+
+    /// Scales, then rotates, then translates, as a matrix for the shaders.
+    pub fn matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.translation)
+            * Mat4::from(self.rotation)
+            * Mat4::from_scale(self.scale)
+    }
+
+    pub fn transform_point(&self, point: [f32; 3]) -> [f32; 3] {
+        (self.transform_vector(point.into()) + self.translation).into()
+    }
+
+    /// Rotates and scales `vector` without translating it, for directions and offsets.
+    pub fn transform_vector(&self, vector: Vec3) -> Vec3 {
+        self.rotation.rotate_vector(vector * self.scale)
+    }
+
+    /// The transform that undoes this one.
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.invert();
+        let scale = 1.0 / self.scale;
+        Transform {
+            translation: rotation.rotate_vector(-self.translation * scale),
+            rotation,
+            scale,
+        }
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform::identity()
+    }
+}
+
+/// `parent * child` places `child` relative to `parent`: the result applies `child` first, then
+/// `parent`, the same way round as multiplying their matrices.
+impl Mul for Transform {
+    type Output = Transform;
+
+    fn mul(self, child: Transform) -> Transform {
+        Transform {
+            translation: self.transform_vector(child.translation) + self.translation,
+            rotation: self.rotation * child.rotation,
+            scale: self.scale * child.scale,
+        }
+    }
+}
+
+/// A plane where `normal · p + distance` is 0, with `normal` a unit vector. Points on the side
+/// `normal` points to are in front of it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: Vec3,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// The plane `x * a + y * b + z * c + w = 0` for `coefficients` `(a, b, c, w)`, scaled so
+    /// the normal has unit length.
+    pub fn from_coefficients(coefficients: Vec4) -> Self {
+        let normal = coefficients.truncate();
+        let length = normal.magnitude();
+        Plane { normal: normal / length, distance: coefficients.w / length }
+    }
+
+    /// How far `point` is in front of the plane, negative if it's behind.
+    pub fn signed_distance(&self, point: [f32; 3]) -> f32 {
+        self.normal.dot(point.into()) + self.distance
+    }
+
+    /// Whether any part of `aabb` is in front of the plane, or on it. Only the corner furthest
+    /// along the normal needs checking: if that one's behind, they all are.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let mut furthest = aabb.min;
+        for axis in 0..3 {
+            if self.normal[axis] >= 0.0 {
+                furthest[axis] = aabb.max[axis];
+            }
+        }
+        self.signed_distance(furthest) >= 0.0
+    }
+}
+
+/// A box lined up with the axes, from `min` to `max`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    pub fn new(min: [f32; 3], max: [f32; 3]) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn center(&self) -> [f32; 3] {
+        [
+            (self.min[0] + self.max[0]) * 0.5,
+            (self.min[1] + self.max[1]) * 0.5,
+            (self.min[2] + self.max[2]) * 0.5,
+        ]
+    }
+
+    pub fn contains_point(&self, point: [f32; 3]) -> bool {
+        (0..3).all(|axis| point[axis] >= self.min[axis] && point[axis] <= self.max[axis])
+    }
+
+    pub fn size(&self) -> [f32; 3] {
+        [self.max[0] - self.min[0], self.max[1] - self.min[1], self.max[2] - self.min[2]]
+    }
+
+    pub fn corners(&self) -> [[f32; 3]; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            [min[0], min[1], min[2]],
+            [max[0], min[1], min[2]],
+            [min[0], max[1], min[2]],
+            [max[0], max[1], min[2]],
+            [min[0], min[1], max[2]],
+            [max[0], min[1], max[2]],
+            [min[0], max[1], max[2]],
+            [max[0], max[1], max[2]],
+        ]
+    }
+
+    /// The smallest box holding this one after it's been moved by `transform`. Rotating a box
+    /// makes it bigger, so this is only tight for transforms that don't rotate.
+    pub fn transformed(&self, transform: &Transform) -> Aabb {
+        let corners = self.corners();
+        let first = transform.transform_point(corners[0]);
+        let mut result = Aabb { min: first, max: first };
+        for &corner in &corners[1..] {
+            let point = transform.transform_point(corner);
+            for axis in 0..3 {
+                result.min[axis] = result.min[axis].min(point[axis]);
+                result.max[axis] = result.max[axis].max(point[axis]);
+            }
+        }
+        result
+    }
+}
+
+/// The six planes bounding what a camera can see, each facing inwards.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far, in that order. With y down in clip space, bottom
+    /// and top swap over on screen, which doesn't matter for anything the frustum is used for.
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Pulls the planes out of a view projection matrix for `clip` (Gribb and Hartmann's method).
+    /// A point is inside when each clip space coordinate is between `-w` and `w`, or `0` and `w`
+    /// for depth in a zero to one range, and each of those comparisons is a plane.
+    pub fn from_matrix(view_projection: &Mat4, clip: ClipSpace) -> Self {
+        let rows = [
+            view_projection.row(0),
+            view_projection.row(1),
+            view_projection.row(2),
+            view_projection.row(3),
+        ];
+        let near = match clip.depth {
+            DepthRange::ZeroToOne => rows[2],
+            DepthRange::NegativeOneToOne => rows[3] + rows[2],
+        };
+        Frustum {
+            planes: [
+                Plane::from_coefficients(rows[3] + rows[0]),
+                Plane::from_coefficients(rows[3] - rows[0]),
+                Plane::from_coefficients(rows[3] + rows[1]),
+                Plane::from_coefficients(rows[3] - rows[1]),
+                Plane::from_coefficients(near),
+                Plane::from_coefficients(rows[3] - rows[2]),
+            ],
+        }
+    }
+
+    /// Whether `point` is inside all six planes.
+    pub fn contains_point(&self, point: [f32; 3]) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(point) >= 0.0)
+    }
+
+    /// Whether any part of the sphere might be visible. Spheres just outside a corner of the
+    /// frustum can pass, which is fine for culling: it only has to never reject anything
+    /// visible.
+    pub fn intersects_sphere(&self, center: [f32; 3], radius: f32) -> bool {
+        self.planes.iter().all(|plane| plane.signed_distance(center) >= -radius)
+    }
+
+    /// Whether any part of `aabb` might be visible. Like `intersects_sphere` this is
+    /// conservative: a box that's outside the frustum but not wholly behind any one plane, just
+    /// past a corner, still passes.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| plane.intersects_aabb(aabb))
+    }
+}
+
+/// The world space corners of what `view_projection` sees, in the order `Aabb::corners` gives
+/// them for a box from the near bottom left of `clip` to its far top right. `None` if the matrix
+/// can't be inverted.
+pub fn frustum_corners(view_projection: &Mat4, clip: ClipSpace) -> Option<[[f32; 3]; 8]> {
+    let inverse = view_projection.invert()?;
+    let near = match clip.depth {
+        DepthRange::ZeroToOne => 0.0,
+        DepthRange::NegativeOneToOne => -1.0,
+    };
+    let mut corners = Aabb::new([-1.0, -1.0, near], [1.0, 1.0, 1.0]).corners();
+    for corner in corners.iter_mut() {
+        let world = inverse * Vec4::new(corner[0], corner[1], corner[2], 1.0);
+        *corner = [world.x / world.w, world.y / world.w, world.z / world.w];
+    }
+    Some(corners)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a camera at the origin looking down -z sees, with a 90 degree field of view so the
+    /// side planes are at 45 degrees.
+    fn test_frustum() -> Frustum {
+        let projection = perspective(Deg(90.0).into(), 1.0, 0.1, 100.0, HAL_CLIP_SPACE);
+        let view = look_along([0.0; 3], -Vec3::unit_z(), Vec3::unit_y());
+        Frustum::from_matrix(&(projection * view), HAL_CLIP_SPACE)
+    }
+
+    fn unit_box_at(center: [f32; 3]) -> Aabb {
+        Aabb::new(
+            [center[0] - 0.5, center[1] - 0.5, center[2] - 0.5],
+            [center[0] + 0.5, center[1] + 0.5, center[2] + 0.5],
+        )
+    }
+
+    #[test]
+    fn plane_keeps_boxes_in_front_and_across() {
+        let plane = Plane { normal: Vec3::unit_x(), distance: 0.0 };
+        assert!(plane.intersects_aabb(&unit_box_at([2.0, 0.0, 0.0])));
+        assert!(plane.intersects_aabb(&unit_box_at([0.25, 0.0, 0.0])));
+        assert!(plane.intersects_aabb(&unit_box_at([-0.5, 0.0, 0.0])));
+        assert!(!plane.intersects_aabb(&unit_box_at([-2.0, 0.0, 0.0])));
+    }
+
+    #[test]
+    fn plane_uses_the_corner_furthest_along_a_diagonal_normal() {
+        let normal = Vec3::new(1.0, 1.0, 0.0).normalize();
+        let plane = Plane { normal, distance: 0.0 };
+        // Centred behind the plane, but its top right corner pokes through
+        assert!(plane.intersects_aabb(&unit_box_at([-0.3, -0.3, 0.0])));
+        assert!(!plane.intersects_aabb(&unit_box_at([-0.6, -0.6, 0.0])));
+    }
+
+    #[test]
+    fn frustum_planes_face_inwards() {
+        let frustum = test_frustum();
+        for plane in &frustum.planes {
+            assert!(plane.signed_distance([0.0, 0.0, -10.0]) > 0.0);
+            assert!((plane.normal.magnitude() - 1.0).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn frustum_keeps_boxes_in_view() {
+        let frustum = test_frustum();
+        assert!(frustum.intersects_aabb(&unit_box_at([0.0, 0.0, -5.0])));
+        assert!(frustum.intersects_aabb(&unit_box_at([4.0, -4.0, -5.0])));
+    }
+
+    #[test]
+    fn frustum_keeps_boxes_crossing_its_edges() {
+        let frustum = test_frustum();
+        // Straddling the left plane, the near plane and the far plane
+        assert!(frustum.intersects_aabb(&unit_box_at([-5.2, 0.0, -5.0])));
+        assert!(frustum.intersects_aabb(&unit_box_at([0.0, 0.0, 0.0])));
+        assert!(frustum.intersects_aabb(&unit_box_at([0.0, 0.0, -100.0])));
+    }
+
+    #[test]
+    fn frustum_culls_boxes_out_of_view() {
+        let frustum = test_frustum();
+        assert!(!frustum.intersects_aabb(&unit_box_at([0.0, 0.0, 5.0])));
+        assert!(!frustum.intersects_aabb(&unit_box_at([10.0, 0.0, -5.0])));
+        assert!(!frustum.intersects_aabb(&unit_box_at([0.0, 10.0, -5.0])));
+        assert!(!frustum.intersects_aabb(&unit_box_at([0.0, 0.0, -101.0])));
+    }
+
+    #[test]
+    fn frustum_corners_are_on_the_near_and_far_planes() {
+        let projection = perspective(Deg(90.0).into(), 1.0, 0.1, 100.0, HAL_CLIP_SPACE);
+        let view = look_along([0.0; 3], -Vec3::unit_z(), Vec3::unit_y());
+        let corners = frustum_corners(&(projection * view), HAL_CLIP_SPACE).unwrap();
+        for (index, corner) in corners.iter().enumerate() {
+            let depth = if index < 4 { 0.1 } else { 100.0 };
+            assert!((corner[2] + depth).abs() < depth * 1e-3, "{:?}", corner);
+            // At 45 degrees to the middle, on every side
+            assert!((corner[0].abs() - depth).abs() < depth * 1e-3, "{:?}", corner);
+            assert!((corner[1].abs() - depth).abs() < depth * 1e-3, "{:?}", corner);
+        }
+    }
+
+    #[test]
+    fn frustum_works_with_opengl_depth() {
+        let projection = perspective(Deg(90.0).into(), 1.0, 0.1, 100.0, ClipSpace::OPENGL);
+        let frustum = Frustum::from_matrix(&projection, ClipSpace::OPENGL);
+        assert!(frustum.intersects_aabb(&unit_box_at([0.0, 0.0, -5.0])));
+        assert!(!frustum.intersects_aabb(&unit_box_at([0.0, 0.0, 5.0])));
+        assert!(!frustum.intersects_aabb(&unit_box_at([0.0, 0.0, -101.0])));
+    }
+
+    #[test]
+    fn transformed_box_contains_rotated_corners() {
+        let transform = Transform {
+            rotation: Quat::from_angle_y(Deg(45.0)),
+            ..Transform::from_translation([1.0, 2.0, 3.0])
+        };
+        let aabb = unit_box_at([0.0; 3]).transformed(&transform);
+        let half_diagonal = 0.5 * 2.0f32.sqrt();
+        assert!((aabb.max[0] - (1.0 + half_diagonal)).abs() < 1e-5);
+        assert!((aabb.min[2] - (3.0 - half_diagonal)).abs() < 1e-5);
+        assert!((aabb.max[1] - 2.5).abs() < 1e-5);
+    }
+}
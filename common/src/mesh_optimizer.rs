@@ -0,0 +1,513 @@
+//! Reordering chunk meshes so the gpu draws them faster, without changing what they look like.
+//!
+//! The meshers emit quads in the order they find faces, which is fine for correctness but not
+//! for the gpu. `optimize` runs three passes over a `ChunkMesh` after it's made:
+//!
+//! - Vertices that are the same in every attribute are merged, so a corner shared by two
+//!   triangles is only shaded once.
+//! - The triangles are reordered so they reuse vertices that were shaded recently, using Tom
+//!   Forsyth's linear-speed vertex cache optimisation. The post-transform cache only remembers
+//!   the last few vertices, so the order decides how many are shaded again.
+//! - Runs of opaque triangles that start with a cold cache are sorted so the ones facing out from
+//!   the middle of the chunk come first. They're the ones most likely to hide the rest, so fewer
+//!   fragments get shaded and then thrown away by the depth test.
+//!
+//! Finally the vertices are put in the order the triangles first use them, so fetching them
+//! walks through the vertex buffer instead of jumping around it.
+//!
+//! How well it worked is measured as the ACMR, the average number of cache misses per triangle
+//! with a `VERTEX_CACHE_SIZE` entry first in, first out cache. Two triangles that share nothing
+//! can't do better than 3, and a quad of four vertices can't do better than 2.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::ops::AddAssign;
+
+use mesher::{ ChunkMesh, ChunkVertex };
+
+/// How many vertices the post-transform cache is assumed to hold, for both reordering and
+/// measuring. Real caches vary, but they're rarely smaller.
+pub const VERTEX_CACHE_SIZE: usize = 16;
+
+// Forsyth's tuning for how a vertex's score falls off with its age in the cache and rises as it
+// has fewer triangles left to draw.
+const CACHE_DECAY_POWER: f32 = 1.5;
+const LAST_TRIANGLE_SCORE: f32 = 0.75;
+const VALENCE_BOOST_SCALE: f32 = 2.0;
+const VALENCE_BOOST_POWER: f32 = 0.5;
+
+/// How big a mesh is, and how well its triangles use the vertex cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshStats {
+    pub triangles: usize,
+    pub vertices: usize,
+    /// How many vertices a `VERTEX_CACHE_SIZE` cache would miss drawing the triangles in order.
+    pub cache_misses: usize,
+}
+
+impl MeshStats {
+    /// Measures every triangle list in `mesh`, drawn one after the other.
+    pub fn of(mesh: &ChunkMesh) -> MeshStats {
+        let mut cache = FifoCache::new(VERTEX_CACHE_SIZE);
+        let mut cache_misses = 0;
+        for indices in &[&mesh.indices, &mesh.translucent_indices, &mesh.water_indices] {
+            cache_misses += indices.iter().filter(|&&index| !cache.touch(index)).count();
+        }
+        MeshStats {
+            triangles: mesh.triangle_count(),
+            vertices: mesh.vertices.len(),
+            cache_misses,
+        }
+    }
+
+    /// The average cache misses per triangle, or 0 for an empty mesh.
+    pub fn acmr(&self) -> f32 {
+        if self.triangles == 0 {
+            0.0
+        } else {
+            self.cache_misses as f32 / self.triangles as f32
+        }
+    }
+}
+
+impl AddAssign for MeshStats {
+    fn add_assign(&mut self, other: MeshStats) {
+        self.triangles += other.triangles;
+        self.vertices += other.vertices;
+        self.cache_misses += other.cache_misses;
+    }
+}
+
+/// What `optimize` did to a mesh.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MeshOptimization {
+    pub before: MeshStats,
+    pub after: MeshStats,
+}
+
+impl AddAssign for MeshOptimization {
+    fn add_assign(&mut self, other: MeshOptimization) {
+        self.before += other.before;
+        self.after += other.after;
+    }
+}
+
+/// Runs every pass over `mesh`. It draws the same triangles afterwards, facing the same way,
+/// with the translucent and water faces still in their own lists.
+pub fn optimize(mesh: &mut ChunkMesh) -> MeshOptimization {
+    let before = MeshStats::of(mesh);
+    deduplicate_vertices(mesh);
+    let vertex_count = mesh.vertices.len();
+    optimize_vertex_cache(&mut mesh.indices, vertex_count);
+    optimize_overdraw(&mut mesh.indices, &mesh.vertices);
+    // Blending already leaves the order of a chunk's translucent faces up to chance, so they're
+    // only reordered for the cache, not sorted
+    optimize_vertex_cache(&mut mesh.translucent_indices, vertex_count);
+    optimize_vertex_cache(&mut mesh.water_indices, vertex_count);
+    optimize_vertex_fetch(mesh);
+    MeshOptimization {
+        before,
+        after: MeshStats::of(mesh),
+    }
+}
+
+/// The bits of every attribute, so vertices can be compared and hashed exactly.
+fn vertex_key(vertex: &ChunkVertex) -> [u32; 13] {
+    [
+        vertex.position[0].to_bits(),
+        vertex.position[1].to_bits(),
+        vertex.position[2].to_bits(),
+        vertex.normal[0].to_bits(),
+        vertex.normal[1].to_bits(),
+        vertex.normal[2].to_bits(),
+        vertex.uv[0].to_bits(),
+        vertex.uv[1].to_bits(),
+        vertex.tile,
+        vertex.ao.to_bits(),
+        vertex.light[0].to_bits(),
+        vertex.light[1].to_bits(),
+        vertex.water_depth.to_bits(),
+    ]
+}
+
+/// Merges vertices that are the same in every attribute, pointing the indices at the one kept.
+pub fn deduplicate_vertices(mesh: &mut ChunkMesh) {
+    let mut unique: HashMap<[u32; 13], u32> = HashMap::with_capacity(mesh.vertices.len());
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+    let mut remap = Vec::with_capacity(mesh.vertices.len());
+    for vertex in &mesh.vertices {
+        let next = vertices.len() as u32;
+        let index = *unique.entry(vertex_key(vertex)).or_insert(next);
+        if index == next {
+            vertices.push(*vertex);
+        }
+        remap.push(index);
+    }
+    mesh.vertices = vertices;
+    for indices in &mut [&mut mesh.indices, &mut mesh.translucent_indices, &mut mesh.water_indices] {
+        for index in indices.iter_mut() {
+            *index = remap[*index as usize];
+        }
+    }
+}
+
+/// A vertex's score in Forsyth's algorithm, from where it is in the cache, if it is, and how
+/// many triangles it still has to be drawn in. Finished vertices score -1 so they're never
+/// picked.
+fn vertex_score(cache_position: Option<usize>, remaining: u32) -> f32 {
+    if remaining == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        // The last triangle's vertices all score the same, so which of its edges is shared
+        // doesn't matter
+        Some(position) if position < 3 => LAST_TRIANGLE_SCORE,
+        Some(position) => {
+            let scale = 1.0 / (VERTEX_CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(CACHE_DECAY_POWER)
+        }
+        None => 0.0,
+    };
+    cache_score + VALENCE_BOOST_SCALE * (remaining as f32).powf(-VALENCE_BOOST_POWER)
+}
+
+/// Reorders the triangles in `indices`, which point into `vertex_count` vertices, to miss the
+/// vertex cache less. Each triangle keeps its corners in the same order, so it faces the same
+/// way.
+pub fn optimize_vertex_cache(indices: &mut [u32], vertex_count: usize) {
+    let triangle_count = indices.len() / 3;
+    if triangle_count == 0 {
+        return;
+    }
+
+    // Which triangles use each vertex, as ranges of one flat list
+    let mut remaining = vec![0u32; vertex_count];
+    for &index in indices.iter() {
+        remaining[index as usize] += 1;
+    }
+    let mut offsets = Vec::with_capacity(vertex_count + 1);
+    let mut total = 0;
+    for &count in &remaining {
+        offsets.push(total);
+        total += count as usize;
+    }
+    offsets.push(total);
+    let mut triangles_of = vec![0u32; total];
+    let mut filled = offsets.clone();
+    for (triangle, corners) in indices.chunks(3).enumerate() {
+        for &index in corners {
+            triangles_of[filled[index as usize]] = triangle as u32;
+            filled[index as usize] += 1;
+        }
+    }
+
+    let mut cache_position: Vec<Option<usize>> = vec![None; vertex_count];
+    let mut scores: Vec<f32> = remaining.iter().map(|&count| vertex_score(None, count)).collect();
+    let mut triangle_scores: Vec<f32> = indices
+        .chunks(3)
+        .map(|corners| corners.iter().map(|&index| scores[index as usize]).sum())
+        .collect();
+    let mut emitted = vec![false; triangle_count];
+    let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+    let mut ordered = Vec::with_capacity(indices.len());
+    // Where to look for a triangle to carry on from when nothing in the cache has any left
+    let mut next_unemitted = 0;
+
+    let mut best = Some(0);
+    while let Some(triangle) = best {
+        emitted[triangle] = true;
+        let corners = [indices[triangle * 3], indices[triangle * 3 + 1], indices[triangle * 3 + 2]];
+        ordered.extend_from_slice(&corners);
+
+        // Its corners go to the front of the cache, and whatever falls off the end is evicted
+        let mut new_cache = corners.to_vec();
+        new_cache.extend(cache.iter().cloned().filter(|index| !corners.contains(index)));
+        for &index in &corners {
+            remaining[index as usize] -= 1;
+        }
+        for (position, &index) in new_cache.iter().enumerate() {
+            cache_position[index as usize] = if position < VERTEX_CACHE_SIZE { Some(position) } else { None };
+        }
+
+        // Only the vertices that moved in the cache have new scores, and so do their triangles
+        let mut best_score = -1.0;
+        best = None;
+        for &index in &new_cache {
+            let index = index as usize;
+            let score = vertex_score(cache_position[index], remaining[index]);
+            let change = score - scores[index];
+            scores[index] = score;
+            for &other in &triangles_of[offsets[index]..offsets[index + 1]] {
+                let other = other as usize;
+                if emitted[other] {
+                    continue;
+                }
+                triangle_scores[other] += change;
+                if triangle_scores[other] > best_score {
+                    best_score = triangle_scores[other];
+                    best = Some(other);
+                }
+            }
+        }
+        new_cache.truncate(VERTEX_CACHE_SIZE);
+        cache = new_cache;
+
+        // A dead end, where nothing in the cache has triangles left, carries on from the first
+        // triangle that hasn't been drawn
+        if best.is_none() {
+            while next_unemitted < triangle_count && emitted[next_unemitted] {
+                next_unemitted += 1;
+            }
+            if next_unemitted < triangle_count {
+                best = Some(next_unemitted);
+            }
+        }
+    }
+
+    indices.copy_from_slice(&ordered);
+}
+
+/// Reorders the triangles in `indices` so the ones facing out from the middle of the mesh are
+/// drawn first, in runs that each start with a cache miss on every corner, so the runs can be
+/// moved around without adding many more. Run `optimize_vertex_cache` first.
+pub fn optimize_overdraw(indices: &mut [u32], vertices: &[ChunkVertex]) {
+    if indices.len() < 6 {
+        return;
+    }
+
+    // Split where a triangle shares nothing with what's in the cache
+    let mut cache = FifoCache::new(VERTEX_CACHE_SIZE);
+    let mut starts = Vec::new();
+    for (triangle, corners) in indices.chunks(3).enumerate() {
+        let misses = corners.iter().filter(|&&index| !cache.touch(index)).count();
+        if misses == 3 {
+            starts.push(triangle * 3);
+        }
+    }
+    let runs: Vec<&[u32]> = starts
+        .iter()
+        .zip(starts.iter().skip(1).chain(Some(&indices.len())))
+        .map(|(&start, &end)| &indices[start..end])
+        .collect();
+    if runs.len() < 2 {
+        return;
+    }
+
+    let mut middle = [0.0; 3];
+    for vertex in vertices {
+        for axis in 0..3 {
+            middle[axis] += vertex.position[axis] / vertices.len() as f32;
+        }
+    }
+
+    // How far out each run faces: its middle relative to the mesh's, along its average normal
+    let mut keyed: Vec<(f32, &[u32])> = runs
+        .into_iter()
+        .map(|run| {
+            let mut centre = [0.0; 3];
+            let mut normal = [0.0; 3];
+            for &index in run.iter() {
+                let vertex = &vertices[index as usize];
+                for axis in 0..3 {
+                    centre[axis] += vertex.position[axis] / run.len() as f32;
+                    normal[axis] += vertex.normal[axis];
+                }
+            }
+            let outwards = (0..3).map(|axis| (centre[axis] - middle[axis]) * normal[axis]).sum::<f32>()
+                / run.len() as f32;
+            (outwards, run)
+        })
+        .collect();
+    keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+    let ordered: Vec<u32> = keyed.iter().flat_map(|&(_, run)| run.iter().cloned()).collect();
+    indices.copy_from_slice(&ordered);
+}
+
+/// Puts the vertices in the order the triangles first use them, the opaque ones first, and
+/// drops any that nothing uses.
+pub fn optimize_vertex_fetch(mesh: &mut ChunkMesh) {
+    let mut remap: Vec<Option<u32>> = vec![None; mesh.vertices.len()];
+    let mut vertices = Vec::with_capacity(mesh.vertices.len());
+    for indices in &mut [&mut mesh.indices, &mut mesh.translucent_indices, &mut mesh.water_indices] {
+        for index in indices.iter_mut() {
+            let old = *index as usize;
+            *index = match remap[old] {
+                Some(new) => new,
+                None => {
+                    let new = vertices.len() as u32;
+                    vertices.push(mesh.vertices[old]);
+                    remap[old] = Some(new);
+                    new
+                }
+            };
+        }
+    }
+    mesh.vertices = vertices;
+}
+
+/// A first in, first out post-transform cache, the simplest kind and the usual one to measure
+/// against.
+struct FifoCache {
+    entries: Vec<u32>,
+    next: usize,
+}
+
+impl FifoCache {
+    fn new(size: usize) -> Self {
+        FifoCache {
+            entries: vec![u32::max_value(); size],
+            next: 0,
+        }
+    }
+
+    /// Looks `index` up, adding it if it wasn't there. Returns whether it was a hit.
+    fn touch(&mut self, index: u32) -> bool {
+        if self.entries.contains(&index) {
+            return true;
+        }
+        self.entries[self.next] = index;
+        self.next = (self.next + 1) % self.entries.len();
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atlas::BlockTextureId;
+    use mesher::{ BlockTextures, ChunkNeighborhood, Mesher };
+    use world::{ BlockId, Chunk, CHUNK_SIZE };
+
+    fn vertex(position: [f32; 3]) -> ChunkVertex {
+        ChunkVertex {
+            position,
+            normal: [0.0, 1.0, 0.0],
+            uv: [0.0, 0.0],
+            tile: 0,
+            ao: 1.0,
+            light: [1.0, 0.0],
+            water_depth: 0.0,
+        }
+    }
+
+    /// A flat grid of `size` by `size` quads that share their corners, with the triangles in
+    /// column order, which wastes the cache on every row.
+    fn grid(size: u32) -> ChunkMesh {
+        let mut mesh = ChunkMesh::new();
+        for z in 0..size + 1 {
+            for x in 0..size + 1 {
+                mesh.vertices.push(vertex([x as f32, 0.0, z as f32]));
+            }
+        }
+        let corner = |x: u32, z: u32| z * (size + 1) + x;
+        for x in 0..size {
+            for z in 0..size {
+                mesh.indices.extend_from_slice(&[corner(x, z), corner(x, z + 1), corner(x + 1, z + 1)]);
+                mesh.indices.extend_from_slice(&[corner(x + 1, z + 1), corner(x + 1, z), corner(x, z)]);
+            }
+        }
+        mesh
+    }
+
+    /// Every triangle as the positions of its corners, starting from the smallest so a triangle
+    /// that's only been rotated still matches, sorted.
+    fn triangles(mesh: &ChunkMesh, indices: &[u32]) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<[[u32; 3]; 3]> = indices
+            .chunks(3)
+            .map(|corners| {
+                let mut triangle = [[0; 3]; 3];
+                for (corner, &index) in triangle.iter_mut().zip(corners) {
+                    let position = mesh.vertices[index as usize].position;
+                    *corner = [position[0].to_bits(), position[1].to_bits(), position[2].to_bits()];
+                }
+                let first = (0..3).min_by_key(|&corner| triangle[corner]).unwrap();
+                [triangle[first], triangle[(first + 1) % 3], triangle[(first + 2) % 3]]
+            })
+            .collect();
+        triangles.sort();
+        triangles
+    }
+
+    #[test]
+    fn the_cache_counts_misses_first_in_first_out() {
+        let mut cache = FifoCache::new(2);
+        assert!(!cache.touch(1));
+        assert!(!cache.touch(2));
+        assert!(cache.touch(1));
+        assert!(!cache.touch(3));
+        assert!(!cache.touch(1));
+    }
+
+    #[test]
+    fn reordering_uses_the_cache_better() {
+        let mut mesh = grid(24);
+        let before = MeshStats::of(&mesh);
+        let vertex_count = mesh.vertices.len();
+        optimize_vertex_cache(&mut mesh.indices, vertex_count);
+        let after = MeshStats::of(&mesh);
+        assert_eq!(after.triangles, before.triangles);
+        assert!(after.acmr() < before.acmr() * 0.8, "{} then {}", before.acmr(), after.acmr());
+        assert!(after.acmr() < 1.0);
+    }
+
+    #[test]
+    fn duplicates_are_merged() {
+        let mut mesh = ChunkMesh::new();
+        mesh.vertices = vec![vertex([0.0; 3]), vertex([1.0, 0.0, 0.0]), vertex([0.0; 3]), vertex([0.0, 0.0, 1.0])];
+        mesh.indices = vec![0, 1, 3];
+        mesh.water_indices = vec![2, 3, 1];
+        deduplicate_vertices(&mut mesh);
+        assert_eq!(mesh.vertices.len(), 3);
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        assert_eq!(mesh.water_indices, vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn vertices_end_up_in_the_order_theyre_used() {
+        let mut mesh = ChunkMesh::new();
+        mesh.vertices = (0..5).map(|x| vertex([x as f32, 0.0, 0.0])).collect();
+        mesh.indices = vec![4, 2, 0];
+        optimize_vertex_fetch(&mut mesh);
+        assert_eq!(mesh.indices, vec![0, 1, 2]);
+        let order: Vec<f32> = mesh.vertices.iter().map(|vertex| vertex.position[0]).collect();
+        assert_eq!(order, vec![4.0, 2.0, 0.0]);
+    }
+
+    #[test]
+    fn optimizing_keeps_the_same_triangles() {
+        let mut chunk = Chunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for y in 0..3 + (x * 5 + z * 3) % 4 {
+                    chunk.set([x, y, z], BlockId(1 + (x / 3 + z) as u16 % 2));
+                }
+            }
+        }
+        let mut textures = BlockTextures::new();
+        textures.set_all(BlockId(1), BlockTextureId(3));
+        textures.set_all(BlockId(2), BlockTextureId(4));
+
+        for &mesher in &Mesher::ALL {
+            let original = mesher.mesh(&ChunkNeighborhood::new(&chunk), &textures);
+            let mut mesh = original.clone();
+            let optimization = optimize(&mut mesh);
+            assert_eq!(triangles(&mesh, &mesh.indices), triangles(&original, &original.indices));
+            assert_eq!(optimization.before, MeshStats::of(&original));
+            assert_eq!(optimization.after, MeshStats::of(&mesh));
+            assert_eq!(optimization.after.triangles, optimization.before.triangles);
+            assert!(optimization.after.vertices <= optimization.before.vertices);
+            assert!(optimization.after.acmr() <= optimization.before.acmr() + 0.01);
+        }
+    }
+
+    #[test]
+    fn stats_add_up() {
+        let mut total = MeshStats::default();
+        total += MeshStats { triangles: 2, vertices: 4, cache_misses: 4 };
+        total += MeshStats { triangles: 2, vertices: 3, cache_misses: 2 };
+        assert_eq!(total, MeshStats { triangles: 4, vertices: 7, cache_misses: 6 });
+        assert!((total.acmr() - 1.5).abs() < 1e-6);
+        assert!(MeshStats::default().acmr().abs() < 1e-6);
+    }
+}
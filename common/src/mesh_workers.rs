@@ -0,0 +1,340 @@
+//! Meshing chunks on worker threads.
+//!
+//! Meshing a chunk takes a few milliseconds, so when a lot of chunks change at once, like when
+//! they're first loaded, doing it all on the render thread drops frames. Instead the render
+//! thread sends jobs to a pool of workers over a channel, and picks finished meshes up from
+//! another channel once a frame. All it's left to do is upload them.
+//!
+//! A job holds shared copies of the chunks it needs, from `World::surrounding_shared`, so the
+//! world can keep changing while the job runs. When a chunk changes again or is unloaded before
+//! its job is done, the job is cancelled. Workers skip cancelled jobs they haven't started yet,
+//! and anything a cancelled job still finishes is thrown away when it comes back, so a chunk's
+//! newest mesh is the only one ever returned.
+//!
+//! Each job also has the detail its chunk is meshed with, from `lod::ChunkDetail`. The
+//! neighbours at other levels are left out of the job, and the worker merges the cells of the
+//! rest before meshing them.
+
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicBool, Ordering };
+use std::sync::Arc;
+use std::thread::{ self, JoinHandle };
+use std::time::Instant;
+
+use crossbeam_channel::{ self as channel, Receiver, Sender };
+use num_cpus;
+
+use error::Result;
+use lod::{ merge_cells, ChunkDetail, Lod };
+use mesher::{ BlockTextures, ChunkMesh, ChunkNeighborhood, Mesher };
+use world::{ Chunk, ChunkCoord, World };
+
+struct MeshJob {
+    coord: ChunkCoord,
+    generation: u64,
+    chunks: [Option<Arc<Chunk>>; 27],
+    mesher: Mesher,
+    detail: ChunkDetail,
+    cancelled: Arc<AtomicBool>,
+}
+
+struct JobResult {
+    coord: ChunkCoord,
+    generation: u64,
+    mesh: ChunkMesh,
+    milliseconds: f32,
+}
+
+/// A job that has been sent to the workers and hasn't come back yet.
+struct PendingJob {
+    /// Counts up with every job sent, so a result can be matched to the newest job for its
+    /// chunk.
+    generation: u64,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// A mesh that's ready to upload.
+pub struct MeshedChunk {
+    pub coord: ChunkCoord,
+    pub mesh: ChunkMesh,
+    /// How long the worker took to build it.
+    pub milliseconds: f32,
+}
+
+pub struct MeshWorkers {
+    /// `None` once the pool is shutting down, which is what tells the workers to stop.
+    jobs: Option<Sender<MeshJob>>,
+    results: Receiver<JobResult>,
+    threads: Vec<JoinHandle<()>>,
+    pending: HashMap<ChunkCoord, PendingJob>,
+    next_generation: u64,
+}
+
+impl MeshWorkers {
+    /// Starts `threads` workers meshing with `textures`. 0 picks one fewer than the number of
+    /// cpu cores, leaving one for the render thread.
+    pub fn new(threads: usize, textures: Arc<BlockTextures>) -> Result<Self> {
+        let threads = if threads == 0 { num_cpus::get().saturating_sub(1).max(1) } else { threads };
+        let (job_sender, job_receiver) = channel::unbounded::<MeshJob>();
+        let (result_sender, result_receiver) = channel::unbounded();
+
+        let handles = (0..threads)
+            .map(|index| {
+                let jobs = job_receiver.clone();
+                let results = result_sender.clone();
+                let textures = textures.clone();
+                thread::Builder::new()
+                    .name(format!("mesher {}", index))
+                    .spawn(move || run_worker(&jobs, &results, &textures))
+            })
+            .collect::<::std::result::Result<Vec<_>, _>>()?;
+        info!("Meshing chunks on {} threads", threads);
+
+        Ok(MeshWorkers {
+            jobs: Some(job_sender),
+            results: result_receiver,
+            threads: handles,
+            pending: HashMap::new(),
+            next_generation: 0,
+        })
+    }
+
+    pub fn thread_count(&self) -> usize {
+        self.threads.len()
+    }
+
+    /// Queues the chunk at `coord` to be meshed as it is now with `detail`, cancelling any job
+    /// for it that's already queued. Does nothing and returns false if it isn't loaded.
+    pub fn submit(&mut self, world: &World, coord: ChunkCoord, mesher: Mesher, detail: ChunkDetail) -> bool {
+        self.cancel(coord);
+        if !world.contains_chunk(coord) {
+            return false;
+        }
+
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut chunks = world.surrounding_shared(coord);
+        for (index, chunk) in chunks.iter_mut().enumerate() {
+            if !detail.shares_level(index) {
+                *chunk = None;
+            }
+        }
+        let job = MeshJob {
+            coord,
+            generation,
+            chunks,
+            mesher,
+            detail,
+            cancelled: cancelled.clone(),
+        };
+        // The workers only hang up when a thread has panicked
+        if self.jobs.as_ref().unwrap().send(job).is_err() {
+            error!("The meshing threads have stopped, so chunk {:?} won't be meshed", coord);
+            return false;
+        }
+        self.pending.insert(coord, PendingJob { generation, cancelled });
+        true
+    }
+
+    /// Cancels the job for the chunk at `coord`, if there is one, so its mesh never comes
+    /// back. Call this when a chunk is unloaded.
+    pub fn cancel(&mut self, coord: ChunkCoord) {
+        if let Some(job) = self.pending.remove(&coord) {
+            job.cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub fn is_pending(&self, coord: ChunkCoord) -> bool {
+        self.pending.contains_key(&coord)
+    }
+
+    /// How many chunks are queued or being meshed.
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// The meshes that have finished since the last call, without waiting for any more.
+    pub fn poll(&mut self) -> Vec<MeshedChunk> {
+        let mut finished = Vec::new();
+        while let Ok(result) = self.results.try_recv() {
+            if let Some(meshed) = self.accept(result) {
+                finished.push(meshed);
+            }
+        }
+        finished
+    }
+
+    /// Waits until every queued chunk has been meshed, and returns them all. Headless runs use
+    /// this so the frames they save don't depend on how fast the workers were.
+    pub fn wait(&mut self) -> Vec<MeshedChunk> {
+        let mut finished = self.poll();
+        while !self.pending.is_empty() {
+            match self.results.recv() {
+                Ok(result) => {
+                    if let Some(meshed) = self.accept(result) {
+                        finished.push(meshed);
+                    }
+                }
+                Err(_) => {
+                    error!("The meshing threads have stopped with {} chunks left", self.pending.len());
+                    self.pending.clear();
+                }
+            }
+        }
+        finished
+    }
+
+    /// Passes `result` on if it's from the newest job for its chunk.
+    fn accept(&mut self, result: JobResult) -> Option<MeshedChunk> {
+        let newest = self
+            .pending
+            .get(&result.coord)
+            .map_or(false, |job| job.generation == result.generation);
+        if !newest {
+            return None;
+        }
+        self.pending.remove(&result.coord);
+        Some(MeshedChunk {
+            coord: result.coord,
+            mesh: result.mesh,
+            milliseconds: result.milliseconds,
+        })
+    }
+}
+
+impl Drop for MeshWorkers {
+    fn drop(&mut self) {
+        for job in self.pending.values() {
+            job.cancelled.store(true, Ordering::Relaxed);
+        }
+        // Hanging up the job channel ends each worker's loop once it's finished what it's on
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            if thread.join().is_err() {
+                error!("A meshing thread panicked");
+            }
+        }
+    }
+}
+
+fn run_worker(jobs: &Receiver<MeshJob>, results: &Sender<JobResult>, textures: &BlockTextures) {
+    while let Ok(job) = jobs.recv() {
+        if job.cancelled.load(Ordering::Relaxed) {
+            continue;
+        }
+        let start = Instant::now();
+        // Merging cells only saves anything if the faces of each cell are merged too
+        let (chunks, mesher) = if job.detail.lod == Lod::Full {
+            (job.chunks, job.mesher)
+        } else {
+            let mut merged: [Option<Arc<Chunk>>; 27] = Default::default();
+            for (merged, chunk) in merged.iter_mut().zip(job.chunks.iter()) {
+                *merged = chunk.as_ref().map(|chunk| Arc::new(merge_cells(chunk, job.detail.lod)));
+            }
+            (merged, Mesher::Greedy)
+        };
+        let mesh = match ChunkNeighborhood::from_shared(&chunks) {
+            Some(neighborhood) => mesher.mesh(&neighborhood, textures),
+            None => continue,
+        };
+        let elapsed = start.elapsed();
+
+        let result = JobResult {
+            coord: job.coord,
+            generation: job.generation,
+            mesh,
+            milliseconds: elapsed.as_secs() as f32 * 1000.0 + elapsed.subsec_nanos() as f32 * 1e-6,
+        };
+        // The pool only hangs up when it's being dropped, and then nobody wants the result
+        if results.send(result).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::BlockId;
+
+    const STONE: BlockId = BlockId(1);
+
+    fn world_with(coords: &[ChunkCoord]) -> World {
+        let mut world = World::new();
+        for &coord in coords {
+            world.insert_chunk(coord, Chunk::filled(STONE));
+        }
+        world
+    }
+
+    fn workers() -> MeshWorkers {
+        MeshWorkers::new(2, Arc::new(BlockTextures::new())).unwrap()
+    }
+
+    #[test]
+    fn every_submitted_chunk_comes_back() {
+        let coords = [ChunkCoord::new(0, 0, 0), ChunkCoord::new(1, 0, 0), ChunkCoord::new(0, 0, 5)];
+        let world = world_with(&coords);
+        let mut workers = workers();
+        for &coord in &coords {
+            assert!(workers.submit(&world, coord, Mesher::Greedy, ChunkDetail::FULL));
+        }
+        assert_eq!(workers.pending_count(), 3);
+
+        let mut meshed = workers.wait().into_iter().map(|meshed| meshed.coord).collect::<Vec<_>>();
+        meshed.sort();
+        assert_eq!(meshed, [coords[0], coords[2], coords[1]]);
+        assert_eq!(workers.pending_count(), 0);
+    }
+
+    #[test]
+    fn unloaded_chunks_are_not_submitted() {
+        let world = world_with(&[]);
+        let mut workers = workers();
+        assert!(!workers.submit(&world, ChunkCoord::new(0, 0, 0), Mesher::Naive, ChunkDetail::FULL));
+        assert!(workers.wait().is_empty());
+    }
+
+    #[test]
+    fn cancelled_chunks_never_come_back() {
+        let coord = ChunkCoord::new(0, 0, 0);
+        let world = world_with(&[coord]);
+        let mut workers = workers();
+        workers.submit(&world, coord, Mesher::Greedy, ChunkDetail::FULL);
+        workers.cancel(coord);
+        assert!(!workers.is_pending(coord));
+        assert!(workers.wait().is_empty());
+    }
+
+    #[test]
+    fn only_the_newest_mesh_for_a_chunk_comes_back() {
+        let coord = ChunkCoord::new(0, 0, 0);
+        let mut world = world_with(&[coord]);
+        let mut workers = workers();
+        workers.submit(&world, coord, Mesher::Greedy, ChunkDetail::FULL);
+
+        // Carving a block out leaves more faces than the solid chunk had
+        world.set_block([3, 3, 3], BlockId::AIR);
+        workers.submit(&world, coord, Mesher::Greedy, ChunkDetail::FULL);
+
+        let meshed = workers.wait();
+        assert_eq!(meshed.len(), 1);
+        assert_eq!(meshed[0].mesh.quad_count(), 6 + 6);
+    }
+
+    #[test]
+    fn distant_chunks_are_meshed_with_their_cells_merged() {
+        let coord = ChunkCoord::new(0, 0, 0);
+        let mut world = world_with(&[coord]);
+        // The hole is one block of a cell that's otherwise full, so it's filled in
+        world.set_block([3, 3, 3], BlockId::AIR);
+        let mut workers = workers();
+        let half = ChunkDetail { lod: Lod::Half, same_level: ChunkDetail::FULL.same_level };
+        workers.submit(&world, coord, Mesher::Naive, half);
+
+        let meshed = workers.wait();
+        assert_eq!(meshed.len(), 1);
+        assert_eq!(meshed[0].mesh.quad_count(), 6);
+    }
+}
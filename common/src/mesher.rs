@@ -0,0 +1,1134 @@
+//! Turning chunks of blocks into triangles.
+//!
+//! Only faces between a block and air, or a block that can be seen through, can ever be seen,
+//! so those are the only ones meshed. Blocks on the edge of a chunk are checked against the neighbouring chunk. Where
+//! that isn't loaded the face is kept, so the edge of the loaded world is closed off rather than
+//! see-through; loading the neighbour marks this chunk dirty, and remeshing then drops them.
+//!
+//! There are two meshers, picked with the `mesher` setting. `Mesher::Naive` makes a quad for
+//! every visible face, and `Mesher::Greedy` merges neighbouring faces that look the same into
+//! bigger quads, which takes a little longer but gives far fewer triangles.
+//!
+//! Each vertex also has an ambient occlusion value, baked in from the blocks around it: a
+//! corner tucked in against other blocks gets less of the light coming from all around than
+//! one out in the open, which is most of what makes blocky terrain read as 3D. See
+//! `ChunkNeighborhood::face_ao`.
+//!
+//! They also have the sky and block light of the air around the corner, averaged over the same
+//! blocks ambient occlusion looks at, so light fades smoothly across a face rather than
+//! stepping from one block to the next. See `ChunkNeighborhood::face_light`.
+//!
+//! Water is meshed along with everything else, but its faces go in a list of their own,
+//! `ChunkMesh::water_indices`, so they can be drawn blended over the rest once it's all there.
+//! Only water faces that meet air are kept, and each has how deep the water is under it baked
+//! in, for how much of the colour behind it the water soaks up. See
+//! `ChunkNeighborhood::water_depth`.
+//!
+//! Translucent blocks, like glass, are the same: their faces go in
+//! `ChunkMesh::translucent_indices`, to be blended over the opaque ones. They use the atlas like
+//! everything else, with the tile's alpha saying how much of what's behind shows through.
+//!
+//! Vertex positions are relative to the chunk's origin, so a chunk's mesh doesn't change when
+//! it's moved and the numbers stay small enough for `f32` to hold exactly. The chunk's world
+//! position goes in its model matrix instead.
+
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use atlas::BlockTextureId;
+use world::{ surrounding_index, BlockId, Chunk, ChunkCoord, Direction, Light, World, CHUNK_SIZE, MAX_LIGHT };
+
+/// One corner of a block face. Has to match the vertex attributes of the chunk pipeline.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ChunkVertex {
+    /// Relative to the chunk's origin, in blocks.
+    pub position: [f32; 3],
+    pub normal: [f32; 3],
+    /// Where in its tile the corner is, counted in blocks, so 0 to 1 across a single block's
+    /// face. The fragment shader wraps this into the tile, so a quad covering several blocks
+    /// repeats the texture across them.
+    pub uv: [f32; 2],
+    /// Which atlas tile the face shows.
+    pub tile: u32,
+    /// How much ambient light reaches the corner, from 0 when it's boxed in on three sides to
+    /// 1 when there's nothing around it.
+    pub ao: f32,
+    /// How brightly the sky and blocks light the corner, each from 0 to 1.
+    pub light: [f32; 2],
+    /// How many blocks of water there are under a water face, from its own down, and 0 for
+    /// any other face.
+    pub water_depth: f32,
+}
+
+/// How far down `ChunkNeighborhood::water_depth` looks. Any deeper than this and the water has
+/// long since soaked up everything behind it.
+pub const MAX_WATER_DEPTH: u8 = 16;
+
+/// How finely `face_light` measures light: twelfths of a level, since it averages over one to
+/// four blocks and that's exact for all of them.
+const LIGHT_STEPS: f32 = MAX_LIGHT as f32 * 12.0;
+
+/// The triangles for one chunk, ready to upload as a vertex and index buffer.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkMesh {
+    pub vertices: Vec<ChunkVertex>,
+    /// Triangle lists, in counter-clockwise order seen from in front. A chunk can have more
+    /// than 65536 vertices, so 16 bits isn't enough.
+    pub indices: Vec<u32>,
+    /// The triangles of the translucent faces, into the same vertices.
+    pub translucent_indices: Vec<u32>,
+    /// The triangles of the water faces, into the same vertices.
+    pub water_indices: Vec<u32>,
+}
+
+impl ChunkMesh {
+    pub fn new() -> Self {
+        ChunkMesh::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty() && self.translucent_indices.is_empty() && self.water_indices.is_empty()
+    }
+
+    pub fn quad_count(&self) -> usize {
+        self.vertices.len() / 4
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        (self.indices.len() + self.translucent_indices.len() + self.water_indices.len()) / 3
+    }
+
+    /// Adds a quad facing `direction` on the face of the block at `position`, `size[0]` blocks
+    /// along the face's first axis from `face_axes` and `size[1]` along its second, looking like
+    /// `face`.
+    fn push_quad(&mut self, direction: Direction, position: [usize; 3], size: [usize; 2], face: Face) {
+        let (tile, ao, light, surface, water_depth) = face;
+        let axis = direction.axis();
+        let (u_axis, v_axis) = face_axes(direction);
+        let mut base = [position[0] as f32, position[1] as f32, position[2] as f32];
+        if direction.is_positive() {
+            base[axis] += 1.0;
+        }
+        let normal = {
+            let offset = direction.offset();
+            [offset[0] as f32, offset[1] as f32, offset[2] as f32]
+        };
+        let (width, height) = (size[0] as f32, size[1] as f32);
+
+        // Corners go round counter-clockwise seen from the side the face is facing. For
+        // positive directions u × v points the same way as the face, so that's
+        // (0, 0), (1, 0), (1, 1), (0, 1); for negative ones it's the other way round. The last
+        // number is which of `ao` and `light` goes with the corner.
+        let corners = if direction.is_positive() {
+            [(0.0, 0.0, 0), (width, 0.0, 1), (width, height, 2), (0.0, height, 3)]
+        } else {
+            [(0.0, 0.0, 0), (0.0, height, 3), (width, height, 2), (width, 0.0, 1)]
+        };
+
+        let first = self.vertices.len() as u32;
+        let mut levels = [0; 4];
+        for (level, &(u, v, ao_index)) in levels.iter_mut().zip(corners.iter()) {
+            let mut corner = base;
+            corner[u_axis] += u;
+            corner[v_axis] += v;
+            *level = ao[ao_index];
+            self.vertices.push(ChunkVertex {
+                position: corner,
+                normal,
+                uv: texture_coordinates(direction, u, v, width, height),
+                tile: tile.0 as u32,
+                ao: *level as f32 / 3.0,
+                light: [light[ao_index][0] as f32 / LIGHT_STEPS, light[ao_index][1] as f32 / LIGHT_STEPS],
+                water_depth: water_depth as f32,
+            });
+        }
+
+        // A quad is drawn as two triangles, and the ambient occlusion is interpolated across
+        // each separately, so the diagonal they share shows. Split along the brighter diagonal,
+        // so a single dark corner shades one triangle in a smooth fan rather than a streak
+        // running the length of the quad. Flipping the diagonal keeps the winding the same.
+        let indices = if levels[1] + levels[3] > levels[0] + levels[2] {
+            [1, 2, 3, 3, 0, 1]
+        } else {
+            [0, 1, 2, 2, 3, 0]
+        };
+        let list = match surface {
+            Surface::Opaque => &mut self.indices,
+            Surface::Translucent => &mut self.translucent_indices,
+            Surface::Water => &mut self.water_indices,
+        };
+        list.extend(indices.iter().map(|&index| first + index));
+    }
+}
+
+/// The two axes a face facing `direction` lies along, ordered so that for positive directions
+/// the first crossed with the second points along `direction`.
+pub fn face_axes(direction: Direction) -> (usize, usize) {
+    match direction.axis() {
+        0 => (1, 2),
+        1 => (2, 0),
+        _ => (0, 1),
+    }
+}
+
+/// Where the corner `u`, `v` blocks along a `width` by `height` quad's axes falls in the
+/// texture. Side faces are turned so the texture's top is up and it reads left to right seen
+/// from outside, and top and bottom faces line up with the x and z axes.
+fn texture_coordinates(direction: Direction, u: f32, v: f32, width: f32, height: f32) -> [f32; 2] {
+    match direction {
+        // u is y and v is z
+        Direction::PosX => [height - v, width - u],
+        Direction::NegX => [v, width - u],
+        // u is z and v is x
+        Direction::PosY => [v, u],
+        Direction::NegY => [v, width - u],
+        // u is x and v is y
+        Direction::PosZ => [u, height - v],
+        Direction::NegZ => [width - u, height - v],
+    }
+}
+
+/// How a block's faces are drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Surface {
+    /// Solid, with its atlas tiles, hiding whatever is behind it.
+    Opaque,
+    /// With its atlas tiles, blended over what's behind it by their alpha.
+    Translucent,
+    /// Blended over what's behind it by the water pass, which doesn't use the atlas.
+    Water,
+}
+
+impl Default for Surface {
+    fn default() -> Self {
+        Surface::Opaque
+    }
+}
+
+/// Which atlas tile each block shows on each of its faces, and what kind of surface it is.
+#[derive(Clone, Debug, Default)]
+pub struct BlockTextures {
+    /// Indexed by `BlockId`, then in the order of `Direction::ALL`.
+    faces: Vec<[BlockTextureId; 6]>,
+    /// Indexed by `BlockId`.
+    surfaces: Vec<Surface>,
+}
+
+impl BlockTextures {
+    pub fn new() -> Self {
+        BlockTextures::default()
+    }
+
+    /// Gives every face of `block` the same tile.
+    pub fn set_all(&mut self, block: BlockId, tile: BlockTextureId) {
+        self.set_faces(block, [tile; 6]);
+    }
+
+    /// Gives `block` a tile for its top, one for its bottom and one for its four sides, like
+    /// grass.
+    pub fn set_top_bottom_sides(
+        &mut self,
+        block: BlockId,
+        top: BlockTextureId,
+        bottom: BlockTextureId,
+        sides: BlockTextureId,
+    ) {
+        self.set_faces(block, [sides, sides, top, bottom, sides, sides]);
+    }
+
+    /// Sets the tile for each face of `block`, in the order of `Direction::ALL`.
+    pub fn set_faces(&mut self, block: BlockId, faces: [BlockTextureId; 6]) {
+        let index = block.0 as usize;
+        if index >= self.faces.len() {
+            self.faces.resize(index + 1, [BlockTextureId(0); 6]);
+        }
+        self.faces[index] = faces;
+    }
+
+    /// The tile on the `direction` face of `block`, or tile 0 if it hasn't been given one.
+    pub fn get(&self, block: BlockId, direction: Direction) -> BlockTextureId {
+        self.faces
+            .get(block.0 as usize)
+            .map(|faces| faces[direction.index()])
+            .unwrap_or(BlockTextureId(0))
+    }
+
+    /// Makes `block` draw as `surface`.
+    pub fn set_surface(&mut self, block: BlockId, surface: Surface) {
+        let index = block.0 as usize;
+        if index >= self.surfaces.len() {
+            self.surfaces.resize(index + 1, Surface::Opaque);
+        }
+        self.surfaces[index] = surface;
+    }
+
+    /// What kind of surface `block` has, which is opaque unless it's been given another.
+    pub fn surface(&self, block: BlockId) -> Surface {
+        self.surfaces.get(block.0 as usize).cloned().unwrap_or(Surface::Opaque)
+    }
+
+    /// Whether `block` is solid and hides what's behind it, which air, water and translucent
+    /// blocks don't.
+    pub fn is_opaque(&self, block: BlockId) -> bool {
+        !block.is_air() && self.surface(block) == Surface::Opaque
+    }
+}
+
+/// A chunk along with the 26 around it, which is everything needed to mesh it. The ones that
+/// share a face with it decide which of its faces can be seen, and the rest only matter for
+/// ambient occlusion at its edges and corners.
+#[derive(Clone, Copy)]
+pub struct ChunkNeighborhood<'a> {
+    pub chunk: &'a Chunk,
+    /// In the order `world::surrounding_index` gives them, or `None` where the chunk isn't
+    /// loaded. The middle one is always `None`, since that's `chunk`.
+    neighbors: [Option<&'a Chunk>; 27],
+}
+
+impl<'a> ChunkNeighborhood<'a> {
+    /// Just `chunk`, as if nothing around it were loaded.
+    pub fn new(chunk: &'a Chunk) -> Self {
+        ChunkNeighborhood { chunk, neighbors: [None; 27] }
+    }
+
+    /// The chunk at `coord` and its neighbours, or `None` if it isn't loaded.
+    pub fn from_world(world: &'a World, coord: ChunkCoord) -> Option<Self> {
+        let mut neighbors = world.surrounding(coord);
+        let chunk = neighbors[surrounding_index([0, 0, 0])].take()?;
+        Some(ChunkNeighborhood { chunk, neighbors })
+    }
+
+    /// The middle chunk of `chunks` and the ones around it, as `World::surrounding_shared`
+    /// gives them, or `None` if the middle one is missing.
+    pub fn from_shared(chunks: &'a [Option<Arc<Chunk>>; 27]) -> Option<Self> {
+        let mut neighbors = [None; 27];
+        for (neighbor, chunk) in neighbors.iter_mut().zip(chunks.iter()) {
+            *neighbor = chunk.as_ref().map(|chunk| &**chunk);
+        }
+        let chunk = neighbors[surrounding_index([0, 0, 0])].take()?;
+        Some(ChunkNeighborhood { chunk, neighbors })
+    }
+
+    /// Sets the chunk `offset` chunks away, with each part of `offset` from -1 to 1.
+    pub fn set_neighbor(&mut self, offset: [i32; 3], chunk: Option<&'a Chunk>) {
+        assert!(offset != [0, 0, 0], "The middle of a neighborhood is the chunk itself");
+        self.neighbors[surrounding_index(offset)] = chunk;
+    }
+
+    /// The block at `position` relative to the chunk's origin, which can be up to one block
+    /// outside the chunk on each axis. Blocks in chunks that aren't loaded are air.
+    pub fn block(&self, position: [i32; 3]) -> BlockId {
+        let (chunk, local) = self.locate(position);
+        chunk.map(|chunk| chunk.get(local)).unwrap_or(BlockId::AIR)
+    }
+
+    /// The light at `position`, which can be outside the chunk like `block`'s. Chunks that
+    /// aren't loaded are lit like open sky.
+    pub fn light(&self, position: [i32; 3]) -> Light {
+        let (chunk, local) = self.locate(position);
+        chunk.map(|chunk| chunk.light(local)).unwrap_or(Light::SKY)
+    }
+
+    /// The chunk `position` is in, if it's loaded, and where in that chunk it is.
+    fn locate(&self, position: [i32; 3]) -> (Option<&'a Chunk>, [usize; 3]) {
+        let size = CHUNK_SIZE as i32;
+        let mut local = [0; 3];
+        let mut offset = [0; 3];
+        for axis in 0..3 {
+            let n = position[axis];
+            debug_assert!(n >= -1 && n <= size, "{:?} is too far outside the chunk", position);
+            offset[axis] = if n < 0 { -1 } else if n >= size { 1 } else { 0 };
+            local[axis] = (n - offset[axis] * size) as usize;
+        }
+
+        if offset == [0, 0, 0] {
+            (Some(self.chunk), local)
+        } else {
+            (self.neighbors[surrounding_index(offset)], local)
+        }
+    }
+
+    /// Whether the face of the block at `local` facing `direction` can be seen. Every face
+    /// that meets air can, and so can every face that meets a different block that isn't
+    /// opaque, but a face against something opaque or against another block of its own kind
+    /// can't. That leaves water and glass with only their outsides.
+    pub fn face_visible(&self, local: [usize; 3], direction: Direction, textures: &BlockTextures) -> bool {
+        let offset = direction.offset();
+        let neighbor = self.block([
+            local[0] as i32 + offset[0],
+            local[1] as i32 + offset[1],
+            local[2] as i32 + offset[2],
+        ]);
+        neighbor.is_air() || (!textures.is_opaque(neighbor) && neighbor != self.chunk.get(local))
+    }
+
+    /// The ambient occlusion level of each corner of the face of the block at `local` facing
+    /// `direction`, from 0 to 3, in the order (0, 0), (1, 0), (1, 1), (0, 1) along the face's
+    /// `face_axes`.
+    ///
+    /// Each corner is darkened by the blocks in front of the face that touch it: the two along
+    /// its edges and the one diagonally out from it. Two edge blocks already close the corner
+    /// off completely, so the diagonal one doesn't matter then. That gives four levels, 3 with
+    /// nothing there down to 0 in a tight corner. Water and translucent blocks don't get in the
+    /// way.
+    pub fn face_ao(&self, local: [usize; 3], direction: Direction, textures: &BlockTextures) -> [u8; 4] {
+        let (u_axis, v_axis) = face_axes(direction);
+        let offset = direction.offset();
+        let front = [
+            local[0] as i32 + offset[0],
+            local[1] as i32 + offset[1],
+            local[2] as i32 + offset[2],
+        ];
+        let solid = |du: i32, dv: i32| {
+            let mut position = front;
+            position[u_axis] += du;
+            position[v_axis] += dv;
+            textures.is_opaque(self.block(position)) as u8
+        };
+
+        let mut levels = [0; 4];
+        for (level, &(du, dv)) in levels.iter_mut().zip(&[(-1, -1), (1, -1), (1, 1), (-1, 1)]) {
+            let (side_u, side_v) = (solid(du, 0), solid(0, dv));
+            *level = if side_u + side_v == 2 {
+                0
+            } else {
+                3 - side_u - side_v - solid(du, dv)
+            };
+        }
+        levels
+    }
+
+    /// The light at each corner of the face of the block at `local` facing `direction`, in the
+    /// same order as `face_ao`, as sky and block light in twelfths of a level.
+    ///
+    /// Each corner averages the light of whatever isn't opaque in front of the face that touches it:
+    /// the block straight in front, the two along its edges and the one diagonally out, leaving
+    /// out the diagonal when both edges are solid, since no light gets round to the corner from
+    /// there. Solid blocks are left out altogether, since they're dark.
+    pub fn face_light(&self, local: [usize; 3], direction: Direction, textures: &BlockTextures) -> [[u8; 2]; 4] {
+        let (u_axis, v_axis) = face_axes(direction);
+        let offset = direction.offset();
+        let front = [
+            local[0] as i32 + offset[0],
+            local[1] as i32 + offset[1],
+            local[2] as i32 + offset[2],
+        ];
+        let around = |du: i32, dv: i32| {
+            let mut position = front;
+            position[u_axis] += du;
+            position[v_axis] += dv;
+            if textures.is_opaque(self.block(position)) {
+                None
+            } else {
+                Some(self.light(position))
+            }
+        };
+
+        let mut corners = [[0; 2]; 4];
+        for (corner, &(du, dv)) in corners.iter_mut().zip(&[(-1, -1), (1, -1), (1, 1), (-1, 1)]) {
+            let (side_u, side_v) = (around(du, 0), around(0, dv));
+            let diagonal = if side_u.is_none() && side_v.is_none() { None } else { around(du, dv) };
+            let (mut sky, mut block, mut count) = (0, 0, 0);
+            let lit = [Some(self.light(front)), side_u, side_v, diagonal];
+            for light in lit.iter().filter_map(|&light| light) {
+                sky += light.sky() as u32;
+                block += light.block() as u32;
+                count += 1;
+            }
+            *corner = [(sky * 12 / count) as u8, (block * 12 / count) as u8];
+        }
+        corners
+    }
+
+    /// How many blocks of water there are from the one at `local` down, through this chunk and
+    /// the one below if it's loaded, up to `MAX_WATER_DEPTH`.
+    pub fn water_depth(&self, local: [usize; 3], textures: &BlockTextures) -> u8 {
+        let below = self.neighbors[surrounding_index([0, -1, 0])];
+        let mut depth = 0;
+        let mut y = local[1] as i32;
+        while depth < MAX_WATER_DEPTH {
+            let block = if y >= 0 {
+                self.chunk.get([local[0], y as usize, local[2]])
+            } else {
+                match below {
+                    Some(chunk) if y >= -(CHUNK_SIZE as i32) => {
+                        chunk.get([local[0], (y + CHUNK_SIZE as i32) as usize, local[2]])
+                    }
+                    _ => break,
+                }
+            };
+            if textures.surface(block) != Surface::Water {
+                break;
+            }
+            depth += 1;
+            y -= 1;
+        }
+        depth
+    }
+
+    /// What the `direction` face of the block at `local` looks like, if it can be seen.
+    fn face(&self, local: [usize; 3], direction: Direction, textures: &BlockTextures) -> Option<Face> {
+        let block = self.chunk.get(local);
+        if block.is_air() || !self.face_visible(local, direction, textures) {
+            return None;
+        }
+        let surface = textures.surface(block);
+        let water_depth = if surface == Surface::Water { self.water_depth(local, textures) } else { 0 };
+        Some((
+            textures.get(block, direction),
+            self.face_ao(local, direction, textures),
+            self.face_light(local, direction, textures),
+            surface,
+            water_depth,
+        ))
+    }
+}
+
+/// What a visible face looks like: its tile, the ambient occlusion level and light of each
+/// corner, as `face_ao` and `face_light` give them, what kind of surface it is, and how deep
+/// the water is under it, as `water_depth` gives it, if it's water.
+type Face = (BlockTextureId, [u8; 4], [[u8; 2]; 4], Surface, u8);
+
+/// Which algorithm to mesh chunks with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mesher {
+    Naive,
+    Greedy,
+}
+
+impl Mesher {
+    pub const ALL: [Mesher; 2] = [Mesher::Naive, Mesher::Greedy];
+
+    pub fn mesh(self, neighborhood: &ChunkNeighborhood, textures: &BlockTextures) -> ChunkMesh {
+        match self {
+            Mesher::Naive => mesh_naive(neighborhood, textures),
+            Mesher::Greedy => mesh_greedy(neighborhood, textures),
+        }
+    }
+
+    /// The other mesher, for switching between them.
+    pub fn next(self) -> Mesher {
+        match self {
+            Mesher::Naive => Mesher::Greedy,
+            Mesher::Greedy => Mesher::Naive,
+        }
+    }
+}
+
+impl Default for Mesher {
+    fn default() -> Self {
+        Mesher::Greedy
+    }
+}
+
+impl fmt::Display for Mesher {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Mesher::Naive => "naive",
+            Mesher::Greedy => "greedy",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Mesher {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Mesher, String> {
+        Mesher::ALL
+            .iter()
+            .cloned()
+            .find(|mesher| mesher.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown mesher {:?}, expected naive or greedy", name))
+    }
+}
+
+/// Meshes a chunk with one quad for every visible block face. Simple, but it makes a lot of
+/// vertices: a flat 32 by 32 floor one block thick is over two thousand quads.
+pub fn mesh_naive(neighborhood: &ChunkNeighborhood, textures: &BlockTextures) -> ChunkMesh {
+    let mut mesh = ChunkMesh::new();
+    if neighborhood.chunk.is_empty() {
+        return mesh;
+    }
+
+    for y in 0..CHUNK_SIZE {
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let local = [x, y, z];
+                if neighborhood.chunk.get(local).is_air() {
+                    continue;
+                }
+                for &direction in &Direction::ALL {
+                    if let Some(face) = neighborhood.face(local, direction, textures) {
+                        mesh.push_quad(direction, local, [1, 1], face);
+                    }
+                }
+            }
+        }
+    }
+    mesh
+}
+
+/// Meshes a chunk, merging visible faces with the same tile into as few quads as it can.
+///
+/// Faces are handled one slice of the chunk at a time, for each direction. For each slice a
+/// mask records which faces are visible, what tile they show, the ambient occlusion and light
+/// at their corners, what kind of surface they are and how deep the water is under water
+/// faces. Faces only merge when all of
+/// that matches, since a merged quad only has the
+/// four corners to interpolate between. Then, starting from the first
+/// face left in the mask, a quad is grown along the face's first axis as far as the faces
+/// match, then along its second axis as long as every face in the next row matches too. Its
+/// faces are cleared from the mask, and that repeats until the slice is empty. The quads aren't
+/// the fewest possible, but they're close, and it only takes a single pass over each slice.
+///
+/// The flat floor that takes the naive mesher over two thousand quads takes this six.
+pub fn mesh_greedy(neighborhood: &ChunkNeighborhood, textures: &BlockTextures) -> ChunkMesh {
+    let mut mesh = ChunkMesh::new();
+    if neighborhood.chunk.is_empty() {
+        return mesh;
+    }
+
+    let mut mask: Vec<Option<Face>> = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+    for &direction in &Direction::ALL {
+        let axis = direction.axis();
+        let (u_axis, v_axis) = face_axes(direction);
+
+        for slice in 0..CHUNK_SIZE {
+            // Which faces in this slice can be seen, indexed by v then u
+            for v in 0..CHUNK_SIZE {
+                for u in 0..CHUNK_SIZE {
+                    let mut local = [0; 3];
+                    local[axis] = slice;
+                    local[u_axis] = u;
+                    local[v_axis] = v;
+                    mask[v * CHUNK_SIZE + u] = neighborhood.face(local, direction, textures);
+                }
+            }
+
+            for v in 0..CHUNK_SIZE {
+                let mut u = 0;
+                while u < CHUNK_SIZE {
+                    let face = match mask[v * CHUNK_SIZE + u] {
+                        Some(face) => face,
+                        None => {
+                            u += 1;
+                            continue;
+                        }
+                    };
+
+                    let mut width = 1;
+                    while u + width < CHUNK_SIZE && mask[v * CHUNK_SIZE + u + width] == Some(face) {
+                        width += 1;
+                    }
+                    let mut height = 1;
+                    while v + height < CHUNK_SIZE {
+                        let row = (v + height) * CHUNK_SIZE;
+                        if mask[row + u..row + u + width].iter().all(|&other| other == Some(face)) {
+                            height += 1;
+                        } else {
+                            break;
+                        }
+                    }
+
+                    for row in v..v + height {
+                        for cell in &mut mask[row * CHUNK_SIZE + u..row * CHUNK_SIZE + u + width] {
+                            *cell = None;
+                        }
+                    }
+                    let mut position = [0; 3];
+                    position[axis] = slice;
+                    position[u_axis] = u;
+                    position[v_axis] = v;
+                    mesh.push_quad(direction, position, [width, height], face);
+                    u += width;
+                }
+            }
+        }
+    }
+    mesh
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::{ CHUNK_SIZE, CHUNK_VOLUME };
+
+    const STONE: BlockId = BlockId(1);
+    const GRASS: BlockId = BlockId(2);
+    const WATER: BlockId = BlockId(3);
+    const GLASS: BlockId = BlockId(4);
+
+    fn alone<'a>(chunk: &'a Chunk) -> ChunkNeighborhood<'a> {
+        ChunkNeighborhood::new(chunk)
+    }
+
+    /// The normal of each quad's first triangle, going by its winding.
+    fn winding_normal(mesh: &ChunkMesh, quad: usize) -> [f32; 3] {
+        let corner = |i: usize| mesh.vertices[mesh.indices[quad * 6 + i] as usize].position;
+        let (a, b, c) = (corner(0), corner(1), corner(2));
+        let ab = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let ac = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        [
+            ab[1] * ac[2] - ab[2] * ac[1],
+            ab[2] * ac[0] - ab[0] * ac[2],
+            ab[0] * ac[1] - ab[1] * ac[0],
+        ]
+    }
+
+    #[test]
+    fn empty_chunk_has_no_mesh() {
+        let chunk = Chunk::new();
+        assert!(mesh_naive(&alone(&chunk), &BlockTextures::new()).is_empty());
+    }
+
+    #[test]
+    fn single_block_has_six_faces() {
+        let mut chunk = Chunk::new();
+        chunk.set([4, 5, 6], STONE);
+        let mesh = mesh_naive(&alone(&chunk), &BlockTextures::new());
+        assert_eq!(mesh.quad_count(), 6);
+        assert_eq!(mesh.vertices.len(), 24);
+        assert_eq!(mesh.triangle_count(), 12);
+        for vertex in &mesh.vertices {
+            for axis in 0..3 {
+                let min = [4.0, 5.0, 6.0][axis];
+                assert!(vertex.position[axis] == min || vertex.position[axis] == min + 1.0);
+            }
+        }
+    }
+
+    #[test]
+    fn faces_wind_counter_clockwise_from_outside() {
+        let mut chunk = Chunk::new();
+        chunk.set([0, 0, 0], STONE);
+        let mesh = mesh_naive(&alone(&chunk), &BlockTextures::new());
+        for quad in 0..mesh.quad_count() {
+            let normal = mesh.vertices[quad * 4].normal;
+            let winding = winding_normal(&mesh, quad);
+            let dot = normal[0] * winding[0] + normal[1] * winding[1] + normal[2] * winding[2];
+            assert!(dot > 0.0, "Quad facing {:?} is wound the wrong way", normal);
+        }
+    }
+
+    #[test]
+    fn touching_blocks_hide_their_shared_faces() {
+        let mut chunk = Chunk::new();
+        chunk.set([1, 1, 1], STONE);
+        chunk.set([2, 1, 1], STONE);
+        let mesh = mesh_naive(&alone(&chunk), &BlockTextures::new());
+        assert_eq!(mesh.quad_count(), 10);
+    }
+
+    #[test]
+    fn neighbor_chunks_hide_faces_on_the_boundary() {
+        let mut chunk = Chunk::new();
+        chunk.set([CHUNK_SIZE - 1, 0, 0], STONE);
+        let solid = Chunk::filled(STONE);
+
+        let mut neighborhood = ChunkNeighborhood::new(&chunk);
+        let mesh = mesh_naive(&neighborhood, &BlockTextures::new());
+        assert_eq!(mesh.quad_count(), 6);
+
+        neighborhood.set_neighbor([1, 0, 0], Some(&solid));
+        let mesh = mesh_naive(&neighborhood, &BlockTextures::new());
+        assert_eq!(mesh.quad_count(), 5);
+        assert!(mesh.vertices.iter().all(|vertex| vertex.normal != [1.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn unloaded_neighbors_leave_the_boundary_closed() {
+        let chunk = Chunk::filled(STONE);
+        let mesh = mesh_naive(&alone(&chunk), &BlockTextures::new());
+        assert_eq!(mesh.quad_count(), 6 * CHUNK_SIZE * CHUNK_SIZE);
+    }
+
+    #[test]
+    fn buried_chunk_has_no_mesh() {
+        let chunk = Chunk::filled(STONE);
+        let solid = Chunk::filled(STONE);
+        let mut neighborhood = ChunkNeighborhood::new(&chunk);
+        for &direction in &Direction::ALL {
+            neighborhood.set_neighbor(direction.offset(), Some(&solid));
+        }
+        assert!(mesh_naive(&neighborhood, &BlockTextures::new()).is_empty());
+    }
+
+    #[test]
+    fn faces_use_their_block_textures() {
+        let mut textures = BlockTextures::new();
+        textures.set_all(STONE, BlockTextureId(3));
+        textures.set_top_bottom_sides(GRASS, BlockTextureId(7), BlockTextureId(8), BlockTextureId(9));
+
+        let mut chunk = Chunk::new();
+        chunk.set([0, 0, 0], GRASS);
+        chunk.set([5, 0, 0], STONE);
+        let mesh = mesh_naive(&alone(&chunk), &textures);
+        for vertex in &mesh.vertices {
+            let expected = if vertex.position[0] >= 5.0 {
+                3
+            } else if vertex.normal[1] > 0.0 {
+                7
+            } else if vertex.normal[1] < 0.0 {
+                8
+            } else {
+                9
+            };
+            assert_eq!(vertex.tile, expected);
+        }
+    }
+
+    /// A chunk that's solid up to a bumpy surface, with a different block on top.
+    fn hills() -> Chunk {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let height = 8 + (x * 7 + z * 3) % 5 + if x > 20 { 6 } else { 0 };
+                for y in 0..height {
+                    chunk.set([x, y, z], if y + 1 == height { GRASS } else { STONE });
+                }
+            }
+        }
+        chunk
+    }
+
+    /// How many blocks' worth of face each direction's quads cover.
+    fn face_area(mesh: &ChunkMesh) -> Vec<([f32; 3], f32)> {
+        let mut areas: Vec<([f32; 3], f32)> = Vec::new();
+        for quad in 0..mesh.quad_count() {
+            let normal = mesh.vertices[quad * 4].normal;
+            let winding = winding_normal(mesh, quad);
+            let area = (winding[0] + winding[1] + winding[2]).abs();
+            match areas.iter_mut().find(|&&mut (n, _)| n == normal) {
+                Some(entry) => entry.1 += area,
+                None => areas.push((normal, area)),
+            }
+        }
+        areas.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        areas
+    }
+
+    #[test]
+    fn greedy_merges_a_flat_floor_into_six_quads() {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.set([x, 3, z], STONE);
+            }
+        }
+        assert_eq!(mesh_naive(&alone(&chunk), &BlockTextures::new()).quad_count(), 2 * 32 * 32 + 4 * 32);
+        assert_eq!(mesh_greedy(&alone(&chunk), &BlockTextures::new()).quad_count(), 6);
+    }
+
+    #[test]
+    fn greedy_keeps_different_tiles_apart() {
+        let mut textures = BlockTextures::new();
+        textures.set_all(STONE, BlockTextureId(1));
+        textures.set_all(GRASS, BlockTextureId(2));
+        let mut chunk = Chunk::new();
+        for x in 0..4 {
+            chunk.set([x, 0, 0], if x < 2 { STONE } else { GRASS });
+        }
+        let mesh = mesh_greedy(&alone(&chunk), &textures);
+        // The top, bottom and two long sides split in two, and an end on each block type
+        assert_eq!(mesh.quad_count(), 10);
+    }
+
+    #[test]
+    fn greedy_covers_the_same_faces_as_naive() {
+        let chunk = hills();
+        let naive = mesh_naive(&alone(&chunk), &BlockTextures::new());
+        let greedy = mesh_greedy(&alone(&chunk), &BlockTextures::new());
+        assert!(greedy.quad_count() < naive.quad_count());
+        assert_eq!(face_area(&greedy), face_area(&naive));
+    }
+
+    #[test]
+    fn greedy_quads_wind_counter_clockwise_from_outside() {
+        let mesh = mesh_greedy(&alone(&hills()), &BlockTextures::new());
+        for quad in 0..mesh.quad_count() {
+            let normal = mesh.vertices[quad * 4].normal;
+            let winding = winding_normal(&mesh, quad);
+            let dot = normal[0] * winding[0] + normal[1] * winding[1] + normal[2] * winding[2];
+            assert!(dot > 0.0, "Quad facing {:?} is wound the wrong way", normal);
+        }
+    }
+
+    #[test]
+    fn greedy_uvs_count_blocks_across_the_quad() {
+        let chunk = Chunk::filled(STONE);
+        let mesh = mesh_greedy(&alone(&chunk), &BlockTextures::new());
+        assert_eq!(mesh.quad_count(), 6);
+        for vertex in &mesh.vertices {
+            for &uv in &vertex.uv {
+                assert!(uv == 0.0 || uv == CHUNK_SIZE as f32);
+            }
+        }
+    }
+
+    #[test]
+    fn open_faces_are_fully_lit() {
+        let mut chunk = Chunk::new();
+        chunk.set([4, 4, 4], STONE);
+        let mesh = mesh_naive(&alone(&chunk), &BlockTextures::new());
+        assert!(mesh.vertices.iter().all(|vertex| vertex.ao == 1.0));
+    }
+
+    #[test]
+    fn corners_against_the_floor_are_darkened() {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.set([x, 0, z], STONE);
+            }
+        }
+        chunk.set([5, 1, 5], STONE);
+        let neighborhood = alone(&chunk);
+
+        // The side of the block on the floor, whose corners go along y then z: the floor runs
+        // along its bottom edge and out diagonally from it
+        let side = neighborhood.face_ao([5, 1, 5], Direction::PosX, &BlockTextures::new());
+        assert_eq!(side, [1, 3, 3, 1]);
+        // The floor next to it, along z then x, has the block along one edge only
+        let floor = neighborhood.face_ao([6, 0, 5], Direction::PosY, &BlockTextures::new());
+        assert_eq!(floor, [2, 2, 3, 3]);
+    }
+
+    #[test]
+    fn two_walls_close_a_corner_off() {
+        let mut chunk = Chunk::new();
+        chunk.set([1, 0, 1], STONE);
+        chunk.set([0, 1, 1], STONE);
+        chunk.set([1, 1, 0], STONE);
+        // Up is along z then x, so the corner at (0, 0) is the one between both walls
+        assert_eq!(alone(&chunk).face_ao([1, 0, 1], Direction::PosY, &BlockTextures::new())[0], 0);
+    }
+
+    #[test]
+    fn ambient_occlusion_reaches_across_chunk_corners() {
+        let mut chunk = Chunk::new();
+        let edge = CHUNK_SIZE - 1;
+        chunk.set([edge, 0, edge], STONE);
+        let solid = Chunk::filled(STONE);
+
+        let mut neighborhood = alone(&chunk);
+        assert_eq!(neighborhood.face_ao([edge, 0, edge], Direction::PosY, &BlockTextures::new()), [3; 4]);
+        // Only the chunk diagonally across is loaded, so only its corner of the face darkens
+        neighborhood.set_neighbor([1, 0, 1], Some(&solid));
+        assert_eq!(neighborhood.face_ao([edge, 0, edge], Direction::PosY, &BlockTextures::new()), [3, 3, 2, 3]);
+    }
+
+    #[test]
+    fn corners_average_the_light_of_the_air_around_them() {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.set([x, 0, z], STONE);
+            }
+        }
+        chunk.fill_light(Light::default());
+        chunk.set_light([5, 1, 5], Light::new(12, 0));
+        chunk.set_light([6, 1, 6], Light::new(0, 8));
+        let neighborhood = alone(&chunk);
+
+        // Every corner of the floor under the lit block takes a quarter of its light, and the
+        // one towards the block lamp-lit diagonally across takes a quarter of that too
+        assert_eq!(
+            neighborhood.face_light([5, 0, 5], Direction::PosY, &BlockTextures::new()),
+            [[36, 0], [36, 0], [36, 24], [36, 0]]
+        );
+        let mesh = mesh_naive(&neighborhood, &BlockTextures::new());
+        assert!(mesh.vertices
+            .iter()
+            .any(|vertex| vertex.light == [0.2, 0.0] && vertex.position == [5.0, 1.0, 5.0]));
+    }
+
+    #[test]
+    fn light_doesnt_come_round_a_closed_off_corner() {
+        let mut chunk = Chunk::new();
+        chunk.set([1, 0, 1], STONE);
+        chunk.set([0, 1, 1], STONE);
+        chunk.set([1, 1, 0], STONE);
+        chunk.fill_light(Light::default());
+        chunk.set_light([1, 1, 1], Light::new(MAX_LIGHT, 0));
+        chunk.set_light([0, 1, 0], Light::new(0, MAX_LIGHT));
+        assert_eq!(alone(&chunk).face_light([1, 0, 1], Direction::PosY, &BlockTextures::new())[0], [180, 0]);
+    }
+
+    #[test]
+    fn greedy_keeps_differently_lit_faces_apart() {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.set([x, 3, z], STONE);
+            }
+        }
+        chunk.set_light([10, 4, 10], Light::new(MAX_LIGHT, MAX_LIGHT));
+        let naive = mesh_naive(&alone(&chunk), &BlockTextures::new());
+        let greedy = mesh_greedy(&alone(&chunk), &BlockTextures::new());
+        assert!(greedy.quad_count() > 6);
+        assert_eq!(face_area(&greedy), face_area(&naive));
+    }
+
+    #[test]
+    fn quads_split_along_their_brighter_diagonal() {
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.set([x, 0, z], STONE);
+            }
+        }
+        chunk.set([5, 1, 5], STONE);
+        chunk.set([9, 1, 9], STONE);
+        for &mesher in &Mesher::ALL {
+            let mesh = mesher.mesh(&alone(&chunk), &BlockTextures::new());
+            for quad in 0..mesh.quad_count() {
+                let indices = &mesh.indices[quad * 6..quad * 6 + 6];
+                let ao = |index: u32| mesh.vertices[index as usize].ao;
+                // The first triangle's first and third corners are the shared diagonal
+                let diagonal = ao(indices[0]) + ao(indices[2]);
+                let other = ao(indices[1]) + ao(indices[4]);
+                assert!(diagonal >= other, "{} mesher split a quad along its darker diagonal", mesher);
+            }
+        }
+    }
+
+    /// Textures where `WATER` is water, and a chunk with a stone floor under a pool of it,
+    /// `depth` blocks deep, that stops a block short of the chunk's edges.
+    fn pool(depth: usize) -> (BlockTextures, Chunk) {
+        let mut textures = BlockTextures::new();
+        textures.set_surface(WATER, Surface::Water);
+        let mut chunk = Chunk::new();
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                chunk.set([x, 0, z], STONE);
+                let inside = x > 0 && z > 0 && x < CHUNK_SIZE - 1 && z < CHUNK_SIZE - 1;
+                for y in 1..depth + 1 {
+                    chunk.set([x, y, z], if inside { WATER } else { STONE });
+                }
+            }
+        }
+        (textures, chunk)
+    }
+
+    #[test]
+    fn water_faces_only_show_against_air() {
+        let (textures, chunk) = pool(3);
+        for &mesher in &Mesher::ALL {
+            let mesh = mesher.mesh(&alone(&chunk), &textures);
+            let quads: Vec<&[u32]> = mesh.water_indices.chunks(6).collect();
+            assert!(!quads.is_empty());
+            for quad in quads {
+                let vertex = mesh.vertices[quad[0] as usize];
+                assert_eq!(vertex.normal, [0.0, 1.0, 0.0], "{} mesher kept a hidden water face", mesher);
+                assert_eq!(vertex.position[1], 4.0);
+            }
+        }
+    }
+
+    #[test]
+    fn the_ground_under_water_is_meshed_unshadowed() {
+        let (textures, chunk) = pool(3);
+        let neighborhood = alone(&chunk);
+        assert!(neighborhood.face_visible([5, 0, 5], Direction::PosY, &textures));
+        assert_eq!(neighborhood.face_ao([5, 0, 5], Direction::PosY, &textures), [3; 4]);
+        let mesh = mesh_greedy(&neighborhood, &textures);
+        let floor = mesh.indices.chunks(6).any(|quad| {
+            let vertex = mesh.vertices[quad[0] as usize];
+            vertex.normal == [0.0, 1.0, 0.0] && vertex.position[1] == 1.0
+        });
+        assert!(floor);
+    }
+
+    #[test]
+    fn water_faces_know_how_deep_the_water_is() {
+        let (textures, chunk) = pool(3);
+        let mesh = mesh_naive(&alone(&chunk), &textures);
+        let depth = |index: &u32| mesh.vertices[*index as usize].water_depth;
+        assert!(mesh.water_indices.iter().all(|index| depth(index) == 3.0));
+        assert!(mesh.indices.iter().all(|index| depth(index) == 0.0));
+
+        // Water over the top of the chunk below carries on down into it
+        let (_, deep) = pool(CHUNK_SIZE - 1);
+        let (_, mut top) = pool(2);
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                top.set([x, 0, z], if top.get([x, 1, z]) == WATER { WATER } else { STONE });
+            }
+        }
+        let mut neighborhood = alone(&top);
+        assert_eq!(neighborhood.water_depth([5, 2, 5], &textures), 3);
+        neighborhood.set_neighbor([0, -1, 0], Some(&deep));
+        assert_eq!(neighborhood.water_depth([5, 2, 5], &textures), MAX_WATER_DEPTH);
+    }
+
+    #[test]
+    fn translucent_faces_go_in_their_own_list() {
+        let mut textures = BlockTextures::new();
+        textures.set_surface(GLASS, Surface::Translucent);
+        let mut chunk = Chunk::new();
+        chunk.set([4, 4, 4], GLASS);
+        chunk.set([5, 4, 4], GLASS);
+        chunk.set([4, 3, 4], STONE);
+        for &mesher in &Mesher::ALL {
+            let mesh = mesher.mesh(&alone(&chunk), &textures);
+            assert!(mesh.water_indices.is_empty());
+            // The stone shows its top through the glass above it, but hides the bottom of that
+            // glass, and the two panes don't show each other the faces between them
+            assert_eq!(mesh.indices.len(), 6 * 6, "{} mesher", mesher);
+            let glass_faces = if mesher == Mesher::Greedy { 6 } else { 9 };
+            assert_eq!(mesh.translucent_indices.len(), glass_faces * 6, "{} mesher", mesher);
+            assert_eq!(mesh.triangle_count(), (6 + glass_faces) * 2);
+        }
+    }
+
+    #[test]
+    fn different_see_through_blocks_show_the_faces_between_them() {
+        let mut textures = BlockTextures::new();
+        textures.set_surface(GLASS, Surface::Translucent);
+        textures.set_surface(WATER, Surface::Water);
+        let mut chunk = Chunk::new();
+        chunk.set([4, 4, 4], GLASS);
+        chunk.set([5, 4, 4], WATER);
+        let neighborhood = alone(&chunk);
+        assert!(neighborhood.face_visible([4, 4, 4], Direction::PosX, &textures));
+        assert!(neighborhood.face_visible([5, 4, 4], Direction::NegX, &textures));
+        // Glass doesn't darken the corners of the faces behind it
+        chunk.set([4, 3, 5], STONE);
+        let neighborhood = alone(&chunk);
+        assert_eq!(neighborhood.face_ao([4, 3, 5], Direction::PosY, &textures), [3; 4]);
+    }
+
+    #[test]
+    fn meshers_parse_from_their_names() {
+        for &mesher in &Mesher::ALL {
+            assert_eq!(mesher.to_string().parse::<Mesher>(), Ok(mesher));
+        }
+        assert_eq!("Greedy".parse::<Mesher>(), Ok(Mesher::Greedy));
+        assert!("clever".parse::<Mesher>().is_err());
+    }
+
+    #[test]
+    fn checkerboard_meshes_every_face() {
+        let mut chunk = Chunk::new();
+        let mut blocks = 0;
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    if (x + y + z) % 2 == 0 {
+                        chunk.set([x, y, z], STONE);
+                        blocks += 1;
+                    }
+                }
+            }
+        }
+        assert_eq!(blocks, CHUNK_VOLUME / 2);
+        for &mesher in &Mesher::ALL {
+            let mesh = mesher.mesh(&alone(&chunk), &BlockTextures::new());
+            assert_eq!(mesh.quad_count(), blocks * 6);
+        }
+    }
+}
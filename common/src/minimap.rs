@@ -0,0 +1,270 @@
+//! A map of the world around the camera, seen from straight above, for a corner of the HUD.
+//!
+//! The map is a second camera drawing the same chunks as the main one: an orthographic view
+//! looking straight down, with north (towards -z) at the top. `cull.comp` culls a draw list of
+//! its own for it, and it's drawn with a pipeline of the chapter's into a small image that the
+//! HUD then shows like any other. The world under the camera hardly changes from one frame to
+//! the next, so it's only drawn again every `RENDER_INTERVAL` frames, and the HUD moves the
+//! camera's marker across it in between.
+//!
+//! `Minimap` owns the image, the depth buffer it's drawn with and the render pass and
+//! framebuffer for drawing into them. `minimap_view_projection` works out where the map's
+//! camera goes.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hal::{
+    format as f, image as i, memory, pso,
+    Backend, Device,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use context::GfxContext;
+use depth::depth_range;
+use error::Result;
+use math::{ look_along, orthographic, Mat4, Vec3, HAL_CLIP_SPACE };
+use pass::create_offscreen_depth_render_pass;
+use texture::COLOR_RANGE;
+
+/// The map is drawn lit but untonemapped, straight into an sRGB image.
+pub const MINIMAP_FORMAT: f::Format = f::Format::Rgba8Srgb;
+
+/// How many texels across the map is.
+pub const MINIMAP_RESOLUTION: u32 = 256;
+
+/// How far the map reaches from the middle to each edge, in blocks.
+pub const MINIMAP_RADIUS: f32 = 64.0;
+
+/// How many frames go by between drawing the map.
+const RENDER_INTERVAL: u32 = 4;
+
+/// From world space to the map, looking straight down from `top` onto everything from there
+/// down to `bottom`. The map is `2 * radius` blocks across either way, `resolution` texels
+/// across, around `center`, which is snapped to the texel grid so the map doesn't shimmer as
+/// it follows the camera. Returns the snapped centre along with the matrix.
+pub fn minimap_view_projection(
+    center: [f32; 3],
+    radius: f32,
+    resolution: u32,
+    top: f32,
+    bottom: f32,
+) -> (Mat4, [f32; 3]) {
+    let texel = 2.0 * radius / resolution.max(1) as f32;
+    let x = (center[0] / texel).floor() * texel;
+    let z = (center[2] / texel).floor() * texel;
+    // Looking down with -z as up puts +x on the right, like a map with north at the top
+    let view = look_along([x, top, z], -Vec3::unit_y(), -Vec3::unit_z());
+    let projection = orthographic(-radius, radius, -radius, radius, 0.0, top - bottom, HAL_CLIP_SPACE);
+    (projection * view, [x, center[1], z])
+}
+
+/// Counts the frames until the map is due to be drawn again. It's due on the first frame.
+#[derive(Clone, Copy, Debug)]
+pub struct RenderSchedule {
+    interval: u32,
+    frames_left: u32,
+}
+
+impl RenderSchedule {
+    pub fn new(interval: u32) -> Self {
+        RenderSchedule { interval: interval.max(1), frames_left: 0 }
+    }
+
+    /// Moves on a frame, saying whether the map is drawn in this one.
+    pub fn advance(&mut self) -> bool {
+        if self.frames_left == 0 {
+            self.frames_left = self.interval - 1;
+            true
+        } else {
+            self.frames_left -= 1;
+            false
+        }
+    }
+}
+
+struct MinimapImage<B: Backend> {
+    image: B::Image,
+    view: B::ImageView,
+    allocation: Allocation,
+}
+
+/// The image the map is drawn into, and what's needed to draw into it.
+pub struct Minimap<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    render_pass: Option<B::RenderPass>,
+    color: Option<MinimapImage<B>>,
+    depth: Option<MinimapImage<B>>,
+    framebuffer: Option<B::Framebuffer>,
+    schedule: RenderSchedule,
+    /// Where the map was last drawn around, once it has been.
+    center: Option<[f32; 3]>,
+    /// The top and bottom of the world, which the map's camera looks down between.
+    heights: (f32, f32),
+}
+
+impl<B: Backend> Minimap<B> {
+    /// A map of the world from `top` down to `bottom`, drawn with a depth buffer of
+    /// `depth_format`, which should come from `depth::choose_depth_format`.
+    pub fn new(context: &GfxContext<B>, depth_format: f::Format, top: f32, bottom: f32) -> Result<Self> {
+        let device = context.device.clone();
+        let render_pass = create_offscreen_depth_render_pass::<B>(&device, MINIMAP_FORMAT, depth_format);
+        let mut minimap = Minimap {
+            device,
+            allocator: context.allocator.clone(),
+            render_pass: Some(render_pass),
+            color: None,
+            depth: None,
+            framebuffer: None,
+            schedule: RenderSchedule::new(RENDER_INTERVAL),
+            center: None,
+            heights: (top, bottom),
+        };
+        minimap.color = Some(minimap.create_image(
+            MINIMAP_FORMAT,
+            i::Usage::COLOR_ATTACHMENT | i::Usage::SAMPLED,
+            COLOR_RANGE,
+        )?);
+        minimap.depth = Some(minimap.create_image(
+            depth_format,
+            i::Usage::DEPTH_STENCIL_ATTACHMENT,
+            depth_range(depth_format),
+        )?);
+        let framebuffer = {
+            let attachments = vec![&minimap.color.as_ref().unwrap().view, &minimap.depth.as_ref().unwrap().view];
+            minimap.device.create_framebuffer(
+                minimap.render_pass(),
+                attachments,
+                i::Extent { width: MINIMAP_RESOLUTION, height: MINIMAP_RESOLUTION, depth: 1 },
+            )?
+        };
+        minimap.framebuffer = Some(framebuffer);
+        Ok(minimap)
+    }
+
+    fn create_image(&self, format: f::Format, usage: i::Usage, range: i::SubresourceRange) -> Result<MinimapImage<B>> {
+        let unbound = self.device.create_image(
+            i::Kind::D2(MINIMAP_RESOLUTION, MINIMAP_RESOLUTION, 1, 1),
+            1,
+            format,
+            i::Tiling::Optimal,
+            usage,
+            i::ViewCapabilities::empty(),
+        )?;
+        let requirements = self.device.get_image_requirements(&unbound);
+
+        let mut allocator = self.allocator.borrow_mut();
+        let allocation = allocator
+            .allocate(
+                &requirements,
+                memory::Properties::DEVICE_LOCAL,
+                ResourceKind::Optimal,
+                MemoryCategory::Attachments,
+            )?;
+        let image = self.device
+            .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+        let view = self.device.create_image_view(&image, i::ViewKind::D2, format, f::Swizzle::NO, range)?;
+        Ok(MinimapImage { image, view, allocation })
+    }
+
+    /// Moves on a frame. On the frames the map is due to be drawn again, gives the map's view
+    /// projection centred on `eye`, and remembers where that was for `center`.
+    pub fn next_view(&mut self, eye: [f32; 3]) -> Option<Mat4> {
+        if !self.schedule.advance() {
+            return None;
+        }
+        let (top, bottom) = self.heights;
+        let (view_projection, center) = minimap_view_projection(eye, MINIMAP_RADIUS, MINIMAP_RESOLUTION, top, bottom);
+        self.center = Some(center);
+        Some(view_projection)
+    }
+
+    /// Where the map in the image was drawn around, once it's been drawn.
+    pub fn center(&self) -> Option<[f32; 3]> {
+        self.center
+    }
+
+    /// Where `position` is on the map as it was last drawn, from -1 to 1 left to right and top
+    /// to bottom.
+    pub fn map_position(&self, position: [f32; 3]) -> [f32; 2] {
+        let center = self.center.unwrap_or(position);
+        [(position[0] - center[0]) / MINIMAP_RADIUS, (position[2] - center[2]) / MINIMAP_RADIUS]
+    }
+
+    pub fn render_pass(&self) -> &B::RenderPass {
+        self.render_pass.as_ref().unwrap()
+    }
+
+    pub fn framebuffer(&self) -> &B::Framebuffer {
+        self.framebuffer.as_ref().unwrap()
+    }
+
+    /// The map, for sampling. It's in `ShaderReadOnlyOptimal` once it's been drawn.
+    pub fn view(&self) -> &B::ImageView {
+        &self.color.as_ref().unwrap().view
+    }
+
+    /// A viewport covering the whole map.
+    pub fn viewport(&self) -> pso::Viewport {
+        let size = MINIMAP_RESOLUTION as i16;
+        pso::Viewport {
+            rect: pso::Rect { x: 0, y: 0, w: size, h: size },
+            depth: 0.0..1.0,
+        }
+    }
+}
+
+impl<B: Backend> Drop for Minimap<B> {
+    fn drop(&mut self) {
+        if let Some(framebuffer) = self.framebuffer.take() {
+            self.device.destroy_framebuffer(framebuffer);
+        }
+        for image in self.color.take().into_iter().chain(self.depth.take()) {
+            self.device.destroy_image_view(image.view);
+            self.device.destroy_image(image.image);
+            self.allocator.borrow_mut().free(image.allocation);
+        }
+        if let Some(render_pass) = self.render_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::Vec4;
+
+    #[test]
+    fn the_map_is_drawn_every_few_frames_starting_with_the_first() {
+        let mut schedule = RenderSchedule::new(3);
+        let drawn: Vec<bool> = (0..7).map(|_| schedule.advance()).collect();
+        assert_eq!(drawn, vec![true, false, false, true, false, false, true]);
+    }
+
+    #[test]
+    fn north_is_at_the_top_of_the_map_and_east_on_the_right() {
+        let (view_projection, center) = minimap_view_projection([10.0, 40.0, 20.0], 16.0, 32, 128.0, 0.0);
+        assert_eq!(center, [10.0, 40.0, 20.0]);
+        let clip = |x: f32, y: f32, z: f32| view_projection * Vec4::new(x, y, z, 1.0);
+
+        let middle = clip(10.0, 60.0, 20.0);
+        assert!(middle.x.abs() < 1e-5 && middle.y.abs() < 1e-5);
+        assert!(middle.z > 0.0 && middle.z < 1.0);
+        let east = clip(26.0, 60.0, 20.0);
+        assert!((east.x - 1.0).abs() < 1e-5);
+        // Further north is up the screen, whichever way up the clip space is
+        let north = clip(10.0, 60.0, 4.0);
+        let up_the_screen = if HAL_CLIP_SPACE.y_down { -1.0 } else { 1.0 };
+        assert!((north.y - up_the_screen).abs() < 1e-5);
+        // Higher is nearer
+        assert!(clip(10.0, 100.0, 20.0).z < middle.z);
+    }
+
+    #[test]
+    fn the_centre_snaps_to_the_texel_grid() {
+        let (_, center) = minimap_view_projection([10.3, 0.0, -5.7], 16.0, 32, 128.0, 0.0);
+        assert_eq!(center, [10.0, 0.0, -6.0]);
+    }
+}
@@ -0,0 +1,57 @@
+//! Choosing how many samples per pixel to render with.
+
+use hal::{ image as i, Adapter, Backend, PhysicalDevice };
+
+use error::{ RendererError, Result };
+
+/// The sample counts `--msaa` accepts.
+pub const SAMPLE_COUNTS: [i::NumSamples; 4] = [1, 2, 4, 8];
+
+/// The sample counts the adapter can use for both color and depth attachments. The limits are
+/// bit masks with one bit set for each supported count, the same as Vulkan's
+/// `VkSampleCountFlags`.
+pub fn supported_sample_counts<B: Backend>(adapter: &Adapter<B>) -> Vec<i::NumSamples> {
+    let limits = adapter.physical_device.limits();
+    let mask = limits.framebuffer_color_samples_count & limits.framebuffer_depth_samples_count;
+    SAMPLE_COUNTS
+        .iter()
+        .cloned()
+        .filter(|&samples| samples == 1 || mask & samples != 0)
+        .collect()
+}
+
+/// Checks the sample count passed with `--msaa`, defaulting to 1 (no multisampling). Returns an
+/// error describing the valid options if the value isn't a sample count or the adapter can't
+/// render with it.
+pub fn choose_sample_count<B: Backend>(
+    adapter: &Adapter<B>,
+    requested: Option<i::NumSamples>,
+) -> Result<i::NumSamples> {
+    let samples = match requested {
+        Some(samples) => samples,
+        None => return Ok(1),
+    };
+
+    let supported = supported_sample_counts(adapter);
+    let options = supported
+        .iter()
+        .map(|samples| samples.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    if !SAMPLE_COUNTS.contains(&samples) {
+        return Err(RendererError::InvalidOption(format!(
+            "Invalid sample count '{}'. Expected one of: {}",
+            samples, options,
+        )));
+    }
+
+    if supported.contains(&samples) {
+        Ok(samples)
+    } else {
+        Err(RendererError::InvalidOption(format!(
+            "{}x MSAA isn't supported by {}. Supported sample counts: {}",
+            samples, adapter.info.name, options,
+        )))
+    }
+}
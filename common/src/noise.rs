@@ -0,0 +1,292 @@
+//! Gradient noise for generating terrain.
+//!
+//! `Perlin` is Ken Perlin's improved noise: a lattice of pseudo-random gradients, picked with a
+//! permutation table, blended smoothly between lattice points. It's shuffled from a seed, so the
+//! same seed always gives the same noise, on any machine. `Fbm` adds up several octaves of it at
+//! rising frequencies and falling amplitudes, which looks much more like real ground than a
+//! single octave does.
+//!
+//! Noise is close to 0 on lattice points and stays within about -1 to 1 everywhere.
+
+/// A seeded source of 2D and 3D gradient noise.
+#[derive(Clone)]
+pub struct Perlin {
+    /// A shuffle of 0 to 255, repeated once so lookups of `index + 1` never need wrapping.
+    permutation: [u8; 512],
+}
+
+/// A small, fast generator for shuffling the permutation table. SplitMix64, from Java's
+/// `SplittableRandom`.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Mixes `salt` into `seed`, so that several noises made from one world seed don't all come out
+/// the same.
+pub fn derive_seed(seed: u64, salt: u64) -> u64 {
+    SplitMix64(seed ^ salt.wrapping_mul(0x9e37_79b9_7f4a_7c15)).next()
+}
+
+/// A pseudo-random number for the block at `position`, that's always the same for the same
+/// seed and position. For deciding things like where trees grow.
+pub fn hash_position(seed: u64, position: [i32; 3]) -> u64 {
+    let mut hash = seed;
+    for &coordinate in &position {
+        hash = derive_seed(hash, coordinate as u32 as u64);
+    }
+    hash
+}
+
+/// A seeded stream of pseudo-random numbers, for features like trees that need more than one.
+pub struct Random(SplitMix64);
+
+impl Random {
+    pub fn new(seed: u64) -> Self {
+        Random(SplitMix64(seed))
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0.next()
+    }
+
+    /// A number from 0 up to but not including 1.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.0.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// A whole number from `low` up to but not including `high`.
+    pub fn range(&mut self, low: i32, high: i32) -> i32 {
+        assert!(low < high, "Empty range {}..{}", low, high);
+        low + (self.0.next() % (high - low) as u64) as i32
+    }
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Self {
+        let mut table = [0u8; 256];
+        for (i, entry) in table.iter_mut().enumerate() {
+            *entry = i as u8;
+        }
+        // Fisher-Yates
+        let mut random = SplitMix64(seed);
+        for i in (1..256).rev() {
+            let j = (random.next() % (i as u64 + 1)) as usize;
+            table.swap(i, j);
+        }
+
+        let mut permutation = [0u8; 512];
+        for i in 0..512 {
+            permutation[i] = table[i & 255];
+        }
+        Perlin { permutation }
+    }
+
+    fn hash(&self, x: i32) -> usize {
+        self.permutation[(x & 255) as usize] as usize
+    }
+
+    pub fn get2(&self, x: f32, y: f32) -> f32 {
+        let (xi, xf) = split(x);
+        let (yi, yf) = split(y);
+        let (u, v) = (fade(xf), fade(yf));
+
+        let a = self.hash(xi) + (yi & 255) as usize;
+        let b = self.hash(xi + 1) + (yi & 255) as usize;
+        let corner = |index: usize, dx: f32, dy: f32| grad2(self.permutation[index], dx, dy);
+
+        let bottom = lerp(u, corner(a, xf, yf), corner(b, xf - 1.0, yf));
+        let top = lerp(u, corner(a + 1, xf, yf - 1.0), corner(b + 1, xf - 1.0, yf - 1.0));
+        lerp(v, bottom, top)
+    }
+
+    pub fn get3(&self, x: f32, y: f32, z: f32) -> f32 {
+        let (xi, xf) = split(x);
+        let (yi, yf) = split(y);
+        let (zi, zf) = split(z);
+        let (u, v, w) = (fade(xf), fade(yf), fade(zf));
+
+        let a = self.hash(xi) + (yi & 255) as usize;
+        let aa = self.permutation[a] as usize + (zi & 255) as usize;
+        let ab = self.permutation[a + 1] as usize + (zi & 255) as usize;
+        let b = self.hash(xi + 1) + (yi & 255) as usize;
+        let ba = self.permutation[b] as usize + (zi & 255) as usize;
+        let bb = self.permutation[b + 1] as usize + (zi & 255) as usize;
+        let corner = |index: usize, dx: f32, dy: f32, dz: f32| grad3(self.permutation[index], dx, dy, dz);
+
+        lerp(
+            w,
+            lerp(
+                v,
+                lerp(u, corner(aa, xf, yf, zf), corner(ba, xf - 1.0, yf, zf)),
+                lerp(u, corner(ab, xf, yf - 1.0, zf), corner(bb, xf - 1.0, yf - 1.0, zf)),
+            ),
+            lerp(
+                v,
+                lerp(u, corner(aa + 1, xf, yf, zf - 1.0), corner(ba + 1, xf - 1.0, yf, zf - 1.0)),
+                lerp(
+                    u,
+                    corner(ab + 1, xf, yf - 1.0, zf - 1.0),
+                    corner(bb + 1, xf - 1.0, yf - 1.0, zf - 1.0),
+                ),
+            ),
+        )
+    }
+}
+
+/// Several octaves of `Perlin` noise added together: fractional Brownian motion.
+#[derive(Clone)]
+pub struct Fbm {
+    noise: Perlin,
+    octaves: u32,
+    /// The frequency of the first octave, in lattice cells per unit.
+    frequency: f32,
+    /// How much each octave's frequency is multiplied by.
+    lacunarity: f32,
+    /// How much each octave's amplitude is multiplied by.
+    persistence: f32,
+}
+
+impl Fbm {
+    /// `octaves` octaves starting at `frequency`, each twice the frequency and half the
+    /// amplitude of the one before.
+    pub fn new(seed: u64, octaves: u32, frequency: f32) -> Self {
+        assert!(octaves > 0, "Need at least one octave");
+        Fbm {
+            noise: Perlin::new(seed),
+            octaves,
+            frequency,
+            lacunarity: 2.0,
+            persistence: 0.5,
+        }
+    }
+
+    pub fn with_persistence(mut self, persistence: f32) -> Self {
+        self.persistence = persistence;
+        self
+    }
+
+    pub fn get2(&self, x: f32, y: f32) -> f32 {
+        self.sum(|noise, frequency| noise.get2(x * frequency, y * frequency))
+    }
+
+    pub fn get3(&self, x: f32, y: f32, z: f32) -> f32 {
+        self.sum(|noise, frequency| noise.get3(x * frequency, y * frequency, z * frequency))
+    }
+
+    /// Adds up the octaves, scaled so the result stays within about -1 to 1 however many there
+    /// are.
+    fn sum<F: Fn(&Perlin, f32) -> f32>(&self, octave: F) -> f32 {
+        let mut total = 0.0;
+        let mut frequency = self.frequency;
+        let mut amplitude = 1.0;
+        let mut max = 0.0;
+        for _ in 0..self.octaves {
+            total += octave(&self.noise, frequency) * amplitude;
+            max += amplitude;
+            frequency *= self.lacunarity;
+            amplitude *= self.persistence;
+        }
+        total / max
+    }
+}
+
+/// The lattice cell `value` is in, and how far through it.
+fn split(value: f32) -> (i32, f32) {
+    let floor = value.floor();
+    (floor as i32, value - floor)
+}
+
+/// 6t⁵ - 15t⁴ + 10t³, which has no first or second derivative at 0 and 1 so cells blend
+/// seamlessly.
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f32, a: f32, b: f32) -> f32 {
+    a + t * (b - a)
+}
+
+/// One of 8 directions, picked by `hash`, dotted with the offset from its lattice point.
+fn grad2(hash: u8, x: f32, y: f32) -> f32 {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
+
+/// One of the 12 directions to the middles of a cube's edges, picked by `hash`, dotted with
+/// the offset from its lattice point. The last 4 of 16 repeat some of the 12.
+fn grad3(hash: u8, x: f32, y: f32, z: f32) -> f32 {
+    match hash & 15 {
+        0 | 12 => x + y,
+        1 | 14 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 | 13 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples() -> impl Iterator<Item = (f32, f32, f32)> {
+        (0..2000).map(|i| {
+            let i = i as f32;
+            (i * 0.173 - 91.0, i * 0.291 + 13.5, i * -0.117)
+        })
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_noise() {
+        let (a, b) = (Perlin::new(7), Perlin::new(7));
+        for (x, y, z) in samples() {
+            assert_eq!(a.get3(x, y, z), b.get3(x, y, z));
+            assert_eq!(a.get2(x, y), b.get2(x, y));
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_noise() {
+        let (a, b) = (Perlin::new(7), Perlin::new(8));
+        let same = samples().filter(|&(x, y, z)| a.get3(x, y, z) == b.get3(x, y, z)).count();
+        assert!(same < 50, "{} of the samples matched", same);
+    }
+
+    #[test]
+    fn noise_is_zero_on_the_lattice() {
+        let noise = Perlin::new(1);
+        assert_eq!(noise.get2(3.0, -4.0), 0.0);
+        assert_eq!(noise.get3(-2.0, 5.0, 300.0), 0.0);
+    }
+
+    #[test]
+    fn noise_stays_in_range_and_isnt_flat() {
+        let noise = Fbm::new(3, 4, 0.05);
+        let values: Vec<f32> = samples().flat_map(|(x, y, z)| vec![noise.get2(x, y), noise.get3(x, y, z)]).collect();
+        assert!(values.iter().all(|value| value.abs() <= 1.1));
+        let spread = values.iter().cloned().fold(0.0f32, |max, value| max.max(value.abs()));
+        assert!(spread > 0.3);
+    }
+}
@@ -0,0 +1,149 @@
+//! Skipping draws for things hidden behind other things, with occlusion queries.
+//!
+//! Frustum culling only knows what's in front of the camera, not what's behind a hill or down a
+//! cave. An occlusion query counts the samples that pass the depth test while it's active, so
+//! wrapping an object's draw in one says whether any of it ended up on screen. Like the
+//! `GpuProfiler`'s timestamps, each frame in flight has its own pool, and results are read back
+//! the next time that frame comes around, after `FrameSync::begin_frame` has waited for it. So
+//! they're always a few frames old, but reading them never stalls.
+//!
+//! Each frame, every object that passed frustum culling is either:
+//!
+//! - drawn as normal inside a query, if it was visible the last time it was tested or has never
+//!   been tested, or
+//! - skipped, and just its bounding box drawn inside a query, after everything else and with
+//!   depth and color writes off. If any of the box passes the depth test, the object is drawn
+//!   again once that result comes back.
+//!
+//! The catch is those few frames: something coming out from behind an occluder appears slightly
+//! late. Objects the camera is inside the bounding box of are always drawn, since their box's
+//! faces may all be behind the near plane.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::slice;
+
+use hal::{ command, query, Backend, Device, General };
+
+use error::Result;
+
+/// The most objects that can be tested in one frame. Any past this are drawn without a query,
+/// and count as visible.
+pub const MAX_OCCLUSION_QUERIES: usize = 4096;
+
+struct OcclusionFrame<B: Backend> {
+    pool: Option<B::QueryPool>,
+    /// The object each query was for the last time this frame was used. Query `n` belongs to
+    /// `keys[n]`.
+    keys: Vec<u64>,
+}
+
+/// Remembers which objects were hidden last time they were tested. Objects are identified by a
+/// `u64` key of the chapter's choosing, like a chunk's coordinates packed together.
+pub struct OcclusionCuller<B: Backend> {
+    device: Rc<B::Device>,
+    frames: Vec<OcclusionFrame<B>>,
+    current: usize,
+    /// Whether each object passed its most recent test. Anything missing counts as visible.
+    visible: HashMap<u64, bool>,
+}
+
+impl<B: Backend> OcclusionCuller<B> {
+    pub fn new(device: Rc<B::Device>, frames_in_flight: usize) -> Self {
+        let frames = (0..frames_in_flight)
+            .map(|_| OcclusionFrame {
+                pool: Some(device.create_query_pool(
+                    query::QueryType::Occlusion,
+                    MAX_OCCLUSION_QUERIES as query::QueryId,
+                )),
+                keys: Vec::with_capacity(MAX_OCCLUSION_QUERIES),
+            })
+            .collect();
+
+        OcclusionCuller {
+            device,
+            frames,
+            current: 0,
+            visible: HashMap::new(),
+        }
+    }
+
+    /// Reads back the results from the last time frame `frame_index` was rendered, and resets
+    /// its queries with `command_buffer`. Call this after `FrameSync::begin_frame` and before
+    /// recording any render passes.
+    pub fn begin_frame(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        frame_index: usize,
+    ) -> Result<()> {
+        self.current = frame_index;
+        let frame = &mut self.frames[frame_index];
+        let pool = frame.pool.as_ref().unwrap();
+
+        if !frame.keys.is_empty() {
+            let count = frame.keys.len();
+            let mut samples = vec![0u64; count];
+            let data = unsafe { slice::from_raw_parts_mut(samples.as_mut_ptr() as *mut u8, count * 8) };
+            self.device.get_query_pool_results(
+                pool,
+                0..count as query::QueryId,
+                data,
+                8,
+                query::ResultFlags::BITS_64 | query::ResultFlags::WAIT,
+            )?;
+
+            for (&key, &passed) in frame.keys.iter().zip(samples.iter()) {
+                // Objects forgotten since the query was recorded stay forgotten
+                if let Some(visible) = self.visible.get_mut(&key) {
+                    *visible = passed > 0;
+                }
+            }
+        }
+
+        frame.keys.clear();
+        command_buffer.reset_query_pool(pool, 0..MAX_OCCLUSION_QUERIES as query::QueryId);
+        Ok(())
+    }
+
+    /// Whether object `key` should be drawn, going by its last test.
+    pub fn is_visible(&self, key: u64) -> bool {
+        self.visible.get(&key).cloned().unwrap_or(true)
+    }
+
+    /// Claims a query to test object `key` with this frame, or `None` if they've all been used.
+    /// Begin and end `query(id)` around its draw, or its bounding box's if it isn't visible.
+    pub fn next_query(&mut self, key: u64) -> Option<query::QueryId> {
+        let frame = &mut self.frames[self.current];
+        if frame.keys.len() == MAX_OCCLUSION_QUERIES {
+            return None;
+        }
+        frame.keys.push(key);
+        self.visible.entry(key).or_insert(true);
+        Some((frame.keys.len() - 1) as query::QueryId)
+    }
+
+    /// The query `next_query` handed out as `id`, in this frame's pool.
+    pub fn query(&self, id: query::QueryId) -> query::Query<B> {
+        query::Query {
+            pool: self.frames[self.current].pool.as_ref().unwrap(),
+            id,
+        }
+    }
+
+    /// Drops what's known about object `key`, so it's drawn the next time it's seen. Call this
+    /// when an object is frustum culled or goes away, since its last result will be stale by the
+    /// time it's back in view.
+    pub fn forget(&mut self, key: u64) {
+        self.visible.remove(&key);
+    }
+}
+
+impl<B: Backend> Drop for OcclusionCuller<B> {
+    fn drop(&mut self) {
+        for frame in &mut self.frames {
+            if let Some(pool) = frame.pool.take() {
+                self.device.destroy_query_pool(pool);
+            }
+        }
+    }
+}
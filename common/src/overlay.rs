@@ -0,0 +1,746 @@
+//! A debug UI drawn over the finished frame with Dear ImGui.
+//!
+//! Each frame the chapter passes the overlay its stats and the settings it lets the user change.
+//! The overlay builds its windows from them and records a render pass of its own that draws on
+//! top of the swapchain image. Window events are passed in with `handle_event` so the UI can be
+//! clicked and typed into. F1 shows and hides it.
+
+use std::cell::RefCell;
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+use std::time::Instant;
+
+use hal::{
+    buffer, command, format as f, image as i, pass,
+    pso,
+    Backend, Device, General, IndexType, Primitive, SwapImageIndex,
+};
+
+use imgui::{ FrameSize, ImDrawIdx, ImDrawVert, ImGui, ImGuiCond, ImGuiKey, ImString, ImVec2 };
+use winit;
+
+use allocator::{ Allocator, MemoryCategory };
+use biome::Biome;
+use color::is_srgb;
+use context::GfxContext;
+use culling::CullStats;
+use debug_lines::DebugLineSettings;
+use descriptors::{ DescriptorAllocator, DescriptorSetLayout };
+use error::Result;
+use frame_times::FrameTimes;
+use gbuffer::Shading;
+use gpu_profiler::ScopeTiming;
+use mesh_optimizer::MeshOptimization;
+use mesher::Mesher;
+use pass::create_overlay_render_pass;
+use resources::{ Framebuffers, SwapchainBundle };
+use shader::create_shader_module;
+use staging::StagingRing;
+use texture::Texture;
+use time_of_day::TimeOfDay;
+use view_mode::ViewMode;
+
+/// The SPIR-V for the shaders in `common/shaders/`, compiled by `build.rs`
+#[allow(dead_code)]
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// The keys ImGui needs to know about for text fields and keyboard navigation, in the order of
+/// the indices we give them.
+const KEY_MAP: [(ImGuiKey, winit::VirtualKeyCode); 19] = [
+    (ImGuiKey::Tab, winit::VirtualKeyCode::Tab),
+    (ImGuiKey::LeftArrow, winit::VirtualKeyCode::Left),
+    (ImGuiKey::RightArrow, winit::VirtualKeyCode::Right),
+    (ImGuiKey::UpArrow, winit::VirtualKeyCode::Up),
+    (ImGuiKey::DownArrow, winit::VirtualKeyCode::Down),
+    (ImGuiKey::PageUp, winit::VirtualKeyCode::PageUp),
+    (ImGuiKey::PageDown, winit::VirtualKeyCode::PageDown),
+    (ImGuiKey::Home, winit::VirtualKeyCode::Home),
+    (ImGuiKey::End, winit::VirtualKeyCode::End),
+    (ImGuiKey::Delete, winit::VirtualKeyCode::Delete),
+    (ImGuiKey::Backspace, winit::VirtualKeyCode::Back),
+    (ImGuiKey::Enter, winit::VirtualKeyCode::Return),
+    (ImGuiKey::Escape, winit::VirtualKeyCode::Escape),
+    (ImGuiKey::A, winit::VirtualKeyCode::A),
+    (ImGuiKey::C, winit::VirtualKeyCode::C),
+    (ImGuiKey::V, winit::VirtualKeyCode::V),
+    (ImGuiKey::X, winit::VirtualKeyCode::X),
+    (ImGuiKey::Y, winit::VirtualKeyCode::Y),
+    (ImGuiKey::Z, winit::VirtualKeyCode::Z),
+];
+
+/// The color of a memory heap's line once it's nearly full.
+const NEARLY_FULL_COLOR: (f32, f32, f32, f32) = (1.0, 0.4, 0.3, 1.0);
+
+/// What the overlay shows besides the frame rate and memory use, which it works out itself.
+/// Anything a chapter doesn't have is left empty and its line is left out.
+#[derive(Clone, Debug, Default)]
+pub struct OverlayStats<'a> {
+    pub gpu_timings: &'a [ScopeTiming],
+    pub camera_position: Option<[f32; 3]>,
+    /// The biome the camera is over.
+    pub biome: Option<Biome>,
+    pub loaded_chunks: Option<usize>,
+    /// How many chunks are meshed at each level of detail, from full down.
+    pub lod_chunks: Option<[usize; 3]>,
+    /// How many objects were drawn this frame, and how many frustum and occlusion culling
+    /// skipped.
+    pub culling: Option<CullStats>,
+    /// How many triangles the drawn objects are made of.
+    pub triangles: Option<usize>,
+    /// How many bytes the chunk vertices take, and how many they would as `ChunkVertex`es.
+    pub vertex_memory: Option<(usize, usize)>,
+    /// How the chunk meshes measured before and after `mesh_optimizer::optimize`.
+    pub mesh_optimization: Option<MeshOptimization>,
+    /// How many instances were drawn, and in how many draw calls.
+    pub instances: Option<(usize, usize)>,
+    /// How many point lights were shaded with, out of how many there are loaded.
+    pub point_lights: Option<(usize, usize)>,
+    /// How many particles are alive, and how many there's room for.
+    pub particles: Option<(usize, usize)>,
+    /// The name of the block that placing a block puts down.
+    pub selected_block: Option<&'a str>,
+}
+
+/// The settings the overlay has toggles for. Ticking a box changes the value here, and it's up
+/// to the chapter to act on it. `None` means the chapter doesn't support the setting, so it gets
+/// no toggle.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OverlaySettings {
+    pub vsync: bool,
+    pub wireframe: Option<bool>,
+    pub shadows: Option<bool>,
+    /// Tints everything by which shadow cascade it's in.
+    pub show_cascades: Option<bool>,
+    pub occlusion_culling: Option<bool>,
+    pub mesher: Option<Mesher>,
+    pub shading: Option<Shading>,
+    /// Which debug view the world is drawn with, picked from a list.
+    pub view_mode: Option<ViewMode>,
+    /// Which of the chunk borders, the chunks' boxes and the frozen frustum are drawn.
+    pub debug_lines: Option<DebugLineSettings>,
+    /// Gets a clock, with controls to pause it, speed it up and set the time.
+    pub time_of_day: Option<TimeOfDay>,
+}
+
+/// Has to match the `PushConstants` block in `overlay.vert`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PushConstants {
+    scale: [f32; 2],
+    translate: [f32; 2],
+    srgb_target: f32,
+}
+
+impl PushConstants {
+    /// Push constants are written as a slice of 32 bit words.
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const PushConstants as *const u32,
+                mem::size_of::<PushConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `PushConstants`, in 32 bit words
+const PUSH_CONSTANTS_SIZE: u32 = (mem::size_of::<PushConstants>() / mem::size_of::<u32>()) as u32;
+
+/// How big the ring the UI's vertices and indices go in starts out, in bytes. It grows if a
+/// frame's UI doesn't fit.
+const GEOMETRY_RING_SIZE: u64 = 512 * 1024;
+
+pub struct DebugOverlay<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    imgui: ImGui,
+    visible: bool,
+    /// Which mouse buttons are down, in ImGui's order: left, right, middle.
+    mouse_down: [bool; 5],
+    /// Wheel movement since the last frame, in lines.
+    mouse_wheel: f32,
+    last_frame: Instant,
+    frame_times: FrameTimes,
+    font_texture: Texture<B>,
+    set_layout: Rc<DescriptorSetLayout<B>>,
+    descriptors: DescriptorAllocator<B>,
+    descriptor_set: B::DescriptorSet,
+    render_pass: Option<B::RenderPass>,
+    pipeline_layout: Option<B::PipelineLayout>,
+    pipeline: Option<B::GraphicsPipeline>,
+    framebuffers: Framebuffers<B>,
+    /// The UI's vertices and indices, which are different every frame.
+    geometry: StagingRing<B>,
+    frames_in_flight: usize,
+}
+
+impl<B: Backend> DebugOverlay<B> {
+    /// Uploads ImGui's font atlas and builds the overlay's pipeline for drawing into `swapchain`,
+    /// with `frames_in_flight` frames drawing at once.
+    pub fn new(
+        context: &mut GfxContext<B>,
+        swapchain: &SwapchainBundle<B>,
+        frames_in_flight: usize,
+    ) -> Result<Self> {
+        let mut imgui = ImGui::init();
+        // Don't leave an imgui.ini behind in the working directory
+        imgui.set_ini_filename(None);
+        for (index, &(key, _)) in KEY_MAP.iter().enumerate() {
+            imgui.set_imgui_key(key, index as u8);
+        }
+
+        // The UI is drawn at 1:1, so the font atlas doesn't need any mips
+        let font_texture = imgui.prepare_texture(|handle| {
+            Texture::from_rgba8_with_mips(context, handle.width, handle.height, handle.pixels, 1)
+        })?;
+
+        let device = context.device.clone();
+        let set_layout = Rc::new(DescriptorSetLayout::new(
+            device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut descriptors = DescriptorAllocator::new(device.clone(), set_layout.clone());
+        let descriptor_set = descriptors.allocate()?;
+        device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set: &descriptor_set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(font_texture.view(), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &descriptor_set,
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(font_texture.sampler())),
+            },
+        ]);
+
+        let render_pass = create_overlay_render_pass::<B>(&device, swapchain.format());
+        let pipeline_layout = device.create_pipeline_layout(
+            Some(set_layout.raw()),
+            &[(pso::ShaderStageFlags::VERTEX, 0..PUSH_CONSTANTS_SIZE)],
+        );
+        let pipeline = create_pipeline::<B>(
+            &device,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let framebuffers = Framebuffers::new(device.clone(), &render_pass, swapchain)?;
+        let geometry = StagingRing::new(
+            device.clone(),
+            context.allocator.clone(),
+            GEOMETRY_RING_SIZE,
+            buffer::Usage::VERTEX | buffer::Usage::INDEX,
+        )?;
+
+        Ok(DebugOverlay {
+            device,
+            allocator: context.allocator.clone(),
+            imgui,
+            // Headless runs are compared against reference images, which shouldn't have the
+            // overlay (and its frame rate) in them
+            visible: !context.is_headless(),
+            mouse_down: [false; 5],
+            mouse_wheel: 0.0,
+            last_frame: Instant::now(),
+            frame_times: FrameTimes::new(),
+            font_texture,
+            set_layout,
+            descriptors,
+            descriptor_set,
+            render_pass: Some(render_pass),
+            pipeline_layout: Some(pipeline_layout),
+            pipeline: Some(pipeline),
+            framebuffers,
+            geometry,
+            frames_in_flight,
+        })
+    }
+
+    /// Rebuilds the framebuffers after `swapchain` has been recreated.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.framebuffers.recreate(self.render_pass.as_ref().unwrap(), swapchain)
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Whether the mouse is over one of the overlay's windows, so clicks and wheel movement
+    /// shouldn't also go to the game.
+    pub fn wants_mouse(&self) -> bool {
+        self.visible && self.imgui.want_capture_mouse()
+    }
+
+    /// Whether a text field has focus, so key presses shouldn't also go to the game.
+    pub fn wants_keyboard(&self) -> bool {
+        self.visible && self.imgui.want_capture_keyboard()
+    }
+
+    /// Whether `event` was meant for the overlay rather than the game: a click or scroll over
+    /// one of its windows, or typing into a text field. Releases always go through, so nothing
+    /// the game saw being pressed gets stuck down.
+    pub fn captures(&self, event: &winit::Event) -> bool {
+        let event = match *event {
+            winit::Event::WindowEvent { ref event, .. } => event,
+            _ => return false,
+        };
+        match *event {
+            winit::WindowEvent::MouseInput { state: winit::ElementState::Pressed, .. }
+            | winit::WindowEvent::MouseWheel { .. } => self.wants_mouse(),
+            winit::WindowEvent::KeyboardInput {
+                input: winit::KeyboardInput { state: winit::ElementState::Pressed, .. },
+                ..
+            }
+            | winit::WindowEvent::ReceivedCharacter(_) => self.wants_keyboard(),
+            _ => false,
+        }
+    }
+
+    /// Passes a window event on to ImGui. F1 toggles the overlay.
+    pub fn handle_event(&mut self, event: &winit::Event) {
+        let event = match *event {
+            winit::Event::WindowEvent { ref event, .. } => event,
+            _ => return,
+        };
+        match *event {
+            winit::WindowEvent::CursorMoved { position, .. } => {
+                self.imgui.set_mouse_pos(position.x as f32, position.y as f32);
+            }
+            winit::WindowEvent::MouseInput { state, button, .. } => {
+                let index = match button {
+                    winit::MouseButton::Left => 0,
+                    winit::MouseButton::Right => 1,
+                    winit::MouseButton::Middle => 2,
+                    winit::MouseButton::Other(_) => return,
+                };
+                self.mouse_down[index] = state == winit::ElementState::Pressed;
+                self.imgui.set_mouse_down(self.mouse_down);
+            }
+            winit::WindowEvent::MouseWheel { delta, .. } => {
+                self.mouse_wheel += match delta {
+                    winit::MouseScrollDelta::LineDelta(_, lines) => lines,
+                    // Roughly how far a line scrolls on platforms that report pixels
+                    winit::MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+            }
+            winit::WindowEvent::ReceivedCharacter(character) => {
+                self.imgui.add_input_character(character);
+            }
+            winit::WindowEvent::KeyboardInput { input, .. } => {
+                let pressed = input.state == winit::ElementState::Pressed;
+                self.imgui.set_key_ctrl(input.modifiers.ctrl);
+                self.imgui.set_key_shift(input.modifiers.shift);
+                self.imgui.set_key_alt(input.modifiers.alt);
+                self.imgui.set_key_super(input.modifiers.logo);
+
+                if let Some(key) = input.virtual_keycode {
+                    if key == winit::VirtualKeyCode::F1 && pressed {
+                        self.visible = !self.visible;
+                    }
+                    if let Some(index) = KEY_MAP.iter().position(|&(_, mapped)| mapped == key) {
+                        self.imgui.set_key(index as u8, pressed);
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    /// Builds this frame's UI and records a render pass drawing it over swapchain image
+    /// `image_index`. Call this after the frame's other passes, once a frame.
+    /// `settings` is updated with anything the user changed.
+    pub fn draw(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        image_index: SwapImageIndex,
+        swapchain: &SwapchainBundle<B>,
+        stats: &OverlayStats,
+        settings: &mut OverlaySettings,
+    ) -> Result<()> {
+        let now = Instant::now();
+        let elapsed = now - self.last_frame;
+        let delta_seconds = elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 * 1e-9;
+        self.last_frame = now;
+        self.geometry.begin_frame(self.frames_in_flight);
+        // Keep timing frames while hidden, so the graph is already full when it's shown again
+        self.frame_times.push(delta_seconds);
+        if !self.visible {
+            return Ok(());
+        }
+
+        let memory = self.allocator.borrow().stats();
+        self.imgui.set_mouse_wheel(self.mouse_wheel);
+        self.mouse_wheel = 0.0;
+
+        // Borrow the fields separately so the render callback can use them while the frame has
+        // ImGui borrowed
+        let DebugOverlay {
+            ref mut imgui,
+            ref descriptor_set,
+            ref render_pass,
+            ref pipeline_layout,
+            ref pipeline,
+            ref framebuffers,
+            ref mut geometry,
+            ref frame_times,
+            ..
+        } = *self;
+
+        let logical_size = swapchain.logical_size();
+        let ui = imgui.frame(
+            FrameSize::new(logical_size.width, logical_size.height, swapchain.hidpi_factor()),
+            delta_seconds,
+        );
+        let frame_history = frame_times.history();
+
+        ui.window(im_str!("Renderer"))
+            .position((10.0, 10.0), ImGuiCond::FirstUseEver)
+            .always_auto_resize(true)
+            .build(|| {
+                ui.text(format!(
+                    "{:.1} fps ({:.2} ms, slowest {:.2} ms)",
+                    frame_times.frames_per_second(),
+                    frame_times.average_milliseconds(),
+                    frame_times.max_milliseconds(),
+                ));
+                // Scaled from zero up to at least a 30 fps frame, so small wobbles in a steady
+                // frame rate don't fill the graph
+                ui.plot_lines(im_str!("##frame times"), &frame_history)
+                    .graph_size(ImVec2::new(240.0, 50.0))
+                    .scale_min(0.0)
+                    .scale_max(frame_times.max_milliseconds().max(1000.0 / 30.0))
+                    .build();
+                for timing in stats.gpu_timings {
+                    ui.text(format!("Gpu {}: {:.3} ms", timing.name, timing.milliseconds));
+                }
+                ui.text(format!(
+                    "Memory: {:.1} of {:.1} MB in {} allocations",
+                    memory.used_bytes as f64 / (1024.0 * 1024.0),
+                    memory.reserved_bytes as f64 / (1024.0 * 1024.0),
+                    memory.allocations,
+                ));
+                let by_category: Vec<String> = MemoryCategory::ALL
+                    .iter()
+                    .filter(|category| memory.categories[category.index()] > 0)
+                    .map(|&category| {
+                        let bytes = memory.categories[category.index()];
+                        format!("{:.1} MB {}", bytes as f64 / (1024.0 * 1024.0), category)
+                    })
+                    .collect();
+                if !by_category.is_empty() {
+                    ui.text(format!("  {}", by_category.join(", ")));
+                }
+                // Only the heaps something's been allocated from, against what the adapter says
+                // they hold
+                let used_heaps = memory.heaps.iter().enumerate().filter(|&(_, heap)| heap.reserved_bytes > 0);
+                for (index, heap) in used_heaps {
+                    let line = format!(
+                        "  Heap {}{}: {:.1} of {:.1} MB, {:.0}%",
+                        index,
+                        if heap.device_local { " (device local)" } else { "" },
+                        heap.reserved_bytes as f64 / (1024.0 * 1024.0),
+                        heap.size as f64 / (1024.0 * 1024.0),
+                        heap.fraction() * 100.0,
+                    );
+                    if heap.near_budget() {
+                        ui.text_colored(NEARLY_FULL_COLOR, &ImString::new(line + ", nearly full"));
+                    } else {
+                        ui.text(line);
+                    }
+                }
+                if let Some(position) = stats.camera_position {
+                    ui.separator();
+                    ui.text(format!("Camera: {:.1}, {:.1}, {:.1}", position[0], position[1], position[2]));
+                }
+                if let Some(biome) = stats.biome {
+                    ui.text(format!("Biome: {}", biome));
+                }
+                if let Some(chunks) = stats.loaded_chunks {
+                    ui.text(format!("Loaded chunks: {}", chunks));
+                }
+                if let Some(counts) = stats.lod_chunks {
+                    ui.text(format!("Detail: {} full, {} half, {} quarter", counts[0], counts[1], counts[2]));
+                }
+                if let Some(culling) = stats.culling {
+                    ui.text(format!(
+                        "Drawn: {}, culled: {}, occluded: {}",
+                        culling.drawn, culling.culled, culling.occluded,
+                    ));
+                }
+                if let Some(triangles) = stats.triangles {
+                    ui.text(format!("Triangles: {}", triangles));
+                }
+                if let Some((bytes, full_bytes)) = stats.vertex_memory {
+                    ui.text(format!(
+                        "Vertices: {:.2} MB, {:.2} MB unpacked",
+                        bytes as f64 / (1024.0 * 1024.0),
+                        full_bytes as f64 / (1024.0 * 1024.0),
+                    ));
+                }
+                if let Some(optimization) = stats.mesh_optimization {
+                    // Optimizing never changes the triangles, so they're on the line above
+                    ui.text(format!(
+                        "Optimized: {} vertices, ACMR {:.2}, from {} and {:.2}",
+                        optimization.after.vertices,
+                        optimization.after.acmr(),
+                        optimization.before.vertices,
+                        optimization.before.acmr(),
+                    ));
+                }
+                if let Some((instances, draws)) = stats.instances {
+                    ui.text(format!("Instances: {} in {} draws", instances, draws));
+                }
+                if let Some((shaded, loaded)) = stats.point_lights {
+                    ui.text(format!("Point lights: {} of {}", shaded, loaded));
+                }
+                if let Some((alive, capacity)) = stats.particles {
+                    ui.text(format!("Particles: {} of {}", alive, capacity));
+                }
+                if let Some(block) = stats.selected_block {
+                    ui.text(format!("Placing: {}", block));
+                }
+
+                ui.separator();
+                ui.checkbox(im_str!("Vsync"), &mut settings.vsync);
+                if let Some(ref mut wireframe) = settings.wireframe {
+                    ui.checkbox(im_str!("Wireframe"), wireframe);
+                }
+                if let Some(ref mut shadows) = settings.shadows {
+                    ui.checkbox(im_str!("Shadows"), shadows);
+                }
+                if let Some(ref mut show_cascades) = settings.show_cascades {
+                    ui.checkbox(im_str!("Show shadow cascades"), show_cascades);
+                }
+                if let Some(ref mut occlusion_culling) = settings.occlusion_culling {
+                    ui.checkbox(im_str!("Occlusion culling"), occlusion_culling);
+                }
+                if let Some(ref mut mesher) = settings.mesher {
+                    let mut greedy = *mesher == Mesher::Greedy;
+                    ui.checkbox(im_str!("Greedy meshing"), &mut greedy);
+                    *mesher = if greedy { Mesher::Greedy } else { Mesher::Naive };
+                }
+                if let Some(ref mut shading) = settings.shading {
+                    let mut deferred = *shading == Shading::Deferred;
+                    ui.checkbox(im_str!("Deferred shading"), &mut deferred);
+                    *shading = if deferred { Shading::Deferred } else { Shading::Forward };
+                }
+                if let Some(ref mut view_mode) = settings.view_mode {
+                    ui.text("View (F3)");
+                    let mut selected = ViewMode::ALL.iter().position(|&mode| mode == *view_mode).unwrap_or(0) as i32;
+                    for (index, mode) in ViewMode::ALL.iter().enumerate() {
+                        ui.radio_button(&ImString::new(mode.to_string()), &mut selected, index as i32);
+                    }
+                    *view_mode = ViewMode::ALL[selected as usize];
+                }
+                if let Some(ref mut debug_lines) = settings.debug_lines {
+                    ui.text("Debug lines");
+                    ui.checkbox(im_str!("Chunk borders"), &mut debug_lines.chunk_borders);
+                    ui.checkbox(im_str!("Chunk states"), &mut debug_lines.chunk_bounds);
+                    ui.checkbox(im_str!("Freeze frustum"), &mut debug_lines.frustum);
+                }
+                if let Some(ref mut time_of_day) = settings.time_of_day {
+                    ui.separator();
+                    let (hours, minutes) = time_of_day.clock();
+                    ui.text(format!("Time: {:02}:{:02} ({})", hours, minutes, time_of_day.phase()));
+                    ui.checkbox(im_str!("Pause time"), &mut time_of_day.paused);
+                    ui.slider_float(im_str!("Speed"), &mut time_of_day.speed, 0.0, 100.0)
+                        .power(3.0)
+                        .build();
+                    // Set in hours, which are easier to aim for than fractions of a day
+                    let mut hour = time_of_day.time * 24.0;
+                    if ui.slider_float(im_str!("Hour"), &mut hour, 0.0, 24.0).build() {
+                        time_of_day.set_time(hour / 24.0);
+                    }
+                }
+            });
+
+        ui.render(|_, draw_data| {
+            // Gather every draw list into one vertex and one index buffer
+            let mut vertices: Vec<ImDrawVert> = Vec::new();
+            let mut indices: Vec<ImDrawIdx> = Vec::new();
+            let mut draws = Vec::new();
+            for draw_list in &draw_data {
+                let vertex_offset = vertices.len() as i32;
+                let mut index_offset = indices.len() as u32;
+                vertices.extend_from_slice(draw_list.vtx_buffer);
+                indices.extend_from_slice(draw_list.idx_buffer);
+                for command in draw_list.cmd_buffer {
+                    draws.push((index_offset..index_offset + command.elem_count, vertex_offset, command.clip_rect));
+                    index_offset += command.elem_count;
+                }
+            }
+            if draws.is_empty() {
+                return Ok(());
+            }
+
+            // The vertices and the indices go in one allocation, so they're in the same buffer
+            // even if the ring has to grow. Vertices are a whole number of words, so the indices
+            // right after them are aligned.
+            let vertex_bytes = (vertices.len() * mem::size_of::<ImDrawVert>()) as u64;
+            let index_bytes = (indices.len() * mem::size_of::<ImDrawIdx>()) as u64;
+            let vertex_start = geometry.allocate(vertex_bytes + index_bytes)?;
+            let index_start = vertex_start + vertex_bytes;
+            geometry.write_at(vertex_start, &vertices)?;
+            geometry.write_at(index_start, &indices)?;
+
+            let pipeline_layout = pipeline_layout.as_ref().unwrap();
+            let viewport = swapchain.viewport();
+            let push_constants = PushConstants {
+                scale: [2.0 / logical_size.width as f32, 2.0 / logical_size.height as f32],
+                translate: [-1.0, -1.0],
+                srgb_target: if is_srgb(swapchain.format()) { 1.0 } else { 0.0 },
+            };
+
+            command_buffer.set_viewports(0, &[viewport.clone()]);
+            command_buffer.bind_graphics_pipeline(pipeline.as_ref().unwrap());
+            command_buffer.bind_graphics_descriptor_sets(pipeline_layout, 0, Some(descriptor_set), &[]);
+            command_buffer.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(geometry.buffer(), vertex_start)]));
+            command_buffer.bind_index_buffer(buffer::IndexBufferView {
+                buffer: geometry.buffer(),
+                offset: index_start,
+                index_type: IndexType::U16,
+            });
+
+            let mut encoder = command_buffer.begin_render_pass_inline(
+                render_pass.as_ref().unwrap(),
+                framebuffers.get(image_index),
+                viewport.rect,
+                &[],
+            );
+            encoder.push_graphics_constants(
+                pipeline_layout,
+                pso::ShaderStageFlags::VERTEX,
+                0,
+                push_constants.as_words(),
+            );
+
+            // Clip rects are in logical pixels, scissors in physical ones
+            let scale = swapchain.hidpi_factor() as f32;
+            let extent = swapchain.extent();
+            for (indices, vertex_offset, clip) in draws {
+                let x = (clip.x * scale).max(0.0);
+                let y = (clip.y * scale).max(0.0);
+                let right = (clip.z * scale).min(extent.width as f32);
+                let bottom = (clip.w * scale).min(extent.height as f32);
+                if right <= x || bottom <= y {
+                    continue;
+                }
+                encoder.set_scissors(0, &[pso::Rect {
+                    x: x as _,
+                    y: y as _,
+                    w: (right - x) as _,
+                    h: (bottom - y) as _,
+                }]);
+                encoder.draw_indexed(indices, vertex_offset, 0..1);
+            }
+            Ok(())
+        })
+    }
+}
+
+impl<B: Backend> Drop for DebugOverlay<B> {
+    fn drop(&mut self) {
+        if let Some(pipeline) = self.pipeline.take() {
+            self.device.destroy_graphics_pipeline(pipeline);
+        }
+        if let Some(pipeline_layout) = self.pipeline_layout.take() {
+            self.device.destroy_pipeline_layout(pipeline_layout);
+        }
+        if let Some(render_pass) = self.render_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
+
+fn create_pipeline<B: Backend>(
+    device: &B::Device,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders::OVERLAY_VERT)?;
+    let fs_module = create_shader_module::<B>(device, shaders::OVERLAY_FRAG)?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(
+            pso::ColorMask::ALL,
+            pso::BlendState::ALPHA,
+        ));
+
+        // ImGui's own vertex layout: position, uv and a packed RGBA8 color
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<ImDrawVert>() as u32,
+            rate: 0,
+        });
+        for (location, &(format, offset)) in [
+            (f::Format::Rg32Float, 0),
+            (f::Format::Rg32Float, 8),
+            (f::Format::Rgba8Unorm, 16),
+        ].iter().enumerate() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location: location as u32,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the overlay pipeline");
+    Ok(pipeline)
+}
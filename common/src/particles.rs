@@ -0,0 +1,342 @@
+//! Small things thrown about by the world: the bits a broken block breaks into, dust drifting in
+//! the air and leaves falling out of trees.
+//!
+//! Particles are simulated on the cpu, a tick at a time along with everything else, and drawn as
+//! billboards. Each one moves by its velocity, is pulled down by its own gravity, slowed by its
+//! own drag, and stopped by whatever solid blocks it runs into. Once it's lived out its lifetime
+//! it's gone, fading out over the last part of it.
+//!
+//! They live in a pool a fixed size, so spawning never allocates. When it's full, each new
+//! particle takes the place of one of the old ones, going round the pool in turn, so a burst of
+//! them pushes out whatever's been around longest rather than being dropped.
+//!
+//! For more particles than the cpu can keep up with, `GpuParticles` simulates them on the gpu
+//! instead. They're still spawned here, and taken straight back out with `drain` to be handed
+//! over.
+
+use std::vec;
+
+use atlas::UvRect;
+use billboard::Sprite;
+use noise::Random;
+use timestep::Interpolated;
+
+/// How many pieces a broken block breaks into.
+pub const BREAK_PIECES: usize = 16;
+
+/// What fraction of a particle's lifetime it fades out over at the end.
+const FADE_FRACTION: f32 = 0.25;
+
+/// How much faster something lying on the ground slows down than it does in the air, as it
+/// scrapes along.
+const GROUND_FRICTION: f32 = 8.0;
+
+/// One particle. The kinds of particle are no more than which of these they start with.
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub position: Interpolated<[f32; 3]>,
+    /// In blocks per second.
+    pub velocity: [f32; 3],
+    /// How fast it speeds up going down, in blocks per second per second.
+    pub gravity: f32,
+    /// What fraction of its velocity it loses each second.
+    pub drag: f32,
+    /// How long it's been around, and how long it lasts, in seconds.
+    pub age: f32,
+    pub lifetime: f32,
+    /// How wide and tall it is, in blocks.
+    pub size: f32,
+    /// The part of the atlas it shows.
+    pub uv: UvRect,
+    pub color: [f32; 4],
+    /// Sky light, then block light, from 0 to 1, as for a `Sprite`.
+    pub light: [f32; 2],
+}
+
+impl Particle {
+    /// A white particle at `position` that doesn't move, fall or fade until it's told to.
+    pub fn new(position: [f32; 3], size: f32, uv: UvRect, lifetime: f32) -> Self {
+        Particle {
+            position: Interpolated::new(position),
+            velocity: [0.0; 3],
+            gravity: 0.0,
+            drag: 0.0,
+            age: 0.0,
+            lifetime,
+            size,
+            uv,
+            color: [1.0; 4],
+            light: [1.0, 0.0],
+        }
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.age < self.lifetime
+    }
+
+    /// How opaque it is by now, fading from its colour's alpha to nothing at the end.
+    pub fn opacity(&self) -> f32 {
+        let left = (self.lifetime - self.age) / (self.lifetime * FADE_FRACTION);
+        self.color[3] * left.max(0.0).min(1.0)
+    }
+
+    /// Moves it on by one tick of `seconds`, stopping it at any face of a block `solid` says is
+    /// solid. Each axis is moved on its own, so it slides along whatever it hits rather than
+    /// sticking to it.
+    fn update<F: Fn([i32; 3]) -> bool>(&mut self, seconds: f32, solid: &F) {
+        self.age += seconds;
+        self.velocity[1] -= self.gravity * seconds;
+
+        let mut position = self.position.current();
+        let mut grounded = false;
+        for axis in 0..3 {
+            let mut moved = position;
+            moved[axis] += self.velocity[axis] * seconds;
+            if solid(block_at(moved)) {
+                grounded |= axis == 1 && self.velocity[1] < 0.0;
+                self.velocity[axis] = 0.0;
+            } else {
+                position = moved;
+            }
+        }
+        self.position.set(position);
+
+        let drag = if grounded { self.drag + GROUND_FRICTION } else { self.drag };
+        let kept = (1.0 - drag * seconds).max(0.0);
+        for speed in &mut self.velocity {
+            *speed *= kept;
+        }
+    }
+}
+
+fn block_at(position: [f32; 3]) -> [i32; 3] {
+    [position[0].floor() as i32, position[1].floor() as i32, position[2].floor() as i32]
+}
+
+/// The square `size` across of `uv` with its top left corner at `u`, `v`, all as fractions of
+/// `uv`.
+fn part_of(uv: UvRect, u: f32, v: f32, size: f32) -> UvRect {
+    let min = uv.lerp(u, v);
+    let max = uv.lerp(u + size, v + size);
+    UvRect { u0: min[0], v0: min[1], u1: max[0], v1: max[1] }
+}
+
+/// A pool of particles, and the random numbers they're spawned with.
+pub struct Particles {
+    particles: Vec<Particle>,
+    capacity: usize,
+    /// Which particle the next one spawned takes the place of when the pool is full.
+    next_replaced: usize,
+    random: Random,
+}
+
+impl Particles {
+    pub fn new(capacity: usize, seed: u64) -> Self {
+        Particles {
+            particles: Vec::with_capacity(capacity),
+            capacity,
+            next_replaced: 0,
+            random: Random::new(seed),
+        }
+    }
+
+    /// How many particles are alive.
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    /// Random numbers for spawning particles with.
+    pub fn random(&mut self) -> &mut Random {
+        &mut self.random
+    }
+
+    pub fn spawn(&mut self, particle: Particle) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.particles.len() < self.capacity {
+            self.particles.push(particle);
+        } else {
+            self.particles[self.next_replaced] = particle;
+            self.next_replaced = (self.next_replaced + 1) % self.capacity;
+        }
+    }
+
+    /// Breaks the block at `block`, showing `uv` on its sides, into pieces that burst out of it
+    /// and fall, each showing a random quarter of the tile.
+    pub fn break_block(&mut self, block: [i32; 3], uv: UvRect, light: [f32; 2]) {
+        for _ in 0..BREAK_PIECES {
+            let offset = [self.random.next_f32(), self.random.next_f32(), self.random.next_f32()];
+            let position = [
+                block[0] as f32 + offset[0],
+                block[1] as f32 + offset[1],
+                block[2] as f32 + offset[2],
+            ];
+            let (u, v) = (self.random.next_f32() * 0.75, self.random.next_f32() * 0.75);
+            let lifetime = 0.6 + 0.6 * self.random.next_f32();
+            let mut piece = Particle::new(position, 0.12, part_of(uv, u, v, 0.25), lifetime);
+            // Outwards from the middle of the block, and up a bit more
+            piece.velocity = [
+                (offset[0] - 0.5) * 4.0,
+                (offset[1] - 0.5) * 4.0 + 3.0,
+                (offset[2] - 0.5) * 4.0,
+            ];
+            piece.gravity = 20.0;
+            piece.drag = 0.5;
+            piece.light = light;
+            self.spawn(piece);
+        }
+    }
+
+    /// A mote of dust, drifting slowly wherever it started.
+    pub fn dust(&mut self, position: [f32; 3], uv: UvRect, light: [f32; 2]) {
+        let lifetime = 4.0 + 4.0 * self.random.next_f32();
+        let mut mote = Particle::new(position, 0.04, uv, lifetime);
+        mote.velocity = [
+            (self.random.next_f32() - 0.5) * 0.3,
+            (self.random.next_f32() - 0.5) * 0.1,
+            (self.random.next_f32() - 0.5) * 0.3,
+        ];
+        mote.color = [1.0, 1.0, 0.9, 0.6];
+        mote.light = light;
+        self.spawn(mote);
+    }
+
+    /// A leaf, falling slowly from `position` and drifting off to one side as it does.
+    pub fn falling_leaf(&mut self, position: [f32; 3], uv: UvRect, light: [f32; 2]) {
+        let lifetime = 5.0 + 3.0 * self.random.next_f32();
+        // Small enough for the leaf tile's gaps to give it a ragged edge
+        let (u, v) = (self.random.next_f32() * 0.5, self.random.next_f32() * 0.5);
+        let mut leaf = Particle::new(position, 0.2, part_of(uv, u, v, 0.5), lifetime);
+        leaf.velocity = [(self.random.next_f32() - 0.5) * 1.5, 0.0, (self.random.next_f32() - 0.5) * 1.5];
+        // Drag keeps it from ever falling faster than gravity / drag
+        leaf.gravity = 1.5;
+        leaf.drag = 1.5;
+        leaf.light = light;
+        self.spawn(leaf);
+    }
+
+    /// A snowflake, drifting down from `position` and settling wherever it lands.
+    pub fn snowflake(&mut self, position: [f32; 3], uv: UvRect, light: [f32; 2]) {
+        let lifetime = 10.0 + 4.0 * self.random.next_f32();
+        let mut flake = Particle::new(position, 0.06, uv, lifetime);
+        flake.velocity = [(self.random.next_f32() - 0.5) * 0.8, 0.0, (self.random.next_f32() - 0.5) * 0.8];
+        // Falling at no more than two blocks a second
+        flake.gravity = 2.0;
+        flake.drag = 1.0;
+        flake.light = light;
+        self.spawn(flake);
+    }
+
+    /// Takes every particle out of the pool, for simulating somewhere else.
+    pub fn drain(&mut self) -> vec::Drain<'_, Particle> {
+        self.next_replaced = 0;
+        self.particles.drain(..)
+    }
+
+    /// Runs one tick of `seconds`, and lets go of the particles that have lived out their
+    /// lifetimes. `solid` says whether the block at a world position stops particles.
+    pub fn update<F: Fn([i32; 3]) -> bool>(&mut self, seconds: f32, solid: F) {
+        for particle in &mut self.particles {
+            particle.update(seconds, &solid);
+        }
+        self.particles.retain(Particle::is_alive);
+        if self.next_replaced >= self.particles.len() {
+            self.next_replaced = 0;
+        }
+    }
+
+    /// Adds a sprite for each particle to `sprites`, `alpha` of the way from the last tick to
+    /// the next.
+    pub fn sprites(&self, alpha: f32, sprites: &mut Vec<Sprite>) {
+        sprites.extend(self.particles.iter().map(|particle| {
+            let mut sprite = Sprite::new(particle.position.get(alpha), particle.size, particle.uv);
+            sprite.color = particle.color;
+            sprite.color[3] = particle.opacity();
+            sprite.light = particle.light;
+            sprite
+        }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UV: UvRect = UvRect { u0: 0.0, v0: 0.0, u1: 1.0, v1: 1.0 };
+
+    fn no_blocks(_: [i32; 3]) -> bool {
+        false
+    }
+
+    #[test]
+    fn particles_fall_and_die_of_old_age() {
+        let mut particles = Particles::new(4, 1);
+        let mut particle = Particle::new([0.5, 10.0, 0.5], 0.1, UV, 1.0);
+        particle.gravity = 10.0;
+        particles.spawn(particle);
+        for _ in 0..30 {
+            particles.update(1.0 / 60.0, no_blocks);
+        }
+        let mut sprites = Vec::new();
+        particles.sprites(1.0, &mut sprites);
+        assert_eq!(sprites.len(), 1);
+        assert!(sprites[0].position[1] < 10.0);
+
+        for _ in 0..31 {
+            particles.update(1.0 / 60.0, no_blocks);
+        }
+        assert!(particles.is_empty());
+    }
+
+    #[test]
+    fn particles_land_on_solid_blocks() {
+        let mut particles = Particles::new(4, 1);
+        let mut particle = Particle::new([0.5, 2.5, 0.5], 0.1, UV, 10.0);
+        particle.gravity = 20.0;
+        particles.spawn(particle);
+        for _ in 0..120 {
+            particles.update(1.0 / 60.0, |block| block[1] < 1);
+        }
+        let mut sprites = Vec::new();
+        particles.sprites(1.0, &mut sprites);
+        assert!(sprites[0].position[1] >= 1.0 && sprites[0].position[1] < 1.1);
+    }
+
+    #[test]
+    fn a_full_pool_replaces_its_particles_in_turn() {
+        let mut particles = Particles::new(2, 1);
+        for x in 0..3 {
+            particles.spawn(Particle::new([x as f32, 0.0, 0.0], 0.1, UV, 1.0));
+        }
+        assert_eq!(particles.len(), 2);
+        let mut sprites = Vec::new();
+        particles.sprites(1.0, &mut sprites);
+        let xs: Vec<f32> = sprites.iter().map(|sprite| sprite.position[0]).collect();
+        assert_eq!(xs, vec![2.0, 1.0]);
+    }
+
+    #[test]
+    fn draining_empties_the_pool() {
+        let mut particles = Particles::new(2, 1);
+        for x in 0..3 {
+            particles.spawn(Particle::new([x as f32, 0.0, 0.0], 0.1, UV, 1.0));
+        }
+        assert_eq!(particles.drain().count(), 2);
+        assert!(particles.is_empty());
+        particles.spawn(Particle::new([0.0; 3], 0.1, UV, 1.0));
+        assert_eq!(particles.len(), 1);
+    }
+
+    #[test]
+    fn particles_fade_out_at_the_end() {
+        let mut particle = Particle::new([0.0; 3], 0.1, UV, 1.0);
+        assert_eq!(particle.opacity(), 1.0);
+        particle.age = 0.875;
+        assert!((particle.opacity() - 0.5).abs() < 1e-5);
+    }
+}
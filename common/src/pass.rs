@@ -0,0 +1,592 @@
+//! Helpers for building the render passes the chapters share.
+
+use hal::{
+    format as f, image as i, pass,
+    pso::PipelineStage,
+    Backend, Device,
+};
+
+use gbuffer::{ ALBEDO_FORMAT, MATERIAL_FORMAT, NORMAL_FORMAT };
+
+/// Creates the render pass from chapter 02: a single color attachment of `format` that is
+/// cleared at the start of the pass and left ready to present at the end.
+pub fn create_color_render_pass<B: Backend>(device: &B::Device, format: f::Format) -> B::RenderPass {
+    let color_attachment = pass::Attachment {
+        format: Some(format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::Present,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+        depth_stencil: None,
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    let dependency = pass::SubpassDependency {
+        passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+        stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        accesses: i::Access::empty()
+            ..(i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE),
+    };
+
+    device.create_render_pass(&[color_attachment], &[subpass], &[dependency])
+}
+
+/// Like `create_color_render_pass`, with a depth attachment of `depth_format` as attachment 1.
+/// Depth is cleared at the start of the pass and thrown away at the end.
+pub fn create_depth_render_pass<B: Backend>(
+    device: &B::Device,
+    color_format: f::Format,
+    depth_format: f::Format,
+) -> B::RenderPass {
+    let color_attachment = pass::Attachment {
+        format: Some(color_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::Present,
+    };
+
+    let depth_attachment = pass::Attachment {
+        format: Some(depth_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::DontCare,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::DepthStencilAttachmentOptimal,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+        depth_stencil: Some(&(1, i::Layout::DepthStencilAttachmentOptimal)),
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    let dependency = pass::SubpassDependency {
+        passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+        stages: (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS)
+            ..(PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS),
+        accesses: i::Access::empty()
+            ..(i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE
+                | i::Access::DEPTH_STENCIL_ATTACHMENT_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+    };
+
+    device.create_render_pass(&[color_attachment, depth_attachment], &[subpass], &[dependency])
+}
+
+/// A color and depth pass drawn with `samples` samples per pixel.
+///
+/// The multisampled color target (attachment 1) is resolved into the single sampled swapchain
+/// image (attachment 0) at the end of the subpass, and only the resolved image is kept. Depth is
+/// attachment 2. With one sample there's nothing to resolve, so this is just
+/// `create_depth_render_pass`.
+pub fn create_multisampled_render_pass<B: Backend>(
+    device: &B::Device,
+    color_format: f::Format,
+    depth_format: f::Format,
+    samples: i::NumSamples,
+) -> B::RenderPass {
+    if samples <= 1 {
+        return create_depth_render_pass::<B>(device, color_format, depth_format);
+    }
+
+    let resolve_attachment = pass::Attachment {
+        format: Some(color_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::DontCare,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::Present,
+    };
+
+    let color_attachment = pass::Attachment {
+        format: Some(color_format),
+        samples,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::DontCare,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::ColorAttachmentOptimal,
+    };
+
+    let depth_attachment = pass::Attachment {
+        format: Some(depth_format),
+        samples,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::DontCare,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::DepthStencilAttachmentOptimal,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[(1, i::Layout::ColorAttachmentOptimal)],
+        depth_stencil: Some(&(2, i::Layout::DepthStencilAttachmentOptimal)),
+        inputs: &[],
+        resolves: &[(0, i::Layout::ColorAttachmentOptimal)],
+        preserves: &[],
+    };
+
+    let dependency = pass::SubpassDependency {
+        passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+        stages: (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS)
+            ..(PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS),
+        accesses: i::Access::empty()
+            ..(i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE
+                | i::Access::DEPTH_STENCIL_ATTACHMENT_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+    };
+
+    device.create_render_pass(
+        &[resolve_attachment, color_attachment, depth_attachment],
+        &[subpass],
+        &[dependency],
+    )
+}
+
+/// Like `create_multisampled_render_pass`, but drawing into an image that a later pass samples,
+/// like the HDR target from `hdr`, rather than into the swapchain image. Attachment 0 is that
+/// image, which the multisampled color target (attachment 1) is resolved into when there's more
+/// than one sample, and depth comes last. The image is left ready to sample at the end.
+pub fn create_scene_render_pass<B: Backend>(
+    device: &B::Device,
+    color_format: f::Format,
+    depth_format: f::Format,
+    samples: i::NumSamples,
+) -> B::RenderPass {
+    let multisampled = samples > 1;
+    // Only cleared when it's drawn into directly. A resolve overwrites all of it anyway.
+    let target_attachment = pass::Attachment {
+        format: Some(color_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: if multisampled { pass::AttachmentLoadOp::DontCare } else { pass::AttachmentLoadOp::Clear },
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::ShaderReadOnlyOptimal,
+    };
+
+    let color_attachment = pass::Attachment {
+        format: Some(color_format),
+        samples,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::DontCare,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::ColorAttachmentOptimal,
+    };
+
+    let depth_attachment = pass::Attachment {
+        format: Some(depth_format),
+        samples,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::DontCare,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::DepthStencilAttachmentOptimal,
+    };
+
+    let (attachments, color, depth) = if multisampled {
+        (vec![target_attachment, color_attachment, depth_attachment], 1, 2)
+    } else {
+        (vec![target_attachment, depth_attachment], 0, 1)
+    };
+    let colors = [(color, i::Layout::ColorAttachmentOptimal)];
+    let depth_stencil = (depth, i::Layout::DepthStencilAttachmentOptimal);
+    let resolves: &[pass::AttachmentRef] = if multisampled { &[(0, i::Layout::ColorAttachmentOptimal)] } else { &[] };
+    let subpass = pass::SubpassDesc {
+        colors: &colors,
+        depth_stencil: Some(&depth_stencil),
+        inputs: &[],
+        resolves,
+        preserves: &[],
+    };
+
+    // The last pass to sample the image has to be done before it's drawn over, and this one has
+    // to be done drawing it before the next one samples it
+    let dependencies = [
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+            stages: (PipelineStage::FRAGMENT_SHADER | PipelineStage::COLOR_ATTACHMENT_OUTPUT
+                | PipelineStage::EARLY_FRAGMENT_TESTS)
+                ..(PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS),
+            accesses: i::Access::SHADER_READ
+                ..(i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE
+                    | i::Access::DEPTH_STENCIL_ATTACHMENT_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        },
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+            stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::FRAGMENT_SHADER,
+            accesses: i::Access::COLOR_ATTACHMENT_WRITE..i::Access::SHADER_READ,
+        },
+    ];
+
+    device.create_render_pass(&attachments, &[subpass], &dependencies)
+}
+
+/// A pass that draws on top of whatever an earlier pass left in the swapchain image, for
+/// overlays. The image is loaded rather than cleared, and starts and ends ready to present.
+pub fn create_overlay_render_pass<B: Backend>(device: &B::Device, format: f::Format) -> B::RenderPass {
+    let color_attachment = pass::Attachment {
+        format: Some(format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Load,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Present..i::Layout::Present,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+        depth_stencil: None,
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    // The earlier pass has to be done writing the image before we blend over it
+    let dependency = pass::SubpassDependency {
+        passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+        stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        accesses: i::Access::COLOR_ATTACHMENT_WRITE
+            ..(i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE),
+    };
+
+    device.create_render_pass(&[color_attachment], &[subpass], &[dependency])
+}
+
+/// A depth-only pass for drawing a shadow map of `format`. Depth is cleared at the start and
+/// kept at the end, ready for the passes after it to sample.
+pub fn create_shadow_render_pass<B: Backend>(device: &B::Device, format: f::Format) -> B::RenderPass {
+    let depth_attachment = pass::Attachment {
+        format: Some(format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::ShaderReadOnlyOptimal,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[],
+        depth_stencil: Some(&(0, i::Layout::DepthStencilAttachmentOptimal)),
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    // The last frame's passes have to be done sampling the map before it's drawn over, and this
+    // one has to be done drawing it before the next pass samples it
+    let dependencies = [
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+            stages: PipelineStage::FRAGMENT_SHADER..PipelineStage::EARLY_FRAGMENT_TESTS,
+            accesses: i::Access::SHADER_READ
+                ..(i::Access::DEPTH_STENCIL_ATTACHMENT_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        },
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+            stages: PipelineStage::LATE_FRAGMENT_TESTS..PipelineStage::FRAGMENT_SHADER,
+            accesses: i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE..i::Access::SHADER_READ,
+        },
+    ];
+
+    device.create_render_pass(&[depth_attachment], &[subpass], &dependencies)
+}
+
+/// The pass deferred shading draws the world into a G-buffer with. Attachments 0 to 2 are the
+/// color targets, `ALBEDO_FORMAT`, `NORMAL_FORMAT` and `MATERIAL_FORMAT` from `gbuffer`, and
+/// attachment 3 is depth. Everything is cleared at the start and kept at the end, ready for the
+/// lighting pass to read.
+pub fn create_gbuffer_render_pass<B: Backend>(device: &B::Device, depth_format: f::Format) -> B::RenderPass {
+    let color_attachment = |format| pass::Attachment {
+        format: Some(format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::ShaderReadOnlyOptimal,
+    };
+
+    // Read-only at the end, since the lighting pass both samples it and tests the outline
+    // against it
+    let depth_attachment = pass::Attachment {
+        format: Some(depth_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::DepthStencilReadOnlyOptimal,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[
+            (0, i::Layout::ColorAttachmentOptimal),
+            (1, i::Layout::ColorAttachmentOptimal),
+            (2, i::Layout::ColorAttachmentOptimal),
+        ],
+        depth_stencil: Some(&(3, i::Layout::DepthStencilAttachmentOptimal)),
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    // The last lighting pass to use these images has to be done reading them before they're
+    // drawn over, and this one has to be done drawing them before the next one reads them
+    let dependencies = [
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+            stages: (PipelineStage::FRAGMENT_SHADER | PipelineStage::EARLY_FRAGMENT_TESTS)
+                ..(PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS),
+            accesses: (i::Access::SHADER_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_READ)
+                ..(i::Access::COLOR_ATTACHMENT_WRITE
+                    | i::Access::DEPTH_STENCIL_ATTACHMENT_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        },
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+            stages: (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::LATE_FRAGMENT_TESTS)
+                ..(PipelineStage::FRAGMENT_SHADER | PipelineStage::EARLY_FRAGMENT_TESTS),
+            accesses: (i::Access::COLOR_ATTACHMENT_WRITE | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                ..(i::Access::SHADER_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_READ),
+        },
+    ];
+
+    let attachments = [
+        color_attachment(ALBEDO_FORMAT),
+        color_attachment(NORMAL_FORMAT),
+        color_attachment(MATERIAL_FORMAT),
+        depth_attachment,
+    ];
+    device.create_render_pass(&attachments, &[subpass], &dependencies)
+}
+
+/// The pass deferred shading lights the G-buffer in, into the HDR target (attachment 0). The
+/// G-buffer's depth is attachment 1, loaded read-only, so anything drawn after the lighting can
+/// still be depth tested against the world. The HDR target is cleared, for the sky to show
+/// wherever nothing was drawn, and left ready for tonemapping to sample.
+pub fn create_lighting_render_pass<B: Backend>(
+    device: &B::Device,
+    color_format: f::Format,
+    depth_format: f::Format,
+) -> B::RenderPass {
+    let color_attachment = pass::Attachment {
+        format: Some(color_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::ShaderReadOnlyOptimal,
+    };
+
+    let depth_attachment = pass::Attachment {
+        format: Some(depth_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Load,
+            store: pass::AttachmentStoreOp::DontCare,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::DepthStencilReadOnlyOptimal..i::Layout::DepthStencilReadOnlyOptimal,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+        depth_stencil: Some(&(1, i::Layout::DepthStencilReadOnlyOptimal)),
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    // Waiting on the G-buffer is `create_gbuffer_render_pass`'s job. The HDR target is the same
+    // as `create_scene_render_pass`'s: the last tonemapping has to be done reading it before it's
+    // drawn over, and the next can't read it until it's drawn.
+    let dependencies = [
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+            stages: (PipelineStage::FRAGMENT_SHADER | PipelineStage::COLOR_ATTACHMENT_OUTPUT)
+                ..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+            accesses: i::Access::SHADER_READ
+                ..(i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE),
+        },
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+            stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::FRAGMENT_SHADER,
+            accesses: i::Access::COLOR_ATTACHMENT_WRITE..i::Access::SHADER_READ,
+        },
+    ];
+
+    device.create_render_pass(&[color_attachment, depth_attachment], &[subpass], &dependencies)
+}
+
+/// A pass drawing into a single color image of `format` for later passes to sample, like the
+/// occlusion from `ssao`. It's cleared at the start and kept at the end.
+pub fn create_offscreen_render_pass<B: Backend>(device: &B::Device, format: f::Format) -> B::RenderPass {
+    let color_attachment = pass::Attachment {
+        format: Some(format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::ShaderReadOnlyOptimal,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+        depth_stencil: None,
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    // The same as a shadow map's: whatever read the image last has to be done before it's
+    // drawn over, and the next pass can't read it until it's drawn
+    let dependencies = [
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+            stages: PipelineStage::FRAGMENT_SHADER..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+            accesses: i::Access::SHADER_READ..i::Access::COLOR_ATTACHMENT_WRITE,
+        },
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+            stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::FRAGMENT_SHADER,
+            accesses: i::Access::COLOR_ATTACHMENT_WRITE..i::Access::SHADER_READ,
+        },
+    ];
+
+    device.create_render_pass(&[color_attachment], &[subpass], &dependencies)
+}
+
+/// Like `create_offscreen_render_pass`, with a depth attachment of `depth_format` as attachment
+/// 1 for drawing geometry into the image, like the minimap. Depth is cleared at the start of the
+/// pass and thrown away at the end.
+pub fn create_offscreen_depth_render_pass<B: Backend>(
+    device: &B::Device,
+    color_format: f::Format,
+    depth_format: f::Format,
+) -> B::RenderPass {
+    let color_attachment = pass::Attachment {
+        format: Some(color_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::ShaderReadOnlyOptimal,
+    };
+
+    let depth_attachment = pass::Attachment {
+        format: Some(depth_format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Clear,
+            store: pass::AttachmentStoreOp::DontCare,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::Undefined..i::Layout::DepthStencilAttachmentOptimal,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+        depth_stencil: Some(&(1, i::Layout::DepthStencilAttachmentOptimal)),
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    // Whatever read the image last has to be done before it's drawn over, and the depth from
+    // the last time has to be done with before it's cleared
+    let dependencies = [
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+            stages: (PipelineStage::FRAGMENT_SHADER | PipelineStage::LATE_FRAGMENT_TESTS)
+                ..(PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::EARLY_FRAGMENT_TESTS),
+            accesses: (i::Access::SHADER_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE)
+                ..(i::Access::COLOR_ATTACHMENT_WRITE
+                    | i::Access::DEPTH_STENCIL_ATTACHMENT_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE),
+        },
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+            stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::FRAGMENT_SHADER,
+            accesses: i::Access::COLOR_ATTACHMENT_WRITE..i::Access::SHADER_READ,
+        },
+    ];
+
+    device.create_render_pass(&[color_attachment, depth_attachment], &[subpass], &dependencies)
+}
+
+/// Like `create_offscreen_render_pass`, but keeping what's already in the image to blend on top
+/// of, like the upsamples in `bloom`. The image has to have been drawn by a pass like that one
+/// first, since it starts and ends ready to sample.
+pub fn create_blend_render_pass<B: Backend>(device: &B::Device, format: f::Format) -> B::RenderPass {
+    let color_attachment = pass::Attachment {
+        format: Some(format),
+        samples: 1,
+        ops: pass::AttachmentOps {
+            load: pass::AttachmentLoadOp::Load,
+            store: pass::AttachmentStoreOp::Store,
+        },
+        stencil_ops: pass::AttachmentOps::DONT_CARE,
+        layouts: i::Layout::ShaderReadOnlyOptimal..i::Layout::ShaderReadOnlyOptimal,
+    };
+
+    let subpass = pass::SubpassDesc {
+        colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+        depth_stencil: None,
+        inputs: &[],
+        resolves: &[],
+        preserves: &[],
+    };
+
+    // Whatever drew the image or read it last has to be done before it's blended onto, and the
+    // next pass can't read it until it's drawn
+    let dependencies = [
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+            stages: (PipelineStage::COLOR_ATTACHMENT_OUTPUT | PipelineStage::FRAGMENT_SHADER)
+                ..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+            accesses: (i::Access::COLOR_ATTACHMENT_WRITE | i::Access::SHADER_READ)
+                ..(i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE),
+        },
+        pass::SubpassDependency {
+            passes: pass::SubpassRef::Pass(0)..pass::SubpassRef::External,
+            stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::FRAGMENT_SHADER,
+            accesses: i::Access::COLOR_ATTACHMENT_WRITE..i::Access::SHADER_READ,
+        },
+    ];
+
+    device.create_render_pass(&[color_attachment], &[subpass], &dependencies)
+}
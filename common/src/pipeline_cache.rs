@@ -0,0 +1,116 @@
+//! A pipeline cache that is saved to disk on exit and loaded again on startup.
+//!
+//! Building pipelines can take a noticeable amount of time on some backends because the driver
+//! has to compile our shaders to machine code. A `PipelineCache` lets the driver reuse that work,
+//! and persisting it means we only pay for it the first time the program runs.
+//!
+//! The data in a pipeline cache is only meaningful for the adapter (and driver) that produced it,
+//! so the file starts with a header describing the adapter. If it doesn't match the adapter we're
+//! running on now, the file is ignored and overwritten on exit.
+
+use std::env;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::rc::Rc;
+
+use hal::{ AdapterInfo, Backend, Device };
+
+use error::RendererError;
+
+const MAGIC: &[u8; 4] = b"VXPC";
+/// Bump this if the header layout changes.
+const VERSION: u32 = 1;
+
+/// Where the cache lives for an application called `app_name`.
+pub fn default_cache_path(app_name: &str) -> PathBuf {
+    env::temp_dir().join(format!("{}-pipeline-cache.bin", app_name))
+}
+
+/// The header written at the start of the cache file.
+fn header(info: &AdapterInfo) -> Vec<u8> {
+    let mut header = Vec::new();
+    header.extend_from_slice(MAGIC);
+    push_u32(&mut header, VERSION);
+    push_u32(&mut header, info.vendor as u32);
+    push_u32(&mut header, info.device as u32);
+    push_u32(&mut header, info.name.len() as u32);
+    header.extend_from_slice(info.name.as_bytes());
+    header
+}
+
+fn push_u32(bytes: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        bytes.push((value >> (i * 8)) as u8);
+    }
+}
+
+pub struct PipelineCache<B: Backend> {
+    device: Rc<B::Device>,
+    cache: Option<B::PipelineCache>,
+    path: PathBuf,
+    header: Vec<u8>,
+}
+
+impl<B: Backend> PipelineCache<B> {
+    /// Creates a pipeline cache, seeded with the contents of `path` if it was written for the
+    /// same adapter.
+    pub fn load(device: Rc<B::Device>, info: &AdapterInfo, path: PathBuf) -> Result<Self, RendererError> {
+        let header = header(info);
+
+        let data = match fs::read(&path) {
+            Ok(ref bytes) if bytes.starts_with(&header) => {
+                info!("Loaded pipeline cache from {}", path.display());
+                Some(bytes[header.len()..].to_vec())
+            }
+            Ok(_) => {
+                info!("Discarding pipeline cache at {}, it was made for a different adapter", path.display());
+                None
+            }
+            Err(_) => None,
+        };
+
+        let cache = device
+            .create_pipeline_cache(data.as_ref().map(|data| data.as_slice()))
+            .or_else(|_| device.create_pipeline_cache(None))?;
+
+        Ok(PipelineCache {
+            device,
+            cache: Some(cache),
+            path,
+            header,
+        })
+    }
+
+    pub fn cache(&self) -> &B::PipelineCache {
+        self.cache.as_ref().unwrap()
+    }
+
+    /// Where the cache is loaded from and saved to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Writes the current contents of the cache to disk.
+    pub fn save(&self) -> Result<(), String> {
+        let data = self.device
+            .get_pipeline_cache_data(self.cache())
+            .map_err(|err| format!("Failed to read pipeline cache data: {:?}", err))?;
+
+        let mut bytes = self.header.clone();
+        bytes.extend_from_slice(&data);
+
+        fs::write(&self.path, &bytes)
+            .map_err(|err| format!("Failed to write {}: {}", self.path.display(), err))
+    }
+}
+
+impl<B: Backend> Drop for PipelineCache<B> {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            warn!("{}", err);
+        }
+        if let Some(cache) = self.cache.take() {
+            self.device.destroy_pipeline_cache(cache);
+        }
+    }
+}
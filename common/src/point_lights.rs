@@ -0,0 +1,221 @@
+//! Point lights shining from lamps, torches and anything else that glows, for lighting what's
+//! around them in the fragment shader.
+//!
+//! Light levels spread through the world by `light::Lighting` light every face near a lamp the
+//! same, whichever way it's turned. Point lights add the light coming straight from the block
+//! on top of that: brighter on the faces turned towards it, fading out over its radius, and in
+//! its own colour. There can be any number of glowing blocks loaded, but only so many can be
+//! shaded with, so `PointLights` keeps track of where they all are, chunk by chunk, and hands
+//! out the ones nearest the camera each frame.
+
+use std::collections::HashMap;
+
+use world::{ BlockId, ChunkCoord, World, CHUNK_SIZE };
+
+/// A light shining the same in every direction from a point.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PointLight {
+    pub position: [f32; 3],
+    /// Linear RGB, which can go above 1 for bright lights.
+    pub color: [f32; 3],
+    /// How far the light reaches, in blocks. It fades out to nothing there.
+    pub radius: f32,
+}
+
+/// The colour and reach of the light each type of block gives off.
+#[derive(Clone, Debug, Default)]
+pub struct PointLightBlocks {
+    /// Indexed by `BlockId`.
+    lights: Vec<Option<([f32; 3], f32)>>,
+}
+
+impl PointLightBlocks {
+    pub fn new() -> Self {
+        PointLightBlocks::default()
+    }
+
+    /// Makes every `block` a point light of `color`, reaching `radius` blocks.
+    pub fn set(&mut self, block: BlockId, color: [f32; 3], radius: f32) {
+        let index = block.0 as usize;
+        if index >= self.lights.len() {
+            self.lights.resize(index + 1, None);
+        }
+        self.lights[index] = Some((color, radius));
+    }
+
+    /// The colour and radius of the light `block` gives off, if it gives off any.
+    pub fn get(&self, block: BlockId) -> Option<([f32; 3], f32)> {
+        self.lights.get(block.0 as usize).cloned().unwrap_or(None)
+    }
+
+    /// Every block that gives off light.
+    fn blocks<'a>(&'a self) -> impl Iterator<Item = BlockId> + 'a {
+        self.lights
+            .iter()
+            .enumerate()
+            .filter(|&(_, light)| light.is_some())
+            .map(|(index, _)| BlockId(index as u16))
+    }
+}
+
+/// Where the point lights in the loaded chunks are.
+pub struct PointLights {
+    blocks: PointLightBlocks,
+    chunks: HashMap<ChunkCoord, Vec<PointLight>>,
+}
+
+impl PointLights {
+    pub fn new(blocks: PointLightBlocks) -> Self {
+        PointLights { blocks, chunks: HashMap::new() }
+    }
+
+    /// Finds the lights in the chunk at `coord` again, after it's been loaded or changed. A
+    /// chunk that isn't loaded any more is forgotten.
+    pub fn update_chunk(&mut self, world: &World, coord: ChunkCoord) {
+        let chunk = match world.chunk(coord) {
+            Some(chunk) => chunk,
+            None => {
+                self.chunks.remove(&coord);
+                return;
+            }
+        };
+        // Most chunks have nothing that glows, and their palettes say so without looking at
+        // each block
+        if !self.blocks.blocks().any(|block| chunk.contains(block)) {
+            self.chunks.remove(&coord);
+            return;
+        }
+
+        let mut lights = Vec::new();
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    if let Some((color, radius)) = self.blocks.get(chunk.get([x, y, z])) {
+                        let block = coord.block_position([x, y, z]);
+                        lights.push(PointLight {
+                            position: [block[0] as f32 + 0.5, block[1] as f32 + 0.5, block[2] as f32 + 0.5],
+                            color,
+                            radius,
+                        });
+                    }
+                }
+            }
+        }
+        self.chunks.insert(coord, lights);
+    }
+
+    /// Forgets the lights in a chunk that's been unloaded.
+    pub fn remove_chunk(&mut self, coord: ChunkCoord) {
+        self.chunks.remove(&coord);
+    }
+
+    /// How many lights there are in the loaded chunks.
+    pub fn count(&self) -> usize {
+        self.chunks.values().map(|lights| lights.len()).sum()
+    }
+
+    /// Up to `count` of the lights that reach within `distance` of `position`, nearest first.
+    pub fn nearest(&self, position: [f32; 3], distance: f32, count: usize) -> Vec<PointLight> {
+        let mut nearby: Vec<(f32, PointLight)> = self.chunks
+            .values()
+            .flat_map(|lights| lights.iter())
+            .filter_map(|light| {
+                let offset = [
+                    light.position[0] - position[0],
+                    light.position[1] - position[1],
+                    light.position[2] - position[2],
+                ];
+                let squared = offset[0] * offset[0] + offset[1] * offset[1] + offset[2] * offset[2];
+                let reach = distance + light.radius;
+                if squared <= reach * reach { Some((squared, *light)) } else { None }
+            })
+            .collect();
+        nearby.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+        nearby.into_iter().take(count).map(|(_, light)| light).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::Chunk;
+
+    const STONE: BlockId = BlockId(1);
+    const LAMP: BlockId = BlockId(8);
+    const TORCH: BlockId = BlockId(9);
+
+    fn point_lights() -> PointLights {
+        let mut blocks = PointLightBlocks::new();
+        blocks.set(LAMP, [1.0, 0.9, 0.7], 10.0);
+        blocks.set(TORCH, [1.0, 0.6, 0.3], 6.0);
+        PointLights::new(blocks)
+    }
+
+    fn world() -> World {
+        let mut world = World::new();
+        for x in -1..2 {
+            world.insert_chunk(ChunkCoord::new(x, 0, 0), Chunk::filled(STONE));
+        }
+        world
+    }
+
+    #[test]
+    fn finds_the_glowing_blocks_in_a_chunk() {
+        let mut world = world();
+        world.set_block([3, 4, 5], LAMP);
+        world.set_block([-2, 0, 0], TORCH);
+        let mut lights = point_lights();
+        for x in -1..2 {
+            lights.update_chunk(&world, ChunkCoord::new(x, 0, 0));
+        }
+        assert_eq!(lights.count(), 2);
+
+        let nearest = lights.nearest([0.0, 0.0, 0.0], 100.0, 10);
+        assert_eq!(nearest[0].position, [-1.5, 0.5, 0.5]);
+        assert_eq!(nearest[0].color, [1.0, 0.6, 0.3]);
+        assert_eq!(nearest[0].radius, 6.0);
+        assert_eq!(nearest[1].position, [3.5, 4.5, 5.5]);
+    }
+
+    #[test]
+    fn chunks_are_looked_at_again_when_they_change() {
+        let mut world = world();
+        let coord = ChunkCoord::new(0, 0, 0);
+        world.set_block([1, 1, 1], LAMP);
+        let mut lights = point_lights();
+        lights.update_chunk(&world, coord);
+        assert_eq!(lights.count(), 1);
+
+        world.set_block([1, 1, 1], STONE);
+        world.set_block([2, 2, 2], TORCH);
+        world.set_block([3, 3, 3], TORCH);
+        lights.update_chunk(&world, coord);
+        assert_eq!(lights.count(), 2);
+
+        world.remove_chunk(coord);
+        lights.update_chunk(&world, coord);
+        assert_eq!(lights.count(), 0);
+    }
+
+    #[test]
+    fn the_nearest_lights_come_first_and_far_ones_are_left_out() {
+        let mut world = world();
+        for &x in &[-14, -3, 0, 6, 20] {
+            world.set_block([x, 8, 8], LAMP);
+        }
+        let mut lights = point_lights();
+        for x in -1..2 {
+            lights.update_chunk(&world, ChunkCoord::new(x, 0, 0));
+        }
+        assert_eq!(lights.count(), 5);
+
+        let nearest = lights.nearest([0.5, 8.5, 8.5], 100.0, 3);
+        let xs: Vec<f32> = nearest.iter().map(|light| light.position[0]).collect();
+        assert_eq!(xs, vec![0.5, -2.5, 6.5]);
+
+        // A light counts as near if its radius reaches within the distance
+        let nearest = lights.nearest([0.5, 8.5, 8.5], 5.0, 10);
+        assert_eq!(nearest.len(), 4);
+        assert!(nearest.iter().all(|light| light.position[0] != 20.5));
+    }
+}
@@ -0,0 +1,83 @@
+//! Choosing how swapchain images are handed over to the display.
+
+use hal::window::PresentMode;
+
+use error::RendererError;
+
+/// The order we try present modes in when nothing else was asked for. `Mailbox` doesn't tear and
+/// adds at most a frame of latency, `Fifo` is vsync and is the only mode every surface has to
+/// support, and `Immediate` tears.
+pub const DEFAULT_PRESENT_MODES: [PresentMode; 3] = [
+    PresentMode::Mailbox,
+    PresentMode::Fifo,
+    PresentMode::Immediate,
+];
+
+/// With vsync on we stick to `Fifo`. With it off, `Mailbox` still avoids tearing but lets
+/// frames be replaced before they're shown, and `Immediate` is the lowest latency of all.
+pub fn vsync_present_modes(vsync: bool) -> Vec<PresentMode> {
+    if vsync {
+        vec![PresentMode::Fifo]
+    } else {
+        vec![PresentMode::Mailbox, PresentMode::Immediate, PresentMode::Fifo]
+    }
+}
+
+/// Whether `mode` waits for the display's vertical blank.
+pub fn is_vsync(mode: PresentMode) -> bool {
+    match mode {
+        PresentMode::Fifo | PresentMode::Relaxed => true,
+        PresentMode::Immediate | PresentMode::Mailbox => false,
+    }
+}
+
+/// The name `--present-mode` accepts for `mode`.
+pub fn present_mode_name(mode: PresentMode) -> &'static str {
+    match mode {
+        PresentMode::Immediate => "immediate",
+        PresentMode::Mailbox => "mailbox",
+        PresentMode::Fifo => "fifo",
+        PresentMode::Relaxed => "relaxed",
+    }
+}
+
+pub fn parse_present_mode(name: &str) -> Result<PresentMode, String> {
+    match name.trim().to_lowercase().as_str() {
+        "immediate" => Ok(PresentMode::Immediate),
+        "mailbox" => Ok(PresentMode::Mailbox),
+        "fifo" => Ok(PresentMode::Fifo),
+        "relaxed" => Ok(PresentMode::Relaxed),
+        _ => Err(format!(
+            "Unknown present mode '{}'. Expected one of: immediate, mailbox, fifo, relaxed",
+            name,
+        )),
+    }
+}
+
+/// Picks the first mode from `preferred` that the surface supports, falling back to `Fifo`,
+/// which is always available.
+pub fn choose_present_mode(available: &[PresentMode], preferred: &[PresentMode]) -> PresentMode {
+    preferred
+        .iter()
+        .cloned()
+        .find(|mode| available.contains(mode))
+        .unwrap_or(PresentMode::Fifo)
+}
+
+/// Checks that a mode asked for explicitly is one the surface supports.
+pub fn validate_present_mode(available: &[PresentMode], requested: PresentMode) -> Result<PresentMode, RendererError> {
+    if available.contains(&requested) {
+        Ok(requested)
+    } else {
+        let names = available
+            .iter()
+            .map(|&mode| present_mode_name(mode))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Err(RendererError::InvalidOption(format!(
+            "The '{}' present mode isn't supported by this surface. Supported modes: {}",
+            present_mode_name(requested),
+            names,
+        )))
+    }
+}
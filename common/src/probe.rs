@@ -0,0 +1,344 @@
+//! An environment probe: a cube map of the world around a point, for reflections.
+//!
+//! The probe is six square cameras with 90 degree fields of view, one looking out of each face
+//! of a cube, drawing the chunks around it into the matching layer of an HDR cube image. Water
+//! and polished blocks look their reflections up in it by direction, so what's reflected is
+//! what was around the probe rather than around the surface, which is close enough for anything
+//! near the player. Wherever the faces didn't draw any terrain they're left clear, with alpha
+//! 0, and the shaders use their own sky there instead, so the reflected sky is never out of
+//! date.
+//!
+//! All six faces are drawn on the first frame, from wherever the camera starts. After that the
+//! probe is static unless it's dynamic, in which case it follows the camera, drawing one face a
+//! frame, so the whole cube comes round every `CUBE_FACES` frames. Each round starts from where
+//! the camera is at the time, so the faces of a round all see from the same point.
+//!
+//! `cull.comp` fills one draw list for all six faces, culled against the box the faces reach
+//! across, from `EnvironmentProbe::frustum`.
+
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+use hal::{
+    format as f, image as i, memory, pso,
+    Backend, Device,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use atlas::BlockTextureId;
+use context::GfxContext;
+use depth::depth_range;
+use error::Result;
+use hdr::HDR_FORMAT;
+use math::{ look_along, orthographic, perspective, Deg, Frustum, InnerSpace, Mat4, Vec3, HAL_CLIP_SPACE };
+use pass::create_offscreen_depth_render_pass;
+use samplers::SamplerDesc;
+use sky::{ face_direction, CUBE_FACES };
+use texture::COLOR_RANGE;
+
+/// How many texels along each side of a face. Reflections are blurred by ripples and seen at
+/// an angle, so this can be small.
+pub const PROBE_RESOLUTION: u32 = 128;
+
+/// How far the probe sees, in blocks. Anything further away is left to the sky.
+pub const PROBE_RANGE: f32 = 96.0;
+
+/// How close to the probe its faces start drawing, in blocks.
+const PROBE_NEAR: f32 = 0.1;
+
+/// From world space to `face` of a probe at `position`, laid out the way `face_direction` says:
+/// a point that's `face_direction(face, s, t)` away from the probe lands at `s` across and `t`
+/// down the face, whichever way up `HAL_CLIP_SPACE` is.
+///
+/// Cube faces are laid out as seen from inside the cube, which mirrors them, so this turns
+/// triangles inside out, and pipelines that draw with it have to cull the other faces.
+pub fn face_view_projection(face: u32, position: [f32; 3], near: f32, far: f32) -> Mat4 {
+    let forward = Vec3::from(face_direction(face, 0.0, 0.0));
+    let across = Vec3::from(face_direction(face, 1.0, 0.0)) - forward;
+    let down = Vec3::from(face_direction(face, 0.0, 1.0)) - forward;
+    // A right handed view with `-down` as up has `across` on its left, so it's mirrored left to
+    // right
+    debug_assert!((forward.cross(-down) + across).magnitude() < 1e-5);
+    let view = look_along(position, forward, -down);
+    let mirror = Mat4::from_nonuniform_scale(-1.0, 1.0, 1.0);
+    let projection = perspective(Deg(90.0).into(), 1.0, near, far, HAL_CLIP_SPACE);
+    mirror * projection * view
+}
+
+/// A box around `position` reaching `range` every way, as a view projection for culling
+/// against. It doesn't matter which way it looks, only what it covers.
+fn bounds_view_projection(position: [f32; 3], range: f32) -> Mat4 {
+    let top = [position[0], position[1] + range, position[2]];
+    let view = look_along(top, -Vec3::unit_y(), -Vec3::unit_z());
+    orthographic(-range, range, -range, range, 0.0, 2.0 * range, HAL_CLIP_SPACE) * view
+}
+
+/// Which faces of the probe are due to be drawn each frame: all of them on the first, and after
+/// that, one more each frame while the probe is dynamic.
+#[derive(Clone, Copy, Debug)]
+pub struct ProbeSchedule {
+    drawn: bool,
+    next_face: u32,
+}
+
+impl ProbeSchedule {
+    pub fn new() -> Self {
+        ProbeSchedule { drawn: false, next_face: 0 }
+    }
+
+    /// Moves on a frame, giving the faces to draw in it.
+    pub fn advance(&mut self, dynamic: bool) -> Range<u32> {
+        if !self.drawn {
+            self.drawn = true;
+            return 0..CUBE_FACES;
+        }
+        if !dynamic {
+            return 0..0;
+        }
+        let face = self.next_face;
+        self.next_face = (face + 1) % CUBE_FACES;
+        face..face + 1
+    }
+}
+
+/// One bit for each of `tiles`, in four words, for the `polished_tiles` the shaders check.
+pub fn tile_mask(tiles: &[BlockTextureId]) -> [u32; 4] {
+    let mut mask = [0; 4];
+    for &BlockTextureId(tile) in tiles {
+        assert!(tile < 128, "Only the first 128 tiles can be polished, not tile {}", tile);
+        mask[tile as usize / 32] |= 1 << (tile % 32);
+    }
+    mask
+}
+
+struct ProbeImage<B: Backend> {
+    image: B::Image,
+    view: B::ImageView,
+    allocation: Allocation,
+}
+
+/// The cube image the probe is drawn into, and what's needed to draw into it.
+pub struct EnvironmentProbe<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    render_pass: Option<B::RenderPass>,
+    /// The cube, with `color.view` viewing all of it, for sampling.
+    color: Option<ProbeImage<B>>,
+    /// One view and framebuffer per face, for drawing into.
+    face_views: Vec<B::ImageView>,
+    framebuffers: Vec<B::Framebuffer>,
+    /// One depth buffer, shared by the faces, since they're drawn one after the other.
+    depth: Option<ProbeImage<B>>,
+    sampler: Rc<B::Sampler>,
+    schedule: ProbeSchedule,
+    /// Where the faces of the current round are drawn from.
+    position: [f32; 3],
+}
+
+impl<B: Backend> EnvironmentProbe<B> {
+    /// A probe drawn with a depth buffer of `depth_format`, which should come from
+    /// `depth::choose_depth_format`.
+    pub fn new(context: &GfxContext<B>, depth_format: f::Format) -> Result<Self> {
+        let device = context.device.clone();
+        let render_pass = create_offscreen_depth_render_pass::<B>(&device, HDR_FORMAT, depth_format);
+        let sampler = context.samplers.borrow_mut().get(SamplerDesc::new(i::Filter::Linear, i::WrapMode::Clamp));
+        let mut probe = EnvironmentProbe {
+            device,
+            allocator: context.allocator.clone(),
+            render_pass: Some(render_pass),
+            color: None,
+            face_views: Vec::with_capacity(CUBE_FACES as usize),
+            framebuffers: Vec::with_capacity(CUBE_FACES as usize),
+            depth: None,
+            sampler,
+            schedule: ProbeSchedule::new(),
+            position: [0.0; 3],
+        };
+
+        let all_faces = i::SubresourceRange { layers: 0..CUBE_FACES as i::Layer, ..COLOR_RANGE };
+        probe.color = Some(probe.create_image(
+            HDR_FORMAT,
+            CUBE_FACES as i::Layer,
+            i::Usage::COLOR_ATTACHMENT | i::Usage::SAMPLED,
+            i::ViewCapabilities::KIND_CUBE,
+            i::ViewKind::Cube,
+            all_faces,
+        )?);
+        probe.depth = Some(probe.create_image(
+            depth_format,
+            1,
+            i::Usage::DEPTH_STENCIL_ATTACHMENT,
+            i::ViewCapabilities::empty(),
+            i::ViewKind::D2,
+            depth_range(depth_format),
+        )?);
+
+        let extent = i::Extent { width: PROBE_RESOLUTION, height: PROBE_RESOLUTION, depth: 1 };
+        for face in 0..CUBE_FACES as i::Layer {
+            let face_view = probe.device.create_image_view(
+                &probe.color.as_ref().unwrap().image,
+                i::ViewKind::D2,
+                HDR_FORMAT,
+                f::Swizzle::NO,
+                i::SubresourceRange { layers: face..face + 1, ..COLOR_RANGE },
+            )?;
+            let framebuffer = probe.device.create_framebuffer(
+                probe.render_pass(),
+                vec![&face_view, &probe.depth.as_ref().unwrap().view],
+                extent,
+            )?;
+            probe.face_views.push(face_view);
+            probe.framebuffers.push(framebuffer);
+        }
+        Ok(probe)
+    }
+
+    fn create_image(
+        &self,
+        format: f::Format,
+        layers: i::Layer,
+        usage: i::Usage,
+        capabilities: i::ViewCapabilities,
+        view_kind: i::ViewKind,
+        range: i::SubresourceRange,
+    ) -> Result<ProbeImage<B>> {
+        let unbound = self.device.create_image(
+            i::Kind::D2(PROBE_RESOLUTION, PROBE_RESOLUTION, layers, 1),
+            1,
+            format,
+            i::Tiling::Optimal,
+            usage,
+            capabilities,
+        )?;
+        let requirements = self.device.get_image_requirements(&unbound);
+
+        let mut allocator = self.allocator.borrow_mut();
+        let allocation = allocator
+            .allocate(
+                &requirements,
+                memory::Properties::DEVICE_LOCAL,
+                ResourceKind::Optimal,
+                MemoryCategory::Attachments,
+            )?;
+        let image = self.device
+            .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+        let view = self.device.create_image_view(&image, view_kind, format, f::Swizzle::NO, range)?;
+        Ok(ProbeImage { image, view, allocation })
+    }
+
+    /// Moves on a frame, giving the faces that are due to be drawn in it. A dynamic probe starts
+    /// each round of faces from `eye`.
+    pub fn next_faces(&mut self, eye: [f32; 3], dynamic: bool) -> Range<u32> {
+        let faces = self.schedule.advance(dynamic);
+        if faces.start == 0 && faces.end > 0 {
+            self.position = eye;
+        }
+        faces
+    }
+
+    /// Where the probe is drawn from.
+    pub fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    /// From world space to each face, as it's drawn from `position`.
+    pub fn view_projections(&self) -> [Mat4; CUBE_FACES as usize] {
+        let face = |face| face_view_projection(face, self.position, PROBE_NEAR, PROBE_RANGE);
+        [face(0), face(1), face(2), face(3), face(4), face(5)]
+    }
+
+    /// The box the faces see into between them, for culling their draw list.
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_matrix(&bounds_view_projection(self.position, PROBE_RANGE), HAL_CLIP_SPACE)
+    }
+
+    pub fn render_pass(&self) -> &B::RenderPass {
+        self.render_pass.as_ref().unwrap()
+    }
+
+    pub fn framebuffer(&self, face: u32) -> &B::Framebuffer {
+        &self.framebuffers[face as usize]
+    }
+
+    /// The cube, for sampling. It's in `ShaderReadOnlyOptimal` once it's been drawn.
+    pub fn view(&self) -> &B::ImageView {
+        &self.color.as_ref().unwrap().view
+    }
+
+    pub fn sampler(&self) -> &B::Sampler {
+        &self.sampler
+    }
+
+    /// A viewport covering a whole face.
+    pub fn viewport(&self) -> pso::Viewport {
+        let size = PROBE_RESOLUTION as i16;
+        pso::Viewport {
+            rect: pso::Rect { x: 0, y: 0, w: size, h: size },
+            depth: 0.0..1.0,
+        }
+    }
+}
+
+impl<B: Backend> Drop for EnvironmentProbe<B> {
+    fn drop(&mut self) {
+        for framebuffer in self.framebuffers.drain(..) {
+            self.device.destroy_framebuffer(framebuffer);
+        }
+        for face_view in self.face_views.drain(..) {
+            self.device.destroy_image_view(face_view);
+        }
+        for image in self.color.take().into_iter().chain(self.depth.take()) {
+            self.device.destroy_image_view(image.view);
+            self.device.destroy_image(image.image);
+            self.allocator.borrow_mut().free(image.allocation);
+        }
+        if let Some(render_pass) = self.render_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::Vec4;
+
+    #[test]
+    fn every_face_is_drawn_first_then_one_a_frame_while_dynamic() {
+        let mut schedule = ProbeSchedule::new();
+        assert_eq!(schedule.advance(false), 0..CUBE_FACES);
+        assert_eq!(schedule.advance(false), 0..0);
+        let faces: Vec<Range<u32>> = (0..7).map(|_| schedule.advance(true)).collect();
+        assert_eq!(faces, vec![0..1, 1..2, 2..3, 3..4, 4..5, 5..6, 0..1]);
+    }
+
+    #[test]
+    fn faces_are_laid_out_like_a_cube_sampler_reads_them() {
+        let position = [4.0, 60.0, -8.0];
+        for face in 0..CUBE_FACES {
+            let view_projection = face_view_projection(face, position, 0.1, 100.0);
+            for &(s, t) in &[(0.0, 0.0), (0.5, -0.25), (-0.75, 0.5)] {
+                let direction = face_direction(face, s, t);
+                let point = Vec4::new(
+                    position[0] + direction[0] * 10.0,
+                    position[1] + direction[1] * 10.0,
+                    position[2] + direction[2] * 10.0,
+                    1.0,
+                );
+                let clip = view_projection * point;
+                let down_the_face = if HAL_CLIP_SPACE.y_down { clip.y } else { -clip.y };
+                assert!((clip.x / clip.w - s).abs() < 1e-4, "face {} at {}, {}", face, s, t);
+                assert!((down_the_face / clip.w - t).abs() < 1e-4, "face {} at {}, {}", face, s, t);
+                assert!(clip.z > 0.0 && clip.z < clip.w);
+            }
+        }
+    }
+
+    #[test]
+    fn each_tile_has_a_bit() {
+        assert_eq!(tile_mask(&[]), [0; 4]);
+        let tiles = [BlockTextureId(0), BlockTextureId(33), BlockTextureId(127)];
+        assert_eq!(tile_mask(&tiles), [1, 2, 0, 1 << 31]);
+    }
+}
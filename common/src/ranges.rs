@@ -0,0 +1,164 @@
+//! Handing out pieces of something of a fixed size, like a block of memory or a buffer, and
+//! taking them back.
+//!
+//! The free pieces are kept in a list sorted by where they start, and merged with their
+//! neighbours as they're given back, so the list stays short and a fresh allocation can use all
+//! of what's been freed next to it. Allocations take the first free piece they fit in.
+
+use std::ops::Range;
+
+/// The free list for something `size` units long. What a unit is is up to the caller: the
+/// `Allocator` counts bytes, and the chunk meshes count vertices and indices.
+#[derive(Clone, Debug)]
+pub struct RangeAllocator {
+    size: u64,
+    /// Free ranges, sorted by start and never adjacent to one another.
+    free: Vec<Range<u64>>,
+    allocations: usize,
+}
+
+impl RangeAllocator {
+    pub fn new(size: u64) -> Self {
+        RangeAllocator {
+            size,
+            free: if size > 0 { vec![0..size] } else { Vec::new() },
+            allocations: 0,
+        }
+    }
+
+    /// First-fit search of the free list for `size` units starting on a multiple of
+    /// `alignment`. `None` if there's no free range big enough.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<Range<u64>> {
+        let (index, offset) = self.free
+            .iter()
+            .enumerate()
+            .filter_map(|(index, range)| {
+                let offset = align_up(range.start, alignment);
+                if offset + size <= range.end {
+                    Some((index, offset))
+                } else {
+                    None
+                }
+            })
+            .next()?;
+
+        // Split the free range into whatever is left before and after the allocation
+        let range = self.free.remove(index);
+        if offset + size < range.end {
+            self.free.insert(index, offset + size..range.end);
+        }
+        if range.start < offset {
+            self.free.insert(index, range.start..offset);
+        }
+
+        self.allocations += 1;
+        Some(offset..offset + size)
+    }
+
+    /// Gives back a range `allocate` handed out.
+    pub fn free(&mut self, range: Range<u64>) {
+        let index = self.free
+            .iter()
+            .position(|free| free.start > range.start)
+            .unwrap_or(self.free.len());
+        self.free.insert(index, range);
+
+        // Coalesce with the following and then the preceding range
+        if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+            let next = self.free.remove(index + 1);
+            self.free[index].end = next.end;
+        }
+        if index > 0 && self.free[index - 1].end == self.free[index].start {
+            let current = self.free.remove(index);
+            self.free[index - 1].end = current.end;
+        }
+
+        self.allocations -= 1;
+    }
+
+    /// Makes room for more at the end, keeping everything handed out where it is. `size` can't
+    /// be smaller than it was.
+    pub fn grow(&mut self, size: u64) {
+        assert!(size >= self.size, "Can't shrink a range allocator");
+        if size == self.size {
+            return;
+        }
+        let added = self.size..size;
+        self.size = size;
+        match self.free.last_mut() {
+            Some(last) if last.end == added.start => last.end = added.end,
+            _ => self.free.push(added),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// How many ranges are handed out.
+    pub fn allocations(&self) -> usize {
+        self.allocations
+    }
+
+    pub fn free_size(&self) -> u64 {
+        self.free.iter().map(|range| range.end - range.start).sum()
+    }
+}
+
+fn align_up(value: u64, alignment: u64) -> u64 {
+    if alignment == 0 {
+        value
+    } else {
+        (value + alignment - 1) / alignment * alignment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_aligned_and_dont_overlap() {
+        let mut ranges = RangeAllocator::new(100);
+        let first = ranges.allocate(10, 1).unwrap();
+        let second = ranges.allocate(10, 16).unwrap();
+        assert_eq!(first, 0..10);
+        assert_eq!(second, 16..26);
+        // The gap the alignment left is still free
+        assert_eq!(ranges.allocate(6, 1), Some(10..16));
+        assert_eq!(ranges.free_size(), 100 - 26);
+        assert_eq!(ranges.allocations(), 3);
+        assert_eq!(ranges.allocate(75, 1), None);
+    }
+
+    #[test]
+    fn freed_ranges_merge_with_their_neighbours() {
+        let mut ranges = RangeAllocator::new(30);
+        let a = ranges.allocate(10, 1).unwrap();
+        let b = ranges.allocate(10, 1).unwrap();
+        let c = ranges.allocate(10, 1).unwrap();
+        ranges.free(a);
+        ranges.free(c);
+        assert_eq!(ranges.allocate(20, 1), None);
+        ranges.free(b);
+        assert_eq!(ranges.allocations(), 0);
+        assert_eq!(ranges.allocate(30, 1), Some(0..30));
+    }
+
+    #[test]
+    fn growing_extends_the_free_range_at_the_end() {
+        let mut ranges = RangeAllocator::new(16);
+        let used = ranges.allocate(12, 1).unwrap();
+        assert_eq!(ranges.allocate(8, 1), None);
+        ranges.grow(32);
+        assert_eq!(ranges.allocate(8, 1), Some(12..20));
+        ranges.free(used);
+        assert_eq!(ranges.free_size(), 24);
+
+        // Growing a full one starts a new free range
+        let mut full = RangeAllocator::new(4);
+        full.allocate(4, 1).unwrap();
+        full.grow(8);
+        assert_eq!(full.allocate(4, 1), Some(4..8));
+    }
+}
@@ -0,0 +1,201 @@
+//! Finding the first block along a ray, for picking the block the camera is pointing at.
+//!
+//! `raycast` walks the ray through the grid one block at a time, with Amanatides and Woo's
+//! voxel traversal: for each axis it keeps how far along the ray the next boundary between
+//! blocks on that axis is, and steps across whichever boundary is nearest. That visits every
+//! block the ray passes through, in order, without ever skipping over the corner of one the way
+//! stepping along the ray by a fixed distance can, and it knows which face it went in through.
+
+use std::f32;
+
+use world::{ BlockId, Direction, World };
+
+/// A block a ray hit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RayHit {
+    /// The world position of the block.
+    pub position: [i32; 3],
+    pub block: BlockId,
+    /// The face of the block the ray went in through, or `None` if the ray started inside it.
+    pub face: Option<Direction>,
+    /// How far along the ray the block starts, in lengths of the ray's direction.
+    pub distance: f32,
+}
+
+impl RayHit {
+    /// The block on the other side of the face the ray went in through, which is where a block
+    /// placed against it goes. `None` if the ray started inside the block.
+    pub fn adjacent(&self) -> Option<[i32; 3]> {
+        self.face.map(|face| {
+            let offset = face.offset();
+            [self.position[0] + offset[0], self.position[1] + offset[1], self.position[2] + offset[2]]
+        })
+    }
+}
+
+/// The first block that isn't air along the ray from `origin` in `direction`, if one starts
+/// within `max_distance` lengths of `direction`. Chunks that aren't loaded stop the ray, and
+/// nothing is hit.
+pub fn raycast(world: &World, origin: [f32; 3], direction: [f32; 3], max_distance: f32) -> Option<RayHit> {
+    let mut position = [0i32; 3];
+    let mut step = [0i32; 3];
+    // How far along the ray the next boundary on each axis is, and how far apart the
+    // boundaries are
+    let mut next = [f32::INFINITY; 3];
+    let mut spacing = [f32::INFINITY; 3];
+    for axis in 0..3 {
+        position[axis] = origin[axis].floor() as i32;
+        if direction[axis] > 0.0 {
+            step[axis] = 1;
+            spacing[axis] = 1.0 / direction[axis];
+            next[axis] = (position[axis] as f32 + 1.0 - origin[axis]) * spacing[axis];
+        } else if direction[axis] < 0.0 {
+            step[axis] = -1;
+            spacing[axis] = -1.0 / direction[axis];
+            next[axis] = (origin[axis] - position[axis] as f32) * spacing[axis];
+        }
+    }
+
+    let mut face = None;
+    let mut distance = 0.0;
+    loop {
+        match world.block(position) {
+            Some(block) if !block.is_air() => return Some(RayHit { position, block, face, distance }),
+            Some(_) => (),
+            None => return None,
+        }
+
+        let axis = if next[0] < next[1] {
+            if next[0] < next[2] { 0 } else { 2 }
+        } else if next[1] < next[2] {
+            1
+        } else {
+            2
+        };
+        distance = next[axis];
+        if distance > max_distance {
+            return None;
+        }
+        position[axis] += step[axis];
+        next[axis] += spacing[axis];
+        face = Some(entry_face(axis, step[axis]));
+    }
+}
+
+/// The face a ray goes in through when it steps `step` along `axis`: the one facing back the
+/// way it came.
+fn entry_face(axis: usize, step: i32) -> Direction {
+    match (axis, step > 0) {
+        (0, true) => Direction::NegX,
+        (0, false) => Direction::PosX,
+        (1, true) => Direction::NegY,
+        (1, false) => Direction::PosY,
+        (2, true) => Direction::NegZ,
+        _ => Direction::PosZ,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::{ Chunk, ChunkCoord };
+
+    const STONE: BlockId = BlockId(1);
+
+    /// Air chunks from -1 to 0 on each axis, with stone at `stone`.
+    fn world_with(stone: &[[i32; 3]]) -> World {
+        let mut world = World::new();
+        for x in -1..1 {
+            for y in -1..1 {
+                for z in -1..1 {
+                    world.insert_chunk(ChunkCoord::new(x, y, z), Chunk::new());
+                }
+            }
+        }
+        for &position in stone {
+            world.set_block(position, STONE);
+        }
+        world
+    }
+
+    #[test]
+    fn hits_the_first_solid_block_through_the_face_it_enters() {
+        let world = world_with(&[[5, 2, 3], [7, 2, 3]]);
+        let hit = raycast(&world, [0.5, 2.5, 3.5], [1.0, 0.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.position, [5, 2, 3]);
+        assert_eq!(hit.block, STONE);
+        assert_eq!(hit.face, Some(Direction::NegX));
+        assert_eq!(hit.distance, 4.5);
+        assert_eq!(hit.adjacent(), Some([4, 2, 3]));
+    }
+
+    #[test]
+    fn goes_backwards_and_across_chunks() {
+        let world = world_with(&[[-3, -10, 1]]);
+        let hit = raycast(&world, [1.5, -9.5, 1.5], [-1.0, 0.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.position, [-3, -10, 1]);
+        assert_eq!(hit.face, Some(Direction::PosX));
+        assert_eq!(hit.distance, 3.5);
+        assert_eq!(hit.adjacent(), Some([-2, -10, 1]));
+
+        let world = world_with(&[[4, -1, 4]]);
+        let hit = raycast(&world, [4.5, 6.0, 4.5], [0.0, -1.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.face, Some(Direction::PosY));
+        assert_eq!(hit.distance, 6.0);
+    }
+
+    #[test]
+    fn catches_a_block_the_ray_only_clips_the_corner_of() {
+        // The ray crosses x = 1 at y = 0.99, just below the block above
+        let world = world_with(&[[1, 0, 0]]);
+        let hit = raycast(&world, [0.5, 0.5, 0.5], [1.0, 0.98, 0.0], 10.0).unwrap();
+        assert_eq!(hit.position, [1, 0, 0]);
+        assert_eq!(hit.face, Some(Direction::NegX));
+    }
+
+    #[test]
+    fn diagonal_rays_visit_every_block_they_cross() {
+        // Cover the blocks the ray could go into next with stone, so the next hit has to
+        // be one of them, and then clear them again and go on from the one it hit
+        let direction = [0.6, 0.48, 0.64];
+        let mut world = world_with(&[]);
+        let mut previous = [0, 0, 0];
+        for _ in 0..30 {
+            for offset in &[[1, 0, 0], [0, 1, 0], [0, 0, 1]] {
+                world.set_block(
+                    [previous[0] + offset[0], previous[1] + offset[1], previous[2] + offset[2]],
+                    STONE,
+                );
+            }
+            let hit = raycast(&world, [0.5, 0.5, 0.5], direction, 50.0).unwrap();
+            let stepped: i32 = (0..3).map(|axis| hit.position[axis] - previous[axis]).sum();
+            assert_eq!(stepped, 1, "{:?} isn't next to {:?}", hit.position, previous);
+            for offset in &[[1, 0, 0], [0, 1, 0], [0, 0, 1]] {
+                world.set_block(
+                    [previous[0] + offset[0], previous[1] + offset[1], previous[2] + offset[2]],
+                    BlockId::AIR,
+                );
+            }
+            previous = hit.position;
+        }
+    }
+
+    #[test]
+    fn stops_at_the_reach_and_at_unloaded_chunks() {
+        let world = world_with(&[[5, 2, 3]]);
+        assert!(raycast(&world, [0.5, 2.5, 3.5], [1.0, 0.0, 0.0], 4.0).is_none());
+        // Straight out of the loaded chunks
+        assert!(raycast(&world, [0.5, 2.5, 3.5], [0.0, 0.0, 1.0], 100.0).is_none());
+        assert!(raycast(&world, [0.5, 2.5, 3.5], [0.0, 0.0, 0.0], 100.0).is_none());
+    }
+
+    #[test]
+    fn a_ray_starting_inside_a_block_hits_it_straight_away() {
+        let world = world_with(&[[5, 2, 3]]);
+        let hit = raycast(&world, [5.5, 2.5, 3.5], [1.0, 0.0, 0.0], 4.0).unwrap();
+        assert_eq!(hit.position, [5, 2, 3]);
+        assert_eq!(hit.face, None);
+        assert_eq!(hit.distance, 0.0);
+        assert_eq!(hit.adjacent(), None);
+    }
+}
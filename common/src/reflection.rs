@@ -0,0 +1,278 @@
+//! Planar reflections, for the top of the water.
+//!
+//! The sea is flat, so what its surface reflects is exactly what a camera mirrored through it
+//! would see. The reflection pass draws the chunks again with the world mirrored through the
+//! water's plane by `mirror`, at a fraction of the screen's resolution, into an image of its
+//! own. Anything under the water would end up in front of the mirrored camera, so it's clipped
+//! away, and the water itself isn't drawn at all. The water shader then looks its reflection
+//! up where it is on the screen, nudged about by the ripples, instead of in the environment
+//! probe. Where nothing was drawn the image is left clear, with alpha 0, and the water reflects
+//! its own sky there, the same as it does with the probe.
+//!
+//! It's one more full pass over the chunks each frame, which the probe, drawing a sixth of a
+//! cube at a much lower resolution, avoids. In return the reflections line up with what's
+//! reflected wherever the camera is, where the probe's are only right near it. `Reflections`
+//! picks between them.
+//!
+//! `PlanarReflection` owns the image, its depth buffer and the render pass and framebuffer for
+//! drawing into them. Like the other attachments sized by the screen, it has to be recreated
+//! along with the swapchain.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use hal::{
+    format as f, image as i, memory, pso,
+    Backend, Device,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use context::GfxContext;
+use depth::depth_range;
+use error::Result;
+use hdr::HDR_FORMAT;
+use math::Mat4;
+use pass::create_offscreen_depth_render_pass;
+use resources::SwapchainBundle;
+use samplers::SamplerDesc;
+use texture::COLOR_RANGE;
+
+/// How the water reflects what's around it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reflections {
+    /// Looked up in the environment probe by direction. See `probe`.
+    Probe,
+    /// Drawn again from a camera mirrored through the water.
+    Planar,
+}
+
+impl Reflections {
+    pub const ALL: [Reflections; 2] = [Reflections::Probe, Reflections::Planar];
+
+    /// What `water.frag` knows the reflections as.
+    pub fn id(self) -> u32 {
+        match self {
+            Reflections::Probe => 0,
+            Reflections::Planar => 1,
+        }
+    }
+}
+
+impl Default for Reflections {
+    fn default() -> Self {
+        Reflections::Probe
+    }
+}
+
+impl fmt::Display for Reflections {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Reflections::Probe => "probe",
+            Reflections::Planar => "planar",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Reflections {
+    type Err = String;
+
+    fn from_str(name: &str) -> ::std::result::Result<Reflections, String> {
+        Reflections::ALL
+            .iter()
+            .cloned()
+            .find(|reflections| reflections.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown reflections {:?}, expected probe or planar", name))
+    }
+}
+
+/// How many times smaller than the screen the reflection is drawn, each way. The ripples blur
+/// it anyway.
+pub const REFLECTION_SCALE: u32 = 2;
+
+/// The size of the reflection for a screen `width` by `height`, in pixels.
+pub fn reflection_extent(width: u32, height: u32) -> (u32, u32) {
+    ((width / REFLECTION_SCALE).max(1), (height / REFLECTION_SCALE).max(1))
+}
+
+/// Mirrors world space through the flat plane at `height`, for putting between a camera's view
+/// matrix and the world. Like any mirror, it turns triangles inside out.
+pub fn mirror(height: f32) -> Mat4 {
+    Mat4::from_translation([0.0, height, 0.0].into())
+        * Mat4::from_nonuniform_scale(1.0, -1.0, 1.0)
+        * Mat4::from_translation([0.0, -height, 0.0].into())
+}
+
+struct ReflectionImage<B: Backend> {
+    image: B::Image,
+    view: B::ImageView,
+    allocation: Allocation,
+}
+
+/// The image the reflection is drawn into, and what's needed to draw into it.
+pub struct PlanarReflection<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    depth_format: f::Format,
+    extent: (u32, u32),
+    render_pass: Option<B::RenderPass>,
+    color: Option<ReflectionImage<B>>,
+    depth: Option<ReflectionImage<B>>,
+    framebuffer: Option<B::Framebuffer>,
+    sampler: Rc<B::Sampler>,
+}
+
+impl<B: Backend> PlanarReflection<B> {
+    /// A reflection for `swapchain`'s size, drawn with a depth buffer of `depth_format`, which
+    /// should come from `depth::choose_depth_format`.
+    pub fn new(context: &GfxContext<B>, swapchain: &SwapchainBundle<B>, depth_format: f::Format) -> Result<Self> {
+        let device = context.device.clone();
+        let render_pass = create_offscreen_depth_render_pass::<B>(&device, HDR_FORMAT, depth_format);
+        // Clamped, so ripples near the edge of the screen don't pull in the other side
+        let sampler = context.samplers.borrow_mut().get(SamplerDesc::new(i::Filter::Linear, i::WrapMode::Clamp));
+        let mut reflection = PlanarReflection {
+            device,
+            allocator: context.allocator.clone(),
+            depth_format,
+            extent: (0, 0),
+            render_pass: Some(render_pass),
+            color: None,
+            depth: None,
+            framebuffer: None,
+            sampler,
+        };
+        reflection.recreate(swapchain)?;
+        Ok(reflection)
+    }
+
+    /// Rebuilds the images to match `swapchain`'s size.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.destroy_images();
+
+        let extent = swapchain.extent();
+        self.extent = reflection_extent(extent.width, extent.height);
+        self.color = Some(self.create_image(
+            HDR_FORMAT,
+            i::Usage::COLOR_ATTACHMENT | i::Usage::SAMPLED,
+            COLOR_RANGE,
+        )?);
+        let depth_format = self.depth_format;
+        self.depth = Some(self.create_image(
+            depth_format,
+            i::Usage::DEPTH_STENCIL_ATTACHMENT,
+            depth_range(depth_format),
+        )?);
+        let framebuffer = {
+            let attachments = vec![&self.color.as_ref().unwrap().view, &self.depth.as_ref().unwrap().view];
+            let (width, height) = self.extent;
+            self.device.create_framebuffer(self.render_pass(), attachments, i::Extent { width, height, depth: 1 })?
+        };
+        self.framebuffer = Some(framebuffer);
+        Ok(())
+    }
+
+    fn create_image(&self, format: f::Format, usage: i::Usage, range: i::SubresourceRange) -> Result<ReflectionImage<B>> {
+        let (width, height) = self.extent;
+        let unbound = self.device.create_image(
+            i::Kind::D2(width, height, 1, 1),
+            1,
+            format,
+            i::Tiling::Optimal,
+            usage,
+            i::ViewCapabilities::empty(),
+        )?;
+        let requirements = self.device.get_image_requirements(&unbound);
+
+        let mut allocator = self.allocator.borrow_mut();
+        let allocation = allocator
+            .allocate(
+                &requirements,
+                memory::Properties::DEVICE_LOCAL,
+                ResourceKind::Optimal,
+                MemoryCategory::Attachments,
+            )?;
+        let image = self.device
+            .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+        let view = self.device.create_image_view(&image, i::ViewKind::D2, format, f::Swizzle::NO, range)?;
+        Ok(ReflectionImage { image, view, allocation })
+    }
+
+    pub fn render_pass(&self) -> &B::RenderPass {
+        self.render_pass.as_ref().unwrap()
+    }
+
+    pub fn framebuffer(&self) -> &B::Framebuffer {
+        self.framebuffer.as_ref().unwrap()
+    }
+
+    /// The reflection, for sampling. It's in `ShaderReadOnlyOptimal` once it's been drawn.
+    pub fn view(&self) -> &B::ImageView {
+        &self.color.as_ref().unwrap().view
+    }
+
+    /// Filters linearly and clamps to the edge.
+    pub fn sampler(&self) -> &B::Sampler {
+        &self.sampler
+    }
+
+    /// A viewport covering the whole reflection.
+    pub fn viewport(&self) -> pso::Viewport {
+        let (width, height) = self.extent;
+        pso::Viewport {
+            rect: pso::Rect { x: 0, y: 0, w: width as i16, h: height as i16 },
+            depth: 0.0..1.0,
+        }
+    }
+
+    fn destroy_images(&mut self) {
+        if let Some(framebuffer) = self.framebuffer.take() {
+            self.device.destroy_framebuffer(framebuffer);
+        }
+        for image in self.color.take().into_iter().chain(self.depth.take()) {
+            self.device.destroy_image_view(image.view);
+            self.device.destroy_image(image.image);
+            self.allocator.borrow_mut().free(image.allocation);
+        }
+    }
+}
+
+impl<B: Backend> Drop for PlanarReflection<B> {
+    fn drop(&mut self) {
+        self.destroy_images();
+        if let Some(render_pass) = self.render_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use math::{ InnerSpace, Vec4 };
+
+    #[test]
+    fn reflections_parse_from_their_names() {
+        for &reflections in Reflections::ALL.iter() {
+            assert_eq!(reflections.to_string().parse(), Ok(reflections));
+        }
+        assert_eq!("Planar".parse(), Ok(Reflections::Planar));
+        assert!("mirror".parse::<Reflections>().is_err());
+    }
+
+    #[test]
+    fn the_reflection_is_a_fraction_of_the_screen() {
+        assert_eq!(reflection_extent(1280, 720), (640, 360));
+        assert_eq!(reflection_extent(1, 1), (1, 1));
+    }
+
+    #[test]
+    fn mirroring_flips_heights_about_the_plane() {
+        let mirrored = mirror(46.0) * Vec4::new(3.0, 50.0, -2.0, 1.0);
+        assert!((mirrored - Vec4::new(3.0, 42.0, -2.0, 1.0)).magnitude() < 1e-5);
+        let on_the_plane = mirror(46.0) * Vec4::new(7.0, 46.0, 1.0, 1.0);
+        assert!((on_the_plane - Vec4::new(7.0, 46.0, 1.0, 1.0)).magnitude() < 1e-5);
+    }
+}
@@ -0,0 +1,634 @@
+//! Saving the world to disk, and loading it back.
+//!
+//! Chunks are saved in region files, each holding `REGION_SIZE` by `REGION_SIZE` chunks of one
+//! row of the world, so there aren't thousands of tiny files and the chunks around the camera
+//! only take a few files to load. A region file starts with a table of where each of its chunks
+//! is in the file and how long it is, with zeros for the ones that haven't been saved. Chunks
+//! take up whole `SECTOR_SIZE` sectors of the file: a chunk that's saved again goes back where
+//! it was if it still fits, and on the end of the file if it doesn't.
+//!
+//! A saved chunk is its palette, followed by its blocks as runs of the same palette entry. Chunks are
+//! mostly long stretches of stone or air, so that's a few kilobytes at most rather than the
+//! 64 KiB of a `u16` for each block. After the blocks come the edits its decorations made to
+//! other chunks (see `decoration`), so chunks that haven't been generated yet still get them
+//! after a restart.
+//!
+//! The world directory also holds the seed the world was generated from, in `seed`, since the
+//! chunks that haven't been saved yet have to come out matching the ones that have.
+
+use std::collections::HashMap;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Read, Seek, SeekFrom, Write };
+use std::path::{ Path, PathBuf };
+
+use decoration::{ BlockEdit, EditMode, PendingEdits, Spill };
+use error::Result;
+use world::{ BlockId, Chunk, ChunkCoord, World, CHUNK_SIZE, CHUNK_VOLUME };
+
+/// How many chunks wide and deep a region is.
+pub const REGION_SIZE: i32 = 16;
+const REGION_SHIFT: i32 = 4;
+/// Chunks are stored in blocks of this many bytes. The table at the start of the file fits in
+/// the first one.
+const SECTOR_SIZE: u64 = 4096;
+const MAGIC: &[u8; 4] = b"VXRG";
+/// Bumped whenever the format changes, so old files are refused rather than misread.
+const VERSION: u32 = 1;
+const TABLE_ENTRIES: usize = (REGION_SIZE * REGION_SIZE) as usize;
+/// The magic, the version, and an offset and length for each chunk.
+const HEADER_BYTES: usize = 8 + TABLE_ENTRIES * 8;
+/// How many region files are kept open at once. The least recently used is closed to make room
+/// for another.
+const MAX_OPEN_REGIONS: usize = 16;
+const SEED_FILE: &str = "seed";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct RegionCoord {
+    x: i32,
+    y: i32,
+    z: i32,
+}
+
+impl RegionCoord {
+    /// The region the chunk at `coord` is in, and its index in the region's table.
+    fn of_chunk(coord: ChunkCoord) -> (RegionCoord, usize) {
+        let region = RegionCoord {
+            x: coord.x >> REGION_SHIFT,
+            y: coord.y,
+            z: coord.z >> REGION_SHIFT,
+        };
+        let mask = REGION_SIZE - 1;
+        let index = ((coord.z & mask) * REGION_SIZE + (coord.x & mask)) as usize;
+        (region, index)
+    }
+
+    fn file_name(self) -> String {
+        format!("r.{}.{}.{}.region", self.x, self.y, self.z)
+    }
+}
+
+/// Where a chunk is in its region file. A length of 0 means it hasn't been saved.
+#[derive(Clone, Copy, Debug, Default)]
+struct TableEntry {
+    /// In sectors from the start of the file.
+    sector: u32,
+    /// In bytes.
+    length: u32,
+}
+
+impl TableEntry {
+    fn sectors(&self) -> u32 {
+        sectors_for(self.length as usize)
+    }
+}
+
+fn sectors_for(bytes: usize) -> u32 {
+    ((bytes as u64 + SECTOR_SIZE - 1) / SECTOR_SIZE) as u32
+}
+
+struct RegionFile {
+    file: File,
+    table: Vec<TableEntry>,
+    /// The first sector after everything in the file.
+    end_sector: u32,
+    /// When it was last used, to pick which file to close when too many are open.
+    last_used: u64,
+}
+
+impl RegionFile {
+    /// Opens the region file at `path`, or returns `None` if there isn't one and `create` is
+    /// false.
+    fn open(path: &Path, create: bool) -> io::Result<Option<RegionFile>> {
+        if !create && !path.exists() {
+            return Ok(None);
+        }
+        let mut file = OpenOptions::new().read(true).write(true).create(create).open(path)?;
+        let mut table = vec![TableEntry::default(); TABLE_ENTRIES];
+
+        if file.metadata()?.len() == 0 {
+            let mut header = Vec::with_capacity(HEADER_BYTES);
+            header.extend_from_slice(MAGIC);
+            write_u32(&mut header, VERSION);
+            header.resize(HEADER_BYTES, 0);
+            file.write_all(&header)?;
+        } else {
+            let mut header = vec![0; HEADER_BYTES];
+            file.read_exact(&mut header)?;
+            let mut reader = Reader::new(&header);
+            if reader.bytes(4)? != MAGIC {
+                return Err(invalid_data("not a region file".to_string()));
+            }
+            let version = reader.u32()?;
+            if version != VERSION {
+                return Err(invalid_data(format!("saved with format version {}, expected {}", version, VERSION)));
+            }
+            for entry in &mut table {
+                entry.sector = reader.u32()?;
+                entry.length = reader.u32()?;
+            }
+        }
+
+        let end_sector = table.iter().map(|entry| entry.sector + entry.sectors()).max().unwrap_or(0).max(1);
+        Ok(Some(RegionFile {
+            file,
+            table,
+            end_sector,
+            last_used: 0,
+        }))
+    }
+
+    fn read(&mut self, index: usize) -> io::Result<Option<Vec<u8>>> {
+        let entry = self.table[index];
+        if entry.length == 0 {
+            return Ok(None);
+        }
+        self.file.seek(SeekFrom::Start(entry.sector as u64 * SECTOR_SIZE))?;
+        let mut data = vec![0; entry.length as usize];
+        self.file.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    fn write(&mut self, index: usize, data: &[u8]) -> io::Result<()> {
+        let old = self.table[index];
+        let needed = sectors_for(data.len());
+        let sector = if old.length > 0 && needed <= old.sectors() {
+            old.sector
+        } else {
+            let sector = self.end_sector;
+            self.end_sector += needed;
+            sector
+        };
+        self.file.seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE))?;
+        self.file.write_all(data)?;
+
+        // The table entry is written last, so until the chunk is all there the old entry still
+        // points at the old data
+        let entry = TableEntry { sector, length: data.len() as u32 };
+        let mut bytes = Vec::with_capacity(8);
+        write_u32(&mut bytes, entry.sector);
+        write_u32(&mut bytes, entry.length);
+        self.file.seek(SeekFrom::Start(8 + index as u64 * 8))?;
+        self.file.write_all(&bytes)?;
+        self.table[index] = entry;
+        Ok(())
+    }
+}
+
+/// The saved chunks of a world, in a directory of region files.
+pub struct RegionStore {
+    directory: PathBuf,
+    seed: u64,
+    regions: HashMap<RegionCoord, RegionFile>,
+    uses: u64,
+}
+
+impl RegionStore {
+    /// Opens the world saved in `directory`, creating it if it doesn't exist yet. A new world
+    /// is generated from `seed`; an existing one keeps the seed it was made with.
+    pub fn open(directory: &Path, seed: u64) -> Result<Self> {
+        fs::create_dir_all(directory)?;
+        let seed_path = directory.join(SEED_FILE);
+        let seed = if seed_path.exists() {
+            let text = fs::read_to_string(&seed_path)?;
+            text.trim()
+                .parse()
+                .map_err(|_| invalid_data(format!("{} doesn't hold a seed", seed_path.display())))?
+        } else {
+            fs::write(&seed_path, format!("{}\n", seed))?;
+            seed
+        };
+        Ok(RegionStore {
+            directory: directory.to_path_buf(),
+            seed,
+            regions: HashMap::new(),
+            uses: 0,
+        })
+    }
+
+    /// The seed the world is generated from.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// The chunk at `coord` and the edits its decorations made to other chunks, or `None` if
+    /// it hasn't been saved.
+    pub fn load(&mut self, coord: ChunkCoord) -> Result<Option<(Chunk, Spill)>> {
+        let (region, index) = RegionCoord::of_chunk(coord);
+        let path = self.directory.join(region.file_name());
+        let data = match self.region(region, false)? {
+            Some(file) => file.read(index).map_err(|err| in_file(&path, err))?,
+            None => None,
+        };
+        match data {
+            Some(data) => {
+                let decoded = decode_chunk(&data).map_err(|err| in_file(&path, err))?;
+                Ok(Some(decoded))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Saves the chunk at `coord`, along with the edits its decorations made to other chunks.
+    pub fn save(&mut self, coord: ChunkCoord, chunk: &Chunk, spill: &Spill) -> Result<()> {
+        let (region, index) = RegionCoord::of_chunk(coord);
+        let path = self.directory.join(region.file_name());
+        let data = encode_chunk(chunk, spill);
+        let file = self.region(region, true)?.expect("Region files are created when saving");
+        file.write(index, &data).map_err(|err| in_file(&path, err))?;
+        Ok(())
+    }
+
+    /// Loads the chunk at `coord` into `world` if it's been saved, returning whether it was.
+    /// The edits its decorations made to other chunks are recorded in `pending`, for the ones
+    /// that haven't been generated yet; the ones that have were saved with them already.
+    pub fn load_into(&mut self, world: &mut World, pending: &mut PendingEdits, coord: ChunkCoord) -> Result<bool> {
+        match self.load(coord)? {
+            Some((chunk, spill)) => {
+                world.insert_chunk(coord, chunk);
+                pending.insert(coord, &spill);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Makes `spill`'s edits to the chunks it's for that have already been saved, and saves
+    /// them again. For the edits a newly generated chunk made to chunks that aren't loaded.
+    pub fn apply_to_saved(&mut self, spill: &Spill) -> Result<()> {
+        for &(target, ref edits) in spill {
+            if let Some((mut chunk, target_spill)) = self.load(target)? {
+                let changed = edits.iter().fold(false, |changed, edit| edit.apply(&mut chunk) || changed);
+                if changed {
+                    self.save(target, &chunk, &target_spill)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Saves the chunk at `coord` if it's loaded. Call this before unloading a chunk that
+    /// `World::is_unsaved`.
+    pub fn save_chunk(&mut self, world: &World, pending: &PendingEdits, coord: ChunkCoord) -> Result<()> {
+        match world.chunk(coord) {
+            Some(chunk) => self.save(coord, chunk, &pending.spill_from(coord)),
+            None => Ok(()),
+        }
+    }
+
+    /// Saves every chunk in `world` with changes that haven't been saved, and returns how many
+    /// there were. If one fails, it and the ones after it are left unsaved.
+    pub fn save_unsaved(&mut self, world: &mut World, pending: &PendingEdits) -> Result<usize> {
+        let unsaved = world.take_unsaved();
+        for (i, &coord) in unsaved.iter().enumerate() {
+            if let Err(err) = self.save_chunk(world, pending, coord) {
+                for &coord in &unsaved[i..] {
+                    world.mark_unsaved(coord);
+                }
+                return Err(err);
+            }
+        }
+        Ok(unsaved.len())
+    }
+
+    /// Waits for everything saved so far to reach the disk.
+    pub fn flush(&mut self) -> Result<()> {
+        for file in self.regions.values() {
+            file.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    /// The region file for `region`, opening it if it isn't already. Returns `None` if it
+    /// doesn't exist and `create` is false.
+    fn region(&mut self, region: RegionCoord, create: bool) -> Result<Option<&mut RegionFile>> {
+        self.uses += 1;
+        if !self.regions.contains_key(&region) {
+            let path = self.directory.join(region.file_name());
+            let file = match RegionFile::open(&path, create).map_err(|err| in_file(&path, err))? {
+                Some(file) => file,
+                None => return Ok(None),
+            };
+            if self.regions.len() >= MAX_OPEN_REGIONS {
+                let oldest = self.regions.iter().min_by_key(|&(_, file)| file.last_used).map(|(&coord, _)| coord);
+                if let Some(oldest) = oldest {
+                    self.regions.remove(&oldest);
+                }
+            }
+            self.regions.insert(region, file);
+        }
+        let file = self.regions.get_mut(&region).unwrap();
+        file.last_used = self.uses;
+        Ok(Some(file))
+    }
+}
+
+/// Where the block at `index` in a saved chunk goes: rows along x, then z, then y.
+fn local_of(index: usize) -> [usize; 3] {
+    [index % CHUNK_SIZE, index / (CHUNK_SIZE * CHUNK_SIZE), index / CHUNK_SIZE % CHUNK_SIZE]
+}
+
+/// The bytes a chunk is saved as, along with the edits its decorations made to other chunks.
+pub fn encode_chunk(chunk: &Chunk, spill: &Spill) -> Vec<u8> {
+    let mut palette: Vec<BlockId> = Vec::new();
+    // Palette entry and length
+    let mut runs: Vec<(u16, u16)> = Vec::new();
+    for index in 0..CHUNK_VOLUME {
+        let block = chunk.get(local_of(index));
+        let entry = match palette.iter().position(|&other| other == block) {
+            Some(entry) => entry,
+            None => {
+                palette.push(block);
+                palette.len() - 1
+            }
+        } as u16;
+        match runs.last_mut() {
+            Some(run) if run.0 == entry && run.1 < u16::max_value() => run.1 += 1,
+            _ => runs.push((entry, 1)),
+        }
+    }
+
+    let mut data = Vec::new();
+    write_u16(&mut data, palette.len() as u16);
+    for block in &palette {
+        write_u16(&mut data, block.0);
+    }
+    write_u32(&mut data, runs.len() as u32);
+    for &(entry, length) in &runs {
+        write_u16(&mut data, entry);
+        write_u16(&mut data, length);
+    }
+
+    write_u32(&mut data, spill.len() as u32);
+    for &(target, ref edits) in spill {
+        for &coordinate in &[target.x, target.y, target.z] {
+            write_u32(&mut data, coordinate as u32);
+        }
+        write_u32(&mut data, edits.len() as u32);
+        for edit in edits {
+            data.extend_from_slice(&edit.local);
+            write_u16(&mut data, edit.block.0);
+            data.push(match edit.mode {
+                EditMode::Fill => 0,
+                EditMode::Replace => 1,
+            });
+        }
+    }
+    data
+}
+
+/// A chunk and its decorations' edits to other chunks, back from the bytes `encode_chunk` made.
+pub fn decode_chunk(data: &[u8]) -> io::Result<(Chunk, Spill)> {
+    let mut reader = Reader::new(data);
+    let palette_len = reader.u16()? as usize;
+    let mut palette = Vec::with_capacity(palette_len);
+    for _ in 0..palette_len {
+        palette.push(BlockId(reader.u16()?));
+    }
+
+    let run_count = reader.u32()?;
+    let mut chunk: Option<Chunk> = None;
+    let mut index = 0;
+    for _ in 0..run_count {
+        let (entry, length) = (reader.u16()? as usize, reader.u16()? as usize);
+        let block = *palette
+            .get(entry)
+            .ok_or_else(|| invalid_data(format!("palette entry {} of {} used", entry, palette.len())))?;
+        if index + length > CHUNK_VOLUME {
+            return Err(invalid_data("too many blocks in chunk".to_string()));
+        }
+        // Starting from a chunk full of the first run's block skips setting most of them
+        let chunk = chunk.get_or_insert_with(|| Chunk::filled(block));
+        for index in index..index + length {
+            chunk.set(local_of(index), block);
+        }
+        index += length;
+    }
+    if index != CHUNK_VOLUME {
+        return Err(invalid_data(format!("{} blocks in chunk, expected {}", index, CHUNK_VOLUME)));
+    }
+
+    let mut spill = Vec::new();
+    for _ in 0..reader.u32()? {
+        let target = ChunkCoord::new(reader.i32()?, reader.i32()?, reader.i32()?);
+        let edit_count = reader.u32()?;
+        let mut edits = Vec::with_capacity(edit_count as usize);
+        for _ in 0..edit_count {
+            let local = reader.bytes(3)?;
+            let local = [local[0], local[1], local[2]];
+            if local.iter().any(|&coordinate| coordinate as usize >= CHUNK_SIZE) {
+                return Err(invalid_data(format!("edit outside its chunk at {:?}", local)));
+            }
+            let block = BlockId(reader.u16()?);
+            let mode = match reader.u8()? {
+                0 => EditMode::Fill,
+                1 => EditMode::Replace,
+                other => return Err(invalid_data(format!("unknown edit mode {}", other))),
+            };
+            edits.push(BlockEdit { local, block, mode });
+        }
+        spill.push((target, edits));
+    }
+    if !reader.is_done() {
+        return Err(invalid_data("extra bytes after chunk".to_string()));
+    }
+
+    Ok((chunk.unwrap_or_else(Chunk::new), spill))
+}
+
+fn write_u16(data: &mut Vec<u8>, value: u16) {
+    data.push(value as u8);
+    data.push((value >> 8) as u8);
+}
+
+fn write_u32(data: &mut Vec<u8>, value: u32) {
+    for shift in &[0, 8, 16, 24] {
+        data.push((value >> shift) as u8);
+    }
+}
+
+/// Reads little endian numbers from the front of a slice.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data }
+    }
+
+    fn bytes(&mut self, count: usize) -> io::Result<&'a [u8]> {
+        if self.data.len() < count {
+            return Err(invalid_data("ends too soon".to_string()));
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> io::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> io::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(bytes[0] as u16 | (bytes[1] as u16) << 8)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(bytes.iter().rev().fold(0, |value, &byte| value << 8 | byte as u32))
+    }
+
+    fn i32(&mut self) -> io::Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn is_done(&self) -> bool {
+        self.data.is_empty()
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// Adds the file `err` happened in to its message.
+fn in_file(path: &Path, err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    const STONE: BlockId = BlockId(1);
+    const LEAVES: BlockId = BlockId(2);
+
+    /// An empty directory to save a world in, removed when it's dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path = env::temp_dir().join(format!("region-test-{}-{}", name, process::id()));
+            let _ = fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Stone up to `height`, with a hole in it.
+    fn hilly_chunk(height: usize) -> Chunk {
+        let mut chunk = Chunk::new();
+        for y in 0..height {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    chunk.set([x, y, z], STONE);
+                }
+            }
+        }
+        chunk.set([3, 1, 4], BlockId::AIR);
+        chunk.set([3, height, 4], LEAVES);
+        chunk
+    }
+
+    fn spill() -> Spill {
+        let edit = BlockEdit { local: [31, 2, 0], block: LEAVES, mode: EditMode::Fill };
+        vec![(ChunkCoord::new(-1, 0, 0), vec![edit]), (ChunkCoord::new(0, 1, 0), Vec::new())]
+    }
+
+    #[test]
+    fn chunks_come_back_as_they_were_saved() {
+        let chunk = hilly_chunk(20);
+        let data = encode_chunk(&chunk, &spill());
+        // A handful of runs, rather than a number for every block
+        assert!(data.len() < 200, "{} bytes", data.len());
+        let (decoded, decoded_spill) = decode_chunk(&data).unwrap();
+        assert!(decoded == chunk);
+        assert_eq!(decoded_spill, spill());
+    }
+
+    #[test]
+    fn damaged_chunks_are_refused() {
+        let data = encode_chunk(&hilly_chunk(5), &spill());
+        assert!(decode_chunk(&data[..data.len() - 1]).is_err());
+        let mut longer = data.clone();
+        longer.push(0);
+        assert!(decode_chunk(&longer).is_err());
+    }
+
+    #[test]
+    fn saved_chunks_load_again_after_reopening() {
+        let dir = TempDir::new("reopen");
+        let coords = [ChunkCoord::new(0, 0, 0), ChunkCoord::new(15, 0, -1), ChunkCoord::new(16, 2, 3)];
+        {
+            let mut store = RegionStore::open(&dir.0, 5).unwrap();
+            for (i, &coord) in coords.iter().enumerate() {
+                store.save(coord, &hilly_chunk(i + 1), &spill()).unwrap();
+            }
+            store.flush().unwrap();
+        }
+
+        let mut store = RegionStore::open(&dir.0, 5).unwrap();
+        for (i, &coord) in coords.iter().enumerate() {
+            let (chunk, spill) = store.load(coord).unwrap().unwrap();
+            assert!(chunk == hilly_chunk(i + 1));
+            assert_eq!(spill.len(), 2);
+        }
+        assert!(store.load(ChunkCoord::new(1, 0, 0)).unwrap().is_none());
+        assert!(store.load(ChunkCoord::new(100, 0, 100)).unwrap().is_none());
+    }
+
+    #[test]
+    fn chunks_that_outgrow_their_sectors_move_without_disturbing_the_others() {
+        let dir = TempDir::new("grow");
+        let mut store = RegionStore::open(&dir.0, 0).unwrap();
+        let (small, next) = (ChunkCoord::new(0, 0, 0), ChunkCoord::new(1, 0, 0));
+        store.save(small, &Chunk::filled(STONE), &Vec::new()).unwrap();
+        store.save(next, &hilly_chunk(3), &Vec::new()).unwrap();
+
+        // A checkerboard has a run for every block, which takes more than one sector
+        let mut checkered = Chunk::new();
+        for index in (0..CHUNK_VOLUME).step_by(2) {
+            checkered.set(local_of(index), STONE);
+        }
+        store.save(small, &checkered, &Vec::new()).unwrap();
+
+        assert!(store.load(small).unwrap().unwrap().0 == checkered);
+        assert!(store.load(next).unwrap().unwrap().0 == hilly_chunk(3));
+    }
+
+    #[test]
+    fn a_world_keeps_the_seed_it_was_made_with() {
+        let dir = TempDir::new("seed");
+        assert_eq!(RegionStore::open(&dir.0, 42).unwrap().seed(), 42);
+        assert_eq!(RegionStore::open(&dir.0, 7).unwrap().seed(), 42);
+    }
+
+    #[test]
+    fn only_unsaved_chunks_are_saved() {
+        let dir = TempDir::new("unsaved");
+        let mut store = RegionStore::open(&dir.0, 0).unwrap();
+        let pending = PendingEdits::new();
+        let mut world = World::new();
+        let (edited, untouched) = (ChunkCoord::new(0, 0, 0), ChunkCoord::new(2, 0, 0));
+        world.insert_chunk(edited, Chunk::new());
+        world.insert_chunk(untouched, Chunk::new());
+        world.set_block([1, 1, 1], STONE);
+
+        assert_eq!(store.save_unsaved(&mut world, &pending).unwrap(), 1);
+        assert_eq!(store.save_unsaved(&mut world, &pending).unwrap(), 0);
+        assert!(store.load(edited).unwrap().is_some());
+        assert!(store.load(untouched).unwrap().is_none());
+    }
+}
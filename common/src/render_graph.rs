@@ -0,0 +1,647 @@
+//! The gpu side of a render graph: the images, render passes and framebuffers for a compiled
+//! `graph::Graph`, and recording and submitting it with the barriers and semaphores it worked
+//! out.
+//!
+//! Every pass that draws into the graph's own images gets a render pass of its own, which leaves
+//! its attachments in the layout they're drawn in. The graph moves them between layouts itself,
+//! with a barrier before each pass, so none of the render passes need dependencies. Like the
+//! other per-frame attachments, there's a set of images for each swapchain image, sized to match
+//! the swapchain.
+//!
+//! With a compute queue to run on, each of the graph's submissions is recorded into a command
+//! buffer of its own and submitted to its queue, waiting on a semaphore that the submission it
+//! waits for signals. A resource is handed over by the semaphore and a barrier in the receiving
+//! queue's submission, since barriers in this version of `hal` can't release a resource to
+//! another queue family. Without one, every pass is recorded in order into the caller's command
+//! buffer on the graphics queue, and the compute passes run there.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hal::{
+    buffer, command, format as f, image as i, memory, pass, pool, pso,
+    pso::PipelineStage,
+    queue::Supports,
+    Backend, Compute, Device, General, QueueGroup, Submission, SwapImageIndex, Transfer,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use async_compute::AsyncCompute;
+use context::GfxContext;
+use depth::depth_range;
+use error::Result;
+use graph::{ Graph, GraphBuilder, GraphError, Load, PassId, Queue, ResourceId, Transition, Usage };
+use resources::SwapchainBundle;
+use texture::COLOR_RANGE;
+
+/// What a transient image in a render graph is like. Resources with the same description can
+/// share an image.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ImageDesc {
+    pub format: f::Format,
+    /// What the swapchain's size is divided by along each side, 1 for the same size.
+    pub divisor: u32,
+}
+
+impl ImageDesc {
+    /// An image of `format`, the same size as the swapchain.
+    pub fn full(format: f::Format) -> Self {
+        ImageDesc { format, divisor: 1 }
+    }
+}
+
+struct SlotImage<B: Backend> {
+    image: B::Image,
+    view: B::ImageView,
+    allocation: Allocation,
+}
+
+/// What a pass is given to record into.
+pub enum Commands<'a, 'b: 'a, B: Backend + 'b> {
+    /// The render pass of a pass that draws into the graph's images, with the viewport and
+    /// scissor set to their size.
+    RenderPass(&'a mut command::RenderPassInlineEncoder<'b, B>),
+    /// A command buffer on the graphics queue, for a pass that draws in render passes of its own
+    /// or for a compute pass when there's no compute queue. The viewport and scissor are left to
+    /// the pass.
+    Graphics(&'a mut command::CommandBuffer<'b, B, General, command::OneShot>),
+    /// A command buffer on the compute queue.
+    Compute(&'a mut command::CommandBuffer<'b, B, Compute, command::OneShot>),
+}
+
+impl<'a, 'b: 'a, B: Backend + 'b> Commands<'a, 'b, B> {
+    /// The command buffer to dispatch into, whichever queue it's on. Panics in a render pass.
+    pub fn compute(&mut self) -> &mut command::CommandBuffer<'b, B, Compute, command::OneShot> {
+        match *self {
+            Commands::Compute(ref mut command_buffer) => &mut **command_buffer,
+            Commands::Graphics(ref mut command_buffer) => command_buffer.downgrade(),
+            Commands::RenderPass(_) => panic!("A pass drawing into the graph's images can't dispatch"),
+        }
+    }
+
+    /// The command buffer on the graphics queue, if the pass is recorded into one outside a
+    /// render pass.
+    pub fn graphics(&mut self) -> Option<&mut command::CommandBuffer<'b, B, General, command::OneShot>> {
+        match *self {
+            Commands::Graphics(ref mut command_buffer) => Some(&mut **command_buffer),
+            _ => None,
+        }
+    }
+}
+
+/// The command pool and semaphores for one frame of the graph's submissions.
+struct GraphFrame<B: Backend> {
+    graphics_pool: Option<pool::CommandPool<B, General>>,
+    /// For each submission, signalled when it's done if anything waits for it.
+    semaphores: Vec<Option<B::Semaphore>>,
+}
+
+/// A compiled graph and everything it needs to run. Recreate its images along with the
+/// swapchain.
+pub struct RenderGraph<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    graph: Graph<ImageDesc>,
+    /// For each step of the graph, if it draws into the graph's images.
+    render_passes: Vec<Option<B::RenderPass>>,
+    /// For each slot, an image per swapchain image.
+    images: Vec<Vec<SlotImage<B>>>,
+    extents: Vec<i::Extent>,
+    /// For each step, a framebuffer per swapchain image, or none if it has no render pass.
+    framebuffers: Vec<Vec<B::Framebuffer>>,
+    /// Empty without a compute queue, when everything goes in the caller's command buffer.
+    frames: Vec<GraphFrame<B>>,
+    async_compute: Option<AsyncCompute<B>>,
+}
+
+impl<B: Backend> RenderGraph<B> {
+    /// Compiles `builder`, and builds its images and framebuffers to match `swapchain`, and the
+    /// command pools and semaphores for `frames_in_flight` frames of it if `context` has a
+    /// compute queue.
+    pub fn new(
+        context: &GfxContext<B>,
+        builder: GraphBuilder<ImageDesc>,
+        swapchain: &SwapchainBundle<B>,
+        frames_in_flight: usize,
+    ) -> Result<Self> {
+        let graph = builder.compile()?;
+        for &pass in graph.culled() {
+            debug!("Culled the {} pass, since nothing uses what it draws", graph.pass_name(pass));
+        }
+
+        let device = context.device.clone();
+        let render_passes = graph.steps()
+            .iter()
+            .map(|step| {
+                if step.attachments.is_empty() {
+                    return None;
+                }
+                let attachments: Vec<_> = step.attachments
+                    .iter()
+                    .map(|attachment| pass::Attachment {
+                        format: Some(graph.slots()[graph.slot(attachment.resource).unwrap()].format),
+                        samples: 1,
+                        ops: pass::AttachmentOps {
+                            load: match attachment.load {
+                                Load::ClearColor(_) | Load::ClearDepth(_) => pass::AttachmentLoadOp::Clear,
+                                Load::Keep => pass::AttachmentLoadOp::Load,
+                                Load::DontCare => pass::AttachmentLoadOp::DontCare,
+                            },
+                            store: if attachment.store {
+                                pass::AttachmentStoreOp::Store
+                            } else {
+                                pass::AttachmentStoreOp::DontCare
+                            },
+                        },
+                        stencil_ops: pass::AttachmentOps::DONT_CARE,
+                        layouts: layout(attachment.usage)..layout(attachment.usage),
+                    })
+                    .collect();
+                let colors: Vec<_> = step.attachments
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, attachment)| attachment.usage == Usage::ColorAttachment)
+                    .map(|(index, _)| (index, i::Layout::ColorAttachmentOptimal))
+                    .collect();
+                let depth = step.attachments
+                    .iter()
+                    .position(|attachment| attachment.usage == Usage::DepthAttachment)
+                    .map(|index| (index, i::Layout::DepthStencilAttachmentOptimal));
+
+                let subpass = pass::SubpassDesc {
+                    colors: &colors,
+                    depth_stencil: depth.as_ref(),
+                    inputs: &[],
+                    resolves: &[],
+                    preserves: &[],
+                };
+                Some(device.create_render_pass(&attachments, &[subpass], &[]))
+            })
+            .collect();
+
+        // Only submissions something waits for need a semaphore
+        let (frames, async_compute) = match context.compute_queue_group {
+            Some(ref compute_queue_group) => {
+                let submissions = graph.submissions();
+                let count = |queue| submissions.iter().filter(|submission| submission.queue == queue).count();
+                let waited: Vec<bool> = (0..submissions.len())
+                    .map(|index| {
+                        graph.wait_after() == Some(index)
+                            || submissions.iter().any(|submission| submission.wait == Some(index))
+                    })
+                    .collect();
+                let frames = (0..frames_in_flight)
+                    .map(|_| GraphFrame {
+                        graphics_pool: Some(device.create_command_pool_typed(
+                            &context.queue_group,
+                            pool::CommandPoolCreateFlags::empty(),
+                            count(Queue::Graphics),
+                        )),
+                        semaphores: waited
+                            .iter()
+                            .map(|&waited| if waited { Some(device.create_semaphore()) } else { None })
+                            .collect(),
+                    })
+                    .collect();
+                let async_compute =
+                    AsyncCompute::new(device.clone(), compute_queue_group, frames_in_flight, count(Queue::Compute));
+                (frames, Some(async_compute))
+            }
+            None => (Vec::new(), None),
+        };
+
+        let mut render_graph = RenderGraph {
+            device,
+            allocator: context.allocator.clone(),
+            graph,
+            render_passes,
+            images: Vec::new(),
+            extents: Vec::new(),
+            framebuffers: Vec::new(),
+            frames,
+            async_compute,
+        };
+        render_graph.recreate(swapchain)?;
+        Ok(render_graph)
+    }
+
+    /// Rebuilds the images and framebuffers to match `swapchain`'s size and image count.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.destroy_images();
+
+        let count = swapchain.frame_images().len();
+        let extent = swapchain.extent();
+        let usages = self.slot_usages();
+        for (slot, desc) in self.graph.slots().iter().enumerate() {
+            let size = i::Extent {
+                width: (extent.width / desc.divisor).max(1),
+                height: (extent.height / desc.divisor).max(1),
+                depth: 1,
+            };
+            let range = subresource_range(desc.format);
+            let mut images = Vec::with_capacity(count);
+            for _ in 0..count {
+                let unbound = self.device.create_image(
+                    i::Kind::D2(size.width, size.height, 1, 1),
+                    1,
+                    desc.format,
+                    i::Tiling::Optimal,
+                    usages[slot],
+                    i::ViewCapabilities::empty(),
+                )?;
+                let requirements = self.device.get_image_requirements(&unbound);
+
+                let mut allocator = self.allocator.borrow_mut();
+                let allocation = allocator
+                    .allocate(
+                        &requirements,
+                        memory::Properties::DEVICE_LOCAL,
+                        ResourceKind::Optimal,
+                        MemoryCategory::Attachments,
+                    )?;
+                let image = self.device
+                    .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+                let view = self.device
+                    .create_image_view(&image, i::ViewKind::D2, desc.format, f::Swizzle::NO, range.clone())?;
+                images.push(SlotImage { image, view, allocation });
+            }
+            self.images.push(images);
+            self.extents.push(size);
+        }
+
+        for (step_index, step) in self.graph.steps().iter().enumerate() {
+            let render_pass = match self.render_passes[step_index] {
+                Some(ref render_pass) => render_pass,
+                None => {
+                    self.framebuffers.push(Vec::new());
+                    continue;
+                }
+            };
+            let slots: Vec<usize> = step.attachments
+                .iter()
+                .map(|attachment| self.graph.slot(attachment.resource).unwrap())
+                .collect();
+            let extent = self.extents[slots[0]];
+            if slots.iter().any(|&slot| self.extents[slot] != extent) {
+                let pass = self.graph.pass_name(step.pass).to_string();
+                return Err(GraphError::MismatchedSizes { pass }.into());
+            }
+
+            let mut framebuffers = Vec::with_capacity(count);
+            for index in 0..count {
+                let views = slots.iter().map(|&slot| &self.images[slot][index].view);
+                framebuffers.push(self.device.create_framebuffer(render_pass, views, extent)?);
+            }
+            self.framebuffers.push(framebuffers);
+        }
+        Ok(())
+    }
+
+    /// The render pass `pass` draws in, for building its pipelines against. Panics if `pass` was
+    /// culled or doesn't draw into any of the graph's images.
+    pub fn render_pass(&self, pass: PassId) -> &B::RenderPass {
+        let step = self.graph.step_of(pass).expect("The pass was culled from the render graph");
+        self.render_passes[step].as_ref().expect("The pass doesn't draw into the render graph's images")
+    }
+
+    /// The image behind `resource` for swapchain image `index`, for reading or writing it
+    /// through a descriptor set. Panics if `resource` isn't one of the graph's own that's in
+    /// use.
+    pub fn view(&self, resource: ResourceId, index: usize) -> &B::ImageView {
+        let slot = self.graph.slot(resource).expect("The resource isn't in the render graph");
+        &self.images[slot][index].view
+    }
+
+    /// Records and submits every pass for frame `frame_index` and swapchain image
+    /// `image_index`, giving each to `record` in turn. The first submission on the graphics
+    /// queue waits on `waits`, and the barriers leaving the exported resources ready to sample
+    /// go in `command_buffer`, which is submitted after the graph on the graphics queue. Returns
+    /// what that submission has to wait on as well.
+    ///
+    /// Only call this once `FrameSync::begin_frame` has waited on the frame, so its command
+    /// buffers and semaphores are free again. Without a compute queue, every pass is recorded
+    /// into `command_buffer` instead, and `waits` are handed back.
+    pub fn execute<'a, F>(
+        &'a mut self,
+        queue_group: &mut QueueGroup<B, General>,
+        compute_queue_group: Option<&mut QueueGroup<B, Compute>>,
+        frame_index: usize,
+        image_index: SwapImageIndex,
+        waits: &[(&'a B::Semaphore, PipelineStage)],
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        mut record: F,
+    ) -> Result<Vec<(&'a B::Semaphore, PipelineStage)>>
+    where
+        F: FnMut(PassId, Commands<B>) -> Result<()>,
+    {
+        let RenderGraph {
+            ref graph,
+            ref render_passes,
+            ref images,
+            ref extents,
+            ref framebuffers,
+            ref mut frames,
+            ref mut async_compute,
+            ..
+        } = *self;
+        let recorder = Recorder {
+            graph,
+            render_passes,
+            images,
+            extents,
+            framebuffers,
+            index: image_index as usize,
+        };
+        let (async_compute, compute_queue_group) = match (async_compute.as_mut(), compute_queue_group) {
+            (Some(async_compute), Some(compute_queue_group)) => (async_compute, compute_queue_group),
+            _ => {
+                for (step_index, step) in graph.steps().iter().enumerate() {
+                    recorder.transition(command_buffer, &step.transitions, Queue::Graphics, true);
+                    recorder.graphics_step(command_buffer, step_index, &mut record)?;
+                }
+                recorder.transition(command_buffer, graph.finish(), Queue::Graphics, true);
+                return Ok(waits.to_vec());
+            }
+        };
+
+        let GraphFrame { ref mut graphics_pool, ref semaphores } = frames[frame_index];
+        let graphics_pool = graphics_pool.as_mut().unwrap();
+        graphics_pool.reset();
+        let compute_pool = async_compute.begin_frame(frame_index);
+
+        // The first submission on the graphics queue takes the caller's waits
+        let mut waits = Some(waits);
+        for (submission_index, submission) in graph.submissions().iter().enumerate() {
+            let mut submission_waits = Vec::new();
+            if let Some(wait) = submission.wait {
+                submission_waits.push((semaphores[wait].as_ref().unwrap(), queue_stages(submission.queue)));
+            }
+            let signal: Vec<_> = semaphores[submission_index].iter().collect();
+            match submission.queue {
+                Queue::Graphics => {
+                    if let Some(waits) = waits.take() {
+                        submission_waits.extend(waits.iter().cloned());
+                    }
+                    let finished_command_buffer = {
+                        let mut command_buffer = graphics_pool.acquire_command_buffer(false);
+                        for step_index in submission.steps.clone() {
+                            let transitions = &graph.steps()[step_index].transitions;
+                            recorder.transition(&mut command_buffer, transitions, Queue::Graphics, false);
+                            recorder.graphics_step(&mut command_buffer, step_index, &mut record)?;
+                        }
+                        command_buffer.finish()
+                    };
+                    let submission = Submission::new()
+                        .wait_on(&submission_waits)
+                        .signal(&signal)
+                        .submit(Some(finished_command_buffer));
+                    queue_group.queues[0].submit(submission, None);
+                }
+                Queue::Compute => {
+                    let finished_command_buffer = {
+                        let mut command_buffer = compute_pool.acquire_command_buffer(false);
+                        for step in &graph.steps()[submission.steps.clone()] {
+                            recorder.transition(&mut command_buffer, &step.transitions, Queue::Compute, false);
+                            record(step.pass, Commands::Compute(&mut command_buffer))?;
+                        }
+                        command_buffer.finish()
+                    };
+                    let submission = Submission::new()
+                        .wait_on(&submission_waits)
+                        .signal(&signal)
+                        .submit(Some(finished_command_buffer));
+                    compute_queue_group.queues[0].submit(submission, None);
+                }
+            }
+        }
+
+        recorder.transition(command_buffer, graph.finish(), Queue::Graphics, false);
+        let mut after = waits.map_or(Vec::new(), |waits| waits.to_vec());
+        if let Some(last) = graph.wait_after() {
+            after.push((semaphores[last].as_ref().unwrap(), queue_stages(Queue::Graphics)));
+        }
+        Ok(after)
+    }
+
+    /// Which ways each slot's images are used, across every resource sharing them.
+    fn slot_usages(&self) -> Vec<i::Usage> {
+        let mut usages = vec![i::Usage::empty(); self.graph.slots().len()];
+        let transitions = self.graph.steps().iter().flat_map(|step| &step.transitions).chain(self.graph.finish());
+        for transition in transitions {
+            if let Some(slot) = self.graph.slot(transition.resource) {
+                usages[slot] |= match transition.to {
+                    Usage::ColorAttachment => i::Usage::COLOR_ATTACHMENT,
+                    Usage::DepthAttachment => i::Usage::DEPTH_STENCIL_ATTACHMENT,
+                    Usage::Sampled => i::Usage::SAMPLED,
+                    Usage::Storage => i::Usage::STORAGE,
+                    Usage::External => i::Usage::empty(),
+                };
+            }
+        }
+        usages
+    }
+
+    fn destroy_images(&mut self) {
+        for framebuffer in self.framebuffers.drain(..).flat_map(|framebuffers| framebuffers) {
+            self.device.destroy_framebuffer(framebuffer);
+        }
+        for image in self.images.drain(..).flat_map(|images| images) {
+            self.device.destroy_image_view(image.view);
+            self.device.destroy_image(image.image);
+            self.allocator.borrow_mut().free(image.allocation);
+        }
+        self.extents.clear();
+    }
+}
+
+impl<B: Backend> Drop for RenderGraph<B> {
+    fn drop(&mut self) {
+        self.destroy_images();
+        for render_pass in self.render_passes.drain(..).filter_map(|render_pass| render_pass) {
+            self.device.destroy_render_pass(render_pass);
+        }
+        for frame in &mut self.frames {
+            for semaphore in frame.semaphores.drain(..).filter_map(|semaphore| semaphore) {
+                self.device.destroy_semaphore(semaphore);
+            }
+            if let Some(graphics_pool) = frame.graphics_pool.take() {
+                self.device.destroy_command_pool(graphics_pool.into_raw());
+            }
+        }
+    }
+}
+
+/// The parts of a `RenderGraph` that recording it reads, borrowed apart from its command pools,
+/// for recording into swapchain image `index`'s images.
+struct Recorder<'a, B: Backend + 'a> {
+    graph: &'a Graph<ImageDesc>,
+    render_passes: &'a [Option<B::RenderPass>],
+    images: &'a [Vec<SlotImage<B>>],
+    extents: &'a [i::Extent],
+    framebuffers: &'a [Vec<B::Framebuffer>],
+    index: usize,
+}
+
+impl<'a, B: Backend> Recorder<'a, B> {
+    /// Gives step `step_index` to `record` in `command_buffer`, inside its render pass with the
+    /// viewport and scissor set to its size if it draws into the graph's images.
+    fn graphics_step<F>(
+        &self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        step_index: usize,
+        record: &mut F,
+    ) -> Result<()>
+    where
+        F: FnMut(PassId, Commands<B>) -> Result<()>,
+    {
+        let step = &self.graph.steps()[step_index];
+        let render_pass = match self.render_passes[step_index] {
+            Some(ref render_pass) => render_pass,
+            None => return record(step.pass, Commands::Graphics(command_buffer)),
+        };
+
+        let extent = self.extents[self.graph.slot(step.attachments[0].resource).unwrap()];
+        let rect = pso::Rect { x: 0, y: 0, w: extent.width as i16, h: extent.height as i16 };
+        command_buffer.set_viewports(0, &[pso::Viewport { rect, depth: 0.0..1.0 }]);
+        command_buffer.set_scissors(0, &[rect]);
+
+        let clear_values: Vec<_> = step.attachments.iter().map(|attachment| clear_value(attachment.load)).collect();
+        let mut encoder = command_buffer.begin_render_pass_inline(
+            render_pass,
+            &self.framebuffers[step_index][self.index],
+            rect,
+            &clear_values,
+        );
+        record(step.pass, Commands::RenderPass(&mut encoder))
+    }
+
+    /// Records the barriers for `transitions` into `command_buffer` on `queue`, all in one.
+    /// `single` is whether every pass is on the graphics queue, with nothing to hand over.
+    fn transition<C>(
+        &self,
+        command_buffer: &mut command::CommandBuffer<B, C, command::OneShot>,
+        transitions: &[Transition],
+        queue: Queue,
+        single: bool,
+    ) where
+        C: Supports<Transfer>,
+    {
+        let mut src_stages = PipelineStage::empty();
+        let mut dst_stages = PipelineStage::empty();
+        let mut external = false;
+        let mut barriers = Vec::with_capacity(transitions.len() + 2);
+        for transition in transitions {
+            let handoff = !single && transition.is_handoff();
+            if transition.to == Usage::External {
+                // The semaphore the submission waited on already covers a handoff. Otherwise all
+                // that's known is that something was written, so everything waits for it.
+                if !handoff {
+                    external = true;
+                    src_stages |= queue_stages(queue);
+                    dst_stages |= queue_stages(queue);
+                }
+                continue;
+            }
+
+            let slot = self.graph.slot(transition.resource).unwrap();
+            // A handoff acquires the image after the wait on the other queue, which is where the
+            // last use finished, so there's nothing left on this queue to wait for
+            let (from_stages, from_access) = if handoff {
+                (queue_stages(queue), i::Access::empty())
+            } else {
+                (stages(transition.from, transition.from_queue), access(transition.from))
+            };
+            src_stages |= from_stages;
+            dst_stages |= stages(transition.to, transition.to_queue);
+            // Anything in the image is thrown away going from undefined
+            let old_layout = if transition.discard { i::Layout::Undefined } else { layout(transition.from) };
+            barriers.push(memory::Barrier::Image {
+                states: (from_access, old_layout)..(access(transition.to), layout(transition.to)),
+                target: &self.images[slot][self.index].image,
+                range: subresource_range(self.graph.slots()[slot].format),
+            });
+        }
+        if external {
+            barriers.push(memory::Barrier::AllBuffers(
+                buffer::Access::MEMORY_WRITE..(buffer::Access::MEMORY_READ | buffer::Access::MEMORY_WRITE),
+            ));
+            barriers.push(memory::Barrier::AllImages(
+                i::Access::MEMORY_WRITE..(i::Access::MEMORY_READ | i::Access::MEMORY_WRITE),
+            ));
+        }
+        if barriers.is_empty() {
+            return;
+        }
+        command_buffer.pipeline_barrier(src_stages..dst_stages, memory::Dependencies::empty(), barriers);
+    }
+}
+
+fn subresource_range(format: f::Format) -> i::SubresourceRange {
+    if format.surface_desc().aspects.contains(f::Aspects::DEPTH) {
+        depth_range(format)
+    } else {
+        COLOR_RANGE.clone()
+    }
+}
+
+/// Imported resources are never moved between layouts, so `External` has none.
+fn layout(usage: Usage) -> i::Layout {
+    match usage {
+        Usage::ColorAttachment => i::Layout::ColorAttachmentOptimal,
+        Usage::DepthAttachment => i::Layout::DepthStencilAttachmentOptimal,
+        Usage::Sampled => i::Layout::ShaderReadOnlyOptimal,
+        Usage::Storage => i::Layout::General,
+        Usage::External => unreachable!(),
+    }
+}
+
+fn access(usage: Usage) -> i::Access {
+    match usage {
+        Usage::ColorAttachment => i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE,
+        Usage::DepthAttachment => {
+            i::Access::DEPTH_STENCIL_ATTACHMENT_READ | i::Access::DEPTH_STENCIL_ATTACHMENT_WRITE
+        }
+        Usage::Sampled => i::Access::SHADER_READ,
+        Usage::Storage => i::Access::SHADER_READ | i::Access::SHADER_WRITE,
+        Usage::External => i::Access::MEMORY_READ | i::Access::MEMORY_WRITE,
+    }
+}
+
+/// Where a pass on `queue` uses a resource in `usage`'s way. Passes on the graphics queue read
+/// and write images in their fragment shaders, and ones on the compute queue in compute shaders.
+fn stages(usage: Usage, queue: Queue) -> PipelineStage {
+    match (usage, queue) {
+        (Usage::ColorAttachment, _) => PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        (Usage::DepthAttachment, _) => PipelineStage::EARLY_FRAGMENT_TESTS | PipelineStage::LATE_FRAGMENT_TESTS,
+        (Usage::Sampled, Queue::Graphics) | (Usage::Storage, Queue::Graphics) => PipelineStage::FRAGMENT_SHADER,
+        (Usage::Sampled, Queue::Compute) | (Usage::Storage, Queue::Compute) => PipelineStage::COMPUTE_SHADER,
+        (Usage::External, _) => queue_stages(queue),
+    }
+}
+
+/// Every stage the graph's passes might use a resource in on `queue`, for waiting on what it
+/// knows nothing about and on the other queue.
+fn queue_stages(queue: Queue) -> PipelineStage {
+    let compute = PipelineStage::DRAW_INDIRECT | PipelineStage::COMPUTE_SHADER | PipelineStage::TRANSFER;
+    match queue {
+        Queue::Graphics => {
+            compute
+                | PipelineStage::VERTEX_INPUT
+                | PipelineStage::VERTEX_SHADER
+                | PipelineStage::FRAGMENT_SHADER
+                | PipelineStage::EARLY_FRAGMENT_TESTS
+                | PipelineStage::LATE_FRAGMENT_TESTS
+                | PipelineStage::COLOR_ATTACHMENT_OUTPUT
+        }
+        Queue::Compute => compute,
+    }
+}
+
+/// Only attachments that are cleared use theirs, but every attachment needs one.
+fn clear_value(load: Load) -> command::ClearValue {
+    match load {
+        Load::ClearDepth(depth) => command::ClearValue::DepthStencil(command::ClearDepthStencil(depth, 0)),
+        Load::ClearColor(color) => command::ClearValue::Color(command::ClearColor::Float(color)),
+        Load::Keep | Load::DontCare => command::ClearValue::Color(command::ClearColor::Float([0.0; 4])),
+    }
+}
@@ -0,0 +1,491 @@
+//! Wrappers around the device resources that need to be destroyed explicitly.
+//!
+//! `hal` doesn't destroy most objects when they go out of scope: you have to hand them back to
+//! the `Device` that created them. These wrappers keep a handle to the device around so that
+//! their `Drop` impls can do that for us, in the right order.
+
+use std::cell::RefCell;
+use std::iter;
+use std::rc::Rc;
+
+use hal::{
+    format as f, image as i, memory, pso,
+    pso::PipelineStage,
+    queue::CommandQueue,
+    window::{ self, AcquireError, Extent2D, SwapchainConfig },
+    Adapter, Backend, Device, General, Submission, Surface, SwapImageIndex,
+};
+
+use winit;
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use attachments::AttachmentImages;
+use color::is_srgb;
+use error::RendererError;
+use frame_sync::{ self, Frame };
+use present::choose_present_mode;
+use texture::COLOR_RANGE;
+
+/// The format of the images headless mode renders into. sRGB like the swapchain formats we
+/// prefer, so a headless frame comes out looking the same as one in a window.
+const OFFSCREEN_FORMAT: f::Format = f::Format::Rgba8Srgb;
+
+/// One offscreen image per frame in flight, so frame `n` always draws into image `n` and never
+/// has to wait for another frame to be done with it.
+const OFFSCREEN_IMAGE_COUNT: usize = frame_sync::DEFAULT_FRAMES_IN_FLIGHT;
+
+/// Picks the size of the swapchain images, either from the surface itself or, if the surface
+/// leaves it up to us, from the current size of the window clamped to what the surface supports.
+fn choose_extent(capabilities: &window::SurfaceCapabilities, window: &winit::Window) -> Extent2D {
+    match capabilities.current_extent {
+        Some(extent) => extent,
+        None => {
+            let window_size = window.get_inner_size().unwrap().to_physical(window.get_hidpi_factor());
+            let mut extent = Extent2D { width: window_size.width as _, height: window_size.height as _ };
+
+            extent.width = extent.width
+                .max(capabilities.extents.start.width)
+                .min(capabilities.extents.end.width);
+            extent.height = extent.height
+                .max(capabilities.extents.start.height)
+                .min(capabilities.extents.end.height);
+            
+            extent
+        }
+    }
+}
+
+/// The swapchain along with the image views we create for each of its backbuffer images.
+///
+/// In headless mode there's no surface to make a swapchain for, so the bundle owns a few
+/// offscreen images instead and hands them out in turn. Chapters draw into them exactly as they
+/// would into swapchain images.
+pub struct SwapchainBundle<B: Backend> {
+    device: Rc<B::Device>,
+    swapchain: Option<B::Swapchain>,
+    frame_images: Vec<(B::Image, B::ImageView)>,
+    /// The allocator and the memory behind each of the offscreen images. Empty for a real
+    /// swapchain, whose images belong to the swapchain.
+    allocator: Option<Rc<RefCell<Allocator<B>>>>,
+    allocations: Vec<Allocation>,
+    /// The offscreen image `acquire_image` hands out next.
+    next_image: usize,
+    format: f::Format,
+    extent: Extent2D,
+    hidpi_factor: f64,
+    present_mode: window::PresentMode,
+    usage: i::Usage,
+}
+
+impl<B: Backend> SwapchainBundle<B> {
+    pub fn new(
+        device: Rc<B::Device>,
+        surface: &mut B::Surface,
+        adapter: &Adapter<B>,
+        window: &winit::Window,
+        preferred_present_modes: &[window::PresentMode],
+    ) -> Result<Self, RendererError> {
+        let mut bundle = SwapchainBundle {
+            device,
+            swapchain: None,
+            frame_images: Vec::new(),
+            allocator: None,
+            allocations: Vec::new(),
+            next_image: 0,
+            format: f::Format::Rgba8Srgb,
+            extent: Extent2D { width: 0, height: 0 },
+            hidpi_factor: 1.0,
+            present_mode: window::PresentMode::Fifo,
+            usage: i::Usage::empty(),
+        };
+        bundle.recreate(surface, adapter, window, preferred_present_modes)?;
+        Ok(bundle)
+    }
+
+    /// Offscreen images of `extent` pixels to draw into in place of a swapchain, for headless
+    /// mode.
+    ///
+    /// The render passes leave them in the `Present` layout like they would swapchain images.
+    /// Nothing ever presents them, but it means the chapters and screenshots don't need to know
+    /// the difference.
+    pub fn offscreen(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        extent: Extent2D,
+    ) -> Result<Self, RendererError> {
+        let usage = i::Usage::COLOR_ATTACHMENT | i::Usage::TRANSFER_SRC;
+        let mut frame_images = Vec::with_capacity(OFFSCREEN_IMAGE_COUNT);
+        let mut allocations = Vec::with_capacity(OFFSCREEN_IMAGE_COUNT);
+        for _ in 0..OFFSCREEN_IMAGE_COUNT {
+            let unbound = device.create_image(
+                i::Kind::D2(extent.width, extent.height, 1, 1),
+                1,
+                OFFSCREEN_FORMAT,
+                i::Tiling::Optimal,
+                usage,
+                i::ViewCapabilities::empty(),
+            )?;
+            let requirements = device.get_image_requirements(&unbound);
+
+            let mut allocator = allocator.borrow_mut();
+            let allocation = allocator
+                .allocate(
+                    &requirements,
+                    memory::Properties::DEVICE_LOCAL,
+                    ResourceKind::Optimal,
+                    MemoryCategory::Attachments,
+                )?;
+            let image = device.bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+            let view = device
+                .create_image_view(&image, i::ViewKind::D2, OFFSCREEN_FORMAT, f::Swizzle::NO, COLOR_RANGE.clone())?;
+
+            frame_images.push((image, view));
+            allocations.push(allocation);
+        }
+
+        Ok(SwapchainBundle {
+            device,
+            swapchain: None,
+            frame_images,
+            allocator: Some(allocator),
+            allocations,
+            next_image: 0,
+            format: OFFSCREEN_FORMAT,
+            extent,
+            hidpi_factor: 1.0,
+            // Nothing waits for vertical blank offscreen
+            present_mode: window::PresentMode::Immediate,
+            usage,
+        })
+    }
+
+    /// Rebuilds the swapchain and image views to match the current state of the surface, using
+    /// the first of `preferred_present_modes` the surface supports. The caller is responsible
+    /// for making sure the gpu is no longer using the old images.
+    pub fn recreate(
+        &mut self,
+        surface: &mut B::Surface,
+        adapter: &Adapter<B>,
+        window: &winit::Window,
+        preferred_present_modes: &[window::PresentMode],
+    ) -> Result<(), RendererError> {
+        for (_, image_view) in self.frame_images.drain(..) {
+            self.device.destroy_image_view(image_view);
+        }
+
+        let (capabilities, formats, presentation_modes) = surface.compatibility(&adapter.physical_device);
+
+        // An sRGB format encodes what the shaders write, which is light in linear space, on
+        // the way to the screen. Without one, only the overlay, HUD, text and tonemapping encode
+        // for themselves, and everything else comes out too dark.
+        let format = formats
+            .map_or(f::Format::Rgba8Srgb, |formats| {
+                formats
+                    .iter()
+                    .find(|&&format| is_srgb(format))
+                    .map(|format| *format)
+                    .unwrap_or_else(|| {
+                        warn!("The surface has no sRGB formats, so colours will be off. Using {:?}", formats[0]);
+                        formats[0]
+                    })
+            });
+
+        let extent = choose_extent(&capabilities, window);
+
+        let presentation_mode = choose_present_mode(&presentation_modes, preferred_present_modes);
+
+        // Copying out of the images is only needed for screenshots, so it's fine if the surface
+        // doesn't allow it
+        let usage = i::Usage::COLOR_ATTACHMENT | (capabilities.usage & i::Usage::TRANSFER_SRC);
+
+        let swap_config = SwapchainConfig::new()
+            .with_color(format)
+            .with_image_count(capabilities.image_count.start)
+            .with_image_usage(usage)
+            .with_mode(presentation_mode);
+
+        let (swapchain, backbuffer) = self.device.create_swapchain(
+            surface,
+            swap_config,
+            self.swapchain.take(),
+            &extent,
+        );
+
+        self.swapchain = Some(swapchain);
+        self.format = format;
+        self.extent = extent;
+        self.hidpi_factor = window.get_hidpi_factor();
+        self.present_mode = presentation_mode;
+        self.usage = usage;
+
+        let device = &self.device;
+        self.frame_images = match backbuffer {
+            window::Backbuffer::Images(images) => {
+                images.into_iter()
+                    .map(|image| {
+                        let image_view = device.create_image_view(
+                            &image,
+                            i::ViewKind::D2,
+                            format,
+                            f::Swizzle::NO,
+                            i::SubresourceRange {
+                                aspects: f::Aspects::COLOR,
+                                levels: 0..1,
+                                layers: 0..1,
+                            }
+                        )?;
+                        Ok((image, image_view))
+                    })
+                    .collect::<Result<_, RendererError>>()?
+            },
+            window::Backbuffer::Framebuffer(_) => {
+                return Err(RendererError::UnsupportedBackbuffer { kind: "framebuffer" });
+            }
+        };
+
+        Ok(())
+    }
+
+    /// The swapchain itself. Panics for offscreen images, which have none.
+    pub fn swapchain(&mut self) -> &mut B::Swapchain {
+        self.swapchain.as_mut().unwrap()
+    }
+
+    /// Whether these are offscreen images rather than a real swapchain.
+    pub fn is_offscreen(&self) -> bool {
+        self.swapchain.is_none()
+    }
+
+    /// Gets the index of the next image to draw into. `frame`'s `image_available` semaphore is
+    /// signalled once it's safe to.
+    pub fn acquire_image(
+        &mut self,
+        frame: &mut Frame<B>,
+        queue: &mut CommandQueue<B, General>,
+    ) -> Result<SwapImageIndex, AcquireError> {
+        match self.swapchain {
+            Some(ref mut swapchain) => frame.acquire_image(swapchain),
+            None => {
+                // Frames wait on `image_available` before drawing, so it has to be signalled
+                // even though there's no presentation engine to wait for
+                queue.submit(Submission::new().signal(&[frame.image_available()]), None);
+                let image_index = self.next_image;
+                self.next_image = (self.next_image + 1) % self.frame_images.len();
+                Ok(image_index as SwapImageIndex)
+            }
+        }
+    }
+
+    /// Presents `image_index` once `frame`'s commands (which must signal `render_finished`) are
+    /// done. Offscreen images aren't shown anywhere, so for those this only waits on
+    /// `render_finished` so that it's unsignalled again for the next time the frame is used.
+    pub fn present(
+        &mut self,
+        frame: &Frame<B>,
+        queue: &mut CommandQueue<B, General>,
+        image_index: SwapImageIndex,
+    ) -> Result<(), ()> {
+        match self.swapchain {
+            Some(ref mut swapchain) => frame.present(swapchain, queue, image_index),
+            None => {
+                let submission = Submission::new()
+                    .wait_on(&[(frame.render_finished(), PipelineStage::BOTTOM_OF_PIPE)]);
+                queue.submit(submission, None);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn frame_images(&self) -> &[(B::Image, B::ImageView)] {
+        &self.frame_images
+    }
+
+    pub fn format(&self) -> f::Format {
+        self.format
+    }
+
+    pub fn extent(&self) -> Extent2D {
+        self.extent
+    }
+
+    /// The scale factor of the monitor the window was on when the swapchain was created.
+    /// Anything sized in logical pixels, like UI, should be multiplied by this to get the size in
+    /// swapchain pixels.
+    pub fn hidpi_factor(&self) -> f64 {
+        self.hidpi_factor
+    }
+
+    /// The size of the swapchain images in logical pixels.
+    pub fn logical_size(&self) -> winit::dpi::LogicalSize {
+        winit::dpi::PhysicalSize::new(self.extent.width as f64, self.extent.height as f64)
+            .to_logical(self.hidpi_factor)
+    }
+
+    /// The present mode the swapchain was actually created with.
+    pub fn present_mode(&self) -> window::PresentMode {
+        self.present_mode
+    }
+
+    /// Whether the swapchain images can be copied from, which screenshots need.
+    pub fn can_copy_from(&self) -> bool {
+        self.usage.contains(i::Usage::TRANSFER_SRC)
+    }
+
+    /// A viewport (and scissor rect, via `viewport.rect`) covering the whole swapchain image.
+    pub fn viewport(&self) -> pso::Viewport {
+        pso::Viewport {
+            rect: pso::Rect {
+                x: 0,
+                y: 0,
+                w: self.extent.width as _,
+                h: self.extent.height as _,
+            },
+            depth: 0.0..1.0,
+        }
+    }
+}
+
+impl<B: Backend> Drop for SwapchainBundle<B> {
+    fn drop(&mut self) {
+        // The image views reference the swapchain's images, so they have to go first
+        for (image, image_view) in self.frame_images.drain(..) {
+            self.device.destroy_image_view(image_view);
+            // Offscreen images are ours to destroy, swapchain images aren't
+            if self.allocator.is_some() {
+                self.device.destroy_image(image);
+            }
+        }
+        if let Some(allocator) = self.allocator.take() {
+            let mut allocator = allocator.borrow_mut();
+            for allocation in self.allocations.drain(..) {
+                allocator.free(allocation);
+            }
+        }
+        if let Some(swapchain) = self.swapchain.take() {
+            self.device.destroy_swapchain(swapchain);
+        }
+    }
+}
+
+/// One framebuffer per swapchain image, each with that image's view as its first attachment,
+/// followed by the matching view from any extra per-frame attachments.
+pub struct Framebuffers<B: Backend> {
+    device: Rc<B::Device>,
+    framebuffers: Vec<B::Framebuffer>,
+}
+
+impl<B: Backend> Framebuffers<B> {
+    pub fn new(
+        device: Rc<B::Device>,
+        render_pass: &B::RenderPass,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<Self, RendererError> {
+        let mut framebuffers = Framebuffers {
+            device,
+            framebuffers: Vec::new(),
+        };
+        framebuffers.recreate(render_pass, swapchain)?;
+        Ok(framebuffers)
+    }
+
+    /// Like `new`, but with one view from each of `extra` attached after the swapchain image.
+    pub fn with_attachments(
+        device: Rc<B::Device>,
+        render_pass: &B::RenderPass,
+        swapchain: &SwapchainBundle<B>,
+        extra: &[&AttachmentImages<B>],
+    ) -> Result<Self, RendererError> {
+        let mut framebuffers = Framebuffers {
+            device,
+            framebuffers: Vec::new(),
+        };
+        framebuffers.recreate_with_attachments(render_pass, swapchain, extra)?;
+        Ok(framebuffers)
+    }
+
+    /// Rebuilds the framebuffers after `swapchain` has been recreated.
+    pub fn recreate(
+        &mut self,
+        render_pass: &B::RenderPass,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<(), RendererError> {
+        self.recreate_with_attachments(render_pass, swapchain, &[])
+    }
+
+    /// Like `with_attachments`, but with only the views from `attachments` and no swapchain
+    /// image, for passes that draw somewhere else first. There's still one framebuffer per
+    /// swapchain image.
+    pub fn offscreen(
+        device: Rc<B::Device>,
+        render_pass: &B::RenderPass,
+        swapchain: &SwapchainBundle<B>,
+        attachments: &[&AttachmentImages<B>],
+    ) -> Result<Self, RendererError> {
+        let mut framebuffers = Framebuffers {
+            device,
+            framebuffers: Vec::new(),
+        };
+        framebuffers.recreate_offscreen(render_pass, swapchain, attachments)?;
+        Ok(framebuffers)
+    }
+
+    /// Rebuilds the framebuffers after `swapchain` and the `extra` attachments have been
+    /// recreated.
+    pub fn recreate_with_attachments(
+        &mut self,
+        render_pass: &B::RenderPass,
+        swapchain: &SwapchainBundle<B>,
+        extra: &[&AttachmentImages<B>],
+    ) -> Result<(), RendererError> {
+        self.build(render_pass, swapchain, true, extra)
+    }
+
+    /// Rebuilds framebuffers made by `offscreen` after `swapchain` and `attachments` have been
+    /// recreated.
+    pub fn recreate_offscreen(
+        &mut self,
+        render_pass: &B::RenderPass,
+        swapchain: &SwapchainBundle<B>,
+        attachments: &[&AttachmentImages<B>],
+    ) -> Result<(), RendererError> {
+        self.build(render_pass, swapchain, false, attachments)
+    }
+
+    fn build(
+        &mut self,
+        render_pass: &B::RenderPass,
+        swapchain: &SwapchainBundle<B>,
+        with_swapchain_image: bool,
+        extra: &[&AttachmentImages<B>],
+    ) -> Result<(), RendererError> {
+        for framebuffer in self.framebuffers.drain(..) {
+            self.device.destroy_framebuffer(framebuffer);
+        }
+
+        let extent = swapchain.extent().to_extent();
+        let device = &self.device;
+        self.framebuffers = swapchain.frame_images()
+            .iter()
+            .enumerate()
+            .map(|(index, &(_, ref image_view))| {
+                let attachments = iter::once(image_view)
+                    .filter(|_| with_swapchain_image)
+                    .chain(extra.iter().map(|attachment| attachment.view(index)));
+                Ok(device.create_framebuffer(render_pass, attachments, extent)?)
+            })
+            .collect::<Result<_, RendererError>>()?;
+        Ok(())
+    }
+
+    pub fn get(&self, image_index: SwapImageIndex) -> &B::Framebuffer {
+        &self.framebuffers[image_index as usize]
+    }
+}
+
+impl<B: Backend> Drop for Framebuffers<B> {
+    fn drop(&mut self) {
+        for framebuffer in self.framebuffers.drain(..) {
+            self.device.destroy_framebuffer(framebuffer);
+        }
+    }
+}
@@ -0,0 +1,232 @@
+//! Samplers, shared between everything that samples the same way.
+//!
+//! Most of what gets sampled is sampled in one of a handful of ways: filtered linearly and
+//! clamped to the edge, say, or not filtered at all. Rather than each texture and attachment
+//! making a sampler of its own, they ask the `SamplerCache` on the `GfxContext` for one with
+//! the settings they need, described by a `SamplerDesc`, and get the same one back as anything
+//! else that asked for those settings.
+//!
+//! Anisotropic filtering samples mipmapped textures along the direction they're squashed in
+//! when they're seen at a glancing angle, which keeps the ground sharp into the distance where
+//! trilinear filtering alone blurs it. Not every adapter can do it, and those that can only go
+//! so far, so the cache clamps what's asked for to what the adapter supports.
+
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use hal::{
+    image as i, pso,
+    Adapter, Backend, Device, Features, PhysicalDevice,
+};
+
+/// How mipmapped textures, like the block atlas, are filtered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextureFiltering {
+    /// The nearest texel of the nearest mip, for the sharpest, blockiest look.
+    Nearest,
+    /// Blended between the four nearest texels of the nearest mip.
+    Bilinear,
+    /// Blended between the two nearest mips as well, so there's no seam where one takes over
+    /// from the next.
+    Trilinear,
+    /// Trilinear, with up to `max_anisotropy` samples along the direction the texture is
+    /// squashed in.
+    Anisotropic,
+}
+
+impl TextureFiltering {
+    pub const ALL: [TextureFiltering; 4] = [
+        TextureFiltering::Nearest,
+        TextureFiltering::Bilinear,
+        TextureFiltering::Trilinear,
+        TextureFiltering::Anisotropic,
+    ];
+}
+
+impl Default for TextureFiltering {
+    fn default() -> Self {
+        TextureFiltering::Anisotropic
+    }
+}
+
+impl fmt::Display for TextureFiltering {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            TextureFiltering::Nearest => "nearest",
+            TextureFiltering::Bilinear => "bilinear",
+            TextureFiltering::Trilinear => "trilinear",
+            TextureFiltering::Anisotropic => "anisotropic",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for TextureFiltering {
+    type Err = String;
+
+    fn from_str(name: &str) -> ::std::result::Result<TextureFiltering, String> {
+        TextureFiltering::ALL
+            .iter()
+            .cloned()
+            .find(|filtering| filtering.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                format!("Unknown texture filtering {:?}, expected nearest, bilinear, trilinear or anisotropic", name)
+            })
+    }
+}
+
+/// Everything about how a sampler samples, and what the cache looks samplers up by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SamplerDesc {
+    /// Between texels, both magnifying and minifying.
+    pub filter: i::Filter,
+    /// Between mips.
+    pub mip_filter: i::Filter,
+    /// Past the edges, the same for every axis.
+    pub wrap: i::WrapMode,
+    /// How many samples anisotropic filtering takes at most. 1 turns it off.
+    pub anisotropy: u8,
+    /// If set, sampling compares against a reference value rather than returning the texels.
+    pub comparison: Option<pso::Comparison>,
+}
+
+impl SamplerDesc {
+    /// Filtered with `filter` within and between mips, and wrapped with `wrap`.
+    pub fn new(filter: i::Filter, wrap: i::WrapMode) -> Self {
+        SamplerDesc {
+            filter,
+            mip_filter: filter,
+            wrap,
+            anisotropy: 1,
+            comparison: None,
+        }
+    }
+
+    /// For a mipmapped texture, filtered the way the settings say, with anisotropic filtering
+    /// going up to `max_anisotropy` samples.
+    pub fn for_filtering(filtering: TextureFiltering, wrap: i::WrapMode, max_anisotropy: u32) -> Self {
+        let max_anisotropy = max_anisotropy.max(1).min(u8::max_value() as u32) as u8;
+        let (filter, mip_filter, anisotropy) = match filtering {
+            TextureFiltering::Nearest => (i::Filter::Nearest, i::Filter::Nearest, 1),
+            TextureFiltering::Bilinear => (i::Filter::Linear, i::Filter::Nearest, 1),
+            TextureFiltering::Trilinear => (i::Filter::Linear, i::Filter::Linear, 1),
+            TextureFiltering::Anisotropic => (i::Filter::Linear, i::Filter::Linear, max_anisotropy),
+        };
+        SamplerDesc { filter, mip_filter, wrap, anisotropy, comparison: None }
+    }
+
+    /// The same, but comparing against a reference value with `comparison`, like shadow maps
+    /// are sampled.
+    pub fn with_comparison(self, comparison: pso::Comparison) -> Self {
+        SamplerDesc { comparison: Some(comparison), ..self }
+    }
+
+    fn info(&self) -> i::SamplerInfo {
+        let mut info = i::SamplerInfo::new(self.filter, self.wrap);
+        info.mip_filter = self.mip_filter;
+        info.comparison = self.comparison;
+        info.anisotropic = if self.anisotropy > 1 {
+            i::Anisotropic::On(self.anisotropy)
+        } else {
+            i::Anisotropic::Off
+        };
+        info
+    }
+}
+
+/// How many samples anisotropic filtering can take, for an adapter whose limit is `limit`, or 1
+/// if it can't do it at all. The limit is a float, but only whole numbers of samples make sense.
+pub fn supported_anisotropy(supported: bool, limit: f32) -> u8 {
+    if supported {
+        limit.max(1.0).min(u8::max_value() as f32) as u8
+    } else {
+        1
+    }
+}
+
+/// Every sampler made so far, made on demand and kept until the device goes away.
+pub struct SamplerCache<B: Backend> {
+    device: Rc<B::Device>,
+    max_anisotropy: u8,
+    samplers: Vec<(SamplerDesc, Rc<B::Sampler>)>,
+}
+
+impl<B: Backend> SamplerCache<B> {
+    /// An empty cache for `device`, which was opened on `adapter`.
+    pub fn new(device: Rc<B::Device>, adapter: &Adapter<B>) -> Self {
+        let max_anisotropy = supported_anisotropy(
+            adapter.physical_device.features().contains(Features::SAMPLER_ANISOTROPY),
+            adapter.physical_device.limits().max_sampler_anisotropy,
+        );
+        debug!("Anisotropic filtering: up to {}x", max_anisotropy);
+        SamplerCache {
+            device,
+            max_anisotropy,
+            samplers: Vec::new(),
+        }
+    }
+
+    /// The most samples anisotropic filtering can take on this adapter. Anything asked for past
+    /// it is clamped down to it.
+    pub fn max_anisotropy(&self) -> u8 {
+        self.max_anisotropy
+    }
+
+    /// A sampler that samples as `desc` says, made the first time it's asked for. There are only
+    /// ever a handful, so looking through them all is quick enough.
+    pub fn get(&mut self, desc: SamplerDesc) -> Rc<B::Sampler> {
+        let desc = SamplerDesc { anisotropy: desc.anisotropy.max(1).min(self.max_anisotropy), ..desc };
+        if let Some(&(_, ref sampler)) = self.samplers.iter().find(|&&(cached, _)| cached == desc) {
+            return sampler.clone();
+        }
+        let sampler = Rc::new(self.device.create_sampler(desc.info()));
+        self.samplers.push((desc, sampler.clone()));
+        sampler
+    }
+}
+
+impl<B: Backend> Drop for SamplerCache<B> {
+    fn drop(&mut self) {
+        for (desc, sampler) in self.samplers.drain(..) {
+            match Rc::try_unwrap(sampler) {
+                Ok(sampler) => self.device.destroy_sampler(sampler),
+                Err(_) => warn!("A {:?} sampler is still in use, so it can't be destroyed", desc),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filtering_parses_from_its_names() {
+        for &filtering in TextureFiltering::ALL.iter() {
+            assert_eq!(filtering.to_string().parse(), Ok(filtering));
+        }
+        assert_eq!("Trilinear".parse(), Ok(TextureFiltering::Trilinear));
+        assert!("linear".parse::<TextureFiltering>().is_err());
+    }
+
+    #[test]
+    fn only_anisotropic_filtering_takes_more_than_one_sample() {
+        for &filtering in TextureFiltering::ALL.iter() {
+            let desc = SamplerDesc::for_filtering(filtering, i::WrapMode::Clamp, 16);
+            let expected = if filtering == TextureFiltering::Anisotropic { 16 } else { 1 };
+            assert_eq!(desc.anisotropy, expected);
+        }
+        let bilinear = SamplerDesc::for_filtering(TextureFiltering::Bilinear, i::WrapMode::Tile, 16);
+        assert_eq!((bilinear.filter, bilinear.mip_filter), (i::Filter::Linear, i::Filter::Nearest));
+    }
+
+    #[test]
+    fn anisotropy_is_clamped_to_what_the_adapter_supports() {
+        assert_eq!(supported_anisotropy(true, 16.0), 16);
+        assert_eq!(supported_anisotropy(true, 8.5), 8);
+        assert_eq!(supported_anisotropy(true, 0.0), 1);
+        assert_eq!(supported_anisotropy(false, 16.0), 1);
+    }
+}
@@ -0,0 +1,156 @@
+//! Saving the contents of the swapchain to a PNG.
+//!
+//! The swapchain image is copied into a host visible buffer once the frame has been drawn but
+//! before it's presented, since after that it belongs to the presentation engine. 8 bit sRGB and
+//! UNORM images both hold the values that end up on screen, which is what a PNG expects, so
+//! the only conversion needed is putting the channels in RGBA order.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use hal::{
+    buffer, command, format as f, image as i, memory,
+    pso::PipelineStage,
+    Backend, PhysicalDevice, SwapImageIndex,
+};
+
+use image;
+
+use allocator::MemoryCategory;
+use buffer::DeviceBuffer;
+use context::GfxContext;
+use error::{ RendererError, Result };
+use resources::SwapchainBundle;
+use texture::COLOR_RANGE;
+
+/// Where screenshots are saved, relative to the working directory.
+pub const SCREENSHOT_DIR: &str = "screenshots";
+
+const PIXEL_SIZE: u32 = 4;
+
+/// Copies swapchain image `image_index` to a new, timestamped PNG in `SCREENSHOT_DIR` and returns
+/// its path. Call this after submitting the frame's commands and before presenting it. Waits for
+/// the gpu to go idle.
+///
+/// In headless mode the PNG is written to `--output` instead, replacing whatever was there.
+pub fn capture<B: Backend>(
+    context: &mut GfxContext<B>,
+    swapchain: &SwapchainBundle<B>,
+    image_index: SwapImageIndex,
+) -> Result<PathBuf> {
+    if !swapchain.can_copy_from() {
+        return Err(RendererError::Screenshot(
+            "the surface doesn't allow copying from swapchain images".to_owned(),
+        ));
+    }
+    let format = swapchain.format();
+    let bgra = match format {
+        f::Format::Rgba8Srgb | f::Format::Rgba8Unorm => false,
+        f::Format::Bgra8Srgb | f::Format::Bgra8Unorm => true,
+        _ => return Err(RendererError::Screenshot(format!("can't read back {:?} images", format))),
+    };
+
+    let extent = swapchain.extent();
+    let image = &swapchain.frame_images()[image_index as usize].0;
+
+    // As with texture uploads, each row in the buffer has to start at a multiple of the copy
+    // pitch alignment
+    let limits = context.adapter.physical_device.limits();
+    let row_alignment_mask = limits.min_buffer_copy_pitch_alignment.max(1) as u32 - 1;
+    let row_pitch = (extent.width * PIXEL_SIZE + row_alignment_mask) & !row_alignment_mask;
+
+    let readback = DeviceBuffer::new(
+        context.device.clone(),
+        context.allocator.clone(),
+        (row_pitch * extent.height) as u64,
+        buffer::Usage::TRANSFER_DST,
+        memory::Properties::CPU_VISIBLE,
+        MemoryCategory::Transient,
+    )?;
+
+    // The frame's commands have to be finished before we can copy the image
+    context.wait_idle()?;
+    context.submit_one_shot(|command_buffer| {
+        command_buffer.pipeline_barrier(
+            PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (i::Access::COLOR_ATTACHMENT_WRITE, i::Layout::Present)
+                    ..(i::Access::TRANSFER_READ, i::Layout::TransferSrcOptimal),
+                target: image,
+                range: COLOR_RANGE.clone(),
+            }],
+        );
+
+        command_buffer.copy_image_to_buffer(
+            image,
+            i::Layout::TransferSrcOptimal,
+            readback.buffer(),
+            &[command::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_width: row_pitch / PIXEL_SIZE,
+                buffer_height: extent.height,
+                image_layers: i::SubresourceLayers {
+                    aspects: f::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: i::Offset { x: 0, y: 0, z: 0 },
+                image_extent: i::Extent { width: extent.width, height: extent.height, depth: 1 },
+            }],
+        );
+
+        // Back to the layout the render pass left it in, ready to be presented
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::BOTTOM_OF_PIPE,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (i::Access::TRANSFER_READ, i::Layout::TransferSrcOptimal)
+                    ..(i::Access::empty(), i::Layout::Present),
+                target: image,
+                range: COLOR_RANGE.clone(),
+            }],
+        );
+
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::HOST,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Buffer {
+                states: buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                target: readback.buffer(),
+            }],
+        );
+    })?;
+
+    let data = readback.read::<u8>()?;
+    let mut pixels = Vec::with_capacity((extent.width * extent.height * PIXEL_SIZE) as usize);
+    for row in data.chunks(row_pitch as usize).take(extent.height as usize) {
+        for pixel in row[..(extent.width * PIXEL_SIZE) as usize].chunks(PIXEL_SIZE as usize) {
+            if bgra {
+                pixels.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            } else {
+                pixels.extend_from_slice(&pixel[..3]);
+            }
+            // Whatever ends up in the swapchain's alpha channel isn't shown, so don't save it
+            pixels.push(255);
+        }
+    }
+
+    let path = if context.is_headless() {
+        context.args.output.clone()
+    } else {
+        fs::create_dir_all(SCREENSHOT_DIR)?;
+        screenshot_path()
+    };
+    image::save_buffer(&path, &pixels, extent.width, extent.height, image::RGBA(8))
+        .map_err(|err| RendererError::Screenshot(format!("couldn't write {}: {}", path.display(), err)))?;
+    Ok(path)
+}
+
+/// A path in `SCREENSHOT_DIR` named after the current time, down to the millisecond.
+fn screenshot_path() -> PathBuf {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let name = format!("screenshot-{}-{:03}.png", now.as_secs(), now.subsec_millis());
+    PathBuf::from(SCREENSHOT_DIR).join(name)
+}
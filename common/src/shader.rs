@@ -0,0 +1,124 @@
+//! Loading compiled shaders, and reloading them when their source changes.
+
+use std::collections::HashMap;
+use std::path::{ Path, PathBuf };
+use std::sync::mpsc::{ channel, Receiver };
+use std::time::Duration;
+
+use hal::{ Backend, Device };
+
+use notify::{ self, DebouncedEvent, RecommendedWatcher, RecursiveMode, Watcher };
+use shader_build;
+
+use error::RendererError;
+
+/// Creates a shader module from SPIR-V, as generated by the chapters' build scripts.
+pub fn create_shader_module<B: Backend>(device: &B::Device, spirv: &[u8]) -> Result<B::ShaderModule, RendererError> {
+    Ok(device.create_shader_module(spirv)?)
+}
+
+/// The current SPIR-V for a chapter's shaders, keyed by file name (e.g. `"triangle.vert"`).
+///
+/// It starts out with the shaders compiled by the build script, and if the chapter's `shaders/`
+/// directory can be found at runtime it is watched for changes. `poll_changes` recompiles any
+/// shader that was modified and tells the caller which ones changed, so that it can rebuild the
+/// pipelines that use them.
+pub struct ShaderSet {
+    spirv: HashMap<String, Vec<u8>>,
+    watcher: Option<(RecommendedWatcher, Receiver<DebouncedEvent>)>,
+}
+
+impl ShaderSet {
+    /// `builtin` is the `ALL` table from the generated `shaders.rs`, and `dir` is the directory
+    /// the shaders were compiled from, usually `concat!(env!("CARGO_MANIFEST_DIR"), "/shaders")`.
+    pub fn new<P: AsRef<Path>>(dir: P, builtin: &[(&str, &[u8])]) -> Self {
+        let spirv = builtin
+            .iter()
+            .map(|&(name, spirv)| (name.to_owned(), spirv.to_vec()))
+            .collect();
+
+        let watcher = match watch(dir.as_ref()) {
+            Ok(watcher) => {
+                info!("Watching {} for shader changes", dir.as_ref().display());
+                Some(watcher)
+            }
+            Err(err) => {
+                warn!("Shader hot-reloading is disabled: {}", err);
+                None
+            }
+        };
+
+        ShaderSet { spirv, watcher }
+    }
+
+    /// The SPIR-V for the shader called `name`. Panics if there's no such shader.
+    pub fn get(&self, name: &str) -> &[u8] {
+        match self.spirv.get(name) {
+            Some(spirv) => spirv,
+            None => panic!("No shader named {}", name),
+        }
+    }
+
+    /// Recompiles any shaders that have changed on disk since the last call, or that might
+    /// include a file that has, and returns their names. Shaders that fail to compile print the
+    /// error and keep their previous SPIR-V.
+    pub fn poll_changes(&mut self) -> Vec<String> {
+        let mut changed_paths = Vec::new();
+        if let Some((_, ref events)) = self.watcher {
+            while let Ok(event) = events.try_recv() {
+                match event {
+                    DebouncedEvent::Create(path)
+                    | DebouncedEvent::Write(path)
+                    | DebouncedEvent::Rename(_, path) => changed_paths.push(path),
+                    _ => (),
+                }
+            }
+        }
+        changed_paths.sort();
+        changed_paths.dedup();
+
+        // `.glsl` files are included by shaders rather than compiled, so any of them could use one
+        let mut recompile = Vec::new();
+        for path in changed_paths {
+            let name = match path.file_name() {
+                Some(name) => name.to_string_lossy().into_owned(),
+                None => continue,
+            };
+            if self.spirv.contains_key(&name) {
+                recompile.push((name, path));
+            } else if path.extension().map_or(false, |extension| extension == "glsl") {
+                let dir = path.parent().unwrap_or_else(|| Path::new("."));
+                recompile.extend(self.spirv.keys().map(|name| (name.clone(), dir.join(name))));
+            }
+        }
+        recompile.sort();
+        recompile.dedup();
+
+        let mut changed = Vec::new();
+        for (name, path) in recompile {
+            match shader_build::compile_file(&path) {
+                Ok(spirv) => {
+                    info!("Reloaded shader {}", name);
+                    self.spirv.insert(name.clone(), spirv);
+                    changed.push(name);
+                }
+                Err(err) => error!("Failed to reload shader {}", err),
+            }
+        }
+        changed
+    }
+}
+
+pub(crate) fn watch(dir: &Path) -> Result<(RecommendedWatcher, Receiver<DebouncedEvent>), String> {
+    let dir: PathBuf = dir.canonicalize()
+        .map_err(|err| format!("can't find {}: {}", dir.display(), err))?;
+
+    let (sender, receiver) = channel();
+    let mut watcher = notify::watcher(sender, Duration::from_millis(200))
+        .map_err(|err| err.to_string())?;
+    watcher
+        .watch(&dir, RecursiveMode::NonRecursive)
+        .map_err(|err| err.to_string())?;
+
+    Ok((watcher, receiver))
+}
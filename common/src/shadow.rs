@@ -0,0 +1,445 @@
+//! Shadows from the sun, drawn with cascaded shadow maps.
+//!
+//! Before the main pass the world is drawn again from the sun's point of view, into depth-only
+//! images covering the part of the world the camera can see. The main pass then looks up each
+//! fragment's position in them: anything further from the sun than the depth stored there is
+//! behind something else, and in shadow.
+//!
+//! One map stretched over everything in view would spend as many texels on the distance as on
+//! the ground at the camera's feet, where shadows are looked at most closely. So the view is cut
+//! into `CASCADE_COUNT` slices by distance, each a few times longer than the one before, and
+//! each gets its own map fitted around it. Up close that puts a texel on a fraction of a block,
+//! and far away one covers several, which is about what they each take up on screen.
+//! `ShadowMap` owns the maps, as the layers of one image, along with the render pass and
+//! framebuffers that draw into them and the comparison sampler the main pass reads them with.
+//! `fit_cascades` works out where each one goes.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use hal::{
+    format as f, image as i, memory, pso,
+    Backend, Device, PhysicalDevice,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use camera::Projection;
+use context::GfxContext;
+use depth::{ choose_shadow_format, depth_range };
+use error::Result;
+use math::{ look_along, orthographic, InnerSpace, Mat4, SquareMatrix, Vec3, Vec4, HAL_CLIP_SPACE };
+use pass::create_shadow_render_pass;
+use samplers::SamplerDesc;
+
+/// How many slices the view is cut into, each with its own shadow map. The shaders have this
+/// many as well.
+pub const CASCADE_COUNT: usize = 4;
+
+/// How the slices are spaced, from 0 for all the same length to 1 for each the same number of
+/// times longer than the one before. Evenly spaced slices waste the first one on a stretch
+/// that's mostly behind the near plane, and fully logarithmic ones leave the first few blocks
+/// in front of the camera with a whole slice each.
+const CASCADE_SPLIT_LAMBDA: f32 = 0.75;
+
+/// One slice of the view and the shadow map fitted around it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Cascade {
+    /// From world space to this cascade's shadow map.
+    pub view_projection: Mat4,
+    /// How far along the camera's view the slice ends. It starts where the one before ended.
+    pub far: f32,
+}
+
+/// The parts of a shadow map that depend on its resolution.
+struct ShadowTarget<B: Backend> {
+    image: B::Image,
+    /// All of the layers, for sampling.
+    view: B::ImageView,
+    /// One view and framebuffer per layer, for drawing into.
+    layer_views: Vec<B::ImageView>,
+    framebuffers: Vec<B::Framebuffer>,
+    allocation: Allocation,
+}
+
+/// A square depth image with a layer for each cascade of the sun's shadows, and what's needed
+/// to draw into it and sample it.
+pub struct ShadowMap<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    format: f::Format,
+    resolution: u32,
+    render_pass: Option<B::RenderPass>,
+    sampler: Rc<B::Sampler>,
+    target: Option<ShadowTarget<B>>,
+}
+
+impl<B: Backend> ShadowMap<B> {
+    /// `CASCADE_COUNT` `resolution` by `resolution` shadow maps, in the best format from
+    /// `depth::choose_shadow_format`. Where the adapter can filter that format, the comparisons
+    /// against the four nearest texels are blended together, which smooths the edges of shadows
+    /// for free.
+    pub fn new(context: &GfxContext<B>, resolution: u32) -> Result<Self> {
+        let device = context.device.clone();
+        let format = choose_shadow_format(&context.adapter)?;
+        let linear = context.adapter.physical_device
+            .format_properties(Some(format))
+            .optimal_tiling
+            .contains(f::ImageFeature::SAMPLED_LINEAR);
+        debug!("Shadow map format: {:?}, {} filtering", format, if linear { "linear" } else { "nearest" });
+
+        let render_pass = create_shadow_render_pass::<B>(&device, format);
+
+        let filter = if linear { i::Filter::Linear } else { i::Filter::Nearest };
+        // Sampling gives how much of the texels are at least as far from the sun as the
+        // reference depth, rather than the depth itself
+        let sampler = context.samplers.borrow_mut().get(
+            SamplerDesc::new(filter, i::WrapMode::Clamp).with_comparison(pso::Comparison::LessEqual),
+        );
+
+        let mut shadow_map = ShadowMap {
+            device,
+            allocator: context.allocator.clone(),
+            format,
+            resolution: 0,
+            render_pass: Some(render_pass),
+            sampler,
+            target: None,
+        };
+        shadow_map.set_resolution(resolution)?;
+        Ok(shadow_map)
+    }
+
+    /// Rebuilds the maps at a new resolution, if it's changed. The old ones are destroyed
+    /// straight away, so the device has to be idle, and descriptor sets need pointing at the new
+    /// `view`.
+    pub fn set_resolution(&mut self, resolution: u32) -> Result<()> {
+        let resolution = resolution.max(1);
+        if self.target.is_some() && resolution == self.resolution {
+            return Ok(());
+        }
+        self.destroy_target();
+
+        let unbound = self.device.create_image(
+            i::Kind::D2(resolution, resolution, CASCADE_COUNT as i::Layer, 1),
+            1,
+            self.format,
+            i::Tiling::Optimal,
+            i::Usage::DEPTH_STENCIL_ATTACHMENT | i::Usage::SAMPLED,
+            i::ViewCapabilities::empty(),
+        )?;
+        let requirements = self.device.get_image_requirements(&unbound);
+
+        let (image, allocation) = {
+            let mut allocator = self.allocator.borrow_mut();
+            let allocation = allocator
+                .allocate(
+                    &requirements,
+                    memory::Properties::DEVICE_LOCAL,
+                    ResourceKind::Optimal,
+                    MemoryCategory::Attachments,
+                )?;
+            let image = self.device
+                .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+            (image, allocation)
+        };
+        let format = self.format;
+        let layers = |layers| i::SubresourceRange { layers, ..depth_range(format) };
+        let view = self.device.create_image_view(
+            &image,
+            i::ViewKind::D2Array,
+            format,
+            f::Swizzle::NO,
+            layers(0..CASCADE_COUNT as i::Layer),
+        )?;
+        let mut layer_views = Vec::with_capacity(CASCADE_COUNT);
+        let mut framebuffers = Vec::with_capacity(CASCADE_COUNT);
+        for layer in 0..CASCADE_COUNT as i::Layer {
+            let layer_view = self.device.create_image_view(
+                &image,
+                i::ViewKind::D2,
+                format,
+                f::Swizzle::NO,
+                layers(layer..layer + 1),
+            )?;
+            let framebuffer = self.device.create_framebuffer(
+                self.render_pass(),
+                Some(&layer_view),
+                i::Extent { width: resolution, height: resolution, depth: 1 },
+            )?;
+            layer_views.push(layer_view);
+            framebuffers.push(framebuffer);
+        }
+
+        self.resolution = resolution;
+        self.target = Some(ShadowTarget { image, view, layer_views, framebuffers, allocation });
+        Ok(())
+    }
+
+    pub fn format(&self) -> f::Format {
+        self.format
+    }
+
+    pub fn resolution(&self) -> u32 {
+        self.resolution
+    }
+
+    pub fn render_pass(&self) -> &B::RenderPass {
+        self.render_pass.as_ref().unwrap()
+    }
+
+    /// The framebuffer for drawing `cascade`'s map.
+    pub fn framebuffer(&self, cascade: usize) -> &B::Framebuffer {
+        &self.target.as_ref().unwrap().framebuffers[cascade]
+    }
+
+    /// The view of every cascade's map to bind for sampling, as a `texture2DArray`. It's in
+    /// `ShaderReadOnlyOptimal` once the shadow passes are done.
+    pub fn view(&self) -> &B::ImageView {
+        &self.target.as_ref().unwrap().view
+    }
+
+    /// Compares rather than reading depths, so it has to be bound to a `samplerShadow`.
+    pub fn sampler(&self) -> &B::Sampler {
+        &self.sampler
+    }
+
+    /// A viewport covering the whole of a map.
+    pub fn viewport(&self) -> pso::Viewport {
+        let size = self.resolution as i16;
+        pso::Viewport {
+            rect: pso::Rect { x: 0, y: 0, w: size, h: size },
+            depth: 0.0..1.0,
+        }
+    }
+
+    fn destroy_target(&mut self) {
+        if let Some(target) = self.target.take() {
+            for framebuffer in target.framebuffers {
+                self.device.destroy_framebuffer(framebuffer);
+            }
+            for layer_view in target.layer_views {
+                self.device.destroy_image_view(layer_view);
+            }
+            self.device.destroy_image_view(target.view);
+            self.device.destroy_image(target.image);
+            self.allocator.borrow_mut().free(target.allocation);
+        }
+    }
+}
+
+impl<B: Backend> Drop for ShadowMap<B> {
+    fn drop(&mut self) {
+        self.destroy_target();
+        if let Some(render_pass) = self.render_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
+
+/// Where each cascade of the sun's shadows goes, for a camera with `view` and `projection`
+/// drawing to a viewport `aspect` times wider than it is tall, shining along `sun_direction`
+/// (towards the sun). Shadows reach `distance` along the view, or to the far plane if that's
+/// nearer, and each map is `resolution` texels across.
+pub fn fit_cascades(
+    sun_direction: [f32; 3],
+    view: &Mat4,
+    projection: &Projection,
+    aspect: f32,
+    distance: f32,
+    resolution: u32,
+) -> Vec<Cascade> {
+    let far = distance.min(projection.far).max(projection.near);
+    let splits = cascade_splits(projection.near, far, CASCADE_COUNT);
+    let inverse_view = view.invert().unwrap_or(Mat4::identity());
+    splits
+        .windows(2)
+        .map(|slice| {
+            let corners = slice_corners(&inverse_view, projection, aspect, slice[0], slice[1]);
+            let (center, radius) = bounding_sphere(&corners);
+            Cascade {
+                view_projection: sun_view_projection(sun_direction, center, radius, far, resolution),
+                far: slice[1],
+            }
+        })
+        .collect()
+}
+
+/// Where the view from `near` to `far` is cut into `count` slices, as `count + 1` distances
+/// along it, starting with `near` and ending with `far`. See `CASCADE_SPLIT_LAMBDA`.
+pub fn cascade_splits(near: f32, far: f32, count: usize) -> Vec<f32> {
+    (0..count + 1)
+        .map(|index| {
+            if index == count {
+                return far;
+            }
+            let fraction = index as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(fraction);
+            let uniform = near + (far - near) * fraction;
+            CASCADE_SPLIT_LAMBDA * logarithmic + (1.0 - CASCADE_SPLIT_LAMBDA) * uniform
+        })
+        .collect()
+}
+
+/// The corners of the slice of the view from `near` to `far` along it, in world space.
+fn slice_corners(inverse_view: &Mat4, projection: &Projection, aspect: f32, near: f32, far: f32) -> [Vec3; 8] {
+    let tan_y = (projection.fov_y / 2.0).tan();
+    let tan_x = tan_y * aspect;
+    let mut corners = [Vec3::new(0.0, 0.0, 0.0); 8];
+    for (index, corner) in corners.iter_mut().enumerate() {
+        let depth = if index & 4 == 0 { near } else { far };
+        let x = if index & 1 == 0 { -tan_x } else { tan_x } * depth;
+        let y = if index & 2 == 0 { -tan_y } else { tan_y } * depth;
+        *corner = (inverse_view * Vec4::new(x, y, -depth, 1.0)).truncate();
+    }
+    corners
+}
+
+/// A sphere around all of `points`, centred on their average. Its radius is rounded up to a
+/// whole block, so it stays exactly the same from frame to frame as the camera moves and turns,
+/// and so does the size of the texels in the map fitted around it.
+fn bounding_sphere(points: &[Vec3]) -> ([f32; 3], f32) {
+    let center = points.iter().fold(Vec3::new(0.0, 0.0, 0.0), |sum, &point| sum + point) / points.len() as f32;
+    let radius = points.iter().map(|&point| (point - center).magnitude()).fold(0.0, f32::max);
+    (center.into(), radius.ceil().max(1.0))
+}
+
+/// The view projection for a shadow map of `resolution` texels across, looking along
+/// `sun_direction` (towards the sun) at a box `radius` out from `center` in every direction.
+/// Anything up to `caster_reach` further towards the sun is drawn too, so that hills and trees
+/// outside the box can still cast shadows into it.
+///
+/// The box only ever moves across the sun's view a whole texel at a time. Otherwise the texels
+/// would land on slightly different parts of the world each time the camera moved, and the
+/// edges of shadows would crawl. Snapping it can leave it up to a texel short of the box, so
+/// the map has a texel to spare on each side.
+pub fn sun_view_projection(
+    sun_direction: [f32; 3],
+    center: [f32; 3],
+    radius: f32,
+    caster_reach: f32,
+    resolution: u32,
+) -> Mat4 {
+    let direction = Vec3::from(sun_direction).normalize();
+    // Any up will do as long as it's not along the direction, and it stays the same from frame
+    // to frame so the texel grid doesn't turn
+    let up = if direction.y.abs() > 0.99 { Vec3::unit_z() } else { Vec3::unit_y() };
+    let view = look_along([0.0; 3], -direction, up);
+
+    let light_space = view * Vec4::new(center[0], center[1], center[2], 1.0);
+    let texel = 2.0 * radius / (resolution.max(3) - 2) as f32;
+    let x = (light_space.x / texel).floor() * texel;
+    let y = (light_space.y / texel).floor() * texel;
+    let depth = -light_space.z;
+
+    let half_width = radius + texel;
+    let projection = orthographic(
+        x - half_width,
+        x + half_width,
+        y - half_width,
+        y + half_width,
+        depth - radius - caster_reach,
+        depth + radius,
+        HAL_CLIP_SPACE,
+    );
+    projection * view
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use math::Matrix;
+
+    const SUN: [f32; 3] = [0.6, 0.7, -0.38];
+
+    fn projection() -> Projection {
+        Projection { fov_y: 70f32.to_radians(), near: 0.1, far: 1000.0 }
+    }
+
+    fn camera_view(eye: [f32; 3], direction: [f32; 3]) -> Mat4 {
+        look_along(eye, Vec3::from(direction).normalize(), Vec3::unit_y())
+    }
+
+    /// Where `point` lands in clip space after `view_projection`.
+    fn project(view_projection: &Mat4, point: Vec3) -> Vec3 {
+        let clip = view_projection * point.extend(1.0);
+        clip.truncate() / clip.w
+    }
+
+    #[test]
+    fn splits_grow_from_the_near_plane_to_the_shadow_distance() {
+        let splits = cascade_splits(0.1, 128.0, 4);
+        assert_eq!(splits.len(), 5);
+        assert!((splits[0] - 0.1).abs() < 1e-5);
+        assert_eq!(splits[4], 128.0);
+        for pair in splits.windows(2) {
+            assert!(pair[1] > pair[0]);
+        }
+        // Each slice is longer than the one before
+        for triple in splits.windows(3) {
+            assert!(triple[2] - triple[1] > triple[1] - triple[0]);
+        }
+    }
+
+    #[test]
+    fn each_cascade_covers_its_slice_of_the_view() {
+        let view = camera_view([12.0, 70.0, -30.0], [0.3, -0.4, -1.0]);
+        let projection = projection();
+        let cascades = fit_cascades(SUN, &view, &projection, 16.0 / 9.0, 128.0, 2048);
+        assert_eq!(cascades.len(), CASCADE_COUNT);
+        assert_eq!(cascades[CASCADE_COUNT - 1].far, 128.0);
+
+        let inverse_view = view.invert().unwrap();
+        let mut near = projection.near;
+        for cascade in &cascades {
+            for corner in &slice_corners(&inverse_view, &projection, 16.0 / 9.0, near, cascade.far) {
+                let clip = project(&cascade.view_projection, *corner);
+                assert!(clip.x.abs() <= 1.0 && clip.y.abs() <= 1.0, "{:?} is off the map", clip);
+                assert!(clip.z >= 0.0 && clip.z <= 1.0, "{:?} is outside the depth range", clip);
+            }
+            near = cascade.far;
+        }
+    }
+
+    #[test]
+    fn nearer_cascades_have_smaller_texels() {
+        let view = camera_view([0.0, 64.0, 0.0], [1.0, 0.0, 0.0]);
+        let cascades = fit_cascades(SUN, &view, &projection(), 1.5, 128.0, 2048);
+        // The x scale of an orthographic projection is one over half its width
+        let widths: Vec<f32> = cascades.iter().map(|cascade| 1.0 / cascade.view_projection.row(0).truncate().magnitude()).collect();
+        for pair in widths.windows(2) {
+            assert!(pair[1] > pair[0], "{:?}", widths);
+        }
+    }
+
+    #[test]
+    fn the_shadow_distance_stops_at_the_far_plane() {
+        let view = camera_view([0.0, 64.0, 0.0], [1.0, 0.0, 0.0]);
+        let projection = Projection { far: 50.0, ..projection() };
+        let cascades = fit_cascades(SUN, &view, &projection, 1.5, 128.0, 2048);
+        assert_eq!(cascades[CASCADE_COUNT - 1].far, 50.0);
+    }
+
+    #[test]
+    fn maps_move_a_whole_texel_at_a_time() {
+        // However far the center moves, the map only moves by whole texels
+        let resolution = 64;
+        let point = Vec3::new(3.0, 60.0, -7.0);
+        let base = sun_view_projection(SUN, [0.0, 60.0, 0.0], 32.0, 0.0, resolution);
+        let base_x = project(&base, point).x;
+        for step in 1..20 {
+            let center = [step as f32 * 0.173, 60.0, step as f32 * -0.091];
+            let moved = sun_view_projection(SUN, center, 32.0, 0.0, resolution);
+            // Clip space is 2 wide, so a texel is 2 / resolution of it
+            let texels = (base_x - project(&moved, point).x) / (2.0 / resolution as f32);
+            assert!((texels - texels.round()).abs() < 1e-2, "moved by {} texels", texels);
+        }
+    }
+
+    #[test]
+    fn the_sun_overhead_still_has_a_view() {
+        let view_projection = sun_view_projection([0.0, 1.0, 0.0], [0.0, 0.0, 0.0], 16.0, 16.0, 512);
+        let clip = project(&view_projection, Vec3::new(4.0, 0.0, 4.0));
+        assert!(clip.x.is_finite() && clip.y.is_finite() && clip.z.is_finite());
+    }
+}
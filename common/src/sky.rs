@@ -0,0 +1,472 @@
+//! The sky drawn behind the terrain.
+//!
+//! It's one full-screen triangle at the far plane, drawn after the terrain with the depth test
+//! on, so only the pixels nothing else was drawn into get any sky. Each works out which way it
+//! looks from the camera's inverse view projection, and the sky is picked by direction from
+//! there, one of two ways:
+//!
+//! - `Sky::Cubemap` looks it up in a `Skybox`, a cube of six square faces. Since everything in
+//!   it has to change colour with the time of day, the faces don't hold colours but what the
+//!   shader needs to make them: how much the sky glows at that point, how much cloud there is,
+//!   and how bright a star. They're generated from noise when the example starts.
+//! - `Sky::Atmosphere` works the colour out analytically, by single scattering off the air
+//!   (which scatters blue the most) and off haze (which scatters mostly forwards, into the glow
+//!   around the sun). The further the sunlight and the view pass through the air, the more is
+//!   scattered out of them, which is what turns the sky orange at dusk.
+//!
+//! Fog fades into the colour of the sky at the horizon, which `horizon_colors` works out, the
+//! same way the shader does.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use hal::{
+    buffer, command, format as f, image as i, memory,
+    pso::PipelineStage,
+    Backend, Device, PhysicalDevice,
+};
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use buffer::DeviceBuffer;
+use context::GfxContext;
+use error::Result;
+use noise::{ derive_seed, hash_position, Fbm };
+use samplers::SamplerDesc;
+use transfer::UploadCopy;
+
+/// How the sky is drawn.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sky {
+    /// Scattering worked out for each pixel from the sun's direction.
+    Atmosphere,
+    /// Clouds and stars from a `Skybox`, tinted by the time of day.
+    Cubemap,
+}
+
+impl Sky {
+    pub const ALL: [Sky; 2] = [Sky::Atmosphere, Sky::Cubemap];
+
+    /// What `sky.frag` knows the sky as.
+    pub fn id(self) -> u32 {
+        match self {
+            Sky::Atmosphere => 0,
+            Sky::Cubemap => 1,
+        }
+    }
+}
+
+impl Default for Sky {
+    fn default() -> Self {
+        Sky::Atmosphere
+    }
+}
+
+impl fmt::Display for Sky {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Sky::Atmosphere => "atmosphere",
+            Sky::Cubemap => "cubemap",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Sky {
+    type Err = String;
+
+    fn from_str(name: &str) -> ::std::result::Result<Sky, String> {
+        Sky::ALL
+            .iter()
+            .cloned()
+            .find(|sky| sky.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("Unknown sky {:?}, expected atmosphere or cubemap", name))
+    }
+}
+
+/// How many texels along each side of a skybox face. The sky is mostly soft gradients, so this
+/// doesn't need to be much.
+pub const SKYBOX_SIZE: u32 = 256;
+
+/// The faces of a cube image, in the order its layers are in.
+pub const CUBE_FACES: u32 = 6;
+
+/// The faces hold data rather than colours, so they aren't sRGB.
+const SKYBOX_FORMAT: f::Format = f::Format::Rgba8Unorm;
+
+/// Bytes per texel of `SKYBOX_FORMAT`.
+const PIXEL_SIZE: u32 = 4;
+
+/// Roughly how many texels in each thousand are stars.
+const STARS_PER_THOUSAND: u64 = 3;
+
+/// A cube image of the sky's glow, clouds and stars, for `Sky::Cubemap`.
+pub struct Skybox<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    image: Option<B::Image>,
+    view: Option<B::ImageView>,
+    sampler: Rc<B::Sampler>,
+    allocation: Option<Allocation>,
+}
+
+impl<B: Backend> Skybox<B> {
+    /// Generates the faces from `seed` with `skybox_pixels` and uploads them.
+    pub fn new(context: &mut GfxContext<B>, seed: u64) -> Result<Self> {
+        let device = context.device.clone();
+        let size = SKYBOX_SIZE;
+        let pixels = skybox_pixels(seed, size);
+
+        // Each row and face has to start at a multiple of the copy pitch alignment, the same
+        // as for any other texture
+        let limits = context.adapter.physical_device.limits();
+        let alignment_mask = limits.min_buffer_copy_pitch_alignment.max(1) as u32 - 1;
+        let row_pitch = (size * PIXEL_SIZE + alignment_mask) & !alignment_mask;
+        let mut staging_data = Vec::new();
+        let mut copies = Vec::with_capacity(CUBE_FACES as usize);
+        for (face, face_pixels) in pixels.chunks((size * size * PIXEL_SIZE) as usize).enumerate() {
+            let buffer_offset = staging_data.len() as u64;
+            for row in face_pixels.chunks((size * PIXEL_SIZE) as usize) {
+                let start = staging_data.len();
+                staging_data.extend_from_slice(row);
+                staging_data.resize(start + row_pitch as usize, 0);
+            }
+            copies.push(command::BufferImageCopy {
+                buffer_offset,
+                buffer_width: row_pitch / PIXEL_SIZE,
+                buffer_height: size,
+                image_layers: i::SubresourceLayers {
+                    aspects: f::Aspects::COLOR,
+                    level: 0,
+                    layers: face as i::Layer..face as i::Layer + 1,
+                },
+                image_offset: i::Offset { x: 0, y: 0, z: 0 },
+                image_extent: i::Extent { width: size, height: size, depth: 1 },
+            });
+        }
+
+        let staging = DeviceBuffer::new(
+            device.clone(),
+            context.allocator.clone(),
+            staging_data.len() as u64,
+            buffer::Usage::TRANSFER_SRC,
+            memory::Properties::CPU_VISIBLE,
+            MemoryCategory::Transient,
+        )?;
+        staging.write(&staging_data)?;
+
+        let unbound = device.create_image(
+            i::Kind::D2(size, size, CUBE_FACES as i::Layer, 1),
+            1,
+            SKYBOX_FORMAT,
+            i::Tiling::Optimal,
+            i::Usage::TRANSFER_DST | i::Usage::SAMPLED,
+            i::ViewCapabilities::KIND_CUBE,
+        )?;
+        let requirements = device.get_image_requirements(&unbound);
+        let (image, allocation) = {
+            let mut allocator = context.allocator.borrow_mut();
+            let allocation = allocator
+                .allocate(
+                    &requirements,
+                    memory::Properties::DEVICE_LOCAL,
+                    ResourceKind::Optimal,
+                    MemoryCategory::Textures,
+                )?;
+            let image = device
+                .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+            (image, allocation)
+        };
+
+        let all_faces = i::SubresourceRange {
+            aspects: f::Aspects::COLOR,
+            levels: 0..1,
+            layers: 0..CUBE_FACES as i::Layer,
+        };
+        context.submit_upload(
+            &[UploadCopy::Image {
+                staging: staging.buffer(),
+                target: &image,
+                range: all_faces.clone(),
+                regions: &copies,
+            }],
+            |command_buffer| {
+                command_buffer.pipeline_barrier(
+                    PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+                    memory::Dependencies::empty(),
+                    &[memory::Barrier::Image {
+                        states: (i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal)
+                            ..(i::Access::SHADER_READ, i::Layout::ShaderReadOnlyOptimal),
+                        target: &image,
+                        range: all_faces.clone(),
+                    }],
+                );
+            },
+        )?;
+
+        let view = device.create_image_view(&image, i::ViewKind::Cube, SKYBOX_FORMAT, f::Swizzle::NO, all_faces)?;
+        // Filtering blends across the seams between faces as well as within them
+        let sampler = context.samplers.borrow_mut().get(SamplerDesc::new(i::Filter::Linear, i::WrapMode::Clamp));
+        debug!("Generated a {}x{} skybox", size, size);
+
+        Ok(Skybox {
+            device,
+            allocator: context.allocator.clone(),
+            image: Some(image),
+            view: Some(view),
+            sampler,
+            allocation: Some(allocation),
+        })
+    }
+
+    pub fn view(&self) -> &B::ImageView {
+        self.view.as_ref().unwrap()
+    }
+
+    pub fn sampler(&self) -> &B::Sampler {
+        &self.sampler
+    }
+}
+
+impl<B: Backend> Drop for Skybox<B> {
+    fn drop(&mut self) {
+        if let Some(view) = self.view.take() {
+            self.device.destroy_image_view(view);
+        }
+        if let Some(image) = self.image.take() {
+            self.device.destroy_image(image);
+        }
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.borrow_mut().free(allocation);
+        }
+    }
+}
+
+/// How much of each colour the air and the haze scatter looking straight up, how much of the
+/// haze's goes forwards, and how bright sunlight is. These have to match `sky.frag`.
+const AIR_SCATTERING: [f32; 3] = [0.05, 0.11, 0.26];
+const HAZE_SCATTERING: f32 = 0.01;
+const HAZE_FORWARD: f32 = 0.76;
+const SUN_INTENSITY: f32 = 4.0;
+/// The most air the view is taken to go through. Single scattering leaves out the light that's
+/// scattered more than once, which evens out how bright the sky is, and without a cap the
+/// horizon comes out several times brighter than the sky above it.
+const MAX_VIEW_AIR_MASS: f32 = 4.0;
+/// What's left of the sky with the sun down.
+const NIGHT_SKY: [f32; 3] = [0.002, 0.004, 0.01];
+
+/// The colour of `sky` right at the horizon, looking away from the sun and towards it, for fog
+/// to fade into. `sun_direction` is towards the sun and `sky_color` the time of day's.
+pub fn horizon_colors(sky: Sky, sun_direction: [f32; 3], sky_color: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    match sky {
+        Sky::Atmosphere => {
+            let sun = normalize(sun_direction);
+            // Straight away from the sun and straight towards it, along the ground, or any way
+            // at all with the sun overhead
+            let across = (sun[0] * sun[0] + sun[2] * sun[2]).sqrt();
+            let (x, z) = if across > 0.0001 { (sun[0] / across, sun[2] / across) } else { (1.0, 0.0) };
+            let away = atmosphere([-x, 0.0, -z], sun);
+            let towards = atmosphere([x, 0.0, z], sun);
+            (away, towards)
+        }
+        // The skybox's glow is at its brightest at the horizon, and its clouds have faded out
+        Sky::Cubemap => (sky_color, sky_color),
+    }
+}
+
+/// Sunlight scattered towards the camera from along `view`, a single time, by the air and the
+/// haze, without the sun's disk. Both have to be normalized. The same as `atmosphere` in
+/// `sky.frag`, down to the night sky it never goes below.
+pub fn atmosphere(view: [f32; 3], sun: [f32; 3]) -> [f32; 3] {
+    let cosine = dot(view, sun);
+    let air_phase = 0.75 * (1.0 + cosine * cosine);
+    let g = HAZE_FORWARD;
+    let haze_phase = (1.0 - g * g) / (1.0 + g * g - 2.0 * g * cosine).powf(1.5);
+    let fade = smoothstep(-0.1, 0.05, sun[1]);
+
+    let mut color = [0.0; 3];
+    for channel in 0..3 {
+        let extinction = AIR_SCATTERING[channel] + HAZE_SCATTERING;
+        let sunlight = SUN_INTENSITY * (-extinction * air_mass(sun[1])).exp();
+        let view_extinction = (-extinction * air_mass(view[1]).min(MAX_VIEW_AIR_MASS)).exp();
+        let scattered = (AIR_SCATTERING[channel] * air_phase + HAZE_SCATTERING * haze_phase) / extinction;
+        let sky = sunlight * scattered * (1.0 - view_extinction) * fade;
+        color[channel] = sky.max(NIGHT_SKY[channel]);
+    }
+    color
+}
+
+/// How much air there is along a ray going out at `height` (the sine of its angle above the
+/// horizon), compared to straight up. Kasten and Young's fit, which stays finite at the horizon.
+fn air_mass(height: f32) -> f32 {
+    let elevation = height.max(0.0).min(1.0).asin().to_degrees();
+    1.0 / (height.max(0.0) + 0.50572 * (elevation + 6.07995).powf(-1.6364))
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = dot(v, v).sqrt().max(0.0001);
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+fn smoothstep(low: f32, high: f32, value: f32) -> f32 {
+    let t = clamp01((value - low) / (high - low));
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// The direction from the centre of the cube through point (`s`, `t`) of `face`, each from -1
+/// to 1 across it, not normalized. The faces are +X, -X, +Y, -Y, +Z and -Z, laid out the way
+/// cube samplers expect, which is as seen from inside the cube with t going down.
+pub fn face_direction(face: u32, s: f32, t: f32) -> [f32; 3] {
+    match face {
+        0 => [1.0, -t, -s],
+        1 => [-1.0, -t, s],
+        2 => [s, 1.0, t],
+        3 => [s, -1.0, -t],
+        4 => [s, -t, 1.0],
+        5 => [-s, -t, -1.0],
+        _ => panic!("A cube only has {} faces, not {}", CUBE_FACES, face + 1),
+    }
+}
+
+/// The six `size` by `size` faces of a skybox, one after the other, generated from `seed`. Each
+/// texel's red is how much the sky glows there, brightest at the horizon, its green how cloudy it
+/// is, and its blue how bright a star is there.
+pub fn skybox_pixels(seed: u64, size: u32) -> Vec<u8> {
+    let clouds = Fbm::new(derive_seed(seed, 301), 5, 1.5);
+    let star_seed = derive_seed(seed, 302);
+    let mut pixels = Vec::with_capacity((CUBE_FACES * size * size * PIXEL_SIZE) as usize);
+    for face in 0..CUBE_FACES {
+        for y in 0..size {
+            for x in 0..size {
+                let s = (x as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                let t = (y as f32 + 0.5) / size as f32 * 2.0 - 1.0;
+                let direction = face_direction(face, s, t);
+                let (dx, dy, dz) = (direction[0], direction[1], direction[2]);
+                let up = dy / (dx * dx + dy * dy + dz * dz).sqrt();
+
+                // Looking through more air towards the horizon, the sky is hazier and brighter
+                let glow = 0.75 + 0.25 * (1.0 - up.abs()).powi(4);
+
+                // Clouds are on a flat layer above the camera, so they bunch up towards the
+                // horizon, where they fade out into the haze
+                let cloud = if up > 0.0 {
+                    let (u, v) = (dx / dy, dz / dy);
+                    let cover = (clouds.get2(u, v) - 0.05) / 0.4;
+                    clamp01(cover) * clamp01(up * 6.0)
+                } else {
+                    0.0
+                };
+
+                let hash = hash_position(star_seed, [face as i32, x as i32, y as i32]);
+                let star = if up > 0.0 && hash % 1000 < STARS_PER_THOUSAND {
+                    0.3 + 0.7 * ((hash >> 16) % 256) as f32 / 255.0
+                } else {
+                    0.0
+                };
+
+                pixels.push(to_byte(glow));
+                pixels.push(to_byte(cloud));
+                pixels.push(to_byte(star));
+                pixels.push(255);
+            }
+        }
+    }
+    pixels
+}
+
+fn clamp01(value: f32) -> f32 {
+    value.max(0.0).min(1.0)
+}
+
+fn to_byte(value: f32) -> u8 {
+    (clamp01(value) * 255.0).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_point_along_their_axes() {
+        assert_eq!(face_direction(0, 0.0, 0.0), [1.0, 0.0, 0.0]);
+        assert_eq!(face_direction(1, 0.0, 0.0), [-1.0, 0.0, 0.0]);
+        assert_eq!(face_direction(2, 0.0, 0.0), [0.0, 1.0, 0.0]);
+        assert_eq!(face_direction(3, 0.0, 0.0), [0.0, -1.0, 0.0]);
+        assert_eq!(face_direction(4, 0.0, 0.0), [0.0, 0.0, 1.0]);
+        assert_eq!(face_direction(5, 0.0, 0.0), [0.0, 0.0, -1.0]);
+    }
+
+    #[test]
+    fn neighbouring_faces_meet_at_their_corners() {
+        // The corner at +X, +Y and +Z is on all three faces
+        assert_eq!(face_direction(0, -1.0, -1.0), [1.0, 1.0, 1.0]);
+        assert_eq!(face_direction(2, 1.0, 1.0), [1.0, 1.0, 1.0]);
+        assert_eq!(face_direction(4, 1.0, -1.0), [1.0, 1.0, 1.0]);
+        // And the one at -X, -Y and -Z on the other three
+        assert_eq!(face_direction(1, -1.0, 1.0), [-1.0, -1.0, -1.0]);
+        assert_eq!(face_direction(3, -1.0, 1.0), [-1.0, -1.0, -1.0]);
+        assert_eq!(face_direction(5, 1.0, 1.0), [-1.0, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn clouds_and_stars_stay_above_the_horizon() {
+        let size = 16;
+        let pixels = skybox_pixels(7, size);
+        assert_eq!(pixels.len(), (CUBE_FACES * size * size * PIXEL_SIZE) as usize);
+
+        let face_bytes = (size * size * PIXEL_SIZE) as usize;
+        let below = &pixels[3 * face_bytes..4 * face_bytes];
+        assert!(below.chunks(4).all(|texel| texel[1] == 0 && texel[2] == 0));
+        let above = &pixels[2 * face_bytes..3 * face_bytes];
+        assert!(above.chunks(4).any(|texel| texel[1] > 0));
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_sky() {
+        assert_eq!(skybox_pixels(3, 8), skybox_pixels(3, 8));
+        assert!(skybox_pixels(3, 8) != skybox_pixels(4, 8));
+    }
+
+    #[test]
+    fn the_horizon_glows_towards_a_setting_sun() {
+        let sun = normalize([1.0, 0.05, 0.0]);
+        let (away, towards) = horizon_colors(Sky::Atmosphere, sun, [0.0; 3]);
+        assert!(towards.iter().zip(away.iter()).all(|(towards, away)| towards > away));
+        // And it's the blue that's been scattered out of the light coming from the sun
+        assert!(towards[0] > towards[2]);
+    }
+
+    #[test]
+    fn the_sky_is_blue_overhead_at_noon() {
+        let color = atmosphere([0.0, 1.0, 0.0], normalize([0.3, 1.0, 0.0]));
+        assert!(color[2] > color[1] && color[1] > color[0]);
+    }
+
+    #[test]
+    fn the_sky_never_goes_darker_than_night() {
+        let (away, towards) = horizon_colors(Sky::Atmosphere, [0.0, -1.0, 0.0], [0.0; 3]);
+        assert_eq!(away, NIGHT_SKY);
+        assert_eq!(towards, NIGHT_SKY);
+    }
+
+    #[test]
+    fn the_cubemap_horizon_is_the_sky_colour() {
+        let sky_color = [0.4, 0.6, 0.9];
+        assert_eq!(horizon_colors(Sky::Cubemap, [0.0, 1.0, 0.0], sky_color), (sky_color, sky_color));
+    }
+
+    #[test]
+    fn skies_parse_from_their_names() {
+        for &sky in &Sky::ALL {
+            assert_eq!(sky.to_string().parse::<Sky>(), Ok(sky));
+        }
+        assert_eq!("Cubemap".parse::<Sky>(), Ok(Sky::Cubemap));
+        assert!("skydome".parse::<Sky>().is_err());
+    }
+}
@@ -0,0 +1,104 @@
+//! Screen-space ambient occlusion, worked out from the G-buffer.
+//!
+//! The ambient occlusion baked into the chunk meshes only knows about the blocks touching each
+//! corner of a face. SSAO darkens anything with something else close in front of it, whatever
+//! that is, by looking around each pixel in the G-buffer. Points are picked in the hemisphere
+//! above the surface from a kernel, with a different twist on it at each pixel, and the more of
+//! them that end up behind what was drawn, the more occluded the pixel is. The twist makes the
+//! results noisy, so a blur that doesn't reach across edges in depth smooths them out before the
+//! lighting pass scales the ambient light by them.
+//!
+//! Both passes are compute shaders in the frame graph, which owns the images the occlusion is
+//! written to before and after the blur. They go on the compute queue when there is one, so they
+//! run while the graphics queue draws the shadows and the other views. `hemisphere_kernel` makes
+//! the points to look at.
+
+use hal::format as f;
+
+use noise::Random;
+
+/// The most points SSAO can look at around each pixel. Has to match the shaders.
+pub const MAX_SSAO_SAMPLES: usize = 64;
+
+/// How many pixels along each side each group of the shaders covers. Has to match their local
+/// size.
+pub const SSAO_GROUP_SIZE: u32 = 8;
+
+/// How much occlusion there is at each pixel, from 0 for completely hidden to 1 for open sky.
+/// The shaders write it as a storage image, which every adapter can do in this format but not
+/// always in smaller ones.
+pub const OCCLUSION_FORMAT: f::Format = f::Format::R32Float;
+
+/// So the kernel is the same every run, and the same in every headless frame.
+const KERNEL_SEED: u64 = 0x55a0;
+
+/// How far off flat along the surface the points have to be, as the cosine of their angle from
+/// the normal. Any closer and flat ground occludes itself wherever depth is a little off.
+const MIN_KERNEL_ELEVATION: f32 = 0.15;
+
+/// `count` points in the hemisphere above a surface facing +z, up to `MAX_SSAO_SAMPLES`, padded
+/// out to `vec4`s for a uniform buffer. They're no further than 1 from the surface, and get
+/// further out along the kernel, so there are more of them close in where occlusion matters
+/// most.
+pub fn hemisphere_kernel(count: usize) -> Vec<[f32; 4]> {
+    let count = count.min(MAX_SSAO_SAMPLES);
+    let mut random = Random::new(KERNEL_SEED);
+    let mut kernel = Vec::with_capacity(count);
+    while kernel.len() < count {
+        // Picking from the cube and throwing away what's outside the ball keeps the directions
+        // even, rather than bunched towards the cube's corners
+        let x = random.next_f32() * 2.0 - 1.0;
+        let y = random.next_f32() * 2.0 - 1.0;
+        let z = random.next_f32();
+        let length = (x * x + y * y + z * z).sqrt();
+        if length > 1.0 || length < 0.01 || z / length < MIN_KERNEL_ELEVATION {
+            continue;
+        }
+
+        let along = (kernel.len() + 1) as f32 / count as f32;
+        let scale = lerp(0.1, 1.0, along * along) / length;
+        kernel.push([x * scale, y * scale, z * scale, 0.0]);
+    }
+    kernel
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn length(point: &[f32; 4]) -> f32 {
+        (point[0] * point[0] + point[1] * point[1] + point[2] * point[2]).sqrt()
+    }
+
+    #[test]
+    fn points_stay_above_the_surface_and_inside_the_radius() {
+        let kernel = hemisphere_kernel(32);
+        assert_eq!(kernel.len(), 32);
+        for point in &kernel {
+            assert!(point[2] >= MIN_KERNEL_ELEVATION * length(point) - 1e-6);
+            assert!(length(point) <= 1.0 + 1e-6);
+            assert_eq!(point[3], 0.0);
+        }
+    }
+
+    #[test]
+    fn points_get_further_out_along_the_kernel() {
+        let kernel = hemisphere_kernel(16);
+        assert!(length(&kernel[0]) >= 0.1 - 1e-6);
+        assert!((length(&kernel[15]) - 1.0).abs() < 1e-5);
+        for pair in kernel.windows(2) {
+            assert!(length(&pair[0]) < length(&pair[1]));
+        }
+    }
+
+    #[test]
+    fn the_kernel_is_the_same_every_time_and_has_a_limit() {
+        assert_eq!(hemisphere_kernel(8), hemisphere_kernel(8));
+        assert_eq!(hemisphere_kernel(1000).len(), MAX_SSAO_SAMPLES);
+        assert!(hemisphere_kernel(0).is_empty());
+    }
+}
@@ -0,0 +1,409 @@
+//! A ring buffer for data that's written again every frame.
+//!
+//! Debug lines, UI vertices and small mesh patches are only needed for the frame they're written
+//! in, so making a buffer for each of them, or keeping one per frame in flight that's replaced
+//! whenever the data outgrows it, allocates far more often than it needs to. A `StagingRing` is
+//! one host visible buffer handed out front to back, wrapping round to the start when it gets to
+//! the end. What each frame wrote is given back once that frame is done, which is known without
+//! asking the gpu: `FrameSync::begin_frame` waits for the fence of the frame that last used the
+//! same slot, so by the time a frame begins, everything from `frames_in_flight` frames before it
+//! has finished.
+//!
+//! Data that's copied out of the ring rather than drawn from it is only read once the copy is
+//! submitted, which needn't be in the frame it was written. Those writes are ended as a batch
+//! when they're submitted instead, with `end_batch`, and given back with `release_through` once
+//! that submission is known to be done.
+//!
+//! `FrameRing` does the bookkeeping, and knows nothing about the gpu. The data can be drawn
+//! straight out of the ring, or copied out of it into a device local buffer.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+use hal::{ buffer, memory, Backend };
+
+use allocator::{ Allocator, MemoryCategory };
+use buffer::DeviceBuffer;
+use error::Result;
+
+/// What every allocation from a ring is aligned to, which is enough for vertices, indices and
+/// copies into buffers and images.
+pub const STAGING_ALIGNMENT: u64 = 16;
+
+/// The bytes written in one frame or batch, which are all given back together.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct BatchUse {
+    batch: u64,
+    /// Where the ring's head was when the batch ended, which is where the space it frees ends.
+    end: u64,
+    /// How many bytes it took, including the padding and any space skipped to wrap round.
+    bytes: u64,
+}
+
+/// The ranges of a ring buffer that each frame, or each batch of writes, is using.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FrameRing {
+    size: u64,
+    /// Where the next allocation starts looking from.
+    head: u64,
+    /// The start of the oldest range still in use.
+    tail: u64,
+    used: u64,
+    /// The number the batch being written now will have.
+    batch: u64,
+    /// Bytes taken since the last batch ended.
+    batch_bytes: u64,
+    in_flight: VecDeque<BatchUse>,
+}
+
+impl FrameRing {
+    pub fn new(size: u64) -> Self {
+        FrameRing {
+            size,
+            head: 0,
+            tail: 0,
+            used: 0,
+            batch: 0,
+            batch_bytes: 0,
+            in_flight: VecDeque::new(),
+        }
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// How many bytes are in use, by this frame and the ones still in flight.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+
+    /// The number the batch being written now will have when it ends.
+    pub fn batch(&self) -> u64 {
+        self.batch
+    }
+
+    /// Takes `size` bytes, aligned to `alignment`, for the current frame. Returns where they
+    /// start, or `None` if there isn't that much room in one piece.
+    pub fn allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        if size > self.size {
+            return None;
+        }
+        if self.used == 0 {
+            // Nothing's in use, so start again from the beginning, where there's the most room
+            self.head = 0;
+            self.tail = 0;
+        }
+        let start = align_up(self.head, alignment);
+        // The free space is from the head round to the tail. It's all in one piece when the
+        // head has wrapped round behind the tail, or none at all when it's caught up with it,
+        // and otherwise split between the end and the start.
+        let wrapped = self.used > 0 && self.head <= self.tail;
+        let (start, taken) = if wrapped {
+            if start + size > self.tail {
+                return None;
+            }
+            (start, start - self.head + size)
+        } else if start + size <= self.size {
+            (start, start - self.head + size)
+        } else if size <= self.tail {
+            // Skip what's left at the end, which comes back with the rest of this frame's
+            (0, self.size - self.head + size)
+        } else {
+            return None;
+        };
+        self.head = start + size;
+        self.used += taken;
+        self.batch_bytes += taken;
+        Some(start)
+    }
+
+    /// Ends the current frame, and gives back what the frames from `frames_in_flight` or more
+    /// frames ago took. Call right after `FrameSync::begin_frame`. Each frame is a batch.
+    pub fn begin_frame(&mut self, frames_in_flight: usize) {
+        let ended = self.end_batch();
+        if let Some(done) = (ended + 1).checked_sub(frames_in_flight as u64) {
+            self.release_through(done);
+        }
+    }
+
+    /// Ends the batch of everything allocated since the last one ended, and returns its number.
+    pub fn end_batch(&mut self) -> u64 {
+        if self.batch_bytes > 0 {
+            self.in_flight.push_back(BatchUse {
+                batch: self.batch,
+                end: self.head,
+                bytes: self.batch_bytes,
+            });
+            self.batch_bytes = 0;
+        }
+        self.batch += 1;
+        self.batch - 1
+    }
+
+    /// Gives back every batch up to and including `batch`. Batches that haven't ended yet are
+    /// kept whatever `batch` is.
+    pub fn release_through(&mut self, batch: u64) {
+        while self.in_flight.front().map_or(false, |done| done.batch <= batch) {
+            let done = self.in_flight.pop_front().unwrap();
+            self.tail = done.end;
+            self.used -= done.bytes;
+        }
+    }
+
+    /// Gives everything back, for once the gpu is idle.
+    pub fn release_all(&mut self) {
+        self.in_flight.clear();
+        self.batch_bytes = 0;
+        self.used = 0;
+        self.head = 0;
+        self.tail = 0;
+    }
+}
+
+fn align_up(offset: u64, alignment: u64) -> u64 {
+    (offset + alignment - 1) / alignment * alignment
+}
+
+/// A host visible buffer that per-frame data is written into, with a `FrameRing` to say where.
+pub struct StagingRing<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    usage: buffer::Usage,
+    buffer: DeviceBuffer<B>,
+    ring: FrameRing,
+    /// Buffers the ring has outgrown, kept until the frames that used them are done, with the
+    /// batch they were last used in.
+    retired: VecDeque<(u64, DeviceBuffer<B>)>,
+}
+
+impl<B: Backend> StagingRing<B> {
+    /// Makes a ring of `size` bytes that can be used as `usage` as well as copied out of.
+    pub fn new(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        size: u64,
+        usage: buffer::Usage,
+    ) -> Result<Self> {
+        let usage = usage | buffer::Usage::TRANSFER_SRC;
+        let buffer = create_ring_buffer(&device, &allocator, size, usage)?;
+        Ok(StagingRing {
+            device,
+            allocator,
+            usage,
+            buffer,
+            ring: FrameRing::new(size),
+            retired: VecDeque::new(),
+        })
+    }
+
+    /// Call right after `FrameSync::begin_frame`, for rings whose batches are frames. Gives
+    /// back what the frames from `frames_in_flight` or more frames ago wrote.
+    pub fn begin_frame(&mut self, frames_in_flight: usize) {
+        let ended = self.end_batch();
+        if let Some(done) = (ended + 1).checked_sub(frames_in_flight as u64) {
+            self.release_through(done);
+        }
+    }
+
+    /// Ends the batch of everything written since the last one ended, and returns its number.
+    pub fn end_batch(&mut self) -> u64 {
+        self.ring.end_batch()
+    }
+
+    /// Gives back every batch up to and including `batch`, along with the buffers the ring
+    /// outgrew while they were being written.
+    pub fn release_through(&mut self, batch: u64) {
+        self.ring.release_through(batch);
+        while self.retired.front().map_or(false, |&(last_used, _)| last_used <= batch) {
+            // The buffer frees itself as it's dropped
+            self.retired.pop_front();
+        }
+    }
+
+    /// Gives everything back, for once the gpu is idle.
+    pub fn release_all(&mut self) {
+        self.ring.release_all();
+        self.retired.clear();
+    }
+
+    /// Copies `data` into the ring for this frame, and returns where in `buffer` it went, or
+    /// `None` if there's no room for it.
+    pub fn try_write<T: Copy>(&mut self, data: &[T]) -> Result<Option<u64>> {
+        let size = (data.len() * mem::size_of::<T>()) as u64;
+        match self.try_allocate(size, STAGING_ALIGNMENT) {
+            Some(offset) => {
+                self.buffer.write_at(offset, data)?;
+                Ok(Some(offset))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Takes `size` bytes for this frame, and returns where in `buffer` they start. If there's
+    /// no room, the ring moves to a new buffer twice as big, or big enough for a few frames of
+    /// `size`, and the old one is kept until the frames that used it are done. Anything written
+    /// before that is still in the old buffer, so whatever binds `buffer` has to do it after
+    /// the allocations it reads from.
+    pub fn allocate(&mut self, size: u64) -> Result<u64> {
+        self.allocate_aligned(size, STAGING_ALIGNMENT)
+    }
+
+    /// Like `allocate`, but starting at a multiple of `alignment`, which has to be a multiple
+    /// of `STAGING_ALIGNMENT`, for copies into images that need more.
+    pub fn allocate_aligned(&mut self, size: u64, alignment: u64) -> Result<u64> {
+        if let Some(offset) = self.try_allocate(size, alignment) {
+            return Ok(offset);
+        }
+        let new_size = (self.ring.size() * 2).max((size * 4).next_power_of_two());
+        debug!("Growing a staging ring from {} to {} bytes", self.ring.size(), new_size);
+        let buffer = create_ring_buffer(&self.device, &self.allocator, new_size, self.usage)?;
+        let old = mem::replace(&mut self.buffer, buffer);
+        self.retired.push_back((self.ring.batch(), old));
+        self.ring = FrameRing {
+            batch: self.ring.batch(),
+            ..FrameRing::new(new_size)
+        };
+        Ok(self.ring.allocate(size, alignment).unwrap())
+    }
+
+    /// Takes `size` bytes for this frame starting at a multiple of `alignment`, if there's room
+    /// for them without growing, and returns where in `buffer` they start.
+    pub fn try_allocate(&mut self, size: u64, alignment: u64) -> Option<u64> {
+        self.ring.allocate(size, alignment)
+    }
+
+    /// Copies `data` into the ring for this frame, making room with `allocate` if it has to,
+    /// and returns where in `buffer` it went.
+    pub fn write<T: Copy>(&mut self, data: &[T]) -> Result<u64> {
+        let offset = self.allocate((data.len() * mem::size_of::<T>()) as u64)?;
+        self.buffer.write_at(offset, data)?;
+        Ok(offset)
+    }
+
+    /// Copies `data` into an allocation from `allocate`, `offset` bytes in.
+    pub fn write_at<T: Copy>(&self, offset: u64, data: &[T]) -> Result<()> {
+        self.buffer.write_at(offset, data)
+    }
+
+    /// The buffer the ring is in now.
+    pub fn buffer(&self) -> &B::Buffer {
+        self.buffer.buffer()
+    }
+
+    /// How many bytes are in use, by this frame and the ones still in flight.
+    pub fn used(&self) -> u64 {
+        self.ring.used()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.ring.size()
+    }
+}
+
+fn create_ring_buffer<B: Backend>(
+    device: &Rc<B::Device>,
+    allocator: &Rc<RefCell<Allocator<B>>>,
+    size: u64,
+    usage: buffer::Usage,
+) -> Result<DeviceBuffer<B>> {
+    DeviceBuffer::new(
+        device.clone(),
+        allocator.clone(),
+        size,
+        usage,
+        memory::Properties::CPU_VISIBLE | memory::Properties::COHERENT,
+        MemoryCategory::Transient,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocations_are_aligned_and_in_order() {
+        let mut ring = FrameRing::new(256);
+        assert_eq!(ring.allocate(10, 16), Some(0));
+        assert_eq!(ring.allocate(10, 16), Some(16));
+        assert_eq!(ring.allocate(1, 4), Some(28));
+        assert_eq!(ring.used(), 29);
+    }
+
+    #[test]
+    fn frames_are_given_back_once_theyre_done() {
+        let mut ring = FrameRing::new(100);
+        assert_eq!(ring.allocate(60, 1), Some(0));
+        ring.begin_frame(2);
+        assert_eq!(ring.allocate(30, 1), Some(60));
+        // Neither frame is done yet, so there's only 10 bytes left
+        assert_eq!(ring.allocate(20, 1), None);
+        ring.begin_frame(2);
+        assert_eq!(ring.used(), 30);
+        // The first frame's space at the start is free again, and the end is skipped
+        assert_eq!(ring.allocate(50, 1), Some(0));
+        assert_eq!(ring.used(), 90);
+        ring.begin_frame(2);
+        ring.begin_frame(2);
+        assert_eq!(ring.used(), 0);
+    }
+
+    #[test]
+    fn batches_are_only_given_back_once_theyve_ended() {
+        let mut ring = FrameRing::new(100);
+        assert_eq!(ring.allocate(40, 1), Some(0));
+        // Releasing anything before the batch has ended, as when frames go by without the
+        // copies out of it being submitted, leaves it alone
+        for batch in 0..10 {
+            ring.release_through(batch);
+        }
+        assert_eq!(ring.used(), 40);
+        assert_eq!(ring.allocate(70, 1), None);
+
+        let batch = ring.end_batch();
+        ring.allocate(20, 1);
+        ring.release_through(batch);
+        // The batch after it hasn't ended, so it's still in use
+        assert_eq!(ring.used(), 20);
+        let next = ring.end_batch();
+        assert!(next > batch);
+        ring.release_through(next);
+        assert_eq!(ring.used(), 0);
+    }
+
+    #[test]
+    fn a_wrapped_head_stops_at_the_tail() {
+        let mut ring = FrameRing::new(100);
+        ring.allocate(40, 1);
+        ring.begin_frame(2);
+        ring.allocate(50, 1);
+        ring.begin_frame(2);
+        // The second frame is from 40 to 90, so only 40 fit at the start
+        assert_eq!(ring.allocate(20, 1), Some(0));
+        assert_eq!(ring.allocate(30, 1), None);
+        assert_eq!(ring.allocate(20, 1), Some(20));
+        // Caught up with the tail, so it's full
+        assert_eq!(ring.allocate(1, 1), None);
+    }
+
+    #[test]
+    fn too_big_is_never_allocated() {
+        let mut ring = FrameRing::new(64);
+        assert_eq!(ring.allocate(65, 1), None);
+        assert_eq!(ring.allocate(64, 1), Some(0));
+        assert_eq!(ring.allocate(1, 1), None);
+    }
+
+    #[test]
+    fn releasing_everything_starts_again() {
+        let mut ring = FrameRing::new(64);
+        ring.allocate(50, 1);
+        ring.begin_frame(3);
+        ring.allocate(10, 1);
+        ring.release_all();
+        assert_eq!(ring.used(), 0);
+        assert_eq!(ring.allocate(64, 1), Some(0));
+    }
+}
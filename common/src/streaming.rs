@@ -0,0 +1,212 @@
+//! Loading chunks around the camera as it moves.
+//!
+//! The world goes on for ever sideways, so only the chunks within the render distance of the
+//! camera are kept loaded. Whenever the camera crosses into another chunk, `ChunkLoader` works
+//! out which chunks in range aren't loaded yet and queues them nearest first, so the ground
+//! under the camera fills in before the horizon does. Chunks that have fallen out of range are
+//! handed back to be unloaded.
+//!
+//! Range is measured across the ground in chunks, so it's a cylinder rather than a sphere:
+//! the world is only a few chunks tall, and all of them are wanted wherever the camera is.
+//! Chunks are only unloaded once they're a chunk further out than they're loaded, so moving
+//! back and forth over a chunk border doesn't keep loading and unloading the same row.
+
+use std::ops::Range;
+
+use world::{ ChunkCoord, World };
+
+/// How many chunks beyond the render distance a chunk has to be before it's unloaded.
+pub const UNLOAD_MARGIN: u32 = 1;
+
+pub struct ChunkLoader {
+    render_distance: u32,
+    /// The rows of chunks the world has, bottom to top.
+    layers: Range<i32>,
+    /// The chunk the queue was last worked out around, or `None` if it needs working out again.
+    center: Option<ChunkCoord>,
+    /// Chunks in range that weren't loaded when the queue was worked out, furthest first so the
+    /// nearest is popped off the end.
+    queue: Vec<ChunkCoord>,
+}
+
+impl ChunkLoader {
+    /// Keeps the chunks within `render_distance` chunks loaded, in each of the rows `layers`.
+    pub fn new(render_distance: u32, layers: Range<i32>) -> Self {
+        ChunkLoader {
+            render_distance,
+            layers,
+            center: None,
+            queue: Vec::new(),
+        }
+    }
+
+    pub fn render_distance(&self) -> u32 {
+        self.render_distance
+    }
+
+    /// Changes how far out chunks are kept loaded. Takes effect on the next `update`.
+    pub fn set_render_distance(&mut self, render_distance: u32) {
+        if render_distance != self.render_distance {
+            self.render_distance = render_distance;
+            self.center = None;
+        }
+    }
+
+    /// Whether `coord` should be loaded while the camera is in `center`.
+    pub fn in_range(&self, center: ChunkCoord, coord: ChunkCoord) -> bool {
+        self.layers.start <= coord.y && coord.y < self.layers.end
+            && horizontal_distance_squared(center, coord) <= square(self.render_distance)
+    }
+
+    /// Call once a frame with the chunk the camera is in. When that's changed, or the render
+    /// distance has, refills the queue and returns the loaded chunks that are now too far away,
+    /// for the caller to unload.
+    pub fn update(&mut self, world: &World, center: ChunkCoord) -> Vec<ChunkCoord> {
+        if self.center == Some(center) {
+            return Vec::new();
+        }
+        self.center = Some(center);
+
+        let unload_distance = square(self.render_distance + UNLOAD_MARGIN);
+        let unload = world
+            .chunks()
+            .map(|(coord, _)| coord)
+            .filter(|&coord| {
+                !(self.layers.start <= coord.y && coord.y < self.layers.end)
+                    || horizontal_distance_squared(center, coord) > unload_distance
+            })
+            .collect();
+
+        let reach = self.render_distance as i32;
+        self.queue.clear();
+        for y in self.layers.clone() {
+            for z in center.z - reach..center.z + reach + 1 {
+                for x in center.x - reach..center.x + reach + 1 {
+                    let coord = ChunkCoord::new(x, y, z);
+                    if self.in_range(center, coord) && !world.contains_chunk(coord) {
+                        self.queue.push(coord);
+                    }
+                }
+            }
+        }
+        // Nearest last, counting height too so the camera's own row comes first
+        self.queue.sort_by_key(|&coord| {
+            let dy = (coord.y - center.y) as i64;
+            -(horizontal_distance_squared(center, coord) + dy * dy)
+        });
+
+        unload
+    }
+
+    /// The nearest chunk in range that still needs loading, if there is one. The caller is
+    /// expected to load it.
+    pub fn next(&mut self, world: &World) -> Option<ChunkCoord> {
+        while let Some(coord) = self.queue.pop() {
+            if !world.contains_chunk(coord) {
+                return Some(coord);
+            }
+        }
+        None
+    }
+
+    /// How many chunks are waiting to be loaded.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// The chunks waiting to be loaded, furthest first. Some of them may have been loaded some
+    /// other way since the queue was worked out, which `next` skips over.
+    pub fn queued_chunks(&self) -> &[ChunkCoord] {
+        &self.queue
+    }
+}
+
+fn horizontal_distance_squared(a: ChunkCoord, b: ChunkCoord) -> i64 {
+    let dx = (a.x - b.x) as i64;
+    let dz = (a.z - b.z) as i64;
+    dx * dx + dz * dz
+}
+
+fn square(distance: u32) -> i64 {
+    distance as i64 * distance as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use world::Chunk;
+
+    fn load_all(loader: &mut ChunkLoader, world: &mut World) -> Vec<ChunkCoord> {
+        let mut loaded = Vec::new();
+        while let Some(coord) = loader.next(world) {
+            world.insert_chunk(coord, Chunk::new());
+            loaded.push(coord);
+        }
+        loaded
+    }
+
+    #[test]
+    fn loads_a_disc_of_columns_around_the_camera() {
+        let mut world = World::new();
+        let mut loader = ChunkLoader::new(2, 0..2);
+        assert!(loader.update(&world, ChunkCoord::new(0, 0, 0)).is_empty());
+        load_all(&mut loader, &mut world);
+
+        // 13 columns are within 2 chunks of the middle one, each 2 chunks tall
+        assert_eq!(world.chunk_count(), 13 * 2);
+        assert!(world.contains_chunk(ChunkCoord::new(2, 1, 0)));
+        assert!(!world.contains_chunk(ChunkCoord::new(2, 0, 1)));
+        assert!(!world.contains_chunk(ChunkCoord::new(0, 2, 0)));
+    }
+
+    #[test]
+    fn nearest_chunks_load_first() {
+        let mut world = World::new();
+        let mut loader = ChunkLoader::new(3, 0..3);
+        let center = ChunkCoord::new(5, 1, -4);
+        loader.update(&world, center);
+        let loaded = load_all(&mut loader, &mut world);
+
+        assert_eq!(loaded[0], center);
+        let distance = |coord: ChunkCoord| {
+            let (dx, dy, dz) = (coord.x - center.x, coord.y - center.y, coord.z - center.z);
+            dx * dx + dy * dy + dz * dz
+        };
+        assert!(loaded.windows(2).all(|pair| distance(pair[0]) <= distance(pair[1])));
+    }
+
+    #[test]
+    fn chunks_left_behind_are_unloaded_past_the_margin() {
+        let mut world = World::new();
+        let mut loader = ChunkLoader::new(2, 0..1);
+        loader.update(&world, ChunkCoord::new(0, 0, 0));
+        load_all(&mut loader, &mut world);
+
+        // One chunk over, the ones two behind are still within the margin
+        assert!(loader.update(&world, ChunkCoord::new(1, 0, 0)).is_empty());
+        assert_eq!(loader.queued(), 5);
+
+        let mut unload = loader.update(&world, ChunkCoord::new(2, 0, 0));
+        unload.sort();
+        assert_eq!(
+            unload,
+            [ChunkCoord::new(-2, 0, 0), ChunkCoord::new(-1, 0, -1), ChunkCoord::new(-1, 0, 1)],
+        );
+    }
+
+    #[test]
+    fn only_moving_or_changing_the_distance_refills_the_queue() {
+        let mut world = World::new();
+        let mut loader = ChunkLoader::new(1, 0..1);
+        loader.update(&world, ChunkCoord::new(0, 0, 0));
+        assert_eq!(loader.queued(), 5);
+        world.insert_chunk(ChunkCoord::new(0, 0, 0), Chunk::new());
+        loader.update(&world, ChunkCoord::new(0, 0, 0));
+        assert_eq!(loader.queued(), 5);
+
+        loader.set_render_distance(0);
+        let unload = loader.update(&world, ChunkCoord::new(0, 0, 0));
+        assert!(unload.is_empty());
+        assert_eq!(loader.queued(), 0);
+    }
+}
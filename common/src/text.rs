@@ -0,0 +1,533 @@
+//! Text drawn over the finished frame, for debug readouts and labels, without going through
+//! ImGui.
+//!
+//! Text is laid out with rusttype, from a font built into the crate. The glyphs it needs are
+//! rasterized into a cache texture as they're first used, and kept there for as long as there's
+//! room. Each frame uploads whichever glyphs are new into the cache, then draws a quad for every
+//! glyph of the text queued since the last frame, in a render pass of its own on top of the
+//! swapchain image, like the overlay's.
+
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+
+use hal::{
+    buffer, command, format as f, image as i, memory, pass,
+    pso::{ self, PipelineStage },
+    Backend, Device, General, Primitive, SwapImageIndex,
+};
+use rusttype::gpu_cache::Cache;
+use rusttype::{ point, Font, PositionedGlyph, Rect, Scale };
+
+use color::is_srgb;
+use context::GfxContext;
+use descriptors::{ DescriptorAllocator, DescriptorSetLayout };
+use error::{ RendererError, Result };
+use pass::create_overlay_render_pass;
+use resources::{ Framebuffers, SwapchainBundle };
+use shader::create_shader_module;
+use staging::{ StagingRing, STAGING_ALIGNMENT };
+use texture::Texture;
+
+/// The SPIR-V for the shaders in `common/shaders/`, compiled by `build.rs`
+#[allow(dead_code)]
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// DejaVu Sans Mono. See `assets/DejaVuSansMono-LICENSE.txt`.
+const FONT: &[u8] = include_bytes!("../assets/DejaVuSansMono.ttf");
+
+/// The width and height of the glyph cache, in pixels. Enough for a few hundred glyphs at the
+/// sizes debug text is drawn at.
+const GLYPH_CACHE_SIZE: u32 = 512;
+
+/// Bytes per pixel of the glyph cache.
+const PIXEL_SIZE: u32 = 4;
+
+/// One corner of a glyph's quad.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct TextVertex {
+    /// In physical pixels from the top left of the window.
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+#[repr(C)]
+struct PushConstants {
+    scale: [f32; 2],
+    translate: [f32; 2],
+    srgb_target: f32,
+}
+
+impl PushConstants {
+    /// Push constants are written as a slice of 32 bit words.
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const PushConstants as *const u32,
+                mem::size_of::<PushConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `PushConstants`, in 32 bit words
+const PUSH_CONSTANTS_SIZE: u32 = (mem::size_of::<PushConstants>() / mem::size_of::<u32>()) as u32;
+
+/// Some text to draw this frame.
+struct TextSection {
+    text: String,
+    /// The top left corner of the first line, in logical pixels.
+    position: [f32; 2],
+    /// The height of a line, in logical pixels.
+    size: f32,
+    /// sRGB, with alpha.
+    color: [f32; 4],
+}
+
+/// How big the ring the glyphs' vertices, and the pixels of glyphs on their way into the cache,
+/// go in starts out, in bytes. It grows if a frame's don't fit.
+const TEXT_RING_SIZE: u64 = 256 * 1024;
+
+pub struct TextRenderer<B: Backend> {
+    device: Rc<B::Device>,
+    font: Font<'static>,
+    cache: Cache<'static>,
+    cache_texture: Texture<B>,
+    /// What rows copied into the cache texture have to be a multiple of, in bytes, less one.
+    row_alignment_mask: u32,
+    sections: Vec<TextSection>,
+    visible: bool,
+    set_layout: Rc<DescriptorSetLayout<B>>,
+    descriptors: DescriptorAllocator<B>,
+    descriptor_set: B::DescriptorSet,
+    render_pass: Option<B::RenderPass>,
+    pipeline_layout: Option<B::PipelineLayout>,
+    pipeline: Option<B::GraphicsPipeline>,
+    framebuffers: Framebuffers<B>,
+    /// This frame's vertices, and the pixels of any glyphs new to the cache.
+    ring: StagingRing<B>,
+    frames_in_flight: usize,
+}
+
+impl<B: Backend> TextRenderer<B> {
+    /// Loads the font, makes an empty glyph cache and builds the pipeline for drawing into
+    /// `swapchain`, with `frames_in_flight` frames drawing at once.
+    pub fn new(
+        context: &mut GfxContext<B>,
+        swapchain: &SwapchainBundle<B>,
+        frames_in_flight: usize,
+    ) -> Result<Self> {
+        let font = Font::from_bytes(FONT)
+            .map_err(|err| RendererError::Asset(format!("Failed to load the built in font: {}", err)))?;
+        let cache = Cache::builder().dimensions(GLYPH_CACHE_SIZE, GLYPH_CACHE_SIZE).build();
+        // Glyphs are drawn at 1:1, so the cache doesn't need any mips
+        let empty = vec![0; (GLYPH_CACHE_SIZE * GLYPH_CACHE_SIZE * PIXEL_SIZE) as usize];
+        let cache_texture = Texture::from_rgba8_with_mips(context, GLYPH_CACHE_SIZE, GLYPH_CACHE_SIZE, &empty, 1)?;
+        let limits = context.adapter.physical_device.limits();
+        let row_alignment_mask = limits.min_buffer_copy_pitch_alignment.max(1) as u32 - 1;
+
+        let device = context.device.clone();
+        let set_layout = Rc::new(DescriptorSetLayout::new(
+            device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut descriptors = DescriptorAllocator::new(device.clone(), set_layout.clone());
+        let descriptor_set = descriptors.allocate()?;
+        device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set: &descriptor_set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(cache_texture.view(), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &descriptor_set,
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(cache_texture.sampler())),
+            },
+        ]);
+
+        let render_pass = create_overlay_render_pass::<B>(&device, swapchain.format());
+        let pipeline_layout = device.create_pipeline_layout(
+            Some(set_layout.raw()),
+            &[(pso::ShaderStageFlags::VERTEX, 0..PUSH_CONSTANTS_SIZE)],
+        );
+        let pipeline = create_pipeline::<B>(
+            &device,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let framebuffers = Framebuffers::new(device.clone(), &render_pass, swapchain)?;
+        let ring = StagingRing::new(
+            device.clone(),
+            context.allocator.clone(),
+            TEXT_RING_SIZE,
+            buffer::Usage::VERTEX,
+        )?;
+
+        Ok(TextRenderer {
+            device,
+            font,
+            cache,
+            cache_texture,
+            row_alignment_mask,
+            sections: Vec::new(),
+            // Like the overlay, headless runs leave it out of their reference images
+            visible: !context.is_headless(),
+            set_layout,
+            descriptors,
+            descriptor_set,
+            render_pass: Some(render_pass),
+            pipeline_layout: Some(pipeline_layout),
+            pipeline: Some(pipeline),
+            framebuffers,
+            ring,
+            frames_in_flight,
+        })
+    }
+
+    /// Rebuilds the framebuffers after `swapchain` has been recreated.
+    pub fn recreate(&mut self, swapchain: &SwapchainBundle<B>) -> Result<()> {
+        self.framebuffers.recreate(self.render_pass.as_ref().unwrap(), swapchain)
+    }
+
+    /// How far apart lines of text `size` tall are, in the same units as `size`.
+    pub fn line_height(&self, size: f32) -> f32 {
+        let metrics = self.font.v_metrics(Scale::uniform(size));
+        metrics.ascent - metrics.descent + metrics.line_gap
+    }
+
+    /// How wide the widest line of `text` is at `size`, in the same units as `size`.
+    pub fn width(&self, text: &str, size: f32) -> f32 {
+        let glyphs = layout(&self.font, text, Scale::uniform(size), [0.0, 0.0]);
+        glyphs
+            .iter()
+            .map(|glyph| glyph.position().x + glyph.unpositioned().h_metrics().advance_width)
+            .fold(0.0, f32::max)
+    }
+
+    /// Queues `text` to be drawn this frame with the top left corner of its first line at
+    /// `position`, in logical pixels from the top left of the window, with lines `size` logical
+    /// pixels tall. `color` is sRGB, with alpha.
+    pub fn queue(&mut self, text: &str, position: [f32; 2], size: f32, color: [f32; 4]) {
+        self.sections.push(TextSection {
+            text: text.to_owned(),
+            position,
+            size,
+            color,
+        });
+    }
+
+    /// Uploads any glyphs the queued text needs that aren't cached yet, and records a render
+    /// pass drawing it all over swapchain image `image_index`. Call this once a frame. The
+    /// queue is empty again after.
+    pub fn draw(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        image_index: SwapImageIndex,
+        swapchain: &SwapchainBundle<B>,
+    ) -> Result<()> {
+        self.ring.begin_frame(self.frames_in_flight);
+        let sections = mem::replace(&mut self.sections, Vec::new());
+        if !self.visible || sections.is_empty() {
+            return Ok(());
+        }
+
+        // Everything is laid out in physical pixels, so it's rasterized at the size it's shown
+        let hidpi_factor = swapchain.hidpi_factor() as f32;
+        let laid_out: Vec<(Vec<PositionedGlyph<'static>>, [f32; 4])> = sections
+            .iter()
+            .map(|section| {
+                let position = [section.position[0] * hidpi_factor, section.position[1] * hidpi_factor];
+                let glyphs = layout(&self.font, &section.text, Scale::uniform(section.size * hidpi_factor), position);
+                (glyphs, section.color)
+            })
+            .collect();
+        for &(ref glyphs, _) in &laid_out {
+            for glyph in glyphs {
+                self.cache.queue_glyph(0, glyph.clone());
+            }
+        }
+        let mut uploads = Vec::new();
+        if let Err(err) = self.cache.cache_queued(|rect, coverage| uploads.push((rect, coverage.to_vec()))) {
+            warn!("Not all of this frame's text fits in the glyph cache: {:?}", err);
+        }
+        if !uploads.is_empty() {
+            self.upload_glyphs(command_buffer, &uploads)?;
+        }
+
+        let mut vertices = Vec::new();
+        for &(ref glyphs, color) in &laid_out {
+            for glyph in glyphs {
+                if let Ok(Some((uv, rect))) = self.cache.rect_for(0, glyph) {
+                    let corner = |x: i32, y: i32, u: f32, v: f32| TextVertex {
+                        position: [x as f32, y as f32],
+                        uv: [u, v],
+                        color,
+                    };
+                    let top_left = corner(rect.min.x, rect.min.y, uv.min.x, uv.min.y);
+                    let top_right = corner(rect.max.x, rect.min.y, uv.max.x, uv.min.y);
+                    let bottom_left = corner(rect.min.x, rect.max.y, uv.min.x, uv.max.y);
+                    let bottom_right = corner(rect.max.x, rect.max.y, uv.max.x, uv.max.y);
+                    vertices.extend_from_slice(&[top_left, top_right, bottom_right, bottom_right, bottom_left, top_left]);
+                }
+            }
+        }
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        let vertex_offset = self.ring.write(&vertices)?;
+
+        let pipeline_layout = self.pipeline_layout.as_ref().unwrap();
+        let viewport = swapchain.viewport();
+        let extent = swapchain.extent();
+        let push_constants = PushConstants {
+            scale: [2.0 / extent.width as f32, 2.0 / extent.height as f32],
+            translate: [-1.0, -1.0],
+            srgb_target: if is_srgb(swapchain.format()) { 1.0 } else { 0.0 },
+        };
+
+        command_buffer.set_viewports(0, &[viewport.clone()]);
+        command_buffer.set_scissors(0, &[viewport.rect]);
+        command_buffer.bind_graphics_pipeline(self.pipeline.as_ref().unwrap());
+        command_buffer.bind_graphics_descriptor_sets(pipeline_layout, 0, Some(&self.descriptor_set), &[]);
+        command_buffer.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(self.ring.buffer(), vertex_offset)]));
+
+        let mut encoder = command_buffer.begin_render_pass_inline(
+            self.render_pass.as_ref().unwrap(),
+            self.framebuffers.get(image_index),
+            viewport.rect,
+            &[],
+        );
+        encoder.push_graphics_constants(
+            pipeline_layout,
+            pso::ShaderStageFlags::VERTEX,
+            0,
+            push_constants.as_words(),
+        );
+        encoder.draw(0..vertices.len() as u32, 0..1);
+        Ok(())
+    }
+
+    /// Copies the glyphs rusttype has just rasterized into the cache texture, through the ring.
+    /// Each is an area of the cache along with how much of each
+    /// of its pixels the glyph covers, which goes in the alpha of a white pixel.
+    fn upload_glyphs(
+        &mut self,
+        command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+        uploads: &[(Rect<u32>, Vec<u8>)],
+    ) -> Result<()> {
+        let mask = self.row_alignment_mask;
+        let mut staging_data = Vec::new();
+        let mut copies = Vec::new();
+        for &(ref rect, ref coverage) in uploads {
+            let (width, height) = (rect.width(), rect.height());
+            if width == 0 || height == 0 {
+                continue;
+            }
+            let row_pitch = (width * PIXEL_SIZE + mask) & !mask;
+            let buffer_offset = staging_data.len() as u64;
+            for row in coverage.chunks(width as usize) {
+                let start = staging_data.len();
+                for &alpha in row {
+                    staging_data.extend_from_slice(&[255, 255, 255, alpha]);
+                }
+                staging_data.resize(start + row_pitch as usize, 0);
+            }
+            let aligned_len = (staging_data.len() as u32 + mask) & !mask;
+            staging_data.resize(aligned_len as usize, 0);
+
+            copies.push(command::BufferImageCopy {
+                buffer_offset,
+                buffer_width: row_pitch / PIXEL_SIZE,
+                buffer_height: height,
+                image_layers: i::SubresourceLayers {
+                    aspects: f::Aspects::COLOR,
+                    level: 0,
+                    layers: 0..1,
+                },
+                image_offset: i::Offset { x: rect.min.x as i32, y: rect.min.y as i32, z: 0 },
+                image_extent: i::Extent { width, height, depth: 1 },
+            });
+        }
+        if copies.is_empty() {
+            return Ok(());
+        }
+
+        // The rows are aligned from the start of the staging data, so it starts aligned too
+        let alignment = (mask as u64 + 1).max(STAGING_ALIGNMENT);
+        let start = self.ring.allocate_aligned(staging_data.len() as u64, alignment)?;
+        self.ring.write_at(start, &staging_data)?;
+        for copy in &mut copies {
+            copy.buffer_offset += start;
+        }
+
+        // The frames before this one might still be drawing text from the cache, which they
+        // have to be done with before it's written to, and this one can't until it's written
+        let image = self.cache_texture.image();
+        let range = i::SubresourceRange {
+            aspects: f::Aspects::COLOR,
+            levels: 0..1,
+            layers: 0..1,
+        };
+        command_buffer.pipeline_barrier(
+            PipelineStage::FRAGMENT_SHADER..PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (i::Access::SHADER_READ, i::Layout::ShaderReadOnlyOptimal)
+                    ..(i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal),
+                target: image,
+                range: range.clone(),
+            }],
+        );
+        command_buffer.copy_buffer_to_image(self.ring.buffer(), image, i::Layout::TransferDstOptimal, &copies);
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal)
+                    ..(i::Access::SHADER_READ, i::Layout::ShaderReadOnlyOptimal),
+                target: image,
+                range,
+            }],
+        );
+        debug!("Cached {} new glyphs", copies.len());
+        Ok(())
+    }
+}
+
+impl<B: Backend> Drop for TextRenderer<B> {
+    fn drop(&mut self) {
+        if let Some(pipeline) = self.pipeline.take() {
+            self.device.destroy_graphics_pipeline(pipeline);
+        }
+        if let Some(pipeline_layout) = self.pipeline_layout.take() {
+            self.device.destroy_pipeline_layout(pipeline_layout);
+        }
+        if let Some(render_pass) = self.render_pass.take() {
+            self.device.destroy_render_pass(render_pass);
+        }
+    }
+}
+
+/// Lays `text` out in `font` at `scale`, a line at a time, with the top left corner of the first
+/// line at `origin`.
+fn layout(font: &Font<'static>, text: &str, scale: Scale, origin: [f32; 2]) -> Vec<PositionedGlyph<'static>> {
+    let metrics = font.v_metrics(scale);
+    let line_height = metrics.ascent - metrics.descent + metrics.line_gap;
+    let mut glyphs = Vec::new();
+    for (index, line) in text.lines().enumerate() {
+        let baseline = point(origin[0], origin[1] + metrics.ascent + line_height * index as f32);
+        glyphs.extend(font.layout(line, scale, baseline).map(|glyph| glyph.standalone()));
+    }
+    glyphs
+}
+
+fn create_pipeline<B: Backend>(
+    device: &B::Device,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders::TEXT_VERT)?;
+    let fs_module = create_shader_module::<B>(device, shaders::TEXT_FRAG)?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(
+            pso::ColorMask::ALL,
+            pso::BlendState::ALPHA,
+        ));
+
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<TextVertex>() as u32,
+            rate: 0,
+        });
+        for (location, &(format, offset)) in [
+            (f::Format::Rg32Float, 0),
+            (f::Format::Rg32Float, 8),
+            (f::Format::Rgba32Float, 16),
+        ].iter().enumerate() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location: location as u32,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the text pipeline");
+    Ok(pipeline)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_line_starts_below_the_last() {
+        let font = Font::from_bytes(FONT).unwrap();
+        let glyphs = layout(&font, "ab\ncd", Scale::uniform(16.0), [10.0, 20.0]);
+        assert_eq!(glyphs.len(), 4);
+        assert_eq!(glyphs[0].position().x, 10.0);
+        assert_eq!(glyphs[2].position().x, 10.0);
+        assert!(glyphs[2].position().y > glyphs[0].position().y + 10.0);
+        assert_eq!(glyphs[0].position().y, glyphs[1].position().y);
+    }
+}
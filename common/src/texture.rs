@@ -0,0 +1,521 @@
+//! Loading images from disk into sampled textures.
+
+use std::cell::RefCell;
+use std::path::Path;
+use std::rc::Rc;
+
+use hal::{
+    buffer, command, format as f, image as i, memory,
+    pso::PipelineStage,
+    Backend, Device, General, PhysicalDevice,
+};
+
+use image;
+
+use allocator::{ Allocation, Allocator, MemoryCategory, ResourceKind };
+use buffer::DeviceBuffer;
+use color::{ linear_to_srgb, srgb_to_linear };
+use context::GfxContext;
+use error::{ RendererError, Result };
+use samplers::SamplerDesc;
+use transfer::UploadCopy;
+
+/// The base level of a single layer color image.
+pub const COLOR_RANGE: i::SubresourceRange = i::SubresourceRange {
+    aspects: f::Aspects::COLOR,
+    levels: 0..1,
+    layers: 0..1,
+};
+
+/// Bytes per pixel of the RGBA8 images we upload.
+const PIXEL_SIZE: u32 = 4;
+
+/// What `Texture::upload` makes.
+struct TextureDesc {
+    width: u32,
+    height: u32,
+    layers: i::Layer,
+    /// Whether it's viewed as an array, which it can be with only the one layer.
+    array: bool,
+    mip_levels: i::Level,
+    format: f::Format,
+    wrap: i::WrapMode,
+}
+
+/// The number of levels in a full mip chain for an image of the given size, down to 1x1.
+pub fn mip_levels_for(width: u32, height: u32) -> i::Level {
+    (32 - width.max(height).max(1).leading_zeros()) as i::Level
+}
+
+/// How many layers an array texture can have on any Vulkan device. Some have room for more.
+pub const MAX_ARRAY_LAYERS: usize = 256;
+
+/// Whether array textures of `layers` layers can be made in `format` and sampled with linear
+/// filtering, for `Texture::array_from_rgba8`.
+pub fn supports_texture_array<B: Backend>(physical_device: &B::PhysicalDevice, format: f::Format, layers: usize) -> bool {
+    let features = f::ImageFeature::SAMPLED | f::ImageFeature::SAMPLED_LINEAR;
+    layers <= MAX_ARRAY_LAYERS && physical_device.format_properties(Some(format)).optimal_tiling.contains(features)
+}
+
+fn mip_range(level: i::Level, layers: i::Layer) -> i::SubresourceRange {
+    i::SubresourceRange {
+        aspects: f::Aspects::COLOR,
+        levels: level..level + 1,
+        layers: 0..layers,
+    }
+}
+
+fn mip_layers(level: i::Level, layers: i::Layer) -> i::SubresourceLayers {
+    i::SubresourceLayers {
+        aspects: f::Aspects::COLOR,
+        level,
+        layers: 0..layers,
+    }
+}
+
+/// Fills in levels `1..mip_levels` of every one of the `layers` layers of `image` by repeatedly
+/// blitting each level into the next, leaving every level in `ShaderReadOnlyOptimal`. Expects
+/// the whole image to be in `TransferDstOptimal` with level 0 already written.
+fn record_mip_blits<B: Backend>(
+    command_buffer: &mut command::CommandBuffer<B, General, command::OneShot>,
+    image: &B::Image,
+    width: u32,
+    height: u32,
+    layers: i::Layer,
+    mip_levels: i::Level,
+) {
+    for level in 1..mip_levels {
+        let src_width = (width >> (level - 1)).max(1) as i32;
+        let src_height = (height >> (level - 1)).max(1) as i32;
+        let dst_width = (width >> level).max(1) as i32;
+        let dst_height = (height >> level).max(1) as i32;
+
+        // The previous level is done being written, so it becomes the blit source
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::TRANSFER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal)
+                    ..(i::Access::TRANSFER_READ, i::Layout::TransferSrcOptimal),
+                target: image,
+                range: mip_range(level - 1, layers),
+            }],
+        );
+
+        command_buffer.blit_image(
+            image,
+            i::Layout::TransferSrcOptimal,
+            image,
+            i::Layout::TransferDstOptimal,
+            i::Filter::Linear,
+            &[command::ImageBlit {
+                src_subresource: mip_layers(level - 1, layers),
+                src_bounds: i::Offset { x: 0, y: 0, z: 0 }..i::Offset { x: src_width, y: src_height, z: 1 },
+                dst_subresource: mip_layers(level, layers),
+                dst_bounds: i::Offset { x: 0, y: 0, z: 0 }..i::Offset { x: dst_width, y: dst_height, z: 1 },
+            }],
+        );
+
+        command_buffer.pipeline_barrier(
+            PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+            memory::Dependencies::empty(),
+            &[memory::Barrier::Image {
+                states: (i::Access::TRANSFER_READ, i::Layout::TransferSrcOptimal)
+                    ..(i::Access::SHADER_READ, i::Layout::ShaderReadOnlyOptimal),
+                target: image,
+                range: mip_range(level - 1, layers),
+            }],
+        );
+    }
+
+    // The last level was only ever written to
+    command_buffer.pipeline_barrier(
+        PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+        memory::Dependencies::empty(),
+        &[memory::Barrier::Image {
+            states: (i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal)
+                ..(i::Access::SHADER_READ, i::Layout::ShaderReadOnlyOptimal),
+            target: image,
+            range: mip_range(mip_levels - 1, layers),
+        }],
+    );
+}
+
+fn srgb8_to_linear(value: u8) -> f32 {
+    srgb_to_linear(unorm_to_float(value))
+}
+
+fn unorm_to_float(value: u8) -> f32 {
+    value as f32 / 255.0
+}
+
+fn float_to_unorm(value: f32) -> u8 {
+    (value * 255.0 + 0.5).max(0.0).min(255.0) as u8
+}
+
+fn linear_to_srgb8(value: f32) -> u8 {
+    float_to_unorm(linear_to_srgb(value))
+}
+
+/// Builds a mip chain of `mip_levels` levels on the cpu with a 2x2 box filter. Color channels are
+/// averaged in linear space, since averaging sRGB values directly makes the mips too dark.
+/// Returns `(width, height, pixels)` for every level, starting with the original image.
+pub fn generate_mips_cpu(width: u32, height: u32, pixels: &[u8], mip_levels: i::Level) -> Vec<(u32, u32, Vec<u8>)> {
+    downsample_mips(width, height, pixels, mip_levels, true)
+}
+
+/// `generate_mips_cpu`, for pixels that are either sRGB or already linear.
+fn downsample_mips(
+    width: u32,
+    height: u32,
+    pixels: &[u8],
+    mip_levels: i::Level,
+    srgb: bool,
+) -> Vec<(u32, u32, Vec<u8>)> {
+    let (decode, encode): (fn(u8) -> f32, fn(f32) -> u8) = if srgb {
+        (srgb8_to_linear, linear_to_srgb8)
+    } else {
+        (unorm_to_float, float_to_unorm)
+    };
+    let mut levels = vec![(width, height, pixels.to_vec())];
+
+    while levels.len() < mip_levels as usize {
+        let next = {
+            let &(src_width, src_height, ref src) = levels.last().unwrap();
+            let dst_width = (src_width / 2).max(1);
+            let dst_height = (src_height / 2).max(1);
+
+            let mut dst = Vec::with_capacity((dst_width * dst_height * PIXEL_SIZE) as usize);
+            for y in 0..dst_height {
+                for x in 0..dst_width {
+                    let mut sum = [0.0f32; 4];
+                    // Clamp so odd sized levels reuse their last row/column
+                    for &(sx, sy) in &[(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        let px = (x * 2 + sx).min(src_width - 1);
+                        let py = (y * 2 + sy).min(src_height - 1);
+                        let index = ((py * src_width + px) * PIXEL_SIZE) as usize;
+                        for c in 0..3 {
+                            sum[c] += decode(src[index + c]);
+                        }
+                        sum[3] += src[index + 3] as f32 / 255.0;
+                    }
+                    for c in 0..3 {
+                        dst.push(encode(sum[c] / 4.0));
+                    }
+                    dst.push((sum[3] / 4.0 * 255.0 + 0.5) as u8);
+                }
+            }
+            (dst_width, dst_height, dst)
+        };
+        levels.push(next);
+    }
+
+    levels
+}
+
+/// A device local image with a view and sampler, ready to be bound to a descriptor set and
+/// sampled from a shader.
+pub struct Texture<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    image: Option<B::Image>,
+    view: Option<B::ImageView>,
+    sampler: Rc<B::Sampler>,
+    allocation: Option<Allocation>,
+    width: u32,
+    height: u32,
+    layers: i::Layer,
+    mip_levels: i::Level,
+}
+
+impl<B: Backend> Texture<B> {
+    /// Loads a PNG or JPEG file (anything the `image` crate can decode, really) as an sRGB
+    /// texture.
+    pub fn load<P: AsRef<Path>>(context: &mut GfxContext<B>, path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let image = image::open(path)
+            .map_err(|err| RendererError::Asset(format!("Failed to load texture {}: {}", path.display(), err)))?
+            .to_rgba();
+        let (width, height) = image.dimensions();
+        Texture::from_rgba8(context, width, height, &image.into_raw())
+    }
+
+    /// Creates a texture from tightly packed 8 bit RGBA pixels in sRGB color space, with a full
+    /// mip chain.
+    ///
+    /// The mips are generated on the gpu by blitting each level down into the next one, which
+    /// needs the format to support linear filtering blits. If it doesn't, they're downsampled on
+    /// the cpu instead and uploaded along with the base level.
+    pub fn from_rgba8(context: &mut GfxContext<B>, width: u32, height: u32, pixels: &[u8]) -> Result<Self> {
+        Texture::from_rgba8_with_mips(context, width, height, pixels, mip_levels_for(width, height))
+    }
+
+    /// Like `from_rgba8`, but with only the first `mip_levels` levels of the mip chain.
+    pub fn from_rgba8_with_mips(
+        context: &mut GfxContext<B>,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+        mip_levels: i::Level,
+    ) -> Result<Self> {
+        let desc = TextureDesc {
+            width,
+            height,
+            layers: 1,
+            array: false,
+            mip_levels,
+            format: f::Format::Rgba8Srgb,
+            wrap: i::WrapMode::Clamp,
+        };
+        Texture::upload(context, desc, pixels)
+    }
+
+    /// Creates an array texture of `layers` sRGB images, each `width` by `height`, from their
+    /// tightly packed 8 bit RGBA pixels one after another, with a full mip chain for each. It
+    /// repeats across the surface it's sampled on, so a face can show several copies of a layer
+    /// without working out where it is in the layer first. Check `supports_texture_array` first.
+    pub fn array_from_rgba8(
+        context: &mut GfxContext<B>,
+        width: u32,
+        height: u32,
+        layers: i::Layer,
+        pixels: &[u8],
+    ) -> Result<Self> {
+        let desc = TextureDesc {
+            width,
+            height,
+            layers,
+            array: true,
+            mip_levels: mip_levels_for(width, height),
+            format: f::Format::Rgba8Srgb,
+            wrap: i::WrapMode::Tile,
+        };
+        Texture::upload(context, desc, pixels)
+    }
+
+    /// Creates a texture that repeats across the surface it's sampled on, from tightly packed
+    /// 8 bit RGBA pixels that aren't colors, like the directions in a normal map, with a full
+    /// mip chain.
+    pub fn tiling_unorm8(context: &mut GfxContext<B>, width: u32, height: u32, pixels: &[u8]) -> Result<Self> {
+        let desc = TextureDesc {
+            width,
+            height,
+            layers: 1,
+            array: false,
+            mip_levels: mip_levels_for(width, height),
+            format: f::Format::Rgba8Unorm,
+            wrap: i::WrapMode::Tile,
+        };
+        Texture::upload(context, desc, pixels)
+    }
+
+    fn upload(context: &mut GfxContext<B>, desc: TextureDesc, pixels: &[u8]) -> Result<Self> {
+        let TextureDesc { width, height, layers, array, mip_levels, format, wrap } = desc;
+        let layer_size = (width * height * PIXEL_SIZE) as usize;
+        assert_eq!(pixels.len(), layer_size * layers as usize);
+
+        let device = context.device.clone();
+        let mip_levels = mip_levels.max(1).min(mip_levels_for(width, height));
+
+        let blit_features = f::ImageFeature::BLIT_SRC | f::ImageFeature::BLIT_DST | f::ImageFeature::SAMPLED_LINEAR;
+        let gpu_mips = context.adapter.physical_device
+            .format_properties(Some(format))
+            .optimal_tiling
+            .contains(blit_features);
+
+        // Either just the base level, or every level if we have to make the mips ourselves. Each
+        // level has every layer's pixels, one after another.
+        let levels = if gpu_mips {
+            vec![(width, height, pixels.to_vec())]
+        } else {
+            let srgb = format == f::Format::Rgba8Srgb;
+            let layer_mips: Vec<_> = pixels
+                .chunks(layer_size)
+                .map(|layer| downsample_mips(width, height, layer, mip_levels, srgb))
+                .collect();
+            (0..mip_levels as usize)
+                .map(|level| {
+                    let (level_width, level_height, _) = layer_mips[0][level];
+                    let level_pixels = layer_mips.iter().flat_map(|mips| mips[level].2.iter().cloned()).collect();
+                    (level_width, level_height, level_pixels)
+                })
+                .collect()
+        };
+
+        // Rows in the staging buffer have to start at a multiple of the copy pitch alignment,
+        // which can be bigger than a row of our image.
+        let limits = context.adapter.physical_device.limits();
+        let row_alignment_mask = limits.min_buffer_copy_pitch_alignment.max(1) as u32 - 1;
+
+        let mut staging_data = Vec::new();
+        let mut copies = Vec::new();
+        for (level, &(level_width, level_height, ref level_pixels)) in levels.iter().enumerate() {
+            let row_pitch = (level_width * PIXEL_SIZE + row_alignment_mask) & !row_alignment_mask;
+            let buffer_offset = staging_data.len() as u64;
+
+            // The layers follow each other a level's worth of rows apart
+            for src in level_pixels.chunks((level_width * PIXEL_SIZE) as usize) {
+                let start = staging_data.len();
+                staging_data.extend_from_slice(src);
+                staging_data.resize(start + row_pitch as usize, 0);
+            }
+            // Keep the next level's offset aligned as well
+            let aligned_len = (staging_data.len() as u32 + row_alignment_mask) & !row_alignment_mask;
+            staging_data.resize(aligned_len as usize, 0);
+
+            copies.push(command::BufferImageCopy {
+                buffer_offset,
+                buffer_width: row_pitch / PIXEL_SIZE,
+                buffer_height: level_height,
+                image_layers: mip_layers(level as i::Level, layers),
+                image_offset: i::Offset { x: 0, y: 0, z: 0 },
+                image_extent: i::Extent { width: level_width, height: level_height, depth: 1 },
+            });
+        }
+
+        let staging = DeviceBuffer::new(
+            device.clone(),
+            context.allocator.clone(),
+            staging_data.len() as u64,
+            buffer::Usage::TRANSFER_SRC,
+            memory::Properties::CPU_VISIBLE,
+            MemoryCategory::Transient,
+        )?;
+        staging.write(&staging_data)?;
+
+        let unbound = device.create_image(
+            i::Kind::D2(width, height, layers, 1),
+            mip_levels,
+            format,
+            i::Tiling::Optimal,
+            i::Usage::TRANSFER_SRC | i::Usage::TRANSFER_DST | i::Usage::SAMPLED,
+            i::ViewCapabilities::empty(),
+        )?;
+        let requirements = device.get_image_requirements(&unbound);
+
+        let (image, allocation) = {
+            let mut allocator = context.allocator.borrow_mut();
+            let allocation = allocator
+                .allocate(
+                    &requirements,
+                    memory::Properties::DEVICE_LOCAL,
+                    ResourceKind::Optimal,
+                    MemoryCategory::Textures,
+                )?;
+            let image = device
+                .bind_image_memory(allocator.memory(&allocation), allocation.offset(), unbound)?;
+            (image, allocation)
+        };
+
+        let all_levels = i::SubresourceRange {
+            aspects: f::Aspects::COLOR,
+            levels: 0..mip_levels,
+            layers: 0..layers,
+        };
+
+        // The pixels are copied in on the transfer queue if there is one, and the mips are made
+        // on the graphics queue, which is the one that can blit
+        context.submit_upload(
+            &[UploadCopy::Image {
+                staging: staging.buffer(),
+                target: &image,
+                range: all_levels.clone(),
+                regions: &copies,
+            }],
+            |command_buffer| {
+                if gpu_mips {
+                    record_mip_blits::<B>(command_buffer, &image, width, height, layers, mip_levels);
+                } else {
+                    // Move the copied levels into a layout the fragment shader can sample from
+                    command_buffer.pipeline_barrier(
+                        PipelineStage::TRANSFER..PipelineStage::FRAGMENT_SHADER,
+                        memory::Dependencies::empty(),
+                        &[memory::Barrier::Image {
+                            states: (i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal)
+                                ..(i::Access::SHADER_READ, i::Layout::ShaderReadOnlyOptimal),
+                            target: &image,
+                            range: all_levels.clone(),
+                        }],
+                    );
+                }
+            },
+        )?;
+
+        let view_kind = if array { i::ViewKind::D2Array } else { i::ViewKind::D2 };
+        let view = device
+            .create_image_view(&image, view_kind, format, f::Swizzle::NO, all_levels)?;
+        // Anything with mips is drawn on surfaces in the world, and filtered the way the settings
+        // say. Images with only the one level are drawn flat on the screen, where filtering them
+        // linearly is all they need
+        let sampler_desc = if mip_levels > 1 {
+            let settings = context.config.settings();
+            SamplerDesc::for_filtering(settings.texture_filtering, wrap, settings.max_anisotropy)
+        } else {
+            SamplerDesc::new(i::Filter::Linear, wrap)
+        };
+        let sampler = context.samplers.borrow_mut().get(sampler_desc);
+        debug!(
+            "Uploaded a {}x{} texture with {} layers and {} mip levels, generated on the {}",
+            width,
+            height,
+            layers,
+            mip_levels,
+            if gpu_mips { "gpu" } else { "cpu" },
+        );
+
+        Ok(Texture {
+            device,
+            allocator: context.allocator.clone(),
+            image: Some(image),
+            view: Some(view),
+            sampler,
+            allocation: Some(allocation),
+            width,
+            height,
+            layers,
+            mip_levels,
+        })
+    }
+
+    pub fn image(&self) -> &B::Image {
+        self.image.as_ref().unwrap()
+    }
+
+    pub fn view(&self) -> &B::ImageView {
+        self.view.as_ref().unwrap()
+    }
+
+    pub fn sampler(&self) -> &B::Sampler {
+        &self.sampler
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn layers(&self) -> i::Layer {
+        self.layers
+    }
+
+    pub fn mip_levels(&self) -> i::Level {
+        self.mip_levels
+    }
+}
+
+impl<B: Backend> Drop for Texture<B> {
+    fn drop(&mut self) {
+        if let Some(view) = self.view.take() {
+            self.device.destroy_image_view(view);
+        }
+        if let Some(image) = self.image.take() {
+            self.device.destroy_image(image);
+        }
+        if let Some(allocation) = self.allocation.take() {
+            self.allocator.borrow_mut().free(allocation);
+        }
+    }
+}
@@ -0,0 +1,269 @@
+//! The time of day, which moves the sun across the sky and sets how bright everything is.
+//!
+//! The sun goes round once a day, rising in the east (+x), passing to the south of straight
+//! overhead at noon and setting in the west. Everything else follows from how high it is: the
+//! colour of the sky, how much ambient light there is, how strongly the sky light that
+//! `light::Lighting` works out lights things, and how much the sun itself shines on the faces
+//! turned towards it. Those are set at a few sun heights through night, dawn or dusk and day, and
+//! blended in between, so dawn and dusk look the same and mirror each other.
+
+use std::f32::consts::PI;
+use std::fmt;
+
+/// How far off straight overhead the sun is at noon, in radians, so that faces lit from the
+/// side still get some of it.
+const SUN_TILT: f32 = 0.4;
+
+/// How the sky looks with the sun at a given height.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Keyframe {
+    /// The height of the sun, from -1 straight down to 1 straight up.
+    height: f32,
+    sky_color: [f32; 3],
+    ambient: f32,
+    daylight: f32,
+    sun: f32,
+}
+
+/// From the middle of the night to the middle of the day, in order of height.
+const KEYFRAMES: [Keyframe; 4] = [
+    Keyframe { height: -0.25, sky_color: [0.01, 0.015, 0.04], ambient: 0.3, daylight: 0.2, sun: 0.0 },
+    Keyframe { height: 0.0, sky_color: [0.85, 0.5, 0.35], ambient: 0.35, daylight: 0.55, sun: 0.0 },
+    Keyframe { height: 0.1, sky_color: [0.85, 0.6, 0.45], ambient: 0.4, daylight: 0.8, sun: 0.6 },
+    Keyframe { height: 0.35, sky_color: [0.55, 0.75, 0.95], ambient: 0.45, daylight: 1.0, sun: 1.0 },
+];
+
+/// Which part of the day it is, for showing in the overlay.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DayPhase {
+    Night,
+    Dawn,
+    Day,
+    Dusk,
+}
+
+impl fmt::Display for DayPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            DayPhase::Night => "night",
+            DayPhase::Dawn => "dawn",
+            DayPhase::Day => "day",
+            DayPhase::Dusk => "dusk",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The time of day, and how fast it's going by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TimeOfDay {
+    /// How far through the day it is, from 0 at midnight through 0.25 at sunrise, 0.5 at noon
+    /// and 0.75 at sunset, up to but not including 1.
+    pub time: f32,
+    /// How many times faster than `day_length` the day goes by.
+    pub speed: f32,
+    pub paused: bool,
+    /// How long a day takes at a speed of 1, in seconds.
+    day_length: f32,
+}
+
+impl TimeOfDay {
+    /// Starts at `time`, as a fraction of the way through the day, with days `day_length`
+    /// seconds long.
+    pub fn new(time: f32, day_length: f32) -> Self {
+        let mut time_of_day = TimeOfDay { time: 0.0, speed: 1.0, paused: false, day_length: 1.0 };
+        time_of_day.set_time(time);
+        time_of_day.set_day_length(day_length);
+        time_of_day
+    }
+
+    /// Moves time on by `seconds`, unless it's paused.
+    pub fn advance(&mut self, seconds: f32) {
+        if !self.paused {
+            let time = self.time + seconds * self.speed / self.day_length;
+            self.set_time(time);
+        }
+    }
+
+    /// Sets the time, wrapping it round into a single day.
+    pub fn set_time(&mut self, time: f32) {
+        self.time = time - time.floor();
+        // Just under 1 can round up to it
+        if self.time >= 1.0 {
+            self.time = 0.0;
+        }
+    }
+
+    pub fn day_length(&self) -> f32 {
+        self.day_length
+    }
+
+    /// Sets how long a day takes at a speed of 1, in seconds. Anything under a second is
+    /// treated as a second.
+    pub fn set_day_length(&mut self, seconds: f32) {
+        self.day_length = seconds.max(1.0);
+    }
+
+    /// The time on a 24 hour clock, as hours and minutes.
+    pub fn clock(&self) -> (u32, u32) {
+        let minutes = (self.time * 24.0 * 60.0) as u32;
+        (minutes / 60 % 24, minutes % 60)
+    }
+
+    /// Towards the sun, as a unit vector. It's below the horizon at night.
+    pub fn sun_direction(&self) -> [f32; 3] {
+        // 0 at sunrise, a quarter turn at noon
+        let angle = (self.time - 0.25) * 2.0 * PI;
+        let (sin, cos) = angle.sin_cos();
+        [cos, sin * SUN_TILT.cos(), -sin * SUN_TILT.sin()]
+    }
+
+    pub fn phase(&self) -> DayPhase {
+        let height = self.sun_direction()[1];
+        if height < KEYFRAMES[0].height / 2.0 {
+            DayPhase::Night
+        } else if height < KEYFRAMES[2].height {
+            if self.time < 0.5 { DayPhase::Dawn } else { DayPhase::Dusk }
+        } else {
+            DayPhase::Day
+        }
+    }
+
+    /// The colour to clear the sky to.
+    pub fn sky_color(&self) -> [f32; 3] {
+        let (from, to, t) = self.keyframes();
+        let mut color = [0.0; 3];
+        for channel in 0..3 {
+            color[channel] = lerp(from.sky_color[channel], to.sky_color[channel], t);
+        }
+        color
+    }
+
+    /// How much of the light from the sky reaches faces turned away from the sun, from 0 to 1.
+    pub fn ambient(&self) -> f32 {
+        let (from, to, t) = self.keyframes();
+        lerp(from.ambient, to.ambient, t)
+    }
+
+    /// How strongly the sky lights blocks, from 0 to 1, to scale their sky light level by. It
+    /// never goes all the way to 0, since there's still a little light at night.
+    pub fn daylight(&self) -> f32 {
+        let (from, to, t) = self.keyframes();
+        lerp(from.daylight, to.daylight, t)
+    }
+
+    /// How brightly the sun shines on faces turned towards it, from 0 to 1. It's 0 from just
+    /// before sunset until just after sunrise.
+    pub fn sun_strength(&self) -> f32 {
+        let (from, to, t) = self.keyframes();
+        lerp(from.sun, to.sun, t)
+    }
+
+    /// The keyframes either side of the sun's height, and how far it is from the first to the
+    /// second.
+    fn keyframes(&self) -> (Keyframe, Keyframe, f32) {
+        let height = self.sun_direction()[1];
+        let last = KEYFRAMES.len() - 1;
+        if height <= KEYFRAMES[0].height {
+            return (KEYFRAMES[0], KEYFRAMES[0], 0.0);
+        }
+        for pair in KEYFRAMES.windows(2) {
+            if height < pair[1].height {
+                let t = (height - pair[0].height) / (pair[1].height - pair[0].height);
+                return (pair[0], pair[1], t);
+            }
+        }
+        (KEYFRAMES[last], KEYFRAMES[last], 0.0)
+    }
+}
+
+fn lerp(from: f32, to: f32, t: f32) -> f32 {
+    from + (to - from) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn the_sun_rises_in_the_east_and_is_highest_at_noon() {
+        let sunrise = TimeOfDay::new(0.25, 600.0).sun_direction();
+        assert!(close(sunrise[0], 1.0) && close(sunrise[1], 0.0));
+        let noon = TimeOfDay::new(0.5, 600.0).sun_direction();
+        assert!(close(noon[1], SUN_TILT.cos()));
+        assert!(TimeOfDay::new(0.0, 600.0).sun_direction()[1] < 0.0);
+        for &time in &[0.0, 0.1, 0.3, 0.6, 0.9] {
+            let direction = TimeOfDay::new(time, 600.0).sun_direction();
+            let length = direction.iter().map(|n| n * n).sum::<f32>().sqrt();
+            assert!(close(length, 1.0));
+            assert!(direction[1] <= noon[1] + 1e-6);
+        }
+    }
+
+    #[test]
+    fn time_wraps_round_and_can_be_paused() {
+        let mut time = TimeOfDay::new(0.9, 100.0);
+        time.advance(20.0);
+        assert!(close(time.time, 0.1));
+        time.speed = 10.0;
+        time.advance(5.0);
+        assert!(close(time.time, 0.6));
+        time.paused = true;
+        time.advance(50.0);
+        assert!(close(time.time, 0.6));
+        time.set_time(-0.25);
+        assert!(close(time.time, 0.75));
+    }
+
+    #[test]
+    fn the_clock_counts_24_hours() {
+        assert_eq!(TimeOfDay::new(0.0, 600.0).clock(), (0, 0));
+        assert_eq!(TimeOfDay::new(0.5, 600.0).clock(), (12, 0));
+        assert_eq!(TimeOfDay::new(0.75 + 0.5 / 24.0, 600.0).clock(), (18, 30));
+    }
+
+    #[test]
+    fn nights_are_dark_and_days_bright() {
+        let night = TimeOfDay::new(0.0, 600.0);
+        let noon = TimeOfDay::new(0.5, 600.0);
+        assert_eq!(night.phase(), DayPhase::Night);
+        assert_eq!(noon.phase(), DayPhase::Day);
+        assert_eq!(TimeOfDay::new(0.25, 600.0).phase(), DayPhase::Dawn);
+        assert_eq!(TimeOfDay::new(0.75, 600.0).phase(), DayPhase::Dusk);
+
+        assert_eq!(noon.sky_color(), KEYFRAMES[3].sky_color);
+        assert_eq!(night.sky_color(), KEYFRAMES[0].sky_color);
+        assert_eq!(noon.daylight(), 1.0);
+        assert!(night.daylight() > 0.0 && night.daylight() < noon.daylight());
+        assert!(night.ambient() < noon.ambient());
+        assert_eq!(night.sun_strength(), 0.0);
+        assert_eq!(noon.sun_strength(), 1.0);
+    }
+
+    #[test]
+    fn dawn_and_dusk_mirror_each_other() {
+        for &offset in &[0.01, 0.03, 0.08] {
+            let dawn = TimeOfDay::new(0.25 + offset, 600.0);
+            let dusk = TimeOfDay::new(0.75 - offset, 600.0);
+            for channel in 0..3 {
+                assert!(close(dawn.sky_color()[channel], dusk.sky_color()[channel]));
+            }
+            assert!(close(dawn.daylight(), dusk.daylight()));
+        }
+    }
+
+    #[test]
+    fn the_light_changes_smoothly() {
+        let mut previous = TimeOfDay::new(0.0, 600.0);
+        for step in 1..=1000 {
+            let time = TimeOfDay::new(step as f32 / 1000.0, 600.0);
+            assert!((time.daylight() - previous.daylight()).abs() < 0.02);
+            assert!((time.sky_color()[0] - previous.sky_color()[0]).abs() < 0.05);
+            previous = time;
+        }
+    }
+}
@@ -0,0 +1,124 @@
+//! Running the simulation at a fixed rate, however fast frames are rendered.
+//!
+//! Game logic (moving the player, spinning things, updating blocks) runs in ticks of exactly
+//! `TICK_SECONDS`, so it behaves the same at 30 fps as at 300. Each frame `FixedTimestep` says how
+//! many ticks are due to catch the simulation up with the clock, and how far the clock has got
+//! into the next one. Rendering then blends between the state before and after the last tick by
+//! that much, using `Interpolated`, so motion stays smooth when the frame rate and tick rate
+//! don't line up. The cost is that what's drawn is up to one tick behind the simulation.
+
+/// The length of one simulation tick: 60 per second.
+pub const TICK_SECONDS: f32 = 1.0 / 60.0;
+
+/// The most ticks run in one frame. After a long stall (a breakpoint, dragging the window) the
+/// simulation skips ahead rather than trying to catch up all at once, which would make the next
+/// frame slow too and never recover.
+pub const MAX_TICKS_PER_FRAME: u32 = 8;
+
+/// How close to a tick boundary, as a fraction of a tick, counts as reaching it. Headless runs
+/// step the clock by exactly one tick a frame, and rounding shouldn't be able to turn that into
+/// no ticks one frame and two the next.
+const TICK_EPSILON: f32 = 1e-3;
+
+#[derive(Clone, Debug, Default)]
+pub struct FixedTimestep {
+    /// How many ticks have been run.
+    ticks: u64,
+    /// Time skipped after stalls, which the simulation never caught up with.
+    skipped_seconds: f32,
+    /// How far into the next tick the clock is, from 0 to 1.
+    alpha: f32,
+}
+
+impl FixedTimestep {
+    pub fn new() -> Self {
+        FixedTimestep::default()
+    }
+
+    /// Catches up with the clock, which reads `seconds`, and returns how many ticks to run this
+    /// frame.
+    pub fn advance_to(&mut self, seconds: f32) -> u32 {
+        let simulated_seconds = seconds - self.skipped_seconds;
+        let due = (simulated_seconds / TICK_SECONDS + TICK_EPSILON).floor().max(0.0) as u64;
+        let mut ticks = due.saturating_sub(self.ticks);
+        if ticks > MAX_TICKS_PER_FRAME as u64 {
+            let skipped = ticks - MAX_TICKS_PER_FRAME as u64;
+            debug!("Simulation fell {} ticks behind, skipping them", skipped);
+            self.skipped_seconds += skipped as f32 * TICK_SECONDS;
+            ticks = MAX_TICKS_PER_FRAME as u64;
+        }
+        self.ticks += ticks;
+
+        let progress = (seconds - self.skipped_seconds) / TICK_SECONDS - self.ticks as f32;
+        self.alpha = progress.max(0.0).min(1.0);
+        ticks as u32
+    }
+
+    /// How far the clock is between the last tick and the next, from 0 to 1. Pass this to
+    /// `Interpolated::get` when rendering.
+    pub fn alpha(&self) -> f32 {
+        self.alpha
+    }
+
+    /// How many ticks have been run so far.
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+}
+
+/// Values that can be blended between two states.
+pub trait Lerp {
+    /// `self` when `alpha` is 0, `other` when it's 1, and in between otherwise.
+    fn lerp(&self, other: &Self, alpha: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(&self, other: &f32, alpha: f32) -> f32 {
+        self + (other - self) * alpha
+    }
+}
+
+impl Lerp for [f32; 3] {
+    fn lerp(&self, other: &[f32; 3], alpha: f32) -> [f32; 3] {
+        [
+            self[0].lerp(&other[0], alpha),
+            self[1].lerp(&other[1], alpha),
+            self[2].lerp(&other[2], alpha),
+        ]
+    }
+}
+
+/// A simulated value along with what it was before the last tick, for rendering in between.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Interpolated<T> {
+    previous: T,
+    current: T,
+}
+
+impl<T: Lerp + Copy> Interpolated<T> {
+    pub fn new(value: T) -> Self {
+        Interpolated { previous: value, current: value }
+    }
+
+    /// The value as of the last tick.
+    pub fn current(&self) -> T {
+        self.current
+    }
+
+    /// Sets the value for a new tick, keeping the old one to interpolate from.
+    pub fn set(&mut self, value: T) {
+        self.previous = self.current;
+        self.current = value;
+    }
+
+    /// Jumps straight to `value` without interpolating, for teleports and resets.
+    pub fn reset(&mut self, value: T) {
+        self.previous = value;
+        self.current = value;
+    }
+
+    /// The value to render, `alpha` of the way from the previous tick to the last one.
+    pub fn get(&self, alpha: f32) -> T {
+        self.previous.lerp(&self.current, alpha)
+    }
+}
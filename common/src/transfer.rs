@@ -0,0 +1,340 @@
+//! A queue of its own for copying uploads onto the gpu.
+//!
+//! Uploading a chunk mesh on the graphics queue means waiting for the copy before going on with
+//! the frame, and the copy itself waits behind whatever the gpu is already drawing. Many gpus
+//! have a queue family that can only copy, which runs on copy engines of its own alongside the
+//! drawing. When the adapter has one, the `GfxContext` opens a queue from it as well, and
+//! uploads go through the `TransferQueue` on it instead.
+//!
+//! Copies are recorded as they come in and submitted together right before the frame is,
+//! signalling a semaphore the frame's submission waits on before it reads any vertices. The
+//! staging buffers they copy out of are kept until every frame that could have waited on them
+//! is done, so the cpu doesn't wait for the copies at all. Most uploads are small enough to be
+//! staged in a `StagingRing` that's kept for good, where what each batch staged is given back
+//! along with the batch, and only the ones that don't fit in it get a staging buffer of their
+//! own. Copies that are recorded but not yet submitted, as when a frame gives up before it gets
+//! that far, keep their part of the ring however many frames go by.
+//!
+//! Handing a resource from one queue family to another is meant to go with a barrier on each
+//! queue, one releasing it and one acquiring it, but barriers in this version of `hal` can't
+//! name queue families. The semaphore orders the copies before the reads, which is all drivers
+//! need in practice, though strictly the contents are only defined with those barriers too.
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::mem;
+use std::rc::Rc;
+
+use hal::{
+    buffer, command, image as i, memory, pool,
+    pso::PipelineStage,
+    queue::{ QueueFamilyId, QueueType, Supports },
+    Adapter, Backend, Device, QueueFamily, QueueGroup, Submission, Transfer,
+};
+
+use allocator::{ Allocator, MemoryCategory };
+use buffer::DeviceBuffer;
+use error::Result;
+use staging::StagingRing;
+
+/// How big the ring uploads are staged in is, in bytes.
+const STAGING_RING_SIZE: u64 = 16 * 1024 * 1024;
+
+/// One copy out of a staging buffer, which can be recorded on either the transfer queue or the
+/// graphics queue.
+pub enum UploadCopy<'a, B: Backend> {
+    /// `region` of `staging` into `target`.
+    Buffer {
+        staging: &'a B::Buffer,
+        target: &'a B::Buffer,
+        region: command::BufferCopy,
+    },
+    /// `regions` of `staging` into `target`. Whatever was in `range` is thrown away, and it's
+    /// left in the layout for copying into, for the graphics queue to move on from.
+    Image {
+        staging: &'a B::Buffer,
+        target: &'a B::Image,
+        range: i::SubresourceRange,
+        regions: &'a [command::BufferImageCopy],
+    },
+}
+
+/// Records `copies` into `command_buffer`, in order.
+pub fn record_copies<B, C>(
+    command_buffer: &mut command::CommandBuffer<B, C, command::OneShot>,
+    copies: &[UploadCopy<B>],
+) where
+    B: Backend,
+    C: Supports<Transfer>,
+{
+    for copy in copies {
+        match *copy {
+            UploadCopy::Buffer { staging, target, ref region } => {
+                command_buffer.copy_buffer(staging, target, Some(region));
+            }
+            UploadCopy::Image { staging, target, ref range, regions } => {
+                command_buffer.pipeline_barrier(
+                    PipelineStage::TOP_OF_PIPE..PipelineStage::TRANSFER,
+                    memory::Dependencies::empty(),
+                    &[memory::Barrier::Image {
+                        states: (i::Access::empty(), i::Layout::Undefined)
+                            ..(i::Access::TRANSFER_WRITE, i::Layout::TransferDstOptimal),
+                        target,
+                        range: range.clone(),
+                    }],
+                );
+                command_buffer.copy_buffer_to_image(staging, target, i::Layout::TransferDstOptimal, regions);
+            }
+        }
+    }
+}
+
+/// The queue family to copy uploads on, if `adapter` has one that does nothing but copy. A
+/// family that can draw or compute as well is no better than the graphics queue's own.
+pub fn pick_transfer_family<B: Backend>(adapter: &Adapter<B>, graphics: QueueFamilyId) -> Option<&B::QueueFamily> {
+    adapter
+        .queue_families
+        .iter()
+        .find(|family| family.queue_type() == QueueType::Transfer && family.id() != graphics)
+}
+
+/// Copies submitted together, and what has to be kept until they're done.
+struct Batch<B: Backend> {
+    command_pool: pool::CommandPool<B, Transfer>,
+    staging: Vec<DeviceBuffer<B>>,
+    /// The batch of the staging ring the copies read from.
+    ring_batch: u64,
+    /// Waited on by the graphics submission, unless the batch was waited for with `finish`.
+    semaphore: Option<B::Semaphore>,
+    /// The frame it was submitted in, from `TransferQueue::begin_frame`.
+    frame: u64,
+}
+
+/// A queue from a family that only copies, and the uploads going through it.
+pub struct TransferQueue<B: Backend> {
+    device: Rc<B::Device>,
+    allocator: Rc<RefCell<Allocator<B>>>,
+    queue_group: QueueGroup<B, Transfer>,
+    /// The pool the copies recorded since the last submit came from, if there were any.
+    command_pool: Option<pool::CommandPool<B, Transfer>>,
+    recorded: Vec<command::Submit<B, Transfer, command::OneShot, command::Primary>>,
+    staging: Vec<DeviceBuffer<B>>,
+    staging_ring: StagingRing<B>,
+    in_flight: VecDeque<Batch<B>>,
+    frame: u64,
+}
+
+impl<B: Backend> TransferQueue<B> {
+    pub fn new(
+        device: Rc<B::Device>,
+        allocator: Rc<RefCell<Allocator<B>>>,
+        queue_group: QueueGroup<B, Transfer>,
+    ) -> Result<Self> {
+        let staging_ring = StagingRing::new(
+            device.clone(),
+            allocator.clone(),
+            STAGING_RING_SIZE,
+            buffer::Usage::TRANSFER_SRC,
+        )?;
+        Ok(TransferQueue {
+            device,
+            allocator,
+            queue_group,
+            command_pool: None,
+            recorded: Vec::new(),
+            staging: Vec::new(),
+            staging_ring,
+            in_flight: VecDeque::new(),
+            frame: 0,
+        })
+    }
+
+    /// The family the queue is from.
+    pub fn family(&self) -> QueueFamilyId {
+        self.queue_group.family()
+    }
+
+    /// Copies `data` into `target` at `offset` through the staging ring, or a staging buffer of
+    /// its own if there isn't room, as part of the next `submit`. Nothing can read it until then.
+    pub fn upload_into<T: Copy>(&mut self, data: &[T], target: &DeviceBuffer<B>, offset: u64) -> Result<()> {
+        let size = (data.len() * mem::size_of::<T>()) as u64;
+        assert!(offset + size <= target.size(), "Data does not fit in the buffer");
+
+        if let Some(src) = self.staging_ring.try_write(data)? {
+            let TransferQueue {
+                ref device,
+                ref queue_group,
+                ref mut command_pool,
+                ref mut recorded,
+                ref staging_ring,
+                ..
+            } = *self;
+            record_into(device, queue_group, command_pool, recorded, &[UploadCopy::Buffer {
+                staging: staging_ring.buffer(),
+                target: target.buffer(),
+                region: command::BufferCopy { src, dst: offset, size },
+            }]);
+            return Ok(());
+        }
+
+        let staging = DeviceBuffer::new(
+            self.device.clone(),
+            self.allocator.clone(),
+            size,
+            buffer::Usage::TRANSFER_SRC,
+            memory::Properties::CPU_VISIBLE,
+            MemoryCategory::Transient,
+        )?;
+        staging.write(data)?;
+        self.record(&[UploadCopy::Buffer {
+            staging: staging.buffer(),
+            target: target.buffer(),
+            region: command::BufferCopy { src: 0, dst: offset, size },
+        }]);
+        self.keep_until_done(staging);
+        Ok(())
+    }
+
+    /// Records `copies` to go in the next `submit`. The staging buffers they copy out of have to
+    /// be handed to `keep_until_done` afterwards.
+    pub fn record(&mut self, copies: &[UploadCopy<B>]) {
+        record_into(&self.device, &self.queue_group, &mut self.command_pool, &mut self.recorded, copies);
+    }
+
+    /// Holds on to `staging` until the copies recorded out of it are done.
+    pub fn keep_until_done(&mut self, staging: DeviceBuffer<B>) {
+        self.staging.push(staging);
+    }
+
+    /// Call right after `FrameSync::begin_frame`. Frees the batches submitted at least
+    /// `frames_in_flight` frames ago, since every frame that waited on them is done by now.
+    pub fn begin_frame(&mut self, frames_in_flight: usize) {
+        self.frame += 1;
+        while self
+            .in_flight
+            .front()
+            .map_or(false, |batch| batch.frame + frames_in_flight as u64 <= self.frame)
+        {
+            let batch = self.in_flight.pop_front().unwrap();
+            self.destroy_batch(batch);
+        }
+    }
+
+    /// Submits everything recorded since the last submit, and returns the semaphore it
+    /// signals, which the frame's submission has to wait on. `None` if nothing was recorded.
+    pub fn submit(&mut self) -> Option<&B::Semaphore> {
+        let command_pool = match self.command_pool.take() {
+            Some(command_pool) => command_pool,
+            None => return None,
+        };
+        let semaphore = self.device.create_semaphore();
+        {
+            let submission = Submission::new()
+                .signal(&[&semaphore])
+                .submit(self.recorded.drain(..));
+            self.queue_group.queues[0].submit(submission, None);
+        }
+        self.in_flight.push_back(Batch {
+            command_pool,
+            staging: mem::replace(&mut self.staging, Vec::new()),
+            ring_batch: self.staging_ring.end_batch(),
+            semaphore: Some(semaphore),
+            frame: self.frame,
+        });
+        self.in_flight.back().and_then(|batch| batch.semaphore.as_ref())
+    }
+
+    /// Submits anything still recorded and waits for the whole device to go idle, after which
+    /// every batch is done with. For before a buffer that copies go into is replaced.
+    pub fn finish(&mut self) -> Result<()> {
+        if let Some(command_pool) = self.command_pool.take() {
+            {
+                let submission = Submission::new().submit(self.recorded.drain(..));
+                self.queue_group.queues[0].submit(submission, None);
+            }
+            self.in_flight.push_back(Batch {
+                command_pool,
+                staging: mem::replace(&mut self.staging, Vec::new()),
+                ring_batch: self.staging_ring.end_batch(),
+                semaphore: None,
+                frame: self.frame,
+            });
+        }
+        self.device.wait_idle()?;
+        while let Some(batch) = self.in_flight.pop_front() {
+            self.destroy_batch(batch);
+        }
+        Ok(())
+    }
+
+    /// Copies `copies` right away, signalling `semaphore` once they're done, for loading things
+    /// that have more done to them on the graphics queue afterwards, like textures. Whatever
+    /// waits on the semaphore has to finish before the staging buffers are dropped, and before
+    /// the pool the copies were recorded from, which is returned, is destroyed.
+    pub fn copy_now(&mut self, copies: &[UploadCopy<B>], semaphore: &B::Semaphore) -> pool::CommandPool<B, Transfer> {
+        let mut command_pool = self.device.create_command_pool_typed(
+            &self.queue_group,
+            pool::CommandPoolCreateFlags::TRANSIENT,
+            1,
+        );
+        let finished_command_buffer = {
+            let mut command_buffer = command_pool.acquire_command_buffer(false);
+            record_copies(&mut command_buffer, copies);
+            command_buffer.finish()
+        };
+        let submission = Submission::new()
+            .signal(&[semaphore])
+            .submit(Some(finished_command_buffer));
+        self.queue_group.queues[0].submit(submission, None);
+        command_pool
+    }
+
+    fn destroy_batch(&mut self, batch: Batch<B>) {
+        self.staging_ring.release_through(batch.ring_batch);
+        self.device.destroy_command_pool(batch.command_pool.into_raw());
+        if let Some(semaphore) = batch.semaphore {
+            self.device.destroy_semaphore(semaphore);
+        }
+        // The staging buffers free themselves as they're dropped
+    }
+}
+
+/// Records `copies` into a command buffer from `command_pool`, making the pool if there isn't
+/// one yet, and adds it to `recorded`.
+fn record_into<B: Backend>(
+    device: &B::Device,
+    queue_group: &QueueGroup<B, Transfer>,
+    command_pool: &mut Option<pool::CommandPool<B, Transfer>>,
+    recorded: &mut Vec<command::Submit<B, Transfer, command::OneShot, command::Primary>>,
+    copies: &[UploadCopy<B>],
+) {
+    if command_pool.is_none() {
+        *command_pool = Some(device.create_command_pool_typed(
+            queue_group,
+            pool::CommandPoolCreateFlags::TRANSIENT,
+            16,
+        ));
+    }
+    let finished_command_buffer = {
+        let mut command_buffer = command_pool.as_mut().unwrap().acquire_command_buffer(false);
+        record_copies(&mut command_buffer, copies);
+        command_buffer.finish()
+    };
+    recorded.push(finished_command_buffer);
+}
+
+impl<B: Backend> Drop for TransferQueue<B> {
+    fn drop(&mut self) {
+        // Copies that were never submitted just go away with their pool
+        if let Some(command_pool) = self.command_pool.take() {
+            self.recorded.clear();
+            self.device.destroy_command_pool(command_pool.into_raw());
+        }
+        if let Err(err) = self.device.wait_idle() {
+            warn!("Couldn't wait for the transfer queue to finish: {:?}", err);
+        }
+        while let Some(batch) = self.in_flight.pop_front() {
+            self.destroy_batch(batch);
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Turning on the backends' validation layers with `--validation`, and getting their messages
+//! into the log.
+//!
+//! `hal` doesn't let us choose instance layers or install our own debug messenger, so each
+//! backend's validation is switched on from the outside before its instance is created. The
+//! Vulkan backend's debug callback passes every message it gets to the `log` crate at a level
+//! matching its severity, and `logging::init` lets them through when validation is on.
+
+use std::env;
+
+use backend::Backend;
+
+/// The layer that turns on all of the standard Vulkan validation.
+const VULKAN_VALIDATION_LAYER: &str = "VK_LAYER_LUNARG_standard_validation";
+
+/// How the entries in `VK_INSTANCE_LAYERS` are separated, the same as in `PATH`.
+#[cfg(windows)]
+const LAYER_SEPARATOR: &str = ";";
+#[cfg(not(windows))]
+const LAYER_SEPARATOR: &str = ":";
+
+/// Enables validation for `backend`. Has to be called before its instance is created.
+pub fn enable(backend: Backend) {
+    match backend {
+        Backend::Vulkan => {
+            // The loader adds any layers listed here to every instance it creates
+            let mut layers: Vec<String> = env::var("VK_INSTANCE_LAYERS")
+                .unwrap_or_default()
+                .split(LAYER_SEPARATOR)
+                .filter(|layer| !layer.is_empty())
+                .map(|layer| layer.to_owned())
+                .collect();
+            if !layers.iter().any(|layer| layer == VULKAN_VALIDATION_LAYER) {
+                layers.push(VULKAN_VALIDATION_LAYER.to_owned());
+            }
+            env::set_var("VK_INSTANCE_LAYERS", layers.join(LAYER_SEPARATOR));
+            info!("Enabled {}", VULKAN_VALIDATION_LAYER);
+        }
+        Backend::Dx12 => enable_d3d12_debug_layer(),
+        Backend::Metal => {
+            // Wraps the device in one that checks every call made through it
+            env::set_var("METAL_DEVICE_WRAPPER_TYPE", "1");
+            info!("Enabled Metal API validation");
+        }
+    }
+}
+
+/// The debug layer has to be enabled before any D3D12 device is created. Its messages go to the
+/// debugger's output window rather than through the backend, so they won't show up in our log.
+#[cfg(all(feature = "dx12", windows))]
+fn enable_d3d12_debug_layer() {
+    use std::ptr;
+
+    use winapi::shared::winerror;
+    use winapi::um::{ d3d12, d3d12sdklayers };
+    use winapi::Interface;
+
+    unsafe {
+        let mut debug: *mut d3d12sdklayers::ID3D12Debug = ptr::null_mut();
+        let hr = d3d12::D3D12GetDebugInterface(
+            &d3d12sdklayers::ID3D12Debug::uuidof(),
+            &mut debug as *mut *mut _ as *mut *mut _,
+        );
+        if winerror::SUCCEEDED(hr) {
+            (*debug).EnableDebugLayer();
+            (*debug).Release();
+            info!("Enabled the D3D12 debug layer");
+        } else {
+            warn!("Couldn't enable the D3D12 debug layer (is the Graphics Tools feature installed?)");
+        }
+    }
+}
+
+#[cfg(not(all(feature = "dx12", windows)))]
+fn enable_d3d12_debug_layer() {}
@@ -0,0 +1,206 @@
+//! A smaller layout for chunk vertices.
+//!
+//! `ChunkVertex` is all floats, 52 bytes a vertex, which is far more than it needs. A vertex is
+//! always on a block corner within its chunk, so each coordinate is a whole number from 0 to
+//! `CHUNK_SIZE`, and fits in a byte. The normal is only ever one of the six directions, the
+//! ambient occlusion one of four levels, the light twelfths of a level from 0 to `MAX_LIGHT`,
+//! and a quad's uvs count whole blocks across it. `PackedVertex` packs all of that into three
+//! 32 bit words, 12 bytes, and the vertex shader takes them apart again with shifts and masks.
+//!
+//! Which layout the chunks are uploaded in is the `vertex_format` setting. They're meshed as
+//! `ChunkVertex`es either way, and packed afterwards with `pack_vertices`.
+
+use std::fmt;
+use std::mem;
+
+use mesher::ChunkVertex;
+use world::{ Direction, CHUNK_SIZE, MAX_LIGHT };
+
+/// Has to match `LIGHT_STEPS` in `mesher`.
+const LIGHT_STEPS: f32 = MAX_LIGHT as f32 * 12.0;
+
+/// Which layout chunk vertices are uploaded in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VertexFormat {
+    /// `ChunkVertex`, as the meshers make them.
+    Full,
+    /// `PackedVertex`.
+    Packed,
+}
+
+impl VertexFormat {
+    pub const ALL: [VertexFormat; 2] = [VertexFormat::Full, VertexFormat::Packed];
+
+    /// How many bytes one vertex takes.
+    pub fn vertex_size(self) -> usize {
+        match self {
+            VertexFormat::Full => mem::size_of::<ChunkVertex>(),
+            VertexFormat::Packed => mem::size_of::<PackedVertex>(),
+        }
+    }
+}
+
+impl Default for VertexFormat {
+    fn default() -> Self {
+        VertexFormat::Full
+    }
+}
+
+impl fmt::Display for VertexFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            VertexFormat::Full => "full",
+            VertexFormat::Packed => "packed",
+        };
+        f.write_str(name)
+    }
+}
+
+/// A `ChunkVertex` in three words. Has to match the decoding in the packed vertex shader:
+///
+/// - The position in x, y and z, a byte each, then which way the face points, as its index in
+///   `Direction::ALL`, in 3 bits, and the ambient occlusion level in 2.
+/// - The uv in u and v, a byte each, then the tile in the top 16 bits.
+/// - The sky light and the block light, in twelfths of a level, a byte each, then the water
+///   depth in a byte.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PackedVertex {
+    pub words: [u32; 3],
+}
+
+impl PackedVertex {
+    /// Packs `vertex`, which has to be one the meshers made: on a block corner in its chunk,
+    /// facing along an axis.
+    pub fn pack(vertex: &ChunkVertex) -> PackedVertex {
+        let byte = |value: f32| {
+            debug_assert_eq!(value as u8 as f32, value, "{} doesn't fit in a byte", value);
+            value as u32
+        };
+        let direction = Direction::ALL
+            .iter()
+            .position(|direction| {
+                let offset = direction.offset();
+                (0..3).all(|axis| vertex.normal[axis] as i32 == offset[axis])
+            })
+            .expect("Chunk vertex normals point along an axis") as u32;
+        let ao = (vertex.ao * 3.0).round() as u32;
+        let light = |value: f32| (value * LIGHT_STEPS).round() as u32;
+
+        debug_assert!(vertex.position.iter().all(|&coordinate| coordinate <= CHUNK_SIZE as f32));
+
+        PackedVertex {
+            words: [
+                byte(vertex.position[0])
+                    | byte(vertex.position[1]) << 8
+                    | byte(vertex.position[2]) << 16
+                    | direction << 24
+                    | ao << 27,
+                byte(vertex.uv[0]) | byte(vertex.uv[1]) << 8 | vertex.tile << 16,
+                light(vertex.light[0]) | light(vertex.light[1]) << 8 | byte(vertex.water_depth) << 16,
+            ],
+        }
+    }
+
+    /// The vertex this was packed from, the way the shader decodes it.
+    pub fn unpack(&self) -> ChunkVertex {
+        let [first, second, third] = self.words;
+        let byte = |word: u32, shift: u32| ((word >> shift) & 0xff) as f32;
+        let offset = Direction::ALL[((first >> 24) & 7) as usize].offset();
+        ChunkVertex {
+            position: [byte(first, 0), byte(first, 8), byte(first, 16)],
+            normal: [offset[0] as f32, offset[1] as f32, offset[2] as f32],
+            uv: [byte(second, 0), byte(second, 8)],
+            tile: second >> 16,
+            ao: (first >> 27) as f32 / 3.0,
+            light: [byte(third, 0) / LIGHT_STEPS, byte(third, 8) / LIGHT_STEPS],
+            water_depth: byte(third, 16),
+        }
+    }
+}
+
+/// Packs every vertex of a mesh, in order, so its indices still point at the same corners.
+pub fn pack_vertices(vertices: &[ChunkVertex]) -> Vec<PackedVertex> {
+    vertices.iter().map(PackedVertex::pack).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use atlas::BlockTextureId;
+    use mesher::{ BlockTextures, ChunkNeighborhood, Mesher };
+    use world::{ BlockId, Chunk, Light };
+
+    /// A bumpy, partly lit chunk, so the meshes have every direction, ambient occlusion level
+    /// and a spread of light in them.
+    fn test_chunk() -> Chunk {
+        let mut chunk = Chunk::new();
+        for x in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                let height = 4 + (x * 7 + z * 3) % 5;
+                for y in 0..height {
+                    chunk.set([x, y, z], BlockId(1 + (x + z) as u16 % 3));
+                }
+                for y in height..CHUNK_SIZE {
+                    chunk.set_light([x, y, z], Light::new(MAX_LIGHT - (x % 4) as u8, (z % 6) as u8));
+                }
+            }
+        }
+        chunk
+    }
+
+    fn textures() -> BlockTextures {
+        let mut textures = BlockTextures::new();
+        textures.set_all(BlockId(1), BlockTextureId(3));
+        textures.set_top_bottom_sides(BlockId(2), BlockTextureId(4), BlockTextureId(5), BlockTextureId(300));
+        textures
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn packing_keeps_everything_the_meshers_make() {
+        let chunk = test_chunk();
+        for &mesher in &Mesher::ALL {
+            let mesh = mesher.mesh(&ChunkNeighborhood::new(&chunk), &textures());
+            assert!(!mesh.vertices.is_empty());
+            for vertex in &mesh.vertices {
+                let unpacked = PackedVertex::pack(vertex).unpack();
+                assert_eq!(unpacked.position, vertex.position);
+                assert_eq!(unpacked.normal, vertex.normal);
+                assert_eq!(unpacked.uv, vertex.uv);
+                assert_eq!(unpacked.tile, vertex.tile);
+                assert_close(unpacked.ao, vertex.ao);
+                assert_close(unpacked.light[0], vertex.light[0]);
+                assert_close(unpacked.light[1], vertex.light[1]);
+                assert_eq!(unpacked.water_depth, vertex.water_depth);
+            }
+        }
+    }
+
+    #[test]
+    fn the_fields_go_where_the_shader_looks() {
+        let vertex = ChunkVertex {
+            position: [32.0, 1.0, 2.0],
+            normal: [0.0, 0.0, -1.0],
+            uv: [5.0, 6.0],
+            tile: 7,
+            ao: 2.0 / 3.0,
+            light: [1.0, 3.0 / LIGHT_STEPS],
+            water_depth: 9.0,
+        };
+        let packed = PackedVertex::pack(&vertex);
+        assert_eq!(packed.words[0], 32 | 1 << 8 | 2 << 16 | 5 << 24 | 2 << 27);
+        assert_eq!(packed.words[1], 5 | 6 << 8 | 7 << 16);
+        assert_eq!(packed.words[2], 180 | 3 << 8 | 9 << 16);
+    }
+
+    #[test]
+    fn packed_vertices_are_a_quarter_of_the_size() {
+        assert_eq!(VertexFormat::Packed.vertex_size(), 12);
+        assert!(VertexFormat::Full.vertex_size() >= VertexFormat::Packed.vertex_size() * 4);
+    }
+}
@@ -0,0 +1,129 @@
+//! Debug views of the world, for seeing what the renderer is doing rather than what the world
+//! looks like.
+//!
+//! Every view but the normal one draws all of a chunk's faces the way opaque ones are drawn,
+//! translucent blocks and water included, so what they show is the whole mesh. The ones that
+//! show a value rather than a lit picture go to the screen as they are, without exposure,
+//! bloom or tonemapping, so a normal or an occlusion level reads the same however bright the
+//! scene around it was.
+
+use std::fmt;
+use std::str::FromStr;
+
+/// What the world is drawn as.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ViewMode {
+    /// Lit, the way it's meant to look.
+    Normal,
+    /// Lit, but only the edges of the triangles.
+    Wireframe,
+    /// Which way each face points, with x, y and z as red, green and blue.
+    Normals,
+    /// How many times each pixel is drawn, as a heatmap from blue for once up to red for many.
+    /// It has to be drawn with forward shading, since the G-buffer only keeps the nearest
+    /// surface.
+    Overdraw,
+    /// Only the ambient occlusion, baked into the mesh and from SSAO when there is any.
+    Occlusion,
+}
+
+impl ViewMode {
+    /// In the order `next` steps through them.
+    pub const ALL: [ViewMode; 5] = [
+        ViewMode::Normal,
+        ViewMode::Wireframe,
+        ViewMode::Normals,
+        ViewMode::Overdraw,
+        ViewMode::Occlusion,
+    ];
+
+    /// What the shaders know the view as.
+    pub fn id(self) -> u32 {
+        match self {
+            ViewMode::Normal => 0,
+            ViewMode::Wireframe => 1,
+            ViewMode::Normals => 2,
+            ViewMode::Overdraw => 3,
+            ViewMode::Occlusion => 4,
+        }
+    }
+
+    /// The view after this one, going back round to `Normal` after the last.
+    pub fn next(self) -> ViewMode {
+        let index = ViewMode::ALL.iter().position(|&mode| mode == self).unwrap();
+        ViewMode::ALL[(index + 1) % ViewMode::ALL.len()]
+    }
+
+    /// Whether the view is the lit scene, which goes through exposure and tonemapping like it
+    /// always does, rather than a value shown as it is.
+    pub fn is_lit(self) -> bool {
+        self == ViewMode::Normal || self == ViewMode::Wireframe
+    }
+}
+
+impl Default for ViewMode {
+    fn default() -> Self {
+        ViewMode::Normal
+    }
+}
+
+impl fmt::Display for ViewMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            ViewMode::Normal => "normal",
+            ViewMode::Wireframe => "wireframe",
+            ViewMode::Normals => "normals",
+            ViewMode::Overdraw => "overdraw",
+            ViewMode::Occlusion => "occlusion",
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for ViewMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<ViewMode, String> {
+        ViewMode::ALL
+            .iter()
+            .cloned()
+            .find(|mode| mode.to_string().eq_ignore_ascii_case(name))
+            .ok_or_else(|| {
+                format!(
+                    "Unknown view mode {:?}, expected normal, wireframe, normals, overdraw or occlusion",
+                    name,
+                )
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn next_steps_through_every_view_and_back() {
+        let mut mode = ViewMode::Normal;
+        for &expected in ViewMode::ALL.iter().skip(1) {
+            mode = mode.next();
+            assert_eq!(mode, expected);
+        }
+        assert_eq!(mode.next(), ViewMode::Normal);
+    }
+
+    #[test]
+    fn view_modes_parse_from_their_names() {
+        for &mode in &ViewMode::ALL {
+            assert_eq!(mode.to_string().parse::<ViewMode>(), Ok(mode));
+        }
+        assert_eq!("Overdraw".parse::<ViewMode>(), Ok(ViewMode::Overdraw));
+        assert!("xray".parse::<ViewMode>().is_err());
+    }
+
+    #[test]
+    fn ids_are_all_different() {
+        for (index, &mode) in ViewMode::ALL.iter().enumerate() {
+            assert!(ViewMode::ALL[index + 1..].iter().all(|&other| other.id() != mode.id()));
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! The ripples on the surface of water.
+//!
+//! Water faces are meshed with the rest of a chunk, into a list of their own, and drawn after
+//! everything opaque, blended over it. They don't use the atlas: what they look like comes from
+//! a normal map of ripples, sampled twice, scrolling in two different directions, so the
+//! surface never settles into a pattern. The tile that repeats across the water is generated
+//! here when the example starts, as the slopes of a sum of waves whose wavelengths all fit a
+//! whole number of times across it, which is what makes it wrap with no seam.
+//!
+//! How much of the colour behind the water comes through depends on how far the view goes
+//! through it, from how deep the mesher found the water to be and how steeply the surface is
+//! looked down at.
+
+use std::f32::consts::PI;
+
+use noise::{ derive_seed, Random };
+
+/// How many pixels across the ripple tile is.
+pub const RIPPLE_SIZE: u32 = 128;
+/// How many waves make up the ripples.
+const WAVES: usize = 24;
+/// The most times a wave repeats across the tile, along each axis.
+const MAX_WAVE_NUMBER: i32 = 9;
+/// How steep the ripples are. Each wave's slope is this over the square root of `WAVES`, so
+/// together they come to about this.
+const STEEPNESS: f32 = 0.35;
+
+/// A tile of ripple normals `size` pixels across, as 8 bit RGBA: x and y across the tile in red
+/// and green, and up out of it in blue, each from -1 at 0 to 1 at 255. Alpha is always 255.
+pub fn ripple_normals(seed: u64, size: u32) -> Vec<u8> {
+    let mut random = Random::new(derive_seed(seed, 401));
+    let mut waves = Vec::with_capacity(WAVES);
+    while waves.len() < WAVES {
+        let wave_number = [
+            random.range(-MAX_WAVE_NUMBER, MAX_WAVE_NUMBER + 1),
+            random.range(-MAX_WAVE_NUMBER, MAX_WAVE_NUMBER + 1),
+        ];
+        if wave_number == [0, 0] {
+            continue;
+        }
+        let phase = random.next_f32() * 2.0 * PI;
+        waves.push((wave_number, phase));
+    }
+    // Every wave is as steep as the others, so none of them drowns the rest out
+    let slope_scale = STEEPNESS / (WAVES as f32).sqrt();
+
+    let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+    for y in 0..size {
+        for x in 0..size {
+            let mut slope = [0.0f32; 2];
+            for &(wave_number, phase) in &waves {
+                let k = [
+                    2.0 * PI * wave_number[0] as f32 / size as f32,
+                    2.0 * PI * wave_number[1] as f32 / size as f32,
+                ];
+                let length = (k[0] * k[0] + k[1] * k[1]).sqrt();
+                let angle = k[0] * x as f32 + k[1] * y as f32 + phase;
+                slope[0] += k[0] / length * angle.cos();
+                slope[1] += k[1] / length * angle.cos();
+            }
+            let normal = normalize([-slope[0] * slope_scale, -slope[1] * slope_scale, 1.0]);
+            pixels.extend(normal.iter().map(|&n| to_byte(n * 0.5 + 0.5)));
+            pixels.push(255);
+        }
+    }
+    pixels
+}
+
+fn normalize(v: [f32; 3]) -> [f32; 3] {
+    let length = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    [v[0] / length, v[1] / length, v[2] / length]
+}
+
+fn to_byte(value: f32) -> u8 {
+    (value.max(0.0).min(1.0) * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn normal(pixels: &[u8], size: u32, x: u32, y: u32) -> [f32; 3] {
+        let index = ((y % size * size + x % size) * 4) as usize;
+        let decode = |byte: u8| byte as f32 / 255.0 * 2.0 - 1.0;
+        [decode(pixels[index]), decode(pixels[index + 1]), decode(pixels[index + 2])]
+    }
+
+    #[test]
+    fn ripples_point_mostly_up() {
+        let pixels = ripple_normals(3, 64);
+        assert_eq!(pixels.len(), 64 * 64 * 4);
+        for y in 0..64 {
+            for x in 0..64 {
+                let n = normal(&pixels, 64, x, y);
+                assert!(n[2] > 0.7, "{:?} at {}, {}", n, x, y);
+                let length = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+                assert!((length - 1.0).abs() < 0.02);
+            }
+        }
+    }
+
+    #[test]
+    fn ripples_wrap_without_a_seam() {
+        let size = 64;
+        let pixels = ripple_normals(3, size);
+        // Neighbouring pixels across the wrap differ no more than neighbours anywhere else
+        let step = |a: [f32; 3], b: [f32; 3]| (0..3).map(|i| (a[i] - b[i]).abs()).fold(0.0, f32::max);
+        let mut inside = 0.0f32;
+        let mut across = 0.0f32;
+        for i in 0..size {
+            for j in 0..size - 1 {
+                inside = inside.max(step(normal(&pixels, size, j, i), normal(&pixels, size, j + 1, i)));
+                inside = inside.max(step(normal(&pixels, size, i, j), normal(&pixels, size, i, j + 1)));
+            }
+            across = across.max(step(normal(&pixels, size, size - 1, i), normal(&pixels, size, size, i)));
+            across = across.max(step(normal(&pixels, size, i, size - 1), normal(&pixels, size, i, size)));
+        }
+        assert!(across <= inside + 1.0 / 255.0, "{} across the seam, {} inside", across, inside);
+    }
+
+    #[test]
+    fn ripples_come_from_the_seed() {
+        assert_eq!(ripple_normals(5, 32), ripple_normals(5, 32));
+        assert!(ripple_normals(5, 32) != ripple_normals(6, 32));
+    }
+}
@@ -0,0 +1,650 @@
+//! Blocks, the chunks they're stored in, and the world made out of chunks.
+//!
+//! The world is split into `CHUNK_SIZE` blocks cubed chunks, each meshed and drawn on its own.
+//! Blocks are addressed by world position as `[i32; 3]`, or within a chunk by local position
+//! from 0 to `CHUNK_SIZE - 1` on each axis. `ChunkCoord::of_block` converts between the two.
+//!
+//! Most chunks only use a handful of different blocks, and a lot are all air or all stone, so
+//! a `Chunk` stores a palette of the blocks it contains and, for each block, an index into that
+//! palette packed into as few bits as the palette needs. A chunk of a single block type has no
+//! per-block data at all.
+//!
+//! Chunks also hold how brightly each block is lit, which `light::Lighting` works out. That's
+//! a byte per block, and none at all while the whole chunk is lit the same, like a chunk of
+//! open sky or one deep underground.
+//!
+//! The world keeps each chunk behind an `Arc`, so meshing threads can hold on to the chunks a
+//! mesh is being built from without copying them. Changing a chunk only copies it when a mesh
+//! job still has the old version.
+
+use std::collections::{ HashMap, HashSet };
+use std::mem;
+use std::sync::Arc;
+
+/// How many blocks wide, tall and deep a chunk is.
+pub const CHUNK_SIZE: usize = 32;
+
+/// `log2(CHUNK_SIZE)`, for converting world positions with shifts and masks. Those round
+/// towards negative infinity, unlike division, which is what's needed for negative positions.
+pub const CHUNK_SHIFT: u32 = 5;
+
+/// How many blocks a chunk holds.
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+/// A type of block. What each one looks like is up to the renderer; the world only knows that
+/// `AIR` is empty space.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub u16);
+
+impl BlockId {
+    pub const AIR: BlockId = BlockId(0);
+
+    pub fn is_air(self) -> bool {
+        self == BlockId::AIR
+    }
+}
+
+/// The brightest a block can be lit.
+pub const MAX_LIGHT: u8 = 15;
+
+/// How brightly a block is lit by the sky and by blocks that give off light, each from 0 to
+/// `MAX_LIGHT`. They're kept apart so the sky's share can be dimmed at night.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Light(u8);
+
+impl Light {
+    /// Full sunlight and no block light, which is what blocks out in the open get.
+    pub const SKY: Light = Light(MAX_LIGHT << 4);
+
+    pub fn new(sky: u8, block: u8) -> Self {
+        debug_assert!(sky <= MAX_LIGHT && block <= MAX_LIGHT, "Light {}, {} is too bright", sky, block);
+        Light(sky << 4 | block)
+    }
+
+    pub fn sky(self) -> u8 {
+        self.0 >> 4
+    }
+
+    pub fn block(self) -> u8 {
+        self.0 & 0xf
+    }
+}
+
+/// One of the six directions along the axes, for neighbouring blocks, chunks and block faces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    PosX,
+    NegX,
+    PosY,
+    NegY,
+    PosZ,
+    NegZ,
+}
+
+impl Direction {
+    pub const ALL: [Direction; 6] = [
+        Direction::PosX,
+        Direction::NegX,
+        Direction::PosY,
+        Direction::NegY,
+        Direction::PosZ,
+        Direction::NegZ,
+    ];
+
+    /// Where this direction is in `ALL`, for arrays with one entry per direction.
+    pub fn index(self) -> usize {
+        match self {
+            Direction::PosX => 0,
+            Direction::NegX => 1,
+            Direction::PosY => 2,
+            Direction::NegY => 3,
+            Direction::PosZ => 4,
+            Direction::NegZ => 5,
+        }
+    }
+
+    /// One step in this direction.
+    pub fn offset(self) -> [i32; 3] {
+        match self {
+            Direction::PosX => [1, 0, 0],
+            Direction::NegX => [-1, 0, 0],
+            Direction::PosY => [0, 1, 0],
+            Direction::NegY => [0, -1, 0],
+            Direction::PosZ => [0, 0, 1],
+            Direction::NegZ => [0, 0, -1],
+        }
+    }
+
+    /// 0 for x, 1 for y and 2 for z.
+    pub fn axis(self) -> usize {
+        match self {
+            Direction::PosX | Direction::NegX => 0,
+            Direction::PosY | Direction::NegY => 1,
+            Direction::PosZ | Direction::NegZ => 2,
+        }
+    }
+
+    pub fn is_positive(self) -> bool {
+        match self {
+            Direction::PosX | Direction::PosY | Direction::PosZ => true,
+            _ => false,
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        match self {
+            Direction::PosX => Direction::NegX,
+            Direction::NegX => Direction::PosX,
+            Direction::PosY => Direction::NegY,
+            Direction::NegY => Direction::PosY,
+            Direction::PosZ => Direction::NegZ,
+            Direction::NegZ => Direction::PosZ,
+        }
+    }
+}
+
+/// Which chunk in the world, counted in chunks rather than blocks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ChunkCoord {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl ChunkCoord {
+    pub fn new(x: i32, y: i32, z: i32) -> Self {
+        ChunkCoord { x, y, z }
+    }
+
+    /// The chunk the block at world position `position` is in, and where it is in that chunk.
+    pub fn of_block(position: [i32; 3]) -> (ChunkCoord, [usize; 3]) {
+        let mask = CHUNK_SIZE as i32 - 1;
+        let coord = ChunkCoord::new(
+            position[0] >> CHUNK_SHIFT,
+            position[1] >> CHUNK_SHIFT,
+            position[2] >> CHUNK_SHIFT,
+        );
+        let local = [
+            (position[0] & mask) as usize,
+            (position[1] & mask) as usize,
+            (position[2] & mask) as usize,
+        ];
+        (coord, local)
+    }
+
+    /// The chunk containing the point `position`, like the camera, in world units.
+    pub fn of_point(position: [f32; 3]) -> ChunkCoord {
+        let block = [position[0].floor() as i32, position[1].floor() as i32, position[2].floor() as i32];
+        ChunkCoord::of_block(block).0
+    }
+
+    /// The world position of the chunk's block at local position 0, 0, 0.
+    pub fn origin(self) -> [i32; 3] {
+        [self.x << CHUNK_SHIFT, self.y << CHUNK_SHIFT, self.z << CHUNK_SHIFT]
+    }
+
+    /// The world position of the chunk's block at `local`.
+    pub fn block_position(self, local: [usize; 3]) -> [i32; 3] {
+        let origin = self.origin();
+        [origin[0] + local[0] as i32, origin[1] + local[1] as i32, origin[2] + local[2] as i32]
+    }
+
+    /// The chunk next to this one in `direction`.
+    pub fn neighbor(self, direction: Direction) -> ChunkCoord {
+        self.offset(direction.offset())
+    }
+
+    /// The chunk `offset` chunks away from this one.
+    pub fn offset(self, offset: [i32; 3]) -> ChunkCoord {
+        ChunkCoord::new(self.x + offset[0], self.y + offset[1], self.z + offset[2])
+    }
+}
+
+/// Every offset from a chunk to the 26 chunks around it and itself, in the order
+/// `surrounding_index` gives them.
+pub fn surrounding_offsets() -> impl Iterator<Item = [i32; 3]> {
+    (0..27).map(|index| [index % 3 - 1, index / 9 - 1, index / 3 % 3 - 1])
+}
+
+/// Where the chunk `offset` away from the middle one is in a 3 by 3 by 3 block of chunks, with
+/// each part of `offset` from -1 to 1. Like blocks, x varies fastest, then z, then y.
+pub fn surrounding_index(offset: [i32; 3]) -> usize {
+    debug_assert!(offset.iter().all(|&n| n >= -1 && n <= 1), "{:?} isn't next to the chunk", offset);
+    ((offset[1] + 1) * 9 + (offset[2] + 1) * 3 + offset[0] + 1) as usize
+}
+
+/// The index of the block at `local` in a chunk's block data. x varies fastest, then z, then y,
+/// so horizontal slices are contiguous.
+fn block_index(local: [usize; 3]) -> usize {
+    debug_assert!(local.iter().all(|&n| n < CHUNK_SIZE), "Local position {:?} is outside a chunk", local);
+    (local[1] * CHUNK_SIZE + local[2]) * CHUNK_SIZE + local[0]
+}
+
+/// A `CHUNK_SIZE` cube of blocks, stored as a palette and bit packed indices into it.
+#[derive(Clone, Debug)]
+pub struct Chunk {
+    /// Every block type in the chunk, and maybe some that were and aren't any more.
+    palette: Vec<BlockId>,
+    /// How many blocks use each palette entry. Entries no block uses get reused.
+    counts: Vec<u32>,
+    /// Bits per palette index: 0, 1, 2, 4, 8 or 16. Only powers of two are used so that an index
+    /// never straddles two words. With 0 bits there's no block data, and every block is
+    /// `palette[0]`.
+    bits: u32,
+    data: Vec<u64>,
+    /// The light at each block, in the same order as the blocks, or empty if every block has
+    /// `light_fill`. New chunks are lit like open sky until their light has been worked out.
+    light: Vec<Light>,
+    light_fill: Light,
+}
+
+impl Chunk {
+    /// A chunk that's all air.
+    pub fn new() -> Self {
+        Chunk::filled(BlockId::AIR)
+    }
+
+    /// A chunk that's all `block`.
+    pub fn filled(block: BlockId) -> Self {
+        Chunk {
+            palette: vec![block],
+            counts: vec![CHUNK_VOLUME as u32],
+            bits: 0,
+            data: Vec::new(),
+            light: Vec::new(),
+            light_fill: Light::SKY,
+        }
+    }
+
+    pub fn get(&self, local: [usize; 3]) -> BlockId {
+        self.palette[self.read(block_index(local))]
+    }
+
+    /// Sets the block at `local`, and returns what was there before.
+    pub fn set(&mut self, local: [usize; 3], block: BlockId) -> BlockId {
+        let index = block_index(local);
+        let old_entry = self.read(index);
+        let old_block = self.palette[old_entry];
+        if old_block == block {
+            return old_block;
+        }
+
+        let new_entry = self.palette_entry(block);
+        self.counts[old_entry] -= 1;
+        self.counts[new_entry] += 1;
+        self.write(index, new_entry);
+        old_block
+    }
+
+    /// Sets every block in the chunk to `block`, freeing its block data and forgetting its
+    /// light.
+    pub fn fill(&mut self, block: BlockId) {
+        *self = Chunk::filled(block);
+    }
+
+    pub fn light(&self, local: [usize; 3]) -> Light {
+        if self.light.is_empty() {
+            self.light_fill
+        } else {
+            self.light[block_index(local)]
+        }
+    }
+
+    /// Sets the light at `local`, and returns what it was before.
+    pub fn set_light(&mut self, local: [usize; 3], light: Light) -> Light {
+        if self.light.is_empty() {
+            if light == self.light_fill {
+                return light;
+            }
+            self.light = vec![self.light_fill; CHUNK_VOLUME];
+        }
+        mem::replace(&mut self.light[block_index(local)], light)
+    }
+
+    /// Sets the light at every block in the chunk, freeing its light data.
+    pub fn fill_light(&mut self, light: Light) {
+        self.light = Vec::new();
+        self.light_fill = light;
+    }
+
+    /// Frees the light data if every block turned out to be lit the same.
+    pub fn compact_light(&mut self) {
+        let uniform = match self.light.first() {
+            Some(&first) => self.light.iter().all(|&light| light == first),
+            None => false,
+        };
+        if uniform {
+            let fill = self.light[0];
+            self.fill_light(fill);
+        }
+    }
+
+    /// Whether every block is air, so there's nothing to mesh or draw.
+    pub fn is_empty(&self) -> bool {
+        self.palette
+            .iter()
+            .zip(self.counts.iter())
+            .all(|(block, &count)| block.is_air() || count == 0)
+    }
+
+    /// Whether any block in the chunk is `block`. This only looks at the palette, so it's
+    /// cheap enough to skip chunks with a whole lot of scanning ahead of them.
+    pub fn contains(&self, block: BlockId) -> bool {
+        self.palette
+            .iter()
+            .zip(self.counts.iter())
+            .any(|(&entry, &count)| entry == block && count > 0)
+    }
+
+    /// The block types the chunk contains, along with how many of each there are.
+    pub fn block_counts(&self) -> Vec<(BlockId, u32)> {
+        self.palette
+            .iter()
+            .cloned()
+            .zip(self.counts.iter().cloned())
+            .filter(|&(_, count)| count > 0)
+            .collect()
+    }
+
+    /// Roughly how many bytes the chunk takes up, for the overlay's memory stats.
+    pub fn memory_bytes(&self) -> usize {
+        mem::size_of::<Chunk>()
+            + self.palette.capacity() * mem::size_of::<BlockId>()
+            + self.counts.capacity() * mem::size_of::<u32>()
+            + self.data.capacity() * mem::size_of::<u64>()
+            + self.light.capacity() * mem::size_of::<Light>()
+    }
+
+    /// The palette entry for `block`, adding one if it isn't in the palette yet.
+    fn palette_entry(&mut self, block: BlockId) -> usize {
+        if let Some(entry) = self.palette.iter().position(|&b| b == block) {
+            return entry;
+        }
+        if let Some(entry) = self.counts.iter().position(|&count| count == 0) {
+            self.palette[entry] = block;
+            return entry;
+        }
+
+        self.palette.push(block);
+        self.counts.push(0);
+        let needed = bits_for(self.palette.len());
+        if needed > self.bits {
+            self.repack(needed);
+        }
+        self.palette.len() - 1
+    }
+
+    fn indices_per_word(bits: u32) -> usize {
+        64 / bits as usize
+    }
+
+    fn read(&self, index: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = Chunk::indices_per_word(self.bits);
+        let shift = (index % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        ((self.data[index / per_word] >> shift) & mask) as usize
+    }
+
+    fn write(&mut self, index: usize, entry: usize) {
+        let per_word = Chunk::indices_per_word(self.bits);
+        let shift = (index % per_word) as u32 * self.bits;
+        let mask = (1u64 << self.bits) - 1;
+        let word = &mut self.data[index / per_word];
+        *word = (*word & !(mask << shift)) | ((entry as u64) << shift);
+    }
+
+    /// Rewrites the block data with `bits` bits per index.
+    fn repack(&mut self, bits: u32) {
+        let entries: Vec<usize> = (0..CHUNK_VOLUME).map(|index| self.read(index)).collect();
+        self.bits = bits;
+        self.data = vec![0; CHUNK_VOLUME / Chunk::indices_per_word(bits)];
+        for (index, entry) in entries.into_iter().enumerate() {
+            self.write(index, entry);
+        }
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Self {
+        Chunk::new()
+    }
+}
+
+/// Chunks are equal when they hold the same blocks, whatever order their palettes are in and
+/// however they're lit.
+impl PartialEq for Chunk {
+    fn eq(&self, other: &Chunk) -> bool {
+        if self.palette == other.palette && self.bits == other.bits && self.data == other.data {
+            return true;
+        }
+        (0..CHUNK_VOLUME).all(|index| self.palette[self.read(index)] == other.palette[other.read(index)])
+    }
+}
+
+/// The fewest bits per index, out of the sizes `Chunk` uses, that can address `palette_len`
+/// entries.
+fn bits_for(palette_len: usize) -> u32 {
+    [0, 1, 2, 4, 8]
+        .iter()
+        .cloned()
+        .find(|&bits| palette_len <= 1 << bits)
+        .unwrap_or(16)
+}
+
+/// Every loaded chunk, and which of them need remeshing or saving.
+#[derive(Default)]
+pub struct World {
+    chunks: HashMap<ChunkCoord, Arc<Chunk>>,
+    /// Chunks whose blocks, or whose neighbours' blocks next to them, have changed since their
+    /// mesh was last built.
+    dirty: HashSet<ChunkCoord>,
+    /// Chunks whose blocks have changed since they were last saved, or that have never been.
+    unsaved: HashSet<ChunkCoord>,
+    /// Chunks that have been added since their light was last worked out.
+    unlit: HashSet<ChunkCoord>,
+    /// Where `set_block` has changed blocks since `take_block_changes`.
+    block_changes: Vec<[i32; 3]>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        World::default()
+    }
+
+    pub fn chunk(&self, coord: ChunkCoord) -> Option<&Chunk> {
+        self.chunks.get(&coord).map(|chunk| &**chunk)
+    }
+
+    /// The chunk at `coord`, to change directly. Blocks set this way don't mark anything dirty
+    /// or unsaved, so call `mark_dirty` and `mark_unsaved` afterwards; `set_block` is simpler
+    /// for a few blocks at a time.
+    pub fn chunk_mut(&mut self, coord: ChunkCoord) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&coord).map(Arc::make_mut)
+    }
+
+    pub fn contains_chunk(&self, coord: ChunkCoord) -> bool {
+        self.chunks.contains_key(&coord)
+    }
+
+    /// Adds a chunk, replacing any that was already at `coord`. It's marked dirty along with
+    /// its neighbours, whose faces against it might now be hidden or showing, or shaded
+    /// differently by its blocks' ambient occlusion, and it's marked unlit.
+    pub fn insert_chunk(&mut self, coord: ChunkCoord, chunk: Chunk) -> Option<Chunk> {
+        let old = self.chunks.insert(coord, Arc::new(chunk)).map(unshare);
+        self.unlit.insert(coord);
+        self.mark_dirty(coord);
+        self.mark_neighbors_dirty(coord);
+        old
+    }
+
+    /// Unloads a chunk. Its neighbours are marked dirty, since their faces against it have
+    /// nothing to be hidden by any more. Any changes that haven't been saved are lost, so check
+    /// `is_unsaved` first.
+    pub fn remove_chunk(&mut self, coord: ChunkCoord) -> Option<Chunk> {
+        let old = self.chunks.remove(&coord).map(unshare);
+        self.dirty.remove(&coord);
+        self.unsaved.remove(&coord);
+        self.unlit.remove(&coord);
+        if old.is_some() {
+            self.mark_neighbors_dirty(coord);
+        }
+        old
+    }
+
+    /// The chunk at `coord` and the 26 around it, in the order `surrounding_index` gives them,
+    /// or `None` for those that aren't loaded.
+    pub fn surrounding(&self, coord: ChunkCoord) -> [Option<&Chunk>; 27] {
+        let mut chunks = [None; 27];
+        for (chunk, offset) in chunks.iter_mut().zip(surrounding_offsets()) {
+            *chunk = self.chunk(coord.offset(offset));
+        }
+        chunks
+    }
+
+    /// Like `surrounding`, but sharing the chunks rather than borrowing them, so they can be
+    /// sent to another thread. They stay as they are now even if the world changes.
+    pub fn surrounding_shared(&self, coord: ChunkCoord) -> [Option<Arc<Chunk>>; 27] {
+        let mut chunks: [Option<Arc<Chunk>>; 27] = Default::default();
+        for (chunk, offset) in chunks.iter_mut().zip(surrounding_offsets()) {
+            *chunk = self.chunks.get(&coord.offset(offset)).cloned();
+        }
+        chunks
+    }
+
+    /// The block at world position `position`, or `None` if its chunk isn't loaded.
+    pub fn block(&self, position: [i32; 3]) -> Option<BlockId> {
+        let (coord, local) = ChunkCoord::of_block(position);
+        self.chunks.get(&coord).map(|chunk| chunk.get(local))
+    }
+
+    /// Sets the block at world position `position`, and returns what was there before. Returns
+    /// `None` and does nothing if its chunk isn't loaded.
+    ///
+    /// Changing a block marks its chunk dirty and unsaved, and marks any neighbouring chunk it
+    /// touches dirty as well, even only at an edge or a corner. A block on the edge of a chunk
+    /// can hide or uncover faces in the chunk next door, and change the ambient occlusion of
+    /// faces diagonally across from it. The change is also kept for `take_block_changes`.
+    pub fn set_block(&mut self, position: [i32; 3], block: BlockId) -> Option<BlockId> {
+        let (coord, local) = ChunkCoord::of_block(position);
+        let old = match self.chunks.get_mut(&coord) {
+            Some(chunk) => Arc::make_mut(chunk).set(local, block),
+            None => return None,
+        };
+        if old != block {
+            self.unsaved.insert(coord);
+            self.block_changes.push(position);
+            self.mark_touching_dirty(coord, local);
+        }
+        Some(old)
+    }
+
+    /// The light at world position `position`, or `None` if its chunk isn't loaded.
+    pub fn light(&self, position: [i32; 3]) -> Option<Light> {
+        let (coord, local) = ChunkCoord::of_block(position);
+        self.chunks.get(&coord).map(|chunk| chunk.light(local))
+    }
+
+    /// Sets the light at world position `position`, and returns what it was before. Returns
+    /// `None` and does nothing if its chunk isn't loaded. A change marks the same chunks dirty
+    /// as changing the block would, since faces take their light from the blocks in front of
+    /// them, but it doesn't need saving.
+    pub fn set_light(&mut self, position: [i32; 3], light: Light) -> Option<Light> {
+        let (coord, local) = ChunkCoord::of_block(position);
+        let old = match self.chunks.get_mut(&coord) {
+            Some(chunk) => Arc::make_mut(chunk).set_light(local, light),
+            None => return None,
+        };
+        if old != light {
+            self.mark_touching_dirty(coord, local);
+        }
+        Some(old)
+    }
+
+    /// Marks the chunk at `coord` dirty, along with every neighbour the block at `local` in it
+    /// touches.
+    fn mark_touching_dirty(&mut self, coord: ChunkCoord, local: [usize; 3]) {
+        // Along each axis the block touches the chunk before, the chunk after, or neither
+        let touching = |axis: usize, offset: i32| match offset {
+            -1 => local[axis] == 0,
+            1 => local[axis] == CHUNK_SIZE - 1,
+            _ => true,
+        };
+        for offset in surrounding_offsets() {
+            if (0..3).all(|axis| touching(axis, offset[axis])) {
+                self.mark_dirty(coord.offset(offset));
+            }
+        }
+    }
+
+    /// Flags a chunk to be remeshed. Chunks that aren't loaded are ignored.
+    pub fn mark_dirty(&mut self, coord: ChunkCoord) {
+        if self.chunks.contains_key(&coord) {
+            self.dirty.insert(coord);
+        }
+    }
+
+    fn mark_neighbors_dirty(&mut self, coord: ChunkCoord) {
+        for offset in surrounding_offsets() {
+            if offset != [0, 0, 0] {
+                self.mark_dirty(coord.offset(offset));
+            }
+        }
+    }
+
+    pub fn is_dirty(&self, coord: ChunkCoord) -> bool {
+        self.dirty.contains(&coord)
+    }
+
+    /// Every dirty chunk, clearing their flags. The caller is expected to remesh them all.
+    pub fn take_dirty(&mut self) -> Vec<ChunkCoord> {
+        self.dirty.drain().collect()
+    }
+
+    /// Flags a chunk to be saved. Chunks that aren't loaded are ignored.
+    pub fn mark_unsaved(&mut self, coord: ChunkCoord) {
+        if self.chunks.contains_key(&coord) {
+            self.unsaved.insert(coord);
+        }
+    }
+
+    pub fn is_unsaved(&self, coord: ChunkCoord) -> bool {
+        self.unsaved.contains(&coord)
+    }
+
+    /// Every unsaved chunk, clearing their flags. The caller is expected to save them all.
+    pub fn take_unsaved(&mut self) -> Vec<ChunkCoord> {
+        self.unsaved.drain().collect()
+    }
+
+    /// Every chunk added since the last call, in order of their coordinates. The caller is
+    /// expected to light them all.
+    pub fn take_unlit(&mut self) -> Vec<ChunkCoord> {
+        let mut unlit: Vec<ChunkCoord> = self.unlit.drain().collect();
+        unlit.sort();
+        unlit
+    }
+
+    /// Where `set_block` has changed blocks since the last call, in the order they were
+    /// changed. Changes to chunks that have since been unloaded are left out.
+    pub fn take_block_changes(&mut self) -> Vec<[i32; 3]> {
+        let mut changes = mem::replace(&mut self.block_changes, Vec::new());
+        changes.retain(|&position| self.chunks.contains_key(&ChunkCoord::of_block(position).0));
+        changes
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = (ChunkCoord, &Chunk)> {
+        self.chunks.iter().map(|(&coord, chunk)| (coord, &**chunk))
+    }
+}
+
+/// Takes a chunk back out of its `Arc`, copying it if a mesh job is still using it.
+fn unshare(chunk: Arc<Chunk>) -> Chunk {
+    Arc::try_unwrap(chunk).unwrap_or_else(|chunk| (*chunk).clone())
+}
@@ -0,0 +1,552 @@
+//! Generating terrain from a seed.
+//!
+//! The ground is a heightmap of two layers of 2D noise: broad, gentle rises and falls, with
+//! smaller hills on top. The biome, from `biome::BiomeMap`, raises or lowers it and makes the
+//! hills flatter or rougher, blended near borders so the ground doesn't jump, and picks what
+//! the top few blocks are made of. Near the surface a 3D noise pushes the ground in and out of the
+//! heightmap, which is what makes overhangs and floating bits that a heightmap alone can't.
+//! Underground, tunnels are carved where two more 3D noises are both close to zero, which
+//! happens along winding lines. Whatever is left open below `SEA_LEVEL`, apart from the caves,
+//! is filled with water, and the ground just above and below it is sand.
+//!
+//! The 3D noises are what's slow, so they're only sampled every `LATTICE_STEP` blocks, and
+//! blended between samples for the blocks in between. They're smooth at that scale anyway.
+//!
+//! Every block of terrain only depends on the seed and its position, so chunks can be generated
+//! in any order, on any thread, and always come out the same. On top of that a decoration pass,
+//! from `decoration`, adds trees where the biome grows them and cave worms that wander further
+//! than the noise tunnels. Those reach into the chunks around them, which is what
+//! `generate_into` and `PendingEdits` are for.
+
+use biome::{ Biome, BiomeMap, BiomeSample };
+use decoration::{ self, FeatureWriter, PendingEdits, Spill };
+use noise::{ derive_seed, hash_position, Fbm, Random };
+use world::{ BlockId, Chunk, ChunkCoord, World, CHUNK_SIZE };
+
+/// How many chunks tall the world is, starting from chunk row 0. Nothing is ever generated
+/// above it.
+pub const HEIGHT_IN_CHUNKS: i32 = 5;
+/// The height of the top of the sea: the first block above it is air.
+pub const SEA_LEVEL: i32 = 46;
+
+/// The height the heightmap is centred on, in blocks.
+const BASE_HEIGHT: f32 = 52.0;
+/// How far the broad layer of the heightmap reaches above and below `BASE_HEIGHT`.
+const CONTINENT_HEIGHT: f32 = 28.0;
+/// How far the hills reach above and below the broad layer.
+const HILL_HEIGHT: f32 = 10.0;
+/// How many blocks the 3D noise can push the ground in or out of the heightmap.
+const OVERHANG_DEPTH: f32 = 10.0;
+/// How many blocks of dirt, or sand in the desert, lie under the surface block.
+const DIRT_DEPTH: i32 = 3;
+/// How high mountains have to be to be capped with snow.
+const SNOW_LINE: f32 = 80.0;
+/// How far above `SEA_LEVEL` the plains and forests are sand instead of grass.
+const BEACH_HEIGHT: f32 = 2.0;
+/// How close to zero both cave noises have to be for a block to be carved out. Bigger makes
+/// wider tunnels.
+const CAVE_WIDTH: f32 = 0.07;
+/// The lowest blocks are never carved out, so caves have a floor.
+const CAVE_FLOOR: i32 = 2;
+/// How many blocks apart the 3D noises are sampled. Has to divide `CHUNK_SIZE`.
+const LATTICE_STEP: usize = 4;
+/// The chance each chunk has of starting a cave worm.
+const WORM_CHANCE: f32 = 0.3;
+/// How far under the surface a worm has to start, so it doesn't just scrape along under the
+/// grass.
+const WORM_COVER: i32 = 8;
+
+/// The blocks the generator builds the world out of. It only places them, so what they look
+/// like is up to the caller.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TerrainBlocks {
+    pub stone: BlockId,
+    pub dirt: BlockId,
+    pub grass: BlockId,
+    pub sand: BlockId,
+    pub snow: BlockId,
+    pub log: BlockId,
+    pub leaves: BlockId,
+    pub water: BlockId,
+}
+
+impl TerrainBlocks {
+    /// The block on top of the ground and the ones just under it, for a column of `biome` whose
+    /// surface is at `height`.
+    fn surface(&self, biome: Biome, height: f32) -> (BlockId, BlockId) {
+        match biome {
+            Biome::Plains | Biome::Forest if height < SEA_LEVEL as f32 + BEACH_HEIGHT => (self.sand, self.sand),
+            Biome::Plains | Biome::Forest => (self.grass, self.dirt),
+            Biome::Desert => (self.sand, self.sand),
+            Biome::Mountains if height >= SNOW_LINE => (self.snow, self.stone),
+            Biome::Mountains => (self.stone, self.stone),
+        }
+    }
+}
+
+/// What one column of the world is like, worked out once for all the blocks in it.
+struct Column {
+    /// The height of the heightmap.
+    height: f32,
+    biomes: BiomeSample,
+}
+
+/// A grass block with air above it, that a tree could grow on.
+struct TreeSite {
+    position: [i32; 3],
+    /// The chance of a tree growing there, from the biome.
+    density: f32,
+}
+
+/// A chunk with its decorations, and the edits they made to the chunks around it.
+pub struct GeneratedChunk {
+    pub chunk: Chunk,
+    pub spill: Spill,
+}
+
+pub struct WorldGenerator {
+    seed: u64,
+    blocks: TerrainBlocks,
+    biomes: BiomeMap,
+    continents: Fbm,
+    hills: Fbm,
+    overhangs: Fbm,
+    caves: [Fbm; 2],
+}
+
+impl WorldGenerator {
+    pub fn new(seed: u64, blocks: TerrainBlocks) -> Self {
+        let noise = |salt, octaves, frequency| Fbm::new(derive_seed(seed, salt), octaves, frequency);
+        WorldGenerator {
+            seed,
+            blocks,
+            biomes: BiomeMap::new(seed),
+            continents: noise(1, 3, 1.0 / 400.0),
+            hills: noise(2, 4, 1.0 / 80.0),
+            overhangs: noise(3, 3, 1.0 / 28.0),
+            caves: [noise(4, 2, 1.0 / 64.0), noise(5, 2, 1.0 / 64.0)],
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// The height of the heightmap at block column `x`, `z`: the first block above it is air,
+    /// unless the 3D noise has filled it in. Handy for putting the camera somewhere sensible.
+    pub fn height_at(&self, x: i32, z: i32) -> i32 {
+        self.column(x, z).height.round() as i32
+    }
+
+    /// The biome the column at `x`, `z` mostly belongs to.
+    pub fn biome_at(&self, x: i32, z: i32) -> Biome {
+        self.biomes.sample(x, z).biome
+    }
+
+    fn column(&self, x: i32, z: i32) -> Column {
+        let biomes = self.biomes.sample(x, z);
+        let terrain = biomes.terrain();
+        let (x, z) = (x as f32, z as f32);
+        let height = BASE_HEIGHT
+            + self.continents.get2(x, z) * CONTINENT_HEIGHT
+            + terrain.height_offset
+            + self.hills.get2(x, z) * HILL_HEIGHT * terrain.hill_scale;
+        Column { height, biomes }
+    }
+
+    /// Whether a block `y` high is ground, before caves are carved out of it. `height` is the
+    /// height of its column and `overhang` the overhang noise there.
+    fn is_ground(y: i32, height: f32, overhang: f32) -> bool {
+        y as f32 + 0.5 - height < overhang * OVERHANG_DEPTH
+    }
+
+    /// Whether a block `y` high is carved out, given the two cave noises there.
+    fn is_cave(y: i32, caves: [f32; 2]) -> bool {
+        y >= CAVE_FLOOR && caves.iter().all(|noise| noise.abs() < CAVE_WIDTH)
+    }
+
+    /// The highest anything can be generated, in blocks.
+    fn max_height() -> i32 {
+        let biome = Biome::ALL
+            .iter()
+            .map(|biome| biome.terrain())
+            .map(|terrain| terrain.height_offset + HILL_HEIGHT * terrain.hill_scale)
+            .fold(0.0, f32::max);
+        (BASE_HEIGHT + CONTINENT_HEIGHT + biome + OVERHANG_DEPTH).ceil() as i32
+    }
+
+    /// Generates the terrain of the chunk at `coord`, without any decorations.
+    pub fn generate(&self, coord: ChunkCoord) -> Chunk {
+        self.terrain(coord, &mut Vec::new())
+    }
+
+    /// Generates the chunk at `coord` and its decorations. Only the decorations that started in
+    /// it are there: the ones from the chunks around it are in their `spill`s.
+    pub fn generate_decorated(&self, coord: ChunkCoord) -> GeneratedChunk {
+        let mut sites = Vec::new();
+        let mut chunk = self.terrain(coord, &mut sites);
+        let spill = {
+            let mut writer = FeatureWriter::new(coord, &mut chunk, 0..HEIGHT_IN_CHUNKS);
+            self.carve_worms(&mut writer, coord);
+            for site in sites {
+                let mut random = Random::new(hash_position(derive_seed(self.seed, 202), site.position));
+                // A worm might have carved the ground out from under it
+                let still_grass = writer.get(site.position) == Some(self.blocks.grass);
+                if still_grass && random.next_f32() < site.density {
+                    let base = [site.position[0], site.position[1] + 1, site.position[2]];
+                    decoration::plant_tree(&mut writer, base, self.blocks.log, self.blocks.leaves, &mut random);
+                }
+            }
+            writer.finish()
+        };
+        GeneratedChunk { chunk, spill }
+    }
+
+    /// Generates the chunk at `coord` and adds it to `world`, unsaved, with the edits other
+    /// chunks' decorations have made to it. Its own decorations' edits to other chunks are
+    /// recorded in `pending`, and made straight away to those that are already loaded. The ones
+    /// for chunks that aren't are returned, for a world that's saved to make to its saved
+    /// chunks.
+    pub fn generate_into(&self, world: &mut World, pending: &mut PendingEdits, coord: ChunkCoord) -> Spill {
+        let GeneratedChunk { mut chunk, spill } = self.generate_decorated(coord);
+        pending.apply(coord, &mut chunk);
+        world.insert_chunk(coord, chunk);
+        world.mark_unsaved(coord);
+
+        let mut unloaded = Vec::new();
+        for &(target, ref edits) in &spill {
+            if !world.contains_chunk(target) {
+                unloaded.push((target, edits.clone()));
+                continue;
+            }
+            for edit in edits {
+                let position = target.block_position(edit.local());
+                if world.block(position).map_or(false, |existing| edit.applies_over(existing)) {
+                    world.set_block(position, edit.block);
+                }
+            }
+        }
+        pending.insert(coord, &spill);
+        unloaded
+    }
+
+    /// Starts a cave worm in the chunk at `coord`, if it gets one.
+    fn carve_worms(&self, writer: &mut FeatureWriter, coord: ChunkCoord) {
+        let mut random = Random::new(hash_position(derive_seed(self.seed, 201), [coord.x, coord.y, coord.z]));
+        if random.next_f32() >= WORM_CHANCE {
+            return;
+        }
+        let start = decoration::random_position(coord, &mut random);
+        if start[1] <= CAVE_FLOOR || start[1] > self.height_at(start[0], start[2]) - WORM_COVER {
+            return;
+        }
+        let start = [start[0] as f32 + 0.5, start[1] as f32 + 0.5, start[2] as f32 + 0.5];
+        decoration::carve_worm(writer, start, CAVE_FLOOR, &mut random);
+    }
+
+    /// Generates the terrain of the chunk at `coord`, adding the places trees could grow in it
+    /// to `sites`.
+    fn terrain(&self, coord: ChunkCoord, sites: &mut Vec<TreeSite>) -> Chunk {
+        let mut chunk = Chunk::new();
+        let origin = coord.origin();
+        if origin[1] > WorldGenerator::max_height() || coord.y < 0 || coord.y >= HEIGHT_IN_CHUNKS {
+            return chunk;
+        }
+
+        // The lattices reach a little above the chunk, so the top few blocks in it can tell
+        // whether they're near the surface
+        let overhangs = Lattice::sample(origin, |x, y, z| self.overhangs.get3(x, y, z));
+        // Squashed vertically, so tunnels wander sideways more than up and down
+        let caves = [
+            Lattice::sample(origin, |x, y, z| self.caves[0].get3(x, y * 2.0, z)),
+            Lattice::sample(origin, |x, y, z| self.caves[1].get3(x, y * 2.0, z)),
+        ];
+
+        for z in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let position = coord.block_position([x, 0, z]);
+                let column = self.column(position[0], position[2]);
+                let height = column.height;
+                let (top_block, under_block) = self.blocks.surface(column.biomes.biome, height);
+                let tree_density = column.biomes.terrain().tree_density;
+
+                // How many blocks of ground in a row there are down to and including this one
+                let mut depth = 0;
+                for y in (CHUNK_SIZE..CHUNK_SIZE + DIRT_DEPTH as usize + 1).rev() {
+                    let ground = WorldGenerator::is_ground(origin[1] + y as i32, height, overhangs.get(x, y, z));
+                    depth = if ground { depth + 1 } else { 0 };
+                }
+
+                for y in (0..CHUNK_SIZE).rev() {
+                    let wy = origin[1] + y as i32;
+                    if !WorldGenerator::is_ground(wy, height, overhangs.get(x, y, z)) {
+                        depth = 0;
+                        if wy <= SEA_LEVEL {
+                            chunk.set([x, y, z], self.blocks.water);
+                        }
+                        continue;
+                    }
+                    depth += 1;
+                    if WorldGenerator::is_cave(wy, [caves[0].get(x, y, z), caves[1].get(x, y, z)]) {
+                        continue;
+                    }
+                    let block = if depth == 1 {
+                        top_block
+                    } else if depth <= 1 + DIRT_DEPTH {
+                        under_block
+                    } else {
+                        self.blocks.stone
+                    };
+                    chunk.set([x, y, z], block);
+                    if block == self.blocks.grass && tree_density > 0.0 {
+                        sites.push(TreeSite { position: coord.block_position([x, y, z]), density: tree_density });
+                    }
+                }
+            }
+        }
+        chunk
+    }
+}
+
+/// The points the lattice has along x and z, and along y, where it reaches one step above the
+/// chunk.
+const LATTICE_POINTS: usize = CHUNK_SIZE / LATTICE_STEP + 1;
+const LATTICE_POINTS_Y: usize = LATTICE_POINTS + 1;
+
+/// A noise sampled every `LATTICE_STEP` blocks over a chunk, and a step above it.
+struct Lattice {
+    /// Indexed by `(y * LATTICE_POINTS + z) * LATTICE_POINTS + x`.
+    values: Vec<f32>,
+}
+
+impl Lattice {
+    /// Samples `noise` at the lattice points of the chunk whose first block is at `origin`.
+    fn sample<F: Fn(f32, f32, f32) -> f32>(origin: [i32; 3], noise: F) -> Self {
+        let mut values = Vec::with_capacity(LATTICE_POINTS * LATTICE_POINTS * LATTICE_POINTS_Y);
+        for y in 0..LATTICE_POINTS_Y {
+            for z in 0..LATTICE_POINTS {
+                for x in 0..LATTICE_POINTS {
+                    let position = [x, y, z];
+                    let point = |axis: usize| (origin[axis] + (position[axis] * LATTICE_STEP) as i32) as f32;
+                    values.push(noise(point(0), point(1), point(2)));
+                }
+            }
+        }
+        Lattice { values }
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f32 {
+        self.values[(y * LATTICE_POINTS + z) * LATTICE_POINTS + x]
+    }
+
+    /// The noise at local block `x`, `y`, `z`, blended from the 8 lattice points around it.
+    fn get(&self, x: usize, y: usize, z: usize) -> f32 {
+        let cell = |value: usize| (value / LATTICE_STEP, (value % LATTICE_STEP) as f32 / LATTICE_STEP as f32);
+        let ((x0, tx), (y0, ty), (z0, tz)) = (cell(x), cell(y), cell(z));
+        let lerp = |t: f32, a: f32, b: f32| a + t * (b - a);
+        let row = |y: usize, z: usize| lerp(tx, self.value(x0, y, z), self.value(x0 + 1, y, z));
+        let layer = |y: usize| lerp(tz, row(y, z0), row(y, z0 + 1));
+        lerp(ty, layer(y0), layer(y0 + 1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLOCKS: TerrainBlocks = TerrainBlocks {
+        stone: BlockId(1),
+        dirt: BlockId(2),
+        grass: BlockId(3),
+        sand: BlockId(4),
+        snow: BlockId(5),
+        log: BlockId(6),
+        leaves: BlockId(7),
+        water: BlockId(8),
+    };
+
+    /// The highest solid block in the column at `x`, `z`, under any water.
+    fn top_block(generator: &WorldGenerator, x: i32, z: i32) -> Option<(i32, BlockId)> {
+        let (coord, local) = ChunkCoord::of_block([x, 0, z]);
+        (0..HEIGHT_IN_CHUNKS).rev().filter_map(|cy| {
+            let coord = ChunkCoord::new(coord.x, cy, coord.z);
+            let chunk = generator.generate(coord);
+            (0..CHUNK_SIZE).rev()
+                .map(|y| (coord.block_position([0, y, 0])[1], chunk.get([local[0], y, local[2]])))
+                .find(|&(_, block)| !block.is_air() && block != BLOCKS.water)
+        }).next()
+    }
+
+    #[test]
+    fn the_same_seed_generates_the_same_chunks() {
+        let (a, b) = (WorldGenerator::new(42, BLOCKS), WorldGenerator::new(42, BLOCKS));
+        for &coord in &[ChunkCoord::new(0, 1, 0), ChunkCoord::new(-7, 0, 12), ChunkCoord::new(3, 2, -40)] {
+            assert_eq!(a.generate(coord), b.generate(coord));
+        }
+    }
+
+    #[test]
+    fn different_seeds_generate_different_ground() {
+        let (a, b) = (WorldGenerator::new(1, BLOCKS), WorldGenerator::new(2, BLOCKS));
+        let differences = (0..64).filter(|&x| a.height_at(x * 7, 0) != b.height_at(x * 7, 0)).count();
+        assert!(differences > 32);
+    }
+
+    /// A column of each biome, well away from any border.
+    fn columns_of_each_biome(generator: &WorldGenerator) -> Vec<(Biome, i32, i32)> {
+        Biome::ALL
+            .iter()
+            .filter_map(|&biome| {
+                (0..4000)
+                    .map(|i| (i % 64 * 97 - 3000, i / 64 * 89 - 3000))
+                    .find(|&(x, z)| {
+                        let column = generator.column(x, z);
+                        column.biomes.biome == biome && column.biomes.weights.iter().any(|&weight| weight == 1.0)
+                    })
+                    .map(|(x, z)| (biome, x, z))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn the_surface_is_made_of_the_biomes_blocks_over_stone() {
+        let generator = WorldGenerator::new(7, BLOCKS);
+        let columns = columns_of_each_biome(&generator);
+        assert_eq!(columns.len(), Biome::ALL.len());
+        for (biome, x, z) in columns {
+            let (y, block) = top_block(&generator, x, z).unwrap();
+            let column = generator.column(x, z);
+            assert_eq!(block, BLOCKS.surface(biome, column.height).0, "{} at {}, {}", biome, x, z);
+            assert!((y - generator.height_at(x, z)).abs() <= OVERHANG_DEPTH as i32);
+
+            let (coord, local) = ChunkCoord::of_block([x, y - DIRT_DEPTH - 8, z]);
+            assert_eq!(generator.generate(coord).get(local), BLOCKS.stone);
+        }
+    }
+
+    #[test]
+    fn the_sea_fills_the_lowlands_up_to_sea_level() {
+        let generator = WorldGenerator::new(7, BLOCKS);
+        let (x, z) = (0..40_000)
+            .map(|i| (i % 200 * 31 - 3000, i / 200 * 31 - 3000))
+            .find(|&(x, z)| generator.column(x, z).height < SEA_LEVEL as f32 - 6.0)
+            .unwrap();
+        let block = |y: i32| {
+            let (coord, local) = ChunkCoord::of_block([x, y, z]);
+            generator.generate(coord).get(local)
+        };
+        assert_eq!(block(SEA_LEVEL), BLOCKS.water);
+        assert_eq!(block(SEA_LEVEL + 1), BlockId::AIR);
+        let (_, floor) = top_block(&generator, x, z).unwrap();
+        assert_eq!(floor, BLOCKS.sand);
+    }
+
+    #[test]
+    fn mountains_stand_above_the_plains() {
+        let generator = WorldGenerator::new(7, BLOCKS);
+        let average_height = |biome: Biome| {
+            let heights: Vec<f32> = (0..40_000)
+                .map(|i| (i % 200 * 31 - 3000, i / 200 * 31 - 3000))
+                .map(|(x, z)| generator.column(x, z))
+                .filter(|column| column.biomes.biome == biome)
+                .map(|column| column.height)
+                .collect();
+            heights.iter().sum::<f32>() / heights.len() as f32
+        };
+        assert!(average_height(Biome::Mountains) > average_height(Biome::Plains) + 10.0);
+        assert!(average_height(Biome::Desert) < average_height(Biome::Forest));
+    }
+
+    #[test]
+    fn biome_borders_have_no_cliffs() {
+        let generator = WorldGenerator::new(7, BLOCKS);
+        let mut crossed = 0;
+        let mut last = generator.column(-4000, 0);
+        for x in -3999..4000 {
+            let column = generator.column(x, 0);
+            assert!((column.height - last.height).abs() < 3.0, "a cliff at {}", x);
+            if column.biomes.biome != last.biomes.biome {
+                crossed += 1;
+            }
+            last = column;
+        }
+        assert!(crossed > 0);
+    }
+
+    /// The chunks in a square of `columns` around the column at block `x`, `z`, every row of
+    /// them.
+    fn chunks_around(x: i32, z: i32, columns: i32) -> Vec<ChunkCoord> {
+        let center = ChunkCoord::of_block([x, 0, z]).0;
+        let mut coords = Vec::new();
+        for cy in 0..HEIGHT_IN_CHUNKS {
+            for cz in 0..columns {
+                for cx in 0..columns {
+                    coords.push(ChunkCoord::new(center.x + cx - columns / 2, cy, center.z + cz - columns / 2));
+                }
+            }
+        }
+        coords
+    }
+
+    #[test]
+    fn forests_grow_trees_that_reach_into_the_next_chunk() {
+        let generator = WorldGenerator::new(7, BLOCKS);
+        let (_, x, z) = columns_of_each_biome(&generator).into_iter().find(|&(biome, _, _)| biome == Biome::Forest).unwrap();
+
+        let mut logs = 0;
+        let mut spilled_leaves = 0;
+        for coord in chunks_around(x, z, 1) {
+            let generated = generator.generate_decorated(coord);
+            logs += generated.chunk.block_counts().iter().filter(|&&(block, _)| block == BLOCKS.log).map(|&(_, count)| count).sum::<u32>();
+            spilled_leaves += generated.spill.iter().flat_map(|&(_, ref edits)| edits).filter(|edit| edit.block == BLOCKS.leaves).count();
+        }
+        assert!(logs >= 4 * 4, "only {} logs", logs);
+        assert!(spilled_leaves > 0);
+    }
+
+    #[test]
+    fn decorations_come_out_the_same_whichever_chunk_is_generated_first() {
+        let generator = WorldGenerator::new(7, BLOCKS);
+        let (_, x, z) = columns_of_each_biome(&generator).into_iter().find(|&(biome, _, _)| biome == Biome::Forest).unwrap();
+        let coords = chunks_around(x, z, 3);
+
+        let generate = |order: Vec<ChunkCoord>| {
+            let mut world = World::new();
+            let mut pending = PendingEdits::new();
+            for coord in order {
+                generator.generate_into(&mut world, &mut pending, coord);
+            }
+            world
+        };
+        let forwards = generate(coords.clone());
+        let backwards = generate(coords.iter().rev().cloned().collect());
+        for coord in coords {
+            assert!(forwards.chunk(coord) == backwards.chunk(coord), "chunk {:?} differs", coord);
+        }
+    }
+
+    #[test]
+    fn generating_a_chunk_again_brings_back_the_decorations_from_around_it() {
+        let generator = WorldGenerator::new(7, BLOCKS);
+        let (_, x, z) = columns_of_each_biome(&generator).into_iter().find(|&(biome, _, _)| biome == Biome::Forest).unwrap();
+        let mut world = World::new();
+        let mut pending = PendingEdits::new();
+        for coord in chunks_around(x, z, 3) {
+            generator.generate_into(&mut world, &mut pending, coord);
+        }
+
+        let middle = ChunkCoord::of_block([x, generator.height_at(x, z), z]).0;
+        let before = world.remove_chunk(middle).unwrap();
+        generator.generate_into(&mut world, &mut pending, middle);
+        assert!(world.chunk(middle) == Some(&before));
+        assert!(before != generator.generate(middle));
+    }
+
+    #[test]
+    fn nothing_is_generated_above_the_hills_or_outside_the_world() {
+        let generator = WorldGenerator::new(7, BLOCKS);
+        assert!(generator.generate(ChunkCoord::new(5, HEIGHT_IN_CHUNKS - 1, 5)).is_empty());
+        assert!(generator.generate(ChunkCoord::new(5, -1, 5)).is_empty());
+        assert!(!generator.generate(ChunkCoord::new(5, 0, 5)).is_empty());
+    }
+}
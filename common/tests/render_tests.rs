@@ -0,0 +1,252 @@
+//! Golden image tests. Each scene is rendered by running its chapter with `--headless`, and the
+//! frame it saves is compared against a reference image in `tests/reference`.
+//!
+//! They need a gpu, and build the chapters with cargo as they go, so they're ignored by default:
+//!
+//! ```sh
+//! cargo test -p renderer-common --test render_tests -- --ignored
+//! ```
+//!
+//! The chapters are built with the features in `RENDER_TEST_FEATURES`, `vulkan` if it isn't
+//! set. A scene with no reference image fails. To make the references, or replace them after an
+//! intentional change, run the tests with `UPDATE_REFERENCES=1`, which saves each frame as its
+//! scene's reference instead of comparing it, and look at the new images before committing them:
+//!
+//! ```sh
+//! UPDATE_REFERENCES=1 cargo test -p renderer-common --test render_tests -- --ignored
+//! ```
+
+extern crate image;
+
+use std::env;
+use std::fs;
+use std::path::{ Path, PathBuf };
+use std::process::Command;
+
+use image::{ Rgba, RgbaImage };
+
+/// How different two pixels can be, as a CIE76 color difference, before they count as
+/// different. A difference of about 2.3 is just noticeable, so this only lets through changes
+/// that are hard to see.
+const MAX_PIXEL_DIFFERENCE: f32 = 3.0;
+
+/// The fraction of pixels that can differ before a test fails. Drivers don't all rasterize the
+/// edges of triangles in quite the same way.
+const MAX_DIFFERENT_FRACTION: f32 = 0.005;
+
+/// How to save the frames the tests render as their new reference images.
+const REGENERATE_COMMAND: &str =
+    "UPDATE_REFERENCES=1 cargo test -p renderer-common --test render_tests -- --ignored";
+
+/// The size the scenes are rendered at. Small, to keep the reference images small.
+const WIDTH: u32 = 320;
+const HEIGHT: u32 = 240;
+
+/// A chapter rendered for a fixed number of frames. Headless runs animate with a fixed time
+/// step, so the last frame is the same every time.
+struct Scene {
+    name: &'static str,
+    package: &'static str,
+    frames: u32,
+}
+
+const TRIANGLE: Scene = Scene { name: "triangle", package: "voxel-renderer-03", frames: 1 };
+const TEXTURED_QUAD: Scene = Scene { name: "textured-quad", package: "voxel-renderer-06", frames: 30 };
+const DEPTH_CUBES: Scene = Scene { name: "depth-cubes", package: "voxel-renderer-07", frames: 30 };
+const CHUNKS: Scene = Scene { name: "chunks", package: "voxel-renderer-08", frames: 1 };
+const PROPS: Scene = Scene { name: "props", package: "voxel-renderer-09", frames: 30 };
+const GAMMA: Scene = Scene { name: "gamma", package: "voxel-renderer-10", frames: 1 };
+const DENSITY: Scene = Scene { name: "density", package: "voxel-renderer-11", frames: 30 };
+const GPU_MESHING: Scene = Scene { name: "gpu-meshing", package: "voxel-renderer-12", frames: 3 };
+
+#[test]
+#[ignore]
+fn triangle() {
+    check_scene(&TRIANGLE);
+}
+
+#[test]
+#[ignore]
+fn textured_quad() {
+    check_scene(&TEXTURED_QUAD);
+}
+
+#[test]
+#[ignore]
+fn depth_cubes() {
+    check_scene(&DEPTH_CUBES);
+}
+
+#[test]
+#[ignore]
+fn chunks() {
+    check_scene(&CHUNKS);
+}
+
+#[test]
+#[ignore]
+fn props() {
+    check_scene(&PROPS);
+}
+
+#[test]
+#[ignore]
+fn gamma() {
+    check_scene(&GAMMA);
+}
+
+#[test]
+#[ignore]
+fn density() {
+    check_scene(&DENSITY);
+}
+
+#[test]
+#[ignore]
+fn gpu_meshing() {
+    check_scene(&GPU_MESHING);
+}
+
+fn workspace_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("..")
+}
+
+fn reference_path(scene: &Scene) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/reference")
+        .join(format!("{}.png", scene.name))
+}
+
+/// Where the rendered frames, diffs and the settings file the chapters are run with go.
+fn output_dir() -> PathBuf {
+    workspace_dir().join("target/render-tests")
+}
+
+/// Runs the scene's chapter headless and returns the path of the frame it saved.
+fn render(scene: &Scene) -> PathBuf {
+    let output_dir = output_dir();
+    fs::create_dir_all(&output_dir).unwrap();
+    let output = output_dir.join(format!("{}.png", scene.name));
+    let _ = fs::remove_file(&output);
+
+    let features = env::var("RENDER_TEST_FEATURES").unwrap_or_else(|_| "vulkan".to_owned());
+    let cargo = env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned());
+    let status = Command::new(cargo)
+        .current_dir(workspace_dir())
+        .args(&["run", "--quiet", "-p", scene.package, "--features", &features, "--"])
+        .arg("--headless")
+        .args(&["--frames", &scene.frames.to_string()])
+        .args(&["--width", &WIDTH.to_string(), "--height", &HEIGHT.to_string()])
+        // A settings file of its own, so whatever is in the user's can't change the result
+        .arg("--config")
+        .arg(output_dir.join("settings.toml"))
+        .arg("--output")
+        .arg(&output)
+        .status()
+        .expect("couldn't run cargo");
+
+    assert!(status.success(), "{} exited with {}", scene.package, status);
+    assert!(output.exists(), "{} didn't save a frame to {}", scene.package, output.display());
+    output
+}
+
+fn check_scene(scene: &Scene) {
+    let reference_path = reference_path(scene);
+    let update = env::var("UPDATE_REFERENCES").map(|value| value == "1").unwrap_or(false);
+    assert!(
+        update || reference_path.exists(),
+        "{} has no reference image at {}. Make one with `{}` and check it looks right before \
+         committing it",
+        scene.name,
+        reference_path.display(),
+        REGENERATE_COMMAND,
+    );
+
+    let rendered_path = render(scene);
+    if update {
+        fs::create_dir_all(reference_path.parent().unwrap()).unwrap();
+        fs::copy(&rendered_path, &reference_path).unwrap();
+        println!("Saved a new reference image for {} to {}", scene.name, reference_path.display());
+        return;
+    }
+    let rendered = image::open(&rendered_path).unwrap().to_rgba();
+    let reference = image::open(&reference_path).unwrap().to_rgba();
+
+    assert_eq!(
+        rendered.dimensions(),
+        reference.dimensions(),
+        "{} was rendered at a different size to its reference image",
+        scene.name,
+    );
+
+    let (diff, different) = diff_images(&rendered, &reference);
+    let total = (rendered.width() * rendered.height()) as f32;
+    if different as f32 / total > MAX_DIFFERENT_FRACTION {
+        let diff_path = output_dir().join(format!("{}-diff.png", scene.name));
+        diff.save(&diff_path).unwrap();
+        panic!(
+            "{} differs from its reference image in {} of {} pixels. The differences are \
+             highlighted in {}, and the frame itself is in {}",
+            scene.name,
+            different,
+            total,
+            diff_path.display(),
+            rendered_path.display(),
+        );
+    }
+}
+
+/// An image with the pixels that differ between `a` and `b` in red and the rest a faded copy
+/// of `a`, along with how many pixels differ.
+fn diff_images(a: &RgbaImage, b: &RgbaImage) -> (RgbaImage, usize) {
+    let mut diff = RgbaImage::new(a.width(), a.height());
+    let mut different = 0;
+    for (x, y, pixel) in a.enumerate_pixels() {
+        let other = b.get_pixel(x, y);
+        let diff_pixel = if color_difference(pixel, other) > MAX_PIXEL_DIFFERENCE {
+            different += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let fade = |channel: u8| 128 + channel / 2;
+            Rgba([fade(pixel[0]), fade(pixel[1]), fade(pixel[2]), 255])
+        };
+        diff.put_pixel(x, y, diff_pixel);
+    }
+    (diff, different)
+}
+
+/// The CIE76 difference between two sRGB colors: the distance between them in CIELAB, which is
+/// laid out so that equal distances look about equally different. Alpha is ignored, since
+/// screenshots always have it at 255.
+fn color_difference(a: &Rgba<u8>, b: &Rgba<u8>) -> f32 {
+    let a = srgb_to_lab(a);
+    let b = srgb_to_lab(b);
+    ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+}
+
+fn srgb_to_lab(color: &Rgba<u8>) -> [f32; 3] {
+    let linear = |channel: u8| {
+        let c = channel as f32 / 255.0;
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    let (r, g, b) = (linear(color[0]), linear(color[1]), linear(color[2]));
+
+    // To CIE XYZ, relative to the D65 white point sRGB uses
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+
+    let f = |t: f32| {
+        if t > 0.008856 {
+            t.cbrt()
+        } else {
+            7.787 * t + 16.0 / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
@@ -0,0 +1,263 @@
+//! Build script support for compiling a chapter's GLSL shaders to SPIR-V.
+//!
+//! A chapter's `build.rs` calls `compile_shaders("shaders")`, which compiles every shader in that
+//! directory into `OUT_DIR` and writes a `shaders.rs` file with one `&[u8]` constant per shader,
+//! named after the file (`triangle.vert` becomes `TRIANGLE_VERT`). The chapter then pulls the
+//! constants in with
+//!
+//! ```ignore
+//! mod shaders {
+//!     include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+//! }
+//! ```
+//!
+//! Shaders can share code with `#include "name.glsl"` lines, which are replaced with the named
+//! file from the same directory before the shader is compiled. Included files are left out of
+//! `shaders.rs` unless their extension is a shader stage's, so they're usually `.glsl`.
+//!
+//! Compile errors fail the build with the compiler's message rather than panicking at runtime.
+//! `shaders.rs` also contains an `ALL` table of `(file name, SPIR-V)` pairs, which is what the
+//! runtime shader reloading in `renderer-common` starts from.
+
+extern crate glsl_to_spirv;
+
+use std::env;
+use std::fs::{ self, File };
+use std::io::{ self, Read, Write };
+use std::path::{ Path, PathBuf };
+
+pub use glsl_to_spirv::ShaderType;
+
+/// Maps a shader's file extension to the kind of shader glslang should compile it as.
+pub fn shader_type(extension: &str) -> Option<ShaderType> {
+    match extension {
+        "vert" => Some(ShaderType::Vertex),
+        "frag" => Some(ShaderType::Fragment),
+        "geom" => Some(ShaderType::Geometry),
+        "tesc" => Some(ShaderType::TessellationControl),
+        "tese" => Some(ShaderType::TessellationEvaluation),
+        "comp" => Some(ShaderType::Compute),
+        _ => None,
+    }
+}
+
+/// The name of the constant generated for a shader file, e.g. `TRIANGLE_VERT`.
+fn constant_name(file_name: &str) -> String {
+    file_name
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect()
+}
+
+/// Compiles every shader in `dir` (relative to the crate being built) and generates
+/// `$OUT_DIR/shaders.rs`. Panics, and so fails the build, if any shader doesn't compile.
+pub fn compile_shaders<P: AsRef<Path>>(dir: P) {
+    if let Err(err) = try_compile_shaders(dir.as_ref()) {
+        panic!("{}", err);
+    }
+}
+
+fn try_compile_shaders(dir: &Path) -> Result<(), String> {
+    let out_dir = env::var("OUT_DIR").map_err(|_| "OUT_DIR is not set, is this running from a build script?".to_owned())?;
+    let out_dir = Path::new(&out_dir);
+
+    println!("cargo:rerun-if-changed={}", dir.display());
+
+    let mut entries = fs::read_dir(dir)
+        .and_then(|entries| entries.collect::<io::Result<Vec<_>>>())
+        .map_err(|err| format!("Failed to read shader directory {}: {}", dir.display(), err))?;
+    entries.sort_by_key(|entry| entry.path());
+
+    let mut constants = String::new();
+    let mut table = String::new();
+    let mut errors = Vec::new();
+
+    for entry in entries {
+        let path = entry.path();
+        let kind = match path.extension().and_then(|ext| ext.to_str()).and_then(shader_type) {
+            Some(kind) => kind,
+            None => continue,
+        };
+        let file_name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        println!("cargo:rerun-if-changed={}", path.display());
+
+        let mut included = Vec::new();
+        let source = match read_source(&path, &mut included) {
+            Ok(source) => source,
+            Err(err) => {
+                errors.push(err);
+                continue;
+            }
+        };
+        for include in &included {
+            println!("cargo:rerun-if-changed={}", include.display());
+        }
+
+        let spirv = match compile(&source, kind) {
+            Ok(spirv) => spirv,
+            Err(err) => {
+                errors.push(format!("{}:\n{}", path.display(), err));
+                continue;
+            }
+        };
+
+        let spirv_path = out_dir.join(format!("{}.spv", file_name));
+        fs::write(&spirv_path, &spirv)
+            .map_err(|err| format!("Failed to write {}: {}", spirv_path.display(), err))?;
+
+        constants.push_str(&format!(
+            "pub const {}: &[u8] = include_bytes!({:?});\n",
+            constant_name(&file_name),
+            spirv_path.display(),
+        ));
+        table.push_str(&format!("    ({:?}, {}),\n", file_name, constant_name(&file_name)));
+    }
+
+    if !errors.is_empty() {
+        return Err(format!("Failed to compile shaders:\n\n{}", errors.join("\n\n")));
+    }
+
+    constants.push_str(&format!("\npub const ALL: &[(&str, &[u8])] = &[\n{}];\n", table));
+
+    let shaders_rs = out_dir.join("shaders.rs");
+    File::create(&shaders_rs)
+        .and_then(|mut file| file.write_all(constants.as_bytes()))
+        .map_err(|err| format!("Failed to write {}: {}", shaders_rs.display(), err))
+}
+
+/// Reads the shader at `path` with its `#include` lines replaced by the files they name, and
+/// adds the paths of those files to `included`.
+fn read_source(path: &Path, included: &mut Vec<PathBuf>) -> Result<String, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let mut load = |name: &str| {
+        let include = dir.join(name);
+        let source = fs::read_to_string(&include)
+            .map_err(|err| format!("Failed to read {}: {}", include.display(), err));
+        included.push(include);
+        source
+    };
+    expand_includes(&source, &mut vec![file_name], &mut Vec::new(), &mut load)
+        .map_err(|err| format!("{}: {}", path.display(), err))
+}
+
+/// Replaces each `#include "name"` line in `source` with what `load` returns for `name`, with
+/// its own includes expanded in turn. `including` is the files being expanded, innermost last,
+/// and `seen` the ones that already have been, which aren't included again. `#line` directives
+/// go round each expansion, so compile errors after it still point at the right line.
+fn expand_includes<F>(
+    source: &str,
+    including: &mut Vec<String>,
+    seen: &mut Vec<String>,
+    load: &mut F,
+) -> Result<String, String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let mut expanded = String::with_capacity(source.len());
+    for (index, line) in source.lines().enumerate() {
+        let name = match include_name(line)? {
+            Some(name) => name.to_owned(),
+            None => {
+                expanded.push_str(line);
+                expanded.push('\n');
+                continue;
+            }
+        };
+        if including.contains(&name) {
+            return Err(format!("{} includes itself", name));
+        }
+        if !seen.contains(&name) {
+            seen.push(name.clone());
+            let included = load(&name)?;
+            including.push(name);
+            let included = expand_includes(&included, including, seen, load)?;
+            including.pop();
+            expanded.push_str("#line 1\n");
+            expanded.push_str(&included);
+        }
+        // The line after this one, counting from 1
+        expanded.push_str(&format!("#line {}\n", index + 2));
+    }
+    Ok(expanded)
+}
+
+/// The file an `#include "name"` line names, or `None` if `line` isn't one.
+fn include_name(line: &str) -> Result<Option<&str>, String> {
+    let line = line.trim();
+    if !line.starts_with("#include") {
+        return Ok(None);
+    }
+    let rest = line["#include".len()..].trim();
+    if rest.len() >= 2 && rest.starts_with('"') && rest.ends_with('"') {
+        Ok(Some(&rest[1..rest.len() - 1]))
+    } else {
+        Err(format!("expected a quoted file name after #include, found `{}`", rest))
+    }
+}
+
+/// Compiles a single GLSL shader to SPIR-V.
+pub fn compile(source: &str, kind: ShaderType) -> Result<Vec<u8>, String> {
+    let mut spirv_file = glsl_to_spirv::compile(source, kind)?;
+    let mut spirv = Vec::new();
+    spirv_file.read_to_end(&mut spirv).map_err(|err| err.to_string())?;
+    Ok(spirv)
+}
+
+/// Compiles the shader at `path`, working out its kind from the file extension.
+pub fn compile_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, String> {
+    let path = path.as_ref();
+    let kind = path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(shader_type)
+        .ok_or_else(|| format!("{} is not a shader", path.display()))?;
+    let source = read_source(path, &mut Vec::new())?;
+    compile(&source, kind).map_err(|err| format!("{}:\n{}", path.display(), err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(source: &str, files: &[(&str, &str)]) -> Result<String, String> {
+        let mut load = |name: &str| {
+            files
+                .iter()
+                .find(|&&(file, _)| file == name)
+                .map(|&(_, source)| source.to_owned())
+                .ok_or_else(|| format!("no {}", name))
+        };
+        expand_includes(source, &mut vec!["main.vert".to_owned()], &mut Vec::new(), &mut load)
+    }
+
+    #[test]
+    fn includes_are_replaced_with_their_files() {
+        let source = "#version 450\n#include \"camera.glsl\"\nvoid main() {}\n";
+        let expanded = expand(source, &[("camera.glsl", "uniform Camera;\n")]);
+        assert_eq!(
+            expanded.unwrap(),
+            "#version 450\n#line 1\nuniform Camera;\n#line 3\nvoid main() {}\n",
+        );
+    }
+
+    #[test]
+    fn files_are_only_included_once() {
+        let files = [("a.glsl", "#include \"b.glsl\"\nint a;\n"), ("b.glsl", "int b;\n")];
+        let expanded = expand("#include \"a.glsl\"\n#include \"b.glsl\"\n", &files).unwrap();
+        assert_eq!(expanded.matches("int b;").count(), 1);
+    }
+
+    #[test]
+    fn includes_that_include_themselves_are_errors() {
+        let files = [("a.glsl", "#include \"b.glsl\"\n"), ("b.glsl", "#include \"a.glsl\"\n")];
+        assert!(expand("#include \"a.glsl\"\n", &files).is_err());
+    }
+
+    #[test]
+    fn includes_need_a_quoted_name() {
+        assert!(expand("#include camera.glsl\n", &[]).is_err());
+    }
+}
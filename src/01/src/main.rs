@@ -1,121 +1,100 @@
-#[cfg(feature = "vulkan")]
-extern crate gfx_backend_vulkan as back;
-#[cfg(feature = "dx12")]
-extern crate gfx_backend_dx12 as back;
-#[cfg(feature = "metal")]
-extern crate gfx_backend_metal as back;
-
 extern crate gfx_hal as hal;
-
-extern crate winit;
+extern crate renderer_common;
 
 use hal::{
-    format as f, image as i,
-    window::{ self, SwapchainConfig },
-    Instance, Surface, Device,
+    pso::PipelineStage,
+    Backend, Device, Submission,
 };
 
-fn main() {
-    let mut events_loop = winit::EventsLoop::new();
-
-    let wb = winit::WindowBuilder::new()
-        .with_dimensions(
-            winit::dpi::LogicalSize::from_physical(
-                winit::dpi::PhysicalSize {
-                    width: 1280.0,
-                    height: 720.0,
-                },
-                1.0
-            )
-        )
-        .with_title("voxel-renderer");
-    
-    let window = wb.build(&events_loop).unwrap();
-    
-    // Create instance
-    let instance = back::Instance::create("voxel-renderer", 1);
-    // Acquire surface
-    let mut surface = instance.create_surface(&window);
-
-    // Enumerate adapters and pick one that works for us
-    let mut adapters = instance.enumerate_adapters();
-
-    for adapter in &adapters {
-        println!("{:?}", adapter.info);
-    }
-
-    let mut adapter = adapters.remove(0);
+use renderer_common::events::WindowAction;
+use renderer_common::frame_sync;
+use renderer_common::{ Events, FrameSync, GfxContext, Result, Runner };
 
-    let (mut device, mut queue_group) = adapter
-        .open_with::<_, hal::Graphics>(1, |family| surface.supports_queue_family(family))
-        .unwrap();
-
-    let (capabilities, formats, presentation_modes) = surface.compatibility(&adapter.physical_device);
-
-    let format = formats
-        .map_or(f::Format::Rgba8Srgb, |formats| {
-            formats
-                .iter()
-                .find(|format| format.base_format().1 == f::ChannelType::Srgb)
-                .map(|format| *format)
-                .unwrap_or(formats[0])
-        });
-    
-    let extent = match capabilities.current_extent {
-        Some(extent) => extent,
-        None => {
-            let window_size = window.get_inner_size().unwrap().to_physical(window.get_hidpi_factor());
-            let mut extent = hal::window::Extent2D { width: window_size.width as _, height: window_size.height as _ };
+fn main() {
+    // `launch` opens the window and creates the instance, surface, adapter and device for
+    // whichever backend was chosen, then calls back into `Chapter::run` below.
+    renderer_common::launch("voxel-renderer", Chapter);
+}
 
-            extent.width = extent.width
-                .max(capabilities.extents.start.width)
-                .min(capabilities.extents.end.width);
-            extent.height = extent.height
-                .max(capabilities.extents.start.height)
-                .min(capabilities.extents.end.height);
-            
-            extent
+struct Chapter;
+
+impl Runner for Chapter {
+    fn run<B: Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<()> {
+        let mut swapchain = context.create_swapchain()?;
+        let mut frame_sync = FrameSync::new(
+            context.device.clone(),
+            &context.queue_group,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        );
+
+        let mut running = true;
+        let mut recreate_swapchain = false;
+        while running {
+            let mut toggle_vsync = false;
+            let mut toggle_fullscreen = false;
+            events.poll_actions(|action| match action {
+                WindowAction::Close => running = false,
+                WindowAction::Resize => recreate_swapchain = true,
+                WindowAction::ToggleVsync => toggle_vsync = true,
+                WindowAction::ToggleFullscreen => toggle_fullscreen = true,
+                // Nothing is drawn yet, so there's nothing to save
+                WindowAction::Screenshot => (),
+            });
+
+            if toggle_vsync {
+                context.toggle_vsync(&swapchain);
+                recreate_swapchain = true;
+            }
+            if toggle_fullscreen {
+                context.toggle_fullscreen();
+                recreate_swapchain = true;
+            }
+            if context.poll_config() {
+                recreate_swapchain = true;
+            }
+
+            if recreate_swapchain {
+                context.recreate_swapchain(&mut swapchain)?;
+                recreate_swapchain = false;
+            }
+
+            // Waits until the gpu is done with the last frame that used these resources
+            let mut frame = frame_sync.begin_frame()?;
+
+            // Get the index of the next swapchain image we're allowed to draw into. If the
+            // swapchain is out of date (or suboptimal) for the surface, we rebuild it and try
+            // again next frame.
+            let image_index = match swapchain.acquire_image(&mut frame, &mut context.queue_group.queues[0]) {
+                Ok(image_index) => image_index,
+                Err(_) => {
+                    recreate_swapchain = true;
+                    continue;
+                }
+            };
+
+            // We don't draw anything yet, so the command buffer is empty
+            let finished_command_buffer = {
+                let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+                command_buffer.finish()
+            };
+
+            let submission = Submission::new()
+                .wait_on(&[(frame.image_available(), PipelineStage::BOTTOM_OF_PIPE)])
+                .signal(&[frame.render_finished()])
+                .submit(Some(finished_command_buffer));
+            context.device.reset_fence(frame.fence());
+            context.queue_group.queues[0].submit(submission, Some(frame.fence()));
+
+            if swapchain.present(&frame, &mut context.queue_group.queues[0], image_index).is_err() {
+                recreate_swapchain = true;
+            }
         }
-    };
 
-    let presentation_mode = presentation_modes
-        .iter()
-        .find(|&mode| *mode == window::PresentMode::Immediate)
-        .map(|mode| *mode)
-        .unwrap_or(window::PresentMode::Fifo);
-
-    let swap_config = SwapchainConfig::new()
-        .with_color(format)
-        .with_image_count(capabilities.image_count.start)
-        .with_image_usage(i::Usage::COLOR_ATTACHMENT)
-        .with_mode(presentation_mode);
-
-    let (swapchain, backbuffer) = device.create_swapchain(
-        &mut surface,
-        swap_config,
-        None,
-        &extent,
-    );
-
-    let frame_images: Vec<_> = match backbuffer {
-        window::Backbuffer::Images(images) => {
-            images.into_iter()
-                .map(|image| {
-                    let image_view = device.create_image_view(
-                        &image,
-                        i::ViewKind::D2,
-                        format,
-                        f::Swizzle::NO,
-                        i::SubresourceRange {
-                            aspects: f::Aspects::COLOR,
-                            levels: 0..1,
-                            layers: 0..1,
-                        }
-                    );
-                    (image, image_view)
-                })
-                .collect()
-        },
-        _ => unimplemented!()
-    };
+        // Make sure the gpu is idle before our resources start getting destroyed
+        context.wait_idle()
+    }
 }
@@ -0,0 +1,170 @@
+extern crate gfx_hal as hal;
+#[macro_use]
+extern crate log;
+extern crate renderer_common;
+
+use hal::{
+    command, image as i, pass,
+    pso::{ self, PipelineStage },
+    Backend, Device, Submission,
+};
+
+use renderer_common::color::srgb_to_linear_rgba;
+use renderer_common::events::WindowAction;
+use renderer_common::frame_sync;
+use renderer_common::screenshot;
+use renderer_common::{ Events, FrameSync, Framebuffers, GfxContext, Result, Runner };
+
+/// The color we clear the screen to every frame, in sRGB the way a color picker gives it. The
+/// swapchain encodes everything written to it as sRGB, clears included, so it's decoded to
+/// linear first.
+const CLEAR_COLOR: [f32; 4] = [0.1, 0.2, 0.3, 1.0];
+
+fn main() {
+    renderer_common::launch("voxel-renderer", Chapter);
+}
+
+struct Chapter;
+
+impl Runner for Chapter {
+    fn run<B: Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<()> {
+        let mut swapchain = context.create_swapchain()?;
+
+        // A render pass with a single color attachment, which is cleared when the pass begins
+        // and then handed off to be presented when it ends.
+        let render_pass = {
+            let color_attachment = pass::Attachment {
+                format: Some(swapchain.format()),
+                samples: 1,
+                ops: pass::AttachmentOps {
+                    load: pass::AttachmentLoadOp::Clear,
+                    store: pass::AttachmentStoreOp::Store,
+                },
+                stencil_ops: pass::AttachmentOps::DONT_CARE,
+                layouts: i::Layout::Undefined..i::Layout::Present,
+            };
+
+            let subpass = pass::SubpassDesc {
+                colors: &[(0, i::Layout::ColorAttachmentOptimal)],
+                depth_stencil: None,
+                inputs: &[],
+                resolves: &[],
+                preserves: &[],
+            };
+
+            // Don't start writing to the attachment until the swapchain is done reading from it
+            let dependency = pass::SubpassDependency {
+                passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+                stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+                accesses: i::Access::empty()
+                    ..(i::Access::COLOR_ATTACHMENT_READ | i::Access::COLOR_ATTACHMENT_WRITE),
+            };
+
+            context.device.create_render_pass(&[color_attachment], &[subpass], &[dependency])
+        };
+
+        let mut framebuffers = Framebuffers::new(context.device.clone(), &render_pass, &swapchain)?;
+        let mut frame_sync = FrameSync::new(
+            context.device.clone(),
+            &context.queue_group,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        );
+
+        let mut running = true;
+        let mut recreate_swapchain = false;
+        while running {
+            let mut toggle_vsync = false;
+            let mut toggle_fullscreen = false;
+            let mut take_screenshot = false;
+            events.poll_actions(|action| match action {
+                WindowAction::Close => running = false,
+                WindowAction::Resize => recreate_swapchain = true,
+                WindowAction::ToggleVsync => toggle_vsync = true,
+                WindowAction::ToggleFullscreen => toggle_fullscreen = true,
+                WindowAction::Screenshot => take_screenshot = true,
+            });
+
+            if toggle_vsync {
+                context.toggle_vsync(&swapchain);
+                recreate_swapchain = true;
+            }
+            if toggle_fullscreen {
+                context.toggle_fullscreen();
+                recreate_swapchain = true;
+            }
+            if context.poll_config() {
+                recreate_swapchain = true;
+            }
+
+            if recreate_swapchain {
+                context.recreate_swapchain(&mut swapchain)?;
+                framebuffers.recreate(&render_pass, &swapchain)?;
+                recreate_swapchain = false;
+            }
+
+            // Waits until the gpu is done with the last frame that used these resources
+            let mut frame = frame_sync.begin_frame()?;
+
+            let image_index = match swapchain.acquire_image(&mut frame, &mut context.queue_group.queues[0]) {
+                Ok(image_index) => image_index,
+                Err(_) => {
+                    recreate_swapchain = true;
+                    continue;
+                }
+            };
+
+            let extent = swapchain.extent();
+            let render_area = pso::Rect {
+                x: 0,
+                y: 0,
+                w: extent.width as _,
+                h: extent.height as _,
+            };
+
+            let finished_command_buffer = {
+                let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+
+                // Beginning the render pass clears the attachment; we don't draw anything else
+                // yet, so we can end it straight away by dropping the encoder.
+                command_buffer.begin_render_pass_inline(
+                    &render_pass,
+                    framebuffers.get(image_index),
+                    render_area,
+                    &[command::ClearValue::Color(command::ClearColor::Float(srgb_to_linear_rgba(CLEAR_COLOR)))],
+                );
+
+                command_buffer.finish()
+            };
+
+            let submission = Submission::new()
+                .wait_on(&[(frame.image_available(), PipelineStage::COLOR_ATTACHMENT_OUTPUT)])
+                .signal(&[frame.render_finished()])
+                .submit(Some(finished_command_buffer));
+            context.device.reset_fence(frame.fence());
+            context.queue_group.queues[0].submit(submission, Some(frame.fence()));
+
+            if take_screenshot {
+                match screenshot::capture(context, &swapchain, image_index) {
+                    Ok(path) => info!("Saved a screenshot to {}", path.display()),
+                    Err(err) => error!("{}", err),
+                }
+            }
+
+            if swapchain.present(&frame, &mut context.queue_group.queues[0], image_index).is_err() {
+                recreate_swapchain = true;
+            }
+        }
+
+        context.wait_idle()?;
+
+        // The framebuffers reference the render pass, so they have to be destroyed first
+        drop(framebuffers);
+        context.device.destroy_render_pass(render_pass);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,22 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Push constants are a small block of data written straight into the command buffer, so they
+// are cheap to update for every draw without any buffers or descriptor sets
+layout(push_constant) uniform PushConstants {
+    mat4 mvp;
+} push_constants;
+
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec3 color;
+
+layout(location = 0) out vec3 frag_color;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = push_constants.mvp * vec4(position, 0.0, 1.0);
+    frag_color = color;
+}
@@ -0,0 +1,388 @@
+extern crate gfx_hal as hal;
+#[macro_use]
+extern crate log;
+extern crate renderer_common;
+
+use std::rc::Rc;
+use std::mem;
+use std::slice;
+
+use hal::{
+    buffer, command, format as f, image as i, pass, IndexType,
+    pso::{ self, PipelineStage },
+    Backend, Device,
+    Primitive, Submission,
+};
+
+use renderer_common::color::srgb_to_linear_rgba;
+use renderer_common::descriptors::{ DescriptorAllocator, DescriptorSetLayout };
+use renderer_common::events::WindowAction;
+use renderer_common::frame_sync;
+use renderer_common::screenshot;
+use renderer_common::shader::{ create_shader_module, ShaderSet };
+use renderer_common::{
+    upload_buffer, Clock, Events, FrameSync, Framebuffers, GfxContext, MemoryCategory, Result,
+    Runner, Texture,
+};
+
+const CLEAR_COLOR: [f32; 4] = [0.1, 0.2, 0.3, 1.0];
+
+/// The SPIR-V for the shaders in `shaders/`, compiled by `build.rs`
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// The layout of this struct has to match the vertex attributes we describe to the pipeline,
+/// hence the `repr(C)`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Vertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+}
+
+const QUAD_VERTICES: [Vertex; 4] = [
+    Vertex { position: [-0.5, -0.5], uv: [0.0, 0.0] },
+    Vertex { position: [0.5, -0.5], uv: [1.0, 0.0] },
+    Vertex { position: [0.5, 0.5], uv: [1.0, 1.0] },
+    Vertex { position: [-0.5, 0.5], uv: [0.0, 1.0] },
+];
+
+const TEXTURE_PATH: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/assets/checker.png");
+
+const QUAD_INDICES: [u16; 6] = [0, 1, 2, 2, 3, 0];
+
+/// Has to match the `PushConstants` block in `quad.vert`. Matrices are column major.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PushConstants {
+    mvp: [[f32; 4]; 4],
+}
+
+impl PushConstants {
+    /// Rotates the quad by `angle` radians around the z axis, then squashes it horizontally so
+    /// it stays square whatever the aspect ratio of the window is.
+    fn spinning(angle: f32, aspect: f32) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        PushConstants {
+            mvp: [
+                [cos / aspect, sin, 0.0, 0.0],
+                [-sin / aspect, cos, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ],
+        }
+    }
+
+    /// Push constants are written as a slice of 32 bit words.
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const PushConstants as *const u32,
+                mem::size_of::<PushConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `PushConstants`, in 32 bit words
+const PUSH_CONSTANTS_SIZE: u32 = (mem::size_of::<PushConstants>() / mem::size_of::<u32>()) as u32;
+
+/// Builds the graphics pipeline from the current SPIR-V in `shaders`. This is called again
+/// whenever one of the shaders is changed on disk.
+fn create_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("quad.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("quad.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(
+            pso::ColorMask::ALL,
+            pso::BlendState::ALPHA,
+        ));
+
+        // One interleaved vertex buffer, with a position and color attribute
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<Vertex>() as u32,
+            rate: 0,
+        });
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: 0,
+            binding: 0,
+            element: pso::Element {
+                format: f::Format::Rg32Float,
+                offset: 0,
+            },
+        });
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: 1,
+            binding: 0,
+            element: pso::Element {
+                format: f::Format::Rgb32Float,
+                offset: 8,
+            },
+        });
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    // The modules are only needed while the pipeline is being built
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the graphics pipeline");
+    Ok(pipeline)
+}
+
+fn main() {
+    renderer_common::launch("voxel-renderer", Chapter);
+}
+
+struct Chapter;
+
+impl Runner for Chapter {
+    fn run<B: Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<()> {
+        let mut swapchain = context.create_swapchain()?;
+        let render_pass = renderer_common::pass::create_color_render_pass::<B>(&context.device, swapchain.format());
+
+        // Upload the quad's geometry into device local memory, by way of a staging buffer
+        let vertex_buffer = upload_buffer(context, &QUAD_VERTICES, buffer::Usage::VERTEX, MemoryCategory::Meshes)?;
+        let index_buffer = upload_buffer(context, &QUAD_INDICES, buffer::Usage::INDEX, MemoryCategory::Meshes)?;
+        info!("Gpu memory: {}", context.allocator.borrow().stats());
+
+        let texture = Texture::load(context, TEXTURE_PATH)?;
+
+        // One descriptor set with the texture's image and sampler, used by the fragment shader
+        let set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut descriptors = DescriptorAllocator::new(context.device.clone(), set_layout.clone());
+        let descriptor_set = descriptors.allocate()?;
+        context.device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set: &descriptor_set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(texture.view(), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &descriptor_set,
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(texture.sampler())),
+            },
+        ]);
+
+        let pipeline_layout = context.device.create_pipeline_layout(
+            Some(set_layout.raw()),
+            &[(pso::ShaderStageFlags::VERTEX, 0..PUSH_CONSTANTS_SIZE)],
+        );
+
+        let mut clock = Clock::new(context.is_headless());
+
+        let mut shaders = ShaderSet::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"), shaders::ALL);
+        let mut pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+
+        let mut framebuffers = Framebuffers::new(context.device.clone(), &render_pass, &swapchain)?;
+        let mut frame_sync = FrameSync::new(
+            context.device.clone(),
+            &context.queue_group,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        );
+
+        let mut running = true;
+        let mut recreate_swapchain = false;
+        while running {
+            let mut toggle_vsync = false;
+            let mut toggle_fullscreen = false;
+            let mut take_screenshot = false;
+            events.poll_actions(|action| match action {
+                WindowAction::Close => running = false,
+                WindowAction::Resize => recreate_swapchain = true,
+                WindowAction::ToggleVsync => toggle_vsync = true,
+                WindowAction::ToggleFullscreen => toggle_fullscreen = true,
+                WindowAction::Screenshot => take_screenshot = true,
+            });
+
+            if toggle_vsync {
+                context.toggle_vsync(&swapchain);
+                recreate_swapchain = true;
+            }
+            if toggle_fullscreen {
+                context.toggle_fullscreen();
+                recreate_swapchain = true;
+            }
+            if context.poll_config() {
+                recreate_swapchain = true;
+            }
+
+            // Rebuild the pipeline if any of its shaders were edited
+            if !shaders.poll_changes().is_empty() {
+                context.wait_idle()?;
+                match create_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous pipeline: {}", err),
+                }
+            }
+
+            if recreate_swapchain {
+                context.recreate_swapchain(&mut swapchain)?;
+                framebuffers.recreate(&render_pass, &swapchain)?;
+                recreate_swapchain = false;
+            }
+
+            // Waits until the gpu is done with the last frame that used these resources
+            let mut frame = frame_sync.begin_frame()?;
+
+            let image_index = match swapchain.acquire_image(&mut frame, &mut context.queue_group.queues[0]) {
+                Ok(image_index) => image_index,
+                Err(_) => {
+                    recreate_swapchain = true;
+                    continue;
+                }
+            };
+
+            // The pipeline doesn't bake in a viewport, so we set it (and the scissor) every
+            // frame, which also means we don't need to rebuild the pipeline on resize.
+            let viewport = swapchain.viewport();
+
+            let finished_command_buffer = {
+                let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+
+                command_buffer.set_viewports(0, &[viewport.clone()]);
+                command_buffer.set_scissors(0, &[viewport.rect]);
+                command_buffer.bind_graphics_pipeline(&pipeline);
+                command_buffer.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(vertex_buffer.buffer(), 0)]));
+                command_buffer.bind_index_buffer(buffer::IndexBufferView {
+                    buffer: index_buffer.buffer(),
+                    offset: 0,
+                    index_type: IndexType::U16,
+                });
+
+                let seconds = clock.frame_seconds();
+                let extent = swapchain.extent();
+                let push_constants = PushConstants::spinning(seconds * 0.25, extent.width as f32 / extent.height as f32);
+                command_buffer.bind_graphics_descriptor_sets(&pipeline_layout, 0, Some(&descriptor_set), &[] as &[u32]);
+                command_buffer.push_graphics_constants(
+                    &pipeline_layout,
+                    pso::ShaderStageFlags::VERTEX,
+                    0,
+                    push_constants.as_words(),
+                );
+
+                {
+                    let mut encoder = command_buffer.begin_render_pass_inline(
+                        &render_pass,
+                        framebuffers.get(image_index),
+                        viewport.rect,
+                        &[command::ClearValue::Color(command::ClearColor::Float(srgb_to_linear_rgba(CLEAR_COLOR)))],
+                    );
+                    encoder.draw_indexed(0..QUAD_INDICES.len() as u32, 0, 0..1);
+                }
+
+                command_buffer.finish()
+            };
+
+            let submission = Submission::new()
+                .wait_on(&[(frame.image_available(), PipelineStage::COLOR_ATTACHMENT_OUTPUT)])
+                .signal(&[frame.render_finished()])
+                .submit(Some(finished_command_buffer));
+            context.device.reset_fence(frame.fence());
+            context.queue_group.queues[0].submit(submission, Some(frame.fence()));
+
+            if take_screenshot {
+                match screenshot::capture(context, &swapchain, image_index) {
+                    Ok(path) => info!("Saved a screenshot to {}", path.display()),
+                    Err(err) => error!("{}", err),
+                }
+            }
+
+            if swapchain.present(&frame, &mut context.queue_group.queues[0], image_index).is_err() {
+                recreate_swapchain = true;
+            }
+        }
+
+        context.wait_idle()?;
+
+        drop(framebuffers);
+        drop(vertex_buffer);
+        drop(index_buffer);
+        drop(descriptors);
+        drop(texture);
+        context.device.destroy_graphics_pipeline(pipeline);
+        context.device.destroy_pipeline_layout(pipeline_layout);
+        context.device.destroy_render_pass(render_pass);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,24 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+} camera;
+
+layout(push_constant) uniform PushConstants {
+    mat4 model;
+} push_constants;
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 color;
+
+layout(location = 0) out vec3 frag_color;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = camera.view_projection * push_constants.model * vec4(position, 1.0);
+    frag_color = color;
+}
@@ -0,0 +1,725 @@
+extern crate gfx_hal as hal;
+#[macro_use]
+extern crate log;
+extern crate renderer_common;
+
+use std::iter;
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+use std::time::Instant;
+
+use hal::{
+    buffer, command, format as f, image as i, pass, query, IndexType,
+    pso::{ self, PipelineStage },
+    Backend, Device,
+    Primitive, Submission,
+};
+
+use renderer_common::color::srgb_to_linear_rgba;
+use renderer_common::depth::choose_depth_format;
+use renderer_common::descriptors::{ DescriptorAllocator, DescriptorSetLayout, UniformRing };
+use renderer_common::events::WindowAction;
+use renderer_common::frame_sync;
+use renderer_common::math::{ Mat4, Quat, Rad, Rotation3, ShaderMatrix };
+use renderer_common::msaa::choose_sample_count;
+use renderer_common::present;
+use renderer_common::screenshot;
+use renderer_common::shader::{ create_shader_module, ShaderSet };
+use renderer_common::timestep::TICK_SECONDS;
+use renderer_common::{
+    upload_buffer, Aabb, AttachmentImages, Camera, CameraSwitch, Clock, CpuProfiler, CullStats,
+    DebugOverlay, Events, FixedTimestep, FpsCamera, FrameSync, Framebuffers, Gamepads, GfxContext,
+    GpuProfiler, Input, Interpolated, MemoryCategory, OcclusionCuller, OrbitCamera, OverlaySettings,
+    OverlayStats, Result, Runner, Transform,
+};
+
+const CLEAR_COLOR: [f32; 4] = [0.1, 0.2, 0.3, 1.0];
+
+/// The SPIR-V for the shaders in `shaders/`, compiled by `build.rs`
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// The layout of this struct has to match the vertex attributes we describe to the pipeline,
+/// hence the `repr(C)`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Vertex {
+    position: [f32; 3],
+    color: [f32; 3],
+}
+
+/// The corners of a unit cube centred on the origin.
+const CUBE_CORNERS: [[f32; 3]; 8] = [
+    [-0.5, -0.5, -0.5],
+    [0.5, -0.5, -0.5],
+    [0.5, 0.5, -0.5],
+    [-0.5, 0.5, -0.5],
+    [-0.5, -0.5, 0.5],
+    [0.5, -0.5, 0.5],
+    [0.5, 0.5, 0.5],
+    [-0.5, 0.5, 0.5],
+];
+
+/// The box around `CUBE_CORNERS`, before the cube is moved into place.
+const CUBE_BOUNDS: Aabb = Aabb { min: [-0.5; 3], max: [0.5; 3] };
+
+/// Each face as four indices into `CUBE_CORNERS`, along with the color it's drawn in. Faces
+/// don't share vertices so that each one can have its own flat color.
+const CUBE_FACES: [([usize; 4], [f32; 3]); 6] = [
+    ([4, 5, 6, 7], [1.0, 0.0, 0.0]), // +z
+    ([1, 0, 3, 2], [0.0, 1.0, 1.0]), // -z
+    ([5, 1, 2, 6], [0.0, 1.0, 0.0]), // +x
+    ([0, 4, 7, 3], [1.0, 0.0, 1.0]), // -x
+    ([7, 6, 2, 3], [0.0, 0.0, 1.0]), // +y
+    ([0, 1, 5, 4], [1.0, 1.0, 0.0]), // -y
+];
+
+fn cube_vertices() -> Vec<Vertex> {
+    CUBE_FACES
+        .iter()
+        .flat_map(|&(corners, color)| {
+            corners.iter().map(move |&corner| Vertex { position: CUBE_CORNERS[corner], color })
+        })
+        .collect()
+}
+
+fn cube_indices() -> Vec<u16> {
+    (0..CUBE_FACES.len() as u16)
+        .flat_map(|face| {
+            let base = face * 4;
+            vec![base, base + 1, base + 2, base + 2, base + 3, base]
+        })
+        .collect()
+}
+
+/// Where a cube `spin` radians round the y axis at `position` is, tipped towards the camera a
+/// little so the top faces are visible.
+fn cube_transform(position: [f32; 3], spin: f32) -> Transform {
+    let rotation = Quat::from_angle_x(Rad(0.5)) * Quat::from_angle_y(Rad(spin));
+    Transform { rotation, ..Transform::from_translation(position) }
+}
+
+/// Stretches the cube over `bounds`, for drawing it as an occlusion proxy.
+fn bounds_matrix(bounds: &Aabb) -> Mat4 {
+    let size = bounds.size();
+    Mat4::from_translation(bounds.center().into())
+        * Mat4::from_nonuniform_scale(size[0], size[1], size[2])
+}
+
+/// Has to match the `Camera` block in `cube.vert`. It's written once per frame, into that
+/// frame's copy of the uniform buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CameraUniform {
+    view_projection: ShaderMatrix,
+}
+
+/// Has to match the `PushConstants` block in `cube.vert`. Matrices are column major.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PushConstants {
+    model: ShaderMatrix,
+}
+
+impl PushConstants {
+    /// Push constants are written as a slice of 32 bit words.
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const PushConstants as *const u32,
+                mem::size_of::<PushConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `PushConstants`, in 32 bit words
+const PUSH_CONSTANTS_SIZE: u32 = (mem::size_of::<PushConstants>() / mem::size_of::<u32>()) as u32;
+
+/// Where the two cubes sit, and which way they spin. They're close enough together that they
+/// cut into each other, which is the point: without a depth buffer whichever is drawn second
+/// would always end up on top.
+const CUBES: [([f32; 3], f32); 2] = [
+    ([-0.35, 0.0, -3.0], 1.0),
+    ([0.35, 0.0, -3.2], -0.7),
+];
+
+/// Builds the graphics pipeline from the current SPIR-V in `shaders`. This is called again
+/// whenever one of the shaders is changed on disk.
+///
+/// With `occlusion_proxy` set, the pipeline writes neither color nor depth, for drawing
+/// bounding boxes that only need to be depth tested.
+fn create_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+    occlusion_proxy: bool,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("cube.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("cube.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        // Keep whichever fragment is closest to the camera
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: !occlusion_proxy,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        // Has to match the sample count of the render pass attachments
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        let color_mask = if occlusion_proxy { pso::ColorMask::empty() } else { pso::ColorMask::ALL };
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(color_mask, pso::BlendState::ALPHA));
+
+        // One interleaved vertex buffer, with a position and color attribute
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<Vertex>() as u32,
+            rate: 0,
+        });
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: 0,
+            binding: 0,
+            element: pso::Element {
+                format: f::Format::Rgb32Float,
+                offset: 0,
+            },
+        });
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: 1,
+            binding: 0,
+            element: pso::Element {
+                format: f::Format::Rgb32Float,
+                offset: 12,
+            },
+        });
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    // The modules are only needed while the pipeline is being built
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the graphics pipeline");
+    Ok(pipeline)
+}
+
+/// The extra attachments that follow the swapchain image in each framebuffer, in the order
+/// `create_multisampled_render_pass` expects them.
+fn framebuffer_attachments<'a, B: Backend>(
+    msaa_targets: &'a Option<AttachmentImages<B>>,
+    depth_images: &'a AttachmentImages<B>,
+) -> Vec<&'a AttachmentImages<B>> {
+    msaa_targets.iter().chain(iter::once(depth_images)).collect()
+}
+
+fn main() {
+    renderer_common::launch("voxel-renderer", Chapter);
+}
+
+struct Chapter;
+
+impl Runner for Chapter {
+    fn run<B: Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<()> {
+        let mut cpu_profiler = CpuProfiler::new(context.args.cpu_trace.is_some());
+        let mut swapchain = context.create_swapchain()?;
+        let samples = choose_sample_count(&context.adapter, context.args.msaa)?;
+        let depth_format = choose_depth_format(&context.adapter)?;
+        info!("Depth format: {:?}, {}x MSAA", depth_format, samples);
+        let render_pass = renderer_common::pass::create_multisampled_render_pass::<B>(
+            &context.device,
+            swapchain.format(),
+            depth_format,
+            samples,
+        );
+
+        // With multisampling on we draw into a multisampled color target instead of the
+        // swapchain image, and the render pass resolves it into the swapchain image at the end
+        let mut msaa_targets = if samples > 1 {
+            Some(AttachmentImages::multisampled_color(
+                context.device.clone(),
+                context.allocator.clone(),
+                swapchain.format(),
+                samples,
+                &swapchain,
+            )?)
+        } else {
+            None
+        };
+        let mut depth_images = AttachmentImages::depth(
+            context.device.clone(),
+            context.allocator.clone(),
+            depth_format,
+            samples,
+            &swapchain,
+        )?;
+
+        cpu_profiler.begin_scope("meshing");
+        let vertices = cube_vertices();
+        let indices = cube_indices();
+        cpu_profiler.end_scope();
+        cpu_profiler.begin_scope("upload");
+        let vertex_buffer = upload_buffer(context, &vertices, buffer::Usage::VERTEX, MemoryCategory::Meshes)?;
+        let index_buffer = upload_buffer(context, &indices, buffer::Usage::INDEX, MemoryCategory::Meshes)?;
+        cpu_profiler.end_scope();
+        info!("Gpu memory: {}", context.allocator.borrow().stats());
+
+        // The camera's matrix comes from a uniform buffer, since it's the same for every draw,
+        // and each cube's model matrix from push constants
+        let camera_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![pso::DescriptorSetLayoutBinding {
+                binding: 0,
+                ty: pso::DescriptorType::UniformBuffer,
+                count: 1,
+                stage_flags: pso::ShaderStageFlags::VERTEX,
+                immutable_samplers: false,
+            }],
+        ));
+        let mut descriptors = DescriptorAllocator::new(context.device.clone(), camera_layout.clone());
+        let pipeline_layout = context.device.create_pipeline_layout(
+            Some(camera_layout.raw()),
+            &[(pso::ShaderStageFlags::VERTEX, 0..PUSH_CONSTANTS_SIZE)],
+        );
+
+        let mut clock = Clock::new(context.is_headless());
+        let mut timestep = FixedTimestep::new();
+        let mut input = Input::new(&context.config.settings().bindings);
+        input.set_grab_on_focus(!context.is_headless());
+        let mut gamepads = Gamepads::new(!context.is_headless());
+        // When the clock last read, for how far the gamepad sticks turn each frame
+        let mut last_seconds = 0.0;
+        // How far each cube has turned, which is advanced a tick at a time like the camera
+        let mut spins = [Interpolated::new(0.0); 2];
+        // The orbit camera circles the point between the two cubes, starting from where the FPS
+        // camera is
+        let mut camera = CameraSwitch::new(
+            FpsCamera::new([0.0, 0.0, 0.0], context.config.settings()),
+            OrbitCamera::new([0.0, 0.0, -3.1], 3.1, context.config.settings()),
+        );
+
+        let mut shaders = ShaderSet::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"), shaders::ALL);
+        let mut pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+            false,
+        )?;
+        let mut proxy_pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+            true,
+        )?;
+
+        let mut framebuffers = Framebuffers::with_attachments(
+            context.device.clone(),
+            &render_pass,
+            &swapchain,
+            &framebuffer_attachments(&msaa_targets, &depth_images),
+        )?;
+
+        // One clear value per attachment. The resolve target is never loaded, so its value
+        // doesn't matter.
+        let color_clear = command::ClearValue::Color(command::ClearColor::Float(srgb_to_linear_rgba(CLEAR_COLOR)));
+        let depth_clear = command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0));
+        let clear_values = if samples > 1 {
+            vec![color_clear.clone(), color_clear, depth_clear]
+        } else {
+            vec![color_clear, depth_clear]
+        };
+        let mut frame_sync = FrameSync::new(
+            context.device.clone(),
+            &context.queue_group,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        );
+        let mut gpu_profiler = GpuProfiler::new(
+            context.device.clone(),
+            &context.adapter,
+            frame_sync.frames_in_flight(),
+        );
+        let camera_uniforms = UniformRing::<B, CameraUniform>::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            &context.adapter,
+            &mut descriptors,
+            0,
+            frame_sync.frames_in_flight(),
+        )?;
+        let mut occlusion = OcclusionCuller::new(context.device.clone(), frame_sync.frames_in_flight());
+        let mut occlusion_culling = true;
+        let mut overlay = DebugOverlay::new(context, &swapchain, frame_sync.frames_in_flight())?;
+        let mut last_timing_report = Instant::now();
+
+        let mut running = true;
+        let mut recreate_swapchain = false;
+        while running {
+            cpu_profiler.begin_frame();
+            let mut toggle_vsync = false;
+            let mut toggle_fullscreen = false;
+            let mut take_screenshot = false;
+            input.begin_frame();
+            events.poll_with(
+                |event| {
+                    overlay.handle_event(event);
+                    // While the cursor is grabbed it's hidden, so it can't be over the overlay
+                    if !input.cursor_grabbed() && overlay.captures(event) {
+                        return false;
+                    }
+                    input.handle_event(event)
+                },
+                |action| match action {
+                    WindowAction::Close => running = false,
+                    WindowAction::Resize => recreate_swapchain = true,
+                    WindowAction::ToggleVsync => toggle_vsync = true,
+                    WindowAction::ToggleFullscreen => toggle_fullscreen = true,
+                    WindowAction::Screenshot => take_screenshot = true,
+                },
+            );
+
+            if toggle_vsync {
+                context.toggle_vsync(&swapchain);
+                recreate_swapchain = true;
+            }
+            if toggle_fullscreen {
+                context.toggle_fullscreen();
+                recreate_swapchain = true;
+            }
+            if context.poll_config() {
+                recreate_swapchain = true;
+            }
+
+            // Rebuild the pipelines if any of their shaders were edited
+            if !shaders.poll_changes().is_empty() {
+                context.wait_idle()?;
+                for &occlusion_proxy in &[false, true] {
+                    match create_pipeline::<B>(
+                        &context.device,
+                        &shaders,
+                        &render_pass,
+                        &pipeline_layout,
+                        context.pipeline_cache.cache(),
+                        samples,
+                        occlusion_proxy,
+                    ) {
+                        Ok(new_pipeline) => {
+                            let target = if occlusion_proxy { &mut proxy_pipeline } else { &mut pipeline };
+                            let old_pipeline = mem::replace(target, new_pipeline);
+                            context.device.destroy_graphics_pipeline(old_pipeline);
+                        }
+                        Err(err) => error!("Keeping the previous pipeline: {}", err),
+                    }
+                }
+            }
+
+            if recreate_swapchain {
+                context.recreate_swapchain(&mut swapchain)?;
+                if let Some(ref mut msaa_targets) = msaa_targets {
+                    msaa_targets.recreate(&swapchain)?;
+                }
+                depth_images.recreate(&swapchain)?;
+                framebuffers.recreate_with_attachments(
+                    &render_pass,
+                    &swapchain,
+                    &framebuffer_attachments(&msaa_targets, &depth_images),
+                )?;
+                overlay.recreate(&swapchain)?;
+                recreate_swapchain = false;
+            }
+
+            // Run however many simulation ticks have come due since the last frame
+            cpu_profiler.begin_scope("simulate");
+            context.update_cursor_grab(input.cursor_grabbed());
+            input.apply_bindings(&context.config.settings().bindings);
+            let seconds = clock.frame_seconds();
+            gamepads.poll(&mut input, context.config.settings(), seconds - last_seconds);
+            last_seconds = seconds;
+            camera.apply_settings(context.config.settings());
+            camera.handle_input(&input);
+            for _ in 0..timestep.advance_to(seconds) {
+                camera.update(&input, TICK_SECONDS);
+                for (&(_, speed), spin) in CUBES.iter().zip(spins.iter_mut()) {
+                    let angle = spin.current() + speed * TICK_SECONDS;
+                    spin.set(angle);
+                }
+            }
+            cpu_profiler.end_scope();
+
+            // Waits until the gpu is done with the last frame that used these resources
+            cpu_profiler.begin_scope("wait");
+            let mut frame = frame_sync.begin_frame()?;
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("acquire");
+            let image_index = match swapchain.acquire_image(&mut frame, &mut context.queue_group.queues[0]) {
+                Ok(image_index) => image_index,
+                Err(_) => {
+                    recreate_swapchain = true;
+                    continue;
+                }
+            };
+            cpu_profiler.end_scope();
+
+            // The pipeline doesn't bake in a viewport, so we set it (and the scissor) every
+            // frame, which also means we don't need to rebuild the pipeline on resize.
+            let viewport = swapchain.viewport();
+
+            let vsync = present::is_vsync(swapchain.present_mode());
+            let mut overlay_settings = OverlaySettings {
+                vsync,
+                wireframe: None,
+                shadows: None,
+                show_cascades: None,
+                occlusion_culling: Some(occlusion_culling),
+                mesher: None,
+                shading: None,
+                view_mode: None,
+                debug_lines: None,
+                time_of_day: None,
+            };
+
+            cpu_profiler.begin_scope("record");
+            let finished_command_buffer = {
+                let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+                gpu_profiler.begin_frame(&mut command_buffer, frame.index)?;
+                occlusion.begin_frame(&mut command_buffer, frame.index)?;
+
+                command_buffer.set_viewports(0, &[viewport.clone()]);
+                command_buffer.set_scissors(0, &[viewport.rect]);
+                command_buffer.bind_graphics_pipeline(&pipeline);
+                command_buffer.bind_graphics_descriptor_sets(
+                    &pipeline_layout,
+                    0,
+                    Some(camera_uniforms.set(frame.index)),
+                    &[],
+                );
+                command_buffer.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(vertex_buffer.buffer(), 0)]));
+                command_buffer.bind_index_buffer(buffer::IndexBufferView {
+                    buffer: index_buffer.buffer(),
+                    offset: 0,
+                    index_type: IndexType::U16,
+                });
+
+                // Draw in between the last two ticks, however far the clock is through the next
+                let alpha = timestep.alpha();
+                let extent = swapchain.extent();
+                let aspect = extent.width as f32 / extent.height as f32;
+                let view_projection = camera.interpolated_view_projection(aspect, alpha).into();
+                camera_uniforms.update(frame.index, &CameraUniform { view_projection })?;
+                let frustum = camera.frustum(aspect, alpha);
+                let mut culling = CullStats::new();
+                let eye = camera.position();
+
+                gpu_profiler.begin_scope(&mut command_buffer, "opaque");
+                {
+                    let mut encoder = command_buffer.begin_render_pass_inline(
+                        &render_pass,
+                        framebuffers.get(image_index),
+                        viewport.rect,
+                        &clear_values,
+                    );
+
+                    // Same geometry, different transform: one draw per cube, skipping any that
+                    // are out of view or were hidden last time they were tested
+                    let mut hidden = Vec::new();
+                    for (key, (&(position, _), spin)) in CUBES.iter().zip(spins.iter()).enumerate() {
+                        let key = key as u64;
+                        let transform = cube_transform(position, spin.get(alpha));
+                        let bounds = CUBE_BOUNDS.transformed(&transform);
+                        if !culling.test(&frustum, &bounds) {
+                            occlusion.forget(key);
+                            continue;
+                        }
+
+                        let occlusion_query = if occlusion_culling && !bounds.contains_point(eye) {
+                            occlusion.next_query(key)
+                        } else {
+                            None
+                        };
+                        if let Some(id) = occlusion_query {
+                            if !occlusion.is_visible(key) {
+                                culling.occlude();
+                                hidden.push((id, bounds));
+                                continue;
+                            }
+                        }
+
+                        let push_constants = PushConstants { model: transform.matrix().into() };
+                        encoder.push_graphics_constants(
+                            &pipeline_layout,
+                            pso::ShaderStageFlags::VERTEX,
+                            0,
+                            push_constants.as_words(),
+                        );
+                        if let Some(id) = occlusion_query {
+                            encoder.begin_query(occlusion.query(id), query::QueryControl::empty());
+                        }
+                        encoder.draw_indexed(0..indices.len() as u32, 0, 0..1);
+                        if let Some(id) = occlusion_query {
+                            encoder.end_query(occlusion.query(id));
+                        }
+                    }
+
+                    // Test whether the hidden cubes have come back into view by drawing their
+                    // bounding boxes last, once everything visible is in the depth buffer
+                    if !hidden.is_empty() {
+                        encoder.bind_graphics_pipeline(&proxy_pipeline);
+                        for &(id, ref bounds) in &hidden {
+                            let push_constants = PushConstants { model: bounds_matrix(bounds).into() };
+                            encoder.push_graphics_constants(
+                                &pipeline_layout,
+                                pso::ShaderStageFlags::VERTEX,
+                                0,
+                                push_constants.as_words(),
+                            );
+                            encoder.begin_query(occlusion.query(id), query::QueryControl::empty());
+                            encoder.draw_indexed(0..indices.len() as u32, 0, 0..1);
+                            encoder.end_query(occlusion.query(id));
+                        }
+                    }
+                }
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                // The overlay goes on top of the resolved image, in a pass of its own
+                gpu_profiler.begin_scope(&mut command_buffer, "overlay");
+                {
+                    let stats = OverlayStats {
+                        gpu_timings: gpu_profiler.timings(),
+                        camera_position: Some(camera.position()),
+                        culling: Some(culling),
+                        ..OverlayStats::default()
+                    };
+                    overlay.draw(
+                        &mut command_buffer,
+                        image_index,
+                        &swapchain,
+                        &stats,
+                        &mut overlay_settings,
+                    )?;
+                }
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                command_buffer.finish()
+            };
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("submit");
+            let submission = Submission::new()
+                .wait_on(&[(frame.image_available(), PipelineStage::COLOR_ATTACHMENT_OUTPUT)])
+                .signal(&[frame.render_finished()])
+                .submit(Some(finished_command_buffer));
+            context.device.reset_fence(frame.fence());
+            context.queue_group.queues[0].submit(submission, Some(frame.fence()));
+            cpu_profiler.end_scope();
+
+            if take_screenshot {
+                match screenshot::capture(context, &swapchain, image_index) {
+                    Ok(path) => info!("Saved a screenshot to {}", path.display()),
+                    Err(err) => error!("{}", err),
+                }
+            }
+
+            cpu_profiler.begin_scope("present");
+            if swapchain.present(&frame, &mut context.queue_group.queues[0], image_index).is_err() {
+                recreate_swapchain = true;
+            }
+            cpu_profiler.end_scope();
+
+            // The swapchain can't change mid-frame, so a vsync toggle from the overlay takes
+            // effect on the next one
+            if overlay_settings.vsync != vsync {
+                context.set_vsync(overlay_settings.vsync);
+                recreate_swapchain = true;
+            }
+            if let Some(enabled) = overlay_settings.occlusion_culling {
+                occlusion_culling = enabled;
+            }
+
+            if last_timing_report.elapsed().as_secs() >= 1 {
+                for timing in gpu_profiler.timings() {
+                    debug!("Gpu time for {}: {:.3} ms", timing.name, timing.milliseconds);
+                }
+                debug!("Cpu time for the last frame:\n{}", cpu_profiler.report());
+                last_timing_report = Instant::now();
+            }
+        }
+
+        context.wait_idle()?;
+
+        if let Some(ref path) = context.args.cpu_trace {
+            cpu_profiler.write_chrome_trace(path)?;
+            info!("Wrote the cpu trace to {}", path.display());
+        }
+
+        drop(overlay);
+        drop(occlusion);
+        drop(framebuffers);
+        drop(depth_images);
+        drop(msaa_targets);
+        drop(vertex_buffer);
+        drop(index_buffer);
+        drop(camera_uniforms);
+        drop(descriptors);
+        context.device.destroy_graphics_pipeline(pipeline);
+        context.device.destroy_graphics_pipeline(proxy_pipeline);
+        context.device.destroy_pipeline_layout(pipeline_layout);
+        context.device.destroy_render_pass(render_pass);
+
+        Ok(())
+    }
+}
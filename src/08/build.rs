@@ -0,0 +1,5 @@
+extern crate shader_build;
+
+fn main() {
+    shader_build::compile_shaders("shaders");
+}
@@ -0,0 +1,59 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+layout(set = 0, binding = 1) uniform texture2D atlas_texture;
+layout(set = 0, binding = 2) uniform sampler atlas_sampler;
+
+layout(location = 0) in vec2 frag_uv;
+layout(location = 1) in vec4 frag_color;
+// Sky light, then block light, from 0 to 1
+layout(location = 2) in vec2 frag_light;
+layout(location = 3) in vec3 frag_position;
+
+layout(location = 0) out vec4 out_color;
+
+// Like `chunk.frag`, but a sprite faces wherever the camera is, so it gets the ambient light
+// from the sky and the light from lamps, but no sun or shadows
+const float LIGHT_FALLOFF = 0.8;
+const float MAX_LIGHT = 15.0;
+const float BLOCK_LIGHT_SHADE = 0.8;
+// Anything less opaque than this is cut out rather than blended, so the clear parts of a tile
+// don't hide what's behind them in the depth buffer
+const float ALPHA_CUTOFF = 0.05;
+
+float brightness(float level) {
+    return pow(LIGHT_FALLOFF, MAX_LIGHT * (1.0 - level));
+}
+
+// How far towards the edge of the loaded world the fog starts thickening to hide it
+const float FOG_EDGE_START = 0.75;
+
+// `color` at `position` seen through the fog. The fog thins out exponentially going up, so how
+// much there is along the way is its density around the camera times the distance, scaled by
+// how much it thins out between the two. Its colour is the sky's at the horizon, warmer looking
+// towards the sun.
+vec3 fog(vec3 color, vec3 position) {
+    vec3 to_position = position - camera.eye;
+    float distance = length(to_position);
+    float climb = camera.fog_height_falloff * to_position.y;
+    float thinning = abs(climb) > 0.0001 ? (1.0 - exp(-climb)) / climb : 1.0;
+    float amount = 1.0 - exp(-camera.fog_density * distance * thinning);
+    amount = max(amount, smoothstep(FOG_EDGE_START * camera.fog_end, camera.fog_end, distance));
+
+    vec2 across = to_position.xz / max(length(to_position.xz), 0.0001);
+    vec2 sun_across = camera.sun_direction.xz / max(length(camera.sun_direction.xz), 0.0001);
+    float towards_sun = pow(max(dot(across, sun_across), 0.0), 8.0);
+    vec3 fog_color = mix(camera.fog_color, camera.fog_sun_color, towards_sun);
+    return mix(color, fog_color, amount);
+}
+
+void main() {
+    vec4 color = texture(sampler2D(atlas_texture, atlas_sampler), frag_uv) * frag_color;
+    if (color.a < ALPHA_CUTOFF) {
+        discard;
+    }
+    float sky = brightness(frag_light.x * camera.daylight) * camera.ambient;
+    float block = brightness(frag_light.y) * BLOCK_LIGHT_SHADE;
+    out_color = vec4(fog(color.rgb * max(sky, block), frag_position), color.a);
+}
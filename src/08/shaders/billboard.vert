@@ -0,0 +1,43 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+
+// One sprite per instance, from `Billboards`. Has to match `Sprite`.
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 size;
+// The top left corner of the sprite's part of the texture, then the bottom right
+layout(location = 2) in vec4 uv_rect;
+layout(location = 3) in vec4 color;
+layout(location = 4) in vec2 light;
+
+layout(location = 0) out vec2 frag_uv;
+layout(location = 1) out vec4 frag_color;
+layout(location = 2) out vec2 frag_light;
+layout(location = 3) out vec3 frag_position;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+// The quad's two triangles, from its bottom left corner at (0, 0) to its top right at (1, 1)
+const vec2 CORNERS[6] = vec2[](
+    vec2(0.0, 0.0),
+    vec2(1.0, 0.0),
+    vec2(1.0, 1.0),
+    vec2(1.0, 1.0),
+    vec2(0.0, 1.0),
+    vec2(0.0, 0.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    vec2 offset = (corner - 0.5) * size;
+    vec3 world_position = position + camera.camera_right * offset.x + camera.camera_up * offset.y;
+    gl_Position = camera.view_projection * vec4(world_position, 1.0);
+    // Textures go down from the top, and the quad goes up from the bottom
+    frag_uv = mix(uv_rect.xy, uv_rect.zw, vec2(corner.x, 1.0 - corner.y));
+    frag_color = color;
+    frag_light = light;
+    frag_position = world_position;
+}
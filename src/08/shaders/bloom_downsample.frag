@@ -0,0 +1,38 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// The mip above this one
+layout(set = 0, binding = 0) uniform texture2D source;
+layout(set = 0, binding = 1) uniform sampler source_sampler;
+
+// Has to match `PostConstants` in main.rs
+layout(push_constant) uniform PushConstants {
+    // The size of what's being drawn into, in pixels
+    vec2 target_size;
+    // What the scene is scaled by before it's tonemapped
+    float exposure;
+    // Which curve to use, from `Tonemap::id`
+    uint tonemap;
+    // How much of the bloom is added onto the scene
+    float bloom_strength;
+    // How bright something has to be, once it's been exposed, before it glows
+    float bloom_threshold;
+} constants;
+
+layout(location = 0) out vec4 out_color;
+
+// Four filtered taps a texel out from the corner this pixel is centred on each blend a 2x2
+// block, which between them cover the 4x4 pixels of the source around it
+vec3 downsample() {
+    vec2 uv = gl_FragCoord.xy / constants.target_size;
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(source, source_sampler), 0));
+    vec3 color = texture(sampler2D(source, source_sampler), uv + texel * vec2(-1.0, -1.0)).rgb;
+    color += texture(sampler2D(source, source_sampler), uv + texel * vec2(1.0, -1.0)).rgb;
+    color += texture(sampler2D(source, source_sampler), uv + texel * vec2(-1.0, 1.0)).rgb;
+    color += texture(sampler2D(source, source_sampler), uv + texel * vec2(1.0, 1.0)).rgb;
+    return color * 0.25;
+}
+
+void main() {
+    out_color = vec4(downsample(), 1.0);
+}
@@ -0,0 +1,38 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// The mip below this one, with everything below it already added in
+layout(set = 0, binding = 0) uniform texture2D source;
+layout(set = 0, binding = 1) uniform sampler source_sampler;
+
+// Has to match `PostConstants` in main.rs
+layout(push_constant) uniform PushConstants {
+    // The size of what's being drawn into, in pixels
+    vec2 target_size;
+    // What the scene is scaled by before it's tonemapped
+    float exposure;
+    // Which curve to use, from `Tonemap::id`
+    uint tonemap;
+    // How much of the bloom is added onto the scene
+    float bloom_strength;
+    // How bright something has to be, once it's been exposed, before it glows
+    float bloom_threshold;
+} constants;
+
+// Added onto what the downsample left in this mip
+layout(location = 0) out vec4 out_color;
+
+// A 3x3 tent filter a texel of the source wide, which smooths out the blockiness of blowing a
+// mip up to twice its size
+void main() {
+    vec2 uv = gl_FragCoord.xy / constants.target_size;
+    vec2 texel = 1.0 / vec2(textureSize(sampler2D(source, source_sampler), 0));
+    vec3 color = vec3(0.0);
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            float weight = (2.0 - abs(float(x))) * (2.0 - abs(float(y)));
+            color += texture(sampler2D(source, source_sampler), uv + texel * vec2(x, y)).rgb * weight;
+        }
+    }
+    out_color = vec4(color / 16.0, 1.0);
+}
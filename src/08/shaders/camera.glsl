@@ -0,0 +1,61 @@
+// The camera, shadow cascades, atlas layout and lighting every pass reads, in set 0 binding 0.
+// Has to match `CameraUniform` in main.rs.
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    // Back from clip space to world space, for finding where each pixel of the G-buffer is
+    mat4 inverse_view_projection;
+    // From world space to each cascade's shadow map, with x and y from -1 to 1 across it
+    mat4 shadow_view_projections[4];
+    // How far along the view each cascade ends
+    vec4 cascade_splits;
+    // How far along the view a world position is, as dot(view_depth, vec4(position, 1.0))
+    vec4 view_depth;
+    vec2 atlas_origin;
+    vec2 atlas_cell;
+    vec2 atlas_tile_size;
+    uint atlas_columns;
+    // Towards the sun, scaled by how strongly it's shining
+    vec3 sun;
+    // How strongly the sky lights blocks, and how much of that reaches faces turned away from
+    // the sun
+    float daylight;
+    float ambient;
+    // Non-zero to tint everything by the cascade it's in
+    uint show_cascades;
+    // Towards the sun, whether it's up or not
+    vec3 sun_direction;
+    // Which sky is drawn behind the terrain, from `Sky::id`
+    uint sky;
+    // The colour of the sky at this time of day
+    vec3 sky_color;
+    // How thick the fog is around the camera, and how quickly it thins out going up
+    float fog_density;
+    vec3 eye;
+    float fog_height_falloff;
+    // The colour of the sky at the horizon, looking away from the sun and towards it
+    vec3 fog_color;
+    // How far away the edge of the loaded world is, where the fog thickens to hide it
+    float fog_end;
+    vec3 fog_sun_color;
+    // Seconds since the example started, for moving the ripples on the water
+    float time;
+    // Which debug view to draw, from `ViewMode::id`
+    uint view_mode;
+    // Which ways are right and up on the screen, in world space, for turning billboards to
+    // face the camera
+    vec3 camera_right;
+    vec3 camera_up;
+    // From world space to the minimap, looking straight down
+    mat4 minimap_view_projection;
+    // From world space to each face of the environment probe, in the order of the cube's layers
+    mat4 probe_view_projections[6];
+    // Which atlas tiles are polished, and reflect what's around them, one bit each
+    uvec4 polished_tiles;
+    // From world space to the planar reflection, with the world mirrored through the water
+    mat4 reflection_view_projection;
+    // How high the top of the sea is
+    float water_height;
+    // How the water reflects what's around it, from `Reflections::id`
+    uint reflections;
+} camera;
@@ -0,0 +1,246 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+layout(set = 0, binding = 1) uniform texture2D atlas_texture;
+layout(set = 0, binding = 2) uniform sampler atlas_sampler;
+// A layer per cascade
+layout(set = 0, binding = 3) uniform texture2DArray shadow_map;
+layout(set = 0, binding = 4) uniform samplerShadow shadow_sampler;
+// What's around the environment probe, with alpha 0 wherever it saw only sky
+layout(set = 0, binding = 9) uniform textureCube environment;
+layout(set = 0, binding = 10) uniform sampler environment_sampler;
+
+const uint MAX_POINT_LIGHTS = 128;
+struct PointLight {
+    vec3 position;
+    // How far the light reaches, in blocks
+    float radius;
+    vec3 color;
+};
+// The point lights nearest the camera. Only the first `count` are used.
+layout(set = 1, binding = 0) uniform Lights {
+    uint count;
+    PointLight lights[MAX_POINT_LIGHTS];
+} lights;
+
+layout(location = 0) in vec3 frag_normal;
+layout(location = 1) in vec2 frag_uv;
+layout(location = 2) flat in uint frag_tile;
+layout(location = 3) in float frag_ao;
+// Sky light, then block light, from 0 to 1
+layout(location = 4) in vec2 frag_light;
+layout(location = 5) in vec3 frag_position;
+
+layout(location = 0) out vec4 out_color;
+
+// Has to match `ViewMode::id`
+const uint VIEW_NORMALS = 2;
+const uint VIEW_OVERDRAW = 3;
+const uint VIEW_OCCLUSION = 4;
+
+// How dark a fully occluded corner gets
+const float AO_STRENGTH = 0.6;
+// How much dimmer each light level is than the one above it
+const float LIGHT_FALLOFF = 0.8;
+const float MAX_LIGHT = 15.0;
+// How much of a lamp's light reaches every face, whichever way it's turned
+const float BLOCK_LIGHT_SHADE = 0.8;
+
+// How bright point lights are, on top of the light levels they spread through the world
+const float POINT_LIGHT_STRENGTH = 0.8;
+
+// How much of what's around them polished tiles reflect looking straight at them. Like water,
+// they reflect more and more of it towards grazing angles.
+const float POLISHED_REFLECTANCE = 0.04;
+
+const int CASCADE_COUNT = 4;
+// How far past the depth in the shadow map a fragment has to be before it's in shadow, in
+// texels, so faces don't shadow themselves where the map's texels cut through them at an
+// angle. The steeper the sun hits a face, the more of the face one texel covers, so the bias
+// grows with the slope. Counting in texels keeps it right for every cascade, however much of
+// the world their texels cover.
+const float SHADOW_SLOPE_BIAS = 1.5;
+const float MIN_SHADOW_BIAS = 1.0;
+const float MAX_SHADOW_BIAS = 6.0;
+// What each cascade is tinted with when they're being shown
+const vec3 CASCADE_COLORS[CASCADE_COUNT] = vec3[](
+    vec3(1.0, 0.4, 0.4),
+    vec3(0.4, 1.0, 0.4),
+    vec3(0.4, 0.4, 1.0),
+    vec3(1.0, 1.0, 0.4)
+);
+
+// How bright a block lit at `level`, from 0 to 1, looks. Each level is that much dimmer than
+// the one above, so a cave's entrance dims steadily to black rather than staying grey.
+float brightness(float level) {
+    return pow(LIGHT_FALLOFF, MAX_LIGHT * (1.0 - level));
+}
+
+// Which cascade the fragment is in, going by how far along the view it is, or CASCADE_COUNT
+// if it's past the last one.
+int find_cascade() {
+    float depth = dot(camera.view_depth, vec4(frag_position, 1.0));
+    int cascade = 0;
+    while (cascade < CASCADE_COUNT && depth > camera.cascade_splits[cascade]) {
+        cascade++;
+    }
+    return cascade;
+}
+
+// How much of the sun reaches the fragment in `cascade`, from 0 in full shadow to 1, given the
+// cosine of the angle the sun hits it at. The comparison sampler does the depth test, and
+// averaging it over the 3x3 texels around the fragment softens the edges of shadows.
+float sunlit(int cascade, float n_dot_l) {
+    // Anything too far away for shadows is lit
+    if (cascade >= CASCADE_COUNT) {
+        return 1.0;
+    }
+    mat4 shadow_view_projection = camera.shadow_view_projections[cascade];
+    vec4 clip = shadow_view_projection * vec4(frag_position, 1.0);
+    vec3 position = clip.xyz / clip.w;
+    vec2 uv = position.xy * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || position.z > 1.0) {
+        return 1.0;
+    }
+
+    // The projections are orthographic, so their x and z scales say how many blocks a texel
+    // covers and how much depth a block is
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DArrayShadow(shadow_map, shadow_sampler), 0).xy);
+    float width_scale = length(vec3(shadow_view_projection[0][0], shadow_view_projection[1][0], shadow_view_projection[2][0]));
+    float depth_scale = length(vec3(shadow_view_projection[0][2], shadow_view_projection[1][2], shadow_view_projection[2][2]));
+    float texel_depth = 2.0 * texel.x / width_scale * depth_scale;
+    float slope = tan(acos(clamp(n_dot_l, 0.01, 1.0)));
+    float bias = clamp(SHADOW_SLOPE_BIAS * slope, MIN_SHADOW_BIAS, MAX_SHADOW_BIAS) * texel_depth;
+
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec4 coords = vec4(uv + vec2(x, y) * texel, cascade, position.z - bias);
+            lit += texture(sampler2DArrayShadow(shadow_map, shadow_sampler), coords);
+        }
+    }
+    return lit / 9.0;
+}
+
+// The light reaching the fragment straight from the point lights, brightest on faces turned
+// towards them and fading out to nothing at their radius.
+vec3 point_lights(vec3 normal) {
+    vec3 total = vec3(0.0);
+    for (uint index = 0; index < lights.count; index++) {
+        PointLight light = lights.lights[index];
+        vec3 to_light = light.position - frag_position;
+        float to_light_length = length(to_light);
+        if (to_light_length >= light.radius) {
+            continue;
+        }
+        float falloff = 1.0 - to_light_length / light.radius;
+        float facing = max(dot(normal, to_light / max(to_light_length, 0.0001)), 0.0);
+        total += light.color * falloff * falloff * facing;
+    }
+    return total * POINT_LIGHT_STRENGTH;
+}
+
+// How far towards the edge of the loaded world the fog starts thickening to hide it
+const float FOG_EDGE_START = 0.75;
+
+// `color` at `position` seen through the fog. The fog thins out exponentially going up, so how
+// much there is along the way is its density around the camera times the distance, scaled by
+// how much it thins out between the two. Its colour is the sky's at the horizon, warmer looking
+// towards the sun.
+vec3 fog(vec3 color, vec3 position) {
+    vec3 to_position = position - camera.eye;
+    float distance = length(to_position);
+    float climb = camera.fog_height_falloff * to_position.y;
+    float thinning = abs(climb) > 0.0001 ? (1.0 - exp(-climb)) / climb : 1.0;
+    float amount = 1.0 - exp(-camera.fog_density * distance * thinning);
+    amount = max(amount, smoothstep(FOG_EDGE_START * camera.fog_end, camera.fog_end, distance));
+
+    vec2 across = to_position.xz / max(length(to_position.xz), 0.0001);
+    vec2 sun_across = camera.sun_direction.xz / max(length(camera.sun_direction.xz), 0.0001);
+    float towards_sun = pow(max(dot(across, sun_across), 0.0), 8.0);
+    vec3 fog_color = mix(camera.fog_color, camera.fog_sun_color, towards_sun);
+    return mix(color, fog_color, amount);
+}
+
+// Whether `camera.polished_tiles` has `tile`'s bit set.
+bool is_polished(uint tile) {
+    return (camera.polished_tiles[tile / 32] & (1u << (tile % 32))) != 0u;
+}
+
+// What's reflected looking along `direction` from the fragment: the terrain the environment
+// probe saw, and the sky's gradient from the horizon up wherever it only saw sky, dimmed by how
+// much of the sky can be seen from here. Like `water.frag`'s reflection, without the sun.
+vec3 reflect_surroundings(vec3 direction) {
+    vec2 across = direction.xz / max(length(direction.xz), 0.0001);
+    vec2 sun_across = camera.sun_direction.xz / max(length(camera.sun_direction.xz), 0.0001);
+    vec3 horizon = mix(camera.fog_color, camera.fog_sun_color, pow(max(dot(across, sun_across), 0.0), 8.0));
+    vec3 sky = mix(horizon, camera.sky_color, sqrt(max(direction.y, 0.0))) * brightness(frag_light.x);
+    vec4 surroundings = texture(samplerCube(environment, environment_sampler), direction);
+    return mix(sky, surroundings.rgb, surroundings.a);
+}
+
+void main() {
+    // The uvs count blocks across the face, so a quad covering several blocks repeats its tile
+    // once per block. The derivatives are taken before wrapping, or the mip level would jump
+    // at every block edge where fract() does.
+    vec2 cell = vec2(frag_tile % camera.atlas_columns, frag_tile / camera.atlas_columns);
+    vec2 tile_origin = camera.atlas_origin + cell * camera.atlas_cell;
+    vec2 scaled_uv = frag_uv * camera.atlas_tile_size;
+    vec4 color = textureGrad(
+        sampler2D(atlas_texture, atlas_sampler),
+        tile_origin + fract(frag_uv) * camera.atlas_tile_size,
+        dFdx(scaled_uv),
+        dFdy(scaled_uv)
+    );
+
+    // Sky light dims as the sun goes down, and the sun shines on the faces turned towards it
+    // on top of the ambient light from the rest of the sky. Lamps light every face the same.
+    // Whichever is brighter wins, and the baked ambient occlusion shows where faces meet.
+    // Shadows only block the sun, not the light from the rest of the sky. Its strength is
+    // divided back out for the bias rather than normalizing camera.sun, which is all zeros at
+    // night.
+    vec3 normal = normalize(frag_normal);
+    float ao = 1.0 - AO_STRENGTH * (1.0 - frag_ao);
+    // The debug views that show a value show it as it is. Overdraw adds one to red for every
+    // fragment, blended on top of the ones before.
+    if (camera.view_mode == VIEW_NORMALS) {
+        out_color = vec4(normal * 0.5 + 0.5, 1.0);
+        return;
+    }
+    if (camera.view_mode == VIEW_OVERDRAW) {
+        out_color = vec4(1.0, 0.0, 0.0, 1.0);
+        return;
+    }
+    if (camera.view_mode == VIEW_OCCLUSION) {
+        out_color = vec4(vec3(ao), 1.0);
+        return;
+    }
+    int cascade = find_cascade();
+    float sun = max(dot(normal, camera.sun), 0.0);
+    sun *= sunlit(cascade, sun / max(length(camera.sun), 0.0001));
+    float sky = brightness(frag_light.x * camera.daylight) * (camera.ambient + (1.0 - camera.ambient) * sun);
+    float block = brightness(frag_light.y) * BLOCK_LIGHT_SHADE;
+    float light = max(sky, block) * ao;
+    // Point lights don't check what's in the way, so they're scaled by the block light level,
+    // which only gets round walls the long way. That stops a lamp lighting the other side of
+    // one.
+    vec3 point = point_lights(normal) * frag_light.y * ao;
+    vec3 lit_color = color.rgb * (light + point);
+    if (camera.show_cascades != 0 && cascade < CASCADE_COUNT) {
+        lit_color *= CASCADE_COLORS[cascade];
+    }
+    // Opaque tiles are solid all over, and translucent ones blend by their alpha. Polished
+    // ones reflect what's around them on top, which covers up more of what's behind them, so
+    // like water's the colour is divided back out of the alpha they blend with.
+    float alpha = color.a;
+    if (is_polished(frag_tile)) {
+        vec3 view = normalize(camera.eye - frag_position);
+        float facing = max(dot(normal, view), 0.0);
+        float fresnel = POLISHED_REFLECTANCE + (1.0 - POLISHED_REFLECTANCE) * pow(1.0 - facing, 5.0);
+        vec3 reflection = reflect_surroundings(reflect(-view, normal));
+        alpha = color.a * (1.0 - fresnel) + fresnel;
+        lit_color = (lit_color * color.a * (1.0 - fresnel) + reflection * fresnel) / max(alpha, 0.001);
+    }
+    out_color = vec4(fog(lit_color, frag_position), alpha);
+}
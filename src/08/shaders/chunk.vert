@@ -0,0 +1,35 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in uint tile;
+layout(location = 4) in float ao;
+layout(location = 5) in vec2 light;
+// Where the chunk is, from its record, once per instance
+layout(location = 7) in vec3 chunk_origin;
+
+layout(location = 0) out vec3 frag_normal;
+layout(location = 1) out vec2 frag_uv;
+layout(location = 2) flat out uint frag_tile;
+layout(location = 3) out float frag_ao;
+layout(location = 4) out vec2 frag_light;
+layout(location = 5) out vec3 frag_position;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    vec4 world_position = vec4(chunk_origin + position, 1.0);
+    gl_Position = camera.view_projection * world_position;
+    frag_normal = normal;
+    frag_uv = uv;
+    frag_tile = tile;
+    frag_ao = ao;
+    frag_light = light;
+    frag_position = world_position.xyz;
+}
@@ -0,0 +1,213 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+// A layer per cascade
+layout(set = 0, binding = 3) uniform texture2DArray shadow_map;
+layout(set = 0, binding = 4) uniform samplerShadow shadow_sampler;
+
+const uint MAX_POINT_LIGHTS = 128;
+struct PointLight {
+    vec3 position;
+    // How far the light reaches, in blocks
+    float radius;
+    vec3 color;
+};
+// The point lights nearest the camera. Only the first `count` are used.
+layout(set = 1, binding = 0) uniform Lights {
+    uint count;
+    PointLight lights[MAX_POINT_LIGHTS];
+} lights;
+
+// What `gbuffer.frag` drew for this swapchain image
+layout(set = 2, binding = 0) uniform texture2D gbuffer_albedo;
+layout(set = 2, binding = 1) uniform texture2D gbuffer_normal;
+// Sky light, block light and ambient occlusion
+layout(set = 2, binding = 2) uniform texture2D gbuffer_material;
+layout(set = 2, binding = 3) uniform texture2D gbuffer_depth;
+layout(set = 2, binding = 4) uniform sampler gbuffer_sampler;
+// How much of the ambient light reaches each pixel, from `ssao_blur.comp`
+layout(set = 2, binding = 6) uniform texture2D ambient_occlusion;
+
+layout(location = 0) out vec4 out_color;
+
+// The lighting is the same as `chunk.frag`'s, with what it knew about each fragment read back
+// out of the G-buffer instead, and screen-space ambient occlusion on top. Any other change to
+// one has to be made to the other.
+
+// Has to match `ViewMode::id`
+const uint VIEW_NORMALS = 2;
+const uint VIEW_OCCLUSION = 4;
+
+// How dark a fully occluded corner gets
+const float AO_STRENGTH = 0.6;
+// How much dimmer each light level is than the one above it
+const float LIGHT_FALLOFF = 0.8;
+const float MAX_LIGHT = 15.0;
+// How much of a lamp's light reaches every face, whichever way it's turned
+const float BLOCK_LIGHT_SHADE = 0.8;
+
+// How bright point lights are, on top of the light levels they spread through the world
+const float POINT_LIGHT_STRENGTH = 0.8;
+
+const int CASCADE_COUNT = 4;
+// How far past the depth in the shadow map a fragment has to be before it's in shadow, in
+// texels, so faces don't shadow themselves where the map's texels cut through them at an
+// angle. The steeper the sun hits a face, the more of the face one texel covers, so the bias
+// grows with the slope. Counting in texels keeps it right for every cascade, however much of
+// the world their texels cover.
+const float SHADOW_SLOPE_BIAS = 1.5;
+const float MIN_SHADOW_BIAS = 1.0;
+const float MAX_SHADOW_BIAS = 6.0;
+// What each cascade is tinted with when they're being shown
+const vec3 CASCADE_COLORS[CASCADE_COUNT] = vec3[](
+    vec3(1.0, 0.4, 0.4),
+    vec3(0.4, 1.0, 0.4),
+    vec3(0.4, 0.4, 1.0),
+    vec3(1.0, 1.0, 0.4)
+);
+
+// How bright a block lit at `level`, from 0 to 1, looks. Each level is that much dimmer than
+// the one above, so a cave's entrance dims steadily to black rather than staying grey.
+float brightness(float level) {
+    return pow(LIGHT_FALLOFF, MAX_LIGHT * (1.0 - level));
+}
+
+// Which cascade `position` is in, going by how far along the view it is, or CASCADE_COUNT if
+// it's past the last one.
+int find_cascade(vec3 position) {
+    float depth = dot(camera.view_depth, vec4(position, 1.0));
+    int cascade = 0;
+    while (cascade < CASCADE_COUNT && depth > camera.cascade_splits[cascade]) {
+        cascade++;
+    }
+    return cascade;
+}
+
+// How much of the sun reaches `world_position` in `cascade`, from 0 in full shadow to 1, given
+// the cosine of the angle the sun hits it at. The comparison sampler does the depth test, and
+// averaging it over the 3x3 texels around the pixel softens the edges of shadows.
+float sunlit(vec3 world_position, int cascade, float n_dot_l) {
+    // Anything too far away for shadows is lit
+    if (cascade >= CASCADE_COUNT) {
+        return 1.0;
+    }
+    mat4 shadow_view_projection = camera.shadow_view_projections[cascade];
+    vec4 clip = shadow_view_projection * vec4(world_position, 1.0);
+    vec3 position = clip.xyz / clip.w;
+    vec2 uv = position.xy * 0.5 + 0.5;
+    if (any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0))) || position.z > 1.0) {
+        return 1.0;
+    }
+
+    // The projections are orthographic, so their x and z scales say how many blocks a texel
+    // covers and how much depth a block is
+    vec2 texel = 1.0 / vec2(textureSize(sampler2DArrayShadow(shadow_map, shadow_sampler), 0).xy);
+    float width_scale = length(vec3(shadow_view_projection[0][0], shadow_view_projection[1][0], shadow_view_projection[2][0]));
+    float depth_scale = length(vec3(shadow_view_projection[0][2], shadow_view_projection[1][2], shadow_view_projection[2][2]));
+    float texel_depth = 2.0 * texel.x / width_scale * depth_scale;
+    float slope = tan(acos(clamp(n_dot_l, 0.01, 1.0)));
+    float bias = clamp(SHADOW_SLOPE_BIAS * slope, MIN_SHADOW_BIAS, MAX_SHADOW_BIAS) * texel_depth;
+
+    float lit = 0.0;
+    for (int x = -1; x <= 1; x++) {
+        for (int y = -1; y <= 1; y++) {
+            vec4 coords = vec4(uv + vec2(x, y) * texel, cascade, position.z - bias);
+            lit += texture(sampler2DArrayShadow(shadow_map, shadow_sampler), coords);
+        }
+    }
+    return lit / 9.0;
+}
+
+// The light reaching `position` straight from the point lights, brightest on faces turned
+// towards them and fading out to nothing at their radius.
+vec3 point_lights(vec3 position, vec3 normal) {
+    vec3 total = vec3(0.0);
+    for (uint index = 0; index < lights.count; index++) {
+        PointLight light = lights.lights[index];
+        vec3 to_light = light.position - position;
+        float to_light_length = length(to_light);
+        if (to_light_length >= light.radius) {
+            continue;
+        }
+        float falloff = 1.0 - to_light_length / light.radius;
+        float facing = max(dot(normal, to_light / max(to_light_length, 0.0001)), 0.0);
+        total += light.color * falloff * falloff * facing;
+    }
+    return total * POINT_LIGHT_STRENGTH;
+}
+
+// How far towards the edge of the loaded world the fog starts thickening to hide it
+const float FOG_EDGE_START = 0.75;
+
+// `color` at `position` seen through the fog. The fog thins out exponentially going up, so how
+// much there is along the way is its density around the camera times the distance, scaled by
+// how much it thins out between the two. Its colour is the sky's at the horizon, warmer looking
+// towards the sun. Has to match `chunk.frag`.
+vec3 fog(vec3 color, vec3 position) {
+    vec3 to_position = position - camera.eye;
+    float distance = length(to_position);
+    float climb = camera.fog_height_falloff * to_position.y;
+    float thinning = abs(climb) > 0.0001 ? (1.0 - exp(-climb)) / climb : 1.0;
+    float amount = 1.0 - exp(-camera.fog_density * distance * thinning);
+    amount = max(amount, smoothstep(FOG_EDGE_START * camera.fog_end, camera.fog_end, distance));
+
+    vec2 across = to_position.xz / max(length(to_position.xz), 0.0001);
+    vec2 sun_across = camera.sun_direction.xz / max(length(camera.sun_direction.xz), 0.0001);
+    float towards_sun = pow(max(dot(across, sun_across), 0.0), 8.0);
+    vec3 fog_color = mix(camera.fog_color, camera.fog_sun_color, towards_sun);
+    return mix(color, fog_color, amount);
+}
+
+void main() {
+    // Nothing was drawn where the depth is still what it was cleared to, which is left for
+    // the sky
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    float depth = texelFetch(sampler2D(gbuffer_depth, gbuffer_sampler), pixel, 0).r;
+    if (depth >= 1.0) {
+        discard;
+    }
+
+    // Back from the pixel and its depth to where it is in the world
+    vec2 uv = gl_FragCoord.xy / vec2(textureSize(sampler2D(gbuffer_depth, gbuffer_sampler), 0));
+    vec4 world = camera.inverse_view_projection * vec4(uv * 2.0 - 1.0, depth, 1.0);
+    vec3 position = world.xyz / world.w;
+    vec3 albedo = texelFetch(sampler2D(gbuffer_albedo, gbuffer_sampler), pixel, 0).rgb;
+    vec3 material = texelFetch(sampler2D(gbuffer_material, gbuffer_sampler), pixel, 0).xyz;
+    float ssao = texelFetch(sampler2D(ambient_occlusion, gbuffer_sampler), pixel, 0).r;
+    vec3 normal = normalize(texelFetch(sampler2D(gbuffer_normal, gbuffer_sampler), pixel, 0).xyz * 2.0 - 1.0);
+    float ao = 1.0 - AO_STRENGTH * (1.0 - material.z);
+    // The debug views that show a value show it as it is. Overdraw is never drawn deferred.
+    if (camera.view_mode == VIEW_NORMALS) {
+        out_color = vec4(normal * 0.5 + 0.5, 1.0);
+        return;
+    }
+    if (camera.view_mode == VIEW_OCCLUSION) {
+        out_color = vec4(vec3(ao * ssao), 1.0);
+        return;
+    }
+
+    // Sky light dims as the sun goes down, and the sun shines on the faces turned towards it
+    // on top of the ambient light from the rest of the sky. Lamps light every face the same.
+    // Whichever is brighter wins, and the baked ambient occlusion shows where faces meet.
+    // Shadows only block the sun, not the light from the rest of the sky. Its strength is
+    // divided back out for the bias rather than normalizing camera.sun, which is all zeros at
+    // night.
+    int cascade = find_cascade(position);
+    float sun = max(dot(normal, camera.sun), 0.0);
+    sun *= sunlit(position, cascade, sun / max(length(camera.sun), 0.0001));
+    // SSAO only darkens the light that comes from all around, not the sun's or the point
+    // lights'
+    float sky = brightness(material.x * camera.daylight) * (camera.ambient * ssao + (1.0 - camera.ambient) * sun);
+    float block = brightness(material.y) * BLOCK_LIGHT_SHADE * ssao;
+    float light = max(sky, block) * ao;
+    // Point lights don't check what's in the way, so they're scaled by the block light level,
+    // which only gets round walls the long way. That stops a lamp lighting the other side of
+    // one.
+    vec3 point = point_lights(position, normal) * material.y * ao;
+    vec3 lit_color = albedo * (light + point);
+    if (camera.show_cascades != 0 && cascade < CASCADE_COUNT) {
+        lit_color *= CASCADE_COLORS[cascade];
+    }
+    out_color = vec4(fog(lit_color, position), 1.0);
+}
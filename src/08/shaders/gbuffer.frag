@@ -0,0 +1,41 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+layout(set = 0, binding = 1) uniform texture2D atlas_texture;
+layout(set = 0, binding = 2) uniform sampler atlas_sampler;
+
+layout(location = 0) in vec3 frag_normal;
+layout(location = 1) in vec2 frag_uv;
+layout(location = 2) flat in uint frag_tile;
+layout(location = 3) in float frag_ao;
+// Sky light, then block light, from 0 to 1
+layout(location = 4) in vec2 frag_light;
+layout(location = 5) in vec3 frag_position;
+
+// Everything the lighting pass needs to know about the surface, in the formats from `gbuffer.rs`
+layout(location = 0) out vec4 out_albedo;
+// The normal, mapped into 0 to 1
+layout(location = 1) out vec4 out_normal;
+// Sky light, block light and ambient occlusion
+layout(location = 2) out vec4 out_material;
+
+void main() {
+    // The uvs count blocks across the face, so a quad covering several blocks repeats its tile
+    // once per block. The derivatives are taken before wrapping, or the mip level would jump
+    // at every block edge where fract() does.
+    vec2 cell = vec2(frag_tile % camera.atlas_columns, frag_tile / camera.atlas_columns);
+    vec2 tile_origin = camera.atlas_origin + cell * camera.atlas_cell;
+    vec2 scaled_uv = frag_uv * camera.atlas_tile_size;
+    vec4 color = textureGrad(
+        sampler2D(atlas_texture, atlas_sampler),
+        tile_origin + fract(frag_uv) * camera.atlas_tile_size,
+        dFdx(scaled_uv),
+        dFdy(scaled_uv)
+    );
+
+    // None of it is lit yet. That's left to `deferred.frag`, once per pixel.
+    out_albedo = vec4(color.rgb, 1.0);
+    out_normal = vec4(normalize(frag_normal) * 0.5 + 0.5, 0.0);
+    out_material = vec4(frag_light, frag_ao, 0.0);
+}
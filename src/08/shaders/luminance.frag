@@ -0,0 +1,33 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// The HDR scene, as drawn by forward shading or the lighting pass
+layout(set = 0, binding = 0) uniform texture2D scene;
+layout(set = 0, binding = 1) uniform sampler scene_sampler;
+
+// How many tiles the scene is split into along each side. Has to match `hdr::LUMINANCE_SIZE`.
+const uint LUMINANCE_SIZE = 16;
+// How many pixels along each side of its tile each tile looks at. Exposure changes slowly, so
+// a few hundred pixels of each tile is as good as all of them.
+const int SAMPLES_PER_SIDE = 8;
+
+// The average of the logs of the luminances in this fragment's tile
+layout(location = 0) out float out_log_luminance;
+
+void main() {
+    vec2 tile_size = vec2(textureSize(sampler2D(scene, scene_sampler), 0)) / float(LUMINANCE_SIZE);
+    vec2 tile_origin = floor(gl_FragCoord.xy) * tile_size;
+
+    float total = 0.0;
+    for (int y = 0; y < SAMPLES_PER_SIDE; y++) {
+        for (int x = 0; x < SAMPLES_PER_SIDE; x++) {
+            vec2 offset = (vec2(x, y) + 0.5) / float(SAMPLES_PER_SIDE);
+            ivec2 pixel = ivec2(tile_origin + offset * tile_size);
+            vec3 color = texelFetch(sampler2D(scene, scene_sampler), pixel, 0).rgb;
+            float luminance = dot(color, vec3(0.2126, 0.7152, 0.0722));
+            // Pure black would be minus infinity
+            total += log(max(luminance, 0.0001));
+        }
+    }
+    out_log_luminance = total / float(SAMPLES_PER_SIDE * SAMPLES_PER_SIDE);
+}
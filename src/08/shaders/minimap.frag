@@ -0,0 +1,35 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+layout(set = 0, binding = 1) uniform texture2D atlas_texture;
+layout(set = 0, binding = 2) uniform sampler atlas_sampler;
+
+layout(location = 0) in vec2 frag_uv;
+layout(location = 1) flat in uint frag_tile;
+layout(location = 2) in float frag_height;
+
+layout(location = 0) out vec4 out_color;
+
+// How much brighter or darker the ground gets for each block above or below the camera, and
+// how far that goes either way
+const float HEIGHT_SHADE = 0.015;
+const float MAX_HEIGHT_SHADE = 0.35;
+
+// The map isn't lit by the sun or the lamps, so it can be read at any time of day. Its tiles
+// are shaded by how high they are instead, so hills and valleys show.
+void main() {
+    // Sampled like `chunk.frag` samples the atlas, so the tiles repeat across merged faces
+    vec2 cell = vec2(frag_tile % camera.atlas_columns, frag_tile / camera.atlas_columns);
+    vec2 tile_origin = camera.atlas_origin + cell * camera.atlas_cell;
+    vec2 scaled_uv = frag_uv * camera.atlas_tile_size;
+    vec4 color = textureGrad(
+        sampler2D(atlas_texture, atlas_sampler),
+        tile_origin + fract(frag_uv) * camera.atlas_tile_size,
+        dFdx(scaled_uv),
+        dFdy(scaled_uv)
+    );
+
+    float height = clamp((frag_height - camera.eye.y) * HEIGHT_SHADE, -MAX_HEIGHT_SHADE, MAX_HEIGHT_SHADE);
+    out_color = vec4(color.rgb * (1.0 + height), 1.0);
+}
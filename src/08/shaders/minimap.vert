@@ -0,0 +1,27 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+
+// The parts of the chunk vertices the map needs
+layout(location = 0) in vec3 position;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in uint tile;
+// Where the chunk is, from its record, once per instance
+layout(location = 7) in vec3 chunk_origin;
+
+layout(location = 0) out vec2 frag_uv;
+layout(location = 1) flat out uint frag_tile;
+layout(location = 2) out float frag_height;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    vec3 world_position = chunk_origin + position;
+    gl_Position = camera.minimap_view_projection * vec4(world_position, 1.0);
+    frag_uv = uv;
+    frag_tile = tile;
+    frag_height = world_position.y;
+}
@@ -0,0 +1,23 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+
+// Laid out like the chunk shader's, so both pipelines can share a layout
+layout(push_constant) uniform PushConstants {
+    vec3 block_origin;
+} push_constants;
+
+layout(location = 0) in vec3 position;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+// How far the outline sits outside the block, so the block's own faces don't hide it
+const float OUTSET = 0.005;
+
+void main() {
+    vec3 corner = position * (1.0 + 2.0 * OUTSET) - OUTSET;
+    gl_Position = camera.view_projection * vec4(push_constants.block_origin + corner, 1.0);
+}
@@ -0,0 +1,49 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+layout(set = 0, binding = 1) uniform texture2D atlas_texture;
+layout(set = 0, binding = 2) uniform sampler atlas_sampler;
+
+layout(location = 0) in vec3 frag_normal;
+layout(location = 1) in vec2 frag_uv;
+layout(location = 2) flat in uint frag_tile;
+// Sky light, then block light, from 0 to 1
+layout(location = 3) in vec2 frag_light;
+
+layout(location = 0) out vec4 out_color;
+
+// Have to match `chunk.frag`
+const float LIGHT_FALLOFF = 0.8;
+const float MAX_LIGHT = 15.0;
+const float BLOCK_LIGHT_SHADE = 0.8;
+
+// How bright a block lit at `level` looks. Has to match `chunk.frag`.
+float brightness(float level) {
+    return pow(LIGHT_FALLOFF, MAX_LIGHT * (1.0 - level));
+}
+
+// Lit the way `chunk.frag` lights chunks, without the shadows, point lights or fog, which
+// hardly show in a reflection. Alpha is 1 wherever there's terrain, so the shaders sampling
+// the probe can tell it from the sky.
+void main() {
+    vec2 cell = vec2(frag_tile % camera.atlas_columns, frag_tile / camera.atlas_columns);
+    vec2 tile_origin = camera.atlas_origin + cell * camera.atlas_cell;
+    vec2 scaled_uv = frag_uv * camera.atlas_tile_size;
+    vec4 color = textureGrad(
+        sampler2D(atlas_texture, atlas_sampler),
+        tile_origin + fract(frag_uv) * camera.atlas_tile_size,
+        dFdx(scaled_uv),
+        dFdy(scaled_uv)
+    );
+    // The gaps in leaves show whatever's behind them
+    if (color.a < 0.5) {
+        discard;
+    }
+
+    vec3 normal = normalize(frag_normal);
+    float sun = max(dot(normal, camera.sun), 0.0);
+    float sky = brightness(frag_light.x * camera.daylight) * (camera.ambient + (1.0 - camera.ambient) * sun);
+    float block = brightness(frag_light.y) * BLOCK_LIGHT_SHADE;
+    out_color = vec4(color.rgb * max(sky, block), 1.0);
+}
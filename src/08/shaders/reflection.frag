@@ -0,0 +1,72 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+layout(set = 0, binding = 1) uniform texture2D atlas_texture;
+layout(set = 0, binding = 2) uniform sampler atlas_sampler;
+
+layout(location = 0) in vec3 frag_normal;
+layout(location = 1) in vec2 frag_uv;
+layout(location = 2) flat in uint frag_tile;
+// Sky light, then block light, from 0 to 1
+layout(location = 3) in vec2 frag_light;
+layout(location = 4) in vec3 frag_position;
+
+layout(location = 0) out vec4 out_color;
+
+// Have to match `chunk.frag`
+const float LIGHT_FALLOFF = 0.8;
+const float MAX_LIGHT = 15.0;
+const float BLOCK_LIGHT_SHADE = 0.8;
+const float FOG_EDGE_START = 0.75;
+
+// How bright a block lit at `level` looks. Has to match `chunk.frag`.
+float brightness(float level) {
+    return pow(LIGHT_FALLOFF, MAX_LIGHT * (1.0 - level));
+}
+
+// `color` at `position` seen through the fog from `eye`. Has to match `chunk.frag`'s `fog`,
+// which always looks from the camera.
+vec3 fog(vec3 color, vec3 position, vec3 eye) {
+    vec3 to_position = position - eye;
+    float distance = length(to_position);
+    float climb = camera.fog_height_falloff * to_position.y;
+    float thinning = abs(climb) > 0.0001 ? (1.0 - exp(-climb)) / climb : 1.0;
+    float amount = 1.0 - exp(-camera.fog_density * distance * thinning);
+    amount = max(amount, smoothstep(FOG_EDGE_START * camera.fog_end, camera.fog_end, distance));
+
+    vec2 across = to_position.xz / max(length(to_position.xz), 0.0001);
+    vec2 sun_across = camera.sun_direction.xz / max(length(camera.sun_direction.xz), 0.0001);
+    float towards_sun = pow(max(dot(across, sun_across), 0.0), 8.0);
+    vec3 fog_color = mix(camera.fog_color, camera.fog_sun_color, towards_sun);
+    return mix(color, fog_color, amount);
+}
+
+// Lit like `probe.frag` lights the probe's faces, and fogged as seen from the camera's mirror
+// image under the water, which is how far the light comes to reach the camera by way of the
+// water. Everything under the water is clipped away, since it would be in front of the mirror.
+void main() {
+    if (frag_position.y < camera.water_height) {
+        discard;
+    }
+
+    vec2 cell = vec2(frag_tile % camera.atlas_columns, frag_tile / camera.atlas_columns);
+    vec2 tile_origin = camera.atlas_origin + cell * camera.atlas_cell;
+    vec2 scaled_uv = frag_uv * camera.atlas_tile_size;
+    vec4 color = textureGrad(
+        sampler2D(atlas_texture, atlas_sampler),
+        tile_origin + fract(frag_uv) * camera.atlas_tile_size,
+        dFdx(scaled_uv),
+        dFdy(scaled_uv)
+    );
+    if (color.a < 0.5) {
+        discard;
+    }
+
+    vec3 normal = normalize(frag_normal);
+    float sun = max(dot(normal, camera.sun), 0.0);
+    float sky = brightness(frag_light.x * camera.daylight) * (camera.ambient + (1.0 - camera.ambient) * sun);
+    float block = brightness(frag_light.y) * BLOCK_LIGHT_SHADE;
+    vec3 mirrored_eye = vec3(camera.eye.x, 2.0 * camera.water_height - camera.eye.y, camera.eye.z);
+    out_color = vec4(fog(color.rgb * max(sky, block), frag_position, mirrored_eye), 1.0);
+}
@@ -0,0 +1,21 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+
+layout(push_constant) uniform PushConstants {
+    // Which cascade's map is being drawn. The outline's block origin comes before it.
+    layout(offset = 12) uint cascade;
+} push_constants;
+
+// Only the positions of the chunk vertices are needed for depth, and where the chunk is
+layout(location = 0) in vec3 position;
+layout(location = 7) in vec3 chunk_origin;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = camera.shadow_view_projections[push_constants.cascade] * vec4(chunk_origin + position, 1.0);
+}
@@ -0,0 +1,91 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+// The clouds, stars and glow for `Sky::Cubemap`. See `sky::skybox_pixels`.
+layout(set = 0, binding = 5) uniform textureCube skybox;
+layout(set = 0, binding = 6) uniform sampler skybox_sampler;
+
+layout(location = 0) in vec2 clip;
+
+layout(location = 0) out vec4 color;
+
+// Has to match `Sky::id`
+const uint SKY_ATMOSPHERE = 0;
+const uint SKY_CUBEMAP = 1;
+
+// How much of each colour the air and the haze scatter looking straight up. Air scatters blue
+// most, and haze every colour the same. These have to match `sky::atmosphere`, which works out
+// the horizon's colour for the fog.
+const vec3 AIR_SCATTERING = vec3(0.05, 0.11, 0.26);
+const float HAZE_SCATTERING = 0.01;
+// Haze scatters mostly forwards, which is what makes the glow around the sun
+const float HAZE_FORWARD = 0.76;
+// How bright sunlight is before any of it has been scattered out
+const float SUN_INTENSITY = 4.0;
+// The most air the view is taken to go through, standing in for the light that's scattered
+// more than once
+const float MAX_VIEW_AIR_MASS = 4.0;
+// The cosine of how far from its centre the edge of the sun's disk is, and how bright it is
+const float SUN_DISK = 0.9995;
+const float SUN_DISK_BRIGHTNESS = 20.0;
+// What's left of the sky with the sun down
+const vec3 NIGHT_SKY = vec3(0.002, 0.004, 0.01);
+const float STAR_BRIGHTNESS = 2.0;
+
+// How much air there is along a ray going out at `height` (the sine of its angle above the
+// horizon), compared to straight up. Kasten and Young's fit, which stays finite at the horizon.
+float air_mass(float height) {
+    float elevation = degrees(asin(clamp(height, 0.0, 1.0)));
+    return 1.0 / (max(height, 0.0) + 0.50572 * pow(elevation + 6.07995, -1.6364));
+}
+
+// Sunlight scattered towards the camera from along `view`, a single time, by the air and the
+// haze, and what's left of the sun itself.
+vec3 atmosphere(vec3 view, vec3 sun) {
+    float cosine = dot(view, sun);
+    // Both scale to an average of 1 over every direction
+    float air_phase = 0.75 * (1.0 + cosine * cosine);
+    float g = HAZE_FORWARD;
+    float haze_phase = (1.0 - g * g) / pow(1.0 + g * g - 2.0 * g * cosine, 1.5);
+
+    vec3 extinction = AIR_SCATTERING + HAZE_SCATTERING;
+    // What reaches the sky from the sun, and how much of the light scattered along the view is
+    // scattered out again before it gets to the camera
+    vec3 sunlight = SUN_INTENSITY * exp(-extinction * air_mass(sun.y));
+    vec3 view_extinction = exp(-extinction * min(air_mass(view.y), MAX_VIEW_AIR_MASS));
+    vec3 scattered = (AIR_SCATTERING * air_phase + HAZE_SCATTERING * haze_phase) / extinction;
+    vec3 sky = sunlight * scattered * (1.0 - view_extinction);
+
+    float disk = smoothstep(SUN_DISK, mix(SUN_DISK, 1.0, 0.5), cosine);
+    sky += sunlight * view_extinction * disk * SUN_DISK_BRIGHTNESS;
+
+    // The sky fades out as the sun goes down rather than all at once as it crosses the horizon
+    return sky * smoothstep(-0.1, 0.05, sun.y);
+}
+
+// The cube map's clouds and glow lit by the sky colour of the time of day, and its stars
+// wherever the sky's dark enough to see them.
+vec3 cubemap(vec3 view, vec3 sun) {
+    vec4 texel = texture(samplerCube(skybox, skybox_sampler), view);
+    vec3 sky = camera.sky_color * texel.r;
+    // Clouds are lit by the sun and the sky both, and go dark at night along with everything
+    vec3 clouds = mix(camera.sky_color, vec3(camera.daylight), 0.7);
+    sky = mix(sky, clouds, texel.g);
+    float night = 1.0 - camera.daylight;
+    sky += texel.b * (1.0 - texel.g) * night * night * STAR_BRIGHTNESS;
+
+    float disk = smoothstep(SUN_DISK, mix(SUN_DISK, 1.0, 0.5), dot(view, sun));
+    return sky + disk * SUN_DISK_BRIGHTNESS * camera.daylight * (1.0 - texel.g);
+}
+
+void main() {
+    // Which way this pixel looks, from the points on the near and far planes behind it
+    vec4 near = camera.inverse_view_projection * vec4(clip, 0.0, 1.0);
+    vec4 far = camera.inverse_view_projection * vec4(clip, 1.0, 1.0);
+    vec3 view = normalize(far.xyz / far.w - near.xyz / near.w);
+    vec3 sun = normalize(camera.sun_direction);
+
+    vec3 sky = camera.sky == SKY_ATMOSPHERE ? atmosphere(view, sun) : cubemap(view, sun);
+    color = vec4(max(sky, NIGHT_SKY), 1.0);
+}
@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) out vec2 clip;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+// The same triangle as `fullscreen.vert`, but at the far plane, so the depth test only lets it
+// through where nothing else was drawn
+void main() {
+    vec2 corner = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    clip = corner * 2.0 - 1.0;
+    gl_Position = vec4(clip, 1.0, 1.0);
+}
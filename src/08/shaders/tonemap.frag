@@ -0,0 +1,107 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// The HDR scene, as drawn by forward shading or the lighting pass
+layout(set = 0, binding = 0) uniform texture2D scene;
+layout(set = 0, binding = 1) uniform sampler scene_sampler;
+
+// All of the bloom, added up into the first of its mips
+layout(set = 0, binding = 2) uniform texture2D bloom;
+layout(set = 0, binding = 3) uniform sampler bloom_sampler;
+
+// Has to match `PostConstants` in main.rs
+layout(push_constant) uniform PushConstants {
+    // The size of what's being drawn into, in pixels
+    vec2 target_size;
+    // What the scene is scaled by before it's tonemapped
+    float exposure;
+    // Which curve to use, from `Tonemap::id`
+    uint tonemap;
+    // How much of the bloom is added onto the scene
+    float bloom_strength;
+    // How bright something has to be, once it's been exposed, before it glows
+    float bloom_threshold;
+    // Non-zero when the camera is under water
+    uint underwater;
+    // Which debug view the scene was drawn with, from `ViewMode::id`
+    uint view_mode;
+    // Non-zero when the swapchain is sRGB, and encodes what's written to it
+    uint srgb_target;
+} constants;
+
+const uint TONEMAP_REINHARD = 0;
+const uint TONEMAP_ACES = 1;
+
+// Has to match `ViewMode::id`
+const uint VIEW_NORMALS = 2;
+const uint VIEW_OVERDRAW = 3;
+const uint VIEW_OCCLUSION = 4;
+
+// How many times a pixel has to be drawn over to go all the way up the heatmap, and the
+// colours along it, from nothing drawn at all
+const float MAX_OVERDRAW = 12.0;
+const vec3 HEAT[5] = vec3[](
+    vec3(0.0),
+    vec3(0.0, 0.15, 1.0),
+    vec3(0.0, 0.9, 0.3),
+    vec3(1.0, 0.85, 0.0),
+    vec3(1.0, 0.0, 0.0)
+);
+
+// What the scene is multiplied by under water, which soaks up red first, and how much darker it
+// gets towards the corners of the screen
+const vec3 UNDERWATER_TINT = vec3(0.3, 0.65, 0.75);
+const float UNDERWATER_VIGNETTE = 0.6;
+
+// The swapchain is sRGB, so this is still linear
+layout(location = 0) out vec4 out_color;
+
+vec3 reinhard(vec3 color) {
+    return color / (1.0 + color);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+vec3 aces(vec3 color) {
+    vec3 curved = (color * (2.51 * color + 0.03)) / (color * (2.43 * color + 0.59) + 0.14);
+    return clamp(curved, 0.0, 1.0);
+}
+
+// The heatmap colour for a pixel drawn `count` times.
+vec3 heat(float count) {
+    float along = clamp(count / MAX_OVERDRAW, 0.0, 1.0) * 4.0;
+    int stop = min(int(along), 3);
+    return mix(HEAT[stop], HEAT[stop + 1], along - float(stop));
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+// Everything up to here is linear light, which only an sRGB swapchain encodes for the screen
+vec4 to_screen(vec3 color) {
+    return vec4(constants.srgb_target != 0 ? color : linear_to_srgb(color), 1.0);
+}
+
+void main() {
+    vec3 scene_color = texelFetch(sampler2D(scene, scene_sampler), ivec2(gl_FragCoord.xy), 0).rgb;
+    // The debug views that show a value go to the screen as they are
+    if (constants.view_mode == VIEW_NORMALS || constants.view_mode == VIEW_OCCLUSION) {
+        out_color = to_screen(scene_color);
+        return;
+    }
+    if (constants.view_mode == VIEW_OVERDRAW) {
+        out_color = to_screen(heat(scene_color.r));
+        return;
+    }
+
+    vec3 color = scene_color * constants.exposure;
+    // The bloom was exposed as it was drawn
+    vec2 uv = gl_FragCoord.xy / constants.target_size;
+    color += texture(sampler2D(bloom, bloom_sampler), uv).rgb * constants.bloom_strength;
+    if (constants.underwater != 0) {
+        vec2 offset = uv * 2.0 - 1.0;
+        color *= UNDERWATER_TINT * (1.0 - UNDERWATER_VIGNETTE * 0.5 * dot(offset, offset));
+    }
+    vec3 mapped = constants.tonemap == TONEMAP_ACES ? aces(color) : reinhard(color);
+    out_color = to_screen(mapped);
+}
@@ -0,0 +1,162 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+#include "camera.glsl"
+// A tile of ripple normals from `water::ripple_normals`, with x and y across it and z up
+layout(set = 0, binding = 7) uniform texture2D ripple_texture;
+layout(set = 0, binding = 8) uniform sampler ripple_sampler;
+// What's around the environment probe, with alpha 0 wherever it saw only sky
+layout(set = 0, binding = 9) uniform textureCube environment;
+layout(set = 0, binding = 10) uniform sampler environment_sampler;
+// The planar reflection, with alpha 0 wherever it's only sky
+layout(set = 0, binding = 11) uniform texture2D reflection_texture;
+layout(set = 0, binding = 12) uniform sampler reflection_sampler;
+
+layout(location = 0) in vec3 frag_normal;
+// Sky light, then block light, from 0 to 1
+layout(location = 1) in vec2 frag_light;
+layout(location = 2) in vec3 frag_position;
+// How many blocks deep the water is under the surface
+layout(location = 3) in float frag_water_depth;
+
+layout(location = 0) out vec4 out_color;
+
+// Have to match `chunk.frag`
+const float LIGHT_FALLOFF = 0.8;
+const float MAX_LIGHT = 15.0;
+const float BLOCK_LIGHT_SHADE = 0.8;
+
+// How much of red, green and blue each block of water soaks up, exponentially. Red goes first,
+// so deeper water looks bluer.
+const vec3 ABSORPTION = vec3(0.45, 0.12, 0.08);
+// The colour water scatters back out of itself, in shallow water and in deep water, in full
+// light
+const vec3 SHALLOW_COLOR = vec3(0.06, 0.22, 0.2);
+const vec3 DEEP_COLOR = vec3(0.01, 0.06, 0.12);
+// How much of the view goes through water seen from underneath, which lets most of the sky
+// through
+const float UNDERSIDE_OPACITY = 0.4;
+// The view through the surface is never counted as flatter than this, or the path through the
+// water under it would be endless
+const float MIN_VIEW_COSINE = 0.15;
+
+// How many blocks the ripple tile covers, how fast its two layers drift across the water, in
+// blocks a second, and how much smaller the second layer is
+const float RIPPLE_TILE = 8.0;
+const vec2 RIPPLE_DRIFT[2] = vec2[](vec2(0.35, 0.12), vec2(-0.2, 0.28));
+const float RIPPLE_DETAIL = 2.3;
+
+// How much light the surface reflects looking straight down at it. It reflects more and more
+// of it going towards the horizon.
+const float BASE_REFLECTANCE = 0.02;
+// Has to match `Reflections::id`
+const uint REFLECTIONS_PLANAR = 1;
+// How far the ripples push the planar reflection about, as a fraction of the screen
+const float REFLECTION_DISTORTION = 0.03;
+// How close to `camera.water_height` the top of the water has to be to use the planar
+// reflection
+const float WATER_HEIGHT_TOLERANCE = 0.01;
+
+// How tight and how bright the sun's reflection is
+const float GLINT_SHININESS = 400.0;
+const float GLINT_STRENGTH = 6.0;
+
+// How bright a block lit at `level` looks. Has to match `chunk.frag`.
+float brightness(float level) {
+    return pow(LIGHT_FALLOFF, MAX_LIGHT * (1.0 - level));
+}
+
+// How far towards the edge of the loaded world the fog starts thickening to hide it
+const float FOG_EDGE_START = 0.75;
+
+// `color` at `position` seen through the fog. The fog thins out exponentially going up, so how
+// much there is along the way is its density around the camera times the distance, scaled by
+// how much it thins out between the two. Its colour is the sky's at the horizon, warmer looking
+// towards the sun. Has to match `chunk.frag`.
+vec3 fog(vec3 color, vec3 position) {
+    vec3 to_position = position - camera.eye;
+    float distance = length(to_position);
+    float climb = camera.fog_height_falloff * to_position.y;
+    float thinning = abs(climb) > 0.0001 ? (1.0 - exp(-climb)) / climb : 1.0;
+    float amount = 1.0 - exp(-camera.fog_density * distance * thinning);
+    amount = max(amount, smoothstep(FOG_EDGE_START * camera.fog_end, camera.fog_end, distance));
+
+    vec2 across = to_position.xz / max(length(to_position.xz), 0.0001);
+    vec2 sun_across = camera.sun_direction.xz / max(length(camera.sun_direction.xz), 0.0001);
+    float towards_sun = pow(max(dot(across, sun_across), 0.0), 8.0);
+    vec3 fog_color = mix(camera.fog_color, camera.fog_sun_color, towards_sun);
+    return mix(color, fog_color, amount);
+}
+
+// The normal of the top of the water, with both layers of ripples added on.
+vec3 ripples() {
+    vec2 uv = frag_position.xz / RIPPLE_TILE;
+    vec2 drift = camera.time / RIPPLE_TILE * RIPPLE_DRIFT[0];
+    vec2 detail_drift = camera.time / RIPPLE_TILE * RIPPLE_DRIFT[1];
+    vec2 slope = texture(sampler2D(ripple_texture, ripple_sampler), uv + drift).xy * 2.0 - 1.0;
+    slope += texture(sampler2D(ripple_texture, ripple_sampler), uv * RIPPLE_DETAIL + detail_drift).xy * 2.0 - 1.0;
+    return normalize(vec3(slope.x, 1.0, slope.y));
+}
+
+void main() {
+    vec3 view = normalize(camera.eye - frag_position);
+    vec3 normal = normalize(frag_normal);
+    if (normal.y > 0.5) {
+        normal = ripples();
+    }
+    // A face seen from behind is seen from under the water
+    bool from_below = dot(view, frag_normal) < 0.0;
+    if (from_below) {
+        normal = -normal;
+    }
+
+    // Lit like the tops of blocks are, by the sky and by lamps
+    float sky_light = brightness(frag_light.x * camera.daylight);
+    float sun = max(dot(normal, camera.sun), 0.0);
+    float light = max(sky_light * (camera.ambient + (1.0 - camera.ambient) * sun), brightness(frag_light.y) * BLOCK_LIGHT_SHADE);
+
+    // How much of what's behind the water comes through it goes by how far the view goes
+    // through the water to get there, which is further the flatter it's looked at
+    float path = frag_water_depth / max(abs(view.y), MIN_VIEW_COSINE);
+    vec3 transmitted = exp(-ABSORPTION * path);
+    float opacity = from_below ? UNDERSIDE_OPACITY : 1.0 - dot(transmitted, vec3(1.0 / 3.0));
+    vec3 body = mix(SHALLOW_COLOR, DEEP_COLOR, opacity) * light;
+
+    // From above, the surface reflects the terrain around it that the probe saw and the sky
+    // everywhere else, more of it the flatter it's looked at, and the sun glints off it. The
+    // sky is only reflected as much as it can be seen from the water.
+    float fresnel = 0.0;
+    vec3 reflection = vec3(0.0);
+    if (!from_below) {
+        float facing = max(dot(normal, view), 0.0);
+        fresnel = BASE_REFLECTANCE + (1.0 - BASE_REFLECTANCE) * pow(1.0 - facing, 5.0);
+        vec3 reflected = reflect(-view, normal);
+        vec2 across = reflected.xz / max(length(reflected.xz), 0.0001);
+        vec2 sun_across = camera.sun_direction.xz / max(length(camera.sun_direction.xz), 0.0001);
+        vec3 horizon = mix(camera.fog_color, camera.fog_sun_color, pow(max(dot(across, sun_across), 0.0), 8.0));
+        vec3 sky = mix(horizon, camera.sky_color, sqrt(max(reflected.y, 0.0)));
+        float open_sky = brightness(frag_light.x);
+        float glint = pow(max(dot(reflected, camera.sun_direction), 0.0), GLINT_SHININESS) * GLINT_STRENGTH;
+        // The planar reflection is only right for the top of the sea, looked down on
+        vec4 surroundings;
+        bool planar = camera.reflections == REFLECTIONS_PLANAR
+            && frag_normal.y > 0.5
+            && abs(frag_position.y - camera.water_height) < WATER_HEIGHT_TOLERANCE;
+        if (planar) {
+            // The surface is on the mirror, so it's where it is on the screen in the reflection,
+            // and the ripples push that about
+            vec4 clip = camera.reflection_view_projection * vec4(frag_position, 1.0);
+            vec2 uv = clip.xy / clip.w * 0.5 + 0.5 + normal.xz * REFLECTION_DISTORTION;
+            surroundings = texture(sampler2D(reflection_texture, reflection_sampler), uv);
+        } else {
+            surroundings = texture(samplerCube(environment, environment_sampler), reflected);
+        }
+        vec3 sky_reflection = (sky * fresnel + glint * length(camera.sun)) * open_sky;
+        reflection = mix(sky_reflection, surroundings.rgb * fresnel, surroundings.a);
+    }
+
+    // Blended over what's behind by alpha, so the colour is divided back out of it
+    float alpha = opacity * (1.0 - fresnel) + fresnel;
+    vec3 color = (body * opacity * (1.0 - fresnel) + reflection) / max(alpha, 0.001);
+    out_color = vec4(fog(color, frag_position), alpha);
+}
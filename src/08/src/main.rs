@@ -0,0 +1,4722 @@
+extern crate gfx_hal as hal;
+#[macro_use]
+extern crate log;
+extern crate renderer_common;
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::iter;
+use std::mem;
+use std::rc::Rc;
+use std::slice;
+use std::sync::Arc;
+use std::time::{ Duration, Instant };
+
+use hal::{
+    buffer, command, format as f, image as i, pass,
+    pso::{ self, PipelineStage },
+    Backend, Device,
+    Primitive, Submission,
+};
+
+use renderer_common::atlas::{ AtlasBuilder, UvRect };
+use renderer_common::billboard::add_sprite_attributes;
+use renderer_common::color::is_srgb;
+use renderer_common::debug_lines::{ chunk_aabb, BORDER_COLOR, FRUSTUM_COLOR };
+use renderer_common::depth::choose_depth_format;
+use renderer_common::descriptors::{ DescriptorAllocator, DescriptorSetLayout, UniformRing };
+use renderer_common::events::WindowAction;
+use renderer_common::exposure::{ average_luminance, compensation };
+use renderer_common::frame_sync;
+use renderer_common::hdr::HDR_FORMAT;
+use renderer_common::hud::{ crosshair, hotbar, hotbar_top, minimap_panel };
+use renderer_common::indirect::ChunkRecord;
+use renderer_common::math::{ frustum_corners, Matrix, ShaderMatrix, SquareMatrix, HAL_CLIP_SPACE };
+use renderer_common::msaa::choose_sample_count;
+use renderer_common::present;
+use renderer_common::probe::tile_mask;
+use renderer_common::reflection::mirror;
+use renderer_common::screenshot;
+use renderer_common::shader::{ create_shader_module, ShaderSet };
+use renderer_common::shadow::{ fit_cascades, CASCADE_COUNT };
+use renderer_common::sky::{ horizon_colors, CUBE_FACES };
+use renderer_common::ssao::{ hemisphere_kernel, MAX_SSAO_SAMPLES, OCCLUSION_FORMAT, SSAO_GROUP_SIZE };
+use renderer_common::timestep::TICK_SECONDS;
+use renderer_common::water::{ ripple_normals, RIPPLE_SIZE };
+use renderer_common::world::{ Direction, CHUNK_SIZE, MAX_LIGHT };
+use renderer_common::worldgen::{ HEIGHT_IN_CHUNKS, SEA_LEVEL };
+use renderer_common::{
+    raycast, upload_buffer, Aabb, Action, AttachmentImages, AutoExposure, Billboards, BlockId,
+    BlockLights, BlockTextures, Bloom, Camera, CameraSwitch, ChunkAllocation, ChunkCoord,
+    ChunkDraws, ChunkLoader, ChunkMesh, ChunkState, ChunkVertex, Clock, CpuProfiler,
+    DebugLineSettings, DebugLines, DebugOverlay, DrawList, EnvironmentProbe, Events, FixedTimestep,
+    FpsCamera, FrameSync, Framebuffers, Frustum, GBuffer, Gamepads, GfxContext, GpuParticles,
+    GpuProfiler, GraphBuilder, Hdr, Hud, ImageDesc, Input, Lighting, LineVertex, LodTracker,
+    MemoryCategory, MeshWorkers, MeshedChunk, Mesher, Minimap, OrbitCamera, OverlaySettings,
+    OverlayStats, Particles, PassId, PendingEdits, PlanarReflection, PointLight, PointLightBlocks,
+    PointLights, Queue, Reflections, RegionStore, RenderGraph, ResourceId, Result, RetiredResources,
+    Runner, ShadowMap, Shading, Skybox, Sprite, StagingRing, Surface, TerrainBlocks, TextRenderer,
+    Texture, TimeOfDay, ViewMode, World, WorldGenerator,
+};
+
+/// The SPIR-V for the shaders in `shaders/`, compiled by `build.rs`
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+const STONE: BlockId = BlockId(1);
+const DIRT: BlockId = BlockId(2);
+const GRASS: BlockId = BlockId(3);
+const SAND: BlockId = BlockId(4);
+const SNOW: BlockId = BlockId(5);
+const LOG: BlockId = BlockId(6);
+const LEAVES: BlockId = BlockId(7);
+const LAMP: BlockId = BlockId(8);
+const TORCH: BlockId = BlockId(9);
+const WATER: BlockId = BlockId(10);
+const GLASS: BlockId = BlockId(11);
+
+/// The blocks that can be placed, in the order `NextBlock` steps through them and the hotbar
+/// shows them, with their names for the overlay.
+const PLACEABLE: &[(BlockId, &str)] = &[
+    (STONE, "stone"),
+    (DIRT, "dirt"),
+    (GRASS, "grass"),
+    (SAND, "sand"),
+    (SNOW, "snow"),
+    (LOG, "log"),
+    (LEAVES, "leaves"),
+    (LAMP, "lamp"),
+    (TORCH, "torch"),
+    (WATER, "water"),
+    (GLASS, "glass"),
+];
+
+/// The name of `block`, as the HUD shows it.
+fn block_name(block: BlockId) -> &'static str {
+    PLACEABLE
+        .iter()
+        .find(|&&(placeable, _)| placeable == block)
+        .map_or("something", |&(_, name)| name)
+}
+
+/// Which way along the ground `look_direction` is closest to, like "+x".
+fn facing(look_direction: [f32; 3]) -> &'static str {
+    let (x, z) = (look_direction[0], look_direction[2]);
+    if x.abs() > z.abs() {
+        if x > 0.0 { "+x" } else { "-x" }
+    } else if z > 0.0 {
+        "+z"
+    } else {
+        "-z"
+    }
+}
+
+/// How the text with the camera's position in the bottom left corner looks: how tall its lines
+/// are and how far it is from the corner, in logical pixels, and its colour.
+const READOUT_SIZE: f32 = 16.0;
+const READOUT_MARGIN: f32 = 8.0;
+const READOUT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 0.9];
+
+/// How tall the name of the block being looked at is, and how far above the hotbar, in logical
+/// pixels. It's the readout's colour.
+const TARGET_NAME_SIZE: f32 = 20.0;
+const TARGET_NAME_MARGIN: f32 = 6.0;
+
+/// The width and height of each block texture, in pixels.
+const TILE_SIZE: u32 = 16;
+
+/// How many milliseconds each frame can spend generating chunks that have come into range.
+/// Headless runs load everything in range before their first frame instead.
+const LOAD_BUDGET_MILLISECONDS: u64 = 4;
+
+/// How far through the day it is when the example starts: 9 in the morning.
+const START_TIME: f32 = 0.375;
+
+/// How many point lights things are lit with. Has to match `chunk.frag` and `deferred.frag`.
+const MAX_POINT_LIGHTS: usize = 128;
+
+/// How far from the camera point lights are shaded with, in blocks. Past this, only the light
+/// levels spread through the world light things.
+const POINT_LIGHT_DISTANCE: f32 = 48.0;
+
+/// How far away from the camera blocks can be broken and placed, in blocks.
+const REACH: f32 = 8.0;
+
+/// How many particles can be spawned in one frame. They're handed to the gpu once the frame's
+/// ticks and edits are done, and past this, new ones replace the first spawned.
+const MAX_PARTICLES: usize = 2048;
+
+/// How many particles the gpu simulates at once. Past this, new ones replace the oldest.
+const MAX_GPU_PARTICLES: u32 = 1 << 18;
+
+/// How far down from where a particle spawns the ground under it is looked for, in blocks.
+/// Particles with nothing under them for that far fall until they die.
+const PARTICLE_GROUND_SEARCH: i32 = 64;
+
+/// How far around the camera snow falls, in blocks, and how far above it the flakes start.
+const SNOW_RADIUS: f32 = 48.0;
+const SNOW_HEIGHT: f32 = 24.0;
+
+/// How far around the camera dust and falling leaves are spawned, in blocks, and how many
+/// places each tick tries for them.
+const AMBIENT_PARTICLE_RADIUS: f32 = 16.0;
+const AMBIENT_PARTICLE_TRIES: usize = 8;
+
+/// How likely each place tried that's open air is to get a mote of dust.
+const DUST_CHANCE: f32 = 0.05;
+
+/// How big the icon of the block about to be placed is, in blocks, and how opaque.
+const PLACEMENT_ICON_SIZE: f32 = 0.4;
+const PLACEMENT_ICON_ALPHA: f32 = 0.7;
+
+/// The height `fog_density` in the settings is the density of the fog at, which is around where
+/// the ground is. The fog is thicker below it and thinner above.
+const FOG_BASE_HEIGHT: f32 = 52.0;
+
+/// How high the top of the sea is, which the planar reflection mirrors the world through.
+const WATER_HEIGHT: f32 = (SEA_LEVEL + 1) as f32;
+
+/// How big the ring debug lines are written into starts out, in bytes, which is a few frames of
+/// a couple of thousand lines. It grows if they don't fit.
+const DEBUG_LINE_RING_SIZE: u64 = 512 * 1024;
+
+/// The edges of a block, as pairs of corners for a line list, for outlining the block the
+/// camera is pointing at.
+const OUTLINE_EDGES: [[f32; 3]; 24] = [
+    // Around the bottom
+    [0.0, 0.0, 0.0], [1.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0], [1.0, 0.0, 1.0],
+    [1.0, 0.0, 1.0], [0.0, 0.0, 1.0],
+    [0.0, 0.0, 1.0], [0.0, 0.0, 0.0],
+    // Around the top
+    [0.0, 1.0, 0.0], [1.0, 1.0, 0.0],
+    [1.0, 1.0, 0.0], [1.0, 1.0, 1.0],
+    [1.0, 1.0, 1.0], [0.0, 1.0, 1.0],
+    [0.0, 1.0, 1.0], [0.0, 1.0, 0.0],
+    // Up the sides
+    [0.0, 0.0, 0.0], [0.0, 1.0, 0.0],
+    [1.0, 0.0, 0.0], [1.0, 1.0, 0.0],
+    [1.0, 0.0, 1.0], [1.0, 1.0, 1.0],
+    [0.0, 0.0, 1.0], [0.0, 1.0, 1.0],
+];
+
+/// A tile of `color` with some noise in it, so the faces of neighbouring blocks of the same
+/// kind can be told apart. `seed` gives each tile a different pattern.
+fn noisy_tile(color: [u8; 3], seed: u32) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE * 4) as usize);
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            // A cheap integer hash is plenty for this
+            let mut hash = x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663) ^ seed.wrapping_mul(83_492_791);
+            hash = (hash ^ (hash >> 13)).wrapping_mul(1_274_126_177);
+            let brightness = 0.8 + 0.2 * ((hash >> 24) as f32 / 255.0);
+            for &channel in &color {
+                pixels.push((channel as f32 * brightness) as u8);
+            }
+            pixels.push(255);
+        }
+    }
+    pixels
+}
+
+/// Leaves: a tile of `color` like `noisy_tile`'s, with gaps between them that can be seen
+/// through and the rest only mostly solid.
+fn leaf_tile(color: [u8; 3], seed: u32) -> Vec<u8> {
+    let mut pixels = noisy_tile(color, seed);
+    for (index, pixel) in pixels.chunks_mut(4).enumerate() {
+        let index = index as u32;
+        let mut hash = index.wrapping_mul(2_654_435_761) ^ seed.wrapping_mul(40_503);
+        hash = (hash ^ (hash >> 15)).wrapping_mul(2_246_822_519);
+        pixel[3] = if (hash >> 28) < 5 { 0 } else { 230 };
+    }
+    pixels
+}
+
+/// Glass: faintly tinted and nearly clear, inside a frame that's mostly solid.
+fn glass_tile() -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE * 4) as usize);
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            let frame = x == 0 || y == 0 || x == TILE_SIZE - 1 || y == TILE_SIZE - 1;
+            // A streak across the middle catches the light
+            let streak = x + y == TILE_SIZE / 2 || x + y == TILE_SIZE / 2 + 2;
+            let pixel: [u8; 4] = if frame {
+                [200, 220, 225, 220]
+            } else if streak {
+                [235, 245, 250, 110]
+            } else {
+                [190, 220, 230, 40]
+            };
+            pixels.extend_from_slice(&pixel);
+        }
+    }
+    pixels
+}
+
+/// The side of a block like grass or snow that covers another: `base`, with a band of `cap`
+/// along the top. `seed` and `seed + 1` pick their patterns.
+fn capped_side_tile(cap: [u8; 3], base: [u8; 3], seed: u32) -> Vec<u8> {
+    let mut pixels = noisy_tile(base, seed);
+    let top = noisy_tile(cap, seed + 1);
+    let band = (TILE_SIZE * 3 * 4) as usize;
+    pixels[..band].copy_from_slice(&top[..band]);
+    pixels
+}
+
+/// Adds the block textures to `atlas` and says which faces of which blocks use them. There are
+/// no image files for blocks yet, so the tiles are made up here.
+fn block_textures(atlas: &mut AtlasBuilder) -> Result<BlockTextures> {
+    let grass = [96, 160, 64];
+    let dirt = [134, 96, 67];
+    let stone = [128, 128, 128];
+    let snow = [235, 240, 245];
+    let stone_tile = atlas.add_rgba8("stone", noisy_tile(stone, 1))?;
+    let dirt_tile = atlas.add_rgba8("dirt", noisy_tile(dirt, 2))?;
+    let grass_side = atlas.add_rgba8("grass_side", capped_side_tile(grass, dirt, 3))?;
+    let grass_top = atlas.add_rgba8("grass_top", noisy_tile(grass, 5))?;
+    let sand_tile = atlas.add_rgba8("sand", noisy_tile([219, 203, 146], 6))?;
+    let snow_side = atlas.add_rgba8("snow_side", capped_side_tile(snow, stone, 7))?;
+    let snow_top = atlas.add_rgba8("snow_top", noisy_tile(snow, 9))?;
+    let bark = atlas.add_rgba8("bark", noisy_tile([102, 76, 48], 10))?;
+    let log_end = atlas.add_rgba8("log_end", noisy_tile([168, 134, 88], 11))?;
+    let leaves = atlas.add_rgba8("leaves", leaf_tile([58, 118, 44], 12))?;
+    let lamp = atlas.add_rgba8("lamp", noisy_tile([255, 214, 130], 13))?;
+    let torch = atlas.add_rgba8("torch", noisy_tile([255, 150, 60], 14))?;
+    let glass = atlas.add_rgba8("glass", glass_tile())?;
+
+    let mut textures = BlockTextures::new();
+    textures.set_all(STONE, stone_tile);
+    textures.set_all(DIRT, dirt_tile);
+    textures.set_top_bottom_sides(GRASS, grass_top, dirt_tile, grass_side);
+    textures.set_all(SAND, sand_tile);
+    textures.set_top_bottom_sides(SNOW, snow_top, stone_tile, snow_side);
+    textures.set_top_bottom_sides(LOG, log_end, log_end, bark);
+    textures.set_all(LEAVES, leaves);
+    textures.set_all(LAMP, lamp);
+    textures.set_all(TORCH, torch);
+    textures.set_all(GLASS, glass);
+    textures.set_surface(LEAVES, Surface::Translucent);
+    textures.set_surface(WATER, Surface::Water);
+    textures.set_surface(GLASS, Surface::Translucent);
+    Ok(textures)
+}
+
+/// Which blocks give off light, and how much, and which let it through.
+fn block_lights() -> BlockLights {
+    let mut lights = BlockLights::new();
+    lights.set_emission(LAMP, MAX_LIGHT);
+    lights.set_emission(TORCH, MAX_LIGHT - 2);
+    lights.set_transparent(LEAVES);
+    lights.set_transparent(WATER);
+    lights.set_transparent(GLASS);
+    lights
+}
+
+/// The colour and reach of the point lights that the blocks from `block_lights` shine with.
+/// Torches are dimmer and redder than lamps.
+fn point_light_blocks() -> PointLightBlocks {
+    let mut blocks = PointLightBlocks::new();
+    blocks.set(LAMP, [1.0, 0.85, 0.6], 12.0);
+    blocks.set(TORCH, [1.0, 0.55, 0.25], 9.0);
+    blocks
+}
+
+/// Whether the block at `position` stops particles. Anything but air and water does, and so do
+/// chunks that aren't loaded, so nothing falls out of the world.
+fn stops_particles(world: &World, position: [i32; 3]) -> bool {
+    world.block(position).map_or(true, |block| !block.is_air() && block != WATER)
+}
+
+/// How high the ground a particle at `position` lands on is: the top of the first block under it
+/// that stops particles.
+fn particle_ground(world: &World, position: [f32; 3]) -> f32 {
+    let x = position[0].floor() as i32;
+    let z = position[2].floor() as i32;
+    let top = position[1].floor() as i32;
+    (0..PARTICLE_GROUND_SEARCH)
+        .map(|depth| top - depth)
+        .find(|&y| stops_particles(world, [x, y, z]))
+        .map_or(position[1] - PARTICLE_GROUND_SEARCH as f32, |y| (y + 1) as f32)
+}
+
+/// The light at `position` as a sprite takes it, from 0 to 1.
+fn sprite_light(world: &World, position: [i32; 3]) -> [f32; 2] {
+    world.light(position).map_or([1.0, 0.0], |light| {
+        [light.sky() as f32 / MAX_LIGHT as f32, light.block() as f32 / MAX_LIGHT as f32]
+    })
+}
+
+/// Spawns, for one tick, the particles that come and go by themselves around `around`: motes of
+/// dust in the air, and leaves falling from underneath trees. Each tick tries a few random
+/// positions nearby, and spawns whatever belongs there.
+fn spawn_ambient_particles(
+    particles: &mut Particles,
+    world: &World,
+    around: [f32; 3],
+    dust_uv: UvRect,
+    leaf_uv: UvRect,
+) {
+    for _ in 0..AMBIENT_PARTICLE_TRIES {
+        let offset = {
+            let random = particles.random();
+            [
+                (random.next_f32() * 2.0 - 1.0) * AMBIENT_PARTICLE_RADIUS,
+                (random.next_f32() * 2.0 - 1.0) * AMBIENT_PARTICLE_RADIUS,
+                (random.next_f32() * 2.0 - 1.0) * AMBIENT_PARTICLE_RADIUS,
+            ]
+        };
+        let position = [around[0] + offset[0], around[1] + offset[1], around[2] + offset[2]];
+        let block = [position[0].floor() as i32, position[1].floor() as i32, position[2].floor() as i32];
+        let below = [block[0], block[1] - 1, block[2]];
+        let here = world.block(block);
+        if here.map_or(false, BlockId::is_air) {
+            if particles.random().next_f32() < DUST_CHANCE {
+                particles.dust(position, dust_uv, sprite_light(world, block));
+            }
+        } else if here == Some(LEAVES) && world.block(below).map_or(false, BlockId::is_air) {
+            let under = [position[0], block[1] as f32 - 0.1, position[2]];
+            particles.falling_leaf(under, leaf_uv, sprite_light(world, below));
+        }
+    }
+}
+
+/// Spawns, for one tick, `flakes` snowflakes falling from above `around`, spread evenly over a
+/// disc around it. They're lit by the light where they start.
+fn spawn_snow(particles: &mut Particles, world: &World, around: [f32; 3], uv: UvRect, flakes: u32) {
+    for _ in 0..flakes {
+        let (angle, distance) = {
+            let random = particles.random();
+            (random.next_f32() * 2.0 * PI, random.next_f32().sqrt() * SNOW_RADIUS)
+        };
+        let position = [
+            around[0] + angle.cos() * distance,
+            around[1] + SNOW_HEIGHT,
+            around[2] + angle.sin() * distance,
+        ];
+        let block = [position[0].floor() as i32, position[1].floor() as i32, position[2].floor() as i32];
+        particles.snowflake(position, uv, sprite_light(world, block));
+    }
+}
+
+/// One chunk's mesh on the gpu. Chunks with nothing to draw don't get one.
+struct ChunkBuffers {
+    bounds: Aabb,
+    /// Where the mesh is in the `ChunkDraws`.
+    allocation: ChunkAllocation,
+    vertex_count: usize,
+    index_count: u32,
+    translucent_index_count: u32,
+    water_index_count: u32,
+}
+
+/// Uploads the mesh of the chunk at `coord` into `draws`.
+fn upload_chunk<B: Backend>(
+    context: &mut GfxContext<B>,
+    draws: &mut ChunkDraws<B>,
+    coord: ChunkCoord,
+    mesh: &ChunkMesh,
+) -> Result<ChunkBuffers> {
+    let origin = coord.origin();
+    let origin = [origin[0] as f32, origin[1] as f32, origin[2] as f32];
+    let size = CHUNK_SIZE as f32;
+    let bounds = Aabb::new(origin, [origin[0] + size, origin[1] + size, origin[2] + size]);
+    Ok(ChunkBuffers {
+        bounds,
+        allocation: draws.upload(context, origin, bounds, mesh)?,
+        vertex_count: mesh.vertices.len(),
+        index_count: mesh.indices.len() as u32,
+        translucent_index_count: mesh.translucent_indices.len() as u32,
+        water_index_count: mesh.water_indices.len() as u32,
+    })
+}
+
+/// Loading the world in the first place, or remeshing all of it with another mesher, so how
+/// long it took can be logged once the loader and the workers have run out of chunks.
+struct Remesh {
+    mesher: Mesher,
+    started: Instant,
+    chunks: usize,
+    /// The time the workers spent meshing, added up across all of them.
+    worker_milliseconds: f32,
+}
+
+impl Remesh {
+    fn new(mesher: Mesher) -> Self {
+        Remesh {
+            mesher,
+            started: Instant::now(),
+            chunks: 0,
+            worker_milliseconds: 0.0,
+        }
+    }
+
+    fn add(&mut self, meshed: &MeshedChunk) {
+        self.chunks += 1;
+        self.worker_milliseconds += meshed.milliseconds;
+    }
+
+    /// Logs how long the remesh took and how many triangles came out, so the meshers can be
+    /// compared.
+    fn report(&self, chunks: &HashMap<ChunkCoord, ChunkBuffers>) {
+        let elapsed = self.started.elapsed();
+        let milliseconds = elapsed.as_secs() as f32 * 1000.0 + elapsed.subsec_nanos() as f32 * 1e-6;
+        let triangles: usize = chunks
+            .values()
+            .map(|chunk| (chunk.index_count + chunk.translucent_index_count + chunk.water_index_count) as usize / 3)
+            .sum();
+        let vertices: usize = chunks.values().map(|chunk| chunk.vertex_count).sum();
+        info!(
+            "Meshed {} chunks with the {} mesher in {:.1} ms ({:.1} ms across the workers): {} triangles, {} vertices",
+            self.chunks,
+            self.mesher,
+            milliseconds,
+            self.worker_milliseconds,
+            triangles,
+            vertices,
+        );
+    }
+}
+
+/// Has to match the `Camera` block in `shaders/camera.glsl`, which is laid out by std140 rules.
+/// Those put each `vec2` on an 8 byte boundary, which these fields already are, and a `vec3` on
+/// a 16 byte one, which `sun` and `sun_direction` need padding for. Arrays of matrices are
+/// packed the same as here.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CameraUniform {
+    view_projection: ShaderMatrix,
+    inverse_view_projection: ShaderMatrix,
+    /// From world space to each cascade's shadow map. See `shadow::fit_cascades`.
+    shadow_view_projections: [ShaderMatrix; CASCADE_COUNT],
+    /// How far along the view each cascade ends.
+    cascade_splits: [f32; CASCADE_COUNT],
+    /// The row of the view matrix that gives depth, negated so depths in front of the camera
+    /// are positive.
+    view_depth: [f32; 4],
+    atlas_origin: [f32; 2],
+    atlas_cell: [f32; 2],
+    atlas_tile_size: [f32; 2],
+    atlas_columns: u32,
+    _padding: u32,
+    /// Towards the sun, scaled by `TimeOfDay::sun_strength`.
+    sun: [f32; 3],
+    daylight: f32,
+    ambient: f32,
+    /// Non-zero to tint everything by the shadow cascade it's in.
+    show_cascades: u32,
+    _sun_padding: [u32; 2],
+    /// Towards the sun, unscaled, for drawing it and the sky around it.
+    sun_direction: [f32; 3],
+    /// From `Sky::id`.
+    sky: u32,
+    /// From `TimeOfDay::sky_color`.
+    sky_color: [f32; 3],
+    /// How thick the fog is at the camera's height.
+    fog_density: f32,
+    /// Where the camera is.
+    eye: [f32; 3],
+    /// How much thinner the fog gets each block up, exponentially.
+    fog_height_falloff: f32,
+    /// From `sky::horizon_colors`, away from the sun.
+    fog_color: [f32; 3],
+    /// How far from the camera the loaded chunks reach, in blocks.
+    fog_end: f32,
+    /// From `sky::horizon_colors`, towards the sun.
+    fog_sun_color: [f32; 3],
+    /// Seconds since the example started.
+    time: f32,
+    /// From `ViewMode::id`.
+    view_mode: u32,
+    _view_mode_padding: [u32; 3],
+    /// Which ways are right and up on the screen, in world space.
+    camera_right: [f32; 3],
+    _right_padding: u32,
+    camera_up: [f32; 3],
+    _up_padding: u32,
+    /// From world space to the minimap. See `minimap::minimap_view_projection`.
+    minimap_view_projection: ShaderMatrix,
+    /// From world space to each face of the environment probe. See
+    /// `probe::face_view_projection`.
+    probe_view_projections: [ShaderMatrix; CUBE_FACES as usize],
+    /// From `probe::tile_mask`.
+    polished_tiles: [u32; 4],
+    /// From world space to the planar reflection, through `reflection::mirror`.
+    reflection_view_projection: ShaderMatrix,
+    water_height: f32,
+    /// From `Reflections::id`.
+    reflections: u32,
+    _reflection_padding: [u32; 2],
+}
+
+/// One entry in `LightsUniform`. Laid out like `PointLight` in `chunk.frag`, with the radius
+/// filling out the `vec3` before it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+struct ShaderPointLight {
+    position: [f32; 3],
+    radius: f32,
+    color: [f32; 3],
+    _padding: f32,
+}
+
+impl From<PointLight> for ShaderPointLight {
+    fn from(light: PointLight) -> Self {
+        ShaderPointLight {
+            position: light.position,
+            radius: light.radius,
+            color: light.color,
+            _padding: 0.0,
+        }
+    }
+}
+
+/// Has to match the `Lights` block in `chunk.frag`. Only the first `count` lights are used.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct LightsUniform {
+    count: u32,
+    _padding: [u32; 3],
+    lights: [ShaderPointLight; MAX_POINT_LIGHTS],
+}
+
+/// Has to match the `Ssao` block in `ssao.comp` and `ssao_blur.comp`. Only the first
+/// `sample_count` points of the kernel are used.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct SsaoUniform {
+    kernel: [[f32; 4]; MAX_SSAO_SAMPLES],
+    sample_count: u32,
+    /// How far out the kernel reaches, in blocks.
+    radius: f32,
+    blur_radius: u32,
+    /// The camera's clip planes, for turning depths back into distances.
+    near: f32,
+    far: f32,
+}
+
+/// Has to match the `PushConstants` blocks in the shaders. `outline.vert` uses `block_origin`
+/// as the corner of the block it outlines, and only `shadow.vert` and `probe.vert` use `layer`.
+/// The chunks get their origins from their records in the `ChunkDraws` instead.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PushConstants {
+    block_origin: [f32; 3],
+    /// Which cascade's shadow map, or which face of the environment probe, is being drawn.
+    layer: u32,
+}
+
+impl PushConstants {
+    /// Push constants are written as a slice of 32 bit words.
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const PushConstants as *const u32,
+                mem::size_of::<PushConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `PushConstants`, in 32 bit words
+const PUSH_CONSTANTS_SIZE: u32 = (mem::size_of::<PushConstants>() / mem::size_of::<u32>()) as u32;
+
+/// Has to match the `PushConstants` blocks in the post shaders, which measure the HDR target,
+/// draw the bloom from it and tonemap it. Each uses only the fields it needs.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct PostConstants {
+    /// The size of what's being drawn into, in pixels.
+    target_size: [f32; 2],
+    exposure: f32,
+    /// From `Tonemap::id`.
+    tonemap: u32,
+    bloom_strength: f32,
+    bloom_threshold: f32,
+    /// Non-zero when the camera is under water.
+    underwater: u32,
+    /// From `ViewMode::id`.
+    view_mode: u32,
+    /// Non-zero when the swapchain encodes to sRGB itself.
+    srgb_target: u32,
+}
+
+impl PostConstants {
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const PostConstants as *const u32,
+                mem::size_of::<PostConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `PostConstants`, in 32 bit words
+const POST_CONSTANTS_SIZE: u32 = (mem::size_of::<PostConstants>() / mem::size_of::<u32>()) as u32;
+
+/// Adds where the chunk is to the vertex input of a pipeline that draws chunks, read once per
+/// instance out of its record. `ChunkDraws::draw` binds the records as vertex buffer 1, and
+/// each draw's first instance is its chunk's slot in them.
+fn add_chunk_origin<B: Backend>(pipeline_desc: &mut pso::GraphicsPipelineDesc<B>) {
+    pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+        binding: 1,
+        stride: mem::size_of::<ChunkRecord>() as u32,
+        rate: 1,
+    });
+    pipeline_desc.attributes.push(pso::AttributeDesc {
+        location: 7,
+        binding: 1,
+        element: pso::Element {
+            format: f::Format::Rgb32Float,
+            offset: 0,
+        },
+    });
+}
+
+/// Builds the graphics pipeline from the current SPIR-V in `shaders`. This is called again
+/// whenever one of the shaders is changed on disk. With forward shading it lights chunks into
+/// `render_pass` as it draws them, and with deferred shading it draws them unlit into the
+/// G-buffer's render pass.
+///
+/// Two of the debug views need a pipeline of their own. `ViewMode::Wireframe` rasterizes only
+/// the edges of the triangles, and `ViewMode::Overdraw`, which is only ever forward shaded,
+/// adds every fragment onto what's there without any depth test. Every other view uses the
+/// normal pipeline.
+fn create_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+    shading: Shading,
+    view_mode: ViewMode,
+) -> Result<B::GraphicsPipeline> {
+    let fragment_shader = match shading {
+        Shading::Forward => "chunk.frag",
+        Shading::Deferred => "gbuffer.frag",
+    };
+    let vs_module = create_shader_module::<B>(device, shaders.get("chunk.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get(fragment_shader))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        // Chunk faces are wound counter-clockwise seen from outside, so the insides of blocks
+        // can be skipped
+        let polygon_mode = if view_mode == ViewMode::Wireframe {
+            pso::PolygonMode::Line(1.0)
+        } else {
+            pso::PolygonMode::Fill
+        };
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer {
+                polygon_mode,
+                cull_face: pso::Face::BACK,
+                front_face: pso::FrontFace::CounterClockwise,
+                ..pso::Rasterizer::FILL
+            },
+            pipeline_layout,
+            subpass,
+        );
+        let depth = if view_mode == ViewMode::Overdraw {
+            pso::DepthTest::Off
+        } else {
+            pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: true,
+            }
+        };
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth,
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        // Has to match the sample count of the render pass attachments
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        match shading {
+            Shading::Forward => {
+                let blend = if view_mode == ViewMode::Overdraw { pso::BlendState::ADD } else { pso::BlendState::ALPHA };
+                pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, blend));
+            }
+            // The G-buffer's albedo, normal and material are written as they are
+            Shading::Deferred => {
+                for _ in 0..3 {
+                    pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::Off));
+                }
+            }
+        }
+
+        // One interleaved vertex buffer of `ChunkVertex`es
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<ChunkVertex>() as u32,
+            rate: 0,
+        });
+        let attributes = [
+            (f::Format::Rgb32Float, 0),
+            (f::Format::Rgb32Float, 12),
+            (f::Format::Rg32Float, 24),
+            (f::Format::R32Uint, 32),
+            (f::Format::R32Float, 36),
+            (f::Format::Rg32Float, 40),
+            (f::Format::R32Float, 48),
+        ];
+        for (location, &(format, offset)) in attributes.iter().enumerate() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location: location as u32,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+        add_chunk_origin(&mut pipeline_desc);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    // The modules are only needed while the pipeline is being built
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    if view_mode == ViewMode::Normal {
+        debug!("Built the {} graphics pipeline", shading);
+    } else {
+        debug!("Built the {} {} graphics pipeline", shading, view_mode);
+    }
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that draws the translucent faces of the chunks, like glass, once
+/// everything opaque has been drawn. They're lit the way forward shading lights chunks, even
+/// with deferred shading, since the G-buffer only has room for one surface per pixel, and
+/// blended over what's behind them by their tile's alpha. They're depth tested without writing
+/// any depth, so ones further back still show through the ones in front.
+fn create_translucent_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("chunk.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("chunk.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer {
+                cull_face: pso::Face::BACK,
+                front_face: pso::FrontFace::CounterClockwise,
+                ..pso::Rasterizer::FILL
+            },
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: false,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::ALPHA));
+
+        // The same vertices as the chunk pipeline
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<ChunkVertex>() as u32,
+            rate: 0,
+        });
+        let attributes = [
+            (f::Format::Rgb32Float, 0),
+            (f::Format::Rgb32Float, 12),
+            (f::Format::Rg32Float, 24),
+            (f::Format::R32Uint, 32),
+            (f::Format::R32Float, 36),
+            (f::Format::Rg32Float, 40),
+            (f::Format::R32Float, 48),
+        ];
+        for (location, &(format, offset)) in attributes.iter().enumerate() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location: location as u32,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+        add_chunk_origin(&mut pipeline_desc);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the translucent pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that draws the billboards, quads that turn to face the camera, like the
+/// icon of the block about to be placed. They're blended like the translucent blocks, and drawn
+/// from both sides, since which way a quad winds depends on which way the camera's turned.
+fn create_billboard_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("billboard.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("billboard.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: false,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::ALPHA));
+        add_sprite_attributes(&mut pipeline_desc, 0);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the billboard pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that outlines the block the camera is pointing at. It draws lines
+/// against the depth buffer the chunks leave behind without writing to it, with the same layout
+/// as the chunk pipeline, so the camera's descriptor set stays bound between the two.
+fn create_outline_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("outline.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("outline.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::LineList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: false,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::ALPHA));
+
+        // Just the corners from `OUTLINE_EDGES`
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<[f32; 3]>() as u32,
+            rate: 0,
+        });
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: 0,
+            binding: 0,
+            element: pso::Element {
+                format: f::Format::Rgb32Float,
+                offset: 0,
+            },
+        });
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the outline pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline for the debug lines, from a line list of coloured points in world space.
+/// They're drawn over everything without testing the depth buffer, so chunks behind the ground
+/// still show, with the same layout as the chunk pipeline for the camera.
+fn create_debug_line_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("debug_line.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("debug_line.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::LineList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::Off,
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::Off));
+
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<LineVertex>() as u32,
+            rate: 0,
+        });
+        // Position, then colour
+        for location in 0..2 {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location,
+                binding: 0,
+                element: pso::Element {
+                    format: f::Format::Rgb32Float,
+                    offset: location * mem::size_of::<[f32; 3]>() as u32,
+                },
+            });
+        }
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the debug line pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that draws the sky behind the chunks, with `sky.frag` picking it by
+/// which way each pixel looks. Its one triangle is at the far plane, tested against the depth
+/// the chunks leave behind without writing to it, so it only shows where nothing was drawn. It
+/// has the same layout as the chunk pipeline, for the camera and the skybox.
+fn create_sky_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("sky.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("sky.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        // Or equal, since the far plane is exactly the depth that was cleared to
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: false,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::Off));
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the sky pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that draws the water faces of the chunks over everything else, once it's
+/// all been drawn and lit. It blends them over what's behind and tests them against its depth,
+/// without writing any, so water behind water still shows. Both sides of each face are drawn,
+/// since the top of the water is seen from underneath too. It has the same layout as the chunk
+/// pipeline, for the camera and the ripples.
+fn create_water_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("water.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("water.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: false,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::ALPHA));
+
+        // Only the parts of `ChunkVertex` that water uses
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<ChunkVertex>() as u32,
+            rate: 0,
+        });
+        let attributes = [
+            (0, f::Format::Rgb32Float, 0),
+            (1, f::Format::Rgb32Float, 12),
+            (5, f::Format::Rg32Float, 40),
+            (6, f::Format::R32Float, 48),
+        ];
+        for &(location, format, offset) in &attributes {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+        add_chunk_origin(&mut pipeline_desc);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the water pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that draws chunks into a cascade's shadow map. Only depth is written, so
+/// there's no fragment shader, and only the positions are read out of the chunk vertices. Both
+/// sides of every face are drawn, so light can't leak through the gaps where the map's texels
+/// don't line up with the blocks.
+fn create_shadow_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("shadow.vert"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: None,
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: true,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<ChunkVertex>() as u32,
+            rate: 0,
+        });
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: 0,
+            binding: 0,
+            element: pso::Element {
+                format: f::Format::Rgb32Float,
+                offset: 0,
+            },
+        });
+        add_chunk_origin(&mut pipeline_desc);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the shadow pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that draws chunks into the minimap, from straight above. Everything is
+/// drawn in one go, water and all, without blending, and shaded by height rather than lit.
+fn create_minimap_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("minimap.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("minimap.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer {
+                cull_face: pso::Face::BACK,
+                front_face: pso::FrontFace::CounterClockwise,
+                ..pso::Rasterizer::FILL
+            },
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: true,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::Off));
+
+        // The position, uv and tile out of the chunk vertices
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<ChunkVertex>() as u32,
+            rate: 0,
+        });
+        let attributes = [
+            (0, f::Format::Rgb32Float, 0),
+            (2, f::Format::Rg32Float, 24),
+            (3, f::Format::R32Uint, 32),
+        ];
+        for &(location, format, offset) in attributes.iter() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+        add_chunk_origin(&mut pipeline_desc);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the minimap pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that draws chunks into the faces of the environment probe. Like the
+/// minimap's, everything is drawn in one go without blending, lit roughly the way the chunks
+/// are. The faces are mirrored, so it culls the faces that wind the other way from the rest.
+fn create_probe_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("probe.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("probe.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer {
+                cull_face: pso::Face::BACK,
+                front_face: pso::FrontFace::Clockwise,
+                ..pso::Rasterizer::FILL
+            },
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: true,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::Off));
+
+        // The position, normal, uv, tile and light out of the chunk vertices
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<ChunkVertex>() as u32,
+            rate: 0,
+        });
+        let attributes = [
+            (0, f::Format::Rgb32Float, 0),
+            (1, f::Format::Rgb32Float, 12),
+            (2, f::Format::Rg32Float, 24),
+            (3, f::Format::R32Uint, 32),
+            (5, f::Format::Rg32Float, 40),
+        ];
+        for &(location, format, offset) in attributes.iter() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+        add_chunk_origin(&mut pipeline_desc);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the probe pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the pipeline that draws chunks into the planar reflection, with the world mirrored
+/// through the water. It's lit like the probe's, and for the same reason culls the faces that
+/// wind the other way from the rest.
+fn create_reflection_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("reflection.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("reflection.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer {
+                cull_face: pso::Face::BACK,
+                front_face: pso::FrontFace::Clockwise,
+                ..pso::Rasterizer::FILL
+            },
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: true,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::Off));
+
+        // The position, normal, uv, tile and light out of the chunk vertices
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<ChunkVertex>() as u32,
+            rate: 0,
+        });
+        let attributes = [
+            (0, f::Format::Rgb32Float, 0),
+            (1, f::Format::Rgb32Float, 12),
+            (2, f::Format::Rg32Float, 24),
+            (3, f::Format::R32Uint, 32),
+            (5, f::Format::Rg32Float, 40),
+        ];
+        for &(location, format, offset) in attributes.iter() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+        add_chunk_origin(&mut pipeline_desc);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the reflection pipeline");
+    Ok(pipeline)
+}
+
+/// Builds a compute pipeline from `compute_shader`: `cull.comp`, which tests the chunks against
+/// each view and fills the `ChunkDraws` lists with the ones that pass, or `particles.comp`,
+/// which moves the `GpuParticles`.
+fn create_compute_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    compute_shader: &str,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::ComputePipeline> {
+    let module = create_shader_module::<B>(device, shaders.get(compute_shader))?;
+    let pipeline = device.create_compute_pipeline(
+        &pso::ComputePipelineDesc::new(
+            pso::EntryPoint {
+                entry: "main",
+                module: &module,
+                specialization: &[],
+            },
+            pipeline_layout,
+        ),
+        Some(pipeline_cache),
+    );
+    device.destroy_shader_module(module);
+
+    let pipeline = pipeline?;
+    debug!("Built the {} pipeline", compute_shader);
+    Ok(pipeline)
+}
+
+/// Builds a pipeline that runs `fragment_shader` over every pixel of the screen, for the passes
+/// that work from the G-buffer or the HDR target: lighting the G-buffer in `deferred.frag`, and
+/// measuring, blooming and tonemapping the scene.
+/// It draws one triangle over the whole screen with no vertex buffer, blending it with `blend`.
+fn create_fullscreen_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    fragment_shader: &str,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    blend: pso::BlendState,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("fullscreen.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get(fragment_shader))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        // The depth test is left off, since the triangle covers everything in front of it
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, blend));
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the {} pipeline", fragment_shader);
+    Ok(pipeline)
+}
+
+/// The passes of a frame graph, to tell them apart as they're recorded. Forward shading has no
+/// SSAO or lighting passes.
+#[derive(Clone, Copy)]
+struct FramePasses {
+    cull: PassId,
+    particles: PassId,
+    /// The shadow cascades, the minimap, the environment probe and the planar reflection.
+    views: PassId,
+    /// The chunks, lit as they're drawn with forward shading or into the G-buffer with deferred.
+    scene: PassId,
+    ssao: Option<PassId>,
+    ssao_blur: Option<PassId>,
+    lighting: Option<PassId>,
+}
+
+/// The ambient occlusion in a deferred frame graph, before and after it's blurred.
+#[derive(Clone, Copy)]
+struct Occlusion {
+    occlusion: ResourceId,
+    blurred: ResourceId,
+}
+
+/// The frame graph for `shading`, from culling the chunks to the HDR target being drawn. Only
+/// the ambient occlusion belongs to the graph. Everything else is imported, with buffers filled
+/// and images drawn in render passes of their own, and the graph only keeps them in order and
+/// hands them between queues.
+///
+/// The culling and the particles are worked out on the compute queue, if there is one, while
+/// the graphics queue finishes the frame before. With deferred shading, so is the ambient
+/// occlusion, which is worked out from the G-buffer while the views are drawn.
+fn frame_graph(shading: Shading) -> (GraphBuilder<ImageDesc>, FramePasses, Option<Occlusion>) {
+    let mut builder = GraphBuilder::new();
+    let draws = builder.import("draws");
+    let particles = builder.import("particles");
+    let views = builder.import("views");
+    let target = builder.import("HDR target");
+    let cull = builder.pass("cull").on(Queue::Compute).write(draws).id();
+    let particles_pass = builder.pass("particles").on(Queue::Compute).write(particles).id();
+    match shading {
+        Shading::Forward => {
+            let views_pass = builder.pass("views").read(draws).write(views).id();
+            let scene = builder.pass("scene").read(draws).read(particles).read(views).write(target).id();
+            let passes = FramePasses {
+                cull,
+                particles: particles_pass,
+                views: views_pass,
+                scene,
+                ssao: None,
+                ssao_blur: None,
+                lighting: None,
+            };
+            (builder, passes, None)
+        }
+        Shading::Deferred => {
+            let gbuffer = builder.import("G-buffer");
+            let occlusion = builder.transient("occlusion", ImageDesc::full(OCCLUSION_FORMAT));
+            let blurred = builder.transient("blurred occlusion", ImageDesc::full(OCCLUSION_FORMAT));
+            let scene = builder.pass("gbuffer").read(draws).write(gbuffer).id();
+            let ssao = builder.pass("ssao").on(Queue::Compute).read(gbuffer).storage(occlusion).id();
+            let ssao_blur = builder
+                .pass("ssao blur")
+                .on(Queue::Compute)
+                .read(gbuffer)
+                .read(occlusion)
+                .storage(blurred)
+                .id();
+            // Added after the SSAO, so they're drawn while it's worked out
+            let views_pass = builder.pass("views").read(draws).write(views).id();
+            let lighting = builder
+                .pass("lighting")
+                .read(draws)
+                .read(particles)
+                .read(views)
+                .read(gbuffer)
+                .read(blurred)
+                .write(target)
+                .id();
+            let passes = FramePasses {
+                cull,
+                particles: particles_pass,
+                views: views_pass,
+                scene,
+                ssao: Some(ssao),
+                ssao_blur: Some(ssao_blur),
+                lighting: Some(lighting),
+            };
+            (builder, passes, Some(Occlusion { occlusion, blurred }))
+        }
+    }
+}
+
+/// The storage image descriptor sets for one swapchain image's ambient occlusion, for the SSAO
+/// passes to write it through.
+struct OcclusionSets<B: Backend> {
+    occlusion: B::DescriptorSet,
+    blurred: B::DescriptorSet,
+}
+
+/// Allocates the storage image descriptor sets for each swapchain image's ambient occlusion in
+/// `deferred_graph`. Like `allocate_gbuffer_sets`, this frees everything allocated earlier, and
+/// is called again whenever the graph's images are recreated.
+fn allocate_occlusion_sets<B: Backend>(
+    device: &B::Device,
+    descriptors: &mut DescriptorAllocator<B>,
+    deferred_graph: &RenderGraph<B>,
+    occlusion: Occlusion,
+    count: usize,
+) -> Result<Vec<OcclusionSets<B>>> {
+    descriptors.reset();
+    let mut sets = Vec::with_capacity(count);
+    for index in 0..count {
+        let occlusion_set = descriptors.allocate()?;
+        let blurred_set = descriptors.allocate()?;
+        device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set: &occlusion_set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(deferred_graph.view(occlusion.occlusion, index), i::Layout::General)),
+            },
+            pso::DescriptorSetWrite {
+                set: &blurred_set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(deferred_graph.view(occlusion.blurred, index), i::Layout::General)),
+            },
+        ]);
+        sets.push(OcclusionSets { occlusion: occlusion_set, blurred: blurred_set });
+    }
+    Ok(sets)
+}
+
+/// Allocates a descriptor set for each swapchain image's G-buffer and ambient occlusion, for the
+/// SSAO and lighting passes to read them through. Everything allocated earlier is freed first,
+/// so this is called again whenever they're recreated, once nothing is using the old sets.
+fn allocate_gbuffer_sets<B: Backend>(
+    device: &B::Device,
+    descriptors: &mut DescriptorAllocator<B>,
+    gbuffer: &GBuffer<B>,
+    deferred_graph: &RenderGraph<B>,
+    occlusion: Occlusion,
+    count: usize,
+) -> Result<Vec<B::DescriptorSet>> {
+    descriptors.reset();
+    let mut sets = Vec::with_capacity(count);
+    for index in 0..count {
+        let set = descriptors.allocate()?;
+        let views = gbuffer.views(index);
+        device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set: &set,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(views[0], i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &set,
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(views[1], i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &set,
+                binding: 2,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(views[2], i::Layout::ShaderReadOnlyOptimal)),
+            },
+            // The depth stays attached to the lighting pass while it's sampled, so it's in the
+            // read-only depth layout rather than the usual one for sampling
+            pso::DescriptorSetWrite {
+                set: &set,
+                binding: 3,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(views[3], i::Layout::DepthStencilReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &set,
+                binding: 4,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(gbuffer.sampler())),
+            },
+            pso::DescriptorSetWrite {
+                set: &set,
+                binding: 5,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(deferred_graph.view(occlusion.occlusion, index), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &set,
+                binding: 6,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(deferred_graph.view(occlusion.blurred, index), i::Layout::ShaderReadOnlyOptimal)),
+            },
+        ]);
+        sets.push(set);
+    }
+    Ok(sets)
+}
+
+/// The descriptor sets for one swapchain image's post-processing.
+struct PostSets<B: Backend> {
+    /// The HDR target and all of the bloom, for measuring and tonemapping the scene.
+    scene: B::DescriptorSet,
+    /// The HDR target through the bloom's filtering sampler, for the bright pass.
+    bright: B::DescriptorSet,
+    /// Each of the bloom's mips, for drawing the next one down or the one above from it.
+    mips: Vec<B::DescriptorSet>,
+}
+
+/// Allocates the descriptor sets for each swapchain image's HDR target and bloom, for measuring,
+/// blooming and tonemapping it. Like `allocate_gbuffer_sets`, this frees everything allocated
+/// earlier, and is called again whenever the targets are recreated.
+fn allocate_post_sets<B: Backend>(
+    device: &B::Device,
+    descriptors: &mut DescriptorAllocator<B>,
+    hdr: &Hdr<B>,
+    bloom: &Bloom<B>,
+    count: usize,
+) -> Result<Vec<PostSets<B>>> {
+    descriptors.reset();
+    let mut sets = Vec::with_capacity(count);
+    for index in 0..count {
+        let scene = descriptors.allocate()?;
+        device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set: &scene,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(hdr.view(index), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &scene,
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(hdr.sampler())),
+            },
+            pso::DescriptorSetWrite {
+                set: &scene,
+                binding: 2,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(bloom.view(index, 0), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &scene,
+                binding: 3,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(bloom.sampler())),
+            },
+        ]);
+
+        let bright = descriptors.allocate()?;
+        device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set: &bright,
+                binding: 0,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(hdr.view(index), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set: &bright,
+                binding: 1,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(bloom.sampler())),
+            },
+        ]);
+
+        let mut mips = Vec::with_capacity(bloom.mips() as usize);
+        for level in 0..bloom.mips() {
+            let set = descriptors.allocate()?;
+            device.write_descriptor_sets(vec![
+                pso::DescriptorSetWrite {
+                    set: &set,
+                    binding: 0,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Image(bloom.view(index, level), i::Layout::ShaderReadOnlyOptimal)),
+                },
+                pso::DescriptorSetWrite {
+                    set: &set,
+                    binding: 1,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Sampler(bloom.sampler())),
+                },
+            ]);
+            mips.push(set);
+        }
+
+        sets.push(PostSets { scene, bright, mips });
+    }
+    Ok(sets)
+}
+
+/// Points every frame's descriptor set at the current shadow maps.
+fn write_shadow_descriptors<B: Backend>(
+    device: &B::Device,
+    camera_uniforms: &UniformRing<B, CameraUniform>,
+    shadow_map: &ShadowMap<B>,
+) {
+    for frame_index in 0..camera_uniforms.frames() {
+        let set = camera_uniforms.set(frame_index);
+        device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set,
+                binding: 3,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(shadow_map.view(), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set,
+                binding: 4,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(shadow_map.sampler())),
+            },
+        ]);
+    }
+}
+
+/// Points every frame's descriptor set at the current planar reflection.
+fn write_reflection_descriptors<B: Backend>(
+    device: &B::Device,
+    camera_uniforms: &UniformRing<B, CameraUniform>,
+    reflection: &PlanarReflection<B>,
+) {
+    for frame_index in 0..camera_uniforms.frames() {
+        let set = camera_uniforms.set(frame_index);
+        device.write_descriptor_sets(vec![
+            pso::DescriptorSetWrite {
+                set,
+                binding: 11,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Image(reflection.view(), i::Layout::ShaderReadOnlyOptimal)),
+            },
+            pso::DescriptorSetWrite {
+                set,
+                binding: 12,
+                array_offset: 0,
+                descriptors: Some(pso::Descriptor::Sampler(reflection.sampler())),
+            },
+        ]);
+    }
+}
+
+/// The attachments in each forward shading framebuffer, in the order `create_scene_render_pass`
+/// expects them.
+fn framebuffer_attachments<'a, B: Backend>(
+    hdr: &'a Hdr<B>,
+    msaa_targets: &'a Option<AttachmentImages<B>>,
+    depth_images: &'a AttachmentImages<B>,
+) -> Vec<&'a AttachmentImages<B>> {
+    iter::once(hdr.color()).chain(msaa_targets.iter()).chain(iter::once(depth_images)).collect()
+}
+
+fn main() {
+    renderer_common::launch("voxel-renderer", Chapter);
+}
+
+struct Chapter;
+
+impl Runner for Chapter {
+    fn run<B: Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<()> {
+        let mut cpu_profiler = CpuProfiler::new(context.args.cpu_trace.is_some());
+        let mut swapchain = context.create_swapchain()?;
+        let samples = choose_sample_count(&context.adapter, context.args.msaa)?;
+        let depth_format = choose_depth_format(&context.adapter)?;
+        info!("Depth format: {:?}, {}x MSAA", depth_format, samples);
+        // Everything is drawn into an HDR target first, and tonemapped into the swapchain image
+        // at the end
+        let mut hdr = Hdr::new(context, &swapchain, frame_sync::DEFAULT_FRAMES_IN_FLIGHT)?;
+        let mut bloom = Bloom::new(context, &swapchain, context.config.settings().bloom_mips)?;
+        let render_pass = renderer_common::pass::create_scene_render_pass::<B>(
+            &context.device,
+            HDR_FORMAT,
+            depth_format,
+            samples,
+        );
+
+        let mut msaa_targets = if samples > 1 {
+            Some(AttachmentImages::multisampled_color(
+                context.device.clone(),
+                context.allocator.clone(),
+                HDR_FORMAT,
+                samples,
+                &swapchain,
+            )?)
+        } else {
+            None
+        };
+        let mut depth_images = AttachmentImages::depth(
+            context.device.clone(),
+            context.allocator.clone(),
+            depth_format,
+            samples,
+            &swapchain,
+        )?;
+        // Deferred shading draws into a G-buffer and lights it into the HDR target after.
+        // Both are kept around, so switching between the two is instant.
+        let mut gbuffer = GBuffer::new(context, &swapchain)?;
+        let lighting_pass = renderer_common::pass::create_lighting_render_pass::<B>(
+            &context.device,
+            HDR_FORMAT,
+            gbuffer.depth().format(),
+        );
+        // Each way of shading has a frame graph, which puts the passes in order, splits them
+        // between the queues and works out the barriers and semaphores between them. Ambient
+        // occlusion is worked out from the G-buffer, so it's only in the deferred one, which
+        // owns the images it's written to.
+        let (forward_builder, forward_passes, _) = frame_graph(Shading::Forward);
+        let mut forward_graph =
+            RenderGraph::new(context, forward_builder, &swapchain, frame_sync::DEFAULT_FRAMES_IN_FLIGHT)?;
+        let (deferred_builder, deferred_passes, occlusion) = frame_graph(Shading::Deferred);
+        let occlusion = occlusion.unwrap();
+        let mut deferred_graph =
+            RenderGraph::new(context, deferred_builder, &swapchain, frame_sync::DEFAULT_FRAMES_IN_FLIGHT)?;
+        if samples > 1 {
+            info!("Deferred shading doesn't multisample, so {}x MSAA only applies to forward shading", samples);
+        }
+
+        let mut atlas_builder = AtlasBuilder::new(TILE_SIZE);
+        let textures = Arc::new(block_textures(&mut atlas_builder)?);
+        let atlas = atlas_builder.build(context)?;
+        let grid = atlas.layout.grid();
+
+        // The world starts empty and is generated around the camera, or loaded if `--world`
+        // names a directory it's been saved in. Chunks are meshed in the background and drawn
+        // as their meshes arrive.
+        let requested_seed = context.args.seed.unwrap_or(context.config.settings().seed);
+        let mut store = match context.args.world {
+            Some(ref directory) => Some(RegionStore::open(directory, requested_seed)?),
+            None => None,
+        };
+        let seed = store.as_ref().map_or(requested_seed, |store| store.seed());
+        if let Some(ref store) = store {
+            if context.args.seed.map_or(false, |requested| requested != seed) {
+                warn!("The world in {} was made with seed {}, so --seed is ignored", store.directory().display(), seed);
+            }
+            info!("Saving the world in {}", store.directory().display());
+        }
+        info!("Generating the world from seed {}", seed);
+        let generator = WorldGenerator::new(
+            seed,
+            TerrainBlocks {
+                stone: STONE,
+                dirt: DIRT,
+                grass: GRASS,
+                sand: SAND,
+                snow: SNOW,
+                log: LOG,
+                leaves: LEAVES,
+                water: WATER,
+            },
+        );
+        // The cube map sky's clouds and stars come from the world's seed too, and so do the
+        // ripples on the water
+        let skybox = Skybox::new(context, seed)?;
+        let ripples = Texture::tiling_unorm8(context, RIPPLE_SIZE, RIPPLE_SIZE, &ripple_normals(seed, RIPPLE_SIZE))?;
+        let mut world = World::new();
+        let mut pending_edits = PendingEdits::new();
+        let lighting = Lighting::new(block_lights(), HEIGHT_IN_CHUNKS);
+        let mut point_lights = PointLights::new(point_light_blocks());
+        let mut loader = ChunkLoader::new(context.config.settings().render_distance, 0..HEIGHT_IN_CHUNKS);
+        let mut lods = LodTracker::new(context.config.settings().lod_distance);
+        let mut workers = MeshWorkers::new(context.config.settings().mesh_threads, textures.clone())?;
+        let mut mesher = context.config.settings().mesher;
+        let mut remesh = Some(Remesh::new(mesher));
+        let mut meshed_with = mesher;
+        let mut chunks: HashMap<ChunkCoord, ChunkBuffers> = HashMap::new();
+        // Their meshes all go into the same big buffers, and are culled and drawn from there
+        // on the gpu
+        let mut chunk_draws = ChunkDraws::new(context, frame_sync::DEFAULT_FRAMES_IN_FLIGHT)?;
+        // Bits of broken blocks, dust, leaves and snow are spawned on the cpu each tick, then
+        // handed to the gpu, which simulates and draws them from then on
+        let mut particles = Particles::new(MAX_PARTICLES, seed);
+        let mut gpu_particles = GpuParticles::new(context, MAX_GPU_PARTICLES, frame_sync::DEFAULT_FRAMES_IN_FLIGHT)?;
+        // However much of a flake is due each tick, carried over to the next
+        let mut snow_due = 0.0;
+        // The dust and the snow are specks of the snow tile, which is the closest the atlas has
+        // to white, and the leaves are pieces of the leaf tile
+        let dust_uv = atlas.uv(textures.get(SNOW, Direction::PosY));
+        let leaf_uv = atlas.uv(textures.get(LEAVES, Direction::PosY));
+        // The hotbar shows each placeable block by its side, like the placement icon
+        let hotbar_icons: Vec<UvRect> = PLACEABLE
+            .iter()
+            .map(|&(block, _)| atlas.uv(textures.get(block, Direction::PosX)))
+            .collect();
+        // Quads facing the camera, written again each frame
+        let mut billboards = Billboards::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        )?;
+
+        // The camera and the atlas layout come from a uniform buffer, which SSAO reads as well,
+        // and the atlas, the shadow map, the skybox, the ripples on the water, the environment
+        // probe and the planar reflection are sampled in the fragment shader. Each chunk's
+        // position comes from its record in `chunk_draws`.
+        let set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::VERTEX
+                        | pso::ShaderStageFlags::FRAGMENT
+                        | pso::ShaderStageFlags::COMPUTE,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 2,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 3,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 4,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 5,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 6,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 7,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 8,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 9,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 10,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 11,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 12,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut descriptors = DescriptorAllocator::new(context.device.clone(), set_layout.clone());
+        // The point lights near the camera are in a second set, since they're only needed for
+        // shading chunks
+        let lights_set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut lights_descriptors = DescriptorAllocator::new(context.device.clone(), lights_set_layout.clone());
+        // And the G-buffer the lighting pass reads from is in a third, one per swapchain image,
+        // along with the ambient occlusion before and after it's blurred. Only deferred shading
+        // uses it.
+        let gbuffer_set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            (0..7)
+                .map(|binding| pso::DescriptorSetLayoutBinding {
+                    binding,
+                    ty: if binding == 4 { pso::DescriptorType::Sampler } else { pso::DescriptorType::SampledImage },
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT | pso::ShaderStageFlags::COMPUTE,
+                    immutable_samplers: false,
+                })
+                .collect(),
+        ));
+        let mut gbuffer_descriptors = DescriptorAllocator::new(context.device.clone(), gbuffer_set_layout.clone());
+        // The SSAO kernel and settings are in a fourth, which changes with the settings
+        let ssao_set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::COMPUTE,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut ssao_descriptors = DescriptorAllocator::new(context.device.clone(), ssao_set_layout.clone());
+        let pipeline_layout = context.device.create_pipeline_layout(
+            vec![set_layout.raw(), lights_set_layout.raw(), gbuffer_set_layout.raw(), ssao_set_layout.raw()],
+            &[(pso::ShaderStageFlags::VERTEX, 0..PUSH_CONSTANTS_SIZE)],
+        );
+        // The SSAO passes are compute shaders, which write the occlusion through a fifth set,
+        // one for each image they write for each swapchain image
+        let occlusion_set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::StorageImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::COMPUTE,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut occlusion_descriptors = DescriptorAllocator::new(context.device.clone(), occlusion_set_layout.clone());
+        let ssao_pipeline_layout = context.device.create_pipeline_layout(
+            vec![
+                set_layout.raw(),
+                lights_set_layout.raw(),
+                gbuffer_set_layout.raw(),
+                ssao_set_layout.raw(),
+                occlusion_set_layout.raw(),
+            ],
+            &[],
+        );
+        // Measuring, blooming and tonemapping the HDR target have a layout of their own, since
+        // they don't need any of the above. Each reads from one image, and tonemapping from the
+        // bloom as well, with everything else pushed as constants.
+        let post_set_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 2,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 3,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut post_descriptors = DescriptorAllocator::new(context.device.clone(), post_set_layout.clone());
+        let post_pipeline_layout = context.device.create_pipeline_layout(
+            vec![post_set_layout.raw()],
+            &[(pso::ShaderStageFlags::FRAGMENT, 0..POST_CONSTANTS_SIZE)],
+        );
+
+        let mut clock = Clock::new(context.is_headless());
+        let mut timestep = FixedTimestep::new();
+        let mut input = Input::new(&context.config.settings().bindings);
+        input.set_grab_on_focus(!context.is_headless());
+        let mut gamepads = Gamepads::new(!context.is_headless());
+        let mut last_seconds = 0.0;
+        // Start a little above the ground looking out over it, with the orbit camera circling
+        // the origin from further out
+        let start_height = generator.height_at(0, 40) as f32 + 16.0;
+        let ground_height = generator.height_at(0, 0) as f32;
+        let mut camera = CameraSwitch::new(
+            FpsCamera::new([0.0, start_height, 40.0], context.config.settings()),
+            OrbitCamera::new([0.0, ground_height, 0.0], 90.0, context.config.settings()),
+        );
+        // The mesher from the settings file, so an edit to it can be told apart from the
+        // overlay or a key changing it
+        let mut settings_mesher = mesher;
+        // Which of `PLACEABLE` placing a block puts down, and how far the wheel has turned
+        // towards the next one
+        let mut selected_block = 0;
+        let mut block_scroll = 0.0;
+        let mut time_of_day = TimeOfDay::new(START_TIME, context.config.settings().day_length);
+        let mut shadow_map = ShadowMap::new(context, context.config.settings().shadow_resolution)?;
+        let world_height = (HEIGHT_IN_CHUNKS * CHUNK_SIZE as i32) as f32;
+        let mut minimap = Minimap::new(context, depth_format, world_height, 0.0)?;
+        let mut probe = EnvironmentProbe::new(context, depth_format)?;
+        let polished_tiles = tile_mask(&[textures.get(GLASS, Direction::PosX)]);
+        let mut reflection = PlanarReflection::new(context, &swapchain, depth_format)?;
+        let mut shadows_enabled = true;
+        let mut show_cascades = false;
+        let mut view_mode = ViewMode::Normal;
+        let mut debug_line_settings = DebugLineSettings::default();
+        // The lines are built again every frame, into the same vertices
+        let mut debug_lines = DebugLines::new();
+        // The corners of the frustum from when drawing it was turned on
+        let mut frozen_frustum = None;
+        // Like the mesher, the settings file's shading is remembered so an edit to it can be
+        // told apart from the overlay changing it
+        let mut shading = context.config.settings().shading;
+        let mut settings_shading = shading;
+        // The kernel only changes with the number of samples, so it's kept until that does
+        let mut ssao_kernel = hemisphere_kernel(context.config.settings().ssao_samples as usize);
+        let mut auto_exposure = AutoExposure::new();
+
+        let mut shaders = ShaderSet::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"), shaders::ALL);
+        let mut pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+            Shading::Forward,
+            ViewMode::Normal,
+        )?;
+        let mut outline_pipeline = create_outline_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+        let mut gbuffer_pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            gbuffer.render_pass(),
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            1,
+            Shading::Deferred,
+            ViewMode::Normal,
+        )?;
+        // The debug views that draw differently
+        let mut wireframe_pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+            Shading::Forward,
+            ViewMode::Wireframe,
+        )?;
+        let mut deferred_wireframe_pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            gbuffer.render_pass(),
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            1,
+            Shading::Deferred,
+            ViewMode::Wireframe,
+        )?;
+        let mut overdraw_pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+            Shading::Forward,
+            ViewMode::Overdraw,
+        )?;
+        let mut lighting_pipeline = create_fullscreen_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "deferred.frag",
+            &lighting_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            pso::BlendState::Off,
+        )?;
+        let mut ssao_pipeline = create_compute_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "ssao.comp",
+            &ssao_pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let mut ssao_blur_pipeline = create_compute_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "ssao_blur.comp",
+            &ssao_pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        // With deferred shading the outline is drawn after the lighting, into its pass
+        let mut deferred_outline_pipeline = create_outline_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &lighting_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            1,
+        )?;
+        // And the sky after that, behind the lit G-buffer
+        let mut sky_pipeline = create_sky_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+        let mut deferred_sky_pipeline = create_sky_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &lighting_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            1,
+        )?;
+        // And the translucent blocks and the water over the top of it all
+        let mut translucent_pipeline = create_translucent_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+        let mut deferred_translucent_pipeline = create_translucent_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &lighting_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            1,
+        )?;
+        let mut water_pipeline = create_water_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+        let mut deferred_water_pipeline = create_water_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &lighting_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            1,
+        )?;
+        // Then the billboards, blended over the water
+        let mut billboard_pipeline = create_billboard_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+        let mut deferred_billboard_pipeline = create_billboard_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &lighting_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            1,
+        )?;
+        // And last of all the debug lines, on top of everything
+        let mut debug_line_pipeline = create_debug_line_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+        let mut deferred_debug_line_pipeline = create_debug_line_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &lighting_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            1,
+        )?;
+        let mut shadow_pipeline = create_shadow_pipeline::<B>(
+            &context.device,
+            &shaders,
+            shadow_map.render_pass(),
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let mut minimap_pipeline = create_minimap_pipeline::<B>(
+            &context.device,
+            &shaders,
+            minimap.render_pass(),
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let mut probe_pipeline = create_probe_pipeline::<B>(
+            &context.device,
+            &shaders,
+            probe.render_pass(),
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let mut reflection_pipeline = create_reflection_pipeline::<B>(
+            &context.device,
+            &shaders,
+            reflection.render_pass(),
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let mut cull_pipeline = create_compute_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "cull.comp",
+            chunk_draws.pipeline_layout(),
+            context.pipeline_cache.cache(),
+        )?;
+        let mut particle_pipeline = create_compute_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "particles.comp",
+            gpu_particles.pipeline_layout(),
+            context.pipeline_cache.cache(),
+        )?;
+        let mut luminance_pipeline = create_fullscreen_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "luminance.frag",
+            hdr.luminance_pass(),
+            &post_pipeline_layout,
+            context.pipeline_cache.cache(),
+            pso::BlendState::Off,
+        )?;
+        let mut tonemap_pipeline = create_fullscreen_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "tonemap.frag",
+            hdr.tonemap_pass(),
+            &post_pipeline_layout,
+            context.pipeline_cache.cache(),
+            pso::BlendState::Off,
+        )?;
+        let mut bloom_bright_pipeline = create_fullscreen_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "bloom_bright.frag",
+            bloom.downsample_pass(),
+            &post_pipeline_layout,
+            context.pipeline_cache.cache(),
+            pso::BlendState::Off,
+        )?;
+        let mut bloom_downsample_pipeline = create_fullscreen_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "bloom_downsample.frag",
+            bloom.downsample_pass(),
+            &post_pipeline_layout,
+            context.pipeline_cache.cache(),
+            pso::BlendState::Off,
+        )?;
+        // Each mip on the way up is added onto what the way down left in it
+        let mut bloom_upsample_pipeline = create_fullscreen_pipeline::<B>(
+            &context.device,
+            &shaders,
+            "bloom_upsample.frag",
+            bloom.upsample_pass(),
+            &post_pipeline_layout,
+            context.pipeline_cache.cache(),
+            pso::BlendState::ADD,
+        )?;
+        let outline_vertices = upload_buffer(
+            context,
+            &OUTLINE_EDGES,
+            buffer::Usage::VERTEX,
+            MemoryCategory::Meshes,
+        )?;
+
+        let mut framebuffers = Framebuffers::offscreen(
+            context.device.clone(),
+            &render_pass,
+            &swapchain,
+            &framebuffer_attachments(&hdr, &msaa_targets, &depth_images),
+        )?;
+        let mut lighting_framebuffers = Framebuffers::offscreen(
+            context.device.clone(),
+            &lighting_pass,
+            &swapchain,
+            &[hdr.color(), gbuffer.depth()],
+        )?;
+        let mut gbuffer_sets = allocate_gbuffer_sets(
+            &context.device,
+            &mut gbuffer_descriptors,
+            &gbuffer,
+            &deferred_graph,
+            occlusion,
+            swapchain.frame_images().len(),
+        )?;
+        let mut occlusion_sets = allocate_occlusion_sets(
+            &context.device,
+            &mut occlusion_descriptors,
+            &deferred_graph,
+            occlusion,
+            swapchain.frame_images().len(),
+        )?;
+        let mut post_sets = allocate_post_sets(
+            &context.device,
+            &mut post_descriptors,
+            &hdr,
+            &bloom,
+            swapchain.frame_images().len(),
+        )?;
+
+        let mut frame_sync = FrameSync::new(
+            context.device.clone(),
+            &context.queue_group,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        );
+        // Buffers replaced by a new mesh, kept until no frame in flight can be drawing them
+        let mut retired_chunks = RetiredResources::new(frame_sync.frames_in_flight());
+        // Debug lines are written into a ring each frame, which grows if there are more of them
+        // than fit
+        let mut debug_line_ring = StagingRing::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            DEBUG_LINE_RING_SIZE,
+            buffer::Usage::VERTEX,
+        )?;
+        let mut gpu_profiler = GpuProfiler::new(
+            context.device.clone(),
+            &context.adapter,
+            frame_sync.frames_in_flight(),
+        );
+        let camera_uniforms = UniformRing::<B, CameraUniform>::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            &context.adapter,
+            &mut descriptors,
+            0,
+            frame_sync.frames_in_flight(),
+        )?;
+        let light_uniforms = UniformRing::<B, LightsUniform>::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            &context.adapter,
+            &mut lights_descriptors,
+            0,
+            frame_sync.frames_in_flight(),
+        )?;
+        let ssao_uniforms = UniformRing::<B, SsaoUniform>::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            &context.adapter,
+            &mut ssao_descriptors,
+            0,
+            frame_sync.frames_in_flight(),
+        )?;
+        // Every frame's set points at the same atlas, skybox, ripples and environment probe
+        for frame_index in 0..camera_uniforms.frames() {
+            let set = camera_uniforms.set(frame_index);
+            context.device.write_descriptor_sets(vec![
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 1,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Image(atlas.texture.view(), i::Layout::ShaderReadOnlyOptimal)),
+                },
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 2,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Sampler(atlas.texture.sampler())),
+                },
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 5,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Image(skybox.view(), i::Layout::ShaderReadOnlyOptimal)),
+                },
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 6,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Sampler(skybox.sampler())),
+                },
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 7,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Image(ripples.view(), i::Layout::ShaderReadOnlyOptimal)),
+                },
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 8,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Sampler(ripples.sampler())),
+                },
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 9,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Image(probe.view(), i::Layout::ShaderReadOnlyOptimal)),
+                },
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 10,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Sampler(probe.sampler())),
+                },
+            ]);
+        }
+        write_shadow_descriptors(&context.device, &camera_uniforms, &shadow_map);
+        write_reflection_descriptors(&context.device, &camera_uniforms, &reflection);
+        let mut overlay = DebugOverlay::new(context, &swapchain, frame_sync.frames_in_flight())?;
+        let mut hud = Hud::new(context, &swapchain, frame_sync.frames_in_flight(), &atlas.texture)?;
+        let minimap_image = hud.add_image(context, minimap.view(), i::Filter::Linear)?;
+        let mut text = TextRenderer::new(context, &swapchain, frame_sync.frames_in_flight())?;
+        let mut last_timing_report = Instant::now();
+        let mut last_save = Instant::now();
+
+        let mut running = true;
+        let mut recreate_swapchain = false;
+        while running {
+            cpu_profiler.begin_frame();
+            let mut toggle_vsync = false;
+            let mut toggle_fullscreen = false;
+            let mut take_screenshot = false;
+            input.begin_frame();
+            events.poll_with(
+                |event| {
+                    overlay.handle_event(event);
+                    if !input.cursor_grabbed() && overlay.captures(event) {
+                        return false;
+                    }
+                    input.handle_event(event)
+                },
+                |action| match action {
+                    WindowAction::Close => running = false,
+                    WindowAction::Resize => recreate_swapchain = true,
+                    WindowAction::ToggleVsync => toggle_vsync = true,
+                    WindowAction::ToggleFullscreen => toggle_fullscreen = true,
+                    WindowAction::Screenshot => take_screenshot = true,
+                },
+            );
+
+            if toggle_vsync {
+                context.toggle_vsync(&swapchain);
+                recreate_swapchain = true;
+            }
+            if toggle_fullscreen {
+                context.toggle_fullscreen();
+                recreate_swapchain = true;
+            }
+            if context.poll_config() {
+                recreate_swapchain = true;
+            }
+
+            if !shaders.poll_changes().is_empty() {
+                context.wait_idle()?;
+                match create_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                    Shading::Forward,
+                    ViewMode::Normal,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous pipeline: {}", err),
+                }
+                match create_outline_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut outline_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous outline pipeline: {}", err),
+                }
+                match create_shadow_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    shadow_map.render_pass(),
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut shadow_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous shadow pipeline: {}", err),
+                }
+                match create_minimap_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    minimap.render_pass(),
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut minimap_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous minimap pipeline: {}", err),
+                }
+                match create_probe_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    probe.render_pass(),
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut probe_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous probe pipeline: {}", err),
+                }
+                match create_reflection_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    reflection.render_pass(),
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut reflection_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous reflection pipeline: {}", err),
+                }
+                match create_compute_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "cull.comp",
+                    chunk_draws.pipeline_layout(),
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut cull_pipeline, new_pipeline);
+                        context.device.destroy_compute_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous cull pipeline: {}", err),
+                }
+                match create_compute_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "particles.comp",
+                    gpu_particles.pipeline_layout(),
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut particle_pipeline, new_pipeline);
+                        context.device.destroy_compute_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous particle pipeline: {}", err),
+                }
+                match create_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    gbuffer.render_pass(),
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    1,
+                    Shading::Deferred,
+                    ViewMode::Normal,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut gbuffer_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous G-buffer pipeline: {}", err),
+                }
+                match create_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                    Shading::Forward,
+                    ViewMode::Wireframe,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut wireframe_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous wireframe pipeline: {}", err),
+                }
+                match create_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    gbuffer.render_pass(),
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    1,
+                    Shading::Deferred,
+                    ViewMode::Wireframe,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut deferred_wireframe_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous deferred wireframe pipeline: {}", err),
+                }
+                match create_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                    Shading::Forward,
+                    ViewMode::Overdraw,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut overdraw_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous overdraw pipeline: {}", err),
+                }
+                match create_fullscreen_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "deferred.frag",
+                    &lighting_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    pso::BlendState::Off,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut lighting_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous lighting pipeline: {}", err),
+                }
+                match create_compute_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "ssao.comp",
+                    &ssao_pipeline_layout,
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut ssao_pipeline, new_pipeline);
+                        context.device.destroy_compute_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous SSAO pipeline: {}", err),
+                }
+                match create_compute_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "ssao_blur.comp",
+                    &ssao_pipeline_layout,
+                    context.pipeline_cache.cache(),
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut ssao_blur_pipeline, new_pipeline);
+                        context.device.destroy_compute_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous SSAO blur pipeline: {}", err),
+                }
+                match create_fullscreen_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "luminance.frag",
+                    hdr.luminance_pass(),
+                    &post_pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    pso::BlendState::Off,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut luminance_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous luminance pipeline: {}", err),
+                }
+                match create_fullscreen_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "tonemap.frag",
+                    hdr.tonemap_pass(),
+                    &post_pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    pso::BlendState::Off,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut tonemap_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous tonemap pipeline: {}", err),
+                }
+                match create_fullscreen_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "bloom_bright.frag",
+                    bloom.downsample_pass(),
+                    &post_pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    pso::BlendState::Off,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut bloom_bright_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous bloom bright pass pipeline: {}", err),
+                }
+                match create_fullscreen_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "bloom_downsample.frag",
+                    bloom.downsample_pass(),
+                    &post_pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    pso::BlendState::Off,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut bloom_downsample_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous bloom downsample pipeline: {}", err),
+                }
+                match create_fullscreen_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    "bloom_upsample.frag",
+                    bloom.upsample_pass(),
+                    &post_pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    pso::BlendState::ADD,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut bloom_upsample_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous bloom upsample pipeline: {}", err),
+                }
+                match create_outline_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &lighting_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    1,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut deferred_outline_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous deferred outline pipeline: {}", err),
+                }
+                match create_sky_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut sky_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous sky pipeline: {}", err),
+                }
+                match create_sky_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &lighting_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    1,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut deferred_sky_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous deferred sky pipeline: {}", err),
+                }
+                match create_translucent_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut translucent_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous translucent pipeline: {}", err),
+                }
+                match create_translucent_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &lighting_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    1,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut deferred_translucent_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous deferred translucent pipeline: {}", err),
+                }
+                match create_water_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut water_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous water pipeline: {}", err),
+                }
+                match create_water_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &lighting_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    1,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut deferred_water_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous deferred water pipeline: {}", err),
+                }
+                match create_billboard_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut billboard_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous billboard pipeline: {}", err),
+                }
+                match create_billboard_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &lighting_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    1,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut deferred_billboard_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous deferred billboard pipeline: {}", err),
+                }
+                match create_debug_line_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut debug_line_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous debug line pipeline: {}", err),
+                }
+                match create_debug_line_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &lighting_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    1,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut deferred_debug_line_pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous deferred debug line pipeline: {}", err),
+                }
+            }
+
+            if recreate_swapchain {
+                context.recreate_swapchain(&mut swapchain)?;
+                if let Some(ref mut msaa_targets) = msaa_targets {
+                    msaa_targets.recreate(&swapchain)?;
+                }
+                depth_images.recreate(&swapchain)?;
+                hdr.recreate(&swapchain)?;
+                framebuffers.recreate_offscreen(
+                    &render_pass,
+                    &swapchain,
+                    &framebuffer_attachments(&hdr, &msaa_targets, &depth_images),
+                )?;
+                gbuffer.recreate(&swapchain)?;
+                lighting_framebuffers.recreate_offscreen(&lighting_pass, &swapchain, &[hdr.color(), gbuffer.depth()])?;
+                deferred_graph.recreate(&swapchain)?;
+                gbuffer_sets = allocate_gbuffer_sets(
+                    &context.device,
+                    &mut gbuffer_descriptors,
+                    &gbuffer,
+                    &deferred_graph,
+                    occlusion,
+                    swapchain.frame_images().len(),
+                )?;
+                occlusion_sets = allocate_occlusion_sets(
+                    &context.device,
+                    &mut occlusion_descriptors,
+                    &deferred_graph,
+                    occlusion,
+                    swapchain.frame_images().len(),
+                )?;
+                // The number of mips is in the settings too
+                bloom.recreate(&swapchain, context.config.settings().bloom_mips)?;
+                post_sets = allocate_post_sets(
+                    &context.device,
+                    &mut post_descriptors,
+                    &hdr,
+                    &bloom,
+                    swapchain.frame_images().len(),
+                )?;
+                overlay.recreate(&swapchain)?;
+                hud.recreate(&swapchain)?;
+                text.recreate(&swapchain)?;
+                reflection.recreate(&swapchain)?;
+                write_reflection_descriptors(&context.device, &camera_uniforms, &reflection);
+                // The shadow resolution is in the settings, which are what usually bring us here
+                let shadow_resolution = context.config.settings().shadow_resolution;
+                if shadow_resolution != shadow_map.resolution() {
+                    context.wait_idle()?;
+                    shadow_map.set_resolution(shadow_resolution)?;
+                    write_shadow_descriptors(&context.device, &camera_uniforms, &shadow_map);
+                }
+                recreate_swapchain = false;
+            }
+
+            cpu_profiler.begin_scope("simulate");
+            context.update_cursor_grab(input.cursor_grabbed());
+            input.apply_bindings(&context.config.settings().bindings);
+            let seconds = clock.frame_seconds();
+            let frame_seconds = seconds - last_seconds;
+            gamepads.poll(&mut input, context.config.settings(), frame_seconds);
+            last_seconds = seconds;
+            camera.apply_settings(context.config.settings());
+            camera.handle_input(&input);
+            time_of_day.set_day_length(context.config.settings().day_length);
+            let ticks = timestep.advance_to(seconds);
+            for _ in 0..ticks {
+                camera.update(&input, TICK_SECONDS);
+                time_of_day.advance(TICK_SECONDS);
+                spawn_ambient_particles(&mut particles, &world, camera.position(), dust_uv, leaf_uv);
+                snow_due += context.config.settings().snowfall as f32 * TICK_SECONDS;
+                let flakes = snow_due.floor();
+                snow_due -= flakes;
+                spawn_snow(&mut particles, &world, camera.position(), dust_uv, flakes as u32);
+            }
+
+            // The wheel zooms the orbit camera, so it only picks blocks with the FPS one.
+            // Towards the screen is the next block.
+            if !camera.is_orbiting() {
+                block_scroll -= input.wheel_lines();
+            }
+            let scrolled = block_scroll.trunc();
+            block_scroll -= scrolled;
+            let step = scrolled as i32 + input.was_pressed(Action::NextBlock) as i32
+                - input.was_pressed(Action::PreviousBlock) as i32;
+            if step != 0 {
+                let count = PLACEABLE.len() as i32;
+                selected_block = ((selected_block as i32 + step) % count + count) as usize % PLACEABLE.len();
+                debug!("Placing {}", PLACEABLE[selected_block].1);
+            }
+            // The number keys pick a slot of the hotbar straight off
+            for slot in 0..PLACEABLE.len() {
+                if input.was_pressed(Action::SelectSlot(slot)) {
+                    selected_block = slot;
+                    debug!("Placing {}", PLACEABLE[selected_block].1);
+                }
+            }
+
+            // Break or place the block the camera is pointing at. `set_block` marks the chunks
+            // it touches for remeshing, and for saving with the rest of the world. A block put
+            // where the camera is would leave it looking out from inside it, so that's skipped.
+            let mut target = raycast(&world, camera.position(), camera.look_direction(), REACH);
+            let position = camera.position();
+            let camera_block = [position[0].floor() as i32, position[1].floor() as i32, position[2].floor() as i32];
+            let edit = match target {
+                Some(hit) if input.was_pressed(Action::BreakBlock) => Some((hit.position, BlockId::AIR)),
+                Some(hit) if input.was_pressed(Action::PlaceBlock) => hit
+                    .adjacent()
+                    .filter(|&place| place != camera_block)
+                    .map(|place| (place, PLACEABLE[selected_block].0)),
+                _ => None,
+            };
+            if let Some((block_position, block)) = edit {
+                // A broken block bursts into pieces of itself, lit by the light in front of it
+                if let Some(hit) = target.filter(|_| block.is_air()) {
+                    let uv = atlas.uv(textures.get(hit.block, hit.face.unwrap_or(Direction::PosY)));
+                    let light = sprite_light(&world, hit.adjacent().unwrap_or(hit.position));
+                    particles.break_block(hit.position, uv, light);
+                }
+                world.set_block(block_position, block);
+                // Outline what's there now rather than what was
+                target = raycast(&world, camera.position(), camera.look_direction(), REACH);
+            }
+            // Everything spawned goes to the gpu, landing on whatever's under it now
+            for particle in particles.drain() {
+                gpu_particles.spawn(&particle, particle_ground(&world, particle.position.current()));
+            }
+            cpu_profiler.end_scope();
+
+            // Load the chunks that have come into range, nearest first, and unload the ones
+            // that have fallen out of it along with their buffers
+            cpu_profiler.begin_scope("streaming");
+            loader.set_render_distance(context.config.settings().render_distance);
+            for coord in loader.update(&world, ChunkCoord::of_point(camera.position())) {
+                if let Some(ref mut store) = store {
+                    if world.is_unsaved(coord) {
+                        store.save_chunk(&world, &pending_edits, coord)?;
+                    }
+                }
+                world.remove_chunk(coord);
+                workers.cancel(coord);
+                lods.remove(coord);
+                point_lights.remove_chunk(coord);
+                if let Some(old) = chunks.remove(&coord) {
+                    retired_chunks.retire(old);
+                }
+            }
+            let load_start = Instant::now();
+            let load_budget = Duration::from_millis(LOAD_BUDGET_MILLISECONDS);
+            while context.is_headless() || load_start.elapsed() < load_budget {
+                match loader.next(&world) {
+                    Some(coord) => {
+                        let loaded = match store {
+                            Some(ref mut store) => store.load_into(&mut world, &mut pending_edits, coord)?,
+                            None => false,
+                        };
+                        if !loaded {
+                            let unloaded = generator.generate_into(&mut world, &mut pending_edits, coord);
+                            if let Some(ref mut store) = store {
+                                store.apply_to_saved(&unloaded)?;
+                            }
+                        }
+                        // Lighting a chunk takes longer than generating it, so it comes out of
+                        // the budget too
+                        lighting.update(&mut world);
+                    }
+                    None => break,
+                }
+            }
+            if let Some(ref mut store) = store {
+                let interval = Duration::from_millis((context.config.settings().save_interval * 1000.0) as u64);
+                if last_save.elapsed() >= interval {
+                    let saved = store.save_unsaved(&mut world, &pending_edits)?;
+                    store.flush()?;
+                    debug!("Saved {} chunks", saved);
+                    last_save = Instant::now();
+                }
+            }
+            // Relight around the block that was broken or placed, if there was one, before the
+            // chunks it marked dirty are meshed
+            lighting.update(&mut world);
+            cpu_profiler.end_scope();
+
+            // Switch meshers if the settings file, the overlay or a key asked for it. The old
+            // meshes are drawn until the new ones replace them.
+            if context.config.settings().mesher != settings_mesher {
+                settings_mesher = context.config.settings().mesher;
+                mesher = settings_mesher;
+            }
+            if input.was_pressed(Action::SwitchMesher) {
+                mesher = mesher.next();
+            }
+            if input.was_pressed(Action::CycleViewMode) {
+                view_mode = view_mode.next();
+                info!("Drawing the {} view", view_mode);
+            }
+            if context.config.settings().shading != settings_shading {
+                settings_shading = context.config.settings().shading;
+                shading = settings_shading;
+            }
+            let mut to_mesh = if mesher != meshed_with {
+                remesh = Some(Remesh::new(mesher));
+                meshed_with = mesher;
+                world.take_dirty();
+                world.chunks().map(|(coord, _)| coord).collect()
+            } else {
+                world.take_dirty()
+            };
+            // Chunks that have moved to another level of detail, or next to one, are meshed
+            // again as well
+            let lod_center = ChunkCoord::of_point(camera.position());
+            for coord in lods.update(lod_center, context.config.settings().lod_distance) {
+                if !to_mesh.contains(&coord) {
+                    to_mesh.push(coord);
+                }
+            }
+            // Anything that needs remeshing might have had a lamp or torch put down or broken
+            for coord in to_mesh {
+                point_lights.update_chunk(&world, coord);
+                workers.submit(&world, coord, mesher, lods.mesh(coord));
+            }
+
+            cpu_profiler.begin_scope("wait");
+            let frames_in_flight = frame_sync.frames_in_flight();
+            let mut frame = frame_sync.begin_frame()?;
+            retired_chunks.begin_frame();
+            debug_line_ring.begin_frame(frames_in_flight);
+            if let Some(ref mut transfer) = context.transfer {
+                transfer.begin_frame(frames_in_flight);
+            }
+            cpu_profiler.end_scope();
+
+            // Headless runs wait for the workers, so the frames they save don't depend on how
+            // fast those were
+            cpu_profiler.begin_scope("upload");
+            let meshed = if context.is_headless() { workers.wait() } else { workers.poll() };
+            for meshed in meshed {
+                if let Some(ref mut remesh) = remesh {
+                    remesh.add(&meshed);
+                }
+                let old = if meshed.mesh.is_empty() {
+                    chunks.remove(&meshed.coord)
+                } else {
+                    let buffers = upload_chunk(context, &mut chunk_draws, meshed.coord, &meshed.mesh)?;
+                    chunks.insert(meshed.coord, buffers)
+                };
+                if let Some(old) = old {
+                    retired_chunks.retire(old);
+                }
+            }
+            if workers.pending_count() == 0 && loader.queued() == 0 {
+                if let Some(remesh) = remesh.take() {
+                    remesh.report(&chunks);
+                    info!("Gpu memory: {}", context.allocator.borrow().stats());
+                    info!("Holding on to {} decoration edits for chunks around the loaded ones", pending_edits.edit_count());
+                }
+            }
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("acquire");
+            let image_index = match swapchain.acquire_image(&mut frame, &mut context.queue_group.queues[0]) {
+                Ok(image_index) => image_index,
+                Err(_) => {
+                    recreate_swapchain = true;
+                    continue;
+                }
+            };
+            cpu_profiler.end_scope();
+
+            let viewport = swapchain.viewport();
+
+            let vsync = present::is_vsync(swapchain.present_mode());
+            let mut overlay_settings = OverlaySettings {
+                vsync,
+                wireframe: None,
+                shadows: Some(shadows_enabled),
+                show_cascades: Some(show_cascades),
+                occlusion_culling: None,
+                mesher: Some(mesher),
+                shading: Some(shading),
+                view_mode: Some(view_mode),
+                debug_lines: Some(debug_line_settings),
+                time_of_day: Some(time_of_day),
+            };
+
+            // What the last time this frame came round measured of the scene. The fence has been
+            // waited on, so it's all there.
+            let (culling, triangles) = chunk_draws.read_stats(frame.index)?.unwrap_or_default();
+            let alive_particles = gpu_particles.read_alive(frame.index)?;
+            if context.config.settings().auto_exposure && view_mode.is_lit() {
+                if let Some(tiles) = hdr.read_luminance(frame.index)? {
+                    if let Some(luminance) = average_luminance(&tiles) {
+                        auto_exposure.adapt(luminance, frame_seconds);
+                    }
+                }
+            }
+
+            cpu_profiler.begin_scope("record");
+            // The timestamps are reset ahead of everything else on the graphics queue, since the
+            // frame graph can submit its passes in command buffers of their own
+            let finished_start_buffer = {
+                let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+                gpu_profiler.begin_frame(&mut command_buffer, frame.index)?;
+                command_buffer.finish()
+            };
+            context.queue_group.queues[0].submit(Submission::new().submit(Some(finished_start_buffer)), None);
+
+            {
+                // Forward shading lights the chunks as they're drawn. Deferred shading draws
+                // them into the G-buffer instead, and lights that in a pass of its own. The
+                // overdraw view is always forward shaded, and the debug views draw the translucent
+                // blocks and the water along with everything else.
+                let shading_now = if view_mode == ViewMode::Overdraw { Shading::Forward } else { shading };
+                let (frame_graph, passes) = match shading_now {
+                    Shading::Forward => (&mut forward_graph, forward_passes),
+                    Shading::Deferred => (&mut deferred_graph, deferred_passes),
+                };
+                // Chunks uploaded this frame are copied in on the transfer queue, if there is one,
+                // and the frame waits for that before it reads any vertices
+                let mut upload_waits = Vec::new();
+                if let Some(uploaded) = context.transfer.as_mut().and_then(|transfer| transfer.submit()) {
+                    upload_waits.push((uploaded, PipelineStage::VERTEX_INPUT));
+                }
+
+                let (finished_command_buffer, graph_waits) = {
+                    let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+
+                    let alpha = timestep.alpha();
+                    let extent = swapchain.extent();
+                    let aspect = extent.width as f32 / extent.height as f32;
+                    let sun_direction = time_of_day.sun_direction();
+                    let sun_strength = time_of_day.sun_strength();
+                    let view = camera.interpolated_view(alpha);
+                    let cascades = fit_cascades(
+                        sun_direction,
+                        &view,
+                        &camera.projection(),
+                        aspect,
+                        context.config.settings().shadow_distance,
+                        shadow_map.resolution(),
+                    );
+                    let mut shadow_view_projections = [[[0.0; 4]; 4]; CASCADE_COUNT];
+                    let mut cascade_splits = [0.0; CASCADE_COUNT];
+                    for (index, cascade) in cascades.iter().enumerate() {
+                        shadow_view_projections[index] = cascade.view_projection.into();
+                        cascade_splits[index] = cascade.far;
+                    }
+                    let view_projection = camera.interpolated_view_projection(aspect, alpha);
+                    // The fog is measured from wherever the interpolated view is from, and is as
+                    // thick around the camera as it is at the camera's height
+                    let eye: [f32; 3] = view.invert().map_or(camera.position(), |inverse| inverse.w.truncate().into());
+                    let fog_height_falloff = context.config.settings().fog_height_falloff;
+                    let fog_density = context.config.settings().fog_density
+                        * (-fog_height_falloff * (eye[1] - FOG_BASE_HEIGHT)).exp();
+                    let sky_color = time_of_day.sky_color();
+                    let (fog_color, fog_sun_color) =
+                        horizon_colors(context.config.settings().sky, sun_direction, sky_color);
+                    let minimap_view = minimap.next_view(eye);
+                    let probe_faces = probe.next_faces(eye, context.config.settings().dynamic_reflections);
+                    // The planar reflection is only drawn when the water can be seen from above
+                    let reflections = context.config.settings().reflections;
+                    let reflection_view = if reflections == Reflections::Planar && eye[1] > WATER_HEIGHT {
+                        Some(view_projection * mirror(WATER_HEIGHT))
+                    } else {
+                        None
+                    };
+                    let mut probe_view_projections = [[[0.0; 4]; 4]; CUBE_FACES as usize];
+                    for (to, from) in probe_view_projections.iter_mut().zip(probe.view_projections().iter()) {
+                        *to = (*from).into();
+                    }
+                    camera_uniforms.update(
+                        frame.index,
+                        &CameraUniform {
+                            view_projection: view_projection.into(),
+                            inverse_view_projection: view_projection.invert().unwrap_or(view_projection).into(),
+                            shadow_view_projections,
+                            cascade_splits,
+                            view_depth: (-view.row(2)).into(),
+                            atlas_origin: grid.origin,
+                            atlas_cell: grid.cell,
+                            atlas_tile_size: grid.size,
+                            atlas_columns: grid.columns,
+                            _padding: 0,
+                            sun: [
+                                sun_direction[0] * sun_strength,
+                                sun_direction[1] * sun_strength,
+                                sun_direction[2] * sun_strength,
+                            ],
+                            daylight: time_of_day.daylight(),
+                            ambient: time_of_day.ambient(),
+                            show_cascades: show_cascades as u32,
+                            _sun_padding: [0; 2],
+                            sun_direction,
+                            sky: context.config.settings().sky.id(),
+                            sky_color,
+                            fog_density,
+                            eye,
+                            fog_height_falloff,
+                            fog_color,
+                            fog_end: (context.config.settings().render_distance * CHUNK_SIZE as u32) as f32,
+                            fog_sun_color,
+                            time: seconds,
+                            view_mode: view_mode.id(),
+                            _view_mode_padding: [0; 3],
+                            // The view matrix's rows are the camera's axes
+                            camera_right: [view.x.x, view.y.x, view.z.x],
+                            _right_padding: 0,
+                            camera_up: [view.x.y, view.y.y, view.z.y],
+                            _up_padding: 0,
+                            // Only read on the frames the map is drawn in
+                            minimap_view_projection: minimap_view.unwrap_or(view_projection).into(),
+                            probe_view_projections,
+                            polished_tiles,
+                            // Only read when the reflection is drawn
+                            reflection_view_projection: reflection_view.unwrap_or(view_projection).into(),
+                            water_height: WATER_HEIGHT,
+                            // The probe stands in for the reflection whenever it isn't drawn
+                            reflections: if reflection_view.is_some() {
+                                Reflections::Planar
+                            } else {
+                                Reflections::Probe
+                            }
+                            .id(),
+                            _reflection_padding: [0; 2],
+                        },
+                    )?;
+                    let nearest_lights =
+                        point_lights.nearest(camera.position(), POINT_LIGHT_DISTANCE, MAX_POINT_LIGHTS);
+                    let mut lights = LightsUniform {
+                        count: nearest_lights.len() as u32,
+                        _padding: [0; 3],
+                        lights: [ShaderPointLight::default(); MAX_POINT_LIGHTS],
+                    };
+                    for (slot, &light) in lights.lights.iter_mut().zip(nearest_lights.iter()) {
+                        *slot = light.into();
+                    }
+                    light_uniforms.update(frame.index, &lights)?;
+
+                    // The block about to be placed hovers where it would go, as a see-through icon
+                    // of its side, lit by the light that's there
+                    let mut sprites = Vec::new();
+                    if let Some(place) = target.and_then(|hit| hit.adjacent()) {
+                        let block = PLACEABLE[selected_block].0;
+                        let middle = [place[0] as f32 + 0.5, place[1] as f32 + 0.5, place[2] as f32 + 0.5];
+                        let uv = atlas.uv(textures.get(block, Direction::PosX));
+                        let mut icon = Sprite::new(middle, PLACEMENT_ICON_SIZE, uv);
+                        icon.color[3] = PLACEMENT_ICON_ALPHA;
+                        icon.light = sprite_light(&world, place);
+                        sprites.push(icon);
+                    }
+                    billboards.write(&mut sprites, eye)?;
+                    let ssao_samples = (context.config.settings().ssao_samples as usize).min(MAX_SSAO_SAMPLES);
+                    if ssao_samples != ssao_kernel.len() {
+                        ssao_kernel = hemisphere_kernel(ssao_samples);
+                    }
+                    let projection = camera.projection();
+                    let mut ssao_settings = SsaoUniform {
+                        kernel: [[0.0; 4]; MAX_SSAO_SAMPLES],
+                        sample_count: ssao_kernel.len() as u32,
+                        radius: context.config.settings().ssao_radius,
+                        blur_radius: context.config.settings().ssao_blur_radius,
+                        near: projection.near,
+                        far: projection.far,
+                    };
+                    ssao_settings.kernel[..ssao_kernel.len()].copy_from_slice(&ssao_kernel);
+                    ssao_uniforms.update(frame.index, &ssao_settings)?;
+
+                    // Loaded chunks that are neither being meshed nor have a mesh are empty, and
+                    // get no box. The ones with a mesh are boxed by its bounds, the rest by all of
+                    // the space they take up.
+                    debug_lines.clear();
+                    if debug_line_settings.chunk_borders {
+                        debug_lines.chunk_column(ChunkCoord::of_point(eye), 0..HEIGHT_IN_CHUNKS, BORDER_COLOR);
+                    }
+                    if debug_line_settings.chunk_bounds {
+                        for &coord in loader.queued_chunks() {
+                            if !world.contains_chunk(coord) {
+                                debug_lines.aabb(&chunk_aabb(coord), ChunkState::Loading.color());
+                            }
+                        }
+                        for (coord, _) in world.chunks() {
+                            let state = if workers.is_pending(coord) {
+                                ChunkState::Meshing
+                            } else if chunks.contains_key(&coord) {
+                                ChunkState::Resident
+                            } else {
+                                continue;
+                            };
+                            let bounds = chunks.get(&coord).map_or_else(|| chunk_aabb(coord), |chunk| chunk.bounds);
+                            debug_lines.aabb(&bounds, state.color());
+                        }
+                    }
+                    if !debug_line_settings.frustum {
+                        frozen_frustum = None;
+                    } else if frozen_frustum.is_none() {
+                        frozen_frustum = frustum_corners(&view_projection, HAL_CLIP_SPACE);
+                    }
+                    if let Some(ref corners) = frozen_frustum {
+                        debug_lines.cuboid(corners, FRUSTUM_COLOR);
+                    }
+                    let debug_line_count = debug_lines.vertices().len() as u32;
+                    let debug_line_offset = if debug_lines.is_empty() {
+                        None
+                    } else {
+                        Some(debug_line_ring.write(debug_lines.vertices())?)
+                    };
+                    // Cleared to the colour of the sky at this time of day, though the sky pass
+                    // draws over whatever the chunks leave of it. The overdraw view counts up from
+                    // nothing instead.
+                    let color_clear = command::ClearValue::Color(command::ClearColor::Float(
+                        if view_mode == ViewMode::Overdraw {
+                            [0.0, 0.0, 0.0, 1.0]
+                        } else {
+                            [sky_color[0], sky_color[1], sky_color[2], 1.0]
+                        },
+                    ));
+                    let depth_clear = command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0));
+                    let clear_values = if samples > 1 {
+                        vec![color_clear.clone(), color_clear.clone(), depth_clear.clone()]
+                    } else {
+                        vec![color_clear.clone(), depth_clear.clone()]
+                    };
+                    let frustum = camera.frustum(aspect, alpha);
+                    let cascade_frusta: Vec<_> = if shadows_enabled && sun_strength > 0.0 {
+                        cascades
+                            .iter()
+                            .map(|cascade| Frustum::from_matrix(&cascade.view_projection, HAL_CLIP_SPACE))
+                            .collect()
+                    } else {
+                        Vec::new()
+                    };
+
+                    // Furthest first, so nearer chunks blend over the ones behind them. The culling
+                    // keeps that order for the translucent blocks and the water. Faces within a
+                    // chunk aren't sorted, which is mostly fine since only the outsides of glass
+                    // and water are meshed.
+                    let from_eye = |chunk: &ChunkBuffers| {
+                        let center = chunk.bounds.center();
+                        let offset = [center[0] - eye[0], center[1] - eye[1], center[2] - eye[2]];
+                        offset[0] * offset[0] + offset[1] * offset[1] + offset[2] * offset[2]
+                    };
+                    let mut sorted: Vec<_> = chunks.values().collect();
+                    sorted.sort_by(|a, b| from_eye(b).partial_cmp(&from_eye(a)).unwrap());
+                    let order: Vec<u32> = sorted.iter().map(|chunk| chunk.allocation.slot()).collect();
+
+                    // Every chunk is tested against the camera, each cascade and, when they're
+                    // due, the minimap, the environment probe and the planar reflection on the gpu,
+                    // which writes the draws for the passes after
+                    let minimap_frustum =
+                        minimap_view.map(|view_projection| Frustum::from_matrix(&view_projection, HAL_CLIP_SPACE));
+                    let probe_frustum = if probe_faces.end > probe_faces.start { Some(probe.frustum()) } else { None };
+                    let reflection_frustum =
+                        reflection_view.map(|view_projection| Frustum::from_matrix(&view_projection, HAL_CLIP_SPACE));
+                    let blend = view_mode == ViewMode::Normal;
+                    let frame_index = frame.index;
+                    let image = image_index as usize;
+
+                    // The frame graph records its passes in order. With a compute queue, the
+                    // culling, the particles and the ambient occlusion are submitted to that, so
+                    // the occlusion is worked out while the views are drawn. Whatever goes in
+                    // `command_buffer` after the graph comes after all of it.
+                    let graph_waits = frame_graph.execute(
+                        &mut context.queue_group,
+                        context.compute_queue_group.as_mut(),
+                        frame_index,
+                        image_index,
+                        &upload_waits,
+                        &mut command_buffer,
+                        |pass, mut commands| {
+                            if let Some(command_buffer) = commands.graphics() {
+                                command_buffer.bind_graphics_descriptor_sets(
+                                    &pipeline_layout,
+                                    0,
+                                    vec![
+                                        camera_uniforms.set(frame_index),
+                                        light_uniforms.set(frame_index),
+                                        &gbuffer_sets[image],
+                                        ssao_uniforms.set(frame_index),
+                                    ],
+                                    &[],
+                                );
+                            }
+
+                            let compute_scope = if pass == passes.cull {
+                                Some("cull")
+                            } else if pass == passes.particles {
+                                Some("particles")
+                            } else if Some(pass) == passes.ssao {
+                                Some("ssao")
+                            } else if Some(pass) == passes.ssao_blur {
+                                Some("ssao blur")
+                            } else {
+                                None
+                            };
+                            if let Some(scope) = compute_scope {
+                                // The timestamps are only written on the graphics queue, so these
+                                // are only timed when there's no compute queue to put them on
+                                if let Some(command_buffer) = commands.graphics() {
+                                    gpu_profiler.begin_scope(command_buffer, scope);
+                                }
+                                {
+                                    let command_buffer = commands.compute();
+                                    if pass == passes.cull {
+                                        chunk_draws.cull(
+                                            command_buffer,
+                                            &cull_pipeline,
+                                            frame_index,
+                                            &order,
+                                            &frustum,
+                                            &cascade_frusta,
+                                            minimap_frustum.as_ref(),
+                                            probe_frustum.as_ref(),
+                                            reflection_frustum.as_ref(),
+                                        )?;
+                                    } else if pass == passes.particles {
+                                        // The particles move on by however many ticks went by,
+                                        // before anything draws them
+                                        gpu_particles.simulate(
+                                            command_buffer,
+                                            &particle_pipeline,
+                                            frame_index,
+                                            ticks,
+                                            TICK_SECONDS,
+                                            alpha,
+                                        )?;
+                                    } else {
+                                        // Ambient occlusion is worked out from the G-buffer's depth
+                                        // and normals, then blurred, for the lighting pass to
+                                        // darken the ambient light by. With no samples it all comes
+                                        // out unoccluded.
+                                        let (pipeline, occlusion_set) = if Some(pass) == passes.ssao {
+                                            (&ssao_pipeline, &occlusion_sets[image].occlusion)
+                                        } else {
+                                            (&ssao_blur_pipeline, &occlusion_sets[image].blurred)
+                                        };
+                                        command_buffer.bind_compute_pipeline(pipeline);
+                                        command_buffer.bind_compute_descriptor_sets(
+                                            &ssao_pipeline_layout,
+                                            0,
+                                            vec![
+                                                camera_uniforms.set(frame_index),
+                                                light_uniforms.set(frame_index),
+                                                &gbuffer_sets[image],
+                                                ssao_uniforms.set(frame_index),
+                                                occlusion_set,
+                                            ],
+                                            &[],
+                                        );
+                                        command_buffer.dispatch([
+                                            (extent.width + SSAO_GROUP_SIZE - 1) / SSAO_GROUP_SIZE,
+                                            (extent.height + SSAO_GROUP_SIZE - 1) / SSAO_GROUP_SIZE,
+                                            1,
+                                        ]);
+                                    }
+                                }
+                                if let Some(command_buffer) = commands.graphics() {
+                                    gpu_profiler.end_scope(command_buffer);
+                                }
+                                return Ok(());
+                            }
+
+                            // The rest draw in render passes of their own, on the graphics queue
+                            let command_buffer = commands.graphics().unwrap();
+                            if pass == passes.views {
+                                // Each cascade's map gets the chunks the sun can see in it. They're
+                                // still cleared when there's nothing to draw, with shadows turned
+                                // off or the sun down, so that everything is lit by whatever sun
+                                // there is.
+                                gpu_profiler.begin_scope(command_buffer, "shadows");
+                                let shadow_viewport = shadow_map.viewport();
+                                command_buffer.set_viewports(0, &[shadow_viewport.clone()]);
+                                command_buffer.set_scissors(0, &[shadow_viewport.rect]);
+                                for (index, cascade) in cascades.iter().enumerate() {
+                                    let mut encoder = command_buffer.begin_render_pass_inline(
+                                        shadow_map.render_pass(),
+                                        shadow_map.framebuffer(index),
+                                        shadow_viewport.rect,
+                                        &[command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0))],
+                                    );
+                                    if !shadows_enabled || sun_strength <= 0.0 {
+                                        continue;
+                                    }
+
+                                    let push_constants = PushConstants { block_origin: [0.0; 3], layer: index as u32 };
+                                    encoder.bind_graphics_pipeline(&shadow_pipeline);
+                                    encoder.push_graphics_constants(
+                                        &pipeline_layout,
+                                        pso::ShaderStageFlags::VERTEX,
+                                        0,
+                                        push_constants.as_words(),
+                                    );
+                                    chunk_draws.draw(&mut encoder, frame_index, DrawList::Shadow(index));
+                                }
+                                gpu_profiler.end_scope(command_buffer);
+
+                                // Every few frames the map is drawn again, from above, for the HUD
+                                // to show
+                                if minimap_view.is_some() {
+                                    gpu_profiler.begin_scope(command_buffer, "minimap");
+                                    let minimap_viewport = minimap.viewport();
+                                    command_buffer.set_viewports(0, &[minimap_viewport.clone()]);
+                                    command_buffer.set_scissors(0, &[minimap_viewport.rect]);
+                                    {
+                                        let mut encoder = command_buffer.begin_render_pass_inline(
+                                            minimap.render_pass(),
+                                            minimap.framebuffer(),
+                                            minimap_viewport.rect,
+                                            &[
+                                                command::ClearValue::Color(command::ClearColor::Float([
+                                                    0.0, 0.0, 0.0, 1.0,
+                                                ])),
+                                                command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0)),
+                                            ],
+                                        );
+                                        encoder.bind_graphics_pipeline(&minimap_pipeline);
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Minimap);
+                                    }
+                                    gpu_profiler.end_scope(command_buffer);
+                                }
+
+                                // So are whichever faces of the environment probe are due, for the
+                                // water and the glass to reflect. They're cleared to nothing, so
+                                // the sky shows through where there's no terrain.
+                                if probe_faces.end > probe_faces.start {
+                                    gpu_profiler.begin_scope(command_buffer, "probe");
+                                    let probe_viewport = probe.viewport();
+                                    command_buffer.set_viewports(0, &[probe_viewport.clone()]);
+                                    command_buffer.set_scissors(0, &[probe_viewport.rect]);
+                                    for face in probe_faces.clone() {
+                                        let mut encoder = command_buffer.begin_render_pass_inline(
+                                            probe.render_pass(),
+                                            probe.framebuffer(face),
+                                            probe_viewport.rect,
+                                            &[
+                                                command::ClearValue::Color(command::ClearColor::Float([0.0; 4])),
+                                                command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0)),
+                                            ],
+                                        );
+                                        let push_constants = PushConstants { block_origin: [0.0; 3], layer: face };
+                                        encoder.bind_graphics_pipeline(&probe_pipeline);
+                                        encoder.push_graphics_constants(
+                                            &pipeline_layout,
+                                            pso::ShaderStageFlags::VERTEX,
+                                            0,
+                                            push_constants.as_words(),
+                                        );
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Probe);
+                                    }
+                                    gpu_profiler.end_scope(command_buffer);
+                                }
+
+                                // And the planar reflection, when the water uses it, cleared to
+                                // nothing like the probe is
+                                if reflection_view.is_some() {
+                                    gpu_profiler.begin_scope(command_buffer, "reflection");
+                                    let reflection_viewport = reflection.viewport();
+                                    command_buffer.set_viewports(0, &[reflection_viewport.clone()]);
+                                    command_buffer.set_scissors(0, &[reflection_viewport.rect]);
+                                    {
+                                        let mut encoder = command_buffer.begin_render_pass_inline(
+                                            reflection.render_pass(),
+                                            reflection.framebuffer(),
+                                            reflection_viewport.rect,
+                                            &[
+                                                command::ClearValue::Color(command::ClearColor::Float([0.0; 4])),
+                                                command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0)),
+                                            ],
+                                        );
+                                        encoder.bind_graphics_pipeline(&reflection_pipeline);
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Reflection);
+                                    }
+                                    gpu_profiler.end_scope(command_buffer);
+                                }
+                            } else if pass == passes.scene {
+                                command_buffer.set_viewports(0, &[viewport.clone()]);
+                                command_buffer.set_scissors(0, &[viewport.rect]);
+                                let gbuffer_clears = [
+                                    command::ClearValue::Color(command::ClearColor::Float([0.0; 4])),
+                                    command::ClearValue::Color(command::ClearColor::Float([0.0; 4])),
+                                    command::ClearValue::Color(command::ClearColor::Float([0.0; 4])),
+                                    depth_clear.clone(),
+                                ];
+                                let (chunk_pass, chunk_framebuffer, chunk_clears, scope) = match shading_now {
+                                    Shading::Forward => {
+                                        (&render_pass, framebuffers.get(image_index), &clear_values[..], "opaque")
+                                    }
+                                    Shading::Deferred => (
+                                        gbuffer.render_pass(),
+                                        gbuffer.framebuffer(image_index),
+                                        &gbuffer_clears[..],
+                                        "gbuffer",
+                                    ),
+                                };
+                                let chunk_pipeline = match (shading_now, view_mode) {
+                                    (Shading::Forward, ViewMode::Wireframe) => &wireframe_pipeline,
+                                    (Shading::Deferred, ViewMode::Wireframe) => &deferred_wireframe_pipeline,
+                                    (_, ViewMode::Overdraw) => &overdraw_pipeline,
+                                    (Shading::Forward, _) => &pipeline,
+                                    (Shading::Deferred, _) => &gbuffer_pipeline,
+                                };
+                                command_buffer.bind_graphics_pipeline(chunk_pipeline);
+
+                                gpu_profiler.begin_scope(command_buffer, scope);
+                                {
+                                    let mut encoder = command_buffer.begin_render_pass_inline(
+                                        chunk_pass,
+                                        chunk_framebuffer,
+                                        viewport.rect,
+                                        chunk_clears,
+                                    );
+
+                                    chunk_draws.draw(&mut encoder, frame_index, DrawList::Opaque);
+                                    if !blend {
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Translucent);
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Water);
+                                    }
+
+                                    // The sky goes after the chunks as well, so it's only drawn
+                                    // where they weren't
+                                    if shading_now == Shading::Forward && view_mode != ViewMode::Overdraw {
+                                        encoder.bind_graphics_pipeline(&sky_pipeline);
+                                        encoder.draw(0..3, 0..1);
+                                    }
+
+                                    // Then the translucent blocks, the water and the billboards,
+                                    // blended over whatever's behind them. All of the translucent
+                                    // blocks go before any of the water.
+                                    if shading_now == Shading::Forward && blend {
+                                        encoder.bind_graphics_pipeline(&translucent_pipeline);
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Translucent);
+                                        encoder.bind_graphics_pipeline(&water_pipeline);
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Water);
+                                        encoder.bind_graphics_pipeline(&billboard_pipeline);
+                                        billboards.draw(&mut encoder);
+                                        gpu_particles.draw(&mut encoder, frame_index);
+                                    }
+
+                                    // The outline goes after the chunks, so it's depth tested
+                                    // against them
+                                    if let (Shading::Forward, Some(hit)) = (shading_now, target) {
+                                        let origin = hit.position;
+                                        let push_constants = PushConstants {
+                                            block_origin: [origin[0] as f32, origin[1] as f32, origin[2] as f32],
+                                            layer: 0,
+                                        };
+                                        encoder.bind_graphics_pipeline(&outline_pipeline);
+                                        encoder.push_graphics_constants(
+                                            &pipeline_layout,
+                                            pso::ShaderStageFlags::VERTEX,
+                                            0,
+                                            push_constants.as_words(),
+                                        );
+                                        encoder.bind_vertex_buffers(0, pso::VertexBufferSet(vec![
+                                            (outline_vertices.buffer(), 0),
+                                        ]));
+                                        encoder.draw(0..OUTLINE_EDGES.len() as u32, 0..1);
+                                    }
+
+                                    if let (Shading::Forward, Some(offset)) = (shading_now, debug_line_offset) {
+                                        encoder.bind_graphics_pipeline(&debug_line_pipeline);
+                                        encoder.bind_vertex_buffers(0, pso::VertexBufferSet(vec![
+                                            (debug_line_ring.buffer(), offset),
+                                        ]));
+                                        encoder.draw(0..debug_line_count, 0..1);
+                                    }
+                                }
+                                gpu_profiler.end_scope(command_buffer);
+                            } else if Some(pass) == passes.lighting {
+                                // Each pixel of the G-buffer is lit once, by a triangle covering
+                                // the screen. Wherever nothing was drawn is left for the sky.
+                                command_buffer.set_viewports(0, &[viewport.clone()]);
+                                command_buffer.set_scissors(0, &[viewport.rect]);
+                                gpu_profiler.begin_scope(command_buffer, "lighting");
+                                {
+                                    let mut encoder = command_buffer.begin_render_pass_inline(
+                                        &lighting_pass,
+                                        lighting_framebuffers.get(image_index),
+                                        viewport.rect,
+                                        &[color_clear.clone()],
+                                    );
+                                    encoder.bind_graphics_pipeline(&lighting_pipeline);
+                                    encoder.draw(0..3, 0..1);
+
+                                    // The G-buffer's depth is still attached to test the sky, the
+                                    // translucent blocks, the water, the billboards and the outline
+                                    // against
+                                    encoder.bind_graphics_pipeline(&deferred_sky_pipeline);
+                                    encoder.draw(0..3, 0..1);
+                                    if blend {
+                                        encoder.bind_graphics_pipeline(&deferred_translucent_pipeline);
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Translucent);
+                                        encoder.bind_graphics_pipeline(&deferred_water_pipeline);
+                                        chunk_draws.draw(&mut encoder, frame_index, DrawList::Water);
+                                        encoder.bind_graphics_pipeline(&deferred_billboard_pipeline);
+                                        billboards.draw(&mut encoder);
+                                        gpu_particles.draw(&mut encoder, frame_index);
+                                    }
+                                    if let Some(hit) = target {
+                                        let origin = hit.position;
+                                        let push_constants = PushConstants {
+                                            block_origin: [origin[0] as f32, origin[1] as f32, origin[2] as f32],
+                                            layer: 0,
+                                        };
+                                        encoder.bind_graphics_pipeline(&deferred_outline_pipeline);
+                                        encoder.push_graphics_constants(
+                                            &pipeline_layout,
+                                            pso::ShaderStageFlags::VERTEX,
+                                            0,
+                                            push_constants.as_words(),
+                                        );
+                                        encoder.bind_vertex_buffers(0, pso::VertexBufferSet(vec![
+                                            (outline_vertices.buffer(), 0),
+                                        ]));
+                                        encoder.draw(0..OUTLINE_EDGES.len() as u32, 0..1);
+                                    }
+                                    if let Some(offset) = debug_line_offset {
+                                        encoder.bind_graphics_pipeline(&deferred_debug_line_pipeline);
+                                        encoder.bind_vertex_buffers(0, pso::VertexBufferSet(vec![
+                                            (debug_line_ring.buffer(), offset),
+                                        ]));
+                                        encoder.draw(0..debug_line_count, 0..1);
+                                    }
+                                }
+                                gpu_profiler.end_scope(command_buffer);
+                            }
+                            Ok(())
+                        },
+                    )?;
+
+                    // The frame graph's passes can be in command buffers of their own, so the
+                    // viewport is set again for everything after them
+                    command_buffer.set_viewports(0, &[viewport.clone()]);
+                    command_buffer.set_scissors(0, &[viewport.rect]);
+
+                    // The debug views that aren't lit go to the screen as they are, so there's
+                    // nothing to expose or bloom
+                    let measure = context.config.settings().auto_exposure && view_mode.is_lit();
+                    let exposure = compensation(context.config.settings().exposure)
+                        * if measure { auto_exposure.exposure() } else { 1.0 };
+                    let draw_bloom =
+                        bloom.mips() > 0 && context.config.settings().bloom_strength > 0.0 && view_mode.is_lit();
+                    let extent = swapchain.extent();
+                    let eye_block = [eye[0].floor() as i32, eye[1].floor() as i32, eye[2].floor() as i32];
+                    let post_constants = PostConstants {
+                        target_size: [extent.width as f32, extent.height as f32],
+                        exposure,
+                        tonemap: context.config.settings().tonemap.id(),
+                        bloom_strength: if draw_bloom { context.config.settings().bloom_strength } else { 0.0 },
+                        bloom_threshold: context.config.settings().bloom_threshold,
+                        underwater: (world.block(eye_block) == Some(WATER) && view_mode.is_lit()) as u32,
+                        view_mode: view_mode.id(),
+                        srgb_target: is_srgb(swapchain.format()) as u32,
+                    };
+                    let image_sets = &post_sets[image_index as usize];
+
+                    // The bright part of the scene is drawn into the first bloom mip and
+                    // downsampled all the way down the chain, then each mip is blurred back up and
+                    // added onto the one above, until the first holds all of it. With bloom off the
+                    // first mip is
+                    // still cleared, for tonemapping to read nothing from.
+                    gpu_profiler.begin_scope(&mut command_buffer, "bloom");
+                    let black = [command::ClearValue::Color(command::ClearColor::Float([0.0, 0.0, 0.0, 1.0]))];
+                    if draw_bloom {
+                        for level in 0..bloom.mips() {
+                            let (pipeline, source) = if level == 0 {
+                                (&bloom_bright_pipeline, &image_sets.bright)
+                            } else {
+                                (&bloom_downsample_pipeline, &image_sets.mips[level as usize - 1])
+                            };
+                            let (width, height) = bloom.extent(level);
+                            let constants =
+                                PostConstants { target_size: [width as f32, height as f32], ..post_constants };
+                            let mip_viewport = bloom.viewport(level);
+                            command_buffer.set_viewports(0, &[mip_viewport.clone()]);
+                            command_buffer.set_scissors(0, &[mip_viewport.rect]);
+                            command_buffer.bind_graphics_descriptor_sets(&post_pipeline_layout, 0, vec![source], &[]);
+                            let mut encoder = command_buffer.begin_render_pass_inline(
+                                bloom.downsample_pass(),
+                                bloom.framebuffer(image_index, level),
+                                mip_viewport.rect,
+                                &black,
+                            );
+                            encoder.bind_graphics_pipeline(pipeline);
+                            encoder.push_graphics_constants(
+                                &post_pipeline_layout,
+                                pso::ShaderStageFlags::FRAGMENT,
+                                0,
+                                constants.as_words(),
+                            );
+                            encoder.draw(0..3, 0..1);
+                        }
+                        for level in (0..bloom.mips() - 1).rev() {
+                            let (width, height) = bloom.extent(level);
+                            let constants =
+                                PostConstants { target_size: [width as f32, height as f32], ..post_constants };
+                            let mip_viewport = bloom.viewport(level);
+                            command_buffer.set_viewports(0, &[mip_viewport.clone()]);
+                            command_buffer.set_scissors(0, &[mip_viewport.rect]);
+                            command_buffer.bind_graphics_descriptor_sets(
+                                &post_pipeline_layout,
+                                0,
+                                vec![&image_sets.mips[level as usize + 1]],
+                                &[],
+                            );
+                            let mut encoder = command_buffer.begin_render_pass_inline(
+                                bloom.upsample_pass(),
+                                bloom.framebuffer(image_index, level),
+                                mip_viewport.rect,
+                                &[],
+                            );
+                            encoder.bind_graphics_pipeline(&bloom_upsample_pipeline);
+                            encoder.push_graphics_constants(
+                                &post_pipeline_layout,
+                                pso::ShaderStageFlags::FRAGMENT,
+                                0,
+                                constants.as_words(),
+                            );
+                            encoder.draw(0..3, 0..1);
+                        }
+                        command_buffer.set_viewports(0, &[viewport.clone()]);
+                        command_buffer.set_scissors(0, &[viewport.rect]);
+                    } else {
+                        // Ending the pass straight away leaves just the clear
+                        command_buffer.begin_render_pass_inline(
+                            bloom.downsample_pass(),
+                            bloom.framebuffer(image_index, 0),
+                            bloom.viewport(0).rect,
+                            &black,
+                        );
+                    }
+                    gpu_profiler.end_scope(&mut command_buffer);
+
+                    // The scene is measured for auto exposure, then tonemapped into the swapchain
+                    // image for the overlay to go on top of
+                    gpu_profiler.begin_scope(&mut command_buffer, "tonemap");
+                    command_buffer.bind_graphics_descriptor_sets(
+                        &post_pipeline_layout,
+                        0,
+                        vec![&image_sets.scene],
+                        &[],
+                    );
+                    if measure {
+                        let luminance_viewport = hdr.luminance_viewport();
+                        command_buffer.set_viewports(0, &[luminance_viewport.clone()]);
+                        command_buffer.set_scissors(0, &[luminance_viewport.rect]);
+                        {
+                            let mut encoder = command_buffer.begin_render_pass_inline(
+                                hdr.luminance_pass(),
+                                hdr.luminance_framebuffer(),
+                                luminance_viewport.rect,
+                                &[command::ClearValue::Color(command::ClearColor::Float([0.0; 4]))],
+                            );
+                            encoder.bind_graphics_pipeline(&luminance_pipeline);
+                            encoder.draw(0..3, 0..1);
+                        }
+                        hdr.copy_luminance(&mut command_buffer, frame.index);
+                        command_buffer.set_viewports(0, &[viewport.clone()]);
+                        command_buffer.set_scissors(0, &[viewport.rect]);
+                    }
+                    {
+                        let mut encoder = command_buffer.begin_render_pass_inline(
+                            hdr.tonemap_pass(),
+                            hdr.tonemap_framebuffer(image_index),
+                            viewport.rect,
+                            &[command::ClearValue::Color(command::ClearColor::Float([0.0, 0.0, 0.0, 1.0]))],
+                        );
+                        encoder.bind_graphics_pipeline(&tonemap_pipeline);
+                        encoder.push_graphics_constants(
+                            &post_pipeline_layout,
+                            pso::ShaderStageFlags::FRAGMENT,
+                            0,
+                            post_constants.as_words(),
+                        );
+                        encoder.draw(0..3, 0..1);
+                    }
+                    gpu_profiler.end_scope(&mut command_buffer);
+
+                    // The crosshair, and the hotbar with the block that placing puts down picked
+                    // out
+                    let logical_size = swapchain.logical_size();
+                    let screen_size = [logical_size.width as f32, logical_size.height as f32];
+                    gpu_profiler.begin_scope(&mut command_buffer, "hud");
+                    hud.queue(&crosshair(screen_size));
+                    hud.queue(&hotbar(screen_size, &hotbar_icons, selected_block));
+                    // The map lags the camera by a few frames, so its marker moves across it
+                    let look = camera.look_direction();
+                    let heading = [look[0], look[2]];
+                    let heading_length = (heading[0] * heading[0] + heading[1] * heading[1]).sqrt().max(1e-4);
+                    hud.queue(&minimap_panel(
+                        screen_size,
+                        minimap_image,
+                        minimap.map_position(camera.position()),
+                        [heading[0] / heading_length, heading[1] / heading_length],
+                    ));
+                    hud.draw(&mut command_buffer, image_index, &swapchain)?;
+                    gpu_profiler.end_scope(&mut command_buffer);
+
+                    // Where the camera is in the bottom left corner, and the name of the block it's
+                    // looking at above the hotbar, all under the overlay
+                    gpu_profiler.begin_scope(&mut command_buffer, "text");
+                    {
+                        let position = camera.position();
+                        let readout = format!(
+                            "{:.1}, {:.1}, {:.1} facing {}",
+                            position[0],
+                            position[1],
+                            position[2],
+                            facing(camera.look_direction()),
+                        );
+                        let top = screen_size[1] - READOUT_MARGIN - text.line_height(READOUT_SIZE);
+                        text.queue(&readout, [READOUT_MARGIN, top], READOUT_SIZE, READOUT_COLOR);
+                        if let Some(hit) = target {
+                            let name = block_name(hit.block);
+                            let left = (screen_size[0] - text.width(name, TARGET_NAME_SIZE)) * 0.5;
+                            let height = text.line_height(TARGET_NAME_SIZE);
+                            let top = hotbar_top(screen_size[1]) - TARGET_NAME_MARGIN - height;
+                            text.queue(name, [left, top], TARGET_NAME_SIZE, READOUT_COLOR);
+                        }
+                        text.draw(&mut command_buffer, image_index, &swapchain)?;
+                    }
+                    gpu_profiler.end_scope(&mut command_buffer);
+
+                    gpu_profiler.begin_scope(&mut command_buffer, "overlay");
+                    {
+                        let position = camera.position();
+                        let stats = OverlayStats {
+                            gpu_timings: gpu_profiler.timings(),
+                            camera_position: Some(position),
+                            biome: Some(generator.biome_at(position[0].floor() as i32, position[2].floor() as i32)),
+                            loaded_chunks: Some(world.chunk_count()),
+                            lod_chunks: Some(lods.counts()),
+                            culling: Some(culling),
+                            triangles: Some(triangles),
+                            instances: None,
+                            selected_block: Some(PLACEABLE[selected_block].1),
+                            point_lights: Some((nearest_lights.len(), point_lights.count())),
+                            particles: alive_particles.map(|alive| (alive, gpu_particles.capacity() as usize)),
+                        };
+                        overlay.draw(
+                            &mut command_buffer,
+                            image_index,
+                            &swapchain,
+                            &stats,
+                            &mut overlay_settings,
+                        )?;
+                    }
+                    gpu_profiler.end_scope(&mut command_buffer);
+
+                    (command_buffer.finish(), graph_waits)
+                };
+                cpu_profiler.end_scope();
+
+                cpu_profiler.begin_scope("submit");
+                // After whatever the frame graph left for it to wait on
+                let mut waits = vec![(frame.image_available(), PipelineStage::COLOR_ATTACHMENT_OUTPUT)];
+                waits.extend(graph_waits);
+                let submission = Submission::new()
+                    .wait_on(&waits)
+                    .signal(&[frame.render_finished()])
+                    .submit(Some(finished_command_buffer));
+                context.device.reset_fence(frame.fence());
+                context.queue_group.queues[0].submit(submission, Some(frame.fence()));
+            }
+            cpu_profiler.end_scope();
+
+            if take_screenshot {
+                match screenshot::capture(context, &swapchain, image_index) {
+                    Ok(path) => info!("Saved a screenshot to {}", path.display()),
+                    Err(err) => error!("{}", err),
+                }
+            }
+
+            cpu_profiler.begin_scope("present");
+            if swapchain.present(&frame, &mut context.queue_group.queues[0], image_index).is_err() {
+                recreate_swapchain = true;
+            }
+            cpu_profiler.end_scope();
+
+            if overlay_settings.vsync != vsync {
+                context.set_vsync(overlay_settings.vsync);
+                recreate_swapchain = true;
+            }
+            if let Some(chosen) = overlay_settings.mesher {
+                mesher = chosen;
+            }
+            if let Some(chosen) = overlay_settings.shading {
+                shading = chosen;
+            }
+            if let Some(chosen) = overlay_settings.time_of_day {
+                time_of_day = chosen;
+            }
+            if let Some(chosen) = overlay_settings.shadows {
+                shadows_enabled = chosen;
+            }
+            if let Some(chosen) = overlay_settings.show_cascades {
+                show_cascades = chosen;
+            }
+            if let Some(chosen) = overlay_settings.view_mode {
+                view_mode = chosen;
+            }
+            if let Some(chosen) = overlay_settings.debug_lines {
+                debug_line_settings = chosen;
+            }
+
+            if last_timing_report.elapsed().as_secs() >= 1 {
+                for timing in gpu_profiler.timings() {
+                    debug!("Gpu time for {}: {:.3} ms", timing.name, timing.milliseconds);
+                }
+                debug!("Cpu time for the last frame:\n{}", cpu_profiler.report());
+                last_timing_report = Instant::now();
+            }
+        }
+
+        drop(workers);
+        if let Some(ref mut store) = store {
+            let saved = store.save_unsaved(&mut world, &pending_edits)?;
+            store.flush()?;
+            info!("Saved {} chunks in {}", saved, store.directory().display());
+        }
+        context.wait_idle()?;
+
+        if let Some(ref path) = context.args.cpu_trace {
+            cpu_profiler.write_chrome_trace(path)?;
+            info!("Wrote the cpu trace to {}", path.display());
+        }
+
+        drop(overlay);
+        drop(hud);
+        drop(minimap);
+        drop(probe);
+        drop(reflection);
+        drop(text);
+        drop(framebuffers);
+        drop(lighting_framebuffers);
+        drop(depth_images);
+        drop(msaa_targets);
+        drop(retired_chunks);
+        drop(chunks);
+        drop(chunk_draws);
+        drop(billboards);
+        drop(gpu_particles);
+        drop(outline_vertices);
+        drop(debug_line_ring);
+        drop(atlas);
+        drop(skybox);
+        drop(ripples);
+        drop(camera_uniforms);
+        drop(light_uniforms);
+        drop(ssao_uniforms);
+        drop(descriptors);
+        drop(lights_descriptors);
+        drop(gbuffer_sets);
+        drop(gbuffer_descriptors);
+        drop(ssao_descriptors);
+        drop(occlusion_sets);
+        drop(occlusion_descriptors);
+        drop(post_sets);
+        drop(post_descriptors);
+        context.device.destroy_graphics_pipeline(pipeline);
+        context.device.destroy_graphics_pipeline(outline_pipeline);
+        context.device.destroy_graphics_pipeline(shadow_pipeline);
+        context.device.destroy_graphics_pipeline(minimap_pipeline);
+        context.device.destroy_graphics_pipeline(probe_pipeline);
+        context.device.destroy_graphics_pipeline(reflection_pipeline);
+        context.device.destroy_compute_pipeline(cull_pipeline);
+        context.device.destroy_compute_pipeline(particle_pipeline);
+        context.device.destroy_graphics_pipeline(gbuffer_pipeline);
+        context.device.destroy_graphics_pipeline(wireframe_pipeline);
+        context.device.destroy_graphics_pipeline(deferred_wireframe_pipeline);
+        context.device.destroy_graphics_pipeline(overdraw_pipeline);
+        context.device.destroy_graphics_pipeline(lighting_pipeline);
+        context.device.destroy_graphics_pipeline(deferred_outline_pipeline);
+        context.device.destroy_graphics_pipeline(sky_pipeline);
+        context.device.destroy_graphics_pipeline(deferred_sky_pipeline);
+        context.device.destroy_graphics_pipeline(translucent_pipeline);
+        context.device.destroy_graphics_pipeline(deferred_translucent_pipeline);
+        context.device.destroy_graphics_pipeline(water_pipeline);
+        context.device.destroy_graphics_pipeline(deferred_water_pipeline);
+        context.device.destroy_graphics_pipeline(billboard_pipeline);
+        context.device.destroy_graphics_pipeline(deferred_billboard_pipeline);
+        context.device.destroy_graphics_pipeline(debug_line_pipeline);
+        context.device.destroy_graphics_pipeline(deferred_debug_line_pipeline);
+        context.device.destroy_compute_pipeline(ssao_pipeline);
+        context.device.destroy_compute_pipeline(ssao_blur_pipeline);
+        context.device.destroy_graphics_pipeline(luminance_pipeline);
+        context.device.destroy_graphics_pipeline(tonemap_pipeline);
+        context.device.destroy_graphics_pipeline(bloom_bright_pipeline);
+        context.device.destroy_graphics_pipeline(bloom_downsample_pipeline);
+        context.device.destroy_graphics_pipeline(bloom_upsample_pipeline);
+        drop(shadow_map);
+        drop(gbuffer);
+        drop(forward_graph);
+        drop(deferred_graph);
+        drop(bloom);
+        drop(hdr);
+        context.device.destroy_pipeline_layout(pipeline_layout);
+        context.device.destroy_pipeline_layout(post_pipeline_layout);
+        context.device.destroy_pipeline_layout(ssao_pipeline_layout);
+        context.device.destroy_render_pass(render_pass);
+        context.device.destroy_render_pass(lighting_pass);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,40 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    // Which way the wind blows in xy and how far it bends things, and the time in z
+    vec4 wind;
+} camera;
+
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec3 color;
+// How much of the instance's tint the vertex takes
+layout(location = 3) in float tinted;
+// How far the wind moves the vertex, 0 for anything fixed to the ground
+layout(location = 4) in float sway;
+
+// Once per instance, from the second vertex buffer. See `instancing::add_instance_attributes`.
+layout(location = 5) in mat4 model;
+layout(location = 9) in vec4 tint;
+
+layout(location = 0) out vec3 frag_normal;
+layout(location = 1) out vec3 frag_color;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    vec4 world = model * vec4(position, 1.0);
+    // Each prop sways a little out of step with its neighbours, so gusts seem to roll across
+    // the field
+    vec2 rooted_at = model[3].xz;
+    float phase = dot(rooted_at, vec2(0.35, 0.21));
+    world.xz += camera.wind.xy * sway * sin(camera.wind.z * 1.7 + phase);
+
+    gl_Position = camera.view_projection * world;
+    frag_normal = mat3(model) * normal;
+    frag_color = color * mix(vec3(1.0), tint.rgb, tinted);
+}
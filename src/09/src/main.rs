@@ -0,0 +1,845 @@
+extern crate gfx_hal as hal;
+#[macro_use]
+extern crate log;
+extern crate renderer_common;
+
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+use std::time::Instant;
+
+use hal::{
+    buffer, command, format as f, image as i, pass, IndexType,
+    pso::{ self, PipelineStage },
+    Backend, Device,
+    Primitive, Submission,
+};
+
+use renderer_common::color::{ srgb_to_linear_rgb, srgb_to_linear_rgba };
+use renderer_common::depth::choose_depth_format;
+use renderer_common::descriptors::{ DescriptorAllocator, DescriptorSetLayout, UniformRing };
+use renderer_common::events::WindowAction;
+use renderer_common::frame_sync;
+use renderer_common::instancing::add_instance_attributes;
+use renderer_common::math::{ Quat, Rad, Rotation3, ShaderMatrix };
+use renderer_common::msaa::choose_sample_count;
+use renderer_common::noise::{ derive_seed, Perlin, Random };
+use renderer_common::present;
+use renderer_common::screenshot;
+use renderer_common::shader::{ create_shader_module, ShaderSet };
+use renderer_common::timestep::TICK_SECONDS;
+use renderer_common::{
+    upload_buffer, AttachmentImages, Camera, CameraSwitch, Clock, CpuProfiler, DebugOverlay, Events,
+    FixedTimestep, FpsCamera, FrameSync, Framebuffers, Gamepads, GfxContext, GpuProfiler, Input,
+    Instance, InstanceBuffer, Interpolated, MemoryCategory, OrbitCamera, OverlaySettings,
+    OverlayStats, Result, Runner, Transform,
+};
+
+const CLEAR_COLOR: [f32; 4] = [0.55, 0.7, 0.9, 1.0];
+
+/// The SPIR-V for the shaders in `shaders/`, compiled by `build.rs`
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// Has to match the per vertex attributes in `props.vert`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Vertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+    color: [f32; 3],
+    /// How much of the instance's tint this vertex takes: none for a flower's stem, all of it
+    /// for its petals.
+    tinted: f32,
+    /// How far the wind moves this vertex.
+    sway: f32,
+}
+
+/// The meshes the props are copies of. Each one is drawn with a single instanced draw.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Prop {
+    Ground,
+    Grass,
+    Flower,
+    Stone,
+}
+
+const PROPS: [Prop; 4] = [Prop::Ground, Prop::Grass, Prop::Flower, Prop::Stone];
+
+/// Where one prop's mesh is in the shared vertex and index buffers.
+#[derive(Clone, Copy, Debug)]
+struct PropMesh {
+    first_index: u32,
+    index_count: u32,
+    base_vertex: i32,
+}
+
+/// Every prop's mesh, one after another.
+struct PropMeshes {
+    vertices: Vec<Vertex>,
+    indices: Vec<u16>,
+    meshes: Vec<PropMesh>,
+}
+
+impl PropMeshes {
+    fn new() -> Self {
+        let mut meshes = PropMeshes { vertices: Vec::new(), indices: Vec::new(), meshes: Vec::new() };
+        for &prop in &PROPS {
+            let (vertices, indices) = match prop {
+                Prop::Ground => ground_mesh(),
+                Prop::Grass => grass_mesh(),
+                Prop::Flower => flower_mesh(),
+                Prop::Stone => stone_mesh(),
+            };
+            meshes.meshes.push(PropMesh {
+                first_index: meshes.indices.len() as u32,
+                index_count: indices.len() as u32,
+                base_vertex: meshes.vertices.len() as i32,
+            });
+            meshes.vertices.extend(vertices);
+            meshes.indices.extend(indices);
+        }
+        meshes
+    }
+
+    fn get(&self, prop: Prop) -> PropMesh {
+        self.meshes[prop as usize]
+    }
+}
+
+/// Adds a quad with corners going anticlockwise from the bottom left. The bottom two corners
+/// are coloured and sway like the first of `colors` and `sway` say, and the top two like the
+/// second. The colours are sRGB, and decoded here so that they're lit in linear space.
+fn push_quad(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u16>,
+    corners: [[f32; 3]; 4],
+    normal: [f32; 3],
+    colors: ([f32; 3], [f32; 3]),
+    tinted: f32,
+    sway: (f32, f32),
+) {
+    let base = vertices.len() as u16;
+    for (index, &position) in corners.iter().enumerate() {
+        let upper = index >= 2;
+        vertices.push(Vertex {
+            position,
+            normal,
+            color: srgb_to_linear_rgb(if upper { colors.1 } else { colors.0 }),
+            tinted,
+            sway: if upper { sway.1 } else { sway.0 },
+        });
+    }
+    indices.extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+}
+
+/// A vertical quad `width` wide from `bottom` to `top` high, facing `angle` radians round the y
+/// axis, and narrowing to `taper` of its width at the top. Blades are drawn from both sides
+/// and face up rather than out, so that they're lit like the ground they grow out of.
+fn push_blade(
+    vertices: &mut Vec<Vertex>,
+    indices: &mut Vec<u16>,
+    angle: f32,
+    (width, taper): (f32, f32),
+    (bottom, top): (f32, f32),
+    colors: ([f32; 3], [f32; 3]),
+    tinted: f32,
+) {
+    let (sin, cos) = angle.sin_cos();
+    let along = |distance: f32, height: f32| [cos * distance, height, sin * distance];
+    let half = width / 2.0;
+    // The wind bends things further the further up they reach
+    let sway = (bottom * bottom, top * top);
+    push_quad(
+        vertices,
+        indices,
+        [along(-half, bottom), along(half, bottom), along(half * taper, top), along(-half * taper, top)],
+        [0.0, 1.0, 0.0],
+        colors,
+        tinted,
+        sway,
+    );
+}
+
+/// A unit square lying flat, for stretching over the whole field.
+fn ground_mesh() -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let color = [0.32, 0.42, 0.2];
+    push_quad(
+        &mut vertices,
+        &mut indices,
+        [[-0.5, 0.0, 0.5], [0.5, 0.0, 0.5], [0.5, 0.0, -0.5], [-0.5, 0.0, -0.5]],
+        [0.0, 1.0, 0.0],
+        (color, color),
+        1.0,
+        (0.0, 0.0),
+    );
+    (vertices, indices)
+}
+
+/// Three blades crossing in the middle, darker at the roots.
+fn grass_mesh() -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    for blade in 0..3 {
+        let angle = blade as f32 * std::f32::consts::PI / 3.0;
+        push_blade(
+            &mut vertices,
+            &mut indices,
+            angle,
+            (0.3, 0.2),
+            (0.0, 0.5),
+            ([0.12, 0.25, 0.06], [0.45, 0.7, 0.25]),
+            1.0,
+        );
+    }
+    (vertices, indices)
+}
+
+/// A crossed pair of thin green stems with a head on top, whose petals take the tint.
+fn flower_mesh() -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let stem = ([0.15, 0.35, 0.1], [0.25, 0.5, 0.15]);
+    let petals = ([1.0; 3], [1.0; 3]);
+    for &angle in &[0.0, std::f32::consts::FRAC_PI_2] {
+        push_blade(&mut vertices, &mut indices, angle, (0.04, 1.0), (0.0, 0.45), stem, 0.0);
+        push_blade(&mut vertices, &mut indices, angle, (0.16, 1.0), (0.4, 0.52), petals, 1.0);
+    }
+    // The top of the head, so it doesn't vanish from above
+    let head = 0.08;
+    let sway = 0.46 * 0.46;
+    let base = vertices.len() as u16;
+    for &(x, z) in &[(-head, head), (head, head), (head, -head), (-head, -head)] {
+        vertices.push(Vertex { position: [x, 0.46, z], normal: [0.0, 1.0, 0.0], color: [1.0; 3], tinted: 1.0, sway });
+    }
+    indices.extend_from_slice(&[base, base + 1, base + 2, base + 2, base + 3, base]);
+    (vertices, indices)
+}
+
+/// A squat box, sunk into the ground a little when it's placed.
+fn stone_mesh() -> (Vec<Vertex>, Vec<u16>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let (low, high) = (0.0, 0.6);
+    let color = ([0.45, 0.45, 0.43], [0.55, 0.55, 0.52]);
+    let faces: [([[f32; 3]; 4], [f32; 3]); 5] = [
+        ([[-0.5, low, 0.5], [0.5, low, 0.5], [0.5, high, 0.5], [-0.5, high, 0.5]], [0.0, 0.0, 1.0]),
+        ([[0.5, low, -0.5], [-0.5, low, -0.5], [-0.5, high, -0.5], [0.5, high, -0.5]], [0.0, 0.0, -1.0]),
+        ([[0.5, low, 0.5], [0.5, low, -0.5], [0.5, high, -0.5], [0.5, high, 0.5]], [1.0, 0.0, 0.0]),
+        ([[-0.5, low, -0.5], [-0.5, low, 0.5], [-0.5, high, 0.5], [-0.5, high, -0.5]], [-1.0, 0.0, 0.0]),
+        ([[-0.5, high, 0.5], [0.5, high, 0.5], [0.5, high, -0.5], [-0.5, high, -0.5]], [0.0, 1.0, 0.0]),
+    ];
+    // Nothing ever sees the bottom
+    for &(corners, normal) in &faces {
+        let colors = if normal[1] > 0.0 { (color.1, color.1) } else { color };
+        push_quad(&mut vertices, &mut indices, corners, normal, colors, 1.0, (0.0, 0.0));
+    }
+    (vertices, indices)
+}
+
+/// How far the field reaches from the middle along x and z, in metres.
+const FIELD_RADIUS: f32 = 40.0;
+/// How many of each prop are tried. Props only grow where the meadow noise lets them, so
+/// fewer than this end up in the field.
+const GRASS_TRIES: usize = 16_000;
+const FLOWER_TRIES: usize = 6_000;
+const STONE_TRIES: usize = 150;
+
+/// What the flowers' petals are tinted, in sRGB, clumped together by a noise of their own.
+const FLOWER_COLORS: [[f32; 3]; 4] = [
+    [0.95, 0.85, 0.2],
+    [0.9, 0.3, 0.35],
+    [0.6, 0.45, 0.9],
+    [0.95, 0.95, 0.95],
+];
+
+/// A random spot in the field, and a random way round to face there.
+fn random_placement(random: &mut Random) -> ([f32; 3], Quat) {
+    let x = (random.next_f32() * 2.0 - 1.0) * FIELD_RADIUS;
+    let z = (random.next_f32() * 2.0 - 1.0) * FIELD_RADIUS;
+    let angle = random.next_f32() * 2.0 * std::f32::consts::PI;
+    ([x, 0.0, z], Quat::from_angle_y(Rad(angle)))
+}
+
+/// Scatters the props over the field, the same way for the same `seed`. Grass grows thicker
+/// in some patches than others, flowers only in the thickest, and stones anywhere.
+fn scatter_props(seed: u64) -> Vec<(usize, Instance)> {
+    let mut random = Random::new(seed);
+    let meadow = Perlin::new(derive_seed(seed, 1));
+    let colors = Perlin::new(derive_seed(seed, 2));
+    let density = |position: [f32; 3]| meadow.get2(position[0] * 0.06, position[2] * 0.06);
+
+    let ground = Transform { scale: FIELD_RADIUS * 2.0, ..Transform::identity() };
+    let mut instances = vec![(Prop::Ground as usize, Instance::new(&ground, [1.0; 4]))];
+
+    for _ in 0..GRASS_TRIES {
+        let (position, rotation) = random_placement(&mut random);
+        if random.next_f32() > 0.55 + density(position) {
+            continue;
+        }
+        let transform = Transform { rotation, scale: 0.7 + random.next_f32() * 0.6, ..Transform::from_translation(position) };
+        let shade = 0.85 + random.next_f32() * 0.3;
+        instances.push((Prop::Grass as usize, Instance::new(&transform, [shade, shade, shade * 0.9, 1.0])));
+    }
+
+    for _ in 0..FLOWER_TRIES {
+        let (position, rotation) = random_placement(&mut random);
+        if density(position) < 0.25 {
+            continue;
+        }
+        let transform = Transform { rotation, scale: 0.8 + random.next_f32() * 0.4, ..Transform::from_translation(position) };
+        let pick = (colors.get2(position[0] * 0.04, position[2] * 0.04) + 1.0) * 0.5;
+        let color = srgb_to_linear_rgb(
+            FLOWER_COLORS[((pick * FLOWER_COLORS.len() as f32) as usize).min(FLOWER_COLORS.len() - 1)],
+        );
+        instances.push((Prop::Flower as usize, Instance::new(&transform, [color[0], color[1], color[2], 1.0])));
+    }
+
+    for _ in 0..STONE_TRIES {
+        let (position, rotation) = random_placement(&mut random);
+        let position = [position[0], -0.1, position[2]];
+        let transform = Transform { rotation, scale: 0.3 + random.next_f32() * 0.6, ..Transform::from_translation(position) };
+        let shade = 0.8 + random.next_f32() * 0.4;
+        instances.push((Prop::Stone as usize, Instance::new(&transform, [shade, shade, shade, 1.0])));
+    }
+    instances
+}
+
+/// Has to match the `Camera` block in `props.vert`. It's written once per frame, into that
+/// frame's copy of the uniform buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CameraUniform {
+    view_projection: ShaderMatrix,
+    /// Which way the wind blows and how far it bends things, then the time in seconds.
+    wind: [f32; 4],
+}
+
+/// Which way the wind blows, and how many metres it moves the tip of a tuft of grass.
+const WIND: [f32; 2] = [0.08, 0.05];
+
+/// Builds the graphics pipeline from the current SPIR-V in `shaders`. This is called again
+/// whenever one of the shaders is changed on disk.
+fn create_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("props.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("props.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        // Nothing is culled, so blades of grass show from both sides
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer::FILL,
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: true,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        // Has to match the sample count of the render pass attachments
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::ALPHA));
+
+        // The mesh's vertices at binding 0, and the instances at binding 1
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<Vertex>() as u32,
+            rate: 0,
+        });
+        let vertex_attributes = [
+            (f::Format::Rgb32Float, 0),
+            (f::Format::Rgb32Float, 12),
+            (f::Format::Rgb32Float, 24),
+            (f::Format::R32Float, 36),
+            (f::Format::R32Float, 40),
+        ];
+        for (location, &(format, offset)) in vertex_attributes.iter().enumerate() {
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location: location as u32,
+                binding: 0,
+                element: pso::Element { format, offset },
+            });
+        }
+        add_instance_attributes(&mut pipeline_desc, 1, vertex_attributes.len() as u32);
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    // The modules are only needed while the pipeline is being built
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the graphics pipeline");
+    Ok(pipeline)
+}
+
+/// The extra attachments that follow the swapchain image in each framebuffer, in the order
+/// `create_multisampled_render_pass` expects them.
+fn framebuffer_attachments<'a, B: Backend>(
+    msaa_targets: &'a Option<AttachmentImages<B>>,
+    depth_images: &'a AttachmentImages<B>,
+) -> Vec<&'a AttachmentImages<B>> {
+    msaa_targets.iter().chain(iter::once(depth_images)).collect()
+}
+
+fn main() {
+    renderer_common::launch("voxel-renderer", Chapter);
+}
+
+struct Chapter;
+
+impl Runner for Chapter {
+    fn run<B: Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<()> {
+        let mut cpu_profiler = CpuProfiler::new(context.args.cpu_trace.is_some());
+        let mut swapchain = context.create_swapchain()?;
+        let samples = choose_sample_count(&context.adapter, context.args.msaa)?;
+        let depth_format = choose_depth_format(&context.adapter)?;
+        info!("Depth format: {:?}, {}x MSAA", depth_format, samples);
+        let render_pass = renderer_common::pass::create_multisampled_render_pass::<B>(
+            &context.device,
+            swapchain.format(),
+            depth_format,
+            samples,
+        );
+
+        // With multisampling on we draw into a multisampled color target instead of the
+        // swapchain image, and the render pass resolves it into the swapchain image at the end
+        let mut msaa_targets = if samples > 1 {
+            Some(AttachmentImages::multisampled_color(
+                context.device.clone(),
+                context.allocator.clone(),
+                swapchain.format(),
+                samples,
+                &swapchain,
+            )?)
+        } else {
+            None
+        };
+        let mut depth_images = AttachmentImages::depth(
+            context.device.clone(),
+            context.allocator.clone(),
+            depth_format,
+            samples,
+            &swapchain,
+        )?;
+
+        cpu_profiler.begin_scope("meshing");
+        let meshes = PropMeshes::new();
+        let seed = context.args.seed.unwrap_or(context.config.settings().seed);
+        let props = scatter_props(seed);
+        cpu_profiler.end_scope();
+        cpu_profiler.begin_scope("upload");
+        let vertex_buffer = upload_buffer(
+            context,
+            &meshes.vertices,
+            buffer::Usage::VERTEX,
+            MemoryCategory::Meshes,
+        )?;
+        let index_buffer = upload_buffer(
+            context,
+            &meshes.indices,
+            buffer::Usage::INDEX,
+            MemoryCategory::Meshes,
+        )?;
+        let instances = InstanceBuffer::new(context, PROPS.len(), &props)?;
+        cpu_profiler.end_scope();
+        info!("Scattered {} props over the field", instances.count());
+        info!("Gpu memory: {}", context.allocator.borrow().stats());
+
+        // The camera's matrix and the wind come from a uniform buffer. Where each prop is comes
+        // from its instance, so there's nothing to push per draw.
+        let camera_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![pso::DescriptorSetLayoutBinding {
+                binding: 0,
+                ty: pso::DescriptorType::UniformBuffer,
+                count: 1,
+                stage_flags: pso::ShaderStageFlags::VERTEX,
+                immutable_samplers: false,
+            }],
+        ));
+        let mut descriptors = DescriptorAllocator::new(context.device.clone(), camera_layout.clone());
+        let pipeline_layout = context.device.create_pipeline_layout(
+            Some(camera_layout.raw()),
+            iter::empty::<(pso::ShaderStageFlags, Range<u32>)>(),
+        );
+
+        let mut clock = Clock::new(context.is_headless());
+        let mut timestep = FixedTimestep::new();
+        let mut input = Input::new(&context.config.settings().bindings);
+        input.set_grab_on_focus(!context.is_headless());
+        let mut gamepads = Gamepads::new(!context.is_headless());
+        // When the clock last read, for how far the gamepad sticks turn each frame
+        let mut last_seconds = 0.0;
+        // How long the wind has been blowing, which is advanced a tick at a time like the camera
+        let mut wind_seconds = Interpolated::new(0.0);
+        // Standing at the edge of the field looking across it, or circling the middle of it
+        let mut camera = CameraSwitch::new(
+            FpsCamera::new([0.0, 1.7, FIELD_RADIUS * 0.6], context.config.settings()),
+            OrbitCamera::new([0.0, 0.0, 0.0], FIELD_RADIUS * 0.6, context.config.settings()),
+        );
+
+        let mut shaders = ShaderSet::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"), shaders::ALL);
+        let mut pipeline = create_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+
+        let mut framebuffers = Framebuffers::with_attachments(
+            context.device.clone(),
+            &render_pass,
+            &swapchain,
+            &framebuffer_attachments(&msaa_targets, &depth_images),
+        )?;
+
+        // One clear value per attachment. The resolve target is never loaded, so its value
+        // doesn't matter.
+        let color_clear = command::ClearValue::Color(command::ClearColor::Float(srgb_to_linear_rgba(CLEAR_COLOR)));
+        let depth_clear = command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0));
+        let clear_values = if samples > 1 {
+            vec![color_clear.clone(), color_clear, depth_clear]
+        } else {
+            vec![color_clear, depth_clear]
+        };
+        let mut frame_sync = FrameSync::new(
+            context.device.clone(),
+            &context.queue_group,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        );
+        let mut gpu_profiler = GpuProfiler::new(
+            context.device.clone(),
+            &context.adapter,
+            frame_sync.frames_in_flight(),
+        );
+        let camera_uniforms = UniformRing::<B, CameraUniform>::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            &context.adapter,
+            &mut descriptors,
+            0,
+            frame_sync.frames_in_flight(),
+        )?;
+        let mut overlay = DebugOverlay::new(context, &swapchain, frame_sync.frames_in_flight())?;
+        let mut last_timing_report = Instant::now();
+
+        let mut running = true;
+        let mut recreate_swapchain = false;
+        while running {
+            cpu_profiler.begin_frame();
+            let mut toggle_vsync = false;
+            let mut toggle_fullscreen = false;
+            let mut take_screenshot = false;
+            input.begin_frame();
+            events.poll_with(
+                |event| {
+                    overlay.handle_event(event);
+                    // While the cursor is grabbed it's hidden, so it can't be over the overlay
+                    if !input.cursor_grabbed() && overlay.captures(event) {
+                        return false;
+                    }
+                    input.handle_event(event)
+                },
+                |action| match action {
+                    WindowAction::Close => running = false,
+                    WindowAction::Resize => recreate_swapchain = true,
+                    WindowAction::ToggleVsync => toggle_vsync = true,
+                    WindowAction::ToggleFullscreen => toggle_fullscreen = true,
+                    WindowAction::Screenshot => take_screenshot = true,
+                },
+            );
+
+            if toggle_vsync {
+                context.toggle_vsync(&swapchain);
+                recreate_swapchain = true;
+            }
+            if toggle_fullscreen {
+                context.toggle_fullscreen();
+                recreate_swapchain = true;
+            }
+            if context.poll_config() {
+                recreate_swapchain = true;
+            }
+
+            // Rebuild the pipeline if either of its shaders was edited
+            if !shaders.poll_changes().is_empty() {
+                context.wait_idle()?;
+                match create_pipeline::<B>(
+                    &context.device,
+                    &shaders,
+                    &render_pass,
+                    &pipeline_layout,
+                    context.pipeline_cache.cache(),
+                    samples,
+                ) {
+                    Ok(new_pipeline) => {
+                        let old_pipeline = mem::replace(&mut pipeline, new_pipeline);
+                        context.device.destroy_graphics_pipeline(old_pipeline);
+                    }
+                    Err(err) => error!("Keeping the previous pipeline: {}", err),
+                }
+            }
+
+            if recreate_swapchain {
+                context.recreate_swapchain(&mut swapchain)?;
+                if let Some(ref mut msaa_targets) = msaa_targets {
+                    msaa_targets.recreate(&swapchain)?;
+                }
+                depth_images.recreate(&swapchain)?;
+                framebuffers.recreate_with_attachments(
+                    &render_pass,
+                    &swapchain,
+                    &framebuffer_attachments(&msaa_targets, &depth_images),
+                )?;
+                overlay.recreate(&swapchain)?;
+                recreate_swapchain = false;
+            }
+
+            // Run however many simulation ticks have come due since the last frame
+            cpu_profiler.begin_scope("simulate");
+            context.update_cursor_grab(input.cursor_grabbed());
+            input.apply_bindings(&context.config.settings().bindings);
+            let seconds = clock.frame_seconds();
+            gamepads.poll(&mut input, context.config.settings(), seconds - last_seconds);
+            last_seconds = seconds;
+            camera.apply_settings(context.config.settings());
+            camera.handle_input(&input);
+            for _ in 0..timestep.advance_to(seconds) {
+                camera.update(&input, TICK_SECONDS);
+                let blown = wind_seconds.current() + TICK_SECONDS;
+                wind_seconds.set(blown);
+            }
+            cpu_profiler.end_scope();
+
+            // Waits until the gpu is done with the last frame that used these resources
+            cpu_profiler.begin_scope("wait");
+            let mut frame = frame_sync.begin_frame()?;
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("acquire");
+            let image_index = match swapchain.acquire_image(&mut frame, &mut context.queue_group.queues[0]) {
+                Ok(image_index) => image_index,
+                Err(_) => {
+                    recreate_swapchain = true;
+                    continue;
+                }
+            };
+            cpu_profiler.end_scope();
+
+            // The pipeline doesn't bake in a viewport, so we set it (and the scissor) every
+            // frame, which also means we don't need to rebuild the pipeline on resize.
+            let viewport = swapchain.viewport();
+
+            let vsync = present::is_vsync(swapchain.present_mode());
+            let mut overlay_settings = OverlaySettings {
+                vsync,
+                wireframe: None,
+                shadows: None,
+                show_cascades: None,
+                occlusion_culling: None,
+                mesher: None,
+                shading: None,
+                view_mode: None,
+                debug_lines: None,
+                time_of_day: None,
+            };
+
+            cpu_profiler.begin_scope("record");
+            let finished_command_buffer = {
+                let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+                gpu_profiler.begin_frame(&mut command_buffer, frame.index)?;
+
+                command_buffer.set_viewports(0, &[viewport.clone()]);
+                command_buffer.set_scissors(0, &[viewport.rect]);
+                command_buffer.bind_graphics_pipeline(&pipeline);
+                command_buffer.bind_graphics_descriptor_sets(
+                    &pipeline_layout,
+                    0,
+                    Some(camera_uniforms.set(frame.index)),
+                    &[],
+                );
+                // Every prop's mesh shares these, and so do all of their instances
+                command_buffer.bind_vertex_buffers(
+                    0,
+                    pso::VertexBufferSet(vec![(vertex_buffer.buffer(), 0), (instances.buffer(), 0)]),
+                );
+                command_buffer.bind_index_buffer(buffer::IndexBufferView {
+                    buffer: index_buffer.buffer(),
+                    offset: 0,
+                    index_type: IndexType::U16,
+                });
+
+                // Draw in between the last two ticks, however far the clock is through the next
+                let alpha = timestep.alpha();
+                let extent = swapchain.extent();
+                let aspect = extent.width as f32 / extent.height as f32;
+                let view_projection = camera.interpolated_view_projection(aspect, alpha).into();
+                let wind = [WIND[0], WIND[1], wind_seconds.get(alpha), 0.0];
+                camera_uniforms.update(frame.index, &CameraUniform { view_projection, wind })?;
+                let mut triangles = 0;
+
+                gpu_profiler.begin_scope(&mut command_buffer, "props");
+                {
+                    let mut encoder = command_buffer.begin_render_pass_inline(
+                        &render_pass,
+                        framebuffers.get(image_index),
+                        viewport.rect,
+                        &clear_values,
+                    );
+
+                    // One draw for all the copies of each mesh. The instance range picks out
+                    // which instances in the buffer are copies of it.
+                    for &prop in &PROPS {
+                        let mesh = meshes.get(prop);
+                        let batch = instances.batch(prop as usize);
+                        if batch.start == batch.end {
+                            continue;
+                        }
+                        triangles += (mesh.index_count / 3 * (batch.end - batch.start)) as usize;
+                        encoder.draw_indexed(
+                            mesh.first_index..mesh.first_index + mesh.index_count,
+                            mesh.base_vertex,
+                            batch,
+                        );
+                    }
+                }
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                // The overlay goes on top of the resolved image, in a pass of its own
+                gpu_profiler.begin_scope(&mut command_buffer, "overlay");
+                {
+                    let stats = OverlayStats {
+                        gpu_timings: gpu_profiler.timings(),
+                        camera_position: Some(camera.position()),
+                        triangles: Some(triangles),
+                        instances: Some((instances.count() as usize, PROPS.len())),
+                        ..OverlayStats::default()
+                    };
+                    overlay.draw(
+                        &mut command_buffer,
+                        image_index,
+                        &swapchain,
+                        &stats,
+                        &mut overlay_settings,
+                    )?;
+                }
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                command_buffer.finish()
+            };
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("submit");
+            let submission = Submission::new()
+                .wait_on(&[(frame.image_available(), PipelineStage::COLOR_ATTACHMENT_OUTPUT)])
+                .signal(&[frame.render_finished()])
+                .submit(Some(finished_command_buffer));
+            context.device.reset_fence(frame.fence());
+            context.queue_group.queues[0].submit(submission, Some(frame.fence()));
+            cpu_profiler.end_scope();
+
+            if take_screenshot {
+                match screenshot::capture(context, &swapchain, image_index) {
+                    Ok(path) => info!("Saved a screenshot to {}", path.display()),
+                    Err(err) => error!("{}", err),
+                }
+            }
+
+            cpu_profiler.begin_scope("present");
+            if swapchain.present(&frame, &mut context.queue_group.queues[0], image_index).is_err() {
+                recreate_swapchain = true;
+            }
+            cpu_profiler.end_scope();
+
+            // The swapchain can't change mid-frame, so a vsync toggle from the overlay takes
+            // effect on the next one
+            if overlay_settings.vsync != vsync {
+                context.set_vsync(overlay_settings.vsync);
+                recreate_swapchain = true;
+            }
+
+            if last_timing_report.elapsed().as_secs() >= 1 {
+                for timing in gpu_profiler.timings() {
+                    debug!("Gpu time for {}: {:.3} ms", timing.name, timing.milliseconds);
+                }
+                debug!("Cpu time for the last frame:\n{}", cpu_profiler.report());
+                last_timing_report = Instant::now();
+            }
+        }
+
+        context.wait_idle()?;
+
+        if let Some(ref path) = context.args.cpu_trace {
+            cpu_profiler.write_chrome_trace(path)?;
+            info!("Wrote the cpu trace to {}", path.display());
+        }
+
+        drop(overlay);
+        drop(framebuffers);
+        drop(depth_images);
+        drop(msaa_targets);
+        drop(vertex_buffer);
+        drop(index_buffer);
+        drop(instances);
+        drop(camera_uniforms);
+        drop(descriptors);
+        context.device.destroy_graphics_pipeline(pipeline);
+        context.device.destroy_pipeline_layout(pipeline_layout);
+        context.device.destroy_render_pass(render_pass);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,95 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Has to match `PushConstants` in main.rs
+layout(push_constant) uniform PushConstants {
+    // The size of the screen, in pixels
+    vec2 target_size;
+    // Non-zero when the swapchain is sRGB, and encodes what's written to it
+    uint srgb_target;
+} push_constants;
+
+layout(location = 0) out vec4 out_color;
+
+// How many rows of comparisons there are, and how much of each is left dark between them
+const float ROWS = 3.0;
+const float ROW_GAP = 0.06;
+
+// The two colours blended across the first row, in sRGB
+const vec3 BLEND_FROM = vec3(1.0, 0.0, 0.0);
+const vec3 BLEND_TO = vec3(0.0, 1.0, 0.0);
+
+// The colour of the ball in the last row, in sRGB, and how it's lit
+const vec3 ALBEDO = vec3(0.8, 0.45, 0.2);
+// Screen space, with y going down
+const vec3 SUN_DIRECTION = normalize(vec3(-0.6, -0.5, 0.6));
+const float AMBIENT = 0.05;
+
+vec3 srgb_to_linear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+vec3 linear_to_srgb(vec3 color) {
+    return mix(color * 12.92, 1.055 * pow(color, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, color));
+}
+
+// Black and white lines a pixel apart, which give off half as much light as white does
+// wherever they're too fine to make out
+vec3 lines() {
+    return vec3(mod(floor(gl_FragCoord.y), 2.0));
+}
+
+// The colour a row's comparison comes out, either done properly in linear space, or `naive`ly
+// on sRGB values as if they were linear. Both come back ready for the screen, in sRGB.
+vec3 row_color(int row, vec2 uv, bool naive) {
+    if (row == 0) {
+        // Blending: red to green goes through yellow, rather than a muddy brown
+        return naive
+            ? mix(BLEND_FROM, BLEND_TO, uv.x)
+            : linear_to_srgb(mix(srgb_to_linear(BLEND_FROM), srgb_to_linear(BLEND_TO), uv.x));
+    }
+    if (row == 1) {
+        // Brightness: the grey in the middle is half as bright as white, so it should match
+        // the lines either side of it
+        if (abs(uv.x - 0.5) > 0.25) {
+            return lines();
+        }
+        return naive ? vec3(0.5) : linear_to_srgb(vec3(0.5));
+    }
+    // Lighting: a ball lit by the sun from the top left fades smoothly into its shadow, instead
+    // of darkening too soon and ending in a hard edge
+    vec2 offset = (uv - 0.5) * 2.2;
+    float distance_squared = dot(offset, offset);
+    if (distance_squared > 1.0) {
+        return vec3(0.0);
+    }
+    vec3 normal = vec3(offset.x, offset.y, sqrt(1.0 - distance_squared));
+    float light = AMBIENT + max(dot(normal, SUN_DIRECTION), 0.0);
+    return naive ? ALBEDO * light : linear_to_srgb(srgb_to_linear(ALBEDO) * light);
+}
+
+void main() {
+    vec2 position = gl_FragCoord.xy / push_constants.target_size;
+    // The left half is right, and the right half naive, split by a thin line
+    bool naive = position.x >= 0.5;
+    float row_position = position.y * ROWS;
+    int row = int(row_position);
+    vec2 uv = vec2(fract(position.x * 2.0), fract(row_position));
+
+    vec3 color = vec3(0.0);
+    float gap = ROW_GAP * 0.5;
+    bool divider = abs(gl_FragCoord.x - push_constants.target_size.x * 0.5) < 1.0;
+    if (!divider && uv.y > gap && uv.y < 1.0 - gap) {
+        uv.y = (uv.y - gap) / (1.0 - ROW_GAP);
+        // Rows with a shape in them keep it round, whatever shape the window is
+        if (row == 2) {
+            float aspect = (push_constants.target_size.x * 0.5) / (push_constants.target_size.y / ROWS * (1.0 - ROW_GAP));
+            uv.x = (uv.x - 0.5) * aspect + 0.5;
+        }
+        color = row_color(row, uv, naive);
+    }
+
+    // Everything above is sRGB, the way it should look, so it's decoded again for an sRGB
+    // swapchain to encode
+    out_color = vec4(push_constants.srgb_target != 0 ? srgb_to_linear(color) : color, 1.0);
+}
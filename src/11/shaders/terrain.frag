@@ -0,0 +1,16 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+layout(location = 0) in vec3 frag_normal;
+layout(location = 1) in vec3 frag_color;
+
+layout(location = 0) out vec4 out_color;
+
+// The sun doesn't move in this chapter
+const vec3 SUN_DIRECTION = normalize(vec3(0.4, 1.0, 0.3));
+const float AMBIENT = 0.35;
+
+void main() {
+    float diffuse = max(dot(normalize(frag_normal), SUN_DIRECTION), 0.0);
+    out_color = vec4(frag_color * (AMBIENT + (1.0 - AMBIENT) * diffuse), 1.0);
+}
@@ -0,0 +1,79 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Draws a cube for every cell of the density field, straight out of the buffer `density.comp`
+// fills. Only the faces between ground and air are kept, and the rest are moved out of sight.
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+} camera;
+
+// Written by `density.comp` earlier in the frame
+layout(std430, set = 1, binding = 0) readonly buffer Density {
+    float density[];
+};
+
+// Has to match `GRID`
+const ivec3 GRID = ivec3(64, 32, 64);
+
+// A corner of a cube from 0 to 1, and the normal of the face it's part of
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+
+layout(location = 0) out vec3 frag_normal;
+layout(location = 1) out vec3 frag_color;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+// The colours of the ground, in sRGB
+const vec3 GRASS = vec3(0.36, 0.6, 0.24);
+const vec3 DIRT = vec3(0.5, 0.36, 0.24);
+const vec3 STONE = vec3(0.5, 0.5, 0.5);
+// How many cells under the surface the dirt goes before it turns to stone
+const int DIRT_DEPTH = 3;
+
+vec3 srgb_to_linear(vec3 color) {
+    return mix(color / 12.92, pow((color + 0.055) / 1.055, vec3(2.4)), step(0.04045, color));
+}
+
+// Whether there's ground in `cell`. Everything outside the field is air, so its edges are walled
+// off.
+bool solid(ivec3 cell) {
+    if (any(lessThan(cell, ivec3(0))) || any(greaterThanEqual(cell, GRID))) {
+        return false;
+    }
+    return density[cell.x + GRID.x * (cell.y + GRID.y * cell.z)] > 0.0;
+}
+
+void main() {
+    // Has to match `cell_index`
+    int instance = gl_InstanceIndex;
+    ivec3 cell = ivec3(instance % GRID.x, instance / GRID.x % GRID.y, instance / (GRID.x * GRID.y));
+
+    // Every vertex of a face agrees on this, so a hidden face collapses into a point outside the
+    // view and nothing is drawn for it
+    if (!solid(cell) || solid(cell + ivec3(normal))) {
+        gl_Position = vec4(2.0, 2.0, 2.0, 1.0);
+        frag_normal = normal;
+        frag_color = vec3(0.0);
+        return;
+    }
+
+    // The field is centred on the origin along x and z, and stands on y = 0
+    vec3 world = vec3(cell) + position - vec3(GRID.x / 2, 0, GRID.z / 2);
+    gl_Position = camera.view_projection * vec4(world, 1.0);
+    frag_normal = normal;
+
+    // Grass on top, dirt for a few cells under open air, and stone under that
+    vec3 color = STONE;
+    for (int depth = DIRT_DEPTH; depth > 0; depth--) {
+        if (!solid(cell + ivec3(0, depth, 0))) {
+            color = depth == 1 ? GRASS : DIRT;
+        }
+    }
+    // Darker the further down, so the layers of hills stand out from each other
+    float shade = 0.6 + 0.4 * float(cell.y) / float(GRID.y);
+    frag_color = srgb_to_linear(color) * shade;
+}
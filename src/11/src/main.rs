@@ -0,0 +1,838 @@
+extern crate gfx_hal as hal;
+#[macro_use]
+extern crate log;
+extern crate renderer_common;
+
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+use std::slice;
+use std::time::Instant;
+
+use hal::{
+    buffer, command, format as f, image as i, memory, pass, IndexType,
+    pso::{ self, PipelineStage },
+    queue::Supports,
+    Backend, Compute, Device,
+    Primitive, Submission,
+};
+
+use renderer_common::color::srgb_to_linear_rgba;
+use renderer_common::depth::choose_depth_format;
+use renderer_common::descriptors::{ DescriptorAllocator, DescriptorSetLayout, UniformRing };
+use renderer_common::events::WindowAction;
+use renderer_common::frame_sync;
+use renderer_common::math::ShaderMatrix;
+use renderer_common::msaa::choose_sample_count;
+use renderer_common::present;
+use renderer_common::screenshot;
+use renderer_common::shader::{ create_shader_module, ShaderSet };
+use renderer_common::timestep::TICK_SECONDS;
+use renderer_common::{
+    upload_buffer, AttachmentImages, Camera, CameraSwitch, Clock, CpuProfiler, DebugOverlay,
+    DeviceBuffer, Events, FixedTimestep, FpsCamera, FrameSync, Framebuffers, Gamepads, GfxContext,
+    GpuProfiler, Input, Interpolated, MemoryCategory, OrbitCamera, OverlaySettings, OverlayStats,
+    Result, Runner,
+};
+
+const CLEAR_COLOR: [f32; 4] = [0.55, 0.7, 0.9, 1.0];
+
+/// The SPIR-V for the shaders in `shaders/`, compiled by `build.rs`
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+/// How many cells the density field has along x, y and z. Has to match `GRID` in both shaders.
+const GRID: [u32; 3] = [64, 32, 64];
+const CELL_COUNT: u32 = GRID[0] * GRID[1] * GRID[2];
+
+/// How many cells along each axis one work group of `density.comp` fills. Has to match its
+/// `local_size`, and divide `GRID`.
+const GROUP_SIZE: u32 = 4;
+
+/// How many cells per second the field drifts through the noise along x, so that it's clear
+/// it's being filled in again every frame.
+const DRIFT: f32 = 1.5;
+
+/// Where cell `[x, y, z]`'s density is in the field. Has to match the shaders.
+fn cell_index(cell: [u32; 3]) -> usize {
+    (cell[0] + GRID[0] * (cell[1] + GRID[1] * cell[2])) as usize
+}
+
+/// Has to match the per vertex attributes in `terrain.vert`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct Vertex {
+    position: [f32; 3],
+    normal: [f32; 3],
+}
+
+/// The corners of a unit cube from the origin to 1 along each axis, which is one cell.
+const CUBE_CORNERS: [[f32; 3]; 8] = [
+    [0.0, 0.0, 0.0],
+    [1.0, 0.0, 0.0],
+    [1.0, 1.0, 0.0],
+    [0.0, 1.0, 0.0],
+    [0.0, 0.0, 1.0],
+    [1.0, 0.0, 1.0],
+    [1.0, 1.0, 1.0],
+    [0.0, 1.0, 1.0],
+];
+
+/// Each face as four indices into `CUBE_CORNERS`, along with its normal. The vertex shader
+/// decides whether to keep a face from which cell the normal points into.
+const CUBE_FACES: [([usize; 4], [f32; 3]); 6] = [
+    ([4, 5, 6, 7], [0.0, 0.0, 1.0]),
+    ([1, 0, 3, 2], [0.0, 0.0, -1.0]),
+    ([5, 1, 2, 6], [1.0, 0.0, 0.0]),
+    ([0, 4, 7, 3], [-1.0, 0.0, 0.0]),
+    ([7, 6, 2, 3], [0.0, 1.0, 0.0]),
+    ([0, 1, 5, 4], [0.0, -1.0, 0.0]),
+];
+
+fn cube_vertices() -> Vec<Vertex> {
+    CUBE_FACES
+        .iter()
+        .flat_map(|&(corners, normal)| {
+            corners.iter().map(move |&corner| Vertex { position: CUBE_CORNERS[corner], normal })
+        })
+        .collect()
+}
+
+fn cube_indices() -> Vec<u16> {
+    (0..CUBE_FACES.len() as u16)
+        .flat_map(|face| {
+            let base = face * 4;
+            vec![base, base + 1, base + 2, base + 2, base + 3, base]
+        })
+        .collect()
+}
+
+/// How high the ground comes up in the column of cells at `x` and `z`, going by `density`, as
+/// read back from the gpu. That's the top of the highest solid cell, so anything standing there
+/// stands on the surface.
+fn surface_height(density: &[f32], x: u32, z: u32) -> f32 {
+    (0..GRID[1])
+        .rev()
+        .find(|&y| density[cell_index([x, y, z])] > 0.0)
+        .map_or(0.0, |y| (y + 1) as f32)
+}
+
+/// Has to match the `PushConstants` block in `density.comp`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct DensityConstants {
+    offset: [f32; 3],
+    seed: u32,
+}
+
+impl DensityConstants {
+    /// Push constants are written as a slice of 32 bit words.
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const DensityConstants as *const u32,
+                mem::size_of::<DensityConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `DensityConstants`, in 32 bit words
+const DENSITY_CONSTANTS_SIZE: u32 = (mem::size_of::<DensityConstants>() / mem::size_of::<u32>()) as u32;
+
+/// Has to match the `Camera` block in `terrain.vert`. It's written once per frame, into that
+/// frame's copy of the uniform buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CameraUniform {
+    view_projection: ShaderMatrix,
+}
+
+/// Builds the compute pipeline that fills the density field, from `density.comp`. A compute
+/// pipeline is just the one shader and the layout of what it's bound to: there's no vertex
+/// input, rasterizer or render pass to describe.
+fn create_density_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::ComputePipeline> {
+    let module = create_shader_module::<B>(device, shaders.get("density.comp"))?;
+    let pipeline = device.create_compute_pipeline(
+        &pso::ComputePipelineDesc::new(
+            pso::EntryPoint {
+                entry: "main",
+                module: &module,
+                specialization: &[],
+            },
+            pipeline_layout,
+        ),
+        Some(pipeline_cache),
+    );
+    device.destroy_shader_module(module);
+
+    let pipeline = pipeline?;
+    debug!("Built the density pipeline");
+    Ok(pipeline)
+}
+
+/// Builds the graphics pipeline that draws the field's cells as cubes, from `terrain.vert` and
+/// `terrain.frag`.
+fn create_terrain_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let vs_module = create_shader_module::<B>(device, shaders.get("terrain.vert"))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get("terrain.frag"))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer {
+                cull_face: pso::Face::BACK,
+                front_face: pso::FrontFace::CounterClockwise,
+                ..pso::Rasterizer::FILL
+            },
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: true,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        // Has to match the sample count of the render pass attachments
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::ALPHA));
+
+        // Only the cube comes from a vertex buffer. Which cell each copy of it is for comes from
+        // the instance index, and whether it's solid from the density buffer.
+        pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+            binding: 0,
+            stride: mem::size_of::<Vertex>() as u32,
+            rate: 0,
+        });
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: 0,
+            binding: 0,
+            element: pso::Element {
+                format: f::Format::Rgb32Float,
+                offset: 0,
+            },
+        });
+        pipeline_desc.attributes.push(pso::AttributeDesc {
+            location: 1,
+            binding: 0,
+            element: pso::Element {
+                format: f::Format::Rgb32Float,
+                offset: 12,
+            },
+        });
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    // The modules are only needed while the pipeline is being built
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the terrain pipeline");
+    Ok(pipeline)
+}
+
+/// Records filling the density field with `pipeline`, starting `constants.offset` cells into the
+/// noise. Whatever reads the field afterwards needs a barrier from the compute shader stage
+/// first.
+fn record_density<B, C>(
+    command_buffer: &mut command::CommandBuffer<B, C, command::OneShot>,
+    pipeline: &B::ComputePipeline,
+    pipeline_layout: &B::PipelineLayout,
+    set: &B::DescriptorSet,
+    constants: &DensityConstants,
+) where
+    B: Backend,
+    C: Supports<Compute>,
+{
+    command_buffer.bind_compute_pipeline(pipeline);
+    command_buffer.bind_compute_descriptor_sets(pipeline_layout, 0, Some(set), &[]);
+    command_buffer.push_compute_constants(pipeline_layout, 0, constants.as_words());
+    // One invocation per cell, in groups of `GROUP_SIZE` cubed
+    command_buffer.dispatch([GRID[0] / GROUP_SIZE, GRID[1] / GROUP_SIZE, GRID[2] / GROUP_SIZE]);
+}
+
+/// The extra attachments that follow the swapchain image in each framebuffer, in the order
+/// `create_multisampled_render_pass` expects them.
+fn framebuffer_attachments<'a, B: Backend>(
+    msaa_targets: &'a Option<AttachmentImages<B>>,
+    depth_images: &'a AttachmentImages<B>,
+) -> Vec<&'a AttachmentImages<B>> {
+    msaa_targets.iter().chain(iter::once(depth_images)).collect()
+}
+
+fn main() {
+    renderer_common::launch("voxel-renderer", Chapter);
+}
+
+struct Chapter;
+
+impl Runner for Chapter {
+    fn run<B: Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<()> {
+        let mut cpu_profiler = CpuProfiler::new(context.args.cpu_trace.is_some());
+        let mut swapchain = context.create_swapchain()?;
+        let samples = choose_sample_count(&context.adapter, context.args.msaa)?;
+        let depth_format = choose_depth_format(&context.adapter)?;
+        info!("Depth format: {:?}, {}x MSAA", depth_format, samples);
+        let render_pass = renderer_common::pass::create_multisampled_render_pass::<B>(
+            &context.device,
+            swapchain.format(),
+            depth_format,
+            samples,
+        );
+
+        // With multisampling on we draw into a multisampled color target instead of the
+        // swapchain image, and the render pass resolves it into the swapchain image at the end
+        let mut msaa_targets = if samples > 1 {
+            Some(AttachmentImages::multisampled_color(
+                context.device.clone(),
+                context.allocator.clone(),
+                swapchain.format(),
+                samples,
+                &swapchain,
+            )?)
+        } else {
+            None
+        };
+        let mut depth_images = AttachmentImages::depth(
+            context.device.clone(),
+            context.allocator.clone(),
+            depth_format,
+            samples,
+            &swapchain,
+        )?;
+
+        let vertex_buffer = upload_buffer(
+            context,
+            &cube_vertices(),
+            buffer::Usage::VERTEX,
+            MemoryCategory::Meshes,
+        )?;
+        let index_buffer = upload_buffer(
+            context,
+            &cube_indices(),
+            buffer::Usage::INDEX,
+            MemoryCategory::Meshes,
+        )?;
+
+        // The density field only ever lives on the gpu: the compute shader writes it and the
+        // vertex shader reads it, as a storage buffer
+        let density_size = CELL_COUNT as u64 * mem::size_of::<f32>() as u64;
+        let density = DeviceBuffer::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            density_size,
+            buffer::Usage::STORAGE | buffer::Usage::TRANSFER_SRC,
+            memory::Properties::DEVICE_LOCAL,
+            MemoryCategory::Storage,
+        )?;
+
+        // The same set is bound to the compute pipeline, which writes through it, and to the
+        // graphics pipeline, which reads through it, so its binding is visible to both stages
+        let density_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![pso::DescriptorSetLayoutBinding {
+                binding: 0,
+                ty: pso::DescriptorType::StorageBuffer,
+                count: 1,
+                stage_flags: pso::ShaderStageFlags::COMPUTE | pso::ShaderStageFlags::VERTEX,
+                immutable_samplers: false,
+            }],
+        ));
+        let mut density_descriptors = DescriptorAllocator::new(context.device.clone(), density_layout.clone());
+        let density_set = density_descriptors.allocate()?;
+        context.device.write_descriptor_sets(Some(pso::DescriptorSetWrite {
+            set: &density_set,
+            binding: 0,
+            array_offset: 0,
+            descriptors: Some(pso::Descriptor::Buffer(density.buffer(), None..None)),
+        }));
+        let density_pipeline_layout = context.device.create_pipeline_layout(
+            Some(density_layout.raw()),
+            &[(pso::ShaderStageFlags::COMPUTE, 0..DENSITY_CONSTANTS_SIZE)],
+        );
+
+        // The terrain is drawn with the camera in set 0 and the density field in set 1
+        let camera_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![pso::DescriptorSetLayoutBinding {
+                binding: 0,
+                ty: pso::DescriptorType::UniformBuffer,
+                count: 1,
+                stage_flags: pso::ShaderStageFlags::VERTEX,
+                immutable_samplers: false,
+            }],
+        ));
+        let mut camera_descriptors = DescriptorAllocator::new(context.device.clone(), camera_layout.clone());
+        let terrain_pipeline_layout = context.device.create_pipeline_layout(
+            vec![camera_layout.raw(), density_layout.raw()],
+            iter::empty::<(pso::ShaderStageFlags, Range<u32>)>(),
+        );
+
+        let mut shaders = ShaderSet::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"), shaders::ALL);
+        let mut density_pipeline = create_density_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &density_pipeline_layout,
+            context.pipeline_cache.cache(),
+        )?;
+        let mut terrain_pipeline = create_terrain_pipeline::<B>(
+            &context.device,
+            &shaders,
+            &render_pass,
+            &terrain_pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+
+        // Fill the field once up front and read it back, to find where the ground is for the
+        // camera to start on. The copy into the readback buffer has to wait for the compute
+        // shader's writes, and the cpu's reads for the copy's.
+        let seed = context.args.seed.unwrap_or(context.config.settings().seed) as u32;
+        cpu_profiler.begin_scope("density");
+        let start_height = {
+            let readback = DeviceBuffer::new(
+                context.device.clone(),
+                context.allocator.clone(),
+                density_size,
+                buffer::Usage::TRANSFER_DST,
+                memory::Properties::CPU_VISIBLE,
+                MemoryCategory::Transient,
+            )?;
+            context.submit_one_shot(|command_buffer| {
+                let constants = DensityConstants { offset: [0.0; 3], seed };
+                record_density(command_buffer, &density_pipeline, &density_pipeline_layout, &density_set, &constants);
+                command_buffer.pipeline_barrier(
+                    PipelineStage::COMPUTE_SHADER..PipelineStage::TRANSFER,
+                    memory::Dependencies::empty(),
+                    &[memory::Barrier::Buffer {
+                        states: buffer::Access::SHADER_WRITE..buffer::Access::TRANSFER_READ,
+                        target: density.buffer(),
+                    }],
+                );
+                command_buffer.copy_buffer(
+                    density.buffer(),
+                    readback.buffer(),
+                    &[command::BufferCopy { src: 0, dst: 0, size: density_size }],
+                );
+                command_buffer.pipeline_barrier(
+                    PipelineStage::TRANSFER..PipelineStage::HOST,
+                    memory::Dependencies::empty(),
+                    &[memory::Barrier::Buffer {
+                        states: buffer::Access::TRANSFER_WRITE..buffer::Access::HOST_READ,
+                        target: readback.buffer(),
+                    }],
+                );
+            })?;
+            let field = readback.read::<f32>()?;
+            let solid = field.iter().filter(|&&density| density > 0.0).count();
+            info!("{} of the {} cells in the density field are solid", solid, CELL_COUNT);
+            surface_height(&field, GRID[0] / 2, GRID[2] / 2)
+        };
+        cpu_profiler.end_scope();
+        info!("Gpu memory: {}", context.allocator.borrow().stats());
+
+        let mut clock = Clock::new(context.is_headless());
+        let mut timestep = FixedTimestep::new();
+        let mut input = Input::new(&context.config.settings().bindings);
+        input.set_grab_on_focus(!context.is_headless());
+        let mut gamepads = Gamepads::new(!context.is_headless());
+        // When the clock last read, for how far the gamepad sticks turn each frame
+        let mut last_seconds = 0.0;
+        // How far the field has drifted, which is advanced a tick at a time like the camera
+        let mut drift = Interpolated::new(0.0);
+        // Standing on the ground in the middle of the field, or circling it from above
+        let mut camera = CameraSwitch::new(
+            FpsCamera::new([0.0, start_height + 1.7, 0.0], context.config.settings()),
+            OrbitCamera::new([0.0, GRID[1] as f32 * 0.4, 0.0], GRID[0] as f32, context.config.settings()),
+        );
+
+        let mut framebuffers = Framebuffers::with_attachments(
+            context.device.clone(),
+            &render_pass,
+            &swapchain,
+            &framebuffer_attachments(&msaa_targets, &depth_images),
+        )?;
+
+        // One clear value per attachment. The resolve target is never loaded, so its value
+        // doesn't matter.
+        let color_clear = command::ClearValue::Color(command::ClearColor::Float(srgb_to_linear_rgba(CLEAR_COLOR)));
+        let depth_clear = command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0));
+        let clear_values = if samples > 1 {
+            vec![color_clear.clone(), color_clear, depth_clear]
+        } else {
+            vec![color_clear, depth_clear]
+        };
+        let mut frame_sync = FrameSync::new(
+            context.device.clone(),
+            &context.queue_group,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        );
+        let mut gpu_profiler = GpuProfiler::new(
+            context.device.clone(),
+            &context.adapter,
+            frame_sync.frames_in_flight(),
+        );
+        let camera_uniforms = UniformRing::<B, CameraUniform>::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            &context.adapter,
+            &mut camera_descriptors,
+            0,
+            frame_sync.frames_in_flight(),
+        )?;
+        let mut overlay = DebugOverlay::new(context, &swapchain, frame_sync.frames_in_flight())?;
+        let mut last_timing_report = Instant::now();
+
+        let mut running = true;
+        let mut recreate_swapchain = false;
+        while running {
+            cpu_profiler.begin_frame();
+            let mut toggle_vsync = false;
+            let mut toggle_fullscreen = false;
+            let mut take_screenshot = false;
+            input.begin_frame();
+            events.poll_with(
+                |event| {
+                    overlay.handle_event(event);
+                    // While the cursor is grabbed it's hidden, so it can't be over the overlay
+                    if !input.cursor_grabbed() && overlay.captures(event) {
+                        return false;
+                    }
+                    input.handle_event(event)
+                },
+                |action| match action {
+                    WindowAction::Close => running = false,
+                    WindowAction::Resize => recreate_swapchain = true,
+                    WindowAction::ToggleVsync => toggle_vsync = true,
+                    WindowAction::ToggleFullscreen => toggle_fullscreen = true,
+                    WindowAction::Screenshot => take_screenshot = true,
+                },
+            );
+
+            if toggle_vsync {
+                context.toggle_vsync(&swapchain);
+                recreate_swapchain = true;
+            }
+            if toggle_fullscreen {
+                context.toggle_fullscreen();
+                recreate_swapchain = true;
+            }
+            if context.poll_config() {
+                recreate_swapchain = true;
+            }
+
+            // Rebuild whichever pipeline was built from a shader that was edited
+            let changed = shaders.poll_changes();
+            if !changed.is_empty() {
+                context.wait_idle()?;
+                if changed.iter().any(|name| name == "density.comp") {
+                    match create_density_pipeline::<B>(
+                        &context.device,
+                        &shaders,
+                        &density_pipeline_layout,
+                        context.pipeline_cache.cache(),
+                    ) {
+                        Ok(new_pipeline) => {
+                            let old_pipeline = mem::replace(&mut density_pipeline, new_pipeline);
+                            context.device.destroy_compute_pipeline(old_pipeline);
+                        }
+                        Err(err) => error!("Keeping the previous density pipeline: {}", err),
+                    }
+                }
+                if changed.iter().any(|name| name != "density.comp") {
+                    match create_terrain_pipeline::<B>(
+                        &context.device,
+                        &shaders,
+                        &render_pass,
+                        &terrain_pipeline_layout,
+                        context.pipeline_cache.cache(),
+                        samples,
+                    ) {
+                        Ok(new_pipeline) => {
+                            let old_pipeline = mem::replace(&mut terrain_pipeline, new_pipeline);
+                            context.device.destroy_graphics_pipeline(old_pipeline);
+                        }
+                        Err(err) => error!("Keeping the previous terrain pipeline: {}", err),
+                    }
+                }
+            }
+
+            if recreate_swapchain {
+                context.recreate_swapchain(&mut swapchain)?;
+                if let Some(ref mut msaa_targets) = msaa_targets {
+                    msaa_targets.recreate(&swapchain)?;
+                }
+                depth_images.recreate(&swapchain)?;
+                framebuffers.recreate_with_attachments(
+                    &render_pass,
+                    &swapchain,
+                    &framebuffer_attachments(&msaa_targets, &depth_images),
+                )?;
+                overlay.recreate(&swapchain)?;
+                recreate_swapchain = false;
+            }
+
+            // Run however many simulation ticks have come due since the last frame
+            cpu_profiler.begin_scope("simulate");
+            context.update_cursor_grab(input.cursor_grabbed());
+            input.apply_bindings(&context.config.settings().bindings);
+            let seconds = clock.frame_seconds();
+            gamepads.poll(&mut input, context.config.settings(), seconds - last_seconds);
+            last_seconds = seconds;
+            camera.apply_settings(context.config.settings());
+            camera.handle_input(&input);
+            for _ in 0..timestep.advance_to(seconds) {
+                camera.update(&input, TICK_SECONDS);
+                let drifted = drift.current() + DRIFT * TICK_SECONDS;
+                drift.set(drifted);
+            }
+            cpu_profiler.end_scope();
+
+            // Waits until the gpu is done with the last frame that used these resources
+            cpu_profiler.begin_scope("wait");
+            let mut frame = frame_sync.begin_frame()?;
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("acquire");
+            let image_index = match swapchain.acquire_image(&mut frame, &mut context.queue_group.queues[0]) {
+                Ok(image_index) => image_index,
+                Err(_) => {
+                    recreate_swapchain = true;
+                    continue;
+                }
+            };
+            cpu_profiler.end_scope();
+
+            // The pipeline doesn't bake in a viewport, so we set it (and the scissor) every
+            // frame, which also means we don't need to rebuild the pipeline on resize.
+            let viewport = swapchain.viewport();
+
+            let vsync = present::is_vsync(swapchain.present_mode());
+            let mut overlay_settings = OverlaySettings {
+                vsync,
+                wireframe: None,
+                shadows: None,
+                show_cascades: None,
+                occlusion_culling: None,
+                mesher: None,
+                shading: None,
+                view_mode: None,
+                debug_lines: None,
+                time_of_day: None,
+            };
+
+            cpu_profiler.begin_scope("record");
+            let finished_command_buffer = {
+                let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+                gpu_profiler.begin_frame(&mut command_buffer, frame.index)?;
+
+                // Draw in between the last two ticks, however far the clock is through the next
+                let alpha = timestep.alpha();
+                let extent = swapchain.extent();
+                let aspect = extent.width as f32 / extent.height as f32;
+                let view_projection = camera.interpolated_view_projection(aspect, alpha).into();
+                camera_uniforms.update(frame.index, &CameraUniform { view_projection })?;
+
+                // There's only one density field, so the frame before this one has to be done
+                // drawing from it before it's filled in again. It was submitted to the same
+                // queue, so a barrier is enough, and only the stages need to wait: nothing it
+                // wrote has to be made visible.
+                gpu_profiler.begin_scope(&mut command_buffer, "density");
+                command_buffer.pipeline_barrier(
+                    PipelineStage::VERTEX_SHADER..PipelineStage::COMPUTE_SHADER,
+                    memory::Dependencies::empty(),
+                    &[memory::Barrier::Buffer {
+                        states: buffer::Access::SHADER_READ..buffer::Access::SHADER_WRITE,
+                        target: density.buffer(),
+                    }],
+                );
+                let constants = DensityConstants { offset: [drift.get(alpha), 0.0, 0.0], seed };
+                record_density(
+                    &mut command_buffer,
+                    &density_pipeline,
+                    &density_pipeline_layout,
+                    &density_set,
+                    &constants,
+                );
+                // And the vertex shader can't read the field until the compute shader has
+                // finished writing it, and its writes are visible to shader reads
+                command_buffer.pipeline_barrier(
+                    PipelineStage::COMPUTE_SHADER..PipelineStage::VERTEX_SHADER,
+                    memory::Dependencies::empty(),
+                    &[memory::Barrier::Buffer {
+                        states: buffer::Access::SHADER_WRITE..buffer::Access::SHADER_READ,
+                        target: density.buffer(),
+                    }],
+                );
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                gpu_profiler.begin_scope(&mut command_buffer, "terrain");
+                {
+                    command_buffer.set_viewports(0, &[viewport.clone()]);
+                    command_buffer.set_scissors(0, &[viewport.rect]);
+                    command_buffer.bind_graphics_pipeline(&terrain_pipeline);
+                    command_buffer.bind_graphics_descriptor_sets(
+                        &terrain_pipeline_layout,
+                        0,
+                        vec![camera_uniforms.set(frame.index), &density_set],
+                        &[],
+                    );
+                    command_buffer.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(vertex_buffer.buffer(), 0)]));
+                    command_buffer.bind_index_buffer(buffer::IndexBufferView {
+                        buffer: index_buffer.buffer(),
+                        offset: 0,
+                        index_type: IndexType::U16,
+                    });
+
+                    let mut encoder = command_buffer.begin_render_pass_inline(
+                        &render_pass,
+                        framebuffers.get(image_index),
+                        viewport.rect,
+                        &clear_values,
+                    );
+                    // A cube for every cell. Which of their faces get drawn is only known on the
+                    // gpu, so there's no triangle count for the overlay.
+                    let index_count = (CUBE_FACES.len() * 6) as u32;
+                    encoder.draw_indexed(0..index_count, 0, 0..CELL_COUNT);
+                }
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                // The overlay goes on top of the resolved image, in a pass of its own
+                gpu_profiler.begin_scope(&mut command_buffer, "overlay");
+                {
+                    let stats = OverlayStats {
+                        gpu_timings: gpu_profiler.timings(),
+                        camera_position: Some(camera.position()),
+                        ..OverlayStats::default()
+                    };
+                    overlay.draw(
+                        &mut command_buffer,
+                        image_index,
+                        &swapchain,
+                        &stats,
+                        &mut overlay_settings,
+                    )?;
+                }
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                command_buffer.finish()
+            };
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("submit");
+            let submission = Submission::new()
+                .wait_on(&[(frame.image_available(), PipelineStage::COLOR_ATTACHMENT_OUTPUT)])
+                .signal(&[frame.render_finished()])
+                .submit(Some(finished_command_buffer));
+            context.device.reset_fence(frame.fence());
+            context.queue_group.queues[0].submit(submission, Some(frame.fence()));
+            cpu_profiler.end_scope();
+
+            if take_screenshot {
+                match screenshot::capture(context, &swapchain, image_index) {
+                    Ok(path) => info!("Saved a screenshot to {}", path.display()),
+                    Err(err) => error!("{}", err),
+                }
+            }
+
+            cpu_profiler.begin_scope("present");
+            if swapchain.present(&frame, &mut context.queue_group.queues[0], image_index).is_err() {
+                recreate_swapchain = true;
+            }
+            cpu_profiler.end_scope();
+
+            // The swapchain can't change mid-frame, so a vsync toggle from the overlay takes
+            // effect on the next one
+            if overlay_settings.vsync != vsync {
+                context.set_vsync(overlay_settings.vsync);
+                recreate_swapchain = true;
+            }
+
+            if last_timing_report.elapsed().as_secs() >= 1 {
+                for timing in gpu_profiler.timings() {
+                    debug!("Gpu time for {}: {:.3} ms", timing.name, timing.milliseconds);
+                }
+                debug!("Cpu time for the last frame:\n{}", cpu_profiler.report());
+                last_timing_report = Instant::now();
+            }
+        }
+
+        context.wait_idle()?;
+
+        if let Some(ref path) = context.args.cpu_trace {
+            cpu_profiler.write_chrome_trace(path)?;
+            info!("Wrote the cpu trace to {}", path.display());
+        }
+
+        drop(overlay);
+        drop(framebuffers);
+        drop(depth_images);
+        drop(msaa_targets);
+        drop(vertex_buffer);
+        drop(index_buffer);
+        drop(density);
+        drop(camera_uniforms);
+        drop(camera_descriptors);
+        drop(density_descriptors);
+        context.device.destroy_compute_pipeline(density_pipeline);
+        context.device.destroy_graphics_pipeline(terrain_pipeline);
+        context.device.destroy_pipeline_layout(density_pipeline_layout);
+        context.device.destroy_pipeline_layout(terrain_pipeline_layout);
+        context.device.destroy_render_pass(render_pass);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,52 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Shades the blocks however they were meshed, from the atlas, their ambient occlusion and their
+// light.
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    vec3 region_origin;
+    uint atlas_columns;
+    vec2 atlas_origin;
+    vec2 atlas_cell;
+    vec2 atlas_tile_size;
+} camera;
+
+layout(set = 0, binding = 1) uniform texture2D atlas_texture;
+layout(set = 0, binding = 2) uniform sampler atlas_sampler;
+
+layout(location = 0) in vec3 frag_normal;
+layout(location = 1) in vec2 frag_uv;
+layout(location = 2) flat in uint frag_tile;
+layout(location = 3) in float frag_ao;
+layout(location = 4) in vec2 frag_light;
+
+layout(location = 0) out vec4 out_color;
+
+// The sun doesn't move in this chapter
+const vec3 SUN_DIRECTION = normalize(vec3(0.4, 1.0, 0.3));
+const float AMBIENT = 0.5;
+// How bright it is with no light at all, so caves aren't pitch black
+const float MIN_LIGHT = 0.04;
+
+void main() {
+    // The uvs count blocks across the face, so a quad covering several blocks repeats its tile.
+    // The gradients come from the unwrapped uvs, so the wrap doesn't pick the smallest mip.
+    vec2 cell = vec2(frag_tile % camera.atlas_columns, frag_tile / camera.atlas_columns);
+    vec2 tile_origin = camera.atlas_origin + cell * camera.atlas_cell;
+    vec2 scaled_uv = frag_uv * camera.atlas_tile_size;
+    vec4 texel = textureGrad(
+        sampler2D(atlas_texture, atlas_sampler),
+        tile_origin + fract(frag_uv) * camera.atlas_tile_size,
+        dFdx(scaled_uv),
+        dFdy(scaled_uv)
+    );
+
+    float diffuse = max(dot(normalize(frag_normal), SUN_DIRECTION), 0.0);
+    float sky = frag_light.x * (AMBIENT + (1.0 - AMBIENT) * diffuse);
+    float light = max(max(sky, frag_light.y), MIN_LIGHT);
+    // Ambient occlusion only darkens the corners by so much, or they'd go black
+    float occlusion = 0.4 + 0.6 * frag_ao;
+    out_color = vec4(texel.rgb * light * occlusion, 1.0);
+}
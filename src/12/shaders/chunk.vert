@@ -0,0 +1,47 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Draws the meshes the cpu made, a chunk at a time, from `ChunkVertex`es. Makes the same outputs
+// as `quads.vert`, for `chunk.frag`.
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    vec3 region_origin;
+    uint atlas_columns;
+    vec2 atlas_origin;
+    vec2 atlas_cell;
+    vec2 atlas_tile_size;
+} camera;
+
+// Has to match `ChunkPushConstants`
+layout(push_constant) uniform PushConstants {
+    // Where the chunk starts in the world, in blocks
+    vec3 chunk_origin;
+} constants;
+
+// Has to match `ChunkVertex`
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec3 normal;
+layout(location = 2) in vec2 uv;
+layout(location = 3) in uint tile;
+layout(location = 4) in float ao;
+layout(location = 5) in vec2 light;
+
+layout(location = 0) out vec3 frag_normal;
+layout(location = 1) out vec2 frag_uv;
+layout(location = 2) flat out uint frag_tile;
+layout(location = 3) out float frag_ao;
+layout(location = 4) out vec2 frag_light;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main() {
+    gl_Position = camera.view_projection * vec4(constants.chunk_origin + position, 1.0);
+    frag_normal = normal;
+    frag_uv = uv;
+    frag_tile = tile;
+    frag_ao = ao;
+    frag_light = light;
+}
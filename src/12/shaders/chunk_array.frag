@@ -0,0 +1,42 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Shades the blocks like `chunk.frag`, with each tile a layer of an array texture instead of a
+// cell of the atlas.
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    vec3 region_origin;
+    uint atlas_columns;
+    vec2 atlas_origin;
+    vec2 atlas_cell;
+    vec2 atlas_tile_size;
+} camera;
+
+layout(set = 0, binding = 1) uniform texture2DArray tile_textures;
+layout(set = 0, binding = 2) uniform sampler tile_sampler;
+
+layout(location = 0) in vec3 frag_normal;
+layout(location = 1) in vec2 frag_uv;
+layout(location = 2) flat in uint frag_tile;
+layout(location = 3) in float frag_ao;
+layout(location = 4) in vec2 frag_light;
+
+layout(location = 0) out vec4 out_color;
+
+// Has to match `chunk.frag`
+const vec3 SUN_DIRECTION = normalize(vec3(0.4, 1.0, 0.3));
+const float AMBIENT = 0.5;
+const float MIN_LIGHT = 0.04;
+
+void main() {
+    // The sampler repeats the tile across a quad covering several blocks by itself, and the
+    // tile is the layer
+    vec4 texel = texture(sampler2DArray(tile_textures, tile_sampler), vec3(frag_uv, float(frag_tile)));
+
+    float diffuse = max(dot(normalize(frag_normal), SUN_DIRECTION), 0.0);
+    float sky = frag_light.x * (AMBIENT + (1.0 - AMBIENT) * diffuse);
+    float light = max(max(sky, frag_light.y), MIN_LIGHT);
+    float occlusion = 0.4 + 0.6 * frag_ao;
+    out_color = vec4(texel.rgb * light * occlusion, 1.0);
+}
@@ -0,0 +1,61 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Draws the meshes the cpu made, a chunk at a time, from `PackedVertex`es. Makes the same
+// outputs as `chunk.vert`.
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    vec3 region_origin;
+    uint atlas_columns;
+    vec2 atlas_origin;
+    vec2 atlas_cell;
+    vec2 atlas_tile_size;
+} camera;
+
+// Has to match `ChunkPushConstants`
+layout(push_constant) uniform PushConstants {
+    vec3 chunk_origin;
+} constants;
+
+// Has to match `PackedVertex`
+layout(location = 0) in uvec3 packed_vertex;
+
+layout(location = 0) out vec3 frag_normal;
+layout(location = 1) out vec2 frag_uv;
+layout(location = 2) flat out uint frag_tile;
+layout(location = 3) out float frag_ao;
+layout(location = 4) out vec2 frag_light;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+// Has to match `LIGHT_STEPS`
+const float LIGHT_STEPS = 15.0 * 12.0;
+
+// In the order of `Direction::ALL`
+const vec3 NORMALS[6] = vec3[](
+    vec3(1.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0),
+    vec3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0),
+    vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0)
+);
+
+// The bytes of a word, from the lowest
+uvec4 bytes(uint word) {
+    return uvec4(word, word >> 8, word >> 16, word >> 24) & 0xffu;
+}
+
+void main() {
+    uvec4 first = bytes(packed_vertex.x);
+    uvec4 second = bytes(packed_vertex.y);
+    uvec4 third = bytes(packed_vertex.z);
+
+    vec3 position = vec3(first.xyz);
+    gl_Position = camera.view_projection * vec4(constants.chunk_origin + position, 1.0);
+    frag_normal = NORMALS[first.w & 7u];
+    frag_uv = vec2(second.xy);
+    frag_tile = packed_vertex.y >> 16;
+    frag_ao = float(first.w >> 3) / 3.0;
+    frag_light = vec2(third.xy) / LIGHT_STEPS;
+}
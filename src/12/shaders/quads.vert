@@ -0,0 +1,104 @@
+#version 450
+#extension GL_ARB_separate_shader_objects : enable
+
+// Draws the quads `mesh.comp` made, one instance of six vertices each, building each corner the
+// way `ChunkMesh::push_quad` does for a quad one block across.
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+    // Where the region starts in the world, in blocks
+    vec3 region_origin;
+    uint atlas_columns;
+    vec2 atlas_origin;
+    vec2 atlas_cell;
+    vec2 atlas_tile_size;
+} camera;
+
+// Written by `mesh.comp` earlier in the frame
+layout(std430, set = 1, binding = 2) readonly buffer Quads {
+    uvec4 quads[];
+};
+
+layout(location = 0) out vec3 frag_normal;
+layout(location = 1) out vec2 frag_uv;
+layout(location = 2) flat out uint frag_tile;
+layout(location = 3) out float frag_ao;
+layout(location = 4) out vec2 frag_light;
+
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+// Has to match `LIGHT_STEPS`
+const float LIGHT_STEPS = 15.0 * 12.0;
+
+// In the order of `Direction::ALL`
+const vec3 NORMALS[6] = vec3[](
+    vec3(1.0, 0.0, 0.0), vec3(-1.0, 0.0, 0.0),
+    vec3(0.0, 1.0, 0.0), vec3(0.0, -1.0, 0.0),
+    vec3(0.0, 0.0, 1.0), vec3(0.0, 0.0, -1.0)
+);
+
+// The axes each direction's faces lie along, as `face_axes` gives them
+const vec3 U_AXES[3] = vec3[](vec3(0.0, 1.0, 0.0), vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0));
+const vec3 V_AXES[3] = vec3[](vec3(0.0, 0.0, 1.0), vec3(1.0, 0.0, 0.0), vec3(0.0, 1.0, 0.0));
+
+// Where each corner is along the face's axes, going round counter-clockwise seen from in front,
+// for positive and then negative directions. Which of the ambient occlusion and light values
+// goes with each corner is the index of the one it's at in the positive order.
+const vec2 CORNERS[4] = vec2[](vec2(0.0, 0.0), vec2(1.0, 0.0), vec2(1.0, 1.0), vec2(0.0, 1.0));
+const uint NEGATIVE_ORDER[4] = uint[](0u, 3u, 2u, 1u);
+
+// The two triangles of a quad, split along one diagonal or the other
+const uint SPLIT[6] = uint[](0u, 1u, 2u, 2u, 3u, 0u);
+const uint FLIPPED[6] = uint[](1u, 2u, 3u, 3u, 0u, 1u);
+
+// Has to match `texture_coordinates` for a quad one block across
+vec2 texture_coordinates(uint direction, vec2 corner) {
+    float u = corner.x;
+    float v = corner.y;
+    switch (direction) {
+        case 0u: return vec2(1.0 - v, 1.0 - u);
+        case 1u: return vec2(v, 1.0 - u);
+        case 2u: return vec2(v, u);
+        case 3u: return vec2(v, 1.0 - u);
+        case 4u: return vec2(u, 1.0 - v);
+        default: return vec2(1.0 - u, 1.0 - v);
+    }
+}
+
+void main() {
+    uvec4 quad = quads[gl_InstanceIndex];
+    uvec3 block = uvec3(quad.x & 1023u, (quad.x >> 10) & 1023u, (quad.x >> 20) & 1023u);
+    uint tile = quad.y & 0xffffu;
+    uint direction = (quad.y >> 16) & 7u;
+    uint ao = quad.y >> 20;
+    uint axis = direction / 2u;
+    bool positive = direction % 2u == 0u;
+
+    // The ambient occlusion at each corner in the order they go round, to split the quad along
+    // its brighter diagonal, like `push_quad`
+    uint levels[4];
+    for (uint index = 0u; index < 4u; index++) {
+        uint corner = positive ? index : NEGATIVE_ORDER[index];
+        levels[index] = (ao >> (corner * 2u)) & 3u;
+    }
+    bool flip = levels[1] + levels[3] > levels[0] + levels[2];
+    uint index = flip ? FLIPPED[gl_VertexIndex] : SPLIT[gl_VertexIndex];
+    uint corner = positive ? index : NEGATIVE_ORDER[index];
+    vec2 along = CORNERS[corner];
+
+    vec3 base = vec3(block);
+    if (positive) {
+        base[axis] += 1.0;
+    }
+    vec3 position = camera.region_origin + base + U_AXES[axis] * along.x + V_AXES[axis] * along.y;
+    gl_Position = camera.view_projection * vec4(position, 1.0);
+
+    frag_normal = NORMALS[direction];
+    frag_uv = texture_coordinates(direction, along);
+    frag_tile = tile;
+    frag_ao = float((ao >> (corner * 2u)) & 3u) / 3.0;
+    uvec2 light = uvec2(quad.z, quad.w) >> (corner * 8u) & 0xffu;
+    frag_light = vec2(light) / LIGHT_STEPS;
+}
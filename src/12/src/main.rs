@@ -0,0 +1,1103 @@
+extern crate gfx_hal as hal;
+#[macro_use]
+extern crate log;
+extern crate renderer_common;
+
+use std::iter;
+use std::mem;
+use std::ops::Range;
+use std::rc::Rc;
+use std::slice;
+use std::time::Instant;
+
+use hal::{
+    buffer, command, format as f, image as i, pass, IndexType,
+    pso::{ self, PipelineStage },
+    Backend, Device,
+    Primitive, Submission,
+};
+
+use renderer_common::atlas::{ AtlasBuilder, AtlasGrid, TextureAtlas, TileArray };
+use renderer_common::color::srgb_to_linear_rgba;
+use renderer_common::depth::choose_depth_format;
+use renderer_common::descriptors::{ DescriptorAllocator, DescriptorSetLayout, UniformRing };
+use renderer_common::events::WindowAction;
+use renderer_common::frame_sync;
+use renderer_common::math::ShaderMatrix;
+use renderer_common::mesh_optimizer;
+use renderer_common::msaa::choose_sample_count;
+use renderer_common::present;
+use renderer_common::screenshot;
+use renderer_common::shader::{ create_shader_module, ShaderSet };
+use renderer_common::timestep::TICK_SECONDS;
+use renderer_common::vertex_format::pack_vertices;
+use renderer_common::worldgen::HEIGHT_IN_CHUNKS;
+use renderer_common::{
+    upload_buffer, AttachmentImages, BlockId, BlockLights, BlockTextures, Camera, CameraSwitch,
+    ChunkCoord, ChunkNeighborhood, ChunkVertex, Clock, CpuProfiler, DebugOverlay, DeviceBuffer,
+    Events, FixedTimestep, FpsCamera, FrameSync, Framebuffers, Gamepads, GfxContext, GpuMesher,
+    GpuProfiler, Input, Lighting, MemoryCategory, MeshOptimization, MeshRegion, Mesher, OrbitCamera,
+    OverlaySettings, OverlayStats, PackedVertex, PendingEdits, Result, Runner, Surface,
+    TerrainBlocks, Texture, VertexFormat, World, WorldGenerator,
+};
+
+const CLEAR_COLOR: [f32; 4] = [0.55, 0.7, 0.9, 1.0];
+
+/// The SPIR-V for the shaders in `shaders/`, compiled by `build.rs`
+mod shaders {
+    include!(concat!(env!("OUT_DIR"), "/shaders.rs"));
+}
+
+const STONE: BlockId = BlockId(1);
+const DIRT: BlockId = BlockId(2);
+const GRASS: BlockId = BlockId(3);
+const SAND: BlockId = BlockId(4);
+const SNOW: BlockId = BlockId(5);
+const LOG: BlockId = BlockId(6);
+const LEAVES: BlockId = BlockId(7);
+const WATER: BlockId = BlockId(8);
+
+/// The width and height of each block texture, in pixels.
+const TILE_SIZE: u32 = 16;
+
+/// How many chunks across the region that's meshed is, along x and z. It goes all the way up.
+const REGION_CHUNKS: u32 = 6;
+
+/// How many quads the gpu mesher has room for, which is a good few more than the region ever
+/// needs.
+const MAX_QUADS: u32 = 1 << 20;
+
+/// A tile of `color` with some noise in it, so the faces of neighbouring blocks of the same
+/// kind can be told apart. `seed` gives each tile a different pattern.
+fn noisy_tile(color: [u8; 3], seed: u32) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((TILE_SIZE * TILE_SIZE * 4) as usize);
+    for y in 0..TILE_SIZE {
+        for x in 0..TILE_SIZE {
+            // A cheap integer hash is plenty for this
+            let mut hash = x.wrapping_mul(73_856_093) ^ y.wrapping_mul(19_349_663) ^ seed.wrapping_mul(83_492_791);
+            hash = (hash ^ (hash >> 13)).wrapping_mul(1_274_126_177);
+            let brightness = 0.8 + 0.2 * ((hash >> 24) as f32 / 255.0);
+            for &channel in &color {
+                pixels.push((channel as f32 * brightness) as u8);
+            }
+            pixels.push(255);
+        }
+    }
+    pixels
+}
+
+/// The side of a block like grass or snow that covers another: `base`, with a band of `cap`
+/// along the top. `seed` and `seed + 1` pick their patterns.
+fn capped_side_tile(cap: [u8; 3], base: [u8; 3], seed: u32) -> Vec<u8> {
+    let mut pixels = noisy_tile(base, seed);
+    let top = noisy_tile(cap, seed + 1);
+    let band = (TILE_SIZE * 3 * 4) as usize;
+    pixels[..band].copy_from_slice(&top[..band]);
+    pixels
+}
+
+/// Adds the block textures to `atlas` and says which faces of which blocks use them. The leaves
+/// are solid here, so that everything but the water is opaque and both meshers make the same
+/// faces of it.
+fn block_textures(atlas: &mut AtlasBuilder) -> Result<BlockTextures> {
+    let grass = [96, 160, 64];
+    let dirt = [134, 96, 67];
+    let stone = [128, 128, 128];
+    let snow = [235, 240, 245];
+    let stone_tile = atlas.add_rgba8("stone", noisy_tile(stone, 1))?;
+    let dirt_tile = atlas.add_rgba8("dirt", noisy_tile(dirt, 2))?;
+    let grass_side = atlas.add_rgba8("grass_side", capped_side_tile(grass, dirt, 3))?;
+    let grass_top = atlas.add_rgba8("grass_top", noisy_tile(grass, 5))?;
+    let sand_tile = atlas.add_rgba8("sand", noisy_tile([219, 203, 146], 6))?;
+    let snow_side = atlas.add_rgba8("snow_side", capped_side_tile(snow, stone, 7))?;
+    let snow_top = atlas.add_rgba8("snow_top", noisy_tile(snow, 9))?;
+    let bark = atlas.add_rgba8("bark", noisy_tile([102, 76, 48], 10))?;
+    let log_end = atlas.add_rgba8("log_end", noisy_tile([168, 134, 88], 11))?;
+    let leaves = atlas.add_rgba8("leaves", noisy_tile([58, 118, 44], 12))?;
+
+    let mut textures = BlockTextures::new();
+    textures.set_all(STONE, stone_tile);
+    textures.set_all(DIRT, dirt_tile);
+    textures.set_top_bottom_sides(GRASS, grass_top, dirt_tile, grass_side);
+    textures.set_all(SAND, sand_tile);
+    textures.set_top_bottom_sides(SNOW, snow_top, stone_tile, snow_side);
+    textures.set_top_bottom_sides(LOG, log_end, log_end, bark);
+    textures.set_all(LEAVES, leaves);
+    textures.set_surface(WATER, Surface::Water);
+    Ok(textures)
+}
+
+/// Which blocks let light through. Nothing in this chapter gives any off.
+fn block_lights() -> BlockLights {
+    let mut lights = BlockLights::new();
+    lights.set_transparent(LEAVES);
+    lights.set_transparent(WATER);
+    lights
+}
+
+/// Has to match the `Camera` block in each shader. It's written once per frame, into that
+/// frame's copy of the uniform buffer.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct CameraUniform {
+    view_projection: ShaderMatrix,
+    region_origin: [f32; 3],
+    atlas_columns: u32,
+    atlas_origin: [f32; 2],
+    atlas_cell: [f32; 2],
+    atlas_tile_size: [f32; 2],
+}
+
+/// The block textures, as an array texture with a layer per tile where the adapter can sample
+/// one, or as the atlas where it can't. Either way a face's tile picks its texture, so all the
+/// blocks are drawn with one set bound.
+enum TileImages<B: Backend> {
+    Array(TileArray<B>),
+    Atlas(TextureAtlas<B>),
+}
+
+impl<B: Backend> TileImages<B> {
+    fn build(context: &mut GfxContext<B>, builder: AtlasBuilder) -> Result<Self> {
+        if builder.supports_array(context) {
+            info!("Sampling the block textures from an array texture");
+            Ok(TileImages::Array(builder.build_array(context)?))
+        } else {
+            info!("The adapter can't sample the block textures from an array texture, so they're in an atlas");
+            Ok(TileImages::Atlas(builder.build(context)?))
+        }
+    }
+
+    fn texture(&self) -> &Texture<B> {
+        match *self {
+            TileImages::Array(ref array) => &array.texture,
+            TileImages::Atlas(ref atlas) => &atlas.texture,
+        }
+    }
+
+    /// Where the tiles are in the atlas. An array doesn't need one, so it's a single cell that
+    /// covers the whole texture.
+    fn grid(&self) -> AtlasGrid {
+        match *self {
+            TileImages::Array(_) => AtlasGrid { columns: 1, origin: [0.0; 2], cell: [1.0; 2], size: [1.0; 2] },
+            TileImages::Atlas(ref atlas) => atlas.layout.grid(),
+        }
+    }
+
+    /// The fragment shader that samples them.
+    fn fragment_shader(&self) -> &'static str {
+        match *self {
+            TileImages::Array(_) => "chunk_array.frag",
+            TileImages::Atlas(_) => "chunk.frag",
+        }
+    }
+}
+
+/// Has to match the `PushConstants` block in `chunk.vert` and `chunk_packed.vert`.
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+struct ChunkPushConstants {
+    chunk_origin: [f32; 3],
+}
+
+impl ChunkPushConstants {
+    /// Push constants are written as a slice of 32 bit words.
+    fn as_words(&self) -> &[u32] {
+        unsafe {
+            slice::from_raw_parts(
+                self as *const ChunkPushConstants as *const u32,
+                mem::size_of::<ChunkPushConstants>() / mem::size_of::<u32>(),
+            )
+        }
+    }
+}
+
+/// The size of `ChunkPushConstants`, in 32 bit words
+const CHUNK_PUSH_CONSTANTS_SIZE: u32 = (mem::size_of::<ChunkPushConstants>() / mem::size_of::<u32>()) as u32;
+
+/// The vertex shader that reads chunk vertices laid out in `format`.
+fn chunk_vertex_shader(format: VertexFormat) -> &'static str {
+    match format {
+        VertexFormat::Full => "chunk.vert",
+        VertexFormat::Packed => "chunk_packed.vert",
+    }
+}
+
+/// One chunk's part of a `CpuMesh`.
+struct ChunkDraw {
+    origin: [f32; 3],
+    indices: Range<u32>,
+    base_vertex: i32,
+}
+
+/// The region's opaque faces meshed on the cpu, all in one pair of buffers and drawn a chunk at
+/// a time. The vertices are relative to their chunk, which packed vertices need.
+struct CpuMesh<B: Backend> {
+    vertices: DeviceBuffer<B>,
+    indices: DeviceBuffer<B>,
+    draws: Vec<ChunkDraw>,
+    /// Which mesher made it.
+    mesher: Mesher,
+    /// Which layout the vertices are in.
+    format: VertexFormat,
+    /// What optimizing the chunks' meshes did, if they were.
+    optimization: Option<MeshOptimization>,
+    vertex_count: usize,
+    triangle_count: usize,
+}
+
+impl<B: Backend> CpuMesh<B> {
+    /// Meshes every chunk in `region` of `world` with `mesher`, optimizes the meshes if
+    /// `optimize` is set, and uploads them in `format`. This waits for the uploads, and says in
+    /// the log how long the meshing took.
+    fn new(
+        context: &mut GfxContext<B>,
+        world: &World,
+        region: MeshRegion,
+        textures: &BlockTextures,
+        mesher: Mesher,
+        format: VertexFormat,
+        optimize: bool,
+    ) -> Result<Self> {
+        let started = Instant::now();
+        let mut vertices: Vec<ChunkVertex> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        let mut draws = Vec::new();
+        let mut optimization = MeshOptimization::default();
+        for coord in region.coords() {
+            let neighborhood = match ChunkNeighborhood::from_world(world, coord) {
+                Some(neighborhood) => neighborhood,
+                None => continue,
+            };
+            let mut mesh = mesher.mesh(&neighborhood, textures);
+            if mesh.indices.is_empty() {
+                continue;
+            }
+            if optimize {
+                optimization += mesh_optimizer::optimize(&mut mesh);
+            }
+            let origin = coord.origin();
+            let first_index = indices.len() as u32;
+            draws.push(ChunkDraw {
+                origin: [origin[0] as f32, origin[1] as f32, origin[2] as f32],
+                indices: first_index..first_index + mesh.indices.len() as u32,
+                base_vertex: vertices.len() as i32,
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+        let elapsed = started.elapsed();
+        info!(
+            "Meshed {} chunks on the cpu with the {} mesher in {:.1} ms, making {} triangles",
+            region.chunk_count(),
+            mesher,
+            elapsed.as_secs() as f64 * 1000.0 + elapsed.subsec_nanos() as f64 / 1e6,
+            indices.len() / 3,
+        );
+        if optimize {
+            info!(
+                "Optimizing them took the vertices from {} to {} and the ACMR from {:.2} to {:.2}",
+                optimization.before.vertices,
+                optimization.after.vertices,
+                optimization.before.acmr(),
+                optimization.after.acmr(),
+            );
+        }
+
+        let vertex_buffer = match format {
+            VertexFormat::Full => upload_buffer(
+                context,
+                &vertices,
+                buffer::Usage::VERTEX,
+                MemoryCategory::Meshes,
+            )?,
+            VertexFormat::Packed => upload_buffer(
+                context,
+                &pack_vertices(&vertices),
+                buffer::Usage::VERTEX,
+                MemoryCategory::Meshes,
+            )?,
+        };
+        Ok(CpuMesh {
+            vertices: vertex_buffer,
+            indices: upload_buffer(context, &indices, buffer::Usage::INDEX, MemoryCategory::Meshes)?,
+            draws,
+            mesher,
+            format,
+            optimization: if optimize { Some(optimization) } else { None },
+            vertex_count: vertices.len(),
+            triangle_count: indices.len() / 3,
+        })
+    }
+
+    /// How many bytes the vertices take, and how many they would as `ChunkVertex`es.
+    fn vertex_memory(&self) -> (usize, usize) {
+        (
+            self.vertex_count * self.format.vertex_size(),
+            self.vertex_count * VertexFormat::Full.vertex_size(),
+        )
+    }
+}
+
+/// Builds the compute pipeline that meshes the region on the gpu, from `mesh.comp`.
+fn create_mesh_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+) -> Result<B::ComputePipeline> {
+    let module = create_shader_module::<B>(device, shaders.get("mesh.comp"))?;
+    let pipeline = device.create_compute_pipeline(
+        &pso::ComputePipelineDesc::new(
+            pso::EntryPoint {
+                entry: "main",
+                module: &module,
+                specialization: &[],
+            },
+            pipeline_layout,
+        ),
+        Some(pipeline_cache),
+    );
+    device.destroy_shader_module(module);
+
+    let pipeline = pipeline?;
+    debug!("Built the mesh pipeline");
+    Ok(pipeline)
+}
+
+/// Builds a graphics pipeline that draws the blocks with `stages`, a vertex and a fragment
+/// shader. The vertex shader is `chunk.vert` or `chunk_packed.vert`, which take the cpu's meshes
+/// as `ChunkVertex`es or `PackedVertex`es, or `quads.vert`, which builds the gpu's quads with no
+/// vertex buffer at all, and the fragment shader is the one for the `TileImages`.
+fn create_terrain_pipeline<B: Backend>(
+    device: &B::Device,
+    shaders: &ShaderSet,
+    stages: (&str, &str),
+    render_pass: &B::RenderPass,
+    pipeline_layout: &B::PipelineLayout,
+    pipeline_cache: &B::PipelineCache,
+    samples: i::NumSamples,
+) -> Result<B::GraphicsPipeline> {
+    let (vertex_shader, fragment_shader) = stages;
+    let vs_module = create_shader_module::<B>(device, shaders.get(vertex_shader))?;
+    let fs_module = create_shader_module::<B>(device, shaders.get(fragment_shader))?;
+
+    let pipeline = {
+        let shader_entries = pso::GraphicsShaderSet {
+            vertex: pso::EntryPoint {
+                entry: "main",
+                module: &vs_module,
+                specialization: &[],
+            },
+            hull: None,
+            domain: None,
+            geometry: None,
+            fragment: Some(pso::EntryPoint {
+                entry: "main",
+                module: &fs_module,
+                specialization: &[],
+            }),
+        };
+
+        let subpass = pass::Subpass {
+            index: 0,
+            main_pass: render_pass,
+        };
+
+        let mut pipeline_desc = pso::GraphicsPipelineDesc::new(
+            shader_entries,
+            Primitive::TriangleList,
+            pso::Rasterizer {
+                cull_face: pso::Face::BACK,
+                front_face: pso::FrontFace::CounterClockwise,
+                ..pso::Rasterizer::FILL
+            },
+            pipeline_layout,
+            subpass,
+        );
+        pipeline_desc.depth_stencil = pso::DepthStencilDesc {
+            depth: pso::DepthTest::On {
+                fun: pso::Comparison::LessEqual,
+                write: true,
+            },
+            depth_bounds: false,
+            stencil: pso::StencilTest::Off,
+        };
+        // Has to match the sample count of the render pass attachments
+        if samples > 1 {
+            pipeline_desc.multisampling = Some(pso::Multisampling {
+                rasterization_samples: samples,
+                sample_shading: None,
+                sample_mask: !0,
+                alpha_coverage: false,
+                alpha_to_one: false,
+            });
+        }
+        pipeline_desc.blender.targets.push(pso::ColorBlendDesc(pso::ColorMask::ALL, pso::BlendState::ALPHA));
+
+        if vertex_shader == chunk_vertex_shader(VertexFormat::Full) {
+            // One interleaved vertex buffer of `ChunkVertex`es, leaving out the water depth,
+            // since there's no water
+            pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+                binding: 0,
+                stride: mem::size_of::<ChunkVertex>() as u32,
+                rate: 0,
+            });
+            let attributes = [
+                (f::Format::Rgb32Float, 0),
+                (f::Format::Rgb32Float, 12),
+                (f::Format::Rg32Float, 24),
+                (f::Format::R32Uint, 32),
+                (f::Format::R32Float, 36),
+                (f::Format::Rg32Float, 40),
+            ];
+            for (location, &(format, offset)) in attributes.iter().enumerate() {
+                pipeline_desc.attributes.push(pso::AttributeDesc {
+                    location: location as u32,
+                    binding: 0,
+                    element: pso::Element { format, offset },
+                });
+            }
+        } else if vertex_shader == chunk_vertex_shader(VertexFormat::Packed) {
+            // The three words of each `PackedVertex`, which the shader takes apart itself
+            pipeline_desc.vertex_buffers.push(pso::VertexBufferDesc {
+                binding: 0,
+                stride: mem::size_of::<PackedVertex>() as u32,
+                rate: 0,
+            });
+            pipeline_desc.attributes.push(pso::AttributeDesc {
+                location: 0,
+                binding: 0,
+                element: pso::Element {
+                    format: f::Format::Rgb32Uint,
+                    offset: 0,
+                },
+            });
+        }
+
+        device.create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    };
+
+    // The modules are only needed while the pipeline is being built
+    device.destroy_shader_module(vs_module);
+    device.destroy_shader_module(fs_module);
+
+    let pipeline = pipeline?;
+    debug!("Built the terrain pipeline from {} and {}", vertex_shader, fragment_shader);
+    Ok(pipeline)
+}
+
+/// The extra attachments that follow the swapchain image in each framebuffer, in the order
+/// `create_multisampled_render_pass` expects them.
+fn framebuffer_attachments<'a, B: Backend>(
+    msaa_targets: &'a Option<AttachmentImages<B>>,
+    depth_images: &'a AttachmentImages<B>,
+) -> Vec<&'a AttachmentImages<B>> {
+    msaa_targets.iter().chain(iter::once(depth_images)).collect()
+}
+
+fn main() {
+    renderer_common::launch("voxel-renderer", Chapter);
+}
+
+struct Chapter;
+
+impl Runner for Chapter {
+    fn run<B: Backend>(
+        &mut self,
+        context: &mut GfxContext<B>,
+        events: &mut Events,
+    ) -> Result<()> {
+        let mut cpu_profiler = CpuProfiler::new(context.args.cpu_trace.is_some());
+        let mut swapchain = context.create_swapchain()?;
+        let samples = choose_sample_count(&context.adapter, context.args.msaa)?;
+        let depth_format = choose_depth_format(&context.adapter)?;
+        info!("Depth format: {:?}, {}x MSAA", depth_format, samples);
+        let render_pass = renderer_common::pass::create_multisampled_render_pass::<B>(
+            &context.device,
+            swapchain.format(),
+            depth_format,
+            samples,
+        );
+
+        // With multisampling on we draw into a multisampled color target instead of the
+        // swapchain image, and the render pass resolves it into the swapchain image at the end
+        let mut msaa_targets = if samples > 1 {
+            Some(AttachmentImages::multisampled_color(
+                context.device.clone(),
+                context.allocator.clone(),
+                swapchain.format(),
+                samples,
+                &swapchain,
+            )?)
+        } else {
+            None
+        };
+        let mut depth_images = AttachmentImages::depth(
+            context.device.clone(),
+            context.allocator.clone(),
+            depth_format,
+            samples,
+            &swapchain,
+        )?;
+
+        let mut atlas_builder = AtlasBuilder::new(TILE_SIZE);
+        let textures = block_textures(&mut atlas_builder)?;
+        let tile_images = TileImages::build(context, atlas_builder)?;
+        let grid = tile_images.grid();
+        let fragment_shader = tile_images.fragment_shader();
+
+        // The whole region is generated and lit up front, and nothing else is ever loaded
+        let seed = context.args.seed.unwrap_or(context.config.settings().seed);
+        info!("Generating the world from seed {}", seed);
+        let generator = WorldGenerator::new(
+            seed,
+            TerrainBlocks {
+                stone: STONE,
+                dirt: DIRT,
+                grass: GRASS,
+                sand: SAND,
+                snow: SNOW,
+                log: LOG,
+                leaves: LEAVES,
+                water: WATER,
+            },
+        );
+        let half = REGION_CHUNKS as i32 / 2;
+        let region = MeshRegion::new(
+            ChunkCoord::new(-half, 0, -half),
+            [REGION_CHUNKS, HEIGHT_IN_CHUNKS as u32, REGION_CHUNKS],
+        );
+        let mut world = World::new();
+        {
+            // Trees that spill out of the region are cut off at its edge
+            let mut pending_edits = PendingEdits::new();
+            for coord in region.coords() {
+                generator.generate_into(&mut world, &mut pending_edits, coord);
+            }
+            Lighting::new(block_lights(), HEIGHT_IN_CHUNKS).update(&mut world);
+        }
+
+        // The blocks go to the gpu whichever way they're meshed, so the setting can be changed
+        // while it's running
+        let mut gpu_mesher = GpuMesher::new(
+            context,
+            &world,
+            region,
+            &textures,
+            MAX_QUADS,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        )?;
+        let mut cpu_mesh: Option<CpuMesh<B>> = None;
+
+        // The blocks are drawn with the camera and block textures in set 0, and the gpu's quads, when
+        // that's what's drawn, in set 1
+        let camera_layout = Rc::new(DescriptorSetLayout::new(
+            context.device.clone(),
+            vec![
+                pso::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    ty: pso::DescriptorType::UniformBuffer,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::VERTEX | pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 1,
+                    ty: pso::DescriptorType::SampledImage,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+                pso::DescriptorSetLayoutBinding {
+                    binding: 2,
+                    ty: pso::DescriptorType::Sampler,
+                    count: 1,
+                    stage_flags: pso::ShaderStageFlags::FRAGMENT,
+                    immutable_samplers: false,
+                },
+            ],
+        ));
+        let mut camera_descriptors = DescriptorAllocator::new(context.device.clone(), camera_layout.clone());
+        let terrain_pipeline_layout = context.device.create_pipeline_layout(
+            vec![camera_layout.raw(), gpu_mesher.set_layout().raw()],
+            &[(pso::ShaderStageFlags::VERTEX, 0..CHUNK_PUSH_CONSTANTS_SIZE)],
+        );
+
+        let mut shaders = ShaderSet::new(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders"), shaders::ALL);
+        let mut mesh_pipeline = create_mesh_pipeline::<B>(
+            &context.device,
+            &shaders,
+            gpu_mesher.pipeline_layout(),
+            context.pipeline_cache.cache(),
+        )?;
+        // One for each layout the cpu's meshes can be in, in the order of `VertexFormat::ALL`
+        let mut chunk_pipelines = Vec::new();
+        for &format in &VertexFormat::ALL {
+            chunk_pipelines.push(create_terrain_pipeline::<B>(
+                &context.device,
+                &shaders,
+                (chunk_vertex_shader(format), fragment_shader),
+                &render_pass,
+                &terrain_pipeline_layout,
+                context.pipeline_cache.cache(),
+                samples,
+            )?);
+        }
+        let mut quads_pipeline = create_terrain_pipeline::<B>(
+            &context.device,
+            &shaders,
+            ("quads.vert", fragment_shader),
+            &render_pass,
+            &terrain_pipeline_layout,
+            context.pipeline_cache.cache(),
+            samples,
+        )?;
+        info!("Gpu memory: {}", context.allocator.borrow().stats());
+
+        let mut clock = Clock::new(context.is_headless());
+        let mut timestep = FixedTimestep::new();
+        let mut input = Input::new(&context.config.settings().bindings);
+        input.set_grab_on_focus(!context.is_headless());
+        let mut gamepads = Gamepads::new(!context.is_headless());
+        // When the clock last read, for how far the gamepad sticks turn each frame
+        let mut last_seconds = 0.0;
+        // Standing on the ground in the middle of the region, or circling it from above
+        let start_height = generator.height_at(0, 0) as f32 + 1.0;
+        let region_blocks = region.blocks();
+        let mut camera = CameraSwitch::new(
+            FpsCamera::new([0.0, start_height + 1.7, 0.0], context.config.settings()),
+            OrbitCamera::new([0.0, start_height, 0.0], region_blocks[0] as f32, context.config.settings()),
+        );
+
+        let mut framebuffers = Framebuffers::with_attachments(
+            context.device.clone(),
+            &render_pass,
+            &swapchain,
+            &framebuffer_attachments(&msaa_targets, &depth_images),
+        )?;
+
+        // One clear value per attachment. The resolve target is never loaded, so its value
+        // doesn't matter.
+        let color_clear = command::ClearValue::Color(command::ClearColor::Float(srgb_to_linear_rgba(CLEAR_COLOR)));
+        let depth_clear = command::ClearValue::DepthStencil(command::ClearDepthStencil(1.0, 0));
+        let clear_values = if samples > 1 {
+            vec![color_clear.clone(), color_clear, depth_clear]
+        } else {
+            vec![color_clear, depth_clear]
+        };
+        let mut frame_sync = FrameSync::new(
+            context.device.clone(),
+            &context.queue_group,
+            frame_sync::DEFAULT_FRAMES_IN_FLIGHT,
+        );
+        let mut gpu_profiler = GpuProfiler::new(
+            context.device.clone(),
+            &context.adapter,
+            frame_sync.frames_in_flight(),
+        );
+        let camera_uniforms = UniformRing::<B, CameraUniform>::new(
+            context.device.clone(),
+            context.allocator.clone(),
+            &context.adapter,
+            &mut camera_descriptors,
+            0,
+            frame_sync.frames_in_flight(),
+        )?;
+        // Every frame's set points at the same block textures
+        for frame_index in 0..camera_uniforms.frames() {
+            let set = camera_uniforms.set(frame_index);
+            context.device.write_descriptor_sets(vec![
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 1,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Image(tile_images.texture().view(), i::Layout::ShaderReadOnlyOptimal)),
+                },
+                pso::DescriptorSetWrite {
+                    set,
+                    binding: 2,
+                    array_offset: 0,
+                    descriptors: Some(pso::Descriptor::Sampler(tile_images.texture().sampler())),
+                },
+            ]);
+        }
+        let mut overlay = DebugOverlay::new(context, &swapchain, frame_sync.frames_in_flight())?;
+        let mut last_timing_report = Instant::now();
+        // Whether the gpu has been told there wasn't room for all its quads
+        let mut warned_full = false;
+
+        let mut running = true;
+        let mut recreate_swapchain = false;
+        while running {
+            cpu_profiler.begin_frame();
+            let mut toggle_vsync = false;
+            let mut toggle_fullscreen = false;
+            let mut take_screenshot = false;
+            input.begin_frame();
+            events.poll_with(
+                |event| {
+                    overlay.handle_event(event);
+                    // While the cursor is grabbed it's hidden, so it can't be over the overlay
+                    if !input.cursor_grabbed() && overlay.captures(event) {
+                        return false;
+                    }
+                    input.handle_event(event)
+                },
+                |action| match action {
+                    WindowAction::Close => running = false,
+                    WindowAction::Resize => recreate_swapchain = true,
+                    WindowAction::ToggleVsync => toggle_vsync = true,
+                    WindowAction::ToggleFullscreen => toggle_fullscreen = true,
+                    WindowAction::Screenshot => take_screenshot = true,
+                },
+            );
+
+            if toggle_vsync {
+                context.toggle_vsync(&swapchain);
+                recreate_swapchain = true;
+            }
+            if toggle_fullscreen {
+                context.toggle_fullscreen();
+                recreate_swapchain = true;
+            }
+            if context.poll_config() {
+                recreate_swapchain = true;
+            }
+
+            // Rebuild whichever pipeline was built from a shader that was edited
+            let changed = shaders.poll_changes();
+            if !changed.is_empty() {
+                context.wait_idle()?;
+                if changed.iter().any(|name| name == "mesh.comp") {
+                    match create_mesh_pipeline::<B>(
+                        &context.device,
+                        &shaders,
+                        gpu_mesher.pipeline_layout(),
+                        context.pipeline_cache.cache(),
+                    ) {
+                        Ok(new_pipeline) => {
+                            let old_pipeline = mem::replace(&mut mesh_pipeline, new_pipeline);
+                            context.device.destroy_compute_pipeline(old_pipeline);
+                        }
+                        Err(err) => error!("Keeping the previous mesh pipeline: {}", err),
+                    }
+                }
+                for (index, &format) in VertexFormat::ALL.iter().enumerate() {
+                    let vertex_shader = chunk_vertex_shader(format);
+                    if !changed.iter().any(|name| name == vertex_shader || name == fragment_shader) {
+                        continue;
+                    }
+                    match create_terrain_pipeline::<B>(
+                        &context.device,
+                        &shaders,
+                        (vertex_shader, fragment_shader),
+                        &render_pass,
+                        &terrain_pipeline_layout,
+                        context.pipeline_cache.cache(),
+                        samples,
+                    ) {
+                        Ok(new_pipeline) => {
+                            let old_pipeline = mem::replace(&mut chunk_pipelines[index], new_pipeline);
+                            context.device.destroy_graphics_pipeline(old_pipeline);
+                        }
+                        Err(err) => error!("Keeping the previous {} chunk pipeline: {}", format, err),
+                    }
+                }
+                if changed.iter().any(|name| name == "quads.vert" || name == fragment_shader) {
+                    match create_terrain_pipeline::<B>(
+                        &context.device,
+                        &shaders,
+                        ("quads.vert", fragment_shader),
+                        &render_pass,
+                        &terrain_pipeline_layout,
+                        context.pipeline_cache.cache(),
+                        samples,
+                    ) {
+                        Ok(new_pipeline) => {
+                            let old_pipeline = mem::replace(&mut quads_pipeline, new_pipeline);
+                            context.device.destroy_graphics_pipeline(old_pipeline);
+                        }
+                        Err(err) => error!("Keeping the previous quads pipeline: {}", err),
+                    }
+                }
+            }
+
+            if recreate_swapchain {
+                context.recreate_swapchain(&mut swapchain)?;
+                if let Some(ref mut msaa_targets) = msaa_targets {
+                    msaa_targets.recreate(&swapchain)?;
+                }
+                depth_images.recreate(&swapchain)?;
+                framebuffers.recreate_with_attachments(
+                    &render_pass,
+                    &swapchain,
+                    &framebuffer_attachments(&msaa_targets, &depth_images),
+                )?;
+                overlay.recreate(&swapchain)?;
+                recreate_swapchain = false;
+            }
+
+            // Meshing on the cpu happens once, whenever it's switched to or the mesher, vertex
+            // format or optimizing is changed. The old buffers might still be drawing, so they
+            // wait for the gpu first.
+            let gpu_meshing = context.config.settings().gpu_meshing;
+            let mesher = context.config.settings().mesher;
+            let vertex_format = context.config.settings().vertex_format;
+            let optimize_meshes = context.config.settings().optimize_meshes;
+            let stale = cpu_mesh.as_ref().map_or(true, |mesh| {
+                mesh.mesher != mesher
+                    || mesh.format != vertex_format
+                    || mesh.optimization.is_some() != optimize_meshes
+            });
+            if !gpu_meshing && stale {
+                cpu_profiler.begin_scope("mesh");
+                context.wait_idle()?;
+                cpu_mesh = None;
+                cpu_mesh = Some(CpuMesh::new(
+                    context,
+                    &world,
+                    region,
+                    &textures,
+                    mesher,
+                    vertex_format,
+                    optimize_meshes,
+                )?);
+                cpu_profiler.end_scope();
+            }
+
+            // Run however many simulation ticks have come due since the last frame
+            cpu_profiler.begin_scope("simulate");
+            context.update_cursor_grab(input.cursor_grabbed());
+            input.apply_bindings(&context.config.settings().bindings);
+            let seconds = clock.frame_seconds();
+            gamepads.poll(&mut input, context.config.settings(), seconds - last_seconds);
+            last_seconds = seconds;
+            camera.apply_settings(context.config.settings());
+            camera.handle_input(&input);
+            for _ in 0..timestep.advance_to(seconds) {
+                camera.update(&input, TICK_SECONDS);
+            }
+            cpu_profiler.end_scope();
+
+            // Waits until the gpu is done with the last frame that used these resources
+            cpu_profiler.begin_scope("wait");
+            let mut frame = frame_sync.begin_frame()?;
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("acquire");
+            let image_index = match swapchain.acquire_image(&mut frame, &mut context.queue_group.queues[0]) {
+                Ok(image_index) => image_index,
+                Err(_) => {
+                    recreate_swapchain = true;
+                    continue;
+                }
+            };
+            cpu_profiler.end_scope();
+
+            // The pipeline doesn't bake in a viewport, so we set it (and the scissor) every
+            // frame, which also means we don't need to rebuild the pipeline on resize.
+            let viewport = swapchain.viewport();
+
+            let vsync = present::is_vsync(swapchain.present_mode());
+            let mut overlay_settings = OverlaySettings {
+                vsync,
+                wireframe: None,
+                shadows: None,
+                show_cascades: None,
+                occlusion_culling: None,
+                mesher: None,
+                shading: None,
+                view_mode: None,
+                debug_lines: None,
+                time_of_day: None,
+            };
+
+            // How many triangles the gpu made the last time this frame came round, which is as
+            // late as it can be known without waiting
+            let triangles = if gpu_meshing {
+                let quads = gpu_mesher.read_quads(frame.index)?;
+                if let Some(quads) = quads {
+                    if quads > gpu_mesher.max_quads() && !warned_full {
+                        warn!("The gpu mesher only had room for {} of {} quads", gpu_mesher.max_quads(), quads);
+                        warned_full = true;
+                    }
+                }
+                quads.map(|quads| quads.min(gpu_mesher.max_quads()) as usize * 2)
+            } else {
+                cpu_mesh.as_ref().map(|mesh| mesh.triangle_count)
+            };
+            let (vertex_memory, mesh_optimization) = match cpu_mesh {
+                Some(ref mesh) if !gpu_meshing => (Some(mesh.vertex_memory()), mesh.optimization),
+                _ => (None, None),
+            };
+
+            cpu_profiler.begin_scope("record");
+            let finished_command_buffer = {
+                let mut command_buffer = frame.command_pool.acquire_command_buffer(false);
+                gpu_profiler.begin_frame(&mut command_buffer, frame.index)?;
+
+                // Draw in between the last two ticks, however far the clock is through the next
+                let alpha = timestep.alpha();
+                let extent = swapchain.extent();
+                let aspect = extent.width as f32 / extent.height as f32;
+                let origin = region.origin.origin();
+                let camera_uniform = CameraUniform {
+                    view_projection: camera.interpolated_view_projection(aspect, alpha).into(),
+                    region_origin: [origin[0] as f32, origin[1] as f32, origin[2] as f32],
+                    atlas_columns: grid.columns,
+                    atlas_origin: grid.origin,
+                    atlas_cell: grid.cell,
+                    atlas_tile_size: grid.size,
+                };
+                camera_uniforms.update(frame.index, &camera_uniform)?;
+
+                // The gpu meshes the whole region again every frame, which it can afford, to show
+                // what it costs
+                if gpu_meshing {
+                    gpu_profiler.begin_scope(&mut command_buffer, "mesh");
+                    gpu_mesher.mesh(&mut command_buffer, &mesh_pipeline, frame.index)?;
+                    gpu_profiler.end_scope(&mut command_buffer);
+                }
+
+                gpu_profiler.begin_scope(&mut command_buffer, "terrain");
+                {
+                    command_buffer.set_viewports(0, &[viewport.clone()]);
+                    command_buffer.set_scissors(0, &[viewport.rect]);
+                    if gpu_meshing {
+                        command_buffer.bind_graphics_pipeline(&quads_pipeline);
+                        command_buffer.bind_graphics_descriptor_sets(
+                            &terrain_pipeline_layout,
+                            0,
+                            vec![camera_uniforms.set(frame.index), gpu_mesher.set(frame.index)],
+                            &[],
+                        );
+                    } else if let Some(ref mesh) = cpu_mesh {
+                        command_buffer.bind_graphics_pipeline(&chunk_pipelines[mesh.format as usize]);
+                        command_buffer.bind_graphics_descriptor_sets(
+                            &terrain_pipeline_layout,
+                            0,
+                            Some(camera_uniforms.set(frame.index)),
+                            &[],
+                        );
+                        command_buffer.bind_vertex_buffers(0, pso::VertexBufferSet(vec![(mesh.vertices.buffer(), 0)]));
+                        command_buffer.bind_index_buffer(buffer::IndexBufferView {
+                            buffer: mesh.indices.buffer(),
+                            offset: 0,
+                            index_type: IndexType::U32,
+                        });
+                    }
+
+                    let mut encoder = command_buffer.begin_render_pass_inline(
+                        &render_pass,
+                        framebuffers.get(image_index),
+                        viewport.rect,
+                        &clear_values,
+                    );
+                    if gpu_meshing {
+                        gpu_mesher.draw(&mut encoder, frame.index);
+                    } else if let Some(ref mesh) = cpu_mesh {
+                        for draw in &mesh.draws {
+                            let constants = ChunkPushConstants { chunk_origin: draw.origin };
+                            encoder.push_graphics_constants(
+                                &terrain_pipeline_layout,
+                                pso::ShaderStageFlags::VERTEX,
+                                0,
+                                constants.as_words(),
+                            );
+                            encoder.draw_indexed(draw.indices.clone(), draw.base_vertex, 0..1);
+                        }
+                    }
+                }
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                // The overlay goes on top of the resolved image, in a pass of its own
+                gpu_profiler.begin_scope(&mut command_buffer, "overlay");
+                {
+                    let stats = OverlayStats {
+                        gpu_timings: gpu_profiler.timings(),
+                        camera_position: Some(camera.position()),
+                        loaded_chunks: Some(world.chunk_count()),
+                        triangles,
+                        vertex_memory,
+                        mesh_optimization,
+                        ..OverlayStats::default()
+                    };
+                    overlay.draw(
+                        &mut command_buffer,
+                        image_index,
+                        &swapchain,
+                        &stats,
+                        &mut overlay_settings,
+                    )?;
+                }
+                gpu_profiler.end_scope(&mut command_buffer);
+
+                command_buffer.finish()
+            };
+            cpu_profiler.end_scope();
+
+            cpu_profiler.begin_scope("submit");
+            let submission = Submission::new()
+                .wait_on(&[(frame.image_available(), PipelineStage::COLOR_ATTACHMENT_OUTPUT)])
+                .signal(&[frame.render_finished()])
+                .submit(Some(finished_command_buffer));
+            context.device.reset_fence(frame.fence());
+            context.queue_group.queues[0].submit(submission, Some(frame.fence()));
+            cpu_profiler.end_scope();
+
+            if take_screenshot {
+                match screenshot::capture(context, &swapchain, image_index) {
+                    Ok(path) => info!("Saved a screenshot to {}", path.display()),
+                    Err(err) => error!("{}", err),
+                }
+            }
+
+            cpu_profiler.begin_scope("present");
+            if swapchain.present(&frame, &mut context.queue_group.queues[0], image_index).is_err() {
+                recreate_swapchain = true;
+            }
+            cpu_profiler.end_scope();
+
+            // The swapchain can't change mid-frame, so a vsync toggle from the overlay takes
+            // effect on the next one
+            if overlay_settings.vsync != vsync {
+                context.set_vsync(overlay_settings.vsync);
+                recreate_swapchain = true;
+            }
+
+            if last_timing_report.elapsed().as_secs() >= 1 {
+                for timing in gpu_profiler.timings() {
+                    debug!("Gpu time for {}: {:.3} ms", timing.name, timing.milliseconds);
+                }
+                debug!("Cpu time for the last frame:\n{}", cpu_profiler.report());
+                last_timing_report = Instant::now();
+            }
+        }
+
+        context.wait_idle()?;
+
+        if let Some(ref path) = context.args.cpu_trace {
+            cpu_profiler.write_chrome_trace(path)?;
+            info!("Wrote the cpu trace to {}", path.display());
+        }
+
+        drop(overlay);
+        drop(framebuffers);
+        drop(depth_images);
+        drop(msaa_targets);
+        drop(cpu_mesh);
+        drop(gpu_mesher);
+        drop(tile_images);
+        drop(camera_uniforms);
+        drop(camera_descriptors);
+        context.device.destroy_compute_pipeline(mesh_pipeline);
+        for pipeline in chunk_pipelines {
+            context.device.destroy_graphics_pipeline(pipeline);
+        }
+        context.device.destroy_graphics_pipeline(quads_pipeline);
+        context.device.destroy_pipeline_layout(terrain_pipeline_layout);
+        context.device.destroy_render_pass(render_pass);
+
+        Ok(())
+    }
+}